@@ -0,0 +1 @@
+DROP TABLE vip_conversation;
@@ -0,0 +1,6 @@
+CREATE TABLE vip_conversation (
+	vip_conversation_pk INTEGER PRIMARY KEY NOT NULL,
+	account VARCHAR NOT NULL,
+	jid VARCHAR NOT NULL,
+	UNIQUE(account, jid)
+);
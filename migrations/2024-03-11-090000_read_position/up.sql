@@ -0,0 +1,8 @@
+CREATE TABLE read_position (
+	read_position_pk INTEGER PRIMARY KEY NOT NULL,
+	account VARCHAR NOT NULL,
+	jid VARCHAR NOT NULL,
+	view BIGINT NOT NULL,
+	last_read VARCHAR,
+	UNIQUE(account, jid)
+);
@@ -0,0 +1,9 @@
+CREATE TABLE conversation_privacy (
+	conversation_privacy_pk INTEGER PRIMARY KEY NOT NULL,
+	account VARCHAR NOT NULL,
+	jid VARCHAR NOT NULL,
+	receipts BOOLEAN,
+	chat_markers BOOLEAN,
+	chat_states BOOLEAN,
+	UNIQUE(account, jid)
+);
@@ -0,0 +1,7 @@
+CREATE TABLE conversation_lang (
+	conversation_lang_pk INTEGER PRIMARY KEY NOT NULL,
+	account VARCHAR NOT NULL,
+	jid VARCHAR NOT NULL,
+	lang VARCHAR NOT NULL,
+	UNIQUE(account, jid)
+);
@@ -0,0 +1,10 @@
+CREATE TABLE pinned_message (
+	pinned_message_pk INTEGER PRIMARY KEY NOT NULL,
+	account VARCHAR NOT NULL,
+	jid VARCHAR NOT NULL,
+	message_id VARCHAR NOT NULL,
+	timestamp VARCHAR NOT NULL,
+	author VARCHAR NOT NULL,
+	body VARCHAR NOT NULL,
+	UNIQUE(account, jid, message_id)
+);
@@ -0,0 +1,8 @@
+CREATE TABLE message_delivery (
+	message_delivery_pk INTEGER PRIMARY KEY NOT NULL,
+	account VARCHAR NOT NULL,
+	jid VARCHAR NOT NULL,
+	message_id VARCHAR NOT NULL,
+	state VARCHAR NOT NULL,
+	UNIQUE(account, jid, message_id)
+);
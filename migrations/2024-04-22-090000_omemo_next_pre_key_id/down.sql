@@ -0,0 +1 @@
+ALTER TABLE omemo_own_device DROP COLUMN next_pre_key_id;
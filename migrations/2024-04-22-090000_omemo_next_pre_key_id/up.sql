@@ -0,0 +1,2 @@
+-- Devices created so far were initialized with prekeys 1 to 100
+ALTER TABLE omemo_own_device ADD COLUMN next_pre_key_id BIGINT NOT NULL DEFAULT 101;
@@ -0,0 +1,8 @@
+CREATE TABLE channel_subject (
+	channel_subject_pk INTEGER PRIMARY KEY NOT NULL,
+	account VARCHAR NOT NULL,
+	jid VARCHAR NOT NULL,
+	timestamp VARCHAR NOT NULL,
+	author VARCHAR,
+	subject VARCHAR NOT NULL
+);
@@ -0,0 +1,9 @@
+CREATE TABLE avatar (
+	avatar_pk INTEGER PRIMARY KEY NOT NULL,
+	account VARCHAR NOT NULL,
+	jid VARCHAR NOT NULL,
+	hash VARCHAR NOT NULL,
+	mime_type VARCHAR NOT NULL,
+	data BLOB NOT NULL,
+	UNIQUE(account, jid)
+);
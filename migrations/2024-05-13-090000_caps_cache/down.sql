@@ -0,0 +1 @@
+DROP TABLE caps_cache;
@@ -0,0 +1,7 @@
+CREATE TABLE caps_cache (
+	caps_cache_pk INTEGER PRIMARY KEY NOT NULL,
+	algo VARCHAR NOT NULL,
+	ver VARCHAR NOT NULL,
+	disco VARCHAR NOT NULL,
+	UNIQUE(algo, ver)
+);
@@ -0,0 +1,7 @@
+CREATE TABLE session_journal (
+	session_journal_pk INTEGER PRIMARY KEY NOT NULL,
+	account VARCHAR NOT NULL,
+	timestamp VARCHAR NOT NULL,
+	event VARCHAR NOT NULL,
+	detail VARCHAR
+);
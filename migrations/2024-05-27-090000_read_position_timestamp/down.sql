@@ -0,0 +1 @@
+ALTER TABLE read_position DROP COLUMN read_at;
@@ -0,0 +1,2 @@
+-- Timestamp of the last read message, to count archived messages received since then
+ALTER TABLE read_position ADD COLUMN read_at VARCHAR;
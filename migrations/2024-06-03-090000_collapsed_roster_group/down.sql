@@ -0,0 +1 @@
+DROP TABLE collapsed_roster_group;
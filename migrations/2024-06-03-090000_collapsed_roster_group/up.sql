@@ -0,0 +1,4 @@
+CREATE TABLE collapsed_roster_group (
+	collapsed_roster_group_pk INTEGER PRIMARY KEY NOT NULL,
+	name VARCHAR NOT NULL UNIQUE
+);
@@ -14,6 +14,10 @@ fn false_() -> bool {
     false
 }
 
+fn true_() -> bool {
+    true
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 #[serde(default)]
 pub struct ConnectionInfo {
@@ -22,6 +26,26 @@ pub struct ConnectionInfo {
     pub port: Option<u16>,
     #[serde(default = "false_")]
     pub autoconnect: bool,
+    /// Keep retrying, with backoff, when autoconnect fails
+    #[serde(default = "true_")]
+    pub autoconnect_retry: bool,
+    /// Language of outgoing messages, unless set for the conversation
+    pub lang: Option<String>,
+    /// Nickname published to contacts on connection (XEP-0172)
+    pub nick: Option<String>,
+    /// Resource to bind, overriding the one of the jid
+    pub resource: Option<String>,
+    /// Append a random suffix to the resource
+    #[serde(default = "false_")]
+    pub random_resource: bool,
+    /// Time windows during which an autoconnected account stays disconnected, see README
+    pub quiet_hours: Vec<String>,
+    /// Publish the location set with /location or read by `location_command` (XEP-0080)
+    #[serde(default = "false_")]
+    pub share_location: bool,
+    /// Ask the server to acknowledge sent stanzas (XEP-0198), only if it supports it
+    #[serde(default = "false_")]
+    pub stream_management: bool,
     #[serde(skip_serializing)]
     pub password: Option<Password>,
 }
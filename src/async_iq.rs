@@ -12,7 +12,8 @@ use crate::core::AparteAsync;
 
 #[derive(Debug)]
 pub enum PendingIqState {
-    Waiting(Option<Waker>),
+    /// Waiting for the response of the iq sent with the account
+    Waiting(Account, Option<Waker>),
     Finished(Iq),
     Errored(anyhow::Error),
 }
@@ -31,7 +32,7 @@ impl IqFuture {
             .pending_iq
             .lock()
             .unwrap()
-            .insert(uuid, PendingIqState::Waiting(None));
+            .insert(uuid, PendingIqState::Waiting(account.clone(), None));
 
         Self { aparte, uuid }
     }
@@ -44,10 +45,10 @@ impl Future for IqFuture {
         let mut pending_iq = self.aparte.pending_iq.lock().unwrap();
         match pending_iq.remove(&self.uuid) {
             None => panic!("Iq response has already been consumed"),
-            Some(PendingIqState::Waiting(_)) => {
+            Some(PendingIqState::Waiting(account, _)) => {
                 pending_iq.insert(
                     self.uuid.clone(),
-                    PendingIqState::Waiting(Some(cx.waker().clone())),
+                    PendingIqState::Waiting(account, Some(cx.waker().clone())),
                 );
                 Poll::Pending
             }
@@ -1,6 +1,8 @@
 /* This Source Code Form is subject to the terms of the Mozilla Public
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use std::collections::HashMap;
+
 use anyhow::{anyhow, Result};
 use unicode_segmentation::UnicodeSegmentation;
 
@@ -35,6 +37,17 @@ impl Command {
         }
     }
 
+    /// Split a command prefixed with `@account` into the account name and the command itself
+    pub fn split_account_prefix<'a>(buf: &'a str) -> Option<(&'a str, &'a str)> {
+        let buf = buf.strip_prefix('@')?;
+        let (name, command) = buf.split_once(char::is_whitespace)?;
+        let command = command.trim_start();
+        match !name.is_empty() && command.starts_with('/') {
+            true => Some((name, command)),
+            false => None,
+        }
+    }
+
     pub fn parse_with_cursor(
         account: Option<Account>,
         context: String,
@@ -251,6 +264,132 @@ pub struct CommandParser {
     pub parse: fn(&Option<Account>, &str, &str) -> anyhow::Result<Command>,
     pub exec: fn(&mut Aparte, Command) -> anyhow::Result<()>,
     pub autocompletions: Vec<Option<Box<dyn Fn(&mut Aparte, Command) -> Vec<String>>>>,
+    /// Position of the subcommand among positional arguments, with subcommand parsers
+    pub subcommands: Option<(usize, HashMap<String, CommandParser>)>,
+    /// Named arguments (name=value) with their known values
+    pub flags: Vec<(String, Vec<String>)>,
+}
+
+impl CommandParser {
+    /// Complete positional arguments and flags without explicit completion with the
+    /// alternatives given in the usage line of the help, e.g. `<on|off>` or `[autojoin=on|off]`
+    pub fn with_usage_completions(mut self) -> Self {
+        let (positional, flags) = usage_values(&self.help);
+        for (completion, values) in self.autocompletions.iter_mut().zip(positional) {
+            if completion.is_none() && !values.is_empty() {
+                *completion = Some(Box::new(move |_: &mut Aparte, _: Command| values.clone()));
+            }
+        }
+        for (name, values) in self.flags.iter_mut() {
+            if let Some((_, usage)) = flags.iter().find(|(flag, _)| flag == name) {
+                if values.is_empty() {
+                    *values = usage.clone();
+                }
+            }
+        }
+        self
+    }
+
+    fn flag<'a>(&'a self, arg: &str) -> Option<&'a (String, Vec<String>)> {
+        let name = arg.split_once('=')?.0;
+        self.flags.iter().find(|(flag, _)| flag == name)
+    }
+
+    /// Completions of the argument under the cursor, args of this command starting at `start`
+    pub fn complete(&self, aparte: &mut Aparte, command: &Command, start: usize) -> Vec<String> {
+        let current = command.args.get(command.cursor).map(String::as_str);
+        if let Some((name, values)) = current.and_then(|current| self.flag(current)) {
+            return values
+                .iter()
+                .map(|value| format!("{name}={value}"))
+                .collect();
+        }
+
+        // Position of the argument under the cursor, flags can be given anywhere
+        let mut position = 0;
+        for (index, arg) in command
+            .args
+            .iter()
+            .enumerate()
+            .take(command.cursor)
+            .skip(start)
+        {
+            if self.flag(arg).is_some() {
+                continue;
+            }
+            if let Some((subcommand, children)) = &self.subcommands {
+                if position == *subcommand {
+                    return match children.get(arg) {
+                        Some(child) => child.complete(aparte, command, index + 1),
+                        None => Vec::new(),
+                    };
+                }
+            }
+            position += 1;
+        }
+
+        let mut completions = match self.autocompletions.get(position) {
+            Some(Some(completion)) => completion(aparte, command.clone()),
+            _ => Vec::new(),
+        };
+        completions.extend(
+            self.flags
+                .iter()
+                .filter(|(name, _)| {
+                    !command.args[start.min(command.args.len())..]
+                        .iter()
+                        .any(|arg| arg.starts_with(&format!("{name}=")))
+                })
+                .map(|(name, _)| format!("{name}=")),
+        );
+        completions
+    }
+}
+
+/// Alternatives of positional arguments and flags found in the first line of a help
+fn usage_values(help: &str) -> (Vec<Vec<String>>, Vec<(String, Vec<String>)>) {
+    let usage = help.lines().next().unwrap_or_default();
+    let mut groups = Vec::new();
+    let mut depth = 0;
+    let mut group = String::new();
+    for c in usage.chars() {
+        match c {
+            '<' | '[' => depth += 1,
+            '>' | ']' if depth > 0 => {
+                depth -= 1;
+                if depth == 0 {
+                    groups.push(group.clone());
+                    group.clear();
+                }
+            }
+            c if depth > 0 => group.push(c),
+            _ => {}
+        }
+    }
+
+    let alternatives = |values: &str| -> Vec<String> {
+        let values = values.split('|').collect::<Vec<_>>();
+        let valid = values.iter().all(|value| {
+            !value.is_empty()
+                && value
+                    .chars()
+                    .all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+        });
+        match valid && values.len() > 1 {
+            true => values.iter().map(|value| value.to_string()).collect(),
+            false => Vec::new(),
+        }
+    };
+
+    let mut positional = Vec::new();
+    let mut flags = Vec::new();
+    for group in groups {
+        match group.split_once('=') {
+            Some((name, values)) => flags.push((name.to_string(), alternatives(values))),
+            None => positional.push(alternatives(&group)),
+        }
+    }
+    (positional, flags)
 }
 
 #[macro_export]
@@ -374,6 +513,9 @@ macro_rules! parse_command_args(
 #[macro_export]
 macro_rules! generate_command_autocompletions(
     ($autocompletions:ident, {}) => ();
+    ($autocompletions:ident, { $argname:ident: Named<$type:ty> $(= $attrs:tt)? $(, $($tail:tt)*)? }) => (
+        generate_command_autocompletions!($autocompletions, { $($($tail)*)? });
+    );
     ($autocompletions:ident, { $argname:ident: $type:ty = $attrs:tt $(, $($tail:tt)*)? }) => (
         let count = $autocompletions.len();
         generate_arg_autocompletion!($autocompletions, $type, $attrs);
@@ -414,6 +556,38 @@ macro_rules! generate_arg_autocompletion(
     );
 );
 
+#[macro_export]
+macro_rules! generate_subcommands(
+    ($subcommands:ident, $position:expr, {}) => ();
+    ($subcommands:ident, $position:expr, { $arg:ident: Command = $attrs:tt $(, $($tail:tt)*)? }) => (
+        let mut children: ::std::collections::HashMap<String, CommandParser> = ::std::collections::HashMap::new();
+        parse_subcommand_attrs!(children, $attrs);
+        $subcommands = Some(($position, children));
+    );
+    ($subcommands:ident, $position:expr, { $arg:ident: Named<$type:ty> $(= $attrs:tt)? $(, $($tail:tt)*)? }) => (
+        generate_subcommands!($subcommands, $position, { $($($tail)*)? });
+    );
+    ($subcommands:ident, $position:expr, { $arg:ident: $type:ty $(= $attrs:tt)? $(, $($tail:tt)*)? }) => (
+        generate_subcommands!($subcommands, $position + 1, { $($($tail)*)? });
+    );
+);
+
+#[macro_export]
+macro_rules! generate_flags(
+    ($flags:ident, {}) => ();
+    ($flags:ident, { $arg:ident: Named<bool> $(= $attrs:tt)? $(, $($tail:tt)*)? }) => (
+        $flags.push((String::from(stringify!($arg)), vec![String::from("true"), String::from("false")]));
+        generate_flags!($flags, { $($($tail)*)? });
+    );
+    ($flags:ident, { $arg:ident: Named<$type:ty> $(= $attrs:tt)? $(, $($tail:tt)*)? }) => (
+        $flags.push((String::from(stringify!($arg)), Vec::new()));
+        generate_flags!($flags, { $($($tail)*)? });
+    );
+    ($flags:ident, { $arg:ident: $type:ty $(= $attrs:tt)? $(, $($tail:tt)*)? }) => (
+        generate_flags!($flags, { $($($tail)*)? });
+    );
+);
+
 #[macro_export]
 macro_rules! generate_sub_help(
     ($help:ident, {}) => ();
@@ -470,6 +644,12 @@ macro_rules! command_def (
             pub fn new() -> CommandParser {
                 let mut autocompletions = Vec::<Option<Box<dyn Fn(&mut Aparte, Command) -> Vec<String>>>>::new();
                 generate_command_autocompletions!(autocompletions, $args);
+                #[allow(unused_mut, unused_assignments)]
+                let mut subcommands = None;
+                generate_subcommands!(subcommands, 0, $args);
+                #[allow(unused_mut)]
+                let mut flags = Vec::new();
+                generate_flags!(flags, $args);
 
                 CommandParser {
                     name: stringify!($name),
@@ -477,7 +657,10 @@ macro_rules! command_def (
                     parse,
                     exec,
                     autocompletions,
+                    subcommands,
+                    flags,
                 }
+                .with_usage_completions()
             }
         }
     );
@@ -512,6 +695,12 @@ macro_rules! command_def (
                 let mut autocompletions = Vec::<Option<Box<dyn Fn(&mut Aparte, Command) -> Vec<String>>>>::new();
 
                 generate_command_autocompletions!(autocompletions, $args);
+                #[allow(unused_mut, unused_assignments)]
+                let mut subcommands = None;
+                generate_subcommands!(subcommands, 0, $args);
+                #[allow(unused_mut)]
+                let mut flags = Vec::new();
+                generate_flags!(flags, $args);
 
                 CommandParser {
                     name: stringify!($name),
@@ -519,7 +708,10 @@ macro_rules! command_def (
                     parse,
                     exec,
                     autocompletions,
+                    subcommands,
+                    flags,
                 }
+                .with_usage_completions()
             }
         }
     );
@@ -600,6 +792,29 @@ mod tests_command_macro {
         assert_eq!(cmd.help, "help");
         assert_eq!(cmd.autocompletions.len(), 2);
     }
+
+    command_def!(
+        named_args,
+        "/named_args <first> [_level=low|high] <fast|slow>",
+        { _first: String, _level: Named<String>, _mode: String },
+        |_aparte, _command| { Ok(()) }
+    );
+
+    #[test]
+    fn test_command_with_named_args_completion() {
+        let cmd = named_args::new();
+
+        assert_eq!(cmd.autocompletions.len(), 2);
+        assert!(cmd.autocompletions[0].is_none());
+        assert!(cmd.autocompletions[1].is_some());
+        assert_eq!(
+            cmd.flags,
+            vec![(
+                String::from("_level"),
+                vec![String::from("low"), String::from("high")]
+            )]
+        );
+    }
 }
 
 #[cfg(test)]
@@ -821,4 +1036,59 @@ mod tests_command_parser {
         assert!(name.is_ok());
         assert_eq!("close", name.unwrap());
     }
+
+    #[test]
+    fn test_split_account_prefix() {
+        assert_eq!(
+            Command::split_account_prefix("@work /join room@muc"),
+            Some(("work", "/join room@muc"))
+        );
+        assert_eq!(
+            Command::split_account_prefix("@me@server.tld   /msg contact@server.tld"),
+            Some(("me@server.tld", "/msg contact@server.tld"))
+        );
+    }
+
+    #[test]
+    fn test_split_account_prefix_without_command() {
+        assert_eq!(Command::split_account_prefix("@work hello"), None);
+        assert_eq!(Command::split_account_prefix("@work"), None);
+        assert_eq!(Command::split_account_prefix("@ /join room@muc"), None);
+        assert_eq!(Command::split_account_prefix("/join room@muc"), None);
+    }
+
+    #[test]
+    fn test_usage_values() {
+        let (positional, flags) =
+            usage_values("/privacy set <notification> <on|off|default> [<jid>]\n\nhelp");
+
+        assert_eq!(
+            positional,
+            vec![
+                vec![],
+                vec![
+                    String::from("on"),
+                    String::from("off"),
+                    String::from("default")
+                ],
+                vec![]
+            ]
+        );
+        assert!(flags.is_empty());
+    }
+
+    #[test]
+    fn test_usage_values_with_flags() {
+        let (positional, flags) =
+            usage_values("/bookmark add <bookmark> <conference> [autojoin=on|off] [-a <account>]");
+
+        assert_eq!(positional, vec![Vec::<String>::new(); 3]);
+        assert_eq!(
+            flags,
+            vec![(
+                String::from("autojoin"),
+                vec![String::from("on"), String::from("off")]
+            )]
+        );
+    }
 }
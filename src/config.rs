@@ -18,7 +18,79 @@ pub struct Config {
     pub accounts: HashMap<String, ConnectionInfo>,
     #[serde(default = "true_")]
     pub bell: bool,
+    /// How the bell rings: audible, visual or both, defaults to audible
+    pub bell_style: Option<String>,
+    /// What the bell rings for among messages, mentions, errors and calls, defaults to all but errors
+    pub bell_events: Option<Vec<String>>,
+    /// Command run with a title and a summary of new important messages, e.g. notify-send
+    pub notify_command: Option<String>,
+    /// Command playing the sound file given as argument, e.g. paplay, instead of the bell
+    pub sound_command: Option<String>,
+    /// Sound played for important messages when sound_command is set
+    pub sound_file: Option<String>,
+    /// Sound file of some conversations by jid, an empty one silences the conversation
+    pub sounds: HashMap<String, String>,
     pub theme: Theme,
+    /// Format of the status line, see README for available placeholders
+    pub status_format: Option<String>,
+    /// Display a clock and own presence at the right of the status line
+    pub status_clock: bool,
+    /// Display colored initials before roster and occupant entries
+    pub roster_avatars: bool,
+    /// Number of archived messages loaded when opening a window or paging up
+    pub history_preload: Option<usize>,
+    /// Number of core events kept for the /events window, tracing is disabled when unset or 0
+    pub event_trace_size: Option<usize>,
+    /// Keep message contents in logs and traced events
+    pub log_message_contents: Option<bool>,
+    /// Base delay between two renderings in milliseconds, adapted to the incoming event rate
+    pub render_debounce_ms: Option<u64>,
+    /// Number of lines of a message body displayed before truncation, 0 means no limit
+    pub body_max_lines: Option<usize>,
+    /// Number of characters of a message body displayed before truncation, 0 means no limit
+    pub body_max_chars: Option<usize>,
+    /// Initial roster grouping strategy: groups, domain, presence or activity
+    pub roster_grouping: Option<String>,
+    /// Send delivery receipts when requested, defaults to true
+    pub send_receipts: Option<bool>,
+    /// Send displayed chat markers, defaults to true
+    pub send_chat_markers: Option<bool>,
+    /// Send typing notifications, defaults to true
+    pub send_chat_states: Option<bool>,
+    /// Preferred languages of incoming messages, most preferred first
+    pub languages: Option<Vec<String>>,
+    /// Size limit in bytes of outgoing message bodies, longer messages are split on request
+    pub max_message_size: Option<usize>,
+    /// Split messages over max_message_size without asking first
+    pub split_messages: Option<bool>,
+    /// Directory in which shared files are downloaded, the user download directory by default
+    pub download_dir: Option<String>,
+    /// Seconds before rejoining a channel we were kicked from, doubled on repeated kicks
+    pub channel_rejoin_delay: Option<u64>,
+    /// Suffixes appended in turn to a nickname taken in a channel, `{n}` counting from 2
+    pub nick_suffixes: Option<Vec<String>>,
+    /// Seconds between two pings of the server, 0 disables them, defaults to 60
+    pub ping_interval: Option<u64>,
+    /// Messages kept in memory per conversation window, 0 keeps them all, defaults to 2000
+    pub window_history_limit: Option<usize>,
+    /// Tell servers when the terminal loses focus or is left idle, defaults to true
+    pub client_state_indication: Option<bool>,
+    /// Seconds without typing after which the client is idle, 0 disables it, defaults to 300
+    pub idle_timeout: Option<u64>,
+    /// Tell contacts since when the client is idle in presence (XEP-0319), defaults to true
+    pub idle_presence: Option<bool>,
+    /// Answer software version queries, defaults to true
+    pub answer_version: Option<bool>,
+    /// Client name given in answers to software version queries, defaults to aparte
+    pub client_name: Option<String>,
+    /// Client version given in answers to software version queries, defaults to the running one
+    pub client_version: Option<String>,
+    /// Publish the track played by MPRIS media players as user tune, read with playerctl
+    pub now_playing: bool,
+    /// Command printing the latitude and longitude to share, optionally followed by a description
+    pub location_command: Option<String>,
+    /// Favorite emojis proposed by the reaction picker after recently used ones
+    pub reactions: Option<Vec<String>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -2,7 +2,10 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 use std::cmp;
+use std::fmt;
 use std::hash::{Hash, Hasher};
+
+use chrono::{DateTime, FixedOffset, Local as LocalTz};
 use xmpp_parsers::roster::Subscription;
 use xmpp_parsers::{BareJid, Element};
 
@@ -16,6 +19,132 @@ pub enum Presence {
     Xa,
 }
 
+impl fmt::Display for Presence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Presence::Unavailable => write!(f, "unavailable"),
+            Presence::Available => write!(f, "available"),
+            Presence::Away => write!(f, "away"),
+            Presence::Chat => write!(f, "free for chat"),
+            Presence::Dnd => write!(f, "do not disturb"),
+            Presence::Xa => write!(f, "extended away"),
+        }
+    }
+}
+
+/// Connected resource of a contact, as announced by its last presence
+#[derive(Clone, Debug)]
+pub struct Resource {
+    pub presence: Presence,
+    pub status: Option<String>,
+    pub priority: i8,
+    /// Last interaction of the user with this resource, when idle (XEP-0319)
+    pub idle: Option<DateTime<FixedOffset>>,
+}
+
+/// Short time elapsed since the given date, e.g. `5m`, `3h` or `2d`
+pub fn format_idle(since: &DateTime<FixedOffset>) -> String {
+    let minutes = LocalTz::now()
+        .signed_duration_since(*since)
+        .num_minutes()
+        .max(0);
+    match minutes {
+        minutes if minutes < 60 => format!("{minutes}m"),
+        minutes if minutes < 48 * 60 => format!("{}h", minutes / 60),
+        minutes => format!("{}d", minutes / (24 * 60)),
+    }
+}
+
+/// XEP-0107: User Mood
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mood {
+    pub mood: String,
+    pub text: Option<String>,
+}
+
+impl fmt::Display for Mood {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.text {
+            Some(text) => write!(f, "{} ({})", self.mood, text),
+            None => write!(f, "{}", self.mood),
+        }
+    }
+}
+
+/// XEP-0118: User Tune
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct Tune {
+    pub artist: Option<String>,
+    pub title: Option<String>,
+    /// Album or other collection the track is part of
+    pub source: Option<String>,
+    /// Duration in seconds
+    pub length: Option<u16>,
+}
+
+impl fmt::Display for Tune {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.artist, &self.title) {
+            (Some(artist), Some(title)) => write!(f, "{artist} – {title}")?,
+            (Some(name), None) | (None, Some(name)) => write!(f, "{name}")?,
+            (None, None) => write!(f, "unknown track")?,
+        }
+        if let Some(source) = &self.source {
+            write!(f, " ({source})")?;
+        }
+        if let Some(length) = self.length {
+            write!(f, " [{}:{:02}]", length / 60, length % 60)?;
+        }
+        Ok(())
+    }
+}
+
+/// XEP-0080: User Location
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct Location {
+    /// Latitude and longitude in decimal degrees
+    pub coordinates: Option<(f64, f64)>,
+    pub locality: Option<String>,
+    pub country: Option<String>,
+    /// Free form description
+    pub text: Option<String>,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(text) = &self.text {
+            parts.push(text.clone());
+        }
+        let place = [&self.locality, &self.country]
+            .iter()
+            .filter_map(|part| part.as_deref())
+            .collect::<Vec<_>>()
+            .join(", ");
+        if !place.is_empty() {
+            parts.push(place);
+        }
+        if let Some((lat, lon)) = self.coordinates {
+            parts.push(format!("{lat:.5}, {lon:.5}"));
+        }
+        match parts.is_empty() {
+            true => write!(f, "unknown place"),
+            false => write!(f, "{}", parts.join(" – ")),
+        }
+    }
+}
+
+/// XEP-0145: Annotations, private note about a contact
+#[derive(Clone, Debug, PartialEq)]
+pub struct Note {
+    pub jid: BareJid,
+    pub text: String,
+    /// Creation date
+    pub cdate: Option<DateTime<FixedOffset>>,
+    /// Last modification date
+    pub mdate: Option<DateTime<FixedOffset>>,
+}
+
 #[derive(Clone, Debug)]
 pub struct Group(pub String);
 
@@ -38,8 +167,29 @@ pub struct Contact {
     pub jid: BareJid,
     pub name: Option<String>,
     pub subscription: Subscription,
+    /// We asked for a subscription the contact did not approve yet
+    pub pending: bool,
     pub presence: Presence,
     pub groups: Vec<Group>,
+    /// SHA-1 of the avatar, once it has been retrieved
+    pub avatar: Option<String>,
+    /// Nickname published by the contact (XEP-0172)
+    pub nick: Option<String>,
+    /// Mood published by the contact (XEP-0107)
+    pub mood: Option<Mood>,
+    /// Track the contact is listening to (XEP-0118)
+    pub tune: Option<Tune>,
+    /// Location shared by the contact (XEP-0080)
+    pub location: Option<Location>,
+    /// Last interaction announced by the latest presence, when idle (XEP-0319)
+    pub idle: Option<DateTime<FixedOffset>>,
+}
+
+impl Contact {
+    /// Name given in the roster, or else the one published by the contact
+    pub fn display_name(&self) -> Option<&str> {
+        self.name.as_deref().or(self.nick.as_deref())
+    }
 }
 
 impl Hash for Contact {
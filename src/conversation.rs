@@ -3,10 +3,14 @@
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 use std::cmp;
 use std::collections::HashMap;
+use std::fmt;
 use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+use std::sync::Arc;
 use xmpp_parsers::BareJid;
 
 use crate::account::Account;
+use crate::intern::intern;
 
 #[derive(Hash, Eq, PartialEq, Clone, Debug, Copy)]
 pub enum Affiliation {
@@ -17,6 +21,33 @@ pub enum Affiliation {
     None,
 }
 
+impl FromStr for Affiliation {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "owner" => Ok(Affiliation::Owner),
+            "admin" => Ok(Affiliation::Admin),
+            "member" => Ok(Affiliation::Member),
+            "outcast" => Ok(Affiliation::Outcast),
+            "none" => Ok(Affiliation::None),
+            _ => anyhow::bail!("Unknown affiliation {s}"),
+        }
+    }
+}
+
+impl fmt::Display for Affiliation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Affiliation::Owner => write!(f, "owner"),
+            Affiliation::Admin => write!(f, "admin"),
+            Affiliation::Member => write!(f, "member"),
+            Affiliation::Outcast => write!(f, "outcast"),
+            Affiliation::None => write!(f, "none"),
+        }
+    }
+}
+
 #[derive(Hash, Eq, PartialEq, Clone, Debug, Copy)]
 pub enum Role {
     Visitor,
@@ -25,9 +56,31 @@ pub enum Role {
     None,
 }
 
+/// Who can see the real jid of channel occupants
+#[derive(Eq, PartialEq, Clone, Debug, Copy)]
+pub enum Anonymity {
+    /// Any occupant
+    NonAnonymous,
+    /// Only moderators
+    SemiAnonymous,
+    /// Nobody
+    FullyAnonymous,
+}
+
+impl fmt::Display for Anonymity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Anonymity::NonAnonymous => write!(f, "non-anonymous"),
+            Anonymity::SemiAnonymous => write!(f, "semi-anonymous"),
+            Anonymity::FullyAnonymous => write!(f, "fully anonymous"),
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Occupant {
-    pub nick: String,
+    /// Interned, as repeated in each event about the occupant
+    pub nick: Arc<str>,
     pub jid: Option<BareJid>,
     pub affiliation: Affiliation,
     pub role: Role,
@@ -54,7 +107,9 @@ pub struct Channel {
     pub nick: String,
     pub name: Option<String>,
     /// Collections of occupants of this channel, key is occupant.nick
-    pub occupants: HashMap<String, Occupant>,
+    pub occupants: HashMap<Arc<str>, Occupant>,
+    /// Previous nicks of occupants during this session, value is the current nick
+    pub nick_aliases: HashMap<String, String>,
 }
 
 impl Channel {
@@ -64,6 +119,37 @@ impl Channel {
             None => self.jid.to_string(),
         }
     }
+
+    /// Get the current nick of an occupant known by the given (possibly previous) nick
+    pub fn resolve_nick<'a>(&'a self, nick: &'a str) -> &'a str {
+        match self.nick_aliases.get(nick) {
+            Some(current) => current,
+            None => nick,
+        }
+    }
+
+    /// Track an occupant nick change, linking the previous nick to the new one
+    pub fn rename_occupant(&mut self, previous: &str, nick: &str) -> Option<Occupant> {
+        // Keep aliases pointing directly to the current nick
+        for current in self.nick_aliases.values_mut() {
+            if current == previous {
+                *current = nick.to_string();
+            }
+        }
+        self.nick_aliases.remove(nick);
+        self.nick_aliases
+            .insert(previous.to_string(), nick.to_string());
+
+        if self.nick == previous {
+            self.nick = nick.to_string();
+        }
+
+        let mut occupant = self.occupants.remove(previous)?;
+        occupant.nick = intern(nick);
+        self.occupants
+            .insert(occupant.nick.clone(), occupant.clone());
+        Some(occupant)
+    }
 }
 
 #[derive(Clone, Debug)]
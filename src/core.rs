@@ -8,14 +8,16 @@ use std::fmt::{self, Debug, Display};
 use std::fs::OpenOptions;
 use std::future::Future;
 use std::io::Read;
+use std::iter;
 use std::path::PathBuf;
 use std::str::FromStr;
 use std::sync::atomic::AtomicBool;
 use std::sync::atomic::Ordering::Relaxed;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use anyhow::{Context, Result};
-use chrono::{DateTime, FixedOffset, Local as LocalTz};
+use chrono::{DateTime, FixedOffset, Local as LocalTz, Timelike};
 use futures::sink::SinkExt;
 use futures::stream::StreamExt;
 use rand::Rng;
@@ -23,13 +25,20 @@ use secrecy::ExposeSecret;
 use termion::event::Key;
 use tokio::runtime::Runtime as TokioRuntime;
 use tokio::signal::unix;
-use tokio::sync::{mpsc, RwLock, RwLockMappedWriteGuard, RwLockReadGuard, RwLockWriteGuard};
+use tokio::sync::{
+    mpsc, oneshot, RwLock, RwLockMappedWriteGuard, RwLockReadGuard, RwLockWriteGuard,
+};
 use tokio::task;
 use uuid::Uuid;
 
 use xmpp_parsers::caps::{self, Caps};
+use xmpp_parsers::chatstates::ChatState;
+use xmpp_parsers::data_forms::DataForm;
+use xmpp_parsers::date::DateTime as XmppDateTime;
 use xmpp_parsers::delay::Delay;
+use xmpp_parsers::disco;
 use xmpp_parsers::hashes as xmpp_hashes;
+use xmpp_parsers::idle::Idle;
 use xmpp_parsers::iq::{Iq, IqType};
 use xmpp_parsers::legacy_omemo;
 use xmpp_parsers::message::Message as XmppParsersMessage;
@@ -47,12 +56,18 @@ use crate::config::Config;
 use crate::conversation::{Channel, Conversation};
 use crate::crypto::CryptoEngine;
 use crate::cursor::Cursor;
-use crate::message::Message;
+use crate::i18n;
+use crate::message::{Delivery, LogMessage, Message, VersionedXmppMessage};
 use crate::mods;
+use crate::quiet_hours;
+use crate::redact;
+use crate::send_queue::SendQueue;
+use crate::stats::Stats;
 use crate::storage::Storage;
+use crate::stream_management::{AckRequest, Handled, StreamManagement, ACK_DELAY, NS_SM};
 use crate::{
-    command_def, generate_arg_autocompletion, generate_command_autocompletions, generate_help,
-    parse_command_args, parse_lookup_arg,
+    command_def, generate_arg_autocompletion, generate_command_autocompletions, generate_flags,
+    generate_help, generate_subcommands, parse_command_args, parse_lookup_arg,
 };
 use crate::{contact, conversation};
 
@@ -64,12 +79,69 @@ const WELCOME: &str = r#"
 "#;
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
+/// Delay before the first autoconnect retry, doubled after each failure
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// Longest delay between two autoconnect retries
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(300);
+
+/// Longest wait for the end of the stream to be sent when disconnecting
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What the bell rings for, each reason being enabled by `bell_events`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BellReason {
+    /// Private message or message of a VIP conversation
+    Message,
+    Mention,
+    Error,
+    /// Incoming call proposed to our devices
+    Call,
+}
+
+impl BellReason {
+    /// Name of the reason in `bell_events`
+    pub fn name(&self) -> &'static str {
+        match self {
+            BellReason::Message => "messages",
+            BellReason::Mention => "mentions",
+            BellReason::Error => "errors",
+            BellReason::Call => "calls",
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum Event {
     Start,
+    /// Storage migrations are done, accounts can connect
+    StorageReady,
     Connect(ConnectionInfo, Password),
+    /// A connection attempt started, with the number of previous failures
+    Connecting(Account, u32),
+    /// An autoconnect attempt failed before being online
+    ConnectionFailed {
+        account: Account,
+        connection_info: ConnectionInfo,
+        password: Password,
+        attempt: u32,
+        error: String,
+    },
+    /// Connect again an account whose connection failed or was lost, with the same resource
+    Reconnect {
+        account: Account,
+        connection_info: ConnectionInfo,
+        password: Password,
+        attempt: u32,
+    },
     Connected(Account, Jid),
     Disconnected(Account, String),
+    /// The connection is dead although not closed, it must be dropped and opened again
+    ConnectionBroken(Account, String),
+    /// Ask the server to acknowledge the stanzas sent recently
+    RequestAck(Account),
+    /// Close the connection for the given reason, without connecting again
+    Disconnect(Account, String),
     AuthError(Account, String),
     Stanza(Account, Element),
     RawMessage {
@@ -111,17 +183,82 @@ pub enum Event {
         payload: StanzaError,
     },
     Disco(Account, Vec<String>),
+    /// Service discovery of an entity, shown in the disco browser
+    DiscoBrowse {
+        account: Account,
+        jid: Jid,
+        node: Option<String>,
+        /// Browsing starts from this entity instead of expanding a listed one
+        root: bool,
+        identities: Vec<String>,
+        features: Vec<String>,
+        items: Vec<disco::Item>,
+    },
+    /// Disco info queried for entity capabilities, features are unknown if the query failed
+    ContactDisco {
+        ver: String,
+        features: Option<Vec<String>>,
+    },
     PubSub {
         account: Account,
         from: Option<Jid>,
         event: PubSubEvent,
     },
+    /// Subscription to a pubsub node changed, or was found while listing subscriptions
+    PubSubSubscription {
+        account: Account,
+        service: Jid,
+        node: String,
+        subscribed: bool,
+    },
+    /// Item published on a subscribed pubsub node, displayed in the pubsub window
+    PubSubLog(Message),
     Presence(Account, presence::Presence),
     ReadPassword(Command),
     Win(String),
     Close(String),
     Contact(Account, contact::Contact),
     ContactUpdate(Account, contact::Contact),
+    /// Avatar of a contact retrieved, or found in storage
+    Avatar {
+        account: Account,
+        jid: BareJid,
+        hash: String,
+    },
+    /// Nickname published by a contact, unset when retracted
+    Nickname {
+        account: Account,
+        jid: BareJid,
+        nick: Option<String>,
+    },
+    /// Mood published by a contact, unset when cleared
+    Mood {
+        account: Account,
+        jid: BareJid,
+        mood: Option<contact::Mood>,
+    },
+    /// Track a contact is listening to, unset once stopped
+    Tune {
+        account: Account,
+        jid: BareJid,
+        tune: Option<contact::Tune>,
+    },
+    /// Track played by the local media player changed, unset once stopped
+    NowPlaying(Option<contact::Tune>),
+    /// Location shared by a contact, unset once they stop sharing it
+    Location {
+        account: Account,
+        jid: BareJid,
+        location: Option<contact::Location>,
+    },
+    /// Own location to share with accounts opted in, unset to stop sharing it
+    ShareLocation(Option<contact::Location>),
+    /// Blocked jids of the account, as returned by the server
+    Blocklist(Account, Vec<Jid>),
+    /// Notes about contacts kept in the private storage of the account
+    Notes(Account, Vec<contact::Note>),
+    /// Contact added to the roster after it has been retrieved
+    ContactAdded(Account, BareJid),
     Bookmark(Account, contact::Bookmark),
     BookmarksUpdate(Account, Vec<contact::Bookmark>),
     DeletedBookmark(BareJid),
@@ -130,6 +267,12 @@ pub enum Event {
         conversation: BareJid,
         occupant: conversation::Occupant,
     },
+    OccupantRenamed {
+        account: Account,
+        conversation: BareJid,
+        previous: String,
+        occupant: conversation::Occupant,
+    },
     WindowChange,
     LoadChannelHistory {
         account: Account,
@@ -141,8 +284,18 @@ pub enum Event {
         contact: BareJid,
         from: Option<DateTime<FixedOffset>>,
     },
+    /// A history request is over, complete is set when there is no older message to load
+    HistoryLoaded {
+        account: Account,
+        conversation: BareJid,
+        complete: bool,
+    },
     Quit,
     Key(Key),
+    /// The terminal gained or lost focus
+    Focus(bool),
+    /// Check whether nothing was typed for the idle timeout
+    IdleTimeout,
     AutoComplete {
         account: Option<Account>,
         context: String,
@@ -156,9 +309,159 @@ pub enum Event {
         conversation: conversation::Conversation,
         important: bool,
     },
+    /// Batching delay of important messages of a conversation has elapsed
+    NotificationTimeout {
+        account: Account,
+        conversation: BareJid,
+        generation: u64,
+    },
+    /// Ring the bell, audible or visual as configured
+    Bell(BellReason),
     Subject(Account, Jid, HashMap<String, String>),
+    /// Reveal the spoiler of the given message of a conversation, or all of them when none is
+    /// given
+    RevealSpoilers {
+        account: Account,
+        conversation: BareJid,
+        id: Option<String>,
+    },
+    /// Display the whole body of the given truncated message of a conversation, or of the last
+    /// one when none is given
+    ExpandMessage {
+        account: Account,
+        conversation: BareJid,
+        id: Option<String>,
+    },
     Omemo(mods::omemo::OmemoEvent),
+    /// Alternative connection methods announced in the host-meta of a domain
+    AltConnections(String, Vec<mods::host_meta::AltConnection>),
+    /// Response of an entity to an ad-hoc command request
+    AdhocStep(mods::adhoc::AdhocStep),
+    /// Let the user fill a data form, the answer being given back with the same id
+    ShowForm {
+        id: String,
+        form: DataForm,
+    },
+    /// Data form filled by the user, unset when canceled
+    FormSubmit {
+        id: String,
+        form: Option<DataForm>,
+    },
+    /// Traced events to display in the events window
+    EventTrace(Vec<Message>),
+    /// Page of archived messages of a contact being imported, identified by its MAM queryid
+    HistoryImport {
+        account: Account,
+        contact: BareJid,
+        queryid: String,
+    },
+    /// A page of archived messages has been imported, or the whole archive when complete
+    HistoryImported {
+        account: Account,
+        contact: BareJid,
+        complete: bool,
+    },
+    /// Status displayed in a conversation window, replacing the previous one with the same id
+    ConversationLog {
+        account: Account,
+        conversation: BareJid,
+        message: LogMessage,
+    },
+    /// Pinned messages to display, of all conversations when none is given
+    Pins {
+        conversation: Option<BareJid>,
+        entries: Vec<Message>,
+    },
+    /// Public channels hosted by a multi-user chat service
+    ChannelList {
+        account: Account,
+        service: Jid,
+        channels: Vec<mods::channels::ChannelListing>,
+    },
+    /// Users affiliated with a channel
+    RoomMembers {
+        account: Account,
+        channel: BareJid,
+        members: Vec<mods::muc::RoomMember>,
+        /// Only display members containing this text
+        filter: Option<String>,
+    },
+    /// Notifications not sent in a conversation, or by default when conversation is None
+    Privacy {
+        conversation: Option<BareJid>,
+        withheld: Vec<mods::receipts::Notification>,
+    },
+    /// Chat state to send to a contact, subject to privacy settings
+    ChatState {
+        account: Account,
+        contact: BareJid,
+        state: ChatState,
+    },
+    /// Time to check the connection of the account is still alive
+    Ping {
+        account: Account,
+        generation: u64,
+    },
+    /// Round trip of the last ping of the server of the account
+    Lag(Account, Duration),
+    /// We stopped typing in the conversation since the given keystroke
+    ComposingTimeout {
+        account: Account,
+        contact: BareJid,
+        generation: u64,
+    },
+    /// Channel reflected a message we sent, the local echo is replaced by the reflection
+    MessageReflected {
+        account: Account,
+        echo: Message,
+        message: Message,
+    },
+    /// Delivery state of an outgoing message has been updated by the contact
+    MessageDelivery {
+        account: Account,
+        contact: BareJid,
+        id: String,
+        delivery: Delivery,
+    },
+    /// Conversation window has been scrolled down to its last message
+    ChatScrolledToBottom {
+        account: Account,
+        contact: BareJid,
+    },
+    /// Chat state received from a contact
+    ContactChatState {
+        account: Account,
+        contact: BareJid,
+        state: ChatState,
+    },
+    /// Current window has been changed
+    WindowChanged(String),
+    /// Incoming channel message mentioning us
+    Mention {
+        account: Account,
+        message: VersionedXmppMessage,
+    },
+    /// Visibility of occupants' real jid in a channel
+    ChannelAnonymity {
+        account: Account,
+        channel: BareJid,
+        anonymity: conversation::Anonymity,
+    },
+    /// Error reported to the user
+    Error(String),
+    /// New entry of the activity log
+    Activity {
+        account: Option<Account>,
+        message: Message,
+    },
+    /// Activity log entries to display, only those of the filtered account if any
+    ActivityLog {
+        filter: Option<BareJid>,
+        entries: Vec<Message>,
+    },
     UIRender,
+    /// Periodic event emitted at the start of each minute
+    Tick,
 }
 
 pub enum Mod {
@@ -173,6 +476,35 @@ pub enum Mod {
     Mam(mods::mam::MamMod),
     Correction(mods::correction::CorrectionMod),
     Omemo(mods::omemo::OmemoMod),
+    Trace(mods::trace::TraceMod),
+    Channels(mods::channels::ChannelsMod),
+    Muc(mods::muc::MucMod),
+    Receipts(mods::receipts::ReceiptsMod),
+    Activity(mods::activity::ActivityMod),
+    PubSub(mods::pubsub::PubSubMod),
+    HostMeta(mods::host_meta::HostMetaMod),
+    Sessions(mods::sessions::SessionsMod),
+    Reactions(mods::reactions::ReactionsMod),
+    Idle(mods::idle::IdleMod),
+    Location(mods::location::LocationMod),
+    Tune(mods::tune::TuneMod),
+    Mood(mods::mood::MoodMod),
+    QuietHours(mods::quiet_hours::QuietHoursMod),
+    Nick(mods::nick::NickMod),
+    Adhoc(mods::adhoc::AdhocMod),
+    Csi(mods::csi::CsiMod),
+    Ping(mods::ping::PingMod),
+    Version(mods::version::VersionMod),
+    LastVersion(mods::last::LastVersionMod),
+    Avatar(mods::avatar::AvatarMod),
+    Retraction(mods::retraction::RetractionMod),
+    Download(mods::download::DownloadMod),
+    Blocking(mods::blocking::BlockingMod),
+    Notes(mods::notes::NotesMod),
+    Calls(mods::calls::CallsMod),
+    Upload(mods::upload::UploadMod),
+    ChatStates(mods::chatstates::ChatStatesMod),
+    Notifications(mods::notifications::NotificationsMod),
 }
 
 macro_rules! from_mod {
@@ -207,10 +539,45 @@ from_mod!(UI, mods::ui::UIMod);
 from_mod!(Mam, mods::mam::MamMod);
 from_mod!(Messages, mods::messages::MessagesMod);
 from_mod!(Correction, mods::correction::CorrectionMod);
+from_mod!(Trace, mods::trace::TraceMod);
+from_mod!(Channels, mods::channels::ChannelsMod);
+from_mod!(Muc, mods::muc::MucMod);
+from_mod!(Receipts, mods::receipts::ReceiptsMod);
+from_mod!(Activity, mods::activity::ActivityMod);
+from_mod!(PubSub, mods::pubsub::PubSubMod);
+from_mod!(HostMeta, mods::host_meta::HostMetaMod);
+from_mod!(Sessions, mods::sessions::SessionsMod);
+from_mod!(Reactions, mods::reactions::ReactionsMod);
+from_mod!(Idle, mods::idle::IdleMod);
+from_mod!(Location, mods::location::LocationMod);
+from_mod!(Tune, mods::tune::TuneMod);
+from_mod!(Mood, mods::mood::MoodMod);
+from_mod!(QuietHours, mods::quiet_hours::QuietHoursMod);
+from_mod!(Nick, mods::nick::NickMod);
+from_mod!(Adhoc, mods::adhoc::AdhocMod);
+from_mod!(Csi, mods::csi::CsiMod);
+from_mod!(Ping, mods::ping::PingMod);
+from_mod!(Version, mods::version::VersionMod);
+from_mod!(LastVersion, mods::last::LastVersionMod);
+from_mod!(Avatar, mods::avatar::AvatarMod);
+from_mod!(Retraction, mods::retraction::RetractionMod);
+from_mod!(Download, mods::download::DownloadMod);
+from_mod!(Blocking, mods::blocking::BlockingMod);
+from_mod!(Notes, mods::notes::NotesMod);
+from_mod!(Calls, mods::calls::CallsMod);
+from_mod!(Upload, mods::upload::UploadMod);
+from_mod!(ChatStates, mods::chatstates::ChatStatesMod);
+from_mod!(Notifications, mods::notifications::NotificationsMod);
 
 pub trait ModTrait: Display {
     fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()>;
     fn on_event(&mut self, aparte: &mut Aparte, event: &Event);
+    /// Handle an event given by value and give it back for the next mods. Mods forwarding
+    /// events to structures of their own override it to avoid cloning them.
+    fn on_owned_event(&mut self, aparte: &mut Aparte, event: Event) -> Event {
+        self.on_event(aparte, &event);
+        event
+    }
     /// Return weither this message can be handled
     /// 0 means no, 1 mean definitely yes
     fn can_handle_xmpp_message(
@@ -249,6 +616,35 @@ impl ModTrait for Mod {
             Mod::Messages(r#mod) => r#mod.init(aparte),
             Mod::Correction(r#mod) => r#mod.init(aparte),
             Mod::Omemo(r#mod) => r#mod.init(aparte),
+            Mod::Trace(r#mod) => r#mod.init(aparte),
+            Mod::Channels(r#mod) => r#mod.init(aparte),
+            Mod::Muc(r#mod) => r#mod.init(aparte),
+            Mod::Receipts(r#mod) => r#mod.init(aparte),
+            Mod::Activity(r#mod) => r#mod.init(aparte),
+            Mod::PubSub(r#mod) => r#mod.init(aparte),
+            Mod::HostMeta(r#mod) => r#mod.init(aparte),
+            Mod::Sessions(r#mod) => r#mod.init(aparte),
+            Mod::Reactions(r#mod) => r#mod.init(aparte),
+            Mod::Idle(r#mod) => r#mod.init(aparte),
+            Mod::Location(r#mod) => r#mod.init(aparte),
+            Mod::Tune(r#mod) => r#mod.init(aparte),
+            Mod::Mood(r#mod) => r#mod.init(aparte),
+            Mod::QuietHours(r#mod) => r#mod.init(aparte),
+            Mod::Nick(r#mod) => r#mod.init(aparte),
+            Mod::Adhoc(r#mod) => r#mod.init(aparte),
+            Mod::Csi(r#mod) => r#mod.init(aparte),
+            Mod::Ping(r#mod) => r#mod.init(aparte),
+            Mod::Version(r#mod) => r#mod.init(aparte),
+            Mod::LastVersion(r#mod) => r#mod.init(aparte),
+            Mod::Avatar(r#mod) => r#mod.init(aparte),
+            Mod::Retraction(r#mod) => r#mod.init(aparte),
+            Mod::Download(r#mod) => r#mod.init(aparte),
+            Mod::Blocking(r#mod) => r#mod.init(aparte),
+            Mod::Notes(r#mod) => r#mod.init(aparte),
+            Mod::Calls(r#mod) => r#mod.init(aparte),
+            Mod::Upload(r#mod) => r#mod.init(aparte),
+            Mod::ChatStates(r#mod) => r#mod.init(aparte),
+            Mod::Notifications(r#mod) => r#mod.init(aparte),
         }
     }
 
@@ -265,6 +661,45 @@ impl ModTrait for Mod {
             Mod::Messages(r#mod) => r#mod.on_event(aparte, event),
             Mod::Correction(r#mod) => r#mod.on_event(aparte, event),
             Mod::Omemo(r#mod) => r#mod.on_event(aparte, event),
+            Mod::Trace(r#mod) => r#mod.on_event(aparte, event),
+            Mod::Channels(r#mod) => r#mod.on_event(aparte, event),
+            Mod::Muc(r#mod) => r#mod.on_event(aparte, event),
+            Mod::Receipts(r#mod) => r#mod.on_event(aparte, event),
+            Mod::Activity(r#mod) => r#mod.on_event(aparte, event),
+            Mod::PubSub(r#mod) => r#mod.on_event(aparte, event),
+            Mod::HostMeta(r#mod) => r#mod.on_event(aparte, event),
+            Mod::Sessions(r#mod) => r#mod.on_event(aparte, event),
+            Mod::Reactions(r#mod) => r#mod.on_event(aparte, event),
+            Mod::Idle(r#mod) => r#mod.on_event(aparte, event),
+            Mod::Location(r#mod) => r#mod.on_event(aparte, event),
+            Mod::Tune(r#mod) => r#mod.on_event(aparte, event),
+            Mod::Mood(r#mod) => r#mod.on_event(aparte, event),
+            Mod::QuietHours(r#mod) => r#mod.on_event(aparte, event),
+            Mod::Nick(r#mod) => r#mod.on_event(aparte, event),
+            Mod::Adhoc(r#mod) => r#mod.on_event(aparte, event),
+            Mod::Csi(r#mod) => r#mod.on_event(aparte, event),
+            Mod::Ping(r#mod) => r#mod.on_event(aparte, event),
+            Mod::Version(r#mod) => r#mod.on_event(aparte, event),
+            Mod::LastVersion(r#mod) => r#mod.on_event(aparte, event),
+            Mod::Avatar(r#mod) => r#mod.on_event(aparte, event),
+            Mod::Retraction(r#mod) => r#mod.on_event(aparte, event),
+            Mod::Download(r#mod) => r#mod.on_event(aparte, event),
+            Mod::Blocking(r#mod) => r#mod.on_event(aparte, event),
+            Mod::Notes(r#mod) => r#mod.on_event(aparte, event),
+            Mod::Calls(r#mod) => r#mod.on_event(aparte, event),
+            Mod::Upload(r#mod) => r#mod.on_event(aparte, event),
+            Mod::ChatStates(r#mod) => r#mod.on_event(aparte, event),
+            Mod::Notifications(r#mod) => r#mod.on_event(aparte, event),
+        }
+    }
+
+    fn on_owned_event(&mut self, aparte: &mut Aparte, event: Event) -> Event {
+        match self {
+            Mod::UI(r#mod) => r#mod.on_owned_event(aparte, event),
+            _ => {
+                self.on_event(aparte, &event);
+                event
+            }
         }
     }
 
@@ -293,6 +728,45 @@ impl ModTrait for Mod {
                 r#mod.can_handle_xmpp_message(aparte, account, message, delay)
             }
             Mod::Omemo(r#mod) => r#mod.can_handle_xmpp_message(aparte, account, message, delay),
+            Mod::Trace(r#mod) => r#mod.can_handle_xmpp_message(aparte, account, message, delay),
+            Mod::Channels(r#mod) => r#mod.can_handle_xmpp_message(aparte, account, message, delay),
+            Mod::Muc(r#mod) => r#mod.can_handle_xmpp_message(aparte, account, message, delay),
+            Mod::Receipts(r#mod) => r#mod.can_handle_xmpp_message(aparte, account, message, delay),
+            Mod::Activity(r#mod) => r#mod.can_handle_xmpp_message(aparte, account, message, delay),
+            Mod::PubSub(r#mod) => r#mod.can_handle_xmpp_message(aparte, account, message, delay),
+            Mod::HostMeta(r#mod) => r#mod.can_handle_xmpp_message(aparte, account, message, delay),
+            Mod::Sessions(r#mod) => r#mod.can_handle_xmpp_message(aparte, account, message, delay),
+            Mod::Reactions(r#mod) => r#mod.can_handle_xmpp_message(aparte, account, message, delay),
+            Mod::Idle(r#mod) => r#mod.can_handle_xmpp_message(aparte, account, message, delay),
+            Mod::Location(r#mod) => r#mod.can_handle_xmpp_message(aparte, account, message, delay),
+            Mod::Tune(r#mod) => r#mod.can_handle_xmpp_message(aparte, account, message, delay),
+            Mod::Mood(r#mod) => r#mod.can_handle_xmpp_message(aparte, account, message, delay),
+            Mod::QuietHours(r#mod) => {
+                r#mod.can_handle_xmpp_message(aparte, account, message, delay)
+            }
+            Mod::Nick(r#mod) => r#mod.can_handle_xmpp_message(aparte, account, message, delay),
+            Mod::Adhoc(r#mod) => r#mod.can_handle_xmpp_message(aparte, account, message, delay),
+            Mod::Csi(r#mod) => r#mod.can_handle_xmpp_message(aparte, account, message, delay),
+            Mod::Ping(r#mod) => r#mod.can_handle_xmpp_message(aparte, account, message, delay),
+            Mod::Version(r#mod) => r#mod.can_handle_xmpp_message(aparte, account, message, delay),
+            Mod::LastVersion(r#mod) => {
+                r#mod.can_handle_xmpp_message(aparte, account, message, delay)
+            }
+            Mod::Avatar(r#mod) => r#mod.can_handle_xmpp_message(aparte, account, message, delay),
+            Mod::Retraction(r#mod) => {
+                r#mod.can_handle_xmpp_message(aparte, account, message, delay)
+            }
+            Mod::Download(r#mod) => r#mod.can_handle_xmpp_message(aparte, account, message, delay),
+            Mod::Blocking(r#mod) => r#mod.can_handle_xmpp_message(aparte, account, message, delay),
+            Mod::Notes(r#mod) => r#mod.can_handle_xmpp_message(aparte, account, message, delay),
+            Mod::Calls(r#mod) => r#mod.can_handle_xmpp_message(aparte, account, message, delay),
+            Mod::Upload(r#mod) => r#mod.can_handle_xmpp_message(aparte, account, message, delay),
+            Mod::ChatStates(r#mod) => {
+                r#mod.can_handle_xmpp_message(aparte, account, message, delay)
+            }
+            Mod::Notifications(r#mod) => {
+                r#mod.can_handle_xmpp_message(aparte, account, message, delay)
+            }
         }
     }
 
@@ -334,6 +808,79 @@ impl ModTrait for Mod {
             Mod::Omemo(r#mod) => {
                 r#mod.handle_xmpp_message(aparte, account, message, delay, archive)
             }
+            Mod::Trace(r#mod) => {
+                r#mod.handle_xmpp_message(aparte, account, message, delay, archive)
+            }
+            Mod::Channels(r#mod) => {
+                r#mod.handle_xmpp_message(aparte, account, message, delay, archive)
+            }
+            Mod::Muc(r#mod) => r#mod.handle_xmpp_message(aparte, account, message, delay, archive),
+            Mod::Receipts(r#mod) => {
+                r#mod.handle_xmpp_message(aparte, account, message, delay, archive)
+            }
+            Mod::Activity(r#mod) => {
+                r#mod.handle_xmpp_message(aparte, account, message, delay, archive)
+            }
+            Mod::PubSub(r#mod) => {
+                r#mod.handle_xmpp_message(aparte, account, message, delay, archive)
+            }
+            Mod::HostMeta(r#mod) => {
+                r#mod.handle_xmpp_message(aparte, account, message, delay, archive)
+            }
+            Mod::Sessions(r#mod) => {
+                r#mod.handle_xmpp_message(aparte, account, message, delay, archive)
+            }
+            Mod::Reactions(r#mod) => {
+                r#mod.handle_xmpp_message(aparte, account, message, delay, archive)
+            }
+            Mod::Idle(r#mod) => r#mod.handle_xmpp_message(aparte, account, message, delay, archive),
+            Mod::Location(r#mod) => {
+                r#mod.handle_xmpp_message(aparte, account, message, delay, archive)
+            }
+            Mod::Tune(r#mod) => r#mod.handle_xmpp_message(aparte, account, message, delay, archive),
+            Mod::Mood(r#mod) => r#mod.handle_xmpp_message(aparte, account, message, delay, archive),
+            Mod::QuietHours(r#mod) => {
+                r#mod.handle_xmpp_message(aparte, account, message, delay, archive)
+            }
+            Mod::Nick(r#mod) => r#mod.handle_xmpp_message(aparte, account, message, delay, archive),
+            Mod::Adhoc(r#mod) => {
+                r#mod.handle_xmpp_message(aparte, account, message, delay, archive)
+            }
+            Mod::Csi(r#mod) => r#mod.handle_xmpp_message(aparte, account, message, delay, archive),
+            Mod::Ping(r#mod) => r#mod.handle_xmpp_message(aparte, account, message, delay, archive),
+            Mod::Version(r#mod) => {
+                r#mod.handle_xmpp_message(aparte, account, message, delay, archive)
+            }
+            Mod::LastVersion(r#mod) => {
+                r#mod.handle_xmpp_message(aparte, account, message, delay, archive)
+            }
+            Mod::Avatar(r#mod) => {
+                r#mod.handle_xmpp_message(aparte, account, message, delay, archive)
+            }
+            Mod::Retraction(r#mod) => {
+                r#mod.handle_xmpp_message(aparte, account, message, delay, archive)
+            }
+            Mod::Download(r#mod) => {
+                r#mod.handle_xmpp_message(aparte, account, message, delay, archive)
+            }
+            Mod::Blocking(r#mod) => {
+                r#mod.handle_xmpp_message(aparte, account, message, delay, archive)
+            }
+            Mod::Notes(r#mod) => {
+                r#mod.handle_xmpp_message(aparte, account, message, delay, archive)
+            }
+            Mod::Calls(r#mod) => {
+                r#mod.handle_xmpp_message(aparte, account, message, delay, archive)
+            }
+            Mod::Upload(r#mod) => {
+                r#mod.handle_xmpp_message(aparte, account, message, delay, archive)
+            }
+            Mod::ChatStates(r#mod) => {
+                r#mod.handle_xmpp_message(aparte, account, message, delay, archive)
+            }
+            Mod::Notifications(r#mod) => {
+                r#mod.handle_xmpp_message(aparte, account, message, delay, archive)
+            }
         }
     }
 }
@@ -352,6 +899,35 @@ impl fmt::Debug for Mod {
             Mod::Messages(_) => f.write_str("Mod::Messages"),
             Mod::Correction(_) => f.write_str("Mod::Correction"),
             Mod::Omemo(_) => f.write_str("Mod::Omemo"),
+            Mod::Trace(_) => f.write_str("Mod::Trace"),
+            Mod::Channels(_) => f.write_str("Mod::Channels"),
+            Mod::Muc(_) => f.write_str("Mod::Muc"),
+            Mod::Receipts(_) => f.write_str("Mod::Receipts"),
+            Mod::Activity(_) => f.write_str("Mod::Activity"),
+            Mod::PubSub(_) => f.write_str("Mod::PubSub"),
+            Mod::HostMeta(_) => f.write_str("Mod::HostMeta"),
+            Mod::Sessions(_) => f.write_str("Mod::Sessions"),
+            Mod::Reactions(_) => f.write_str("Mod::Reactions"),
+            Mod::Idle(_) => f.write_str("Mod::Idle"),
+            Mod::Location(_) => f.write_str("Mod::Location"),
+            Mod::Tune(_) => f.write_str("Mod::Tune"),
+            Mod::Mood(_) => f.write_str("Mod::Mood"),
+            Mod::QuietHours(_) => f.write_str("Mod::QuietHours"),
+            Mod::Nick(_) => f.write_str("Mod::Nick"),
+            Mod::Adhoc(_) => f.write_str("Mod::Adhoc"),
+            Mod::Csi(_) => f.write_str("Mod::Csi"),
+            Mod::Ping(_) => f.write_str("Mod::Ping"),
+            Mod::Version(_) => f.write_str("Mod::Version"),
+            Mod::LastVersion(_) => f.write_str("Mod::LastVersion"),
+            Mod::Avatar(_) => f.write_str("Mod::Avatar"),
+            Mod::Retraction(_) => f.write_str("Mod::Retraction"),
+            Mod::Download(_) => f.write_str("Mod::Download"),
+            Mod::Blocking(_) => f.write_str("Mod::Blocking"),
+            Mod::Notes(_) => f.write_str("Mod::Notes"),
+            Mod::Calls(_) => f.write_str("Mod::Calls"),
+            Mod::Upload(_) => f.write_str("Mod::Upload"),
+            Mod::ChatStates(_) => f.write_str("Mod::ChatStates"),
+            Mod::Notifications(_) => f.write_str("Mod::Notifications"),
         }
     }
 }
@@ -370,6 +946,35 @@ impl Display for Mod {
             Mod::Messages(r#mod) => r#mod.fmt(f),
             Mod::Correction(r#mod) => r#mod.fmt(f),
             Mod::Omemo(r#mod) => r#mod.fmt(f),
+            Mod::Trace(r#mod) => r#mod.fmt(f),
+            Mod::Channels(r#mod) => r#mod.fmt(f),
+            Mod::Muc(r#mod) => r#mod.fmt(f),
+            Mod::Receipts(r#mod) => r#mod.fmt(f),
+            Mod::Activity(r#mod) => r#mod.fmt(f),
+            Mod::PubSub(r#mod) => r#mod.fmt(f),
+            Mod::HostMeta(r#mod) => r#mod.fmt(f),
+            Mod::Sessions(r#mod) => r#mod.fmt(f),
+            Mod::Reactions(r#mod) => r#mod.fmt(f),
+            Mod::Idle(r#mod) => r#mod.fmt(f),
+            Mod::Location(r#mod) => r#mod.fmt(f),
+            Mod::Tune(r#mod) => r#mod.fmt(f),
+            Mod::Mood(r#mod) => r#mod.fmt(f),
+            Mod::QuietHours(r#mod) => r#mod.fmt(f),
+            Mod::Nick(r#mod) => r#mod.fmt(f),
+            Mod::Adhoc(r#mod) => r#mod.fmt(f),
+            Mod::Csi(r#mod) => r#mod.fmt(f),
+            Mod::Ping(r#mod) => r#mod.fmt(f),
+            Mod::Version(r#mod) => r#mod.fmt(f),
+            Mod::LastVersion(r#mod) => r#mod.fmt(f),
+            Mod::Avatar(r#mod) => r#mod.fmt(f),
+            Mod::Retraction(r#mod) => r#mod.fmt(f),
+            Mod::Download(r#mod) => r#mod.fmt(f),
+            Mod::Blocking(r#mod) => r#mod.fmt(f),
+            Mod::Notes(r#mod) => r#mod.fmt(f),
+            Mod::Calls(r#mod) => r#mod.fmt(f),
+            Mod::Upload(r#mod) => r#mod.fmt(f),
+            Mod::ChatStates(r#mod) => r#mod.fmt(f),
+            Mod::Notifications(r#mod) => r#mod.fmt(f),
         }
     }
 }
@@ -377,6 +982,10 @@ impl Display for Mod {
 pub struct Connection {
     pub sink: mpsc::UnboundedSender<Element>,
     pub account: FullJid,
+    /// Drop the connection with a reason, connecting again when it is known to be dead
+    pub kill: oneshot::Sender<(String, bool)>,
+    /// Ask the server to acknowledge sent stanzas once online
+    pub stream_management: bool,
 }
 
 command_def!(connect,
@@ -418,7 +1027,15 @@ Examples:
                 server: None,
                 port: None,
                 autoconnect: false,
+                autoconnect_retry: false,
                 password: None,
+                lang: None,
+                nick: None,
+                resource: None,
+                random_resource: false,
+                stream_management: false,
+                quiet_hours: Vec::new(),
+                share_location: false,
             }
         } else {
             anyhow::bail!("Unknown account or invalid jid {account_name}");
@@ -501,79 +1118,86 @@ Examples:
     window: Option<String> = {
         completion: |aparte, _command| {
             let ui = aparte.get_mod::<mods::ui::UIMod>();
-            let conversation_mod = aparte.get_mod::<mods::conversation::ConversationMod>();
-            ui.get_windows().iter().map(|window| {
-                if let Some(account) = aparte.current_account() {
-                    if let Ok(jid) = BareJid::from_str(&window) {
-                        conversation_mod.get(&account, &jid).cloned()
-                    } else {
-                        None
-                    }
-                } else {
-                    None
-                }
-            }).filter_map(|conversation| {
-                if let Some(Conversation::Channel(channel)) = conversation {
-                    Some(channel.jid.into_inner())
-                } else {
-                    None
-                }
+            ui.get_windows().into_iter().filter(|window| {
+                matches!(ui.window_conversation(window), Some(Conversation::Channel(_)))
             }).collect()
         }
     }
 },
 |aparte, _command| {
-    let current =  {
+    let target = {
         let ui = aparte.get_mod::<mods::ui::UIMod>();
-        ui.current_window().cloned()
+        ui.conversation_target(aparte, window)
     };
-    let window = window.or(current);
-    if let Some(window) = window {
-        if let Some(account) = aparte.current_account() {
-            if let Ok(jid) = BareJid::from_str(&window) {
-                let conversation =  {
-                    let conversation_mod = aparte.get_mod::<mods::conversation::ConversationMod>();
-                    conversation_mod.get(&account, &jid).cloned()
-                };
-                if let Some(Conversation::Channel(channel)) = conversation {
-                    aparte.schedule(Event::Leave(channel));
-                }
-            }
+    if let Ok((account, jid)) = target {
+        let conversation =  {
+            let conversation_mod = aparte.get_mod::<mods::conversation::ConversationMod>();
+            conversation_mod.get(&account, &jid).cloned()
+        };
+        if let Some(Conversation::Channel(channel)) = conversation {
+            aparte.schedule(Event::Leave(channel));
         }
     }
     Ok(())
 });
 
 command_def!(msg,
-r#"/msg <contact> [<message>]
+r#"/msg [-a <account>] <contact> [<message>]
 
+    account       Connected account to use instead of the current one
     contact       Contact to send a message to
     message       Optionnal message to be sent
 
 Description:
     Open a window for a private discussion with a given contact and optionnaly
-    send a message.
+    send a message. When the contact is already chatted with from another
+    account, a separate window labeled with the account is opened.
 
 Example:
     /msg contact@server.tld
     /msg contact@server.tld "Hi there!"
+    /msg -a me@other.tld contact@server.tld
 "#,
 {
     contact: String = {
         completion: |aparte, _command| {
             let contact = aparte.get_mod::<mods::contact::ContactMod>();
-            contact.contacts.values().map(|contact| contact.jid.to_string()).collect()
+            contact.contacts.values().map(|contact| contact.jid.to_string()).chain(iter::once(String::from("-a"))).collect()
+        }
+    },
+    message: Option<String> = {
+        completion: |aparte, command| {
+            match command.args.get(1).map(String::as_str) {
+                Some("-a") => aparte.connected_accounts().iter().map(|account| account.to_bare().to_string()).collect(),
+                _ => Vec::new(),
+            }
         }
     },
-    message: Option<String>
 },
-|aparte, _command| {
-    let account = aparte.current_account().context("No connection found")?;
+|aparte, command| {
+    // Account is given as a leading -a flag
+    let (account, contact, message) = match contact.as_str() {
+        "-a" => {
+            let bare = message.context("Missing account")?;
+            let bare = BareJid::from_str(&bare).context("Invalid account")?;
+            let account = aparte
+                .connected_accounts()
+                .into_iter()
+                .find(|account| account.to_bare() == bare)
+                .with_context(|| format!("No connection found for {bare}"))?;
+            let contact = command.args.get(3).cloned().context("Missing contact argument")?;
+            (account, contact, command.args.get(4).cloned())
+        }
+        _ => (aparte.current_account().context("No connection found")?, contact, message),
+    };
     let jid = Jid::from_str(&contact).context("Invalid JID")?;
     aparte.schedule(Event::Chat { account: account.clone(), contact: jid.to_bare() });
     if let Some(body) = message {
+        let lang = aparte
+            .get_mod::<mods::conversation::ConversationMod>()
+            .get_lang(aparte, &account, &jid.to_bare());
         let mut bodies = HashMap::new();
-        bodies.insert("".to_string(), body);
+        bodies.insert(lang, body);
         let id = Uuid::new_v4().to_string();
         let from: Jid = account.clone().into();
         let timestamp = LocalTz::now();
@@ -598,7 +1222,11 @@ Example:
     muc: String = {
         completion: |aparte, _command| {
             let bookmarks = aparte.get_mod::<mods::bookmarks::BookmarksMod>();
-            bookmarks.bookmarks_by_name.keys().cloned().chain(bookmarks.bookmarks_by_jid.keys().map(|a| a.to_string())).collect()
+            let listed = match aparte.current_account() {
+                Some(account) => aparte.get_mod::<mods::channels::ChannelsMod>().listed_channels(&account),
+                None => Vec::new(),
+            };
+            bookmarks.bookmarks_by_name.keys().cloned().chain(bookmarks.bookmarks_by_jid.keys().map(|a| a.to_string())).chain(listed).collect()
         }
     },
 },
@@ -654,6 +1282,309 @@ Example:
     }
 );
 
+command_def!(
+    stats,
+    r#"/stats
+
+Description:
+    Show performance counters: time spent by each mod handling events,
+    rendering durations and stanza throughput.
+
+Example:
+    /stats"#,
+    {},
+    |aparte, _command| {
+        let mut report = String::from("Performance counters:");
+        let stats = &aparte.stats;
+
+        report.push_str(&format!(
+            "\n  uptime: {}s",
+            stats.started.elapsed().as_secs()
+        ));
+        report.push_str(&format!("\n  startup: {}", stats.startup_report()));
+        report.push_str(&format!(
+            "\n  stanzas: {} received ({:.2}/s), {} sent ({:.2}/s)",
+            stats.received_stanzas,
+            stats.throughput(stats.received_stanzas),
+            stats.sent_stanzas,
+            stats.throughput(stats.sent_stanzas),
+        ));
+        report.push_str(&format!(
+            "\n  render: {} renders, avg {:?}, max {:?}",
+            stats.renders.count,
+            stats.renders.average(),
+            stats.renders.max,
+        ));
+
+        let mut mods = stats.mods.iter().collect::<Vec<_>>();
+        mods.sort_by(|(_, a), (_, b)| b.total.cmp(&a.total));
+        for (type_id, timing) in mods {
+            let name = match aparte.mods.get(type_id).map(|r#mod| r#mod.try_read()) {
+                Some(Ok(r#mod)) => r#mod.to_string(),
+                _ => continue,
+            };
+            report.push_str(&format!(
+                "\n  {name}: {} events, total {:?}, avg {:?}, max {:?}",
+                timing.count,
+                timing.total,
+                timing.average(),
+                timing.max,
+            ));
+        }
+
+        aparte.log(report);
+
+        Ok(())
+    }
+);
+
+command_def!(
+    queue_list,
+    r#"/queue list
+
+Description:
+    List outgoing messages waiting for their account to be online, and
+    messages sent but not acknowledged by the server yet when stream
+    management is enabled
+
+Examples:
+    /queue list
+"#,
+    {},
+    |aparte, _command| {
+        let entries = aparte.send_queue.entries();
+        let unacked = aparte.stream_management.unacked();
+        let mut accounts: Vec<Account> = entries
+            .iter()
+            .map(|(_, account, _)| account.clone())
+            .chain(unacked.iter().map(|(account, _)| account.clone()))
+            .collect();
+        accounts.sort_by_key(|account| account.to_string());
+        accounts.dedup();
+        if accounts.is_empty() {
+            crate::info!(aparte, "No queued message");
+            return Ok(());
+        }
+
+        let mut report = String::from("Queued messages:");
+        for account in accounts {
+            report.push_str(&format!("\n  {}", account.to_bare()));
+            for (number, _, description) in
+                entries.iter().filter(|(_, queued, _)| queued == &account)
+            {
+                report.push_str(&format!("\n    {number}. {description}"));
+            }
+            for (_, stanza) in unacked.iter().filter(|(sent, _)| sent == &account) {
+                report.push_str(&format!("\n    unacked: {}", SendQueue::describe(stanza)));
+            }
+        }
+        aparte.log(report);
+        Ok(())
+    }
+);
+
+command_def!(queue_drop,
+r#"/queue drop <number>|all
+
+    number    Number of the message as listed by /queue list
+
+Description:
+    Drop queued messages instead of sending them
+
+Examples:
+    /queue drop 2
+    /queue drop all
+"#,
+{
+    number: String = {
+        completion: |_aparte, _command| {
+            vec![String::from("all")]
+        }
+    },
+},
+|aparte, _command| {
+    if number == "all" {
+        let count = aparte.send_queue.clear();
+        crate::info!(aparte, "Dropped {count} queued messages");
+    } else {
+        let number = number.parse::<usize>().context("Invalid message number")?;
+        aparte.send_queue.remove(number).context("No such queued message")?;
+        crate::info!(aparte, "Dropped queued message {number}");
+    }
+    Ok(())
+});
+
+command_def!(
+    queue_retry,
+    r#"/queue retry
+
+Description:
+    Try to send queued messages now, even if their account doesn't seem online.
+    Messages that still cannot be sent are kept in the queue.
+
+Examples:
+    /queue retry
+"#,
+    {},
+    |aparte, _command| {
+        for account in aparte.send_queue.accounts() {
+            for stanza in aparte.send_queue.take(&account) {
+                aparte.write_stanza(account.clone(), stanza);
+            }
+        }
+        Ok(())
+    }
+);
+
+command_def!(queue,
+r#"/queue list|drop|retry"#,
+{
+    action: Command = {
+        children: {
+            "list": queue_list,
+            "drop": queue_drop,
+            "retry": queue_retry,
+        }
+    },
+});
+
+/// Parse a stanza typed by the user, defaulting to the client namespace
+fn parse_raw_stanza(raw: &str) -> Result<Element> {
+    let raw = raw.trim();
+    let name_end = raw
+        .find(|c: char| c.is_whitespace() || c == '>' || c == '/')
+        .context("Invalid stanza")?;
+    let root_end = raw.find('>').context("Invalid stanza")?;
+    let raw = match raw[..root_end].contains("xmlns=") {
+        true => raw.to_string(),
+        false => format!(
+            "{} xmlns='jabber:client'{}",
+            &raw[..name_end],
+            &raw[name_end..]
+        ),
+    };
+
+    let mut stanza = Element::from_str(&raw).context("Invalid stanza")?;
+    if !["message", "presence", "iq"]
+        .iter()
+        .any(|name| stanza.is(*name, "jabber:client"))
+    {
+        anyhow::bail!("Only message, presence and iq stanzas can be sent");
+    }
+    if stanza.name() == "iq" {
+        // Responses are matched by id, which have to be unique
+        stanza.set_attr("id", Uuid::new_v4().hyphenated().to_string());
+    }
+    Ok(stanza)
+}
+
+/// Indented representation of a stanza, namespaces being only shown when they change
+fn pretty_xml(element: &Element, parent_ns: &str, depth: usize) -> String {
+    let indent = "  ".repeat(depth);
+    let mut xml = format!("{indent}<{}", element.name());
+    if element.ns() != parent_ns {
+        xml.push_str(&format!(" xmlns='{}'", escape_xml(&element.ns())));
+    }
+    for (name, value) in element.attrs() {
+        xml.push_str(&format!(" {name}='{}'", escape_xml(value)));
+    }
+
+    if element.children().next().is_some() {
+        xml.push('>');
+        for child in element.children() {
+            xml.push('\n');
+            xml.push_str(&pretty_xml(child, &element.ns(), depth + 1));
+        }
+        xml.push_str(&format!("\n{indent}</{}>", element.name()));
+    } else {
+        match element.text() {
+            text if text.is_empty() => xml.push_str("/>"),
+            text => xml.push_str(&format!(">{}</{}>", escape_xml(&text), element.name())),
+        }
+    }
+    xml
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('\'', "&apos;")
+}
+
+command_def!(xml,
+r#"/xml [account=<account>] <stanza>
+
+    account    Connected account to use instead of the current one
+    stanza     Raw message, presence or iq to send
+
+Description:
+    Send a raw stanza, for instance to test server features. The stanza is
+    checked to be well formed first, the client namespace being used when the
+    root element doesn't declare any. Iq are given a new id, their result or
+    error is then shown in the console along with the time the response took.
+    All sent and received stanzas are written to the log file when aparte
+    is started with --debug.
+
+Examples:
+    /xml "<iq type='get' to='server.tld'><query xmlns='jabber:iq:version'/></iq>"
+    /xml account=me@other.tld "<presence to='contact@server.tld' type='probe'/>"
+"#,
+{
+    account: Named<BareJid>,
+    stanza: String,
+},
+|aparte, _command| {
+    let account = match account {
+        Some(bare) => aparte
+            .connected_accounts()
+            .into_iter()
+            .find(|account| account.to_bare() == bare)
+            .with_context(|| format!("No connection found for {bare}"))?,
+        None => aparte.current_account().context("No connection found")?,
+    };
+    let stanza = parse_raw_stanza(&stanza)?;
+    if stanza.name() != "iq" {
+        aparte.send(&account, stanza);
+        return Ok(());
+    }
+
+    let iq = Iq::try_from(stanza).context("Invalid iq")?;
+    if matches!(iq.payload, IqType::Result(_) | IqType::Error(_)) {
+        aparte.send(&account, iq);
+        return Ok(());
+    }
+    Aparte::spawn({
+        let mut aparte = aparte.proxy();
+        async move {
+            let id = iq.id.clone();
+            let sent = Instant::now();
+            match aparte.iq(&account, iq).await {
+                Ok(response) => {
+                    let latency = sent.elapsed();
+                    let type_ = match response.payload {
+                        IqType::Error(_) => "error",
+                        _ => "result",
+                    };
+                    let from = response
+                        .from
+                        .as_ref()
+                        .map(|from| from.to_string())
+                        .unwrap_or_else(|| account.to_bare().to_string());
+                    let response = Element::from(response);
+                    aparte.log(format!(
+                        "Iq {id} {type_} from {from} after {}ms:\n{}",
+                        latency.as_millis(),
+                        pretty_xml(&response, "jabber:client", 1)
+                    ));
+                }
+                Err(err) => crate::error!(aparte, err, "No response to iq {id}"),
+            }
+        }
+    });
+    Ok(())
+});
+
 command_def!(help,
 r#"/help [command]
 
@@ -724,8 +1655,9 @@ mod me {
                         let to: Jid = chat.contact.clone().into();
                         let id = Uuid::new_v4();
                         let timestamp = LocalTz::now().into();
+                        let lang = conversation.get_lang(aparte, account, &chat.contact);
                         let mut bodies = HashMap::new();
-                        bodies.insert("".to_string(), command.args[0].clone());
+                        bodies.insert(lang, command.args[0].clone());
                         Ok(Message::outgoing_chat(
                             id.to_string(),
                             timestamp,
@@ -745,8 +1677,9 @@ mod me {
                         let to: Jid = channel.jid.clone().into();
                         let id = Uuid::new_v4();
                         let timestamp = LocalTz::now().into();
+                        let lang = conversation.get_lang(aparte, account, &channel.jid);
                         let mut bodies = HashMap::new();
-                        bodies.insert("".to_string(), command.args[0].clone());
+                        bodies.insert(lang, command.args[0].clone());
                         Ok(Message::outgoing_channel(
                             id.to_string(),
                             timestamp,
@@ -781,6 +1714,8 @@ Examples:
             parse,
             exec,
             autocompletions: vec![],
+            subcommands: None,
+            flags: Vec::new(),
         }
     }
 }
@@ -801,13 +1736,15 @@ macro_rules! info(
 macro_rules! error(
     ($aparte:ident, $err:ident, $msg:literal, $($args: tt)*) => ({
         let context = format!($msg, $($args)*);
+        $aparte.schedule($crate::core::Event::Error(format!("{}: {:#}", context, $err)));
         ::log::error!("{:?}", $err.context(context.clone()));
-        $aparte.log(context)
+        $aparte.log_with_level(::log::Level::Error, context)
     });
     ($aparte:ident, $err:ident, $msg:literal) => ({
         let context = format!($msg);
+        $aparte.schedule($crate::core::Event::Error(format!("{}: {:#}", context, $err)));
         ::log::error!("{:?}", $err.context(context.clone()));
-        $aparte.log(context)
+        $aparte.log_with_level(::log::Level::Error, context)
     });
 );
 
@@ -826,10 +1763,22 @@ pub struct Aparte {
     /// Aparté main configuration
     pub config: Config,
     pub storage: Storage,
+    /// Performance counters
+    pub stats: Stats,
+    /// Messages waiting for their account to be online
+    pub send_queue: SendQueue,
+    stream_management: StreamManagement,
+    /// Report startup timings once the UI is first rendered
+    debug: bool,
+    startup_reported: bool,
+    /// Connections waiting for storage migrations
+    pending_connections: Option<Vec<(ConnectionInfo, Password)>>,
 }
 
 impl Aparte {
-    pub fn new(config_path: PathBuf, storage_path: PathBuf) -> Result<Self> {
+    pub fn new(config_path: PathBuf, storage_path: PathBuf, debug: bool) -> Result<Self> {
+        let mut stats = Stats::new();
+        let phase = Instant::now();
         log::debug!("Loading aparté with {:?}", config_path);
         let mut config_file = OpenOptions::new()
             .read(true)
@@ -854,6 +1803,16 @@ impl Aparte {
             },
         };
 
+        if let Some(languages) = &config.languages {
+            i18n::set_preferred_langs(languages.clone());
+        }
+        redact::set_redact_contents(!config.log_message_contents.unwrap_or(false));
+        stats.record_startup("config", phase);
+
+        let phase = Instant::now();
+        let storage = Storage::new(storage_path)?;
+        stats.record_startup("storage", phase);
+
         let (event_tx, event_rx) = mpsc::unbounded_channel();
         let (send_tx, send_rx) = mpsc::unbounded_channel();
 
@@ -861,7 +1820,7 @@ impl Aparte {
             command_parsers: Arc::new(HashMap::new()),
             mods: Arc::new(HashMap::new()),
             connections: HashMap::new(),
-            storage: Storage::new(storage_path)?,
+            storage,
             current_connection: None,
             event_tx,
             event_rx: Some(event_rx),
@@ -871,8 +1830,16 @@ impl Aparte {
             pending_iq: Arc::new(Mutex::new(HashMap::new())),
             crypto_engines: Arc::new(Mutex::new(HashMap::new())),
             read_password: AtomicBool::new(false),
+            stats,
+            send_queue: SendQueue::new(),
+            stream_management: StreamManagement::new(),
+            debug,
+            startup_reported: false,
+            pending_connections: Some(Vec::new()),
         };
 
+        let phase = Instant::now();
+
         aparte.add_mod(Mod::Completion(mods::completion::CompletionMod::new()));
         aparte.add_mod(Mod::Carbons(mods::carbons::CarbonsMod::new()));
         aparte.add_mod(Mod::Contact(mods::contact::ContactMod::new()));
@@ -886,6 +1853,38 @@ impl Aparte {
         aparte.add_mod(Mod::Messages(mods::messages::MessagesMod::new()));
         aparte.add_mod(Mod::Correction(mods::correction::CorrectionMod::new()));
         aparte.add_mod(Mod::Omemo(mods::omemo::OmemoMod::new()));
+        aparte.add_mod(Mod::Trace(mods::trace::TraceMod::new()));
+        aparte.add_mod(Mod::Channels(mods::channels::ChannelsMod::new()));
+        aparte.add_mod(Mod::Muc(mods::muc::MucMod::new()));
+        aparte.add_mod(Mod::Receipts(mods::receipts::ReceiptsMod::new()));
+        aparte.add_mod(Mod::Activity(mods::activity::ActivityMod::new()));
+        aparte.add_mod(Mod::PubSub(mods::pubsub::PubSubMod::new()));
+        aparte.add_mod(Mod::HostMeta(mods::host_meta::HostMetaMod::new()));
+        aparte.add_mod(Mod::Sessions(mods::sessions::SessionsMod::new()));
+        aparte.add_mod(Mod::Reactions(mods::reactions::ReactionsMod::new()));
+        aparte.add_mod(Mod::Idle(mods::idle::IdleMod::new()));
+        aparte.add_mod(Mod::Location(mods::location::LocationMod::new()));
+        aparte.add_mod(Mod::Tune(mods::tune::TuneMod::new()));
+        aparte.add_mod(Mod::Mood(mods::mood::MoodMod::new()));
+        aparte.add_mod(Mod::QuietHours(mods::quiet_hours::QuietHoursMod::new()));
+        aparte.add_mod(Mod::Nick(mods::nick::NickMod::new()));
+        aparte.add_mod(Mod::Adhoc(mods::adhoc::AdhocMod::new()));
+        aparte.add_mod(Mod::Csi(mods::csi::CsiMod::new()));
+        aparte.add_mod(Mod::Ping(mods::ping::PingMod::new()));
+        aparte.add_mod(Mod::Version(mods::version::VersionMod::new()));
+        aparte.add_mod(Mod::LastVersion(mods::last::LastVersionMod::new()));
+        aparte.add_mod(Mod::Avatar(mods::avatar::AvatarMod::new()));
+        aparte.add_mod(Mod::Retraction(mods::retraction::RetractionMod::new()));
+        aparte.add_mod(Mod::Download(mods::download::DownloadMod::new()));
+        aparte.add_mod(Mod::Blocking(mods::blocking::BlockingMod::new()));
+        aparte.add_mod(Mod::Notes(mods::notes::NotesMod::new()));
+        aparte.add_mod(Mod::Calls(mods::calls::CallsMod::new()));
+        aparte.add_mod(Mod::Upload(mods::upload::UploadMod::new()));
+        aparte.add_mod(Mod::ChatStates(mods::chatstates::ChatStatesMod::new()));
+        aparte.add_mod(Mod::Notifications(
+            mods::notifications::NotificationsMod::new(),
+        ));
+        aparte.stats.record_startup("mods", phase);
 
         Ok(aparte)
     }
@@ -896,7 +1895,20 @@ impl Aparte {
         context: &String,
         buf: &String,
     ) -> Result<()> {
+        // Commands prefixed with @account run as if it was the current account
+        if let Some((name, buf)) = Command::split_account_prefix(buf) {
+            let prefixed = Some(self.find_account(name)?);
+            let current = std::mem::replace(&mut self.current_connection, prefixed.clone());
+            let result = self.handle_raw_command(&prefixed, context, &buf.to_string());
+            // The command may have changed the current account, e.g. by disconnecting it
+            if self.current_connection == prefixed {
+                self.current_connection = current;
+            }
+            return result;
+        }
+
         let command_name = Command::parse_name(buf)?;
+        self.check_requirement(account, command_name)?;
 
         let parser = self
             .command_parsers
@@ -908,6 +1920,8 @@ impl Aparte {
     }
 
     pub fn handle_command(&mut self, command: Command) -> Result<()> {
+        self.check_requirement(&command.account, &command.args[0])?;
+
         let parser = self
             .command_parsers
             .get(&command.args[0])
@@ -916,6 +1930,29 @@ impl Aparte {
         (parser.exec)(self, command)
     }
 
+    /// Connected account given by its configured name or its jid
+    fn find_account(&self, name: &str) -> Result<Account> {
+        let jid = match self.config.accounts.get(name) {
+            Some(account) => account.jid.clone(),
+            None => name.to_string(),
+        };
+        let bare = Jid::from_str(&jid)
+            .with_context(|| format!("Unknown account {name}"))?
+            .to_bare();
+        self.connected_accounts()
+            .into_iter()
+            .find(|account| account.to_bare() == bare)
+            .with_context(|| format!("No connection found for {name}"))
+    }
+
+    fn check_requirement(&self, account: &Option<Account>, command_name: &str) -> Result<()> {
+        let disco = self.get_mod::<mods::disco::DiscoMod>();
+        if let Some(xep) = disco.missing_requirement(account, command_name) {
+            anyhow::bail!("/{command_name} is unavailable: the server doesn't support {xep}");
+        }
+        Ok(())
+    }
+
     pub fn add_mod(&mut self, r#mod: Mod) {
         log::info!("Add mod `{}`", r#mod);
         let mods = Arc::get_mut(&mut self.mods).unwrap();
@@ -984,13 +2021,195 @@ impl Aparte {
                     RwLock::new(Mod::Omemo(r#mod)),
                 );
             }
+            Mod::Trace(r#mod) => {
+                mods.insert(
+                    TypeId::of::<mods::trace::TraceMod>(),
+                    RwLock::new(Mod::Trace(r#mod)),
+                );
+            }
+            Mod::Channels(r#mod) => {
+                mods.insert(
+                    TypeId::of::<mods::channels::ChannelsMod>(),
+                    RwLock::new(Mod::Channels(r#mod)),
+                );
+            }
+            Mod::Muc(r#mod) => {
+                mods.insert(
+                    TypeId::of::<mods::muc::MucMod>(),
+                    RwLock::new(Mod::Muc(r#mod)),
+                );
+            }
+            Mod::Receipts(r#mod) => {
+                mods.insert(
+                    TypeId::of::<mods::receipts::ReceiptsMod>(),
+                    RwLock::new(Mod::Receipts(r#mod)),
+                );
+            }
+            Mod::Activity(r#mod) => {
+                mods.insert(
+                    TypeId::of::<mods::activity::ActivityMod>(),
+                    RwLock::new(Mod::Activity(r#mod)),
+                );
+            }
+            Mod::PubSub(r#mod) => {
+                mods.insert(
+                    TypeId::of::<mods::pubsub::PubSubMod>(),
+                    RwLock::new(Mod::PubSub(r#mod)),
+                );
+            }
+            Mod::HostMeta(r#mod) => {
+                mods.insert(
+                    TypeId::of::<mods::host_meta::HostMetaMod>(),
+                    RwLock::new(Mod::HostMeta(r#mod)),
+                );
+            }
+            Mod::Sessions(r#mod) => {
+                mods.insert(
+                    TypeId::of::<mods::sessions::SessionsMod>(),
+                    RwLock::new(Mod::Sessions(r#mod)),
+                );
+            }
+            Mod::Reactions(r#mod) => {
+                mods.insert(
+                    TypeId::of::<mods::reactions::ReactionsMod>(),
+                    RwLock::new(Mod::Reactions(r#mod)),
+                );
+            }
+            Mod::Idle(r#mod) => {
+                mods.insert(
+                    TypeId::of::<mods::idle::IdleMod>(),
+                    RwLock::new(Mod::Idle(r#mod)),
+                );
+            }
+            Mod::Location(r#mod) => {
+                mods.insert(
+                    TypeId::of::<mods::location::LocationMod>(),
+                    RwLock::new(Mod::Location(r#mod)),
+                );
+            }
+            Mod::Tune(r#mod) => {
+                mods.insert(
+                    TypeId::of::<mods::tune::TuneMod>(),
+                    RwLock::new(Mod::Tune(r#mod)),
+                );
+            }
+            Mod::Mood(r#mod) => {
+                mods.insert(
+                    TypeId::of::<mods::mood::MoodMod>(),
+                    RwLock::new(Mod::Mood(r#mod)),
+                );
+            }
+            Mod::QuietHours(r#mod) => {
+                mods.insert(
+                    TypeId::of::<mods::quiet_hours::QuietHoursMod>(),
+                    RwLock::new(Mod::QuietHours(r#mod)),
+                );
+            }
+            Mod::Nick(r#mod) => {
+                mods.insert(
+                    TypeId::of::<mods::nick::NickMod>(),
+                    RwLock::new(Mod::Nick(r#mod)),
+                );
+            }
+            Mod::Adhoc(r#mod) => {
+                mods.insert(
+                    TypeId::of::<mods::adhoc::AdhocMod>(),
+                    RwLock::new(Mod::Adhoc(r#mod)),
+                );
+            }
+            Mod::Csi(r#mod) => {
+                mods.insert(
+                    TypeId::of::<mods::csi::CsiMod>(),
+                    RwLock::new(Mod::Csi(r#mod)),
+                );
+            }
+            Mod::Ping(r#mod) => {
+                mods.insert(
+                    TypeId::of::<mods::ping::PingMod>(),
+                    RwLock::new(Mod::Ping(r#mod)),
+                );
+            }
+            Mod::Version(r#mod) => {
+                mods.insert(
+                    TypeId::of::<mods::version::VersionMod>(),
+                    RwLock::new(Mod::Version(r#mod)),
+                );
+            }
+            Mod::LastVersion(r#mod) => {
+                mods.insert(
+                    TypeId::of::<mods::last::LastVersionMod>(),
+                    RwLock::new(Mod::LastVersion(r#mod)),
+                );
+            }
+            Mod::Avatar(r#mod) => {
+                mods.insert(
+                    TypeId::of::<mods::avatar::AvatarMod>(),
+                    RwLock::new(Mod::Avatar(r#mod)),
+                );
+            }
+            Mod::Retraction(r#mod) => {
+                mods.insert(
+                    TypeId::of::<mods::retraction::RetractionMod>(),
+                    RwLock::new(Mod::Retraction(r#mod)),
+                );
+            }
+            Mod::Download(r#mod) => {
+                mods.insert(
+                    TypeId::of::<mods::download::DownloadMod>(),
+                    RwLock::new(Mod::Download(r#mod)),
+                );
+            }
+            Mod::Blocking(r#mod) => {
+                mods.insert(
+                    TypeId::of::<mods::blocking::BlockingMod>(),
+                    RwLock::new(Mod::Blocking(r#mod)),
+                );
+            }
+            Mod::Notes(r#mod) => {
+                mods.insert(
+                    TypeId::of::<mods::notes::NotesMod>(),
+                    RwLock::new(Mod::Notes(r#mod)),
+                );
+            }
+            Mod::Calls(r#mod) => {
+                mods.insert(
+                    TypeId::of::<mods::calls::CallsMod>(),
+                    RwLock::new(Mod::Calls(r#mod)),
+                );
+            }
+            Mod::Upload(r#mod) => {
+                mods.insert(
+                    TypeId::of::<mods::upload::UploadMod>(),
+                    RwLock::new(Mod::Upload(r#mod)),
+                );
+            }
+            Mod::ChatStates(r#mod) => {
+                mods.insert(
+                    TypeId::of::<mods::chatstates::ChatStatesMod>(),
+                    RwLock::new(Mod::ChatStates(r#mod)),
+                );
+            }
+            Mod::Notifications(r#mod) => {
+                mods.insert(
+                    TypeId::of::<mods::notifications::NotificationsMod>(),
+                    RwLock::new(Mod::Notifications(r#mod)),
+                );
+            }
         }
     }
 
-    pub fn add_connection(&mut self, account: Account, sink: mpsc::UnboundedSender<Element>) {
+    pub fn add_connection(
+        &mut self,
+        account: Account,
+        sink: mpsc::UnboundedSender<Element>,
+        kill: oneshot::Sender<(String, bool)>,
+        stream_management: bool,
+    ) {
         let connection = Connection {
             account: account.clone(),
             sink,
+            kill,
+            stream_management,
         };
 
         self.connections.insert(account.clone(), connection);
@@ -998,6 +2217,7 @@ impl Aparte {
     }
 
     pub fn init(&mut self) -> Result<(), ()> {
+        let phase = Instant::now();
         self.add_command(help::new());
         self.add_command(connect::new());
         self.add_command(win::new());
@@ -1007,11 +2227,18 @@ impl Aparte {
         self.add_command(join::new());
         self.add_command(quit::new());
         self.add_command(me::new());
+        self.add_command(stats::new());
+        self.add_command(queue::new());
+        self.add_command(xml::new());
 
         let mods = self.mods.clone();
         for (_, r#mod) in mods.iter() {
-            r#mod.try_write().unwrap().init(self)?;
+            let start = Instant::now();
+            let mut r#mod = r#mod.try_write().unwrap();
+            r#mod.init(self)?;
+            log::debug!("{} initialized in {:?}", r#mod, start.elapsed());
         }
+        self.stats.record_startup("init", phase);
 
         Ok(())
     }
@@ -1089,8 +2316,36 @@ impl Aparte {
         self.log(color::rainbow(WELCOME));
         self.log(format!("Version: {VERSION}"));
 
+        // Migrations can take a while after an upgrade, they run once the UI is displayed
+        Aparte::spawn({
+            let mut aparte = self.proxy();
+            let storage = self.storage.clone();
+            let debug = self.debug;
+            async move {
+                let start = Instant::now();
+                let migrated = tokio::task::spawn_blocking(move || storage.migrate())
+                    .await
+                    .map_err(anyhow::Error::from)
+                    .and_then(|migrated| migrated);
+                match migrated {
+                    Ok(()) => {
+                        if debug {
+                            aparte.log(format!("Storage migrated in {:?}", start.elapsed()));
+                        }
+                        aparte.schedule(Event::StorageReady);
+                    }
+                    Err(err) => {
+                        crate::error!(aparte, err, "Cannot migrate storage, accounts stay offline")
+                    }
+                }
+            }
+        });
+
+        let now = LocalTz::now().naive_local();
         for (name, account) in self.config.accounts.clone() {
-            if account.autoconnect {
+            if account.autoconnect && quiet_hours::is_quiet(&account.quiet_hours, now) {
+                self.log(format!("{name} stays disconnected during its quiet hours"));
+            } else if account.autoconnect {
                 self.schedule(Event::RawCommand(
                     None,
                     "console".to_string(),
@@ -1098,61 +2353,280 @@ impl Aparte {
                 ));
             }
         }
+
+        Aparte::spawn({
+            let mut aparte = self.proxy();
+            async move {
+                loop {
+                    let second = LocalTz::now().second() as u64;
+                    tokio::time::sleep(Duration::from_secs(60 - second)).await;
+                    aparte.schedule(Event::Tick);
+                }
+            }
+        });
     }
 
     fn send_stanza(&mut self, account: Account, stanza: Element) {
+        if stanza.name() == "message" && !self.send_queue.is_online(&account) {
+            self.queue_message(account, stanza);
+        } else {
+            self.write_stanza(account, stanza);
+        }
+    }
+
+    fn write_stanza(&mut self, account: Account, stanza: Element) {
         let mut raw = Vec::<u8>::new();
         stanza.write_to(&mut raw).unwrap();
         log::debug!("SEND: {}", String::from_utf8(raw).unwrap());
-        match self.connections.get_mut(&account) {
-            Some(connection) => {
-                if let Err(e) = connection.sink.send(stanza) {
-                    log::warn!("Cannot send stanza: {}", e);
+        let chat_message = Self::chat_message(&stanza);
+        if stanza.is("enable", NS_SM) {
+            self.stream_management.start(&account);
+        }
+        // Kept until acknowledged by the server
+        let tracked = self
+            .stream_management
+            .is_started(&account)
+            .then(|| stanza.clone());
+        let (stanza, err) = match self.connections.get_mut(&account) {
+            Some(connection) => match connection.sink.send(stanza) {
+                Ok(()) => {
+                    self.stats.sent_stanzas += 1;
+                    match tracked.and_then(|stanza| self.stream_management.sent(&account, stanza)) {
+                        // Messages are sent once acknowledged
+                        Some(AckRequest::Now(request)) => self.write_stanza(account, request),
+                        Some(AckRequest::Later) => Aparte::spawn({
+                            let mut aparte = self.proxy();
+                            async move {
+                                tokio::time::sleep(ACK_DELAY).await;
+                                aparte.schedule(Event::RequestAck(account));
+                            }
+                        }),
+                        Some(AckRequest::Wait) => {}
+                        // Messages echoed as pending are now sent
+                        None => {
+                            if let Some((contact, id)) = chat_message {
+                                self.schedule(Event::MessageDelivery {
+                                    account,
+                                    contact,
+                                    id,
+                                    delivery: Delivery::Sent,
+                                });
+                            }
+                        }
+                    }
+                    return;
+                }
+                Err(mpsc::error::SendError(stanza)) => (
+                    stanza,
+                    anyhow::anyhow!("Cannot send stanza for {}", account),
+                ),
+            },
+            None => (
+                stanza,
+                anyhow::anyhow!("No connection found for {}", account),
+            ),
+        };
+
+        log::warn!("{}", err);
+        match stanza.name() {
+            "message" => self.queue_message(account, stanza),
+            "iq" => {
+                if let Some(id) = stanza.attr("id") {
+                    self.errored_iq(id, err);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Stream management element received from the server
+    fn handle_stream_management(&mut self, account: Account, element: Element) {
+        match self.stream_management.handle(&account, &element) {
+            Handled::Enabled => log::info!("Stream management enabled for {account}"),
+            Handled::Failed => self.log(format!(
+                "Server of {account} refused to enable stream management"
+            )),
+            Handled::Answer(answer) => self.write_stanza(account, answer),
+            Handled::Acked(stanzas) => {
+                for (contact, id) in stanzas.iter().filter_map(Self::chat_message) {
+                    self.schedule(Event::MessageDelivery {
+                        account: account.clone(),
+                        contact,
+                        id,
+                        delivery: Delivery::Sent,
+                    });
                 }
             }
-            None => {
-                log::warn!("No connection found for {}", account);
+            Handled::Ignored => {
+                log::debug!("Ignoring stream management element {}", element.name())
             }
         }
     }
 
-    pub fn connect(&mut self, connection_info: &ConnectionInfo, password: Password) {
-        let account: Account = match Jid::from_str(&connection_info.jid) {
-            Ok(Jid::Full(jid)) => jid,
-            Ok(Jid::Bare(jid)) => {
-                let rand_string: String = rand::thread_rng()
-                    .sample_iter(&rand::distributions::Alphanumeric)
-                    .take(5)
-                    .map(char::from)
-                    .collect();
-                jid.with_resource_str(&format!("aparte_{rand_string}"))
-                    .unwrap()
+    /// Contact and id of a chat message with a body
+    fn chat_message(stanza: &Element) -> Option<(BareJid, String)> {
+        if stanza.name() != "message"
+            || stanza.attr("type") != Some("chat")
+            || !stanza.children().any(|child| child.name() == "body")
+        {
+            return None;
+        }
+        let to = Jid::from_str(stanza.attr("to")?).ok()?;
+        Some((to.to_bare(), stanza.attr("id")?.to_string()))
+    }
+
+    /// Messages the server may not have received are sent again once online
+    fn requeue_unacked(&mut self, account: &Account, unacked: Vec<Element>) {
+        let count = unacked
+            .iter()
+            .filter(|stanza| stanza.name() == "message")
+            .count();
+        if count > 0 {
+            self.log(format!(
+                "{count} messages not acknowledged by the server will be sent again"
+            ));
+        }
+        for stanza in unacked {
+            if stanza.name() == "message" {
+                self.queue_message(account.clone(), stanza);
+            }
+        }
+    }
+
+    /// Announce presence and send queued messages on a new session
+    fn online(&mut self, account: &Account) {
+        self.send_queue.set_online(account, true);
+        let idle_since = self.get_mod::<mods::idle::IdleMod>().idle_since();
+        let presence = self.presence(idle_since);
+        self.send(account, presence);
+
+        for stanza in self.send_queue.take(account) {
+            self.send(account, stanza);
+        }
+    }
+
+    fn queue_message(&mut self, account: Account, stanza: Element) {
+        if stanza.children().any(|child| child.name() == "body") {
+            log::info!("Queue message until {} is online", account);
+            self.send_queue.push(account, stanza);
+        } else {
+            log::debug!("Drop message for offline {}", account);
+        }
+    }
+
+    /// Fail iqs still waiting for a response on a lost connection, so that callers
+    /// don't wait forever and can retry once reconnected
+    fn fail_pending_iqs(&mut self, account: &Account, reason: &str) {
+        let mut pending_iq = self.pending_iq.lock().unwrap();
+        let lost: Vec<Uuid> = pending_iq
+            .iter()
+            .filter_map(|(uuid, state)| match state {
+                PendingIqState::Waiting(waiting_account, _) if waiting_account == account => {
+                    Some(*uuid)
+                }
+                _ => None,
+            })
+            .collect();
+
+        for uuid in lost {
+            if let Some(PendingIqState::Waiting(_, waker)) = pending_iq.remove(&uuid) {
+                let err = anyhow::anyhow!("Connection lost for {}: {}", account, reason);
+                pending_iq.insert(uuid, PendingIqState::Errored(err));
+                if let Some(waker) = waker {
+                    waker.wake();
+                }
             }
+        }
+    }
+
+    pub fn connect(&mut self, connection_info: &ConnectionInfo, password: Password) {
+        let account = match self.new_account(connection_info) {
+            Some(account) => account,
+            None => return,
+        };
+        self.connect_attempt(account, connection_info, password, 0, None);
+    }
+
+    /// Delay before retrying a connection that failed `attempt + 1` times
+    fn retry_delay(attempt: u32) -> Duration {
+        RETRY_DELAY
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(RETRY_MAX_DELAY)
+    }
+
+    /// Account of a new connection, with the configured resource or a generated one
+    fn new_account(&mut self, connection_info: &ConnectionInfo) -> Option<Account> {
+        let random_suffix = || -> String {
+            rand::thread_rng()
+                .sample_iter(&rand::distributions::Alphanumeric)
+                .take(5)
+                .map(char::from)
+                .collect()
+        };
+
+        let jid = match Jid::from_str(&connection_info.jid) {
+            Ok(jid) => jid,
             Err(err) => {
                 self.log(format!(
                     "Cannot connect as {}: {}",
                     connection_info.jid, err
                 ));
-                return;
+                return None;
             }
         };
 
-        self.log(format!("Connecting as {account}"));
+        let resource = match (&connection_info.resource, &jid) {
+            (Some(resource), _) => Some(resource.clone()),
+            (None, Jid::Full(jid)) => Some(jid.resource().to_string()),
+            (None, Jid::Bare(_)) => None,
+        };
+        let resource = match (resource, connection_info.random_resource) {
+            (Some(resource), true) => format!("{resource}.{}", random_suffix()),
+            (Some(resource), false) => resource,
+            (None, _) => format!("aparte_{}", random_suffix()),
+        };
+
+        match jid.to_bare().with_resource_str(&resource) {
+            Ok(account) => Some(account),
+            Err(err) => {
+                self.log(format!("Cannot connect with resource {resource}: {err}"));
+                None
+            }
+        }
+    }
+
+    /// Connect, through the fallback server instead of the configured or SRV one when given
+    fn connect_attempt(
+        &mut self,
+        account: Account,
+        connection_info: &ConnectionInfo,
+        password: Password,
+        attempt: u32,
+        fallback: Option<String>,
+    ) {
+        if attempt == 0 {
+            self.log(format!("Connecting as {account}"));
+        }
+        self.schedule(Event::Connecting(account.clone(), attempt));
+        let retry = connection_info.autoconnect && connection_info.autoconnect_retry;
+        let retry_info = retry.then(|| (connection_info.clone(), password.clone()));
         let config = tokio_xmpp::AsyncConfig {
             jid: Jid::from(account.clone()),
             password: password.expose_secret().clone(),
-            server: match (&connection_info.server, &connection_info.port) {
-                (Some(server), Some(port)) => tokio_xmpp::starttls::ServerConfig::Manual {
-                    host: server.clone(),
-                    port: *port,
-                },
+            server: match (
+                fallback.or_else(|| connection_info.server.clone()),
+                connection_info.port,
+            ) {
+                (Some(server), Some(port)) => {
+                    tokio_xmpp::starttls::ServerConfig::Manual { host: server, port }
+                }
                 (Some(server), None) => tokio_xmpp::starttls::ServerConfig::Manual {
-                    host: server.clone(),
+                    host: server,
                     port: 5222,
                 },
                 (None, Some(port)) => tokio_xmpp::starttls::ServerConfig::Manual {
                     host: account.domain().to_string(),
-                    port: *port,
+                    port,
                 },
                 (None, None) => tokio_xmpp::starttls::ServerConfig::UseSrv,
             },
@@ -1163,8 +2637,14 @@ impl Aparte {
         client.set_reconnect(true);
 
         let (connection_channel, mut rx) = mpsc::unbounded_channel();
+        let (kill, mut killed) = oneshot::channel();
 
-        self.add_connection(account.clone(), connection_channel);
+        self.add_connection(
+            account.clone(),
+            connection_channel,
+            kill,
+            connection_info.stream_management,
+        );
 
         let (mut writer, mut reader) = client.split();
         // XXX could use self.rt.spawn if client was impl Send
@@ -1175,13 +2655,48 @@ impl Aparte {
                     break;
                 }
             }
+            // The sink is dropped along with the connection, once the stanzas queued before are
+            // sent the stream is closed, the client being dropped only after that
+            let end = writer.send(tokio_xmpp::Packet::StreamEnd);
+            match tokio::time::timeout(CLOSE_TIMEOUT, end).await {
+                Ok(Ok(())) => {}
+                Ok(Err(err)) => log::debug!("Cannot close stream: {err}"),
+                Err(_) => log::debug!("Stream not closed after {CLOSE_TIMEOUT:?}"),
+            }
         });
 
         let event_tx = self.event_tx.clone();
+        let reconnect_info = (connection_info.clone(), password.clone());
 
         let reconnect = true;
         task::spawn_local(async move {
-            while let Some(event) = reader.next().await {
+            let mut online = false;
+            loop {
+                let event = tokio::select! {
+                    event = reader.next() => match event {
+                        Some(event) => event,
+                        None => break,
+                    },
+                    Ok((reason, again)) = &mut killed => {
+                        // Reading stops along with this task, a new client is started if asked
+                        let disconnected = Event::Disconnected(account.clone(), reason);
+                        if let Err(err) = event_tx.send(disconnected) {
+                            log::error!("Cannot send event to internal channel: {}", err);
+                        }
+                        if again {
+                            let (connection_info, password) = reconnect_info;
+                            if let Err(err) = event_tx.send(Event::Reconnect {
+                                account: account.clone(),
+                                connection_info,
+                                password,
+                                attempt: 0,
+                            }) {
+                                log::error!("Cannot send event to internal channel: {}", err);
+                            }
+                        }
+                        break;
+                    }
+                };
                 log::debug!("XMPP Event: {:?}", event);
                 match event {
                     tokio_xmpp::Event::Disconnected(tokio_xmpp::Error::Auth(e)) => {
@@ -1192,6 +2707,20 @@ impl Aparte {
                         };
                         break;
                     }
+                    tokio_xmpp::Event::Disconnected(e) if !online && retry_info.is_some() => {
+                        // Never got online, retry later instead of right away
+                        let (connection_info, password) = retry_info.unwrap();
+                        if let Err(err) = event_tx.send(Event::ConnectionFailed {
+                            account: account.clone(),
+                            connection_info,
+                            password,
+                            attempt,
+                            error: format!("{e}"),
+                        }) {
+                            log::error!("Cannot send event to internal channel: {}", err);
+                        };
+                        break;
+                    }
                     tokio_xmpp::Event::Disconnected(e) => {
                         if let Err(err) =
                             event_tx.send(Event::Disconnected(account.clone(), format!("{e}")))
@@ -1212,6 +2741,7 @@ impl Aparte {
                         bound_jid: jid,
                         resumed: false,
                     } => {
+                        online = true;
                         if let Err(err) = event_tx.send(Event::Connected(account.clone(), jid)) {
                             log::error!("Cannot send event to internal channel: {}", err);
                             break;
@@ -1229,7 +2759,7 @@ impl Aparte {
         });
     }
 
-    pub fn handle_event(&mut self, event: Event) -> Result<(), ()> {
+    pub fn handle_event(&mut self, mut event: Event) -> Result<(), ()> {
         if self.read_password.load(Relaxed) && matches!(event, Event::Key(..)) {
             log::debug!("Event: {:?}", Event::Key(Key::Char('*')));
         } else {
@@ -1237,11 +2767,18 @@ impl Aparte {
         }
         {
             let mods = self.mods.clone();
-            for (_, r#mod) in mods.iter() {
-                r#mod.try_write().unwrap().on_event(self, &event);
+            for (type_id, r#mod) in mods.iter() {
+                let start = Instant::now();
+                event = r#mod.try_write().unwrap().on_owned_event(self, event);
+                self.stats.record_event(*type_id, start.elapsed());
             }
         }
 
+        if self.debug && !self.startup_reported && self.stats.first_render.is_some() {
+            self.startup_reported = true;
+            self.log(format!("Startup: {}", self.stats.startup_report()));
+        }
+
         match event {
             Event::Start => {
                 self.start();
@@ -1281,30 +2818,132 @@ impl Aparte {
                     None => self.send(&account, message),
                 }
             }
-            Event::Connect(account, password) => {
-                self.connect(&account, password);
+            Event::Connect(account, password) => match &mut self.pending_connections {
+                Some(pending) => pending.push((account, password)),
+                None => self.connect(&account, password),
+            },
+            Event::StorageReady => {
+                for (account, password) in self.pending_connections.take().unwrap_or_default() {
+                    self.connect(&account, password);
+                }
+            }
+            Event::ConnectionFailed {
+                account,
+                connection_info,
+                password,
+                attempt,
+                error,
+            } => {
+                // The connection is dead, the next attempt adds a new one
+                self.connections.remove(&account);
+                if self.current_connection.as_ref() == Some(&account) {
+                    self.current_connection = None;
+                }
+                let delay = Self::retry_delay(attempt);
+                self.log(format!(
+                    "Cannot connect as {account}: {error}, retrying in {}s",
+                    delay.as_secs()
+                ));
+                Aparte::spawn({
+                    let mut aparte = self.proxy();
+                    async move {
+                        tokio::time::sleep(delay).await;
+                        aparte.schedule(Event::Reconnect {
+                            account,
+                            connection_info,
+                            password,
+                            attempt: attempt + 1,
+                        });
+                    }
+                });
+            }
+            Event::Reconnect {
+                account,
+                connection_info,
+                ..
+            } if quiet_hours::is_quiet(
+                &connection_info.quiet_hours,
+                LocalTz::now().naive_local(),
+            ) =>
+            {
+                // Connected again by the quiet hours mod once they are over
+                self.log(format!(
+                    "{account} stays disconnected during its quiet hours"
+                ));
+            }
+            Event::Reconnect {
+                account,
+                connection_info,
+                password,
+                attempt,
+            } => {
+                // A server that cannot be reached is also tried through hosts it announces
+                let fallback = match (&connection_info.server, Jid::from_str(&connection_info.jid))
+                {
+                    (None, Ok(jid)) => {
+                        let host_meta = self.get_mod::<mods::host_meta::HostMetaMod>();
+                        host_meta.fallback_server(&jid.domain().to_string(), attempt)
+                    }
+                    _ => None,
+                };
+                if let Some(server) = &fallback {
+                    self.log(format!("Trying to connect through {server}"));
+                }
+                // Windows, queued messages and other state are kept under the same account
+                self.connect_attempt(account, &connection_info, password, attempt, fallback);
             }
             Event::Connected(account, _) => {
                 self.log(format!("Connected as {}", account));
-                let mut presence = Presence::new(PresenceType::None);
-                presence.show = Some(PresenceShow::Chat);
-
-                let disco = self.get_mod::<mods::disco::DiscoMod>().get_disco();
-                let disco = caps::compute_disco(&disco);
-                let verification_string =
-                    caps::hash_caps(&disco, xmpp_hashes::Algo::Blake2b_512).unwrap();
-                let caps = Caps::new("aparté", verification_string);
-                presence.add_payload(caps);
-
-                self.send(&account, presence);
+                if let Some(connection) = self.connections.get(&account) {
+                    if connection.stream_management {
+                        self.send(&account, StreamManagement::request());
+                    }
+                }
+                self.online(&account);
             }
             Event::Disconnected(account, err) => {
+                self.send_queue.set_online(&account, false);
+                self.fail_pending_iqs(&account, &err);
                 self.log(format!("Connection lost for {}: {}", account, err));
+                let unacked = self.stream_management.lost(&account);
+                self.requeue_unacked(&account, unacked);
+            }
+            Event::RequestAck(account) => {
+                if let Some(request) = self.stream_management.request_ack(&account) {
+                    self.write_stanza(account, request);
+                }
+            }
+            Event::ConnectionBroken(account, reason) => {
+                if let Some(connection) = self.connections.remove(&account) {
+                    if self.current_connection.as_ref() == Some(&account) {
+                        self.current_connection = None;
+                    }
+                    let _ = connection.kill.send((reason, true));
+                }
+            }
+            Event::Disconnect(account, reason) => {
+                if let Some(connection) = self.connections.remove(&account) {
+                    if self.current_connection.as_ref() == Some(&account) {
+                        self.current_connection = None;
+                    }
+                    // Stop reading right away, the writer still sends the presence and closes the
+                    // stream once the sink is dropped with the connection
+                    let _ = connection
+                        .sink
+                        .send(Presence::new(PresenceType::Unavailable).into());
+                    let _ = connection.kill.send((reason, false));
+                }
             }
             Event::AuthError(account, err) => {
+                self.send_queue.set_online(&account, false);
                 self.log(format!("Authentication error for {}: {}", account, err));
             }
+            Event::Stanza(account, stanza) if stanza.ns() == NS_SM => {
+                self.handle_stream_management(account, stanza);
+            }
             Event::Stanza(account, stanza) => {
+                self.stats.received_stanzas += 1;
+                self.stream_management.received(&account, &stanza);
                 self.handle_stanza(account, stanza);
             }
             Event::RawMessage {
@@ -1317,16 +2956,17 @@ impl Aparte {
             }
             Event::Join {
                 account,
-                channel,
+                channel: Jid::Bare(channel),
+                user_request,
+            } => {
+                // Nickname is resolved first, join is then scheduled again with it
+                mods::muc::MucMod::join(self, &account, &channel, user_request);
+            }
+            Event::Join {
+                account,
+                channel: Jid::Full(to),
                 user_request,
             } => {
-                let to = match channel.clone() {
-                    Jid::Full(jid) => jid,
-                    Jid::Bare(jid) => {
-                        let node = account.node().clone().unwrap();
-                        jid.with_resource_str(&node.to_string()).unwrap()
-                    }
-                };
                 let from: Jid = account.clone().into();
 
                 let mut presence = Presence::new(PresenceType::None);
@@ -1336,7 +2976,7 @@ impl Aparte {
                 self.send(&account, presence);
 
                 // Successful join
-                self.log(format!("Joined {}", channel));
+                self.log(format!("Joined {}", to));
                 self.schedule(Event::Joined {
                     account: account.clone(),
                     channel: to,
@@ -1475,7 +3115,7 @@ impl Aparte {
             let state = self.pending_iq.lock().unwrap().remove(&uuid);
             if let Some(state) = state {
                 match state {
-                    PendingIqState::Waiting(waker) => {
+                    PendingIqState::Waiting(_, waker) => {
                         // XXX dead lock
                         self.pending_iq
                             .lock()
@@ -1510,7 +3150,7 @@ impl Aparte {
         match iq.payload {
             IqType::Error(payload) => {
                 if let Some(text) = payload.texts.get("en") {
-                    let message = Message::log(text.clone());
+                    let message = Message::log_with_level(log::Level::Error, text.clone());
                     self.schedule(Event::Message(Some(account.clone()), message));
                 }
             }
@@ -1528,7 +3168,7 @@ impl Aparte {
             let state = self.pending_iq.lock().unwrap().remove(&uuid);
             if let Some(state) = state {
                 match state {
-                    PendingIqState::Waiting(waker) => {
+                    PendingIqState::Waiting(_, waker) => {
                         // XXX dead lock
                         self.pending_iq
                             .lock()
@@ -1596,6 +3236,32 @@ impl Aparte {
         crypto_engines.insert((account.clone(), recipient.clone()), crypto_engine);
     }
 
+    /// Whether messages sent to the recipient are encrypted
+    pub fn is_encrypted(&self, account: &Account, recipient: &BareJid) -> bool {
+        let crypto_engines = self.crypto_engines.lock().unwrap();
+        crypto_engines.contains_key(&(account.clone(), recipient.clone()))
+    }
+
+    /// Available presence with our entity capabilities, and since when we are idle if we are
+    pub fn presence(&self, idle_since: Option<DateTime<FixedOffset>>) -> Presence {
+        let mut presence = Presence::new(PresenceType::None);
+        presence.show = Some(PresenceShow::Chat);
+
+        let disco = self.get_mod::<mods::disco::DiscoMod>().get_disco();
+        let disco = caps::compute_disco(&disco);
+        let verification_string = caps::hash_caps(&disco, xmpp_hashes::Algo::Blake2b_512).unwrap();
+        let caps = Caps::new("aparté", verification_string);
+        presence.add_payload(caps);
+
+        if let Some(since) = idle_since {
+            presence.add_payload(Idle {
+                since: XmppDateTime(since),
+            });
+        }
+
+        presence
+    }
+
     pub fn send<T>(&mut self, account: &Account, element: T)
     where
         T: TryInto<Element> + Debug,
@@ -1617,11 +3283,20 @@ impl Aparte {
         self.schedule(Event::Message(None, message));
     }
 
-    pub fn error<T: Display>(&mut self, message: T, err: anyhow::Error) {
-        let message = Message::log(format!("{}: {:#}", message, err));
+    pub fn log_with_level<T: ToString>(&mut self, level: log::Level, message: T) {
+        let message = Message::log_with_level(level, message.to_string());
         self.schedule(Event::Message(None, message));
     }
 
+    pub fn error<T: Display>(&mut self, message: T, err: anyhow::Error) {
+        let error = format!("{}: {:#}", message, err);
+        self.schedule(Event::Error(error.clone()));
+        self.schedule(Event::Message(
+            None,
+            Message::log_with_level(log::Level::Error, error),
+        ));
+    }
+
     pub fn get_mod<'a, T>(&'a self) -> RwLockReadGuard<'a, T>
     where
         T: 'static,
@@ -1648,6 +3323,10 @@ impl Aparte {
     pub fn current_account(&self) -> Option<Account> {
         self.current_connection.clone()
     }
+
+    pub fn connected_accounts(&self) -> Vec<Account> {
+        self.connections.keys().cloned().collect()
+    }
 }
 
 #[derive(Clone)]
@@ -1679,11 +3358,20 @@ impl AparteAsync {
         self.schedule(Event::Message(None, message));
     }
 
-    pub fn error<T: Display>(&mut self, message: T, err: anyhow::Error) {
-        let message = Message::log(format!("{}: {:#}", message, err));
+    pub fn log_with_level<T: ToString>(&mut self, level: log::Level, message: T) {
+        let message = Message::log_with_level(level, message.to_string());
         self.schedule(Event::Message(None, message));
     }
 
+    pub fn error<T: Display>(&mut self, message: T, err: anyhow::Error) {
+        let error = format!("{}: {:#}", message, err);
+        self.schedule(Event::Error(error.clone()));
+        self.schedule(Event::Message(
+            None,
+            Message::log_with_level(log::Level::Error, error),
+        ));
+    }
+
     pub fn current_account(&self) -> Option<Account> {
         self.current_connection.clone()
     }
@@ -0,0 +1,509 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use std::convert::TryFrom;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use futures::stream::{self, Stream, StreamExt};
+use tokio::io::BufReader;
+use tokio::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt,
+};
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::{ClientConfig, OwnedTrustAnchor, RootCertStore, ServerName};
+use tokio_rustls::TlsConnector;
+
+/// Maximum number of redirections followed by a GET request
+const MAX_REDIRECTS: usize = 5;
+
+/// Maximum size of a response body kept in memory
+const MAX_BODY_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Connection to a HTTP server, over TLS or not
+trait Transport: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Transport for T {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Url {
+    tls: bool,
+    /// Host and optional port, as sent in the Host header
+    authority: String,
+    host: String,
+    port: u16,
+    /// Path and query
+    target: String,
+}
+
+impl Url {
+    /// Resolve the location of a redirection against this URL
+    fn join(&self, location: &str) -> Result<Url> {
+        match location {
+            location if location.starts_with('/') => {
+                let scheme = if self.tls { "https" } else { "http" };
+                format!("{scheme}://{}{location}", self.authority).parse()
+            }
+            location => location.parse(),
+        }
+    }
+}
+
+impl FromStr for Url {
+    type Err = anyhow::Error;
+
+    fn from_str(url: &str) -> Result<Self> {
+        let (tls, rest) = match url {
+            url if url.starts_with("https://") => (true, &url["https://".len()..]),
+            url if url.starts_with("http://") => (false, &url["http://".len()..]),
+            url => anyhow::bail!("Unsupported URL {url}"),
+        };
+        let rest = rest.split('#').next().unwrap_or_default();
+        let (authority, target) = match rest.find(|c| c == '/' || c == '?') {
+            Some(index) if rest[index..].starts_with('?') => {
+                (&rest[..index], format!("/{}", &rest[index..]))
+            }
+            Some(index) => (&rest[..index], rest[index..].to_string()),
+            None => (rest, String::from("/")),
+        };
+        // Both end up in the request head, don't let them break it
+        if authority.contains(|c: char| c.is_whitespace() || c.is_control()) {
+            anyhow::bail!("Invalid host in URL {url}");
+        }
+        let target: String = target
+            .chars()
+            .map(|c| match c {
+                c if c.is_whitespace() || c.is_control() => {
+                    let mut bytes = [0; 4];
+                    c.encode_utf8(&mut bytes)
+                        .bytes()
+                        .map(|byte| format!("%{byte:02X}"))
+                        .collect()
+                }
+                c => c.to_string(),
+            })
+            .collect();
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) if !port.contains(']') => {
+                let port = port
+                    .parse()
+                    .with_context(|| format!("Invalid port in URL {url}"))?;
+                (host, port)
+            }
+            _ => (authority, if tls { 443 } else { 80 }),
+        };
+        if host.is_empty() {
+            anyhow::bail!("Missing host in URL {url}");
+        }
+
+        Ok(Url {
+            tls,
+            authority: authority.to_string(),
+            host: host
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .to_string(),
+            port,
+            target,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    /// Headers with lowercase names
+    headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn error_for_status(self) -> Result<Self> {
+        match self.status {
+            200..=299 => Ok(self),
+            status => anyhow::bail!("HTTP error {status}"),
+        }
+    }
+
+    pub fn text(self) -> Result<String> {
+        Ok(String::from_utf8(self.body)?)
+    }
+}
+
+/// Fetch a resource in memory, following redirections
+pub async fn get(url: &str) -> Result<Response> {
+    let mut body = Vec::new();
+    let mut response = download(url, &mut body, MAX_BODY_SIZE).await?;
+    response.body = body;
+    Ok(response)
+}
+
+/// Fetch a resource, following redirections, and write its body to the sink
+///
+/// The body is only written for successful responses, and is rejected when
+/// it is larger than `max_size` bytes.
+pub async fn download<W>(url: &str, sink: &mut W, max_size: u64) -> Result<Response>
+where
+    W: AsyncWrite + Unpin,
+{
+    let mut url: Url = url.parse()?;
+    for _ in 0..MAX_REDIRECTS {
+        let (response, mut reader) = send("GET", &url, &[], stream::empty()).await?;
+        match (response.status, response.header("location")) {
+            (301 | 302 | 303 | 307 | 308, Some(location)) => {
+                let location = url.join(location)?;
+                if url.tls && !location.tls {
+                    anyhow::bail!("Refusing redirection from HTTPS to HTTP");
+                }
+                url = location;
+            }
+            (200..=299, _) => {
+                read_body(&mut reader, &response, sink, max_size).await?;
+                sink.flush().await?;
+                return Ok(response);
+            }
+            _ => return Ok(response),
+        }
+    }
+    anyhow::bail!("Too many redirections")
+}
+
+/// Upload a body sent chunk by chunk as they are produced by the stream
+pub async fn put<S>(url: &str, headers: &[(&str, String)], body: S) -> Result<Response>
+where
+    S: Stream<Item = Vec<u8>> + Unpin,
+{
+    let (response, mut reader) = send("PUT", &url.parse()?, headers, body).await?;
+    read_response_body(&mut reader, response).await
+}
+
+async fn connect(url: &Url) -> Result<Box<dyn Transport>> {
+    let tcp = TcpStream::connect((url.host.as_str(), url.port))
+        .await
+        .with_context(|| format!("Cannot connect to {}", url.authority))?;
+    if !url.tls {
+        return Ok(Box::new(tcp));
+    }
+
+    let mut roots = RootCertStore::empty();
+    roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|anchor| {
+        OwnedTrustAnchor::from_subject_spki_name_constraints(
+            anchor.subject,
+            anchor.spki,
+            anchor.name_constraints,
+        )
+    }));
+    let config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let name = ServerName::try_from(url.host.as_str())?;
+    let tls = TlsConnector::from(Arc::new(config))
+        .connect(name, tcp)
+        .await
+        .with_context(|| format!("TLS handshake with {} failed", url.authority))?;
+    Ok(Box::new(tls))
+}
+
+async fn send<S>(
+    method: &str,
+    url: &Url,
+    headers: &[(&str, String)],
+    mut body: S,
+) -> Result<(Response, BufReader<Box<dyn Transport>>)>
+where
+    S: Stream<Item = Vec<u8>> + Unpin,
+{
+    let mut head = format!(
+        "{method} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n",
+        url.target, url.authority
+    );
+    for (name, value) in headers {
+        // Values come from the upload service, don't let them add headers
+        if value.contains(|c| c == '\r' || c == '\n') {
+            anyhow::bail!("Invalid value for HTTP header {name}");
+        }
+        head.push_str(&format!("{name}: {value}\r\n"));
+    }
+    head.push_str("\r\n");
+
+    let mut transport = BufReader::new(connect(url).await?);
+    transport.write_all(head.as_bytes()).await?;
+    while let Some(chunk) = body.next().await {
+        transport.write_all(&chunk).await?;
+    }
+    transport.flush().await?;
+
+    let response = read_head(&mut transport).await?;
+    Ok((response, transport))
+}
+
+async fn read_line<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<String> {
+    let mut line = String::new();
+    if reader.read_line(&mut line).await? == 0 {
+        anyhow::bail!("Connection closed by the HTTP server");
+    }
+    Ok(line
+        .trim_end_matches(|c| c == '\r' || c == '\n')
+        .to_string())
+}
+
+/// Read the status line and headers of the response
+async fn read_head<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<Response> {
+    loop {
+        let status_line = read_line(reader).await?;
+        let status: u16 = status_line
+            .split(' ')
+            .nth(1)
+            .and_then(|status| status.parse().ok())
+            .with_context(|| format!("Invalid HTTP status line {status_line}"))?;
+
+        let mut headers = Vec::new();
+        loop {
+            let line = read_line(reader).await?;
+            if line.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+            }
+        }
+
+        // Interim responses (100 Continue) are followed by the actual one
+        if !(100..200).contains(&status) {
+            return Ok(Response {
+                status,
+                headers,
+                body: Vec::new(),
+            });
+        }
+    }
+}
+
+/// Read the body of the response in memory
+async fn read_response_body<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    mut response: Response,
+) -> Result<Response> {
+    let mut body = Vec::new();
+    read_body(reader, &response, &mut body, MAX_BODY_SIZE).await?;
+    response.body = body;
+    Ok(response)
+}
+
+/// Write the body of the response to the sink, whatever its transfer encoding
+async fn read_body<R, W>(
+    reader: &mut R,
+    response: &Response,
+    sink: &mut W,
+    max_size: u64,
+) -> Result<()>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let chunked = response
+        .header("transfer-encoding")
+        .map_or(false, |encoding| encoding.eq_ignore_ascii_case("chunked"));
+    let length = response
+        .header("content-length")
+        .and_then(|length| length.parse::<u64>().ok());
+    let mut size = 0;
+    if chunked {
+        loop {
+            let line = read_line(reader).await?;
+            let chunk = line.split(';').next().unwrap_or_default().trim();
+            let chunk = u64::from_str_radix(chunk, 16)
+                .with_context(|| format!("Invalid HTTP chunk size {chunk}"))?;
+            if chunk == 0 {
+                // Skip trailers
+                while !read_line(reader).await?.is_empty() {}
+                break;
+            }
+            size = size
+                .checked_add(chunk)
+                .filter(|size| *size <= max_size)
+                .with_context(|| format!("HTTP body larger than {max_size} bytes"))?;
+            copy(reader, sink, Some(chunk), max_size).await?;
+            read_line(reader).await?;
+        }
+    } else if let Some(length) = length {
+        if length > max_size {
+            anyhow::bail!("HTTP body larger than {max_size} bytes");
+        }
+        copy(reader, sink, Some(length), max_size).await?;
+    } else {
+        copy(reader, sink, None, max_size).await?;
+    }
+    Ok(())
+}
+
+/// Copy `length` bytes, or everything until the connection is closed
+async fn copy<R, W>(reader: &mut R, sink: &mut W, length: Option<u64>, max_size: u64) -> Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut buffer = [0; 8192];
+    let mut copied = 0;
+    while Some(copied) != length {
+        let wanted = match length {
+            Some(length) => buffer.len().min((length - copied) as usize),
+            None => buffer.len(),
+        };
+        let read = reader.read(&mut buffer[..wanted]).await?;
+        if read == 0 {
+            match length {
+                Some(_) => anyhow::bail!("Connection closed by the HTTP server"),
+                None => break,
+            }
+        }
+        copied += read as u64;
+        if copied > max_size {
+            anyhow::bail!("HTTP body larger than {max_size} bytes");
+        }
+        sink.write_all(&buffer[..read]).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(raw: &str) -> Result<Response> {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let mut reader = raw.as_bytes();
+            let response = read_head(&mut reader).await?;
+            read_response_body(&mut reader, response).await
+        })
+    }
+
+    #[test]
+    fn test_parse_url() {
+        // When
+        let url: Url = "https://upload.server.tld:5281/file/a b.png?token=1#frag"
+            .parse()
+            .unwrap();
+
+        // Then
+        assert_eq!(
+            url,
+            Url {
+                tls: true,
+                authority: String::from("upload.server.tld:5281"),
+                host: String::from("upload.server.tld"),
+                port: 5281,
+                target: String::from("/file/a%20b.png?token=1"),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_url_default_port_and_path() {
+        // When
+        let url: Url = "http://server.tld".parse().unwrap();
+
+        // Then
+        assert_eq!(url.port, 80);
+        assert_eq!(url.target, "/");
+    }
+
+    #[test]
+    fn test_parse_url_rejects_other_schemes() {
+        assert!("ftp://server.tld/file".parse::<Url>().is_err());
+    }
+
+    #[test]
+    fn test_parse_url_rejects_spaces_in_host() {
+        assert!("https://server.tld\r\nX-Test: yes/file"
+            .parse::<Url>()
+            .is_err());
+    }
+
+    #[test]
+    fn test_join_absolute_path() {
+        // Given
+        let url: Url = "https://server.tld/a/b".parse().unwrap();
+
+        // When
+        let joined = url.join("/c").unwrap();
+
+        // Then
+        assert_eq!(joined, "https://server.tld/c".parse::<Url>().unwrap());
+    }
+
+    #[test]
+    fn test_read_response_with_length() {
+        // When
+        let response =
+            read("HTTP/1.1 200 OK\r\nContent-Length: 5\r\nX-Test: yes\r\n\r\nhello").unwrap();
+
+        // Then
+        assert_eq!(response.status, 200);
+        assert_eq!(response.header("x-test"), Some("yes"));
+        assert_eq!(response.body, b"hello");
+    }
+
+    #[test]
+    fn test_read_chunked_response() {
+        // When
+        let response = read(
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n4\r\nhell\r\n1;ext\r\no\r\n0\r\n\r\n",
+        )
+        .unwrap();
+
+        // Then
+        assert_eq!(response.body, b"hello");
+    }
+
+    #[test]
+    fn test_read_response_too_large() {
+        // When
+        let response = read(&format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n",
+            MAX_BODY_SIZE + 1
+        ));
+
+        // Then
+        assert!(response.is_err());
+    }
+
+    #[test]
+    fn test_read_chunked_response_overflow() {
+        // When
+        let response = read(
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n1\r\na\r\nffffffffffffffff\r\n",
+        );
+
+        // Then
+        assert!(response.is_err());
+    }
+
+    #[test]
+    fn test_read_response_skips_continue() {
+        // When
+        let response = read("HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 201 Created\r\n\r\n").unwrap();
+
+        // Then
+        assert_eq!(response.status, 201);
+        assert!(response.body.is_empty());
+    }
+
+    #[test]
+    fn test_error_for_status() {
+        // When
+        let response = read("HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n").unwrap();
+
+        // Then
+        assert!(response.error_for_status().is_err());
+    }
+}
@@ -1,3 +1,22 @@
+use std::sync::OnceLock;
+
+/// Languages configured by the user, most preferred first
+static PREFERRED_LANGS: OnceLock<Vec<String>> = OnceLock::new();
+
+pub fn set_preferred_langs(langs: Vec<String>) {
+    if PREFERRED_LANGS.set(langs).is_err() {
+        log::warn!("Preferred languages already set");
+    }
+}
+
+/// Languages to give to `get_best` when picking among incoming texts
+pub fn preferred_langs() -> Vec<&'static str> {
+    PREFERRED_LANGS
+        .get()
+        .map(|langs| langs.iter().map(|lang| lang.as_str()).collect())
+        .unwrap_or_default()
+}
+
 pub fn get_best<'a, 'b, I, L, T: ?Sized>(
     items: I,
     mut prefered_langs: Vec<&'b str>,
@@ -107,6 +126,18 @@ mod tests_command_parser {
         assert_eq!(best, Some(("fr", "français")));
     }
 
+    #[test]
+    fn test_get_best_lang_follows_pref_order() {
+        // Given
+        let items = vec![("", "orig"), ("fr", "français"), ("en", "english")];
+
+        // When
+        let best = get_best(items, vec!["de", "en", "fr"]);
+
+        // Then
+        assert_eq!(best, Some(("en", "english")));
+    }
+
     #[test]
     fn test_get_best_lang_without_pref() {
         // Given
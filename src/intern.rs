@@ -0,0 +1,89 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// Interned names are collected once their count doubles since the last collection
+const INITIAL_THRESHOLD: usize = 1024;
+
+static INTERNER: OnceLock<Mutex<Interner>> = OnceLock::new();
+
+/// Set of shared names, so that names repeated across events, conversation state and messages,
+/// such as nicks of occupants, reactors and moderators, are allocated once and cloned without copy
+struct Interner {
+    names: HashSet<Arc<str>>,
+    threshold: usize,
+}
+
+impl Interner {
+    fn new() -> Self {
+        Self {
+            names: HashSet::new(),
+            threshold: INITIAL_THRESHOLD,
+        }
+    }
+
+    fn intern(&mut self, name: &str) -> Arc<str> {
+        if let Some(interned) = self.names.get(name) {
+            return interned.clone();
+        }
+
+        if self.names.len() >= self.threshold {
+            self.collect();
+        }
+
+        let interned: Arc<str> = Arc::from(name);
+        self.names.insert(interned.clone());
+        interned
+    }
+
+    /// Forget names no longer used anywhere else
+    fn collect(&mut self) {
+        self.names.retain(|name| Arc::strong_count(name) > 1);
+        self.threshold = (self.names.len() * 2).max(INITIAL_THRESHOLD);
+    }
+}
+
+/// Shared copy of a name, allocated on first use only
+pub fn intern(name: &str) -> Arc<str> {
+    INTERNER
+        .get_or_init(|| Mutex::new(Interner::new()))
+        .lock()
+        .expect("cannot lock interner")
+        .intern(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_intern_shares_names() {
+        // Given
+        let mut interner = Interner::new();
+
+        // When
+        let first = interner.intern("nick");
+        let second = interner.intern("nick");
+
+        // Then
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(&*first, "nick");
+    }
+
+    #[test]
+    fn test_intern_collects_unused_names() {
+        // Given
+        let mut interner = Interner::new();
+        let kept = interner.intern("kept");
+        interner.intern("dropped");
+
+        // When
+        interner.collect();
+
+        // Then
+        assert_eq!(interner.names.len(), 1);
+        assert!(Arc::ptr_eq(&kept, &interner.intern("kept")));
+    }
+}
@@ -21,9 +21,16 @@ mod command;
 mod color;
 mod crypto;
 mod cursor;
+mod http;
 mod i18n;
+mod intern;
 mod mods;
+mod quiet_hours;
+mod redact;
+mod send_queue;
+mod stats;
 mod storage;
+mod stream_management;
 mod word;
 
 use crate::core::Aparte;
@@ -37,6 +44,9 @@ struct Args {
     /// Path to the shared dir
     #[arg(short, long)]
     shared: Option<std::path::PathBuf>,
+    /// Log debug messages and report startup timings
+    #[arg(short, long)]
+    debug: bool,
 }
 
 fn main() -> Result<()> {
@@ -55,11 +65,14 @@ fn main() -> Result<()> {
         aparte_data
     };
 
-    let logger = flexi_logger::Logger::try_with_env_or_str("info")?.log_to_file(
-        flexi_logger::FileSpec::default()
-            .directory(&aparte_data)
-            .suppress_timestamp(),
-    );
+    let level = if args.debug { "debug" } else { "info" };
+    let logger = flexi_logger::Logger::try_with_env_or_str(level)?
+        .log_to_file(
+            flexi_logger::FileSpec::default()
+                .directory(&aparte_data)
+                .suppress_timestamp(),
+        )
+        .format(redact::log_format);
     if let Err(e) = logger.start() {
         panic!("Cannot start logger: {}", e);
     }
@@ -82,7 +95,7 @@ fn main() -> Result<()> {
 
     log::info!("Starting aparté");
 
-    let mut aparte = Aparte::new(config, storage)?;
+    let mut aparte = Aparte::new(config, storage, args.debug)?;
 
     aparte.init().unwrap();
 
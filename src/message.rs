@@ -2,17 +2,34 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 use chrono::{DateTime, FixedOffset, Local as LocalTz};
+use log::Level;
 use std::cmp::Ordering;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::convert::TryFrom;
+use std::fmt;
 use std::hash;
+use std::str::FromStr;
+use std::sync::Arc;
 use uuid::Uuid;
+use xmpp_parsers::chat_markers::Markable;
 use xmpp_parsers::delay::Delay;
 use xmpp_parsers::message::{Message as XmppParsersMessage, MessageType as XmppParsersMessageType};
+use xmpp_parsers::message_correct::Replace;
+use xmpp_parsers::oob::Oob;
+use xmpp_parsers::receipts::Request;
+use xmpp_parsers::stanza_id::{OriginId, StanzaId};
 use xmpp_parsers::{BareJid, Jid};
 
 use crate::account::Account;
 use crate::i18n;
+use crate::word;
+
+/// XEP-0382: Spoiler messages
+pub const NS_SPOILER: &str = "urn:xmpp:spoiler:0";
+/// XEP-0461: Message Replies
+pub const NS_REPLY: &str = "urn:xmpp:reply:0";
+/// XEP-0428: Fallback Indication
+pub const NS_FALLBACK: &str = "urn:xmpp:fallback:0";
 
 #[derive(Debug, Clone)]
 pub struct XmppMessageVersion {
@@ -51,6 +68,84 @@ impl XmppMessageVersion {
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct Spoiler {
+    /// Hint displayed in place of the body, may be empty
+    pub hint: String,
+    /// Whether the user asked to display the body
+    pub revealed: bool,
+}
+
+/// XEP-0461: Message Replies
+#[derive(Debug, Clone)]
+pub struct Reply {
+    /// Id of the message replied to
+    pub id: String,
+    /// Author of the message replied to
+    pub to: Option<Jid>,
+    /// Body of the message replied to, or the quote sent as fallback when it isn't known
+    pub quote: Option<String>,
+}
+
+impl From<&VersionedXmppMessage> for Reply {
+    fn from(message: &VersionedXmppMessage) -> Self {
+        let to = match message.type_ {
+            XmppMessageType::Channel => message.from_full.clone(),
+            XmppMessageType::Chat => Jid::Bare(message.from.clone()),
+        };
+        Self {
+            id: message.id.clone(),
+            to: Some(to),
+            quote: Some(message.get_last_body().to_string()),
+        }
+    }
+}
+
+/// XEP-0425: Moderation of a channel message
+#[derive(Debug, Clone)]
+pub struct Moderation {
+    /// Nick of the moderator
+    pub by: Option<Arc<str>>,
+    pub reason: Option<String>,
+}
+
+/// Delivery state of an outgoing chat message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Delivery {
+    /// Displayed locally, not handed to the connection yet
+    Pending,
+    Sent,
+    /// XEP-0184: Message Delivery Receipts
+    Received,
+    /// XEP-0333: Displayed Markers
+    Displayed,
+}
+
+impl FromStr for Delivery {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pending" => Ok(Delivery::Pending),
+            "sent" => Ok(Delivery::Sent),
+            "received" => Ok(Delivery::Received),
+            "displayed" => Ok(Delivery::Displayed),
+            _ => anyhow::bail!("Unknown delivery state {s}"),
+        }
+    }
+}
+
+impl fmt::Display for Delivery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Delivery::Pending => write!(f, "pending"),
+            Delivery::Sent => write!(f, "sent"),
+            Delivery::Received => write!(f, "received"),
+            Delivery::Displayed => write!(f, "displayed"),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct VersionedXmppMessage {
     pub id: String,
@@ -62,16 +157,64 @@ pub struct VersionedXmppMessage {
     pub type_: XmppMessageType,
     pub direction: Direction,
     pub archive: bool,
+    pub spoiler: Option<Spoiler>,
+    /// Whether the user asked to display the whole body of a long message
+    pub expanded: bool,
+    /// Delivery state of outgoing chat messages
+    pub delivery: Option<Delivery>,
+    /// Time at which each delivery state was reached, only known for those reached since startup
+    pub delivery_timeline: Vec<(Delivery, DateTime<FixedOffset>)>,
+    /// Whether this is the last message the contact displayed
+    pub read_marker: bool,
+    /// XEP-0066: Out of Band Data, URL of a shared file also given as body
+    pub oob: Option<String>,
+    pub reply: Option<Reply>,
+    /// XEP-0424: Message Retraction, the body is kept to be displayed on demand
+    pub retracted: bool,
+    /// Set when the message was retracted by a moderator of the channel
+    pub moderation: Option<Moderation>,
+    /// XEP-0359: id given to channel messages by the channel, referenced by moderators
+    pub stanza_id: Option<String>,
+    /// XEP-0444: Message Reactions, emojis of each reactor: bare jid in chats, nick in channels
+    pub reactions: BTreeMap<Arc<str>, Vec<String>>,
 }
 
 impl VersionedXmppMessage {
+    /// Number of reactors of each emoji, in order of first appearance
+    pub fn reaction_counts(&self) -> Vec<(&str, usize)> {
+        let mut counts: Vec<(&str, usize)> = Vec::new();
+        for emoji in self.reactions.values().flatten() {
+            match counts.iter_mut().find(|(counted, _)| counted == emoji) {
+                Some((_, count)) => *count += 1,
+                None => counts.push((emoji, 1)),
+            }
+        }
+        counts
+    }
+
     pub fn get_last_bodies<'a>(&'a self) -> impl Iterator<Item = (&'a String, &'a String)> {
         let last = self.history.iter().max().unwrap();
         last.bodies.iter()
     }
     pub fn get_last_body<'a>(&'a self) -> &'a str {
         let last = self.history.iter().max().unwrap();
-        last.get_best_body(vec![])
+        last.get_best_body(i18n::preferred_langs())
+    }
+
+    /// Id of the stanza carrying the last version of the message
+    pub fn get_last_id<'a>(&'a self) -> &'a str {
+        let last = self.history.iter().max().unwrap();
+        &last.id
+    }
+
+    /// XEP-0308: Last Message Correction, replacing the first version by the last one
+    pub fn replace(&self) -> Option<Replace> {
+        match self.has_multiple_version() {
+            true => Some(Replace {
+                id: self.id.clone(),
+            }),
+            false => None,
+        }
     }
 
     pub fn get_original_timestamp<'a>(&'a self) -> &'a DateTime<FixedOffset> {
@@ -84,11 +227,7 @@ impl VersionedXmppMessage {
             .id
             .clone()
             .unwrap_or_else(|| Uuid::new_v4().to_string());
-        let bodies: HashMap<String, String> = message
-            .bodies
-            .iter()
-            .map(|(lang, body)| (lang.clone(), body.0.clone()))
-            .collect();
+        let (bodies, _quote) = bodies_from_xmpp(message);
 
         let delay = message
             .payloads
@@ -98,6 +237,11 @@ impl VersionedXmppMessage {
             .map(|delay| delay.stamp.0)
             .unwrap_or(LocalTz::now().into());
 
+        // Our own corrections are reflected by channels
+        if self.history.iter().any(|version| version.id == id) {
+            return;
+        }
+
         self.history.push(XmppMessageVersion {
             id,
             timestamp,
@@ -108,6 +252,28 @@ impl VersionedXmppMessage {
     pub fn has_multiple_version(&self) -> bool {
         self.history.len() > 1
     }
+
+    /// URLs of files shared by the message, the OOB one first then those found in the body
+    pub fn attachments(&self) -> Vec<String> {
+        let mut attachments: Vec<String> = self.oob.iter().cloned().collect();
+        for url in word::file_urls(self.get_last_body()) {
+            if !attachments.iter().any(|attachment| attachment == url) {
+                attachments.push(url.to_string());
+            }
+        }
+        attachments
+    }
+
+    /// Reveal the body of a spoiler message, return true if it was hidden
+    pub fn reveal_spoiler(&mut self) -> bool {
+        match &mut self.spoiler {
+            Some(spoiler) if !spoiler.revealed => {
+                spoiler.revealed = true;
+                true
+            }
+            _ => false,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Eq, PartialEq)]
@@ -127,6 +293,7 @@ pub struct LogMessage {
     pub id: String,
     pub timestamp: DateTime<FixedOffset>,
     pub body: String,
+    pub level: Level,
 }
 
 #[derive(Debug, Clone)]
@@ -147,11 +314,7 @@ impl Message {
             .clone()
             .unwrap_or_else(|| Uuid::new_v4().to_string());
         if let Some(from) = message.from.clone() {
-            let bodies: HashMap<String, String> = message
-                .bodies
-                .iter()
-                .map(|(lang, body)| (lang.clone(), body.0.clone()))
-                .collect();
+            let (bodies, quote) = bodies_from_xmpp(message);
             let delay = match delay {
                 Some(delay) => Some(delay.clone()),
                 None => message
@@ -163,8 +326,34 @@ impl Message {
                 Some(to) => to,
                 None => account.clone().into(),
             };
+            let spoiler = message
+                .payloads
+                .iter()
+                .find(|payload| payload.is("spoiler", NS_SPOILER))
+                .map(|spoiler| spoiler.text());
+            let oob = message
+                .payloads
+                .iter()
+                .find_map(|payload| Oob::try_from(payload.clone()).ok());
+            let reply = message
+                .payloads
+                .iter()
+                .find(|payload| payload.is("reply", NS_REPLY))
+                .and_then(|reply| {
+                    Some(Reply {
+                        id: reply.attr("id")?.to_string(),
+                        to: reply.attr("to").and_then(|to| Jid::from_str(to).ok()),
+                        quote,
+                    })
+                });
+            let stanza_id = message
+                .payloads
+                .iter()
+                .filter_map(|payload| StanzaId::try_from(payload.clone()).ok())
+                .find(|stanza_id| stanza_id.by == Jid::Bare(from.to_bare()))
+                .map(|stanza_id| stanza_id.id);
 
-            match message.type_ {
+            let message = match message.type_ {
                 XmppParsersMessageType::Chat => {
                     if from.clone().node() == account.node()
                         && from.clone().domain() == account.domain()
@@ -203,7 +392,20 @@ impl Message {
                     archive,
                 )),
                 _ => Err(()),
-            }
+            };
+
+            message.map(|mut message| {
+                if let Message::Xmpp(message) = &mut message {
+                    if message.type_ == XmppMessageType::Channel {
+                        message.stanza_id = stanza_id;
+                    }
+                }
+                let message = message.with_spoiler(spoiler).with_reply(reply);
+                match oob {
+                    Some(oob) => message.with_oob(oob.url),
+                    None => message,
+                }
+            })
         } else {
             Err(())
         }
@@ -284,6 +486,17 @@ impl Message {
             type_: XmppMessageType::Chat,
             direction: Direction::Incoming,
             archive,
+            spoiler: None,
+            expanded: false,
+            reactions: BTreeMap::new(),
+            delivery: None,
+            delivery_timeline: Vec::new(),
+            read_marker: false,
+            oob: None,
+            reply: None,
+            retracted: false,
+            moderation: None,
+            stanza_id: None,
         })
     }
 
@@ -313,6 +526,17 @@ impl Message {
             type_: XmppMessageType::Chat,
             direction: Direction::Outgoing,
             archive,
+            spoiler: None,
+            expanded: false,
+            reactions: BTreeMap::new(),
+            delivery: Some(Delivery::Sent),
+            delivery_timeline: vec![(Delivery::Sent, timestamp)],
+            read_marker: false,
+            oob: None,
+            reply: None,
+            retracted: false,
+            moderation: None,
+            stanza_id: None,
         })
     }
 
@@ -342,6 +566,17 @@ impl Message {
             type_: XmppMessageType::Channel,
             direction: Direction::Incoming,
             archive,
+            spoiler: None,
+            expanded: false,
+            reactions: BTreeMap::new(),
+            delivery: None,
+            delivery_timeline: Vec::new(),
+            read_marker: false,
+            oob: None,
+            reply: None,
+            retracted: false,
+            moderation: None,
+            stanza_id: None,
         })
     }
 
@@ -371,14 +606,63 @@ impl Message {
             type_: XmppMessageType::Channel,
             direction: Direction::Outgoing,
             archive,
+            spoiler: None,
+            expanded: false,
+            reactions: BTreeMap::new(),
+            delivery: None,
+            delivery_timeline: Vec::new(),
+            read_marker: false,
+            oob: None,
+            reply: None,
+            retracted: false,
+            moderation: None,
+            stanza_id: None,
         })
     }
 
+    /// Outgoing chat message echoed before being sent, upgraded once sent
+    pub fn pending(mut self) -> Self {
+        if let Message::Xmpp(message) = &mut self {
+            let timestamp = *message.get_original_timestamp();
+            message.delivery = Some(Delivery::Pending);
+            message.delivery_timeline = vec![(Delivery::Pending, timestamp)];
+        }
+        self
+    }
+
+    pub fn with_spoiler(mut self, hint: Option<String>) -> Self {
+        if let Message::Xmpp(message) = &mut self {
+            // We already know what we sent
+            let revealed = message.direction == Direction::Outgoing;
+            message.spoiler = hint.map(|hint| Spoiler { hint, revealed });
+        }
+        self
+    }
+
+    pub fn with_oob(mut self, url: String) -> Self {
+        if let Message::Xmpp(message) = &mut self {
+            message.oob = Some(url);
+        }
+        self
+    }
+
+    pub fn with_reply(mut self, reply: Option<Reply>) -> Self {
+        if let Message::Xmpp(message) = &mut self {
+            message.reply = reply;
+        }
+        self
+    }
+
     pub fn log(msg: String) -> Self {
+        Message::log_with_level(Level::Info, msg)
+    }
+
+    pub fn log_with_level(level: Level, msg: String) -> Self {
         Message::Log(LogMessage {
             id: Uuid::new_v4().to_string(),
             timestamp: LocalTz::now().into(),
             body: msg,
+            level,
         })
     }
 
@@ -395,7 +679,6 @@ impl Message {
         }
     }
 
-    #[allow(dead_code)]
     pub fn body<'a>(&'a self) -> &'a str {
         match self {
             Message::Xmpp(message) => message.get_last_body(),
@@ -462,7 +745,7 @@ impl TryFrom<Message> for xmpp_parsers::Element {
                         let mut xmpp_message = xmpp_parsers::message::Message::new(Some(
                             Jid::Bare(message.to.clone()),
                         ));
-                        xmpp_message.id = Some(message.id.clone());
+                        xmpp_message.id = Some(message.get_last_id().to_string());
                         xmpp_message.type_ = xmpp_parsers::message::MessageType::Chat;
                         xmpp_message.bodies = message
                             .get_last_bodies()
@@ -470,20 +753,62 @@ impl TryFrom<Message> for xmpp_parsers::Element {
                                 (lang.clone(), xmpp_parsers::message::Body(body.clone()))
                             })
                             .collect();
+                        if let Some(spoiler) = &message.spoiler {
+                            xmpp_message.payloads.push(spoiler_element(&spoiler.hint));
+                        }
+                        if let Some(reply) = &message.reply {
+                            add_reply(&mut xmpp_message, reply);
+                        }
+                        if let Some(replace) = message.replace() {
+                            xmpp_message.payloads.push(replace.into());
+                        }
+                        if let Some(url) = &message.oob {
+                            let oob = Oob {
+                                url: url.clone(),
+                                desc: None,
+                            };
+                            xmpp_message.payloads.push(oob.into());
+                        }
+                        xmpp_message.payloads.push(Request.into());
+                        xmpp_message.payloads.push(Markable.into());
                         Ok(xmpp_message.into())
                     }
                     XmppMessageType::Channel => {
                         let mut xmpp_message = xmpp_parsers::message::Message::new(Some(
                             Jid::Bare(message.to.clone()),
                         ));
-                        xmpp_message.id = Some(message.id.clone());
+                        xmpp_message.id = Some(message.get_last_id().to_string());
                         xmpp_message.type_ = xmpp_parsers::message::MessageType::Groupchat;
+                        // XEP-0359: keeps our id on the reflection, whatever the channel does
+                        // with the stanza id
+                        xmpp_message.payloads.push(
+                            OriginId {
+                                id: message.get_last_id().to_string(),
+                            }
+                            .into(),
+                        );
                         xmpp_message.bodies = message
                             .get_last_bodies()
                             .map(|(lang, body)| {
                                 (lang.clone(), xmpp_parsers::message::Body(body.clone()))
                             })
                             .collect();
+                        if let Some(spoiler) = &message.spoiler {
+                            xmpp_message.payloads.push(spoiler_element(&spoiler.hint));
+                        }
+                        if let Some(reply) = &message.reply {
+                            add_reply(&mut xmpp_message, reply);
+                        }
+                        if let Some(replace) = message.replace() {
+                            xmpp_message.payloads.push(replace.into());
+                        }
+                        if let Some(url) = &message.oob {
+                            let oob = Oob {
+                                url: url.clone(),
+                                desc: None,
+                            };
+                            xmpp_message.payloads.push(oob.into());
+                        }
                         Ok(xmpp_message.into())
                     }
                 },
@@ -492,3 +817,87 @@ impl TryFrom<Message> for xmpp_parsers::Element {
         }
     }
 }
+
+/// Bodies of a message without the quote of a reply fallback, returned apart
+fn bodies_from_xmpp(message: &XmppParsersMessage) -> (HashMap<String, String>, Option<String>) {
+    let range = message
+        .payloads
+        .iter()
+        .find(|payload| {
+            payload.is("fallback", NS_FALLBACK) && payload.attr("for") == Some(NS_REPLY)
+        })
+        .and_then(|fallback| fallback.get_child("body", NS_FALLBACK))
+        .and_then(|body| {
+            let start = body.attr("start")?.parse::<usize>().ok()?;
+            let end = body.attr("end")?.parse::<usize>().ok()?;
+            Some(start..end)
+        });
+
+    let mut quote = None;
+    let bodies = message
+        .bodies
+        .iter()
+        .map(|(lang, body)| {
+            let chars = body.0.chars().collect::<Vec<char>>();
+            let body = match &range {
+                // Ranges are counted in code points
+                Some(range) if range.start <= range.end && range.end <= chars.len() => {
+                    let fallback = chars[range.clone()].iter().collect::<String>();
+                    quote.get_or_insert_with(|| {
+                        fallback
+                            .lines()
+                            .map(|line| line.trim_start_matches('>').trim_start())
+                            .collect::<Vec<_>>()
+                            .join("\n")
+                    });
+                    chars[..range.start]
+                        .iter()
+                        .chain(chars[range.end..].iter())
+                        .collect()
+                }
+                _ => body.0.clone(),
+            };
+            (lang.clone(), body)
+        })
+        .collect();
+
+    (bodies, quote)
+}
+
+/// Reference the message replied to, with its quote as fallback for clients not supporting replies
+fn add_reply(xmpp_message: &mut XmppParsersMessage, reply: &Reply) {
+    let mut element =
+        xmpp_parsers::Element::builder("reply", NS_REPLY).attr("id", reply.id.as_str());
+    if let Some(to) = &reply.to {
+        element = element.attr("to", to.to_string());
+    }
+    xmpp_message.payloads.push(element.build());
+
+    if let Some(quote) = &reply.quote {
+        let fallback = quote
+            .lines()
+            .map(|line| format!("> {line}\n"))
+            .collect::<String>();
+        for body in xmpp_message.bodies.values_mut() {
+            body.0 = format!("{fallback}{}", body.0);
+        }
+        let range = xmpp_parsers::Element::builder("body", NS_FALLBACK)
+            .attr("start", "0")
+            .attr("end", fallback.chars().count().to_string())
+            .build();
+        xmpp_message.payloads.push(
+            xmpp_parsers::Element::builder("fallback", NS_FALLBACK)
+                .attr("for", NS_REPLY)
+                .append(range)
+                .build(),
+        );
+    }
+}
+
+fn spoiler_element(hint: &str) -> xmpp_parsers::Element {
+    let builder = xmpp_parsers::Element::builder("spoiler", NS_SPOILER);
+    match hint.is_empty() {
+        true => builder.build(),
+        false => builder.append(hint.to_string()).build(),
+    }
+}
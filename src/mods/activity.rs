@@ -0,0 +1,181 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use std::collections::VecDeque;
+use std::fmt;
+
+use chrono::{DateTime, FixedOffset, Local as LocalTz};
+use uuid::Uuid;
+use xmpp_parsers::presence::Type as PresenceType;
+use xmpp_parsers::BareJid;
+
+use crate::account::Account;
+use crate::command::{Command, CommandParser};
+use crate::core::{Aparte, Event, ModTrait};
+use crate::message::{LogMessage, Message};
+
+/// Number of entries kept in the activity log
+const ACTIVITY_LOG_SIZE: usize = 1000;
+
+command_def!(activity,
+r#"/activity [<account>]
+
+    account    Only show activity of this account
+
+Description:
+    Show connection events, subscription changes, bookmark updates and
+    errors of all accounts in the activity window. Activity not related to
+    a specific account is only shown when no account is given.
+
+Examples:
+    /activity
+    /activity me@example.org
+"#,
+{
+    account: Option<BareJid> = {
+        completion: |aparte, _command| {
+            aparte.get_mod::<ActivityMod>().accounts()
+        }
+    },
+},
+|aparte, _command| {
+    let entries = {
+        let activity = aparte.get_mod::<ActivityMod>();
+        activity.filter(account.as_ref())
+    };
+    aparte.schedule(Event::ActivityLog {
+        filter: account,
+        entries,
+    });
+    Ok(())
+});
+
+struct ActivityEntry {
+    timestamp: DateTime<FixedOffset>,
+    account: Option<Account>,
+    text: String,
+}
+
+impl ActivityEntry {
+    fn to_message(&self) -> Message {
+        let body = match &self.account {
+            Some(account) => format!("{} {}", account.to_bare(), self.text),
+            None => self.text.clone(),
+        };
+        Message::Log(LogMessage {
+            id: Uuid::new_v4().to_string(),
+            timestamp: self.timestamp,
+            body,
+            level: log::Level::Info,
+        })
+    }
+}
+
+pub struct ActivityMod {
+    /// Last activity entries, oldest first
+    entries: VecDeque<ActivityEntry>,
+}
+
+impl ActivityMod {
+    pub fn new() -> Self {
+        Self {
+            entries: VecDeque::new(),
+        }
+    }
+
+    /// Accounts having some recorded activity
+    fn accounts(&self) -> Vec<String> {
+        let mut accounts: Vec<String> = self
+            .entries
+            .iter()
+            .filter_map(|entry| entry.account.as_ref())
+            .map(|account| account.to_bare().to_string())
+            .collect();
+        accounts.sort();
+        accounts.dedup();
+        accounts
+    }
+
+    fn filter(&self, account: Option<&BareJid>) -> Vec<Message> {
+        self.entries
+            .iter()
+            .filter(|entry| match (account, &entry.account) {
+                (None, _) => true,
+                (Some(filter), Some(account)) => &account.to_bare() == filter,
+                (Some(_), None) => false,
+            })
+            .map(ActivityEntry::to_message)
+            .collect()
+    }
+
+    fn record(&mut self, aparte: &mut Aparte, account: Option<&Account>, text: String) {
+        while self.entries.len() >= ACTIVITY_LOG_SIZE {
+            self.entries.pop_front();
+        }
+
+        let entry = ActivityEntry {
+            timestamp: LocalTz::now().into(),
+            account: account.cloned(),
+            text,
+        };
+        aparte.schedule(Event::Activity {
+            account: entry.account.clone(),
+            message: entry.to_message(),
+        });
+        self.entries.push_back(entry);
+    }
+}
+
+impl ModTrait for ActivityMod {
+    fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
+        aparte.add_command(activity::new());
+        Ok(())
+    }
+
+    fn on_event(&mut self, aparte: &mut Aparte, event: &Event) {
+        match event {
+            Event::Connected(account, _) => {
+                self.record(aparte, Some(account), String::from("Connected"))
+            }
+            Event::Disconnected(account, reason) => {
+                self.record(aparte, Some(account), format!("Connection lost: {reason}"))
+            }
+            Event::AuthError(account, err) => self.record(
+                aparte,
+                Some(account),
+                format!("Authentication error: {err}"),
+            ),
+            Event::Presence(account, presence) => {
+                let from = match &presence.from {
+                    Some(from) => from.to_bare().to_string(),
+                    None => return,
+                };
+                let text = match presence.type_ {
+                    PresenceType::Subscribe => format!("{from} asks for subscription"),
+                    PresenceType::Subscribed => format!("{from} accepted subscription"),
+                    PresenceType::Unsubscribe => format!("{from} unsubscribed"),
+                    PresenceType::Unsubscribed => format!("{from} revoked subscription"),
+                    PresenceType::Error => format!("Presence error from {from}"),
+                    _ => return,
+                };
+                self.record(aparte, Some(account), text);
+            }
+            Event::Bookmark(account, bookmark) => self.record(
+                aparte,
+                Some(account),
+                format!("Bookmarked {}", bookmark.jid),
+            ),
+            Event::DeletedBookmark(jid) => {
+                self.record(aparte, None, format!("Deleted bookmark {jid}"))
+            }
+            Event::Error(error) => self.record(aparte, None, error.clone()),
+            _ => {}
+        }
+    }
+}
+
+impl fmt::Display for ActivityMod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Activity log")
+    }
+}
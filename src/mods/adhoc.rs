@@ -0,0 +1,565 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Context, Result};
+use uuid::Uuid;
+use xmpp_parsers::data_forms::{DataForm, DataFormType, FieldType};
+use xmpp_parsers::iq::{Iq, IqType};
+use xmpp_parsers::{Element, Jid};
+
+use crate::account::Account;
+use crate::command::{Command, CommandParser};
+use crate::core::{Aparte, AparteAsync, Event, ModTrait};
+use crate::i18n;
+use crate::mods::disco::DiscoMod;
+
+/// XEP-0050: Ad-Hoc Commands
+pub const NS_COMMANDS: &str = "http://jabber.org/protocol/commands";
+
+command_def!(cmd_list,
+r#"/cmd list <jid>
+
+    jid    jid of the server or component offering commands
+
+Description:
+    List the ad-hoc commands offered by an entity.
+
+Examples:
+    /cmd list example.org
+"#,
+{
+    jid: String,
+},
+|aparte, _command| {
+    let account = aparte.current_account().context("No connection found")?;
+    let jid = Jid::from_str(&jid).with_context(|| format!("Invalid jid {jid}"))?;
+    AdhocMod::list(aparte, &account, &jid);
+    Ok(())
+});
+
+command_def!(cmd_execute,
+r#"/cmd execute <jid> <node>
+
+    jid     jid of the server or component offering the command
+    node    node of the command to execute
+
+Description:
+    Execute an ad-hoc command. Forms sent by the command open in the form
+    window, they can also be answered from the console with /cmd next,
+    /cmd prev, /cmd complete or /cmd cancel.
+
+Examples:
+    /cmd execute example.org http://jabber.org/protocol/admin#get-online-users-num
+"#,
+{
+    jid: String,
+    node: String,
+},
+|aparte, _command| {
+    let account = aparte.current_account().context("No connection found")?;
+    let jid = Jid::from_str(&jid).with_context(|| format!("Invalid jid {jid}"))?;
+    AdhocMod::execute(aparte, &account, Request {
+        jid,
+        node,
+        action: String::from("execute"),
+        session_id: None,
+        form: None,
+    });
+    Ok(())
+});
+
+command_def!(
+    cmd_next,
+    r#"/cmd next [<var>=<value>...]
+
+    var      field of the form sent by the command
+    value    value of the field, repeat the field for multiple values
+
+Description:
+    Answer the form of the running command and go to its next step. Boolean
+    fields take 1 or 0.
+
+Examples:
+    /cmd next max_items=25
+"#,
+    {},
+    |aparte, command| { AdhocMod::answer_current(aparte, "next", &command.args[1..]) }
+);
+
+command_def!(
+    cmd_prev,
+    r#"/cmd prev
+
+Description:
+    Go back to the previous step of the running command.
+"#,
+    {},
+    |aparte, command| { AdhocMod::answer_current(aparte, "prev", &command.args[1..]) }
+);
+
+command_def!(
+    cmd_complete,
+    r#"/cmd complete [<var>=<value>...]
+
+    var      field of the form sent by the command
+    value    value of the field, repeat the field for multiple values
+
+Description:
+    Answer the form of the running command and complete it. Boolean fields
+    take 1 or 0.
+
+Examples:
+    /cmd complete accountjid=user@example.org accountjid=other@example.org
+"#,
+    {},
+    |aparte, command| { AdhocMod::answer_current(aparte, "complete", &command.args[1..]) }
+);
+
+command_def!(
+    cmd_cancel,
+    r#"/cmd cancel
+
+Description:
+    Give up the running command.
+"#,
+    {},
+    |aparte, command| { AdhocMod::answer_current(aparte, "cancel", &command.args[1..]) }
+);
+
+command_def!(cmd,
+r#"/cmd list|execute|next|prev|complete|cancel"#,
+{
+    action: Command = {
+        children: {
+            "list": cmd_list,
+            "execute": cmd_execute,
+            "next": cmd_next,
+            "prev": cmd_prev,
+            "complete": cmd_complete,
+            "cancel": cmd_cancel,
+        }
+    },
+});
+
+/// Response of an entity to a command request
+#[derive(Debug, Clone)]
+pub struct AdhocStep {
+    pub account: Account,
+    pub jid: Jid,
+    pub node: String,
+    pub session_id: Option<String>,
+    pub status: AdhocStatus,
+    /// Actions allowed to answer the form
+    pub actions: Vec<String>,
+    pub notes: Vec<String>,
+    pub form: Option<DataForm>,
+}
+
+/// Status of a command session, as told by the entity executing it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AdhocStatus {
+    Executing,
+    Completed,
+    Canceled,
+}
+
+/// Step of a command to request
+struct Request {
+    jid: Jid,
+    node: String,
+    action: String,
+    session_id: Option<String>,
+    form: Option<DataForm>,
+}
+
+/// Command session waiting for an answer
+struct Session {
+    jid: Jid,
+    node: String,
+    session_id: Option<String>,
+    actions: Vec<String>,
+    form: Option<DataForm>,
+}
+
+pub struct AdhocMod {
+    sessions: HashMap<Account, Session>,
+}
+
+impl AdhocMod {
+    pub fn new() -> Self {
+        Self {
+            sessions: HashMap::new(),
+        }
+    }
+
+    fn list(aparte: &mut Aparte, account: &Account, jid: &Jid) {
+        Aparte::spawn({
+            let mut aparte = aparte.proxy();
+            let account = account.clone();
+            let jid = jid.clone();
+            async move {
+                let items = DiscoMod::get_disco_items(
+                    &mut aparte,
+                    &account,
+                    &jid,
+                    Some(NS_COMMANDS.to_string()),
+                )
+                .await;
+                match items {
+                    Ok(items) if items.is_empty() => aparte.log(format!("{jid} offers no command")),
+                    Ok(items) => {
+                        let mut output = format!("Commands of {jid}:");
+                        for item in items {
+                            let node = item.node.unwrap_or_default();
+                            match item.name {
+                                Some(name) => output.push_str(&format!("\n  {node}: {name}")),
+                                None => output.push_str(&format!("\n  {node}")),
+                            }
+                        }
+                        aparte.log(output);
+                    }
+                    Err(err) => crate::error!(aparte, err, "Cannot list commands of {jid}"),
+                }
+            }
+        });
+    }
+
+    fn execute(aparte: &mut Aparte, account: &Account, request: Request) {
+        let Request {
+            jid,
+            node,
+            action,
+            session_id,
+            form,
+        } = request;
+        let mut payload = Element::builder("command", NS_COMMANDS)
+            .attr("node", node.as_str())
+            .attr("action", action);
+        if let Some(session_id) = session_id {
+            payload = payload.attr("sessionid", session_id);
+        }
+        if let Some(form) = form {
+            payload = payload.append(Element::from(form));
+        }
+        let iq = Iq {
+            from: None,
+            to: Some(jid.clone()),
+            id: Uuid::new_v4().hyphenated().to_string(),
+            payload: IqType::Set(payload.build()),
+        };
+
+        Aparte::spawn({
+            let mut aparte = aparte.proxy();
+            let account = account.clone();
+            async move {
+                match Self::request(&mut aparte, &account, iq).await {
+                    Ok(response) => match Self::step(account, jid, node, response) {
+                        Ok(step) => aparte.schedule(Event::AdhocStep(step)),
+                        Err(err) => crate::error!(aparte, err, "Invalid response to command"),
+                    },
+                    Err(err) => crate::error!(aparte, err, "Command {node} on {jid} failed"),
+                }
+            }
+        });
+    }
+
+    async fn request(aparte: &mut AparteAsync, account: &Account, iq: Iq) -> Result<Element> {
+        match aparte.iq(account, iq).await?.payload {
+            IqType::Result(Some(el)) if el.is("command", NS_COMMANDS) => Ok(el),
+            IqType::Error(err) => Err(anyhow!("{}", i18n::xmpp_err_to_string(&err, vec![]).1)),
+            _ => Err(anyhow!("invalid response")),
+        }
+    }
+
+    fn step(account: Account, jid: Jid, node: String, response: Element) -> Result<AdhocStep> {
+        let status = match response.attr("status") {
+            Some("completed") => AdhocStatus::Completed,
+            Some("canceled") => AdhocStatus::Canceled,
+            _ => AdhocStatus::Executing,
+        };
+        let actions = response
+            .get_child("actions", NS_COMMANDS)
+            .map(|actions| {
+                actions
+                    .children()
+                    .map(|action| action.name().to_string())
+                    .collect()
+            })
+            .unwrap_or_else(|| match status {
+                // Without explicit actions only completing is allowed
+                AdhocStatus::Executing => vec![String::from("complete")],
+                AdhocStatus::Completed | AdhocStatus::Canceled => Vec::new(),
+            });
+        let notes = response
+            .children()
+            .filter(|child| child.is("note", NS_COMMANDS))
+            .map(|note| match note.attr("type") {
+                Some(type_) if type_ != "info" => format!("{type_}: {}", note.text()),
+                _ => note.text(),
+            })
+            .collect();
+        let form = match response
+            .children()
+            .find(|child| child.name() == "x")
+            .cloned()
+        {
+            Some(form) => Some(DataForm::try_from(form)?),
+            None => None,
+        };
+
+        Ok(AdhocStep {
+            account,
+            jid,
+            node,
+            session_id: response.attr("sessionid").map(String::from),
+            status,
+            actions,
+            notes,
+            form,
+        })
+    }
+
+    /// Field values given as `var=value`, repeated vars adding values
+    fn parse_values(args: &[String]) -> Result<HashMap<String, Vec<String>>> {
+        let mut values: HashMap<String, Vec<String>> = HashMap::new();
+        for arg in args {
+            let (var, value) = arg
+                .split_once('=')
+                .with_context(|| format!("Invalid field value {arg}, expected <var>=<value>"))?;
+            values
+                .entry(var.to_string())
+                .or_default()
+                .push(value.to_string());
+        }
+        Ok(values)
+    }
+
+    /// Answer the running session of the current account with the given action and field values
+    fn answer_current(aparte: &mut Aparte, action: &str, args: &[String]) -> Result<()> {
+        let account = aparte.current_account().context("No connection found")?;
+        let values = Self::parse_values(args)?;
+        let request = aparte
+            .get_mod_mut::<AdhocMod>()
+            .answer(&account, action, values)?;
+        Self::execute(aparte, &account, request);
+        Ok(())
+    }
+
+    /// Answer the form of the running session with the given action and field values
+    fn answer(
+        &mut self,
+        account: &Account,
+        action: &str,
+        mut values: HashMap<String, Vec<String>>,
+    ) -> Result<Request> {
+        let session = self
+            .sessions
+            .get(account)
+            .context("No command is waiting for an answer")?;
+        if action != "cancel" && !session.actions.iter().any(|allowed| allowed == action) {
+            bail!(
+                "Cannot {action} command {}, allowed: {}",
+                session.node,
+                session.actions.join(", ")
+            );
+        }
+
+        let form = match (action, &session.form) {
+            ("next" | "complete", Some(form)) => {
+                let mut fields = Vec::new();
+                for field in form.fields.iter() {
+                    if field.type_ == FieldType::Fixed {
+                        continue;
+                    }
+                    let mut field = field.clone();
+                    if let Some(values) = values.remove(&field.var) {
+                        field.values = values;
+                    }
+                    if field.required && field.values.is_empty() {
+                        let name = field.label.as_ref().unwrap_or(&field.var);
+                        bail!("Missing value for {name} ({})", field.var);
+                    }
+                    fields.push(field);
+                }
+                if let Some(var) = values.keys().next() {
+                    bail!("Unknown field {var}");
+                }
+                Some(DataForm {
+                    type_: DataFormType::Submit,
+                    form_type: form.form_type.clone(),
+                    title: None,
+                    instructions: None,
+                    fields,
+                })
+            }
+            _ => None,
+        };
+
+        let session = self.sessions.remove(account).unwrap();
+        Ok(Request {
+            jid: session.jid,
+            node: session.node,
+            action: action.to_string(),
+            session_id: session.session_id,
+            form,
+        })
+    }
+
+    /// Id of the form displayed for the running session of an account
+    fn form_id(account: &Account) -> String {
+        format!("adhoc/{account}")
+    }
+
+    /// Answer the running session with the form filled in the form window, canceling it without
+    fn submit(&mut self, account: &Account, form: Option<DataForm>) -> Option<Request> {
+        let session = self.sessions.remove(account)?;
+        let action = match form {
+            None => "cancel",
+            Some(_) if session.actions.iter().any(|action| action == "next") => "next",
+            Some(_) => "complete",
+        };
+        Some(Request {
+            jid: session.jid,
+            node: session.node,
+            action: action.to_string(),
+            session_id: session.session_id,
+            form,
+        })
+    }
+
+    fn field_type(type_: &FieldType) -> &'static str {
+        match type_ {
+            FieldType::Boolean => "boolean",
+            FieldType::Fixed => "fixed",
+            FieldType::Hidden => "hidden",
+            FieldType::JidMulti => "jid-multi",
+            FieldType::JidSingle => "jid-single",
+            FieldType::ListMulti => "list-multi",
+            FieldType::ListSingle => "list-single",
+            FieldType::TextMulti => "text-multi",
+            FieldType::TextPrivate => "text-private",
+            FieldType::TextSingle => "text-single",
+        }
+    }
+
+    fn format_form(form: &DataForm) -> String {
+        let mut output = String::new();
+        if let Some(title) = &form.title {
+            output.push_str(&format!("\n  {title}"));
+        }
+        if let Some(instructions) = &form.instructions {
+            output.push_str(&format!("\n  {instructions}"));
+        }
+        for field in form.fields.iter() {
+            let values = field.values.join(", ");
+            match field.type_ {
+                FieldType::Hidden => continue,
+                FieldType::Fixed => {
+                    output.push_str(&format!("\n  {values}"));
+                    continue;
+                }
+                _ => {}
+            }
+            let name = field.label.as_ref().unwrap_or(&field.var);
+            let required = if field.required { ", required" } else { "" };
+            output.push_str(&format!(
+                "\n  {name} ({}, {}{required}): {values}",
+                field.var,
+                Self::field_type(&field.type_)
+            ));
+            if !field.options.is_empty() {
+                let options = field
+                    .options
+                    .iter()
+                    .map(|option| match &option.label {
+                        Some(label) => format!("{} ({label})", option.value),
+                        None => option.value.clone(),
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                output.push_str(&format!("\n    options: {options}"));
+            }
+        }
+        output
+    }
+
+    fn handle_step(&mut self, aparte: &mut Aparte, step: &AdhocStep) {
+        let mut output = match step.status {
+            AdhocStatus::Completed => format!("Command {} on {} completed", step.node, step.jid),
+            AdhocStatus::Canceled => format!("Command {} on {} canceled", step.node, step.jid),
+            AdhocStatus::Executing => format!("Command {} on {}", step.node, step.jid),
+        };
+        for note in step.notes.iter() {
+            output.push_str(&format!("\n  {note}"));
+        }
+        if let Some(form) = &step.form {
+            output.push_str(&Self::format_form(form));
+        }
+
+        if step.status != AdhocStatus::Executing {
+            self.sessions.remove(&step.account);
+        } else {
+            output.push_str(&format!(
+                "\nAnswer with /cmd {}|cancel [<var>=<value>...]",
+                step.actions.join("|")
+            ));
+            self.sessions.insert(
+                step.account.clone(),
+                Session {
+                    jid: step.jid.clone(),
+                    node: step.node.clone(),
+                    session_id: step.session_id.clone(),
+                    actions: step.actions.clone(),
+                    form: step.form.clone(),
+                },
+            );
+            if let Some(form) = &step.form {
+                aparte.schedule(Event::ShowForm {
+                    id: Self::form_id(&step.account),
+                    form: form.clone(),
+                });
+            }
+        }
+        aparte.log(output);
+    }
+}
+
+impl ModTrait for AdhocMod {
+    fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
+        aparte.add_command(cmd::new());
+        Ok(())
+    }
+
+    fn on_event(&mut self, aparte: &mut Aparte, event: &Event) {
+        match event {
+            Event::AdhocStep(step) => self.handle_step(aparte, step),
+            Event::FormSubmit { id, form } => {
+                let account = self
+                    .sessions
+                    .keys()
+                    .find(|account| &Self::form_id(account) == id)
+                    .cloned();
+                if let Some(account) = account {
+                    if let Some(request) = self.submit(&account, form.clone()) {
+                        Self::execute(aparte, &account, request);
+                    }
+                }
+            }
+            Event::Disconnected(account, _) => {
+                self.sessions.remove(account);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl fmt::Display for AdhocMod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "XEP-0050: Ad-Hoc Commands")
+    }
+}
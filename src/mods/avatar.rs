@@ -0,0 +1,498 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use std::convert::TryFrom;
+use std::fmt;
+
+use anyhow::{anyhow, Context, Result};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use sha1::{Digest, Sha1};
+use uuid::Uuid;
+use xmpp_parsers::iq::{Iq, IqType};
+use xmpp_parsers::pubsub::{self, PubSub, PubSubEvent};
+use xmpp_parsers::{ns, BareJid, Element, Jid};
+
+use crate::account::Account;
+use crate::command::{Command, CommandParser};
+use crate::contact::format_idle;
+use crate::conversation::Occupant;
+use crate::core::{Aparte, AparteAsync, Event, ModTrait};
+use crate::i18n;
+use crate::message::NS_REPLY;
+use crate::mods::contact::ContactMod;
+use crate::mods::disco;
+use crate::mods::muc;
+use crate::mods::notes::NotesMod;
+use crate::mods::retraction::NS_RETRACT;
+use crate::mods::ui::UIMod;
+
+/// XEP-0054: vcard-temp
+const NS_VCARD: &str = "vcard-temp";
+/// XEP-0153: vCard-Based Avatars
+const NS_VCARD_UPDATE: &str = "vcard-temp:x:update";
+
+/// Client features shown by /whois for each resource, with their name
+const RESOURCE_FEATURES: [(&str, &str); 3] = [
+    ("corrections", ns::MESSAGE_CORRECT),
+    ("replies", NS_REPLY),
+    ("retractions", NS_RETRACT),
+];
+
+/// vCard fields shown by /vcard, with their label
+const VCARD_FIELDS: [(&str, &str); 7] = [
+    ("FN", "Name"),
+    ("NICKNAME", "Nickname"),
+    ("BDAY", "Birthday"),
+    ("TITLE", "Title"),
+    ("URL", "Website"),
+    ("DESC", "About"),
+    ("NOTE", "Note"),
+];
+
+command_def!(vcard,
+r#"/vcard [<jid>]
+
+    jid    jid of the contact, current conversation when missing
+
+Description:
+    Show the vCard of a contact, along with their avatar when one has been
+    retrieved.
+
+Examples:
+    /vcard
+    /vcard contact@server.tld
+"#,
+{
+    jid: Option<String>,
+},
+|aparte, _command| {
+    let (account, jid) = aparte.get_mod::<UIMod>().conversation_target(aparte, jid)?;
+
+    Aparte::spawn({
+        let mut aparte = aparte.proxy();
+        async move {
+            match AvatarMod::get_vcard(&mut aparte, &account, &jid).await {
+                Ok(vcard) => {
+                    let avatar = aparte.storage.get_avatar(&account, &jid).ok().flatten();
+                    aparte.log(AvatarMod::format_vcard(&jid, &vcard, avatar));
+                }
+                Err(err) => crate::error!(aparte, err, "Cannot get vCard of {jid}"),
+            }
+        }
+    });
+    Ok(())
+});
+
+command_def!(whois,
+r#"/whois [<jid>|<nick>]
+
+    jid     jid of the contact, current conversation when missing
+    nick    Current or previous nick of an occupant of the current channel
+
+Description:
+    Show the vCard of a contact, their avatar and their connected resources
+    with the presence each of them announced and the features their client
+    supports.
+
+Examples:
+    /whois
+    /whois contact@server.tld
+    /whois nick
+"#,
+{
+    jid: Option<String>,
+},
+|aparte, _command| {
+    // Occupants are known by nick, including the ones they had before in this session
+    let occupant = match (&jid, muc::current_channel(aparte)) {
+        (Some(nick), Ok(channel)) => channel
+            .occupants
+            .get(channel.resolve_nick(nick))
+            .cloned()
+            .map(|occupant| (channel.account.clone(), channel.jid.clone(), occupant)),
+        _ => None,
+    };
+    let (account, jid) = match occupant {
+        Some((account, _, Occupant { jid: Some(jid), .. })) => (account, jid),
+        Some((_, channel, occupant)) => {
+            crate::info!(aparte, "{} is an occupant of {} whose real jid is hidden", occupant.nick, channel);
+            return Ok(());
+        }
+        None => aparte.get_mod::<UIMod>().conversation_target(aparte, jid)?,
+    };
+    let (resources, contact) = {
+        let contacts = aparte.get_mod::<ContactMod>();
+        (contacts.resources(&account, &jid), contacts.get(&account, &jid).cloned())
+    };
+    let note = aparte.get_mod::<NotesMod>().get(&account, &jid).map(|note| note.text.clone());
+    // Features are known once the entity capabilities of the resource are discovered
+    let features = {
+        let disco = aparte.get_mod::<disco::DiscoMod>();
+        resources
+            .iter()
+            .map(|(name, _)| {
+                let full = jid.with_resource_str(name).ok()?;
+                let supported = RESOURCE_FEATURES
+                    .iter()
+                    .map(|(name, feature)| Some((*name, disco.contact_has_feature(&account, &full, feature)?)))
+                    .collect::<Option<Vec<_>>>()?;
+                Some(
+                    supported
+                        .into_iter()
+                        .filter(|(_, supported)| *supported)
+                        .map(|(name, _)| name)
+                        .collect::<Vec<_>>(),
+                )
+            })
+            .collect::<Vec<_>>()
+    };
+
+    Aparte::spawn({
+        let mut aparte = aparte.proxy();
+        async move {
+            let avatar = aparte.storage.get_avatar(&account, &jid).ok().flatten();
+            let mut whois = match AvatarMod::get_vcard(&mut aparte, &account, &jid).await {
+                Ok(vcard) => AvatarMod::format_vcard(&jid, &vcard, avatar),
+                Err(err) => format!("vCard of {jid}: {err}"),
+            };
+            if let Some(mood) = contact.as_ref().and_then(|contact| contact.mood.as_ref()) {
+                whois.push_str(&format!("\n  Mood: {mood}"));
+            }
+            if let Some(tune) = contact.as_ref().and_then(|contact| contact.tune.as_ref()) {
+                whois.push_str(&format!("\n  Listening to: {tune}"));
+            }
+            if let Some(location) = contact.as_ref().and_then(|contact| contact.location.as_ref()) {
+                whois.push_str(&format!("\n  Location: {location}"));
+            }
+            if let Some(note) = note {
+                whois.push_str(&format!("\n  Note: {note}"));
+            }
+            if resources.is_empty() {
+                whois.push_str("\n  Resources: none connected");
+            }
+            for ((name, resource), features) in resources.into_iter().zip(features) {
+                whois.push_str(&format!("\n  Resource {name}: {}", resource.presence));
+                if let Some(status) = resource.status {
+                    whois.push_str(&format!(" ({status})"));
+                }
+                if let Some(idle) = resource.idle {
+                    whois.push_str(&format!(", idle for {}", format_idle(&idle)));
+                }
+                if let Some(features) = features.filter(|features| !features.is_empty()) {
+                    whois.push_str(&format!(", supports {}", features.join(", ")));
+                }
+            }
+            aparte.log(whois);
+        }
+    });
+    Ok(())
+});
+
+/// Where the avatar of a contact is published
+#[derive(Debug, Clone)]
+enum Source {
+    /// XEP-0084: User Avatar, with the type announced in the metadata
+    Pep(String),
+    /// XEP-0153: vCard-Based Avatars
+    VCard,
+}
+
+pub struct AvatarMod {}
+
+impl AvatarMod {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    /// Fetch the avatar of a contact unless the announced one is already stored
+    fn update(
+        &mut self,
+        aparte: &mut Aparte,
+        account: &Account,
+        jid: &BareJid,
+        hash: &str,
+        source: Source,
+    ) {
+        match aparte.storage.get_avatar(account, jid) {
+            Ok(Some(avatar)) if avatar.hash == hash => return,
+            Ok(_) => {}
+            Err(err) => {
+                log::warn!("Cannot read avatar of {jid}: {err}");
+                return;
+            }
+        }
+
+        Aparte::spawn({
+            let mut aparte = aparte.proxy();
+            let account = account.clone();
+            let jid = jid.clone();
+            let hash = hash.to_string();
+            async move {
+                if let Err(err) = Self::fetch(&mut aparte, &account, &jid, &hash, source).await {
+                    log::warn!("Cannot retrieve avatar of {jid}: {err:#}");
+                }
+            }
+        });
+    }
+
+    async fn fetch(
+        aparte: &mut AparteAsync,
+        account: &Account,
+        jid: &BareJid,
+        hash: &str,
+        source: Source,
+    ) -> Result<()> {
+        let (mime_type, data) = match source {
+            Source::Pep(mime_type) => (
+                mime_type,
+                Self::get_pep_avatar(aparte, account, jid, hash).await?,
+            ),
+            Source::VCard => Self::vcard_photo(&Self::get_vcard(aparte, account, jid).await?)?,
+        };
+
+        // The announced hash is the one of the image
+        let actual = sha1_hex(&data);
+        if actual != hash {
+            anyhow::bail!("hash mismatch, {hash} announced but got {actual}");
+        }
+
+        aparte
+            .storage
+            .set_avatar(account, jid, hash, &mime_type, &data)?;
+        aparte.schedule(Event::Avatar {
+            account: account.clone(),
+            jid: jid.clone(),
+            hash: hash.to_string(),
+        });
+        Ok(())
+    }
+
+    async fn get_pep_avatar(
+        aparte: &mut AparteAsync,
+        account: &Account,
+        jid: &BareJid,
+        hash: &str,
+    ) -> Result<Vec<u8>> {
+        let items = pubsub::pubsub::Items {
+            max_items: None,
+            node: pubsub::NodeName(String::from(ns::AVATAR_DATA)),
+            subid: None,
+            items: vec![pubsub::pubsub::Item(pubsub::Item {
+                id: Some(pubsub::ItemId(hash.to_string())),
+                publisher: None,
+                payload: None,
+            })],
+        };
+        let iq = Iq::from_get(
+            Uuid::new_v4().hyphenated().to_string(),
+            PubSub::Items(items),
+        )
+        .with_to(Jid::Bare(jid.clone()));
+
+        match aparte.iq(account, iq).await?.payload {
+            IqType::Result(Some(payload)) => match PubSub::try_from(payload)? {
+                PubSub::Items(items) => {
+                    let data = items
+                        .items
+                        .iter()
+                        .filter_map(|item| item.payload.as_ref())
+                        .find(|payload| payload.is("data", ns::AVATAR_DATA))
+                        .context("No avatar data")?;
+                    decode(&data.text())
+                }
+                _ => Err(anyhow!("Invalid pubsub response")),
+            },
+            IqType::Error(err) => Err(anyhow!("{}", i18n::xmpp_err_to_string(&err, vec![]).1)),
+            _ => Err(anyhow!("Invalid iq response")),
+        }
+    }
+
+    /// Type and content of the photo of a vCard
+    fn vcard_photo(vcard: &Element) -> Result<(String, Vec<u8>)> {
+        let photo = vcard
+            .get_child("PHOTO", NS_VCARD)
+            .context("No photo in vCard")?;
+        let mime_type = photo
+            .get_child("TYPE", NS_VCARD)
+            .map(|type_| type_.text())
+            .unwrap_or_else(|| String::from("image/png"));
+        let data = photo
+            .get_child("BINVAL", NS_VCARD)
+            .context("No photo in vCard")?;
+        Ok((mime_type, decode(&data.text())?))
+    }
+
+    /// XEP-0486: fetch and store the avatar a channel publishes in its vCard, returning its type
+    /// and size
+    pub async fn fetch_channel_avatar(
+        aparte: &mut AparteAsync,
+        account: &Account,
+        channel: &BareJid,
+    ) -> Result<(String, usize)> {
+        let vcard = Self::get_vcard(aparte, account, channel).await?;
+        let (mime_type, data) = Self::vcard_photo(&vcard)?;
+        let hash = sha1_hex(&data);
+        aparte
+            .storage
+            .set_avatar(account, channel, &hash, &mime_type, &data)?;
+        aparte.schedule(Event::Avatar {
+            account: account.clone(),
+            jid: channel.clone(),
+            hash,
+        });
+        Ok((mime_type, data.len()))
+    }
+
+    async fn get_vcard(
+        aparte: &mut AparteAsync,
+        account: &Account,
+        jid: &BareJid,
+    ) -> Result<Element> {
+        let iq = Iq {
+            from: None,
+            to: Some(Jid::Bare(jid.clone())),
+            id: Uuid::new_v4().hyphenated().to_string(),
+            payload: IqType::Get(Element::builder("vCard", NS_VCARD).build()),
+        };
+
+        match aparte.iq(account, iq).await?.payload {
+            IqType::Result(Some(vcard)) if vcard.is("vCard", NS_VCARD) => Ok(vcard),
+            IqType::Result(_) => Err(anyhow!("No vCard published")),
+            IqType::Error(err) => Err(anyhow!("{}", i18n::xmpp_err_to_string(&err, vec![]).1)),
+            _ => Err(anyhow!("Invalid iq response")),
+        }
+    }
+
+    fn format_vcard(
+        jid: &BareJid,
+        vcard: &Element,
+        avatar: Option<crate::storage::Avatar>,
+    ) -> String {
+        let mut output = format!("vCard of {jid}:");
+        for (name, label) in VCARD_FIELDS {
+            if let Some(value) = vcard.get_child(name, NS_VCARD).map(|field| field.text()) {
+                if !value.trim().is_empty() {
+                    output.push_str(&format!("\n  {label}: {}", value.trim()));
+                }
+            }
+        }
+        for email in vcard.children().filter(|child| child.is("EMAIL", NS_VCARD)) {
+            if let Some(address) = email.get_child("USERID", NS_VCARD) {
+                output.push_str(&format!("\n  Email: {}", address.text().trim()));
+            }
+        }
+        if let Some(avatar) = avatar {
+            output.push_str(&format!(
+                "\n  Avatar: {}, {} bytes ({})",
+                avatar.mime_type,
+                avatar.data.len(),
+                avatar.hash
+            ));
+        }
+        output
+    }
+
+    /// XEP-0084: first image announced in the metadata of a published avatar
+    fn handle_metadata(
+        &mut self,
+        aparte: &mut Aparte,
+        account: &Account,
+        from: &BareJid,
+        items: &[pubsub::Item],
+    ) {
+        let info = items
+            .iter()
+            .filter_map(|item| item.payload.as_ref())
+            .filter(|payload| payload.is("metadata", ns::AVATAR_METADATA))
+            .flat_map(|metadata| metadata.children())
+            .find(|info| info.is("info", ns::AVATAR_METADATA) && info.attr("url").is_none());
+        if let Some(info) = info {
+            if let (Some(hash), Some(mime_type)) = (info.attr("id"), info.attr("type")) {
+                self.update(
+                    aparte,
+                    account,
+                    from,
+                    hash,
+                    Source::Pep(mime_type.to_string()),
+                );
+            }
+        }
+    }
+}
+
+/// Base64 content of an element, ignoring line breaks
+fn decode(text: &str) -> Result<Vec<u8>> {
+    let text = text.split_whitespace().collect::<String>();
+    BASE64.decode(text).context("Invalid base64")
+}
+
+fn sha1_hex(data: &[u8]) -> String {
+    Sha1::digest(data)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+impl ModTrait for AvatarMod {
+    fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
+        {
+            let mut disco = aparte.get_mod_mut::<disco::DiscoMod>();
+            disco.add_feature(format!("{}+notify", ns::AVATAR_METADATA));
+        }
+        aparte.add_command(vcard::new());
+        aparte.add_command(whois::new());
+
+        Ok(())
+    }
+
+    fn on_event(&mut self, aparte: &mut Aparte, event: &Event) {
+        match event {
+            Event::PubSub {
+                account,
+                from: Some(from),
+                event: PubSubEvent::PublishedItems { node, items },
+            } if node.0 == ns::AVATAR_METADATA => {
+                let items = items.iter().map(|item| item.0.clone()).collect::<Vec<_>>();
+                self.handle_metadata(aparte, account, &from.to_bare(), &items);
+            }
+            Event::Presence(account, presence) => {
+                // Avatars of channel occupants aren't kept
+                if presence
+                    .payloads
+                    .iter()
+                    .any(|payload| payload.is("x", ns::MUC_USER))
+                {
+                    return;
+                }
+                // XEP-0153: hash of the vCard photo, empty when there is none
+                let hash = presence
+                    .payloads
+                    .iter()
+                    .filter(|payload| payload.is("x", NS_VCARD_UPDATE))
+                    .find_map(|update| update.get_child("photo", NS_VCARD_UPDATE))
+                    .map(|photo| photo.text());
+                if let (Some(from), Some(hash)) = (&presence.from, hash) {
+                    if !hash.is_empty() {
+                        self.update(aparte, account, &from.to_bare(), &hash, Source::VCard);
+                    }
+                }
+            }
+            Event::Contact(account, contact) => {
+                if let Ok(Some(avatar)) = aparte.storage.get_avatar(account, &contact.jid) {
+                    aparte.schedule(Event::Avatar {
+                        account: account.clone(),
+                        jid: contact.jid.clone(),
+                        hash: avatar.hash,
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl fmt::Display for AvatarMod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "XEP-0084: User Avatar")
+    }
+}
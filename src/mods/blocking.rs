@@ -0,0 +1,233 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Context, Result};
+use uuid::Uuid;
+use xmpp_parsers::blocking::{Block, BlocklistRequest, BlocklistResult, Unblock};
+use xmpp_parsers::iq::{Iq, IqSetPayload, IqType};
+use xmpp_parsers::{ns, Jid};
+
+use crate::account::Account;
+use crate::command::{Command, CommandParser};
+use crate::core::{Aparte, AparteAsync, Event, ModTrait};
+use crate::i18n;
+use crate::mods::disco::DiscoMod;
+
+command_def!(block,
+r#"/block <jid>
+
+    jid    jid of the contact or server to block
+
+Description:
+    Block all communications with a contact or a whole server. Blocked
+    contacts don't see our presence and their messages are dropped by the
+    server.
+
+Examples:
+    /block spammer@server.tld
+    /block spam.tld
+"#,
+{
+    jid: String,
+},
+|aparte, _command| {
+    let account = aparte.current_account().context("No connection found")?;
+    let jid = Jid::from_str(&jid).with_context(|| format!("Invalid jid {jid}"))?;
+    BlockingMod::request(aparte, &account, Block { items: vec![jid.clone()] }, format!("{jid} blocked"));
+    Ok(())
+});
+
+command_def!(unblock,
+r#"/unblock <jid>
+
+    jid    jid of the blocked contact or server
+
+Description:
+    Unblock a previously blocked contact or server.
+
+Examples:
+    /unblock spammer@server.tld
+"#,
+{
+    jid: String = {
+        completion: |aparte, _command| {
+            match aparte.current_account() {
+                Some(account) => aparte
+                    .get_mod::<BlockingMod>()
+                    .blocked(&account)
+                    .iter()
+                    .map(|jid| jid.to_string())
+                    .collect(),
+                None => Vec::new(),
+            }
+        }
+    },
+},
+|aparte, _command| {
+    let account = aparte.current_account().context("No connection found")?;
+    let jid = Jid::from_str(&jid).with_context(|| format!("Invalid jid {jid}"))?;
+    BlockingMod::request(aparte, &account, Unblock { items: vec![jid.clone()] }, format!("{jid} unblocked"));
+    Ok(())
+});
+
+/// XEP-0191: Blocking Command
+pub struct BlockingMod {
+    /// Blocklist of each account, kept in sync with server pushes
+    blocked: HashMap<Account, HashSet<Jid>>,
+}
+
+impl BlockingMod {
+    pub fn new() -> Self {
+        Self {
+            blocked: HashMap::new(),
+        }
+    }
+
+    /// Blocked jids of an account, sorted
+    pub fn blocked(&self, account: &Account) -> Vec<Jid> {
+        let mut blocked: Vec<Jid> = self
+            .blocked
+            .get(account)
+            .map(|blocked| blocked.iter().cloned().collect())
+            .unwrap_or_default();
+        blocked.sort_by_key(|jid| jid.to_string());
+        blocked
+    }
+
+    /// Send a block or unblock request, the blocklist is updated by the following server push
+    fn request<P: IqSetPayload + Send + 'static>(
+        aparte: &mut Aparte,
+        account: &Account,
+        payload: P,
+        success: String,
+    ) {
+        Aparte::spawn({
+            let mut aparte = aparte.proxy();
+            let account = account.clone();
+            async move {
+                match Self::set(&mut aparte, &account, payload).await {
+                    Ok(()) => crate::info!(aparte, "{}", success),
+                    Err(err) => crate::error!(aparte, err, "Cannot update blocklist"),
+                }
+            }
+        });
+    }
+
+    async fn set<P: IqSetPayload>(
+        aparte: &mut AparteAsync,
+        account: &Account,
+        payload: P,
+    ) -> Result<()> {
+        let id = Uuid::new_v4().hyphenated().to_string();
+        match aparte.iq(account, Iq::from_set(id, payload)).await?.payload {
+            IqType::Error(err) => Err(anyhow!("{}", i18n::xmpp_err_to_string(&err, vec![]).1)),
+            _ => Ok(()),
+        }
+    }
+
+    async fn get_blocklist(aparte: &mut AparteAsync, account: &Account) -> Result<()> {
+        let id = Uuid::new_v4().hyphenated().to_string();
+        let response = aparte
+            .iq(account, Iq::from_get(id, BlocklistRequest))
+            .await?;
+
+        match response.payload {
+            IqType::Result(Some(payload)) => {
+                let blocklist = BlocklistResult::try_from(payload)?;
+                aparte.schedule(Event::Blocklist(account.clone(), blocklist.items));
+                Ok(())
+            }
+            IqType::Error(err) => Err(anyhow!("{}", i18n::xmpp_err_to_string(&err, vec![]).1)),
+            _ => Err(anyhow!("Invalid blocklist response")),
+        }
+    }
+
+    /// Block and unblock pushes, sent by the server when the blocklist is modified
+    fn handle_push(&mut self, aparte: &mut Aparte, account: &Account, iq: &Iq) {
+        let payload = match &iq.payload {
+            IqType::Set(payload) => payload.clone(),
+            _ => return,
+        };
+
+        let (block, unblock) = (
+            Block::try_from(payload.clone()).ok(),
+            Unblock::try_from(payload).ok(),
+        );
+        if block.is_none() && unblock.is_none() {
+            return;
+        }
+
+        // Pushes can only come from our own server
+        if let Some(from) = &iq.from {
+            if from.to_bare() != account.to_bare() {
+                log::warn!("Ignoring blocklist push from {from}");
+                return;
+            }
+        }
+
+        let blocked = self.blocked.entry(account.clone()).or_default();
+        if let Some(block) = block {
+            blocked.extend(block.items);
+        }
+        if let Some(unblock) = unblock {
+            // An empty unblock push means everything has been unblocked
+            match unblock.items.is_empty() {
+                true => blocked.clear(),
+                false => blocked.retain(|jid| !unblock.items.contains(jid)),
+            }
+        }
+
+        aparte.send(
+            account,
+            Iq::from_result(iq.id.clone(), None::<BlocklistResult>),
+        );
+    }
+}
+
+impl ModTrait for BlockingMod {
+    fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
+        aparte.add_command(block::new());
+        aparte.add_command(unblock::new());
+        let mut disco = aparte.get_mod_mut::<DiscoMod>();
+        for command in ["block", "unblock"] {
+            disco.require_feature(command, "XEP-0191: Blocking Command", ns::BLOCKING);
+        }
+        Ok(())
+    }
+
+    fn on_event(&mut self, aparte: &mut Aparte, event: &Event) {
+        match event {
+            Event::Connected(account, _) => {
+                Aparte::spawn({
+                    let mut aparte = aparte.proxy();
+                    let account = account.clone();
+                    async move {
+                        if let Err(err) = Self::get_blocklist(&mut aparte, &account).await {
+                            crate::error!(aparte, err, "Cannot get blocklist");
+                        }
+                    }
+                });
+            }
+            Event::Blocklist(account, items) => {
+                self.blocked
+                    .insert(account.clone(), items.iter().cloned().collect());
+            }
+            Event::Iq(account, iq) => self.handle_push(aparte, account, iq),
+            Event::Disconnected(account, _) => {
+                self.blocked.remove(account);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl fmt::Display for BlockingMod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "XEP-0191: Blocking Command")
+    }
+}
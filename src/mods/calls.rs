@@ -0,0 +1,275 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use uuid::Uuid;
+use xmpp_parsers::delay::Delay;
+use xmpp_parsers::message::{Message as XmppParsersMessage, MessageType};
+use xmpp_parsers::{BareJid, Element, Jid};
+
+use crate::account::Account;
+use crate::command::{Command, CommandParser};
+use crate::core::{Aparte, BellReason, Event, ModTrait};
+use crate::mods::notifications::NotificationsMod;
+
+/// XEP-0353: Jingle Message Initiation
+const NS_JINGLE_MESSAGE: &str = "urn:xmpp:jingle-message:0";
+/// XEP-0167: Jingle RTP Sessions, describing the media of proposed calls
+const NS_JINGLE_RTP: &str = "urn:xmpp:jingle:apps:rtp:1";
+/// XEP-0334: Message Processing Hints
+const NS_HINTS: &str = "urn:xmpp:hints";
+
+/// Delay after which a call nobody answered is considered missed, callers giving up may not say so
+const RING_TIMEOUT: Duration = Duration::from_secs(120);
+
+command_def!(call_reject,
+r#"/call reject [<jid>]
+
+    jid    jid of the caller, latest one when missing
+
+Description:
+    Decline an incoming call, it stops ringing on the caller side and on your
+    other devices.
+
+Examples:
+    /call reject
+    /call reject contact@server.tld
+"#,
+{
+    jid: Option<String> = {
+        completion: |aparte, _command| {
+            aparte
+                .get_mod::<CallsMod>()
+                .calls
+                .iter()
+                .map(|call| call.from.to_bare().to_string())
+                .collect()
+        }
+    },
+},
+|aparte, _command| {
+    let jid = match jid {
+        Some(jid) => Some(BareJid::from_str(&jid).with_context(|| format!("Invalid jid {jid}"))?),
+        None => None,
+    };
+    let call = aparte.get_mod_mut::<CallsMod>().take(jid.as_ref());
+    let call = call.context("No incoming call")?;
+
+    let mut reject = XmppParsersMessage::new(Some(call.from.clone()));
+    reject.id = Some(Uuid::new_v4().to_string());
+    reject.type_ = MessageType::Chat;
+    reject.payloads.push(
+        Element::builder("reject", NS_JINGLE_MESSAGE)
+            .attr("id", call.id.as_str())
+            .build(),
+    );
+    reject
+        .payloads
+        .push(Element::builder("store", NS_HINTS).build());
+    aparte.send(&call.account, reject);
+    crate::info!(aparte, "Call from {} declined", call.from.to_bare());
+    Ok(())
+});
+
+command_def!(call,
+r#"/call reject"#,
+{
+    action: Command = {
+        children: {
+            "reject": call_reject,
+        }
+    },
+});
+
+/// Call proposed to our devices and not answered yet
+struct Call {
+    account: Account,
+    /// Session id chosen by the caller
+    id: String,
+    /// Calling resource
+    from: Jid,
+    since: Instant,
+}
+
+/// XEP-0353: Jingle Message Initiation
+///
+/// Calls cannot be answered, the feature is thus not advertised. Calls proposed to the account
+/// are still announced so that they can be answered on another device, or declined.
+pub struct CallsMod {
+    /// Ringing calls, most recent last
+    calls: Vec<Call>,
+}
+
+impl CallsMod {
+    pub fn new() -> Self {
+        Self { calls: Vec::new() }
+    }
+
+    /// Remove the latest call of this caller, or the latest call
+    fn take(&mut self, from: Option<&BareJid>) -> Option<Call> {
+        let position = self
+            .calls
+            .iter()
+            .rposition(|call| from.map_or(true, |from| &call.from.to_bare() == from))?;
+        Some(self.calls.remove(position))
+    }
+
+    fn take_by_id(&mut self, account: &Account, id: &str) -> Option<Call> {
+        let position = self
+            .calls
+            .iter()
+            .position(|call| &call.account == account && call.id == id)?;
+        Some(self.calls.remove(position))
+    }
+
+    fn propose(
+        &mut self,
+        aparte: &mut Aparte,
+        account: &Account,
+        from: &Jid,
+        id: &str,
+        propose: &Element,
+        delayed: bool,
+    ) {
+        let caller = from.to_bare();
+        // Calls placed from our other devices
+        if caller == account.to_bare() {
+            return;
+        }
+        if delayed {
+            crate::info!(aparte, "Missed call from {caller}");
+            return;
+        }
+
+        let media = propose
+            .children()
+            .filter(|description| description.is("description", NS_JINGLE_RTP))
+            .filter_map(|description| description.attr("media"))
+            .collect::<Vec<_>>();
+        let kind = match media.contains(&"video") {
+            true => "video call",
+            false => "call",
+        };
+        crate::info!(
+            aparte,
+            "📞 {caller} is calling you ({kind}), answer on another device or decline with /call reject"
+        );
+        aparte.schedule(Event::Bell(BellReason::Call));
+        NotificationsMod::desktop_notify(aparte, format!("Incoming {kind} from {caller}"));
+
+        self.calls.push(Call {
+            account: account.clone(),
+            id: id.to_string(),
+            from: from.clone(),
+            since: Instant::now(),
+        });
+    }
+
+    /// Forget calls ringing for too long
+    fn expire(&mut self, aparte: &mut Aparte) {
+        let (expired, ringing): (Vec<Call>, Vec<Call>) = self
+            .calls
+            .drain(..)
+            .partition(|call| call.since.elapsed() >= RING_TIMEOUT);
+        self.calls = ringing;
+        for call in expired.into_iter() {
+            crate::info!(aparte, "Missed call from {}", call.from.to_bare());
+        }
+    }
+
+    /// Ringing stopped: the caller gave up or one of our devices answered or declined
+    fn end(&mut self, aparte: &mut Aparte, account: &Account, from: &Jid, id: &str, action: &str) {
+        let call = match self.take_by_id(account, id) {
+            Some(call) => call,
+            None => return,
+        };
+        let caller = call.from.to_bare();
+        let ours = from.to_bare() == account.to_bare();
+        match (action, ours) {
+            ("retract", false) => crate::info!(aparte, "Missed call from {caller}"),
+            ("accept" | "proceed", true) => {
+                crate::info!(aparte, "Call from {caller} answered on another device")
+            }
+            ("reject", true) => {
+                crate::info!(aparte, "Call from {caller} declined on another device")
+            }
+            _ => {
+                // Not from the caller nor from one of our devices, keep ringing
+                if from.to_bare() != caller {
+                    self.calls.push(call);
+                }
+            }
+        }
+    }
+}
+
+impl ModTrait for CallsMod {
+    fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
+        aparte.add_command(call::new());
+        Ok(())
+    }
+
+    fn can_handle_xmpp_message(
+        &mut self,
+        _aparte: &mut Aparte,
+        _account: &Account,
+        message: &XmppParsersMessage,
+        _delay: &Option<Delay>,
+    ) -> f64 {
+        match message
+            .payloads
+            .iter()
+            .any(|payload| payload.ns() == NS_JINGLE_MESSAGE)
+        {
+            true => 1f64,
+            false => 0f64,
+        }
+    }
+
+    fn handle_xmpp_message(
+        &mut self,
+        aparte: &mut Aparte,
+        account: &Account,
+        message: &XmppParsersMessage,
+        delay: &Option<Delay>,
+        archive: bool,
+    ) {
+        let Some(from) = &message.from else {
+            return;
+        };
+        if archive {
+            return;
+        }
+        for payload in message.payloads.iter() {
+            if payload.ns() != NS_JINGLE_MESSAGE {
+                continue;
+            }
+            let Some(id) = payload.attr("id") else {
+                continue;
+            };
+            match payload.name() {
+                "propose" => self.propose(aparte, account, from, id, payload, delay.is_some()),
+                action @ ("retract" | "accept" | "proceed" | "reject") => {
+                    self.end(aparte, account, from, id, action)
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn on_event(&mut self, aparte: &mut Aparte, event: &Event) {
+        if let Event::Tick = event {
+            self.expire(aparte);
+        }
+    }
+}
+
+impl fmt::Display for CallsMod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "XEP-0353: Jingle Message Initiation")
+    }
+}
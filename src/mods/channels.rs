@@ -0,0 +1,239 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+
+use anyhow::{anyhow, Context, Result};
+use futures::stream::{self, StreamExt};
+use uuid::Uuid;
+use xmpp_parsers::disco;
+use xmpp_parsers::iq::{Iq, IqType};
+use xmpp_parsers::{BareJid, Jid};
+
+use crate::account::Account;
+use crate::command::{Command, CommandParser};
+use crate::core::{Aparte, AparteAsync, Event, ModTrait};
+use crate::i18n;
+use crate::mods::ui::UIMod;
+
+const NS_MUC_ROOMINFO: &str = "http://jabber.org/protocol/muc#roominfo";
+
+/// Number of room info queries in flight while listing channels
+const CHANNEL_INFO_CONCURRENCY: usize = 8;
+
+command_def!(channels_list,
+r#"/channels list <service>
+
+    service    Multi-user chat service to browse
+
+Description:
+    List public channels hosted by a multi-user chat service in the channels
+    window, Enter joins the one selected with Up and Down. Listed channels are
+    proposed by /join completion.
+
+Examples:
+    /channels list conference.fariello.eu
+"#,
+{
+    service: Jid,
+},
+|aparte, _command| {
+    let account = aparte.current_account().context("No connection found")?;
+    crate::info!(aparte, "Fetching channels of {service}");
+    ChannelsMod::fetch(aparte, &account, &service);
+    Ok(())
+});
+
+command_def!(channels_search,
+r#"/channels search [<text>]
+
+    text    Only show channels containing this text, show all channels when missing
+
+Description:
+    Filter the channels window
+
+Examples:
+    /channels search rust
+    /channels search
+"#,
+{
+    text: Option<String>,
+},
+|aparte, _command| {
+    let mut ui = aparte.get_mod_mut::<UIMod>();
+    ui.filter_channels(text);
+    Ok(())
+});
+
+command_def!(channels,
+r#"/channels list|search"#,
+{
+    action: Command = {
+        children: {
+            "list": channels_list,
+            "search": channels_search,
+        }
+    },
+});
+
+/// Public channel advertised by a multi-user chat service
+#[derive(Debug, Clone)]
+pub struct ChannelListing {
+    pub jid: BareJid,
+    pub name: Option<String>,
+    /// Number of occupants, when advertised by the room
+    pub occupants: Option<u32>,
+    pub description: Option<String>,
+}
+
+pub struct ChannelsMod {
+    /// Last channels listed for each account
+    listings: HashMap<Account, Vec<ChannelListing>>,
+}
+
+impl ChannelsMod {
+    pub fn new() -> Self {
+        Self {
+            listings: HashMap::new(),
+        }
+    }
+
+    /// Jids of previously listed channels
+    pub fn listed_channels(&self, account: &Account) -> Vec<String> {
+        self.listings
+            .get(account)
+            .map(|channels| {
+                channels
+                    .iter()
+                    .map(|channel| channel.jid.to_string())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn fetch(aparte: &Aparte, account: &Account, service: &Jid) {
+        Aparte::spawn({
+            let mut aparte = aparte.proxy();
+            let account = account.clone();
+            let service = service.clone();
+            async move {
+                match Self::get_channels(&mut aparte, &account, &service).await {
+                    Ok(channels) => aparte.schedule(Event::ChannelList {
+                        account,
+                        service,
+                        channels,
+                    }),
+                    Err(err) => crate::error!(aparte, err, "Cannot list channels"),
+                }
+            }
+        });
+    }
+
+    async fn get_channels(
+        aparte: &mut AparteAsync,
+        account: &Account,
+        service: &Jid,
+    ) -> Result<Vec<ChannelListing>> {
+        let id = Uuid::new_v4().hyphenated().to_string();
+        let iq = Iq::from_get(id, disco::DiscoItemsQuery { node: None }).with_to(service.clone());
+        let items = match aparte.iq(account, iq).await?.payload {
+            IqType::Result(Some(el)) => disco::DiscoItemsResult::try_from(el)?.items,
+            IqType::Error(err) => {
+                return Err(anyhow!("{}", i18n::xmpp_err_to_string(&err, vec![]).1))
+            }
+            _ => return Err(anyhow!("invalid response")),
+        };
+
+        let channels: Vec<ChannelListing> = stream::iter(items)
+            .map(|item| {
+                let mut aparte = aparte.clone();
+                let account = account.clone();
+                async move {
+                    let jid = item.jid.to_bare();
+                    let mut channel = ChannelListing {
+                        jid,
+                        name: item.name,
+                        occupants: None,
+                        description: None,
+                    };
+                    // Missing room info only means less details
+                    if let Err(err) =
+                        Self::get_channel_info(&mut aparte, &account, &mut channel).await
+                    {
+                        log::warn!("Cannot get info of {}: {}", channel.jid, err);
+                    }
+                    channel
+                }
+            })
+            .buffer_unordered(CHANNEL_INFO_CONCURRENCY)
+            .collect()
+            .await;
+
+        Ok(channels)
+    }
+
+    async fn get_channel_info(
+        aparte: &mut AparteAsync,
+        account: &Account,
+        channel: &mut ChannelListing,
+    ) -> Result<()> {
+        let id = Uuid::new_v4().hyphenated().to_string();
+        let iq = Iq::from_get(id, disco::DiscoInfoQuery { node: None })
+            .with_to(Jid::Bare(channel.jid.clone()));
+        let info = match aparte.iq(account, iq).await?.payload {
+            IqType::Result(Some(el)) => disco::DiscoInfoResult::try_from(el)?,
+            _ => return Err(anyhow!("invalid response")),
+        };
+
+        if channel.name.is_none() {
+            channel.name = info
+                .identities
+                .iter()
+                .find_map(|identity| identity.name.clone());
+        }
+
+        let roominfo = info
+            .extensions
+            .iter()
+            .find(|form| form.form_type.as_deref() == Some(NS_MUC_ROOMINFO));
+        if let Some(roominfo) = roominfo {
+            for field in roominfo.fields.iter() {
+                match (field.var.as_str(), field.values.first()) {
+                    ("muc#roominfo_occupants", Some(value)) => {
+                        channel.occupants = value.parse().ok()
+                    }
+                    ("muc#roominfo_description", Some(value)) if !value.is_empty() => {
+                        channel.description = Some(value.clone())
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl ModTrait for ChannelsMod {
+    fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
+        aparte.add_command(channels::new());
+        Ok(())
+    }
+
+    fn on_event(&mut self, _aparte: &mut Aparte, event: &Event) {
+        if let Event::ChannelList {
+            account, channels, ..
+        } = event
+        {
+            self.listings.insert(account.clone(), channels.clone());
+        }
+    }
+}
+
+impl fmt::Display for ChannelsMod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Channel list discovery")
+    }
+}
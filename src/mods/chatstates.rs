@@ -0,0 +1,153 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::time::Duration;
+
+use xmpp_parsers::chatstates::ChatState;
+use xmpp_parsers::message::{Message as XmppParsersMessage, MessageType};
+use xmpp_parsers::BareJid;
+
+use crate::account::Account;
+use crate::core::{Aparte, Event, ModTrait};
+use crate::message::{Message, XmppMessageType};
+
+/// Delay without typing after which we tell the contact we paused
+const PAUSED_DELAY: Duration = Duration::from_secs(5);
+
+/// XEP-0085: Chat State Notifications
+///
+/// Tell contacts we paused typing, and forward their own chat states to the UI. Composing and
+/// active states are sent by the receipts mod, subject to privacy settings.
+pub struct ChatStatesMod {
+    /// Conversations in which we are typing, incremented on each keystroke
+    composing: HashMap<(Account, BareJid), u64>,
+}
+
+impl ChatStatesMod {
+    pub fn new() -> Self {
+        Self {
+            composing: HashMap::new(),
+        }
+    }
+
+    fn typing(&mut self, aparte: &mut Aparte, account: &Account, contact: &BareJid) {
+        let generation = self
+            .composing
+            .entry((account.clone(), contact.clone()))
+            .or_insert(0);
+        *generation += 1;
+
+        Aparte::spawn({
+            let mut aparte = aparte.proxy();
+            let account = account.clone();
+            let contact = contact.clone();
+            let generation = *generation;
+            async move {
+                tokio::time::sleep(PAUSED_DELAY).await;
+                aparte.schedule(Event::ComposingTimeout {
+                    account,
+                    contact,
+                    generation,
+                });
+            }
+        });
+    }
+
+    fn timeout(
+        &mut self,
+        aparte: &mut Aparte,
+        account: &Account,
+        contact: &BareJid,
+        generation: u64,
+    ) {
+        let index = (account.clone(), contact.clone());
+        if self.composing.get(&index) == Some(&generation) {
+            self.composing.remove(&index);
+            aparte.schedule(Event::ChatState {
+                account: account.clone(),
+                contact: contact.clone(),
+                state: ChatState::Paused,
+            });
+        }
+    }
+
+    fn handle_message(
+        &mut self,
+        aparte: &mut Aparte,
+        account: &Account,
+        message: &XmppParsersMessage,
+    ) {
+        let contact = match &message.from {
+            Some(from) if message.type_ == MessageType::Chat => from.to_bare(),
+            _ => return,
+        };
+        let state = message
+            .payloads
+            .iter()
+            .find_map(|payload| ChatState::try_from(payload.clone()).ok());
+        let state = match state {
+            Some(state) => state,
+            // A message body without chat state means the contact is done typing
+            None if !message.bodies.is_empty() => ChatState::Active,
+            None => return,
+        };
+
+        aparte.schedule(Event::ContactChatState {
+            account: account.clone(),
+            contact,
+            state,
+        });
+    }
+}
+
+impl ModTrait for ChatStatesMod {
+    fn init(&mut self, _aparte: &mut Aparte) -> Result<(), ()> {
+        Ok(())
+    }
+
+    fn on_event(&mut self, aparte: &mut Aparte, event: &Event) {
+        match event {
+            Event::ChatState {
+                account,
+                contact,
+                state: ChatState::Composing,
+            } => self.typing(aparte, account, contact),
+            Event::ChatState {
+                account, contact, ..
+            } => {
+                self.composing.remove(&(account.clone(), contact.clone()));
+            }
+            Event::ComposingTimeout {
+                account,
+                contact,
+                generation,
+            } => self.timeout(aparte, account, contact, *generation),
+            Event::SendMessage(account, Message::Xmpp(message))
+                if message.type_ == XmppMessageType::Chat =>
+            {
+                self.composing
+                    .remove(&(account.clone(), message.to.clone()));
+            }
+            Event::RawMessage {
+                account,
+                message,
+                archive: false,
+                ..
+            } => self.handle_message(aparte, account, message),
+            Event::Disconnected(account, _) => {
+                self.composing
+                    .retain(|(composing, _), _| composing != account);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl fmt::Display for ChatStatesMod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "XEP-0085: Chat State Notifications")
+    }
+}
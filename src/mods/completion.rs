@@ -15,6 +15,7 @@ use crate::conversation::Conversation;
 use crate::core::{Aparte, Event, ModTrait};
 use crate::cursor::Cursor;
 use crate::mods::conversation::ConversationMod;
+use crate::mods::disco::DiscoMod;
 use crate::word::Words;
 
 pub struct CompletionMod {
@@ -112,19 +113,18 @@ impl CompletionMod {
                 cursor.clone(),
             ) {
                 if command.cursor == 0 {
+                    // Hide commands the server cannot serve
+                    let disco = aparte.get_mod::<DiscoMod>();
                     completions = aparte
                         .command_parsers
                         .iter()
+                        .filter(|c| disco.missing_requirement(account, c.0).is_none())
                         .map(|c| c.0.to_string())
                         .collect()
                 } else {
                     let command_parsers = Arc::clone(&aparte.command_parsers);
                     if let Some(parser) = command_parsers.get(&command.args[0]) {
-                        if command.cursor - 1 < parser.autocompletions.len() {
-                            if let Some(completion) = &parser.autocompletions[command.cursor - 1] {
-                                completions = completion(aparte, command.clone())
-                            }
-                        }
+                        completions = parser.complete(aparte, &command, 1);
                     }
                 }
 
@@ -168,7 +168,7 @@ impl CompletionMod {
                                 .iter()
                                 .filter_map(|(_, occupant)| {
                                     if occupant.nick.starts_with(current_word) {
-                                        Some(occupant.nick.clone() + append)
+                                        Some(format!("{}{append}", occupant.nick))
                                     } else {
                                         None
                                     }
@@ -1,26 +1,133 @@
 /* This Source Code Form is subject to the terms of the Mozilla Public
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use std::cmp::Reverse;
 use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::fmt;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use uuid::Uuid;
+use xmpp_parsers::idle::Idle;
 use xmpp_parsers::iq::{Iq, IqType};
+use xmpp_parsers::presence::{Presence, Type as PresenceType};
 use xmpp_parsers::{ns, presence, roster, BareJid, Jid};
 
 use crate::account::Account;
-use crate::contact;
+use crate::command::{Command, CommandParser};
+use crate::contact::{Contact, Group, Location, Mood, Presence as ContactPresence, Resource, Tune};
 use crate::core::{Aparte, AparteAsync, Event, ModTrait};
+use crate::i18n;
+use crate::mods::notes::NotesMod;
+use crate::mods::ui::UIMod;
 
-impl From<roster::Group> for contact::Group {
+fn complete_contacts(aparte: &Aparte) -> Vec<String> {
+    let contacts = aparte.get_mod::<ContactMod>();
+    contacts
+        .iter()
+        .map(|(_, contact)| contact.jid.to_string())
+        .collect()
+}
+
+command_def!(contact_show,
+r#"/contact show [<jid>]
+
+    jid    jid of the contact, current one when missing
+
+Description:
+    Show the details of a roster contact, including the state of the presence
+    subscription and how to fix it when it is not mutual.
+
+Examples:
+    /contact show
+    /contact show contact@server.tld
+"#,
+{
+    jid: Option<String> = {
+        completion: |aparte, _command| complete_contacts(aparte)
+    },
+},
+|aparte, _command| {
+    let (account, jid) = aparte.get_mod::<UIMod>().conversation_target(aparte, jid)?;
+    let note = aparte.get_mod::<NotesMod>().get(&account, &jid).map(|note| note.text.clone());
+    let details = {
+        let contacts = aparte.get_mod::<ContactMod>();
+        let contact = contacts
+            .get(&account, &jid)
+            .with_context(|| format!("{jid} is not in the roster"))?;
+        ContactMod::details(contact, note.as_deref())
+    };
+    for line in details {
+        aparte.log(line);
+    }
+    Ok(())
+});
+
+command_def!(contact_subscribe,
+r#"/contact subscribe [<jid>]
+
+    jid    jid of the contact, current one when missing
+
+Description:
+    Ask the contact to share their presence with you.
+
+Examples:
+    /contact subscribe contact@server.tld
+"#,
+{
+    jid: Option<String> = {
+        completion: |aparte, _command| complete_contacts(aparte)
+    },
+},
+|aparte, _command| {
+    let (account, jid) = aparte.get_mod::<UIMod>().conversation_target(aparte, jid)?;
+    ContactMod::send_subscription(aparte, &account, &jid, PresenceType::Subscribe);
+    crate::info!(aparte, "Subscription to {jid} requested");
+    Ok(())
+});
+
+command_def!(contact_approve,
+r#"/contact approve [<jid>]
+
+    jid    jid of the contact, current one when missing
+
+Description:
+    Share your presence with the contact.
+
+Examples:
+    /contact approve contact@server.tld
+"#,
+{
+    jid: Option<String> = {
+        completion: |aparte, _command| complete_contacts(aparte)
+    },
+},
+|aparte, _command| {
+    let (account, jid) = aparte.get_mod::<UIMod>().conversation_target(aparte, jid)?;
+    ContactMod::send_subscription(aparte, &account, &jid, PresenceType::Subscribed);
+    crate::info!(aparte, "{jid} can now see your presence");
+    Ok(())
+});
+
+command_def!(contact,
+r#"/contact show|subscribe|approve"#,
+{
+    action: Command = {
+        children: {
+            "show": contact_show,
+            "subscribe": contact_subscribe,
+            "approve": contact_approve,
+        }
+    },
+});
+
+impl From<roster::Group> for Group {
     fn from(item: roster::Group) -> Self {
         Self(item.0)
     }
 }
 
-impl From<roster::Item> for contact::Contact {
+impl From<roster::Item> for Contact {
     fn from(item: roster::Item) -> Self {
         let mut groups = Vec::new();
         for group in item.groups {
@@ -31,29 +138,186 @@ impl From<roster::Item> for contact::Contact {
             jid: item.jid.clone(),
             name: item.name.clone(),
             subscription: item.subscription,
-            presence: contact::Presence::Unavailable,
+            pending: item.ask == roster::Ask::Subscribe,
+            presence: ContactPresence::Unavailable,
             groups,
+            avatar: None,
+            nick: None,
+            mood: None,
+            tune: None,
+            location: None,
+            idle: None,
         }
     }
 }
 
-#[derive(Eq, PartialEq, Hash)]
+#[derive(Eq, PartialEq, Hash, Clone)]
 pub struct ContactIndex {
     account: Account,
     jid: BareJid,
 }
 
+/// Personal information published by a contact
+#[derive(Default)]
+struct Published {
+    nick: Option<String>,
+    mood: Option<Mood>,
+    tune: Option<Tune>,
+    location: Option<Location>,
+}
+
+impl Published {
+    fn apply(&self, contact: &mut Contact) {
+        contact.nick = self.nick.clone();
+        contact.mood = self.mood.clone();
+        contact.tune = self.tune.clone();
+        contact.location = self.location.clone();
+    }
+}
+
 pub struct ContactMod {
-    pub contacts: HashMap<ContactIndex, contact::Contact>,
+    pub contacts: HashMap<ContactIndex, Contact>,
+    /// Connected resources of each contact, key is the resource name
+    resources: HashMap<ContactIndex, HashMap<String, Resource>>,
+    /// Personal information published by contacts with PEP, kept across roster updates
+    published: HashMap<ContactIndex, Published>,
 }
 
 impl ContactMod {
     pub fn new() -> Self {
         Self {
             contacts: HashMap::new(),
+            resources: HashMap::new(),
+            published: HashMap::new(),
+        }
+    }
+
+    /// Connected resources of a contact, most prioritary first
+    pub fn resources(&self, account: &Account, jid: &BareJid) -> Vec<(String, Resource)> {
+        let index = ContactIndex {
+            account: account.clone(),
+            jid: jid.clone(),
+        };
+        let mut resources = self
+            .resources
+            .get(&index)
+            .map(|resources| {
+                resources
+                    .iter()
+                    .map(|(name, resource)| (name.clone(), resource.clone()))
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        resources.sort_by(|(a, _), (b, _)| a.cmp(b));
+        resources.sort_by_key(|(_, resource)| Reverse(resource.priority));
+        resources
+    }
+
+    /// Roster contacts of every account
+    pub fn iter(&self) -> impl Iterator<Item = (&Account, &Contact)> {
+        self.contacts
+            .iter()
+            .map(|(index, contact)| (&index.account, contact))
+    }
+
+    pub fn get(&self, account: &Account, jid: &BareJid) -> Option<&Contact> {
+        let index = ContactIndex {
+            account: account.clone(),
+            jid: jid.clone(),
+        };
+        self.contacts.get(&index)
+    }
+
+    /// Update what a contact published, along with their roster entry when there is one
+    fn update_published(
+        &mut self,
+        aparte: &mut Aparte,
+        account: &Account,
+        jid: &BareJid,
+        update: impl FnOnce(&mut Published),
+    ) {
+        let index = ContactIndex {
+            account: account.clone(),
+            jid: jid.clone(),
+        };
+        let published = self.published.entry(index.clone()).or_default();
+        update(published);
+        if let Some(contact) = self.contacts.get_mut(&index) {
+            published.apply(contact);
+            aparte.schedule(Event::ContactUpdate(account.clone(), contact.clone()));
         }
     }
 
+    /// Description of a contact, with commands fixing a non mutual subscription
+    fn details(contact: &Contact, note: Option<&str>) -> Vec<String> {
+        let mut details = vec![match contact.display_name() {
+            Some(name) => format!("{} ({})", contact.jid, name),
+            None => contact.jid.to_string(),
+        }];
+        if !contact.groups.is_empty() {
+            let groups = contact
+                .groups
+                .iter()
+                .map(|group| group.0.as_str())
+                .collect::<Vec<_>>()
+                .join(", ");
+            details.push(format!("  groups: {groups}"));
+        }
+        if let Some(tune) = &contact.tune {
+            details.push(format!("  listening to: {tune}"));
+        }
+        if let Some(location) = &contact.location {
+            details.push(format!("  location: {location}"));
+        }
+        if let Some(note) = note {
+            details.push(format!("  note: {note}"));
+        }
+
+        let (sees_them, sees_us) = match contact.subscription {
+            roster::Subscription::Both => (true, true),
+            roster::Subscription::To => (true, false),
+            roster::Subscription::From => (false, true),
+            roster::Subscription::None | roster::Subscription::Remove => (false, false),
+        };
+        let subscription = match (sees_them, sees_us) {
+            (true, true) => "mutual",
+            (true, false) => "you see their presence",
+            (false, true) => "they see your presence",
+            (false, false) => "none",
+        };
+        details.push(format!("  subscription: {subscription}"));
+
+        if !sees_them {
+            match contact.pending {
+                true => details.push(String::from(
+                    "  your subscription request is pending their approval",
+                )),
+                false => details.push(format!(
+                    "  see their presence with /contact subscribe {}",
+                    contact.jid
+                )),
+            }
+        }
+        if !sees_us {
+            details.push(format!(
+                "  share your presence with /contact approve {}",
+                contact.jid
+            ));
+        }
+        details
+    }
+
+    fn send_subscription(
+        aparte: &mut Aparte,
+        account: &Account,
+        jid: &BareJid,
+        type_: PresenceType,
+    ) {
+        let mut presence = Presence::new(type_);
+        presence.to = Some(Jid::Bare(jid.clone()));
+        aparte.send(account, presence);
+    }
+
     async fn get_roster(aparte: &mut AparteAsync, account: &Account) -> Result<()> {
         let response = aparte.iq(&account, Self::get_roster_iq()).await?;
 
@@ -71,6 +335,43 @@ impl ContactMod {
         Ok(())
     }
 
+    /// RFC 6121: Roster push, sent by the server when the roster is modified
+    fn handle_roster_push(
+        &mut self,
+        aparte: &mut Aparte,
+        account: &Account,
+        iq: &Iq,
+        roster: roster::Roster,
+    ) {
+        // Pushes can only come from our own server
+        if let Some(from) = &iq.from {
+            if from.to_bare() != account.to_bare() {
+                log::warn!("Ignoring roster push from {from}");
+                return;
+            }
+        }
+
+        for item in roster.items {
+            let index = ContactIndex {
+                account: account.clone(),
+                jid: item.jid.clone(),
+            };
+            if item.subscription == roster::Subscription::Remove {
+                self.contacts.remove(&index);
+            } else {
+                if !self.contacts.contains_key(&index) {
+                    aparte.schedule(Event::ContactAdded(account.clone(), item.jid.clone()));
+                }
+                aparte.schedule(Event::Contact(account.clone(), item.into()));
+            }
+        }
+
+        aparte.send(
+            account,
+            Iq::from_result(iq.id.clone(), None::<roster::Roster>),
+        );
+    }
+
     fn get_roster_iq() -> Iq {
         let id = Uuid::new_v4().hyphenated().to_string();
         Iq::from_get(
@@ -84,7 +385,8 @@ impl ContactMod {
 }
 
 impl ModTrait for ContactMod {
-    fn init(&mut self, _aparte: &mut Aparte) -> Result<(), ()> {
+    fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
+        aparte.add_command(contact::new());
         Ok(())
     }
 
@@ -107,7 +409,36 @@ impl ModTrait for ContactMod {
                     account: account.clone(),
                     jid: contact.jid.clone(),
                 };
-                self.contacts.insert(index, contact.clone());
+                let mut contact = contact.clone();
+                if let Some(published) = self.published.get(&index) {
+                    published.apply(&mut contact);
+                    aparte.schedule(Event::ContactUpdate(account.clone(), contact.clone()));
+                }
+                self.contacts.insert(index, contact);
+            }
+            Event::Nickname { account, jid, nick } => {
+                self.update_published(aparte, account, jid, |published| {
+                    published.nick = nick.clone()
+                });
+            }
+            Event::Mood { account, jid, mood } => {
+                self.update_published(aparte, account, jid, |published| {
+                    published.mood = mood.clone()
+                });
+            }
+            Event::Tune { account, jid, tune } => {
+                self.update_published(aparte, account, jid, |published| {
+                    published.tune = tune.clone()
+                });
+            }
+            Event::Location {
+                account,
+                jid,
+                location,
+            } => {
+                self.update_published(aparte, account, jid, |published| {
+                    published.location = location.clone()
+                });
             }
             Event::Presence(account, presence) => {
                 if let Some(from) = &presence.from {
@@ -121,16 +452,61 @@ impl ModTrait for ContactMod {
                     };
                     if let Some(contact) = self.contacts.get_mut(&index) {
                         contact.presence = match presence.show {
-                            Some(presence::Show::Away) => contact::Presence::Away,
-                            Some(presence::Show::Chat) => contact::Presence::Chat,
-                            Some(presence::Show::Dnd) => contact::Presence::Dnd,
-                            Some(presence::Show::Xa) => contact::Presence::Xa,
-                            None => contact::Presence::Available,
+                            Some(presence::Show::Away) => ContactPresence::Away,
+                            Some(presence::Show::Chat) => ContactPresence::Chat,
+                            Some(presence::Show::Dnd) => ContactPresence::Dnd,
+                            Some(presence::Show::Xa) => ContactPresence::Xa,
+                            None => ContactPresence::Available,
                         };
+                        contact.idle = presence
+                            .payloads
+                            .iter()
+                            .find_map(|payload| Idle::try_from(payload.clone()).ok())
+                            .map(|idle| idle.since.0);
+                        if let Jid::Full(from) = from {
+                            let resources = self.resources.entry(index).or_default();
+                            match presence.type_ {
+                                PresenceType::None => {
+                                    let status =
+                                        i18n::get_best(&presence.statuses, i18n::preferred_langs())
+                                            .map(|(_lang, status)| status.clone());
+                                    resources.insert(
+                                        from.resource().to_string(),
+                                        Resource {
+                                            presence: contact.presence.clone(),
+                                            status,
+                                            priority: presence.priority,
+                                            idle: contact.idle,
+                                        },
+                                    );
+                                }
+                                PresenceType::Unavailable => {
+                                    resources.remove(&from.resource().to_string());
+                                }
+                                _ => {}
+                            }
+                        }
                         aparte.schedule(Event::ContactUpdate(account.clone(), contact.clone()));
                     }
                 }
             }
+            Event::Avatar { account, jid, hash } => {
+                let index = ContactIndex {
+                    account: account.clone(),
+                    jid: jid.clone(),
+                };
+                if let Some(contact) = self.contacts.get_mut(&index) {
+                    contact.avatar = Some(hash.clone());
+                    aparte.schedule(Event::ContactUpdate(account.clone(), contact.clone()));
+                }
+            }
+            Event::Iq(account, iq) => {
+                if let IqType::Set(payload) = iq.payload.clone() {
+                    if let Ok(roster) = roster::Roster::try_from(payload) {
+                        self.handle_roster_push(aparte, account, iq, roster);
+                    }
+                }
+            }
             _ => {}
         }
     }
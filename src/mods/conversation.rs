@@ -1,17 +1,382 @@
 /* This Source Code Form is subject to the terms of the Mozilla Public
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::convert::TryFrom;
 use std::fmt;
+use std::str::FromStr;
 use unicode_segmentation::UnicodeSegmentation;
 
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local as LocalTz};
 use xmpp_parsers::{muc, BareJid, Jid};
 
 use crate::account::Account;
+use crate::command::{Command, CommandParser};
 use crate::conversation;
 use crate::core::{Aparte, Event, ModTrait};
-use crate::message;
+use crate::intern::intern;
+use crate::message::{self, LogMessage, Message, XmppMessageType};
+use crate::mods::messages::MessagesMod;
+use crate::mods::ui::UIMod;
+use crate::storage::PinnedMessage;
+
+command_def!(vip_add,
+r#"/vip add [<jid>]
+
+    jid    jid of the contact/channel
+
+Description:
+    Mark the current or a given contact/channel as VIP. Every message received
+    in a VIP conversation is notified as important.
+
+Examples:
+    /vip add
+    /vip add contact@server.tld
+"#,
+{
+    jid: Option<String>,
+},
+|aparte, _command| {
+    let (account, jid) = aparte.get_mod::<UIMod>().conversation_target(aparte, jid)?;
+    {
+        let mut conversation = aparte.get_mod_mut::<ConversationMod>();
+        conversation.set_vip(aparte, &account, &jid, true)?;
+    }
+    crate::info!(aparte, "{jid} is now VIP");
+    Ok(())
+});
+
+command_def!(vip_del,
+r#"/vip del [<jid>]
+
+    jid    jid of the contact/channel
+
+Description:
+    Remove the VIP mark of the current or a given contact/channel.
+
+Examples:
+    /vip del
+    /vip del contact@server.tld
+"#,
+{
+    jid: Option<String> = {
+        completion: |aparte, _command| {
+            let conversation = aparte.get_mod::<ConversationMod>();
+            conversation.vip.iter().map(|index| index.jid.to_string()).collect()
+        }
+    },
+},
+|aparte, _command| {
+    let (account, jid) = aparte.get_mod::<UIMod>().conversation_target(aparte, jid)?;
+    {
+        let mut conversation = aparte.get_mod_mut::<ConversationMod>();
+        conversation.set_vip(aparte, &account, &jid, false)?;
+    }
+    crate::info!(aparte, "{jid} is no longer VIP");
+    Ok(())
+});
+
+command_def!(
+    vip_list,
+    r#"/vip list
+
+Description:
+    List VIP contacts/channels of the current account.
+
+Examples:
+    /vip list
+"#,
+    {},
+    |aparte, _command| {
+        let account = aparte.current_account().context("No connection found")?;
+        let vip = {
+            let conversation = aparte.get_mod::<ConversationMod>();
+            conversation
+                .vip
+                .iter()
+                .filter(|index| index.account == account)
+                .map(|index| index.jid.to_string())
+                .collect::<Vec<_>>()
+        };
+        match vip.is_empty() {
+            true => crate::info!(aparte, "No VIP conversation"),
+            false => crate::info!(aparte, "VIP conversations: {}", vip.join(", ")),
+        }
+        Ok(())
+    }
+);
+
+command_def!(vip,
+r#"/vip add|del|list"#,
+{
+    action: Command = {
+        children: {
+            "add": vip_add,
+            "del": vip_del,
+            "list": vip_list,
+        }
+    },
+});
+
+command_def!(mute,
+r#"/mute [<jid>]
+
+    jid    jid of the contact/channel
+
+Description:
+    Mute the current or a given contact/channel. Messages received in a
+    muted conversation are never notified as important, even mentions.
+
+Examples:
+    /mute
+    /mute channel@conference.server.tld
+"#,
+{
+    jid: Option<String>,
+},
+|aparte, _command| {
+    let (account, jid) = aparte.get_mod::<UIMod>().conversation_target(aparte, jid)?;
+    {
+        let mut conversation = aparte.get_mod_mut::<ConversationMod>();
+        conversation.set_muted(aparte, &account, &jid, true)?;
+    }
+    crate::info!(aparte, "{jid} is now muted");
+    Ok(())
+});
+
+command_def!(unmute,
+r#"/unmute [<jid>]
+
+    jid    jid of the contact/channel
+
+Description:
+    Unmute the current or a given contact/channel.
+
+Examples:
+    /unmute
+    /unmute channel@conference.server.tld
+"#,
+{
+    jid: Option<String> = {
+        completion: |aparte, _command| {
+            let conversation = aparte.get_mod::<ConversationMod>();
+            conversation.muted.iter().map(|index| index.jid.to_string()).collect()
+        }
+    },
+},
+|aparte, _command| {
+    let (account, jid) = aparte.get_mod::<UIMod>().conversation_target(aparte, jid)?;
+    {
+        let mut conversation = aparte.get_mod_mut::<ConversationMod>();
+        conversation.set_muted(aparte, &account, &jid, false)?;
+    }
+    crate::info!(aparte, "{jid} is no longer muted");
+    Ok(())
+});
+
+command_def!(lang_set,
+r#"/lang set <lang> [<jid>]
+
+    lang    Language tag of the messages, e.g. en or fr
+    jid     jid of the contact/channel
+
+Description:
+    Tag messages sent to the current or a given contact/channel with a
+    language (xml:lang).
+
+Examples:
+    /lang set fr
+    /lang set de contact@server.tld
+"#,
+{
+    lang: String,
+    jid: Option<String>,
+},
+|aparte, _command| {
+    let (account, jid) = aparte.get_mod::<UIMod>().conversation_target(aparte, jid)?;
+    {
+        let mut conversation = aparte.get_mod_mut::<ConversationMod>();
+        conversation.set_lang(aparte, &account, &jid, Some(&lang))?;
+    }
+    crate::info!(aparte, "Messages sent to {jid} are now tagged as {lang}");
+    Ok(())
+});
+
+command_def!(lang_reset,
+r#"/lang reset [<jid>]
+
+    jid    jid of the contact/channel
+
+Description:
+    Tag messages sent to the current or a given contact/channel with the
+    language of the account, if any.
+
+Examples:
+    /lang reset
+"#,
+{
+    jid: Option<String>,
+},
+|aparte, _command| {
+    let (account, jid) = aparte.get_mod::<UIMod>().conversation_target(aparte, jid)?;
+    {
+        let mut conversation = aparte.get_mod_mut::<ConversationMod>();
+        conversation.set_lang(aparte, &account, &jid, None)?;
+    }
+    crate::info!(aparte, "Messages sent to {jid} now use the account language");
+    Ok(())
+});
+
+command_def!(lang,
+r#"/lang set|reset"#,
+{
+    action: Command = {
+        children: {
+            "set": lang_set,
+            "reset": lang_reset,
+        }
+    },
+});
+
+/// Pinned messages ordered by their original timestamp, numbered from 1 in /pins
+fn pinned_messages(
+    aparte: &Aparte,
+    account: &Account,
+    jid: Option<&BareJid>,
+) -> Result<Vec<PinnedMessage>> {
+    let mut pinned = aparte.storage.get_pinned_messages(account, jid)?;
+    pinned.sort_by_key(|pinned| DateTime::parse_from_rfc3339(&pinned.timestamp).ok());
+    Ok(pinned)
+}
+
+command_def!(pin,
+r#"/pin [<text>]
+
+    text    Text contained in the message to pin
+
+Description:
+    Pin the last message of the current conversation, or the last one
+    containing the given text. Pinned messages are stored locally and listed
+    with /pins.
+
+Examples:
+    /pin
+    /pin "release date"
+"#,
+{
+    text: Option<String>,
+},
+|aparte, _command| {
+    let (account, jid) = aparte.get_mod::<UIMod>().conversation_target(aparte, None)?;
+    let message = {
+        let messages = aparte.get_mod::<MessagesMod>();
+        messages.find_last(&account, &jid, text.as_deref())
+    };
+    let message = match (message, text) {
+        (Some(message), _) => message,
+        (None, Some(text)) => anyhow::bail!("No message containing \"{text}\" in {jid}"),
+        (None, None) => anyhow::bail!("No message in {jid}"),
+    };
+    let author = match (&message.type_, &message.from_full) {
+        (XmppMessageType::Channel, Jid::Full(from)) => from.resource().to_string(),
+        _ => message.from.to_string(),
+    };
+    aparte.storage.add_pinned_message(
+        &account,
+        &jid,
+        &message.id,
+        &message.get_original_timestamp().to_rfc3339(),
+        &author,
+        message.get_last_body(),
+    )?;
+    crate::info!(aparte, "Pinned message of {author} in {jid}");
+    Ok(())
+});
+
+command_def!(unpin,
+r#"/unpin <number> [<jid>]
+
+    number  Number of the message as listed by /pins
+    jid     jid of the contact/channel
+
+Description:
+    Unpin a message of the current or a given contact/channel.
+
+Examples:
+    /unpin 2
+    /unpin 1 contact@server.tld
+"#,
+{
+    number: usize,
+    jid: Option<String>,
+},
+|aparte, _command| {
+    let (account, jid) = aparte.get_mod::<UIMod>().conversation_target(aparte, jid)?;
+    let pinned = pinned_messages(aparte, &account, Some(&jid))?;
+    let pinned = number
+        .checked_sub(1)
+        .and_then(|index| pinned.get(index))
+        .with_context(|| format!("No pinned message {number} in {jid}"))?;
+    aparte.storage.remove_pinned_message(pinned.pinned_message_pk)?;
+    crate::info!(aparte, "Unpinned message {number} of {jid}");
+    Ok(())
+});
+
+command_def!(pins,
+r#"/pins [<jid>]
+
+    jid    jid of the contact/channel
+
+Description:
+    List messages pinned in the current or a given contact/channel, or in
+    every conversation of the current account when the current window isn't
+    a conversation.
+
+Examples:
+    /pins
+    /pins channel@conference.server.tld
+"#,
+{
+    jid: Option<String>,
+},
+|aparte, _command| {
+    let (account, jid) = match jid {
+        Some(jid) => {
+            let (account, jid) = aparte.get_mod::<UIMod>().conversation_target(aparte, Some(jid))?;
+            (account, Some(jid))
+        }
+        // Pins of every conversation of the account outside of conversation windows
+        None => match aparte.get_mod::<UIMod>().current_conversation() {
+            Ok((account, conversation)) => (account, Some(conversation.get_jid().clone())),
+            Err(_) => (aparte.current_account().context("No connection found")?, None),
+        },
+    };
+
+    let entries = pinned_messages(aparte, &account, jid.as_ref())?
+        .iter()
+        .enumerate()
+        .map(|(index, pinned)| {
+            let timestamp = DateTime::parse_from_rfc3339(&pinned.timestamp)
+                .unwrap_or_else(|_| LocalTz::now().into());
+            let body = match jid {
+                Some(_) => format!("{}. <{}> {}", index + 1, pinned.author, pinned.body),
+                None => format!("{}. {} <{}> {}", index + 1, pinned.jid, pinned.author, pinned.body),
+            };
+            Message::Log(LogMessage {
+                id: pinned.pinned_message_pk.to_string(),
+                timestamp,
+                body,
+                level: log::Level::Info,
+            })
+        })
+        .collect();
+    aparte.schedule(Event::Pins {
+        conversation: jid,
+        entries,
+    });
+    Ok(())
+});
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
 struct ConversationIndex {
@@ -22,15 +387,125 @@ struct ConversationIndex {
 pub struct ConversationMod {
     /// Collections of currently opened conversations.
     conversations: HashMap<ConversationIndex, conversation::Conversation>,
+    /// Conversations for which every message is important
+    vip: HashSet<ConversationIndex>,
+    /// Conversations for which no message is important
+    muted: HashSet<ConversationIndex>,
+    /// Language of messages sent in each conversation
+    langs: HashMap<ConversationIndex, String>,
 }
 
 impl ConversationMod {
     pub fn new() -> Self {
         Self {
             conversations: HashMap::new(),
+            vip: HashSet::new(),
+            muted: HashSet::new(),
+            langs: HashMap::new(),
         }
     }
 
+    pub fn set_lang(
+        &mut self,
+        aparte: &Aparte,
+        account: &Account,
+        jid: &BareJid,
+        lang: Option<&str>,
+    ) -> Result<()> {
+        let index = ConversationIndex {
+            account: account.clone(),
+            jid: jid.clone(),
+        };
+        match lang {
+            Some(lang) => {
+                aparte.storage.set_conversation_lang(account, jid, lang)?;
+                self.langs.insert(index, lang.to_string());
+            }
+            None => {
+                aparte.storage.remove_conversation_lang(account, jid)?;
+                self.langs.remove(&index);
+            }
+        }
+        Ok(())
+    }
+
+    /// Language of messages sent in a conversation, empty when untagged
+    pub fn get_lang(&self, aparte: &Aparte, account: &Account, jid: &BareJid) -> String {
+        let index = ConversationIndex {
+            account: account.clone(),
+            jid: jid.clone(),
+        };
+        self.langs
+            .get(&index)
+            .cloned()
+            .or_else(|| {
+                aparte
+                    .config
+                    .accounts
+                    .values()
+                    .find(|info| {
+                        Jid::from_str(&info.jid).map(|jid| jid.to_bare()).ok()
+                            == Some(account.to_bare())
+                    })
+                    .and_then(|info| info.lang.clone())
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn set_vip(
+        &mut self,
+        aparte: &Aparte,
+        account: &Account,
+        jid: &BareJid,
+        vip: bool,
+    ) -> Result<()> {
+        let index = ConversationIndex {
+            account: account.clone(),
+            jid: jid.clone(),
+        };
+        if vip {
+            aparte.storage.add_vip_conversation(account, jid)?;
+            self.vip.insert(index);
+        } else {
+            aparte.storage.remove_vip_conversation(account, jid)?;
+            self.vip.remove(&index);
+        }
+        Ok(())
+    }
+
+    pub fn set_muted(
+        &mut self,
+        aparte: &Aparte,
+        account: &Account,
+        jid: &BareJid,
+        muted: bool,
+    ) -> Result<()> {
+        let index = ConversationIndex {
+            account: account.clone(),
+            jid: jid.clone(),
+        };
+        if muted {
+            aparte.storage.add_muted_conversation(account, jid)?;
+            self.muted.insert(index);
+        } else {
+            aparte.storage.remove_muted_conversation(account, jid)?;
+            self.muted.remove(&index);
+        }
+        Ok(())
+    }
+
+    /// Muted conversations of an account, sorted
+    pub fn muted(&self, account: &Account) -> Vec<BareJid> {
+        let mut muted: Vec<BareJid> = self
+            .muted
+            .iter()
+            .filter(|index| &index.account == account)
+            .map(|index| index.jid.clone())
+            .collect();
+        muted.sort_by_key(|jid| jid.to_string());
+        muted
+    }
+
     pub fn get<'a>(
         &'a self,
         account: &Account,
@@ -68,12 +543,50 @@ impl From<muc::user::Affiliation> for conversation::Affiliation {
 }
 
 impl ModTrait for ConversationMod {
-    fn init(&mut self, _aparte: &mut Aparte) -> Result<(), ()> {
+    fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
+        aparte.add_command(vip::new());
+        aparte.add_command(mute::new());
+        aparte.add_command(unmute::new());
+        aparte.add_command(lang::new());
+        aparte.add_command(pin::new());
+        aparte.add_command(unpin::new());
+        aparte.add_command(pins::new());
         Ok(())
     }
 
     fn on_event(&mut self, aparte: &mut Aparte, event: &Event) {
         match event {
+            Event::Connected(account, _) => {
+                match aparte.storage.get_vip_conversations(account) {
+                    Ok(vip) => self
+                        .vip
+                        .extend(vip.into_iter().map(|jid| ConversationIndex {
+                            account: account.clone(),
+                            jid,
+                        })),
+                    Err(err) => crate::error!(aparte, err, "Cannot load VIP conversations"),
+                }
+                match aparte.storage.get_muted_conversations(account) {
+                    Ok(muted) => {
+                        self.muted
+                            .extend(muted.into_iter().map(|jid| ConversationIndex {
+                                account: account.clone(),
+                                jid,
+                            }))
+                    }
+                    Err(err) => crate::error!(aparte, err, "Cannot load muted conversations"),
+                }
+                match aparte.storage.get_conversation_langs(account) {
+                    Ok(langs) => self.langs.extend(langs.into_iter().map(|(jid, lang)| {
+                        let index = ConversationIndex {
+                            account: account.clone(),
+                            jid,
+                        };
+                        (index, lang)
+                    })),
+                    Err(err) => crate::error!(aparte, err, "Cannot load conversation languages"),
+                }
+            }
             Event::Chat { account, contact } => {
                 let conversation = conversation::Conversation::Chat(conversation::Chat {
                     account: account.clone(),
@@ -110,20 +623,32 @@ impl ModTrait for ConversationMod {
                     if !message.archive && message.direction == message::Direction::Incoming {
                         let conversation = self.conversations.get(&index);
                         if let Some(conversation) = conversation {
-                            let important = match &conversation {
-                                conversation::Conversation::Chat(_) => true,
+                            let mention = match &conversation {
+                                conversation::Conversation::Chat(_) => false,
                                 conversation::Conversation::Channel(channel) => {
-                                    // Look for mentions
+                                    // Look for mentions, including our previous nicks
                                     let mut mention = false;
                                     let body = message.get_last_body();
                                     for word in body.split_word_bounds() {
-                                        if channel.nick == word {
+                                        if channel.nick == channel.resolve_nick(word) {
                                             mention = true;
                                         }
                                     }
                                     mention
                                 }
                             };
+                            let important = match &conversation {
+                                _ if self.muted.contains(&index) => false,
+                                _ if self.vip.contains(&index) => true,
+                                conversation::Conversation::Chat(_) => true,
+                                conversation::Conversation::Channel(_) => mention,
+                            };
+                            if mention {
+                                aparte.schedule(Event::Mention {
+                                    account: account.clone(),
+                                    message: message.clone(),
+                                });
+                            }
                             aparte.schedule(Event::Notification {
                                 conversation: conversation.clone(),
                                 important,
@@ -142,6 +667,7 @@ impl ModTrait for ConversationMod {
                     nick: channel.resource().to_string(),
                     name: None,
                     occupants: HashMap::new(),
+                    nick_aliases: HashMap::new(),
                 });
 
                 let index = ConversationIndex {
@@ -161,10 +687,31 @@ impl ModTrait for ConversationMod {
                     {
                         for payload in presence.clone().payloads {
                             if let Ok(muc_user) = muc::user::MucUser::try_from(payload) {
+                                let nick_change = muc_user
+                                    .status
+                                    .iter()
+                                    .any(|status| matches!(status, muc::user::Status::NewNick));
                                 for item in muc_user.items {
+                                    if nick_change {
+                                        if let Some(nick) = &item.nick {
+                                            let previous = from.resource().to_string();
+                                            if let Some(occupant) =
+                                                channel.rename_occupant(&previous, nick)
+                                            {
+                                                aparte.schedule(Event::OccupantRenamed {
+                                                    account: index.account.clone(),
+                                                    conversation: index.jid.clone(),
+                                                    previous,
+                                                    occupant,
+                                                });
+                                            }
+                                            continue;
+                                        }
+                                    }
+
                                     let occupant_jid = item.jid.map(|full| full.to_bare());
                                     let occupant = conversation::Occupant {
-                                        nick: from.resource().to_string(),
+                                        nick: intern(from.resource().as_str()),
                                         jid: occupant_jid,
                                         affiliation: item.affiliation.into(),
                                         role: item.role.into(),
@@ -1,19 +1,135 @@
 /* This Source Code Form is subject to the terms of the Mozilla Public
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::fmt;
 
+use anyhow::Context;
+use chrono::Local as LocalTz;
+use uuid::Uuid;
 use xmpp_parsers::delay::Delay;
 use xmpp_parsers::message::Message as XmppParsersMessage;
 use xmpp_parsers::message_correct::Replace;
 use xmpp_parsers::ns;
 
 use crate::account::Account;
+use crate::command::{Command, CommandParser};
 use crate::core::{Aparte, Event, ModTrait};
-use crate::message::Message;
+use crate::i18n;
+use crate::message::{LogMessage, Message, VersionedXmppMessage, XmppMessageVersion};
+use crate::mods::conversation::ConversationMod;
 use crate::mods::disco;
 use crate::mods::messages;
+use crate::mods::ui::UIMod;
+use crate::word;
+
+command_def!(correct,
+r#"/correct <text>
+
+    text    New text of the message
+
+Description:
+    Correct the last message sent in the current conversation. Pressing Up
+    while the input is empty fills it with this command and the last message
+    to edit it.
+
+Examples:
+    /correct "Hello world"
+"#,
+{
+    text: String,
+},
+|aparte, _command| {
+    let (account, conversation) = aparte.get_mod::<UIMod>().current_conversation()?;
+    let jid = conversation.get_jid().clone();
+
+    let message = {
+        let messages = aparte.get_mod::<messages::MessagesMod>();
+        messages.find_last_sent(&account, &jid, None)
+    };
+    let mut message = message.with_context(|| format!("No message sent in {jid}"))?;
+    if message.retracted {
+        anyhow::bail!("Last message sent in {jid} is retracted");
+    }
+
+    let lang = aparte
+        .get_mod::<ConversationMod>()
+        .get_lang(aparte, &account, &jid);
+    let mut bodies = HashMap::new();
+    bodies.insert(lang, text);
+    message.history.push(XmppMessageVersion {
+        id: Uuid::new_v4().to_string(),
+        timestamp: LocalTz::now().into(),
+        bodies,
+    });
+    aparte.schedule(Event::SendMessage(account, Message::Xmpp(message)));
+    Ok(())
+});
+
+command_def!(versions,
+r#"/versions [<text>]
+
+    text    Text contained in one of the versions of the message
+
+Description:
+    List every version of the last corrected message of the current
+    conversation, or of the last one with a version containing the given
+    text, along with when each version was sent.
+
+Examples:
+    /versions
+    /versions "meeting"
+"#,
+{
+    text: Option<String>,
+},
+|aparte, _command| {
+    let (account, conversation) = aparte.get_mod::<UIMod>().current_conversation()?;
+    let jid = conversation.get_jid().clone();
+
+    let message = {
+        let messages = aparte.get_mod::<messages::MessagesMod>();
+        messages.find_last_corrected(&account, &jid, text.as_deref())
+    };
+    let message = match (message, text) {
+        (Some(message), _) => message,
+        (None, Some(text)) => anyhow::bail!("No corrected message containing \"{text}\" in {jid}"),
+        (None, None) => anyhow::bail!("No corrected message in {jid}"),
+    };
+
+    aparte.schedule(Event::ConversationLog {
+        account,
+        conversation: jid,
+        message: LogMessage {
+            id: format!("versions-{}", message.id),
+            timestamp: LocalTz::now().into(),
+            body: versions(&message),
+            level: log::Level::Info,
+        },
+    });
+    Ok(())
+});
+
+/// Versions of a corrected message, oldest first
+fn versions(message: &VersionedXmppMessage) -> String {
+    let mut history = message.history.iter().collect::<Vec<_>>();
+    history.sort();
+
+    let mut versions = format!(
+        "Versions of \"{}\":",
+        word::excerpt(message.get_last_body(), 40)
+    );
+    for (index, version) in history.iter().enumerate() {
+        versions.push_str(&format!(
+            "\n  {}. {} {}",
+            index + 1,
+            version.timestamp.with_timezone(&LocalTz).format("%T"),
+            version.get_best_body(i18n::preferred_langs())
+        ));
+    }
+    versions
+}
 
 pub struct CorrectionMod {}
 
@@ -65,8 +181,12 @@ impl CorrectionMod {
 
 impl ModTrait for CorrectionMod {
     fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
-        let mut disco = aparte.get_mod_mut::<disco::DiscoMod>();
-        disco.add_feature(ns::MESSAGE_CORRECT);
+        {
+            let mut disco = aparte.get_mod_mut::<disco::DiscoMod>();
+            disco.add_feature(ns::MESSAGE_CORRECT);
+        }
+        aparte.add_command(correct::new());
+        aparte.add_command(versions::new());
 
         Ok(())
     }
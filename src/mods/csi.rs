@@ -0,0 +1,138 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use std::collections::HashSet;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use xmpp_parsers::csi::{Active, Inactive};
+
+use crate::account::Account;
+use crate::core::{Aparte, Event, ModTrait};
+
+/// Delay without typing after which the client is told inactive, unless configured otherwise
+const IDLE_TIMEOUT: u64 = 300;
+
+/// XEP-0352: Client State Indication
+///
+/// Tell servers the client is inactive when the terminal loses focus or nothing is typed for a
+/// while, so that they can hold back traffic that isn't urgent.
+pub struct CsiMod {
+    /// Connected accounts
+    connected: HashSet<Account>,
+    active: bool,
+    last_activity: Instant,
+    /// An idle timeout is already on its way
+    timeout_pending: bool,
+}
+
+impl CsiMod {
+    pub fn new() -> Self {
+        Self {
+            connected: HashSet::new(),
+            active: true,
+            last_activity: Instant::now(),
+            timeout_pending: false,
+        }
+    }
+
+    fn enabled(aparte: &Aparte) -> bool {
+        aparte.config.client_state_indication.unwrap_or(true)
+    }
+
+    fn set_active(&mut self, aparte: &mut Aparte, active: bool) {
+        if self.active == active {
+            return;
+        }
+        self.active = active;
+        log::debug!("Client is {}", if active { "active" } else { "inactive" });
+        for account in self.connected.iter() {
+            match active {
+                true => aparte.send(account, Active),
+                false => aparte.send(account, Inactive),
+            }
+        }
+    }
+
+    /// Something was typed, or the terminal got focus
+    fn activity(&mut self, aparte: &mut Aparte) {
+        self.set_active(aparte, true);
+        self.last_activity = Instant::now();
+        if let Some(timeout) = Self::timeout(aparte) {
+            self.schedule_timeout(aparte, timeout);
+        }
+    }
+
+    /// Delay without typing after which the client is idle, none when disabled
+    pub fn timeout(aparte: &Aparte) -> Option<Duration> {
+        match aparte.config.idle_timeout.unwrap_or(IDLE_TIMEOUT) {
+            0 => None,
+            timeout => Some(Duration::from_secs(timeout)),
+        }
+    }
+
+    /// A single timeout is kept pending rather than one per key stroke
+    fn schedule_timeout(&mut self, aparte: &mut Aparte, delay: Duration) {
+        if self.timeout_pending {
+            return;
+        }
+        self.timeout_pending = true;
+        Aparte::spawn({
+            let mut aparte = aparte.proxy();
+            async move {
+                tokio::time::sleep(delay).await;
+                aparte.schedule(Event::IdleTimeout);
+            }
+        });
+    }
+
+    fn idle_timeout(&mut self, aparte: &mut Aparte) {
+        self.timeout_pending = false;
+        let timeout = match Self::timeout(aparte) {
+            Some(timeout) => timeout,
+            None => return,
+        };
+        let idle = self.last_activity.elapsed();
+        match timeout.checked_sub(idle) {
+            // Something was typed since the timeout was scheduled
+            Some(remaining) if !remaining.is_zero() => self.schedule_timeout(aparte, remaining),
+            _ => self.set_active(aparte, false),
+        }
+    }
+}
+
+impl ModTrait for CsiMod {
+    fn init(&mut self, _aparte: &mut Aparte) -> Result<(), ()> {
+        Ok(())
+    }
+
+    fn on_event(&mut self, aparte: &mut Aparte, event: &Event) {
+        if !Self::enabled(aparte) {
+            return;
+        }
+
+        match event {
+            Event::Start => self.activity(aparte),
+            Event::Connected(account, _) => {
+                self.connected.insert(account.clone());
+                // Servers consider new sessions active
+                if !self.active {
+                    aparte.send(account, Inactive);
+                }
+            }
+            Event::Disconnected(account, _) => {
+                self.connected.remove(account);
+            }
+            Event::Key(_) | Event::Focus(true) => self.activity(aparte),
+            Event::Focus(false) => self.set_active(aparte, false),
+            Event::IdleTimeout => self.idle_timeout(aparte),
+            _ => {}
+        }
+    }
+}
+
+impl fmt::Display for CsiMod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "XEP-0352: Client State Indication")
+    }
+}
@@ -6,22 +6,79 @@ use std::convert::TryFrom;
 use std::fmt;
 use std::str::FromStr;
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
 use uuid::Uuid;
 
-use xmpp_parsers::disco;
+use xmpp_parsers::caps::{self, Caps};
+use xmpp_parsers::disco as xmpp_disco;
 use xmpp_parsers::disco::Feature;
 use xmpp_parsers::iq::{Iq, IqType};
-use xmpp_parsers::{ns, Jid};
+use xmpp_parsers::presence::{Presence, Type as PresenceType};
+use xmpp_parsers::{ns, Element, FullJid, Jid};
 
 use crate::account::Account;
+use crate::command::{Command, CommandParser};
 use crate::core::{Aparte, AparteAsync, Event, ModTrait};
 use crate::i18n;
 
+const NS_PUSH: &str = "urn:xmpp:push:0";
+
+/// Server features reported after connection, with their displayed name
+const REPORTED_FEATURES: [(&str, &str); 5] = [
+    ("MAM", ns::MAM),
+    ("carbons", ns::CARBONS),
+    ("blocking", ns::BLOCKING),
+    ("upload", ns::HTTP_UPLOAD),
+    ("push", NS_PUSH),
+];
+
+command_def!(disco,
+r#"/disco [<jid>] [<node>]
+
+    jid     Entity to explore, server of the current account when missing
+    node    Node of the entity
+
+Description:
+    Open a window browsing the services of an entity. Select an entry with
+    the up and down arrows and press enter, with an empty input, to list
+    its items and features, or to fold them again.
+
+Examples:
+    /disco
+    /disco conference.server.tld
+    /disco pubsub.server.tld princely_musings
+"#,
+{
+    jid: Option<String>,
+    node: Option<String>,
+},
+|aparte, _command| {
+    let account = aparte.current_account().context("No connection found")?;
+    let jid = match jid {
+        Some(jid) => Jid::from_str(&jid).with_context(|| format!("Invalid jid {jid}"))?,
+        None => Jid::from_str(&account.domain().to_string())?,
+    };
+    DiscoMod::browse(aparte, &account, &jid, node, true);
+    Ok(())
+});
+
+/// Server feature a command relies on
+struct Requirement {
+    /// Displayed name of the extension providing the feature
+    xep: &'static str,
+    feature: &'static str,
+}
+
 pub struct DiscoMod {
-    identity: disco::Identity,
+    identity: xmpp_disco::Identity,
     client_features: HashSet<Feature>,
+    /// Features of each account's server, once discovered
     server_features: HashMap<Account, Vec<String>>,
+    /// Features of contacts' connected resources, from their entity capabilities
+    contact_features: HashMap<(Account, FullJid), Vec<String>>,
+    /// Resources waiting for the disco info of each verification string being queried
+    pending_caps: HashMap<String, Vec<(Account, FullJid)>>,
+    requirements: HashMap<&'static str, Requirement>,
 }
 
 impl DiscoMod {
@@ -32,9 +89,12 @@ impl DiscoMod {
         name: N,
     ) -> Self {
         Self {
-            identity: disco::Identity::new(category, type_, lang, name),
+            identity: xmpp_disco::Identity::new(category, type_, lang, name),
             client_features: HashSet::new(),
             server_features: HashMap::new(),
+            contact_features: HashMap::new(),
+            pending_caps: HashMap::new(),
+            requirements: HashMap::new(),
         }
     }
 
@@ -47,9 +107,141 @@ impl DiscoMod {
     pub fn has_feature(&self, account: &Account, feature: &str) -> bool {
         self.server_features
             .get(account)
-            .unwrap()
+            .map_or(false, |features| features.iter().any(|i| i == feature))
+    }
+
+    /// Whether the resource of a contact supports the feature, unknown until its
+    /// capabilities are discovered
+    pub fn contact_has_feature(
+        &self,
+        account: &Account,
+        jid: &FullJid,
+        feature: &str,
+    ) -> Option<bool> {
+        self.contact_features
+            .get(&(account.clone(), jid.clone()))
+            .map(|features| features.iter().any(|i| i == feature))
+    }
+
+    /// XEP-0115: Entity Capabilities, disco info being looked up in storage first so that
+    /// each verification string is only queried once
+    fn handle_caps(&mut self, aparte: &mut Aparte, account: &Account, presence: &Presence) {
+        let from = match &presence.from {
+            Some(Jid::Full(from)) => from.clone(),
+            _ => return,
+        };
+        if presence.type_ == PresenceType::Unavailable {
+            self.contact_features.remove(&(account.clone(), from));
+            return;
+        }
+        let caps = match presence
+            .payloads
             .iter()
-            .any(|i| i == feature)
+            .find(|payload| payload.is("c", ns::CAPS))
+            .map(|payload| Caps::try_from(payload.clone()))
+        {
+            Some(Ok(caps)) => caps,
+            _ => return,
+        };
+
+        let algo = String::from(caps.hash.algo.clone());
+        let ver = caps.hash.to_base64();
+        match aparte.storage.get_caps(&algo, &ver) {
+            Ok(Some(disco)) => match Self::parse_disco(&disco) {
+                Ok(disco) => {
+                    let features = disco.features.iter().map(|i| i.var.clone()).collect();
+                    self.contact_features
+                        .insert((account.clone(), from), features);
+                    return;
+                }
+                Err(err) => log::warn!("Invalid cached disco info for {ver}: {err}"),
+            },
+            Ok(None) => {}
+            Err(err) => log::warn!("Cannot read caps cache: {err}"),
+        }
+
+        if let Some(waiting) = self.pending_caps.get_mut(&ver) {
+            waiting.push((account.clone(), from));
+            return;
+        }
+        self.pending_caps
+            .insert(ver.clone(), vec![(account.clone(), from.clone())]);
+        Aparte::spawn({
+            let mut aparte = aparte.proxy();
+            let account = account.clone();
+            async move {
+                let features = match Self::query_caps(
+                    &mut aparte,
+                    &account,
+                    &from,
+                    &caps,
+                    &algo,
+                    &ver,
+                )
+                .await
+                {
+                    Ok(features) => Some(features),
+                    Err(err) => {
+                        log::warn!("Cannot get capabilities of {from}: {err}");
+                        None
+                    }
+                };
+                aparte.schedule(Event::ContactDisco { ver, features });
+            }
+        });
+    }
+
+    /// Query and store the disco info matching a verification string
+    async fn query_caps(
+        aparte: &mut AparteAsync,
+        account: &Account,
+        jid: &FullJid,
+        caps: &Caps,
+        algo: &str,
+        ver: &str,
+    ) -> Result<Vec<String>> {
+        let node = format!("{}#{}", caps.node, ver);
+        let disco =
+            Self::get_disco_info(aparte, account, &Jid::Full(jid.clone()), Some(node)).await?;
+
+        // Only verified results can be shared between entities
+        let hash = caps::hash_caps(&caps::compute_disco(&disco), caps.hash.algo.clone())
+            .map_err(|err| anyhow!("{err}"))?;
+        if hash.hash != caps.hash.hash {
+            return Err(anyhow!("verification string mismatch"));
+        }
+
+        let features = disco.features.iter().map(|i| i.var.clone()).collect();
+        let disco = String::from(&Element::from(disco));
+        aparte.storage.add_caps(algo, ver, &disco)?;
+        Ok(features)
+    }
+
+    fn parse_disco(disco: &str) -> Result<xmpp_disco::DiscoInfoResult> {
+        let disco = Element::from_str(disco)?;
+        Ok(xmpp_disco::DiscoInfoResult::try_from(disco)?)
+    }
+
+    /// Make a command unavailable when the server lacks the given feature
+    pub fn require_feature(
+        &mut self,
+        command: &'static str,
+        xep: &'static str,
+        feature: &'static str,
+    ) {
+        self.requirements
+            .insert(command, Requirement { xep, feature });
+    }
+
+    /// Extension missing on the server of the account for the command to be usable.
+    /// Commands are considered available until the server features are discovered.
+    pub fn missing_requirement(&self, account: &Option<Account>, command: &str) -> Option<&str> {
+        let requirement = self.requirements.get(command)?;
+        let features = self.server_features.get(account.as_ref()?)?;
+        match features.iter().any(|i| i == requirement.feature) {
+            true => None,
+            false => Some(requirement.xep),
+        }
     }
 
     async fn get_server_disco(
@@ -57,43 +249,195 @@ impl DiscoMod {
         account: &Account,
         jid: &Jid,
     ) -> Result<()> {
+        let server = Jid::from_str(&jid.domain().to_string()).unwrap();
+        let mut features = Self::get_features(aparte, account, &server).await?;
+
+        // Some features such as MAM are advertised by the account itself
+        match Self::get_features(aparte, account, &Jid::Bare(account.to_bare())).await {
+            Ok(account_features) => features.extend(account_features),
+            Err(err) => log::warn!("Cannot get account disco info: {}", err),
+        }
+
+        // Upload is usually provided by a server component
+        if !features.iter().any(|feature| feature == ns::HTTP_UPLOAD) {
+            match Self::find_component(aparte, account, &server, ns::HTTP_UPLOAD).await {
+                Ok(Some(_)) => features.push(ns::HTTP_UPLOAD.to_string()),
+                Ok(None) => {}
+                Err(err) => log::warn!("Cannot get server components: {}", err),
+            }
+        }
+
+        aparte.log(Self::feature_report(account, &features));
+        aparte.schedule(Event::Disco(account.clone(), features));
+
+        Ok(())
+    }
+
+    pub async fn get_features(
+        aparte: &mut AparteAsync,
+        account: &Account,
+        jid: &Jid,
+    ) -> Result<Vec<String>> {
+        let disco = Self::get_disco_info(aparte, account, jid, None).await?;
+        Ok(disco.features.iter().map(|i| i.var.clone()).collect())
+    }
+
+    pub async fn get_disco_info(
+        aparte: &mut AparteAsync,
+        account: &Account,
+        jid: &Jid,
+        node: Option<String>,
+    ) -> Result<xmpp_disco::DiscoInfoResult> {
         let resp = aparte
-            .iq(
-                account,
-                Self::disco_info_query_iq(&Jid::from_str(&jid.domain().to_string()).unwrap(), None),
-            )
+            .iq(account, Self::disco_info_query_iq(jid, node))
             .await?;
 
         match resp.payload {
             IqType::Result(Some(el)) => {
-                if let Ok(disco) = disco::DiscoInfoResult::try_from(el) {
-                    aparte.schedule(Event::Disco(
-                        account.clone(),
-                        disco.features.iter().map(|i| i.var.clone()).collect(),
-                    ));
-
-                    Ok(())
+                if let Ok(disco) = xmpp_disco::DiscoInfoResult::try_from(el) {
+                    Ok(disco)
                 } else {
-                    Err(anyhow!("Cannot get server disco info: invalid response"))
+                    Err(anyhow!(
+                        "Cannot get disco info of {}: invalid response",
+                        jid
+                    ))
                 }
             }
             IqType::Error(err) => Err(anyhow!(
-                "Cannot get server disco info: {}",
+                "Cannot get disco info of {}: {}",
+                jid,
+                i18n::xmpp_err_to_string(&err, vec![]).1
+            )),
+            _ => Err(anyhow!(
+                "Cannot get disco info of {}: invalid response",
+                jid
+            )),
+        }
+    }
+
+    /// Query the identities, features and items of an entity for the disco browser
+    pub fn browse(aparte: &Aparte, account: &Account, jid: &Jid, node: Option<String>, root: bool) {
+        Aparte::spawn({
+            let mut aparte = aparte.proxy();
+            let account = account.clone();
+            let jid = jid.clone();
+            async move {
+                let info = Self::get_disco_info(&mut aparte, &account, &jid, node.clone()).await;
+                let items = Self::get_disco_items(&mut aparte, &account, &jid, node.clone()).await;
+                let (info, items) = match (info, items) {
+                    (Err(err), Err(_)) => {
+                        crate::error!(aparte, err, "Cannot explore {jid}");
+                        return;
+                    }
+                    (info, items) => (info.ok(), items.unwrap_or_default()),
+                };
+
+                let identities = info
+                    .iter()
+                    .flat_map(|info| info.identities.iter())
+                    .map(|identity| match &identity.name {
+                        Some(name) => format!("{}/{} ({name})", identity.category, identity.type_),
+                        None => format!("{}/{}", identity.category, identity.type_),
+                    })
+                    .collect();
+                let features = info
+                    .iter()
+                    .flat_map(|info| info.features.iter())
+                    .map(|feature| feature.var.clone())
+                    .collect();
+                aparte.schedule(Event::DiscoBrowse {
+                    account,
+                    jid,
+                    node,
+                    root,
+                    identities,
+                    features,
+                    items,
+                });
+            }
+        });
+    }
+
+    pub async fn get_disco_items(
+        aparte: &mut AparteAsync,
+        account: &Account,
+        jid: &Jid,
+        node: Option<String>,
+    ) -> Result<Vec<xmpp_disco::Item>> {
+        let id = Uuid::new_v4().hyphenated().to_string();
+        let iq = Iq::from_get(id, xmpp_disco::DiscoItemsQuery { node }).with_to(jid.clone());
+        match aparte.iq(account, iq).await?.payload {
+            IqType::Result(Some(el)) => Ok(xmpp_disco::DiscoItemsResult::try_from(el)?.items),
+            IqType::Error(err) => Err(anyhow!(
+                "Cannot get disco items of {}: {}",
+                jid,
                 i18n::xmpp_err_to_string(&err, vec![]).1
             )),
-            _ => Err(anyhow!("Cannot get server disco info: invalid response")),
+            _ => Err(anyhow!(
+                "Cannot get disco items of {}: invalid response",
+                jid
+            )),
+        }
+    }
+
+    /// First component of the server providing the feature
+    pub async fn find_component(
+        aparte: &mut AparteAsync,
+        account: &Account,
+        server: &Jid,
+        feature: &str,
+    ) -> Result<Option<Jid>> {
+        let items = Self::get_disco_items(aparte, account, server, None).await?;
+
+        for item in items {
+            match Self::get_features(aparte, account, &item.jid).await {
+                Ok(features) if features.iter().any(|i| i == feature) => return Ok(Some(item.jid)),
+                Ok(_) => {}
+                Err(err) => log::warn!("{}", err),
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Concise summary of available and missing server features
+    fn feature_report(account: &Account, features: &[String]) -> String {
+        let (available, missing): (Vec<_>, Vec<_>) = REPORTED_FEATURES
+            .iter()
+            .partition(|(_, var)| features.iter().any(|feature| feature == var));
+        let names = |features: Vec<&(&str, &str)>| {
+            features
+                .iter()
+                .map(|(name, _)| *name)
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+
+        match (available.is_empty(), missing.is_empty()) {
+            (_, true) => format!("Server features of {}: all available", account.to_bare()),
+            (true, false) => format!(
+                "Server features of {}: missing {}",
+                account.to_bare(),
+                names(missing)
+            ),
+            (false, false) => format!(
+                "Server features of {}: {} available, missing {}",
+                account.to_bare(),
+                names(available),
+                names(missing)
+            ),
         }
     }
 
     fn disco_info_query_iq(jid: &Jid, node: Option<String>) -> Iq {
         let id = Uuid::new_v4().hyphenated().to_string();
-        let query = disco::DiscoInfoQuery { node };
+        let query = xmpp_disco::DiscoInfoQuery { node };
         Iq::from_get(id, query).with_to(jid.clone())
     }
 
-    pub fn get_disco(&self) -> disco::DiscoInfoResult {
+    pub fn get_disco(&self) -> xmpp_disco::DiscoInfoResult {
         let identities = vec![self.identity.clone()];
-        disco::DiscoInfoResult {
+        xmpp_disco::DiscoInfoResult {
             node: None,
             identities,
             features: self.client_features.iter().cloned().collect(),
@@ -103,8 +447,9 @@ impl DiscoMod {
 }
 
 impl ModTrait for DiscoMod {
-    fn init(&mut self, _aparte: &mut Aparte) -> Result<(), ()> {
+    fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
         self.add_feature(ns::DISCO_INFO);
+        aparte.add_command(disco::new());
         // TODO? self.add_feature(ns::DISCO_ITEMS);
         Ok(())
     }
@@ -112,7 +457,7 @@ impl ModTrait for DiscoMod {
     fn on_event(&mut self, aparte: &mut Aparte, event: &Event) {
         match event {
             Event::Connected(account, jid) => {
-                self.server_features.insert(account.clone(), Vec::new());
+                self.server_features.remove(account);
 
                 Aparte::spawn({
                     let mut aparte = aparte.proxy();
@@ -127,13 +472,21 @@ impl ModTrait for DiscoMod {
                 });
             }
             Event::Disco(account, features) => {
-                if let Some(server_features) = self.server_features.get_mut(account) {
-                    server_features.extend(features.clone());
+                self.server_features
+                    .insert(account.clone(), features.clone());
+            }
+            Event::Presence(account, presence) => self.handle_caps(aparte, account, presence),
+            Event::ContactDisco { ver, features } => {
+                let waiting = self.pending_caps.remove(ver).unwrap_or_default();
+                if let Some(features) = features {
+                    for resource in waiting {
+                        self.contact_features.insert(resource, features.clone());
+                    }
                 }
             }
             Event::Iq(account, iq) => match iq.payload.clone() {
                 IqType::Get(el) => {
-                    if let Ok(_disco) = disco::DiscoInfoQuery::try_from(el) {
+                    if let Ok(_disco) = xmpp_disco::DiscoInfoQuery::try_from(el) {
                         let id = iq.id.clone();
                         let disco = self.get_disco();
                         let iq = Iq::from_result(id, Some(disco));
@@ -0,0 +1,196 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::command::{Command, CommandParser};
+use crate::core::{Aparte, AparteAsync, Event, ModTrait};
+use crate::http;
+use crate::mods::messages::MessagesMod;
+use crate::mods::omemo::{self, AESGCM_SCHEME};
+use crate::mods::ui::UIMod;
+use crate::mods::upload::expand_home;
+use crate::word;
+
+/// Maximum duration of a download
+const TIMEOUT: Duration = Duration::from_secs(600);
+/// Maximum size of a downloaded file
+const MAX_SIZE: u64 = 1024 * 1024 * 1024;
+/// Maximum size of an encrypted file, decrypted in memory
+const MAX_ENCRYPTED_SIZE: u64 = 100 * 1024 * 1024;
+
+command_def!(download,
+r#"/download [<index>|<url>]
+
+    index    Position of the file among those shared in the current
+             conversation, 1 being the most recent one (default)
+    url      URL of the file
+
+Description:
+    Download a file shared in the current conversation, or at a given URL,
+    in the download directory. Files shared with an aesgcm:// URL in OMEMO
+    encrypted conversations are decrypted.
+
+Examples:
+    /download
+    /download 2
+    /download https://upload.server.tld/3f2a/cat.png
+"#,
+{
+    target: Option<String>,
+},
+|aparte, _command| {
+    let url = match target {
+        Some(url) if usize::from_str(&url).is_err() => url,
+        Some(index) => attachment(aparte, usize::from_str(&index)?)?,
+        None => attachment(aparte, 1)?,
+    };
+    DownloadMod::download(aparte, url);
+    Ok(())
+});
+
+/// URL of a file shared in the current conversation, counting from the most recent one
+fn attachment(aparte: &Aparte, index: usize) -> Result<String> {
+    let (account, conversation) = aparte.get_mod::<UIMod>().current_conversation()?;
+    let jid = conversation.get_jid();
+    let attachments = aparte.get_mod::<MessagesMod>().attachments(&account, jid);
+    match index
+        .checked_sub(1)
+        .and_then(|index| attachments.get(index))
+    {
+        Some(url) => Ok(url.clone()),
+        None if attachments.is_empty() => Err(anyhow!("No file shared with {jid}")),
+        None => Err(anyhow!(
+            "Invalid index {index}, {} files shared with {jid}",
+            attachments.len()
+        )),
+    }
+}
+
+/// Path in the directory not overwriting an existing file
+fn unique_path(directory: &Path, filename: &str) -> PathBuf {
+    let path = directory.join(filename);
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let extension = path
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+
+    let mut candidate = path.clone();
+    let mut suffix = 1;
+    while candidate.exists() {
+        candidate = directory.join(format!("{stem}-{suffix}{extension}"));
+        suffix += 1;
+    }
+    candidate
+}
+
+/// Download of shared files
+pub struct DownloadMod {}
+
+impl DownloadMod {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    /// Configured download directory, the user one by default
+    fn directory(aparte: &Aparte) -> PathBuf {
+        match &aparte.config.download_dir {
+            Some(directory) => expand_home(directory),
+            None => dirs::download_dir()
+                .or_else(dirs::home_dir)
+                .unwrap_or_else(|| PathBuf::from(".")),
+        }
+    }
+
+    fn download(aparte: &Aparte, url: String) {
+        let directory = Self::directory(aparte);
+        Aparte::spawn({
+            let mut aparte = aparte.proxy();
+            async move {
+                match Self::fetch(&mut aparte, &url, &directory).await {
+                    Ok(path) => crate::info!(aparte, "{} downloaded to {}", url, path.display()),
+                    Err(err) => crate::error!(aparte, err, "Cannot download {}", url),
+                }
+            }
+        });
+    }
+
+    /// Download the file, decrypting it if needed, and return where it is saved
+    async fn fetch(aparte: &mut AparteAsync, url: &str, directory: &Path) -> Result<PathBuf> {
+        let (location, fragment) = match url {
+            url if url.starts_with(AESGCM_SCHEME) => {
+                let (location, fragment) = omemo::parse_aesgcm_url(url)?;
+                (location, Some(fragment))
+            }
+            url if url.starts_with("https://") || url.starts_with("http://") => {
+                (url.to_string(), None)
+            }
+            url => anyhow::bail!("Unsupported URL {url}"),
+        };
+
+        crate::info!(aparte, "Downloading {}", location);
+        tokio::fs::create_dir_all(directory)
+            .await
+            .with_context(|| format!("Cannot create {}", directory.display()))?;
+        let filename = word::url_file_name(&location).unwrap_or("download");
+        let path = unique_path(directory, filename);
+
+        let downloaded = tokio::time::timeout(TIMEOUT, async {
+            match fragment {
+                Some(fragment) => {
+                    let mut data = Vec::new();
+                    http::download(&location, &mut data, MAX_ENCRYPTED_SIZE)
+                        .await?
+                        .error_for_status()?;
+                    let data = omemo::decrypt_media(&data, &fragment)?;
+                    tokio::fs::write(&path, data)
+                        .await
+                        .with_context(|| format!("Cannot write {}", path.display()))
+                }
+                None => {
+                    // Written as it is received, the file may not fit in memory
+                    let mut file = tokio::fs::File::create(&path)
+                        .await
+                        .with_context(|| format!("Cannot write {}", path.display()))?;
+                    http::download(&location, &mut file, MAX_SIZE)
+                        .await?
+                        .error_for_status()?;
+                    Ok(())
+                }
+            }
+        })
+        .await
+        .map_err(|_| anyhow!("Download timed out"))
+        .and_then(|downloaded| downloaded);
+
+        if downloaded.is_err() {
+            // Don't leave a partial file behind
+            let _ = tokio::fs::remove_file(&path).await;
+        }
+        downloaded.map(|_| path)
+    }
+}
+
+impl ModTrait for DownloadMod {
+    fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
+        aparte.add_command(download::new());
+        Ok(())
+    }
+
+    fn on_event(&mut self, _aparte: &mut Aparte, _event: &Event) {}
+}
+
+impl fmt::Display for DownloadMod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Shared files download")
+    }
+}
@@ -0,0 +1,522 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::iter::Peekable;
+use std::str::Chars;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use xmpp_parsers::Element;
+
+use crate::account::Account;
+use crate::core::{Aparte, Event, ModTrait};
+use crate::http;
+
+/// XRD document served as host-meta
+const NS_XRD: &str = "http://docs.oasis-open.org/ns/xri/xrd-1.0";
+const REL_WEBSOCKET: &str = "urn:xmpp:alt-connections:websocket";
+const REL_BOSH: &str = "urn:xmpp:alt-connections:xbosh";
+
+/// Delay after which host-meta is considered unavailable
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// Deepest nesting of arrays and objects accepted in host-meta.json
+const MAX_JSON_DEPTH: usize = 32;
+
+/// Connection endpoint announced by a server besides its TCP one
+#[derive(Debug, Clone, PartialEq)]
+pub enum AltConnection {
+    WebSocket(String),
+    Bosh(String),
+}
+
+impl AltConnection {
+    /// Host serving the endpoint
+    pub fn host(&self) -> Option<&str> {
+        let url = match self {
+            AltConnection::WebSocket(url) | AltConnection::Bosh(url) => url,
+        };
+        let (_, rest) = url.split_once("://")?;
+        let authority = rest.split(|c| c == '/' || c == '?' || c == '#').next()?;
+        let host = match authority.rsplit_once(':') {
+            Some((host, port)) if !port.contains(']') => host,
+            _ => authority,
+        };
+        Some(host.trim_start_matches('[').trim_end_matches(']')).filter(|host| !host.is_empty())
+    }
+}
+
+impl fmt::Display for AltConnection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AltConnection::WebSocket(url) => write!(f, "WebSocket {url}"),
+            AltConnection::Bosh(url) => write!(f, "BOSH {url}"),
+        }
+    }
+}
+
+/// Value of a JSON document, numbers and literals being kept as written
+#[derive(Debug, PartialEq)]
+enum Json {
+    Scalar(String),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(members) => members
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+}
+
+/// Just enough of a JSON parser to read host-meta.json
+struct JsonParser<'a> {
+    chars: Peekable<Chars<'a>>,
+    /// Arrays and objects being parsed
+    depth: usize,
+}
+
+impl<'a> JsonParser<'a> {
+    fn new(json: &'a str) -> Self {
+        Self {
+            chars: json.chars().peekable(),
+            depth: 0,
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.chars.peek(), Some(c) if c.is_whitespace()) {
+            self.chars.next();
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<()> {
+        self.skip_whitespace();
+        match self.chars.next() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => bail!("Expected {expected} in JSON, got {c}"),
+            None => bail!("Expected {expected} in JSON, got end of document"),
+        }
+    }
+
+    fn value(&mut self) -> Result<Json> {
+        self.skip_whitespace();
+        match self.chars.peek() {
+            Some('{') => self.nested(Self::object),
+            Some('[') => self.nested(Self::array),
+            Some('"') => Ok(Json::String(self.string()?)),
+            Some(_) => {
+                let mut scalar = String::new();
+                while let Some(c) = self
+                    .chars
+                    .peek()
+                    .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '+' | '.'))
+                {
+                    scalar.push(*c);
+                    self.chars.next();
+                }
+                match scalar.is_empty() {
+                    true => bail!("Invalid value in JSON"),
+                    false => Ok(Json::Scalar(scalar)),
+                }
+            }
+            None => bail!("Unexpected end of JSON document"),
+        }
+    }
+
+    /// Parse an array or an object, refusing documents nested deep enough to overflow the stack
+    fn nested(&mut self, parse: fn(&mut Self) -> Result<Json>) -> Result<Json> {
+        if self.depth >= MAX_JSON_DEPTH {
+            bail!("JSON document nested too deeply");
+        }
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn object(&mut self) -> Result<Json> {
+        self.expect('{')?;
+        let mut members = Vec::new();
+        self.skip_whitespace();
+        if self.chars.peek() == Some(&'}') {
+            self.chars.next();
+            return Ok(Json::Object(members));
+        }
+        loop {
+            self.skip_whitespace();
+            let name = self.string()?;
+            self.expect(':')?;
+            members.push((name, self.value()?));
+            self.skip_whitespace();
+            match self.chars.next() {
+                Some(',') => {}
+                Some('}') => return Ok(Json::Object(members)),
+                _ => bail!("Expected , or }} after JSON member"),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Json> {
+        self.expect('[')?;
+        let mut items = Vec::new();
+        self.skip_whitespace();
+        if self.chars.peek() == Some(&']') {
+            self.chars.next();
+            return Ok(Json::Array(items));
+        }
+        loop {
+            items.push(self.value()?);
+            self.skip_whitespace();
+            match self.chars.next() {
+                Some(',') => {}
+                Some(']') => return Ok(Json::Array(items)),
+                _ => bail!("Expected , or ] after JSON item"),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String> {
+        self.expect('"')?;
+        let mut string = String::new();
+        loop {
+            match self.chars.next() {
+                Some('"') => return Ok(string),
+                Some('\\') => match self.chars.next() {
+                    Some('n') => string.push('\n'),
+                    Some('t') => string.push('\t'),
+                    Some('r') => string.push('\r'),
+                    Some('b') => string.push('\u{8}'),
+                    Some('f') => string.push('\u{c}'),
+                    Some('u') => {
+                        let code: String = (0..4).filter_map(|_| self.chars.next()).collect();
+                        let code = u32::from_str_radix(&code, 16)?;
+                        string.push(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER));
+                    }
+                    // Quote, backslash and slash
+                    Some(c) => string.push(c),
+                    None => bail!("Unterminated string in JSON"),
+                },
+                Some(c) => string.push(c),
+                None => bail!("Unterminated string in JSON"),
+            }
+        }
+    }
+}
+
+/// XEP-0156: Discovering Alternative XMPP Connection Methods
+///
+/// When a connection cannot be established, the host-meta of the server is fetched to tell the
+/// user which other endpoints it offers. WebSocket and BOSH transports are not implemented, only
+/// TCP retries are done: they alternate between the usual server and the hosts of these
+/// endpoints, on the XMPP port.
+pub struct HostMetaMod {
+    /// Accounts trying to connect and not online yet
+    connecting: HashSet<Account>,
+    /// Domains already looked up since startup
+    discovered: HashSet<String>,
+    /// Alternative connection methods announced by each domain
+    endpoints: HashMap<String, Vec<AltConnection>>,
+}
+
+impl HostMetaMod {
+    pub fn new() -> Self {
+        Self {
+            connecting: HashSet::new(),
+            discovered: HashSet::new(),
+            endpoints: HashMap::new(),
+        }
+    }
+
+    /// Alternative connection methods announced by the host-meta of a domain
+    pub async fn discover(domain: &str) -> Result<Vec<AltConnection>> {
+        match Self::fetch(&format!("https://{domain}/.well-known/host-meta")).await {
+            Ok(connections) => Ok(connections),
+            Err(err) => {
+                log::debug!("Cannot use host-meta of {domain}: {err}");
+                Self::fetch(&format!("https://{domain}/.well-known/host-meta.json")).await
+            }
+        }
+    }
+
+    async fn fetch(url: &str) -> Result<Vec<AltConnection>> {
+        let host_meta = tokio::time::timeout(TIMEOUT, http::get(url))
+            .await??
+            .error_for_status()?
+            .text()?;
+        Self::parse(&host_meta)
+    }
+
+    /// Alternative connection methods of a host-meta document, either XRD or JSON
+    fn parse(host_meta: &str) -> Result<Vec<AltConnection>> {
+        let links = match host_meta.trim_start().starts_with('{') {
+            true => Self::json_links(host_meta)?,
+            false => Self::xrd_links(host_meta)?,
+        };
+        Ok(links
+            .into_iter()
+            .filter_map(|(rel, href)| match rel.as_str() {
+                REL_WEBSOCKET => Some(AltConnection::WebSocket(href)),
+                REL_BOSH => Some(AltConnection::Bosh(href)),
+                _ => None,
+            })
+            .collect())
+    }
+
+    /// Relation and target of links of a XRD document
+    fn xrd_links(host_meta: &str) -> Result<Vec<(String, String)>> {
+        let xrd: Element = host_meta.parse()?;
+        if !xrd.is("XRD", NS_XRD) {
+            bail!("host-meta is not an XRD document");
+        }
+        Ok(xrd
+            .children()
+            .filter(|child| child.is("Link", NS_XRD))
+            .filter_map(|link| match (link.attr("rel"), link.attr("href")) {
+                (Some(rel), Some(href)) => Some((rel.to_string(), href.to_string())),
+                _ => None,
+            })
+            .collect())
+    }
+
+    /// Relation and target of links of a JRD document
+    fn json_links(host_meta: &str) -> Result<Vec<(String, String)>> {
+        let jrd = JsonParser::new(host_meta).value()?;
+        let links = match jrd.get("links") {
+            Some(Json::Array(links)) => links,
+            _ => bail!("host-meta.json has no links"),
+        };
+        Ok(links
+            .iter()
+            .filter_map(|link| match (link.get("rel"), link.get("href")) {
+                (Some(Json::String(rel)), Some(Json::String(href))) => {
+                    Some((rel.clone(), href.clone()))
+                }
+                _ => None,
+            })
+            .collect())
+    }
+
+    /// Server to try instead of the usual one for this connection attempt, if any
+    pub fn fallback_server(&self, domain: &str, attempt: u32) -> Option<String> {
+        // Retries alternate between the usual server and the hosts of alternative endpoints
+        if attempt % 2 == 0 {
+            return None;
+        }
+        let mut hosts: Vec<&str> = Vec::new();
+        for host in self
+            .endpoints
+            .get(domain)?
+            .iter()
+            .filter_map(AltConnection::host)
+        {
+            if !hosts.contains(&host) {
+                hosts.push(host);
+            }
+        }
+        match hosts.is_empty() {
+            true => None,
+            false => Some(hosts[(attempt as usize / 2) % hosts.len()].to_string()),
+        }
+    }
+
+    /// Look for other ways to reach the server of an account that failed to connect
+    fn failed(&mut self, aparte: &mut Aparte, account: &Account) {
+        if !self.connecting.remove(account) {
+            return;
+        }
+        let domain = account.domain().to_string();
+        if !self.discovered.insert(domain.clone()) {
+            return;
+        }
+
+        Aparte::spawn({
+            let mut aparte = aparte.proxy();
+            async move {
+                match Self::discover(&domain).await {
+                    Ok(connections) if connections.is_empty() => {
+                        log::info!("{domain} announces no alternative connection method")
+                    }
+                    Ok(connections) => {
+                        let announced = connections
+                            .iter()
+                            .map(ToString::to_string)
+                            .collect::<Vec<_>>()
+                            .join(", ");
+                        aparte.log(format!(
+                            "{domain} can also be reached with {announced}, \
+                            their hosts are tried when reconnecting"
+                        ));
+                        aparte.schedule(Event::AltConnections(domain, connections));
+                    }
+                    Err(err) => log::info!("Cannot fetch host-meta of {domain}: {err}"),
+                }
+            }
+        });
+    }
+}
+
+impl ModTrait for HostMetaMod {
+    fn init(&mut self, _aparte: &mut Aparte) -> Result<(), ()> {
+        Ok(())
+    }
+
+    fn on_event(&mut self, aparte: &mut Aparte, event: &Event) {
+        match event {
+            Event::Connecting(account, _) => {
+                self.connecting.insert(account.clone());
+            }
+            Event::Connected(account, _) => {
+                self.connecting.remove(account);
+            }
+            Event::ConnectionFailed { account, .. } | Event::Disconnected(account, _) => {
+                self.failed(aparte, account)
+            }
+            Event::AltConnections(domain, connections) => {
+                self.endpoints.insert(domain.clone(), connections.clone());
+            }
+            _ => {}
+        }
+    }
+}
+
+impl fmt::Display for HostMetaMod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "XEP-0156: Discovering Alternative XMPP Connection Methods"
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_xrd() {
+        // Given
+        let host_meta = r#"<?xml version='1.0' encoding='utf-8'?>
+<XRD xmlns='http://docs.oasis-open.org/ns/xri/xrd-1.0'>
+  <Link rel="urn:xmpp:alt-connections:xbosh" href="https://web.example.com:5280/bosh" />
+  <Link rel="urn:xmpp:alt-connections:websocket" href="wss://web.example.com:443/ws" />
+  <Link rel="lrdd" href="https://example.com/lrdd?uri={uri}" />
+</XRD>"#;
+
+        // When
+        let connections = HostMetaMod::parse(host_meta).unwrap();
+
+        // Then
+        assert_eq!(
+            connections,
+            vec![
+                AltConnection::Bosh(String::from("https://web.example.com:5280/bosh")),
+                AltConnection::WebSocket(String::from("wss://web.example.com:443/ws")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_xrd_rejects_other_documents() {
+        assert!(HostMetaMod::parse("<html xmlns='http://www.w3.org/1999/xhtml'/>").is_err());
+    }
+
+    #[test]
+    fn test_parse_json() {
+        // Given
+        let host_meta = r#"{
+  "subject": "example.com",
+  "expires": null,
+  "links": [
+    {
+      "rel": "urn:xmpp:alt-connections:xbosh",
+      "href": "https:\/\/web.example.com:5280\/bosh",
+      "ttl": 3600
+    },
+    {
+      "rel": "urn:xmpp:alt-connections:websocket",
+      "href": "wss://web.example.com:443/ws"
+    },
+    { "rel": "lrdd", "titles": { "default": "Caf\u00e9" } }
+  ]
+}"#;
+
+        // When
+        let connections = HostMetaMod::parse(host_meta).unwrap();
+
+        // Then
+        assert_eq!(
+            connections,
+            vec![
+                AltConnection::Bosh(String::from("https://web.example.com:5280/bosh")),
+                AltConnection::WebSocket(String::from("wss://web.example.com:443/ws")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_json_without_links() {
+        assert!(HostMetaMod::parse(r#"{"subject": "example.com"}"#).is_err());
+        assert!(HostMetaMod::parse(r#"{"links": [}"#).is_err());
+    }
+
+    #[test]
+    fn test_parse_json_rejects_deep_nesting() {
+        // Given
+        let host_meta = format!("{{\"links\": {}", "[".repeat(100_000));
+
+        // When
+        let parsed = HostMetaMod::parse(&host_meta);
+
+        // Then
+        assert!(parsed.is_err());
+    }
+
+    #[test]
+    fn test_alt_connection_host() {
+        assert_eq!(
+            AltConnection::WebSocket(String::from("wss://web.example.com:443/ws")).host(),
+            Some("web.example.com")
+        );
+        assert_eq!(
+            AltConnection::Bosh(String::from("https://[2001:db8::1]/bosh")).host(),
+            Some("2001:db8::1")
+        );
+        assert_eq!(AltConnection::Bosh(String::from("bosh")).host(), None);
+    }
+
+    #[test]
+    fn test_fallback_server_alternates_with_usual_server() {
+        // Given
+        let mut host_meta = HostMetaMod::new();
+        host_meta.endpoints.insert(
+            String::from("example.com"),
+            vec![
+                AltConnection::Bosh(String::from("https://a.example.com/bosh")),
+                AltConnection::WebSocket(String::from("wss://a.example.com/ws")),
+                AltConnection::WebSocket(String::from("wss://b.example.com/ws")),
+            ],
+        );
+
+        // Then
+        assert_eq!(host_meta.fallback_server("example.com", 0), None);
+        assert_eq!(
+            host_meta.fallback_server("example.com", 1).as_deref(),
+            Some("a.example.com")
+        );
+        assert_eq!(host_meta.fallback_server("example.com", 2), None);
+        assert_eq!(
+            host_meta.fallback_server("example.com", 3).as_deref(),
+            Some("b.example.com")
+        );
+        assert_eq!(host_meta.fallback_server("other.example", 1), None);
+    }
+}
@@ -0,0 +1,104 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use std::collections::HashSet;
+use std::fmt;
+
+use anyhow::Result;
+use chrono::{DateTime, FixedOffset, Local as LocalTz};
+
+use crate::account::Account;
+use crate::core::{Aparte, Event, ModTrait};
+use crate::mods::csi::CsiMod;
+
+/// XEP-0319: Last User Interaction in Presence
+///
+/// Once nothing is typed for the idle timeout, presence is sent again telling since when the
+/// client is idle, and without it on the next key stroke. Idleness is checked every minute.
+pub struct IdleMod {
+    /// Connected accounts
+    connected: HashSet<Account>,
+    last_activity: DateTime<FixedOffset>,
+    idle: bool,
+}
+
+impl IdleMod {
+    pub fn new() -> Self {
+        Self {
+            connected: HashSet::new(),
+            last_activity: LocalTz::now().into(),
+            idle: false,
+        }
+    }
+
+    fn enabled(aparte: &Aparte) -> bool {
+        aparte.config.idle_presence.unwrap_or(true)
+    }
+
+    /// Last interaction, when the client is idle
+    pub fn idle_since(&self) -> Option<DateTime<FixedOffset>> {
+        match self.idle {
+            true => Some(self.last_activity),
+            false => None,
+        }
+    }
+
+    fn set_idle(&mut self, aparte: &mut Aparte, idle: bool) {
+        if self.idle == idle {
+            return;
+        }
+        self.idle = idle;
+        log::debug!("Client is {}", if idle { "idle" } else { "no longer idle" });
+        let presence = aparte.presence(self.idle_since());
+        for account in self.connected.iter() {
+            aparte.send(account, presence.clone());
+        }
+    }
+
+    fn check(&mut self, aparte: &mut Aparte) {
+        let timeout = match CsiMod::timeout(aparte) {
+            Some(timeout) => timeout,
+            None => return,
+        };
+        let inactive = LocalTz::now().signed_duration_since(self.last_activity);
+        if inactive
+            .to_std()
+            .map_or(false, |inactive| inactive >= timeout)
+        {
+            self.set_idle(aparte, true);
+        }
+    }
+}
+
+impl ModTrait for IdleMod {
+    fn init(&mut self, _aparte: &mut Aparte) -> Result<(), ()> {
+        Ok(())
+    }
+
+    fn on_event(&mut self, aparte: &mut Aparte, event: &Event) {
+        if !Self::enabled(aparte) {
+            return;
+        }
+
+        match event {
+            Event::Connected(account, _) => {
+                self.connected.insert(account.clone());
+            }
+            Event::Disconnected(account, _) => {
+                self.connected.remove(account);
+            }
+            Event::Key(_) => {
+                self.last_activity = LocalTz::now().into();
+                self.set_idle(aparte, false);
+            }
+            Event::Tick => self.check(aparte),
+            _ => {}
+        }
+    }
+}
+
+impl fmt::Display for IdleMod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "XEP-0319: Last User Interaction in Presence")
+    }
+}
@@ -0,0 +1,140 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Context, Result};
+use uuid::Uuid;
+use xmpp_parsers::iq::{Iq, IqType};
+use xmpp_parsers::{Element, Jid};
+
+use crate::account::Account;
+use crate::command::{Command, CommandParser};
+use crate::core::{Aparte, AparteAsync, Event, ModTrait};
+use crate::i18n;
+
+const NS_LAST: &str = "jabber:iq:last";
+
+command_def!(last,
+r#"/last <jid>
+
+    jid    jid of the contact, resource or server to query
+
+Description:
+    Show for how long a contact has been offline, a connected resource of a
+    contact has been idle, or a server has been running.
+
+Examples:
+    /last contact@server.tld
+    /last contact@server.tld/phone
+    /last server.tld
+"#,
+{
+    jid: String,
+},
+|aparte, _command| {
+    let account = aparte.current_account().context("No connection found")?;
+    let jid = Jid::from_str(&jid).with_context(|| format!("Invalid jid {jid}"))?;
+
+    Aparte::spawn({
+        let mut aparte = aparte.proxy();
+        async move {
+            match LastActivityMod::query(&mut aparte, &account, &jid).await {
+                Ok((seconds, status)) => {
+                    aparte.log(LastActivityMod::format(&jid, seconds, status.as_deref()))
+                }
+                Err(err) => crate::error!(aparte, err, "Cannot get last activity of {jid}"),
+            }
+        }
+    });
+    Ok(())
+});
+
+/// XEP-0012: Last Activity
+pub struct LastActivityMod {}
+
+impl LastActivityMod {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    /// Seconds given by the entity and the status it may have left
+    async fn query(
+        aparte: &mut AparteAsync,
+        account: &Account,
+        jid: &Jid,
+    ) -> Result<(u64, Option<String>)> {
+        let iq = Iq {
+            from: None,
+            to: Some(jid.clone()),
+            id: Uuid::new_v4().hyphenated().to_string(),
+            payload: IqType::Get(Element::builder("query", NS_LAST).build()),
+        };
+        let query = match aparte.iq(account, iq).await?.payload {
+            IqType::Result(Some(el)) if el.is("query", NS_LAST) => el,
+            IqType::Result(_) => return Err(anyhow!("invalid response")),
+            IqType::Error(err) => {
+                return Err(anyhow!("{}", i18n::xmpp_err_to_string(&err, vec![]).1))
+            }
+            _ => return Err(anyhow!("invalid response")),
+        };
+        let seconds = query
+            .attr("seconds")
+            .and_then(|seconds| seconds.parse::<u64>().ok())
+            .context("invalid response")?;
+        let status = Some(query.text().trim().to_string()).filter(|status| !status.is_empty());
+        Ok((seconds, status))
+    }
+
+    /// Human readable duration keeping its two most significant units, e.g. `3d 4h`
+    fn format_duration(seconds: u64) -> String {
+        let units = [(86400, "d"), (3600, "h"), (60, "m"), (1, "s")];
+        let parts = units
+            .iter()
+            .scan(seconds, |remaining, (unit, suffix)| {
+                let count = *remaining / unit;
+                *remaining %= unit;
+                Some((count, suffix))
+            })
+            .skip_while(|(count, _)| *count == 0)
+            .take(2)
+            .filter(|(count, _)| *count > 0)
+            .map(|(count, suffix)| format!("{count}{suffix}"))
+            .collect::<Vec<_>>();
+        match parts.is_empty() {
+            true => String::from("0s"),
+            false => parts.join(" "),
+        }
+    }
+
+    /// Meaning of the seconds depends on the queried entity
+    fn format(jid: &Jid, seconds: u64, status: Option<&str>) -> String {
+        let duration = Self::format_duration(seconds);
+        let mut output = match jid {
+            Jid::Full(_) => format!("{jid} has been idle for {duration}"),
+            Jid::Bare(bare) if bare.node().is_none() => format!("{jid} has been up for {duration}"),
+            Jid::Bare(_) if seconds == 0 => format!("{jid} is online"),
+            Jid::Bare(_) => format!("{jid} has been offline for {duration}"),
+        };
+        if let Some(status) = status {
+            output.push_str(&format!(" ({status})"));
+        }
+        output
+    }
+}
+
+impl ModTrait for LastActivityMod {
+    fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
+        aparte.add_command(last::new());
+        Ok(())
+    }
+
+    fn on_event(&mut self, _aparte: &mut Aparte, _event: &Event) {}
+}
+
+impl fmt::Display for LastActivityMod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "XEP-0012: Last Activity")
+    }
+}
@@ -0,0 +1,245 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use xmpp_parsers::Jid;
+
+use crate::account::Account;
+use crate::command::{Command, CommandParser};
+use crate::contact::Location;
+use crate::core::{Aparte, Event, ModTrait};
+use crate::mods::disco;
+use crate::mods::pubsub::{PersonalEvent, PubSubMod};
+
+const NS_GEOLOC: &str = "http://jabber.org/protocol/geoloc";
+
+/// Delay between two runs of `location_command`
+const POLL_INTERVAL: Duration = Duration::from_secs(300);
+
+command_def!(location_set,
+r#"/location set <lat> <lon> [<text>]
+
+    lat     latitude in decimal degrees
+    lon     longitude in decimal degrees
+    text    description of the place
+
+Description:
+    Share your location with contacts of accounts having share_location
+    enabled in their configuration.
+
+Examples:
+    /location set 48.8584 2.2945
+    /location set 48.8584 2.2945 "Eiffel Tower"
+"#,
+{
+    lat: String,
+    lon: String,
+    text: Option<String>,
+},
+|aparte, _command| {
+    if !LocationMod::any_opted_in(aparte) {
+        anyhow::bail!("Location sharing is not enabled for any account, see share_location");
+    }
+    let location = Location {
+        coordinates: Some(LocationMod::parse_coordinates(&lat, &lon)?),
+        text,
+        ..Default::default()
+    };
+    crate::info!(aparte, "Sharing location {location}");
+    aparte.schedule(Event::ShareLocation(Some(location)));
+    Ok(())
+});
+
+command_def!(
+    location_clear,
+    r#"/location clear
+
+Description:
+    Stop sharing your location.
+"#,
+    {},
+    |aparte, _command| {
+        crate::info!(aparte, "Location no longer shared");
+        aparte.schedule(Event::ShareLocation(None));
+        Ok(())
+    }
+);
+
+command_def!(location,
+r#"/location set|clear"#,
+{
+    action: Command = {
+        children: {
+            "set": location_set,
+            "clear": location_clear,
+        }
+    },
+});
+
+impl PersonalEvent for Location {
+    const NS: &'static str = NS_GEOLOC;
+    const NAME: &'static str = "geoloc";
+    const DESCRIPTION: &'static str = "location";
+
+    fn fields(&self) -> Vec<(&'static str, Option<String>)> {
+        let (lat, lon) = match self.coordinates {
+            Some((lat, lon)) => (Some(lat), Some(lon)),
+            None => (None, None),
+        };
+        vec![
+            ("country", self.country.clone()),
+            ("lat", lat.map(|lat| lat.to_string())),
+            ("locality", self.locality.clone()),
+            ("lon", lon.map(|lon| lon.to_string())),
+            ("text", self.text.clone()),
+        ]
+    }
+
+    fn from_fields(field: &dyn Fn(&str) -> Option<String>) -> Self {
+        let coordinates = match (field("lat"), field("lon")) {
+            (Some(lat), Some(lon)) => LocationMod::parse_coordinates(&lat, &lon).ok(),
+            _ => None,
+        };
+        Location {
+            coordinates,
+            locality: field("locality"),
+            country: field("country"),
+            text: field("text"),
+        }
+    }
+}
+
+/// XEP-0080: User Location
+///
+/// Nothing is published unless `share_location` is enabled for the account. The location is either
+/// set with /location or read periodically from the output of `location_command`.
+pub struct LocationMod {
+    /// Location currently shared, published to accounts once they get online
+    sharing: Option<Location>,
+}
+
+impl LocationMod {
+    pub fn new() -> Self {
+        Self { sharing: None }
+    }
+
+    /// Whether the user accepted to share their location from this account
+    fn opted_in(aparte: &Aparte, account: &Account) -> bool {
+        aparte.config.accounts.values().any(|info| {
+            info.share_location
+                && Jid::from_str(&info.jid).map(|jid| jid.to_bare()).ok() == Some(account.to_bare())
+        })
+    }
+
+    fn any_opted_in(aparte: &Aparte) -> bool {
+        aparte
+            .config
+            .accounts
+            .values()
+            .any(|info| info.share_location)
+    }
+
+    fn parse_coordinates(lat: &str, lon: &str) -> Result<(f64, f64)> {
+        let lat = lat
+            .parse::<f64>()
+            .ok()
+            .filter(|lat| (-90.0..=90.0).contains(lat))
+            .with_context(|| format!("Invalid latitude {lat}"))?;
+        let lon = lon
+            .parse::<f64>()
+            .ok()
+            .filter(|lon| (-180.0..=180.0).contains(lon))
+            .with_context(|| format!("Invalid longitude {lon}"))?;
+        Ok((lat, lon))
+    }
+
+    /// Location printed by `location_command` as `<lat> <lon> [<text>]`, none when empty
+    fn parse_output(output: &str) -> Result<Option<Location>> {
+        let line = output.lines().next().unwrap_or_default().trim();
+        if line.is_empty() {
+            return Ok(None);
+        }
+        let mut parts = line.splitn(3, char::is_whitespace);
+        let (lat, lon) = match (parts.next(), parts.next()) {
+            (Some(lat), Some(lon)) => (lat, lon),
+            _ => anyhow::bail!("Expected latitude and longitude, got {line}"),
+        };
+        Ok(Some(Location {
+            coordinates: Some(Self::parse_coordinates(lat, lon)?),
+            text: parts
+                .next()
+                .map(|text| text.trim().to_string())
+                .filter(|text| !text.is_empty()),
+            ..Default::default()
+        }))
+    }
+
+    /// Location printed by a run of `location_command`
+    async fn run(command: String) -> Result<Option<Location>> {
+        let output = tokio::process::Command::new(&command)
+            .output()
+            .await
+            .with_context(|| format!("Cannot run location command {command}"))?;
+        Self::parse_output(&String::from_utf8_lossy(&output.stdout))
+    }
+}
+
+impl ModTrait for LocationMod {
+    fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
+        {
+            let mut disco = aparte.get_mod_mut::<disco::DiscoMod>();
+            disco.add_feature(format!("{}+notify", NS_GEOLOC));
+        }
+        aparte.add_command(location::new());
+        Ok(())
+    }
+
+    fn on_event(&mut self, aparte: &mut Aparte, event: &Event) {
+        match event {
+            Event::Start if Self::any_opted_in(aparte) => {
+                if let Some(command) = aparte.config.location_command.clone() {
+                    PubSubMod::watch_personal(
+                        aparte,
+                        POLL_INTERVAL,
+                        move || Self::run(command.clone()),
+                        Event::ShareLocation,
+                    );
+                }
+            }
+            Event::ShareLocation(location) => {
+                self.sharing = location.clone();
+                for account in aparte.connected_accounts() {
+                    if Self::opted_in(aparte, &account) {
+                        PubSubMod::publish_personal(aparte, &account, location.as_ref());
+                    }
+                }
+            }
+            Event::Connected(account, _) => {
+                if let Some(location) = &self.sharing {
+                    if Self::opted_in(aparte, account) {
+                        PubSubMod::publish_personal(aparte, account, Some(location));
+                    }
+                }
+            }
+            event => {
+                if let Some((account, jid, location)) = PubSubMod::personal_event(event) {
+                    aparte.schedule(Event::Location {
+                        account,
+                        jid,
+                        location,
+                    });
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for LocationMod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "XEP-0080: User Location")
+    }
+}
@@ -1,22 +1,92 @@
 /* This Source Code Form is subject to the terms of the Mozilla Public
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use anyhow::{anyhow, Context, Result};
 use chrono::{DateTime, FixedOffset};
 use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::fmt;
+use std::str::FromStr;
 use uuid::Uuid;
-use xmpp_parsers::data_forms::{DataForm, DataFormType, Field, FieldType};
+use xmpp_parsers::data_forms::{self, DataForm, DataFormType, Field, FieldType};
 use xmpp_parsers::delay::Delay;
 use xmpp_parsers::iq::{Iq, IqType};
 use xmpp_parsers::mam;
 use xmpp_parsers::message::Message as XmppParsersMessage;
 use xmpp_parsers::ns;
 use xmpp_parsers::rsm::SetQuery;
-use xmpp_parsers::{BareJid, Jid};
+use xmpp_parsers::{BareJid, Element, Jid};
 
 use crate::account::Account;
-use crate::core::{Aparte, Event, ModTrait};
+use crate::command::{Command, CommandParser};
+use crate::core::{Aparte, AparteAsync, Event, ModTrait};
+use crate::i18n;
+
+/// Number of messages retrieved when opening a window or paging up, unless configured
+const DEFAULT_HISTORY_PRELOAD: usize = 100;
+
+/// Number of messages retrieved per request when importing a whole archive
+const IMPORT_PAGE_SIZE: usize = 250;
+
+/// Messages archived by default, as named by archiving preferences, with their label
+const PREFS_DEFAULTS: [(&str, &str); 3] = [
+    ("always", "All messages"),
+    ("roster", "Messages of contacts"),
+    ("never", "No message"),
+];
+
+command_def!(history_import,
+r#"/history import <jid>
+
+    jid    jid of the contact
+
+Description:
+    Retrieve in background every message exchanged with the contact and
+    archived by the server. Progress is reported after each page.
+
+Examples:
+    /history import contact@server.tld
+"#,
+{
+    jid: String,
+},
+|aparte, _command| {
+    let account = aparte.current_account().context("No connection found")?;
+    let contact = BareJid::from_str(&jid).with_context(|| format!("Invalid jid {jid}"))?;
+    MamMod::import(aparte, account, contact);
+    Ok(())
+});
+
+command_def!(
+    history_prefs,
+    r#"/history prefs
+
+Description:
+    Edit the archiving preferences of the current account in the form window:
+    which messages the server archives by default, and contacts whose messages
+    are always or never archived.
+
+Examples:
+    /history prefs
+"#,
+    {},
+    |aparte, _command| {
+        let account = aparte.current_account().context("No connection found")?;
+        MamMod::edit_prefs(aparte, account);
+        Ok(())
+    }
+);
+
+command_def!(history,
+r#"/history import|prefs"#,
+{
+    action: Command = {
+        children: {
+            "import": history_import,
+            "prefs": history_prefs,
+        }
+    },
+});
 
 struct Query {
     jid: BareJid,
@@ -102,6 +172,18 @@ pub struct MamMod {
 
     /// Mapping between iq ids and query ids
     iq2id: HashMap<String, String>,
+
+    /// Number of messages retrieved per history request
+    preload: usize,
+
+    /// Contact whose archive is imported, indexed by query id of each page
+    imports: HashMap<String, (Account, BareJid)>,
+
+    /// Number of messages imported so far for each contact
+    imported: HashMap<(Account, BareJid), usize>,
+
+    /// Accounts whose archiving preferences are edited, indexed by form id
+    prefs: HashMap<String, Account>,
 }
 
 impl MamMod {
@@ -109,9 +191,250 @@ impl MamMod {
         Self {
             queries: HashMap::new(),
             iq2id: HashMap::new(),
+            preload: DEFAULT_HISTORY_PRELOAD,
+            imports: HashMap::new(),
+            imported: HashMap::new(),
+            prefs: HashMap::new(),
+        }
+    }
+
+    /// Tell whether the server archived messages with a newly added contact
+    fn probe(aparte: &mut Aparte, account: &Account, contact: &BareJid) {
+        let query = Query {
+            jid: account.to_bare(),
+            with: Some(contact.clone()),
+            from: None,
+            count: 1,
+        };
+        let (_, iq) = query.start();
+        Aparte::spawn({
+            let mut aparte = aparte.proxy();
+            let account = account.clone();
+            let contact = contact.clone();
+            async move {
+                let response = match aparte.iq(&account, iq).await {
+                    Ok(response) => response,
+                    Err(err) => {
+                        log::warn!("Cannot probe archive of {contact}: {err}");
+                        return;
+                    }
+                };
+                if let IqType::Result(Some(payload)) = response.payload {
+                    if let Ok(fin) = mam::Fin::try_from(payload) {
+                        if fin.set.first.is_some() {
+                            crate::info!(
+                                aparte,
+                                "{contact} has archived messages, import them with /history import {contact}"
+                            );
+                        }
+                    }
+                }
+            }
+        });
+    }
+
+    fn import(aparte: &mut Aparte, account: Account, contact: BareJid) {
+        crate::info!(aparte, "Importing archived messages of {contact}");
+        Aparte::spawn({
+            let mut aparte = aparte.proxy();
+            async move {
+                if let Err(err) = Self::import_pages(&mut aparte, &account, &contact).await {
+                    crate::error!(aparte, err, "Cannot import archived messages of {contact}");
+                }
+                aparte.schedule(Event::HistoryImported {
+                    account,
+                    contact,
+                    complete: true,
+                });
+            }
+        });
+    }
+
+    /// Retrieve the whole archive with the contact, from the most recent page
+    async fn import_pages(
+        aparte: &mut AparteAsync,
+        account: &Account,
+        contact: &BareJid,
+    ) -> Result<()> {
+        let query = Query {
+            jid: account.to_bare(),
+            with: Some(contact.clone()),
+            from: None,
+            count: IMPORT_PAGE_SIZE,
+        };
+        let (mut queryid, mut iq) = query.start();
+        loop {
+            // Register the page before sending it so that its results are imported
+            aparte.schedule(Event::HistoryImport {
+                account: account.clone(),
+                contact: contact.clone(),
+                queryid,
+            });
+            let response = aparte.iq(account, iq).await?;
+            let fin = match response.payload {
+                IqType::Result(Some(payload)) => mam::Fin::try_from(payload)?,
+                _ => anyhow::bail!("Unexpected MAM response"),
+            };
+
+            match fin.set.first {
+                Some(first) if fin.complete != mam::Complete::True => {
+                    aparte.schedule(Event::HistoryImported {
+                        account: account.clone(),
+                        contact: contact.clone(),
+                        complete: false,
+                    });
+                    (queryid, iq) = query.cont(first);
+                }
+                _ => return Ok(()),
+            }
         }
     }
 
+    fn edit_prefs(aparte: &mut Aparte, account: Account) {
+        let id = format!("mam-prefs/{account}");
+        aparte
+            .get_mod_mut::<MamMod>()
+            .prefs
+            .insert(id.clone(), account.clone());
+        Aparte::spawn({
+            let mut aparte = aparte.proxy();
+            async move {
+                match Self::prefs_query(&mut aparte, &account, None).await {
+                    Ok(prefs) => aparte.schedule(Event::ShowForm {
+                        id,
+                        form: Self::prefs_form(&prefs),
+                    }),
+                    Err(err) => crate::error!(aparte, err, "Cannot get archiving preferences"),
+                }
+            }
+        });
+    }
+
+    fn save_prefs(aparte: &mut Aparte, account: Account, form: DataForm) {
+        let prefs = Self::prefs_element(&form);
+        Aparte::spawn({
+            let mut aparte = aparte.proxy();
+            async move {
+                match Self::prefs_query(&mut aparte, &account, Some(prefs)).await {
+                    Ok(_) => crate::info!(aparte, "Archiving preferences of {account} saved"),
+                    Err(err) => crate::error!(aparte, err, "Cannot save archiving preferences"),
+                }
+            }
+        });
+    }
+
+    /// Get the archiving preferences of the account, or set them when given
+    async fn prefs_query(
+        aparte: &mut AparteAsync,
+        account: &Account,
+        prefs: Option<Element>,
+    ) -> Result<Element> {
+        let payload = match prefs {
+            Some(prefs) => IqType::Set(prefs),
+            None => IqType::Get(Element::builder("prefs", ns::MAM).build()),
+        };
+        let iq = Iq {
+            from: None,
+            to: None,
+            id: Uuid::new_v4().hyphenated().to_string(),
+            payload,
+        };
+        match aparte.iq(account, iq).await?.payload {
+            IqType::Result(Some(el)) if el.is("prefs", ns::MAM) => Ok(el),
+            IqType::Error(err) => Err(anyhow!("{}", i18n::xmpp_err_to_string(&err, vec![]).1)),
+            _ => Err(anyhow!("invalid response")),
+        }
+    }
+
+    /// Jids of the always or never list of archiving preferences
+    fn prefs_jids(prefs: &Element, list: &str) -> Vec<String> {
+        prefs
+            .get_child(list, ns::MAM)
+            .map(|list| {
+                list.children()
+                    .filter(|jid| jid.is("jid", ns::MAM))
+                    .map(Element::text)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Archiving preferences as a form to edit them
+    fn prefs_form(prefs: &Element) -> DataForm {
+        let field = |var: &str, type_, label: &str, values| Field {
+            var: var.to_string(),
+            type_,
+            label: Some(label.to_string()),
+            required: false,
+            options: vec![],
+            values,
+            media: vec![],
+        };
+        let default = prefs.attr("default").unwrap_or("roster").to_string();
+        let default = Field {
+            required: true,
+            options: PREFS_DEFAULTS
+                .iter()
+                .map(|(value, label)| data_forms::Option {
+                    label: Some(label.to_string()),
+                    value: value.to_string(),
+                })
+                .collect(),
+            ..field(
+                "default",
+                FieldType::ListSingle,
+                "Archived by default",
+                vec![default],
+            )
+        };
+
+        DataForm {
+            type_: DataFormType::Form,
+            form_type: None,
+            title: Some(String::from("Archiving preferences")),
+            instructions: None,
+            fields: vec![
+                default,
+                field(
+                    "always",
+                    FieldType::JidMulti,
+                    "Always archived",
+                    Self::prefs_jids(prefs, "always"),
+                ),
+                field(
+                    "never",
+                    FieldType::JidMulti,
+                    "Never archived",
+                    Self::prefs_jids(prefs, "never"),
+                ),
+            ],
+        }
+    }
+
+    /// Archiving preferences filled in a form
+    fn prefs_element(form: &DataForm) -> Element {
+        let values = |var: &str| {
+            form.fields
+                .iter()
+                .find(|field| field.var == var)
+                .map(|field| field.values.clone())
+                .unwrap_or_default()
+        };
+        let default = values("default")
+            .into_iter()
+            .next()
+            .unwrap_or_else(|| String::from("roster"));
+        let mut prefs = Element::builder("prefs", ns::MAM).attr("default", default);
+        for list in ["always", "never"] {
+            let mut jids = Element::builder(list, ns::MAM);
+            for jid in values(list) {
+                jids = jids.append(Element::builder("jid", ns::MAM).append(jid).build());
+            }
+            prefs = prefs.append(jids.build());
+        }
+        prefs.build()
+    }
+
     fn query(&mut self, aparte: &mut Aparte, account: &Account, query: Query) {
         let (queryid, iq) = query.start();
         self.queries.insert(queryid.clone(), query);
@@ -120,6 +443,24 @@ impl MamMod {
     }
 
     fn handle_result(&mut self, aparte: &mut Aparte, account: &Account, result: mam::Result_) {
+        if let Some(import) = result
+            .queryid
+            .as_ref()
+            .and_then(|id| self.imports.get(&id.0))
+        {
+            if let (Some(delay), Some(message)) = (result.forwarded.delay, result.forwarded.stanza)
+            {
+                *self.imported.entry(import.clone()).or_insert(0) += 1;
+                aparte.schedule(Event::RawMessage {
+                    account: account.clone(),
+                    message,
+                    delay: Some(delay),
+                    archive: true,
+                });
+            }
+            return;
+        }
+
         if let Some(id) = &result.queryid {
             if let Some(query) = self.queries.get_mut(&id.0) {
                 query.count -= 1;
@@ -139,8 +480,9 @@ impl MamMod {
     }
 
     fn handle_fin(&mut self, aparte: &mut Aparte, account: &Account, query: Query, fin: mam::Fin) {
-        if fin.complete == mam::Complete::False {
-            if let Some(start) = fin.set.first {
+        let complete = fin.complete == mam::Complete::True;
+        match fin.set.first {
+            Some(start) if !complete && query.count > 0 => {
                 log::info!(
                     "Continuing MAM retrieval for {} with {:?} from {:?}",
                     query.jid,
@@ -152,12 +494,23 @@ impl MamMod {
                 self.iq2id.insert(iq.id.clone(), queryid);
                 aparte.send(account, iq);
             }
+            start => {
+                aparte.schedule(Event::HistoryLoaded {
+                    account: account.clone(),
+                    conversation: query.with.unwrap_or(query.jid),
+                    complete: complete || start.is_none(),
+                });
+            }
         }
     }
 }
 
 impl ModTrait for MamMod {
-    fn init(&mut self, _aparte: &mut Aparte) -> Result<(), ()> {
+    fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
+        aparte.add_command(history::new());
+        if let Some(preload) = aparte.config.history_preload {
+            self.preload = preload;
+        }
         Ok(())
     }
 
@@ -193,14 +546,17 @@ impl ModTrait for MamMod {
 
     fn on_event(&mut self, aparte: &mut Aparte, event: &Event) {
         match event {
+            // Bare joins are scheduled again once the nickname is resolved
             Event::Join {
-                account, channel, ..
+                account,
+                channel: Jid::Full(channel),
+                ..
             } => {
                 let query = Query {
                     jid: channel.to_bare(),
                     with: None,
                     from: None,
-                    count: 100,
+                    count: self.preload,
                 };
                 self.query(aparte, account, query);
             }
@@ -209,7 +565,7 @@ impl ModTrait for MamMod {
                     jid: account.to_bare(),
                     with: Some(contact.clone()),
                     from: None,
-                    count: 100,
+                    count: self.preload,
                 };
                 self.query(aparte, account, query);
             }
@@ -218,7 +574,7 @@ impl ModTrait for MamMod {
                     jid: jid.clone(),
                     with: None,
                     from: *from,
-                    count: 100,
+                    count: self.preload,
                 };
                 self.query(aparte, account, query);
             }
@@ -231,10 +587,49 @@ impl ModTrait for MamMod {
                     jid: account.to_bare(),
                     with: Some(contact.clone()),
                     from: *from,
-                    count: 100,
+                    count: self.preload,
                 };
                 self.query(aparte, account, query);
             }
+            Event::ContactAdded(account, contact) => Self::probe(aparte, account, contact),
+            Event::HistoryImport {
+                account,
+                contact,
+                queryid,
+            } => {
+                self.imports
+                    .insert(queryid.clone(), (account.clone(), contact.clone()));
+            }
+            Event::HistoryImported {
+                account,
+                contact,
+                complete,
+            } => {
+                let index = (account.clone(), contact.clone());
+                let count = self.imported.get(&index).copied().unwrap_or(0);
+                if *complete {
+                    self.imported.remove(&index);
+                    self.imports.retain(|_, import| *import != index);
+                    crate::info!(aparte, "Imported {count} archived messages of {contact}");
+                } else {
+                    crate::info!(
+                        aparte,
+                        "Imported {count} archived messages of {contact} so far"
+                    );
+                }
+            }
+            Event::FormSubmit { id, form } => {
+                if let Some(account) = self.prefs.remove(id) {
+                    match form {
+                        Some(form) => Self::save_prefs(aparte, account, form.clone()),
+                        None => crate::info!(aparte, "Archiving preferences left unchanged"),
+                    }
+                }
+            }
+            Event::Disconnected(account, _) => {
+                self.prefs
+                    .retain(|_, prefs_account| prefs_account != account);
+            }
             Event::Iq(account, iq) => {
                 if let Some(id) = self.iq2id.remove(&iq.id) {
                     if let Some(query) = self.queries.remove(&id) {
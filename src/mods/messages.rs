@@ -1,29 +1,99 @@
 /* This Source Code Form is subject to the terms of the Mozilla Public
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use chrono::Local as LocalTz;
 use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::fmt;
+use std::str::FromStr;
 use xmpp_parsers::delay::Delay;
 use xmpp_parsers::message::{Message as XmppParsersMessage, MessageType as XmppParsersMessageType};
-use xmpp_parsers::ns;
+use xmpp_parsers::stanza_id::OriginId;
+use xmpp_parsers::{ns, BareJid, Jid};
 
 use crate::account::Account;
 use crate::core::{Aparte, Event, ModTrait};
-use crate::message::Message;
+use crate::i18n;
+use crate::message::{
+    Delivery, Direction, Message, VersionedXmppMessage, XmppMessageType, NS_REPLY,
+};
 use crate::mods::disco;
 
 pub struct MessagesMod {
     messages: HashMap<Option<Account>, HashMap<String, Message>>,
+    /// Stored delivery states of outgoing messages, loaded once per conversation
+    deliveries: HashMap<(Account, BareJid), HashMap<String, Delivery>>,
 }
 
 impl MessagesMod {
     pub fn new() -> Self {
         Self {
             messages: HashMap::new(),
+            deliveries: HashMap::new(),
         }
     }
 
+    /// Stored delivery states of a conversation, queried on first use only
+    fn deliveries(
+        &mut self,
+        aparte: &Aparte,
+        account: &Account,
+        contact: &BareJid,
+    ) -> &HashMap<String, Delivery> {
+        self.deliveries
+            .entry((account.clone(), contact.clone()))
+            .or_insert_with(
+                || match aparte.storage.get_message_deliveries(account, contact) {
+                    Ok(deliveries) => deliveries
+                        .into_iter()
+                        .filter_map(|(id, state)| Some((id, Delivery::from_str(&state).ok()?)))
+                        .collect(),
+                    Err(err) => {
+                        log::warn!("Cannot load delivery states of {contact}: {err}");
+                        HashMap::new()
+                    }
+                },
+            )
+    }
+
+    /// Keep track of subject changes of a channel, listed by /topic history
+    fn record_subject(
+        aparte: &Aparte,
+        account: &Account,
+        message: &XmppParsersMessage,
+        delay: &Option<Delay>,
+    ) -> anyhow::Result<()> {
+        let Some(from) = &message.from else {
+            return Ok(());
+        };
+        let channel = from.to_bare();
+        let subject = i18n::get_best(&message.subjects, i18n::preferred_langs())
+            .map(|(_lang, subject)| subject.0.clone())
+            .unwrap_or_default();
+
+        // Channels repeat the current subject to each joining occupant
+        let subjects = aparte.storage.get_channel_subjects(account, &channel)?;
+        if subjects.last().map(|last| &last.subject) == Some(&subject) {
+            return Ok(());
+        }
+
+        let author = match from {
+            Jid::Full(from) => Some(from.resource().to_string()),
+            Jid::Bare(_) => None,
+        };
+        let timestamp = match delay {
+            Some(delay) => delay.stamp.0,
+            None => LocalTz::now().into(),
+        };
+        aparte.storage.add_channel_subject(
+            account,
+            &channel,
+            &timestamp.to_rfc3339(),
+            author.as_deref(),
+            &subject,
+        )
+    }
+
     pub fn get<'a>(&'a self, account: &Option<Account>, id: &String) -> Option<&'a Message> {
         self.messages.get(account)?.get(id)
     }
@@ -36,12 +106,287 @@ impl MessagesMod {
         self.messages.get_mut(account)?.get_mut(id)
     }
 
-    pub fn handle_message(&mut self, account: &Option<Account>, message: &Message) {
+    pub fn handle_message(
+        &mut self,
+        aparte: &Aparte,
+        account: &Option<Account>,
+        message: &Message,
+    ) {
+        let mut message = message.clone();
+        if let (Some(account), Message::Xmpp(message)) = (account, &mut message) {
+            // Archived messages may have been acknowledged in a previous session
+            if message.archive
+                && message.direction == Direction::Outgoing
+                && message.type_ == XmppMessageType::Chat
+            {
+                if let Some(delivery) = self
+                    .deliveries(aparte, account, &message.to)
+                    .get(&message.id)
+                {
+                    message.delivery = Some(*delivery);
+                }
+            }
+        }
+
         let messages = self
             .messages
             .entry(account.clone())
             .or_insert(HashMap::new());
-        messages.insert(message.id().to_string(), message.clone());
+        messages.insert(message.id().to_string(), message);
+    }
+
+    /// Match the reflection of a message we sent in a channel with its local echo, through
+    /// their origin-id, and update the echo with what the channel tells about it
+    fn handle_reflection(
+        &mut self,
+        aparte: &mut Aparte,
+        account: &Account,
+        message: &XmppParsersMessage,
+        delay: &Option<Delay>,
+        archive: bool,
+    ) -> bool {
+        let Some(origin_id) = message
+            .payloads
+            .iter()
+            .find_map(|payload| OriginId::try_from(payload.clone()).ok())
+        else {
+            return false;
+        };
+        let Some(Message::Xmpp(echo)) = self.get(&Some(account.clone()), &origin_id.id) else {
+            return false;
+        };
+        if echo.direction != Direction::Outgoing
+            || echo.type_ != XmppMessageType::Channel
+            || message.from.as_ref().map(|from| from.to_bare()) != Some(echo.to.clone())
+        {
+            return false;
+        }
+        let Ok(Message::Xmpp(reflection)) = Message::from_xmpp(account, message, delay, archive)
+        else {
+            return false;
+        };
+
+        let mut reflected = echo.clone();
+        for version in reflected.history.iter_mut() {
+            if version.id == origin_id.id {
+                version.timestamp = *reflection.get_original_timestamp();
+            }
+        }
+        reflected.stanza_id = reflection.stanza_id;
+        reflected.archive = echo.archive || archive;
+
+        let echo = Message::Xmpp(echo.clone());
+        let reflected = Message::Xmpp(reflected);
+        self.handle_message(aparte, &Some(account.clone()), &reflected);
+        aparte.schedule(Event::MessageReflected {
+            account: account.clone(),
+            echo,
+            message: reflected,
+        });
+        true
+    }
+
+    /// Record the new delivery state of an outgoing message
+    fn update_delivery(
+        &mut self,
+        aparte: &mut Aparte,
+        account: &Account,
+        contact: &BareJid,
+        id: &String,
+        delivery: Delivery,
+    ) {
+        if let Some(Message::Xmpp(message)) = self.get_mut(&Some(account.clone()), id) {
+            if &message.to != contact {
+                return;
+            }
+            // A receipt may arrive after the displayed marker, it is still part of the timeline
+            if !message
+                .delivery_timeline
+                .iter()
+                .any(|(reached, _)| *reached == delivery)
+            {
+                message
+                    .delivery_timeline
+                    .push((delivery, LocalTz::now().into()));
+            }
+            if message.delivery >= Some(delivery) {
+                return;
+            }
+            message.delivery = Some(delivery);
+        }
+
+        // Archived outgoing messages are known to have been sent
+        if delivery <= Delivery::Sent {
+            return;
+        }
+        if let Err(err) =
+            aparte
+                .storage
+                .set_message_delivery(account, contact, id, &delivery.to_string())
+        {
+            crate::error!(aparte, err, "Cannot store delivery state of {id}");
+        }
+        if let Some(deliveries) = self.deliveries.get_mut(&(account.clone(), contact.clone())) {
+            deliveries.insert(id.clone(), delivery);
+        }
+    }
+
+    /// Messages of a conversation, oldest first
+    fn conversation<'a>(
+        &'a self,
+        account: &Account,
+        conversation: &BareJid,
+    ) -> Vec<&'a VersionedXmppMessage> {
+        let mut messages = match self.messages.get(&Some(account.clone())) {
+            Some(messages) => messages
+                .values()
+                .filter_map(|message| match message {
+                    Message::Xmpp(message)
+                        if &message.from == conversation || &message.to == conversation =>
+                    {
+                        Some(message)
+                    }
+                    _ => None,
+                })
+                .collect::<Vec<_>>(),
+            None => return Vec::new(),
+        };
+        messages.sort_by_key(|message| *message.get_original_timestamp());
+        messages
+    }
+
+    /// Most recent message of a conversation, only among those containing the text if any
+    pub fn find_last(
+        &self,
+        account: &Account,
+        conversation: &BareJid,
+        text: Option<&str>,
+    ) -> Option<VersionedXmppMessage> {
+        self.conversation(account, conversation)
+            .into_iter()
+            .filter(|message| contains(message, text))
+            .last()
+            .cloned()
+    }
+
+    /// Most recent corrected message of a conversation, only among those having a version
+    /// containing the text if any
+    pub fn find_last_corrected(
+        &self,
+        account: &Account,
+        conversation: &BareJid,
+        text: Option<&str>,
+    ) -> Option<VersionedXmppMessage> {
+        let text = text.map(|text| text.to_lowercase());
+        self.conversation(account, conversation)
+            .into_iter()
+            .filter(|message| message.has_multiple_version())
+            .filter(|message| match &text {
+                Some(text) => message.history.iter().any(|version| {
+                    version
+                        .get_best_body(i18n::preferred_langs())
+                        .to_lowercase()
+                        .contains(text)
+                }),
+                None => true,
+            })
+            .last()
+            .cloned()
+    }
+
+    /// Most recent message we sent in a conversation, only among those containing the text if any
+    pub fn find_last_sent(
+        &self,
+        account: &Account,
+        conversation: &BareJid,
+        text: Option<&str>,
+    ) -> Option<VersionedXmppMessage> {
+        self.conversation(account, conversation)
+            .into_iter()
+            .filter(|message| message.direction == Direction::Outgoing)
+            .filter(|message| contains(message, text))
+            .last()
+            .cloned()
+    }
+
+    /// Message of a conversation sent before or after the given one, the most recent one when
+    /// none is given and looking for an older one
+    pub fn adjacent(
+        &self,
+        account: &Account,
+        conversation: &BareJid,
+        id: Option<&str>,
+        older: bool,
+    ) -> Option<VersionedXmppMessage> {
+        let messages = self.conversation(account, conversation);
+        let position = match id {
+            Some(id) => messages.iter().position(|message| message.id == id)?,
+            None => messages.len(),
+        };
+        let adjacent = match older {
+            true => position.checked_sub(1)?,
+            false => position + 1,
+        };
+        messages.get(adjacent).map(|message| (*message).clone())
+    }
+
+    /// Files shared in a conversation, most recent first
+    pub fn attachments(&self, account: &Account, conversation: &BareJid) -> Vec<String> {
+        self.conversation(account, conversation)
+            .into_iter()
+            .rev()
+            .filter(|message| !matches!(&message.spoiler, Some(spoiler) if !spoiler.revealed))
+            .filter(|message| !message.retracted)
+            .flat_map(|message| message.attachments())
+            .collect()
+    }
+
+    /// Message of a channel with the id it was given by the channel
+    pub fn get_channel_message_mut<'a>(
+        &'a mut self,
+        account: &Account,
+        channel: &BareJid,
+        stanza_id: &str,
+    ) -> Option<&'a mut VersionedXmppMessage> {
+        self.messages
+            .get_mut(&Some(account.clone()))?
+            .values_mut()
+            .find_map(|message| match message {
+                Message::Xmpp(message)
+                    if message.type_ == XmppMessageType::Channel
+                        && (&message.from == channel || &message.to == channel)
+                        && message.stanza_id.as_deref() == Some(stanza_id) =>
+                {
+                    Some(message)
+                }
+                _ => None,
+            })
+    }
+
+    /// Quote the message replied to when it is known
+    fn resolve_reply(&self, account: &Account, message: &mut Message) {
+        if let Message::Xmpp(VersionedXmppMessage {
+            reply: Some(reply), ..
+        }) = message
+        {
+            if let Some(Message::Xmpp(original)) = self.get(&Some(account.clone()), &reply.id) {
+                reply.quote = Some(original.get_last_body().to_string());
+            }
+        }
+    }
+
+    pub fn reveal_spoilers(&mut self, account: &Account, conversation: &BareJid, id: Option<&str>) {
+        if let Some(messages) = self.messages.get_mut(&Some(account.clone())) {
+            for message in messages.values_mut() {
+                if let Message::Xmpp(message) = message {
+                    if (&message.from == conversation || &message.to == conversation)
+                        && (id.is_none() || id == Some(message.id.as_str()))
+                    {
+                        message.reveal_spoiler();
+                    }
+                }
+            }
+        }
     }
 
     fn handle_headline_message(
@@ -64,10 +409,22 @@ impl MessagesMod {
     }
 }
 
+/// Whether the body of the message contains the text, ignoring case
+fn contains(message: &VersionedXmppMessage, text: Option<&str>) -> bool {
+    match text {
+        Some(text) => message
+            .get_last_body()
+            .to_lowercase()
+            .contains(&text.to_lowercase()),
+        None => true,
+    }
+}
+
 impl ModTrait for MessagesMod {
     fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
         let mut disco = aparte.get_mod_mut::<disco::DiscoMod>();
         disco.add_feature(ns::MESSAGE_CORRECT);
+        disco.add_feature(NS_REPLY);
 
         Ok(())
     }
@@ -119,13 +476,17 @@ impl ModTrait for MessagesMod {
     ) {
         match message.type_ {
             XmppParsersMessageType::Chat => {
-                if let Ok(message) = Message::from_xmpp(account, message, delay, archive) {
+                if let Ok(mut message) = Message::from_xmpp(account, message, delay, archive) {
+                    self.resolve_reply(account, &mut message);
                     aparte.schedule(Event::Message(Some(account.clone()), message));
                 }
             }
             XmppParsersMessageType::Groupchat => {
-                if !message.bodies.is_empty() {
-                    if let Ok(message) = Message::from_xmpp(account, message, delay, archive) {
+                if !message.bodies.is_empty()
+                    && !self.handle_reflection(aparte, account, message, delay, archive)
+                {
+                    if let Ok(mut message) = Message::from_xmpp(account, message, delay, archive) {
+                        self.resolve_reply(account, &mut message);
                         aparte.schedule(Event::Message(Some(account.clone()), message));
                     }
                 }
@@ -144,6 +505,9 @@ impl ModTrait for MessagesMod {
                                 .collect(),
                         ));
                     }
+                    if let Err(err) = Self::record_subject(aparte, account, message, delay) {
+                        log::warn!("Cannot record subject: {err}");
+                    }
                 }
             }
             XmppParsersMessageType::Headline => {
@@ -154,9 +518,20 @@ impl ModTrait for MessagesMod {
         };
     }
 
-    fn on_event(&mut self, _aparte: &mut Aparte, event: &Event) {
+    fn on_event(&mut self, aparte: &mut Aparte, event: &Event) {
         match event {
-            Event::Message(account, message) => self.handle_message(account, message),
+            Event::Message(account, message) => self.handle_message(aparte, account, message),
+            Event::MessageDelivery {
+                account,
+                contact,
+                id,
+                delivery,
+            } => self.update_delivery(aparte, account, contact, id, *delivery),
+            Event::RevealSpoilers {
+                account,
+                conversation,
+                id,
+            } => self.reveal_spoilers(account, conversation, id.as_deref()),
             _ => {}
         }
     }
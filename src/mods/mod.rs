@@ -1,14 +1,43 @@
 /* This Source Code Form is subject to the terms of the Mozilla Public
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+pub mod activity;
+pub mod adhoc;
+pub mod avatar;
+pub mod blocking;
 pub mod bookmarks;
+pub mod calls;
 pub mod carbons;
+pub mod channels;
+pub mod chatstates;
 pub mod completion;
 pub mod contact;
 pub mod conversation;
 pub mod correction;
+pub mod csi;
 pub mod disco;
+pub mod download;
+pub mod host_meta;
+pub mod idle;
+pub mod last;
+pub mod location;
 pub mod mam;
 pub mod messages;
+pub mod mood;
+pub mod muc;
+pub mod nick;
+pub mod notes;
+pub mod notifications;
 pub mod omemo;
+pub mod ping;
+pub mod pubsub;
+pub mod quiet_hours;
+pub mod reactions;
+pub mod receipts;
+pub mod retraction;
+pub mod sessions;
+pub mod trace;
+pub mod tune;
 pub mod ui;
+pub mod upload;
+pub mod version;
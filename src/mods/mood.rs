@@ -0,0 +1,246 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use std::fmt;
+use std::iter;
+
+use anyhow::{Context, Result};
+use xmpp_parsers::pubsub::PubSubEvent;
+use xmpp_parsers::Element;
+
+use crate::command::{Command, CommandParser};
+use crate::contact::Mood;
+use crate::core::{Aparte, Event, ModTrait};
+use crate::mods::disco;
+use crate::mods::pubsub::PubSubMod;
+
+const NS_MOOD: &str = "http://jabber.org/protocol/mood";
+
+/// Moods defined by XEP-0107
+const MOODS: [&str; 84] = [
+    "afraid",
+    "amazed",
+    "amorous",
+    "angry",
+    "annoyed",
+    "anxious",
+    "aroused",
+    "ashamed",
+    "bored",
+    "brave",
+    "calm",
+    "cautious",
+    "cold",
+    "confident",
+    "confused",
+    "contemplative",
+    "contented",
+    "cranky",
+    "crazy",
+    "creative",
+    "curious",
+    "dejected",
+    "depressed",
+    "disappointed",
+    "disgusted",
+    "dismayed",
+    "distracted",
+    "embarrassed",
+    "envious",
+    "excited",
+    "flirtatious",
+    "frustrated",
+    "grateful",
+    "grieving",
+    "grumpy",
+    "guilty",
+    "happy",
+    "hopeful",
+    "hot",
+    "humbled",
+    "humiliated",
+    "hungry",
+    "hurt",
+    "impressed",
+    "in_awe",
+    "in_love",
+    "indignant",
+    "interested",
+    "intoxicated",
+    "invincible",
+    "jealous",
+    "lonely",
+    "lost",
+    "lucky",
+    "mean",
+    "moody",
+    "nervous",
+    "neutral",
+    "offended",
+    "outraged",
+    "playful",
+    "proud",
+    "relaxed",
+    "relieved",
+    "remorseful",
+    "restless",
+    "sad",
+    "sarcastic",
+    "satisfied",
+    "serious",
+    "shocked",
+    "shy",
+    "sick",
+    "sleepy",
+    "spontaneous",
+    "stressed",
+    "strong",
+    "surprised",
+    "thankful",
+    "thirsty",
+    "tired",
+    "undefined",
+    "weak",
+    "worried",
+];
+
+command_def!(mood,
+r#"/mood <mood> [<text>]
+
+    mood    one of the moods defined by XEP-0107, or none to clear it
+    text    optional description of the mood
+
+Description:
+    Publish your mood to your contacts. Moods published by contacts are
+    shown in the roster and by /whois.
+
+Examples:
+    /mood happy
+    /mood tired "Long day at work"
+    /mood none
+"#,
+{
+    mood: String = {
+        completion: |_aparte, _command| {
+            MOODS.iter().map(|mood| mood.to_string()).chain(iter::once(String::from("none"))).collect()
+        }
+    },
+    text: Option<String>,
+},
+|aparte, _command| {
+    let account = aparte.current_account().context("No connection found")?;
+    let mood = match mood.as_str() {
+        "none" => None,
+        mood if MOODS.contains(&mood) => Some(Mood {
+            mood: mood.to_string(),
+            text,
+        }),
+        mood => anyhow::bail!("Unknown mood {mood}"),
+    };
+
+    Aparte::spawn({
+        let mut aparte = aparte.proxy();
+        async move {
+            let payload = MoodMod::to_element(mood.as_ref());
+            match PubSubMod::publish(&mut aparte, &account, NS_MOOD, payload).await {
+                Ok(()) => match mood {
+                    Some(mood) => crate::info!(aparte, "Mood set to {mood}"),
+                    None => crate::info!(aparte, "Mood cleared"),
+                },
+                Err(err) => crate::error!(aparte, err, "Cannot publish mood"),
+            }
+        }
+    });
+    Ok(())
+});
+
+/// XEP-0107: User Mood
+pub struct MoodMod {}
+
+impl MoodMod {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    /// Published mood element, an empty one clearing the mood
+    fn to_element(mood: Option<&Mood>) -> Element {
+        let mut element = Element::builder("mood", NS_MOOD);
+        if let Some(mood) = mood {
+            element = element.append(Element::builder(&mood.mood, NS_MOOD).build());
+            if let Some(text) = &mood.text {
+                element = element.append(
+                    Element::builder("text", NS_MOOD)
+                        .append(text.clone())
+                        .build(),
+                );
+            }
+        }
+        element.build()
+    }
+
+    fn from_element(element: &Element) -> Option<Mood> {
+        if !element.is("mood", NS_MOOD) {
+            return None;
+        }
+        let mood = element
+            .children()
+            .find(|child| child.ns() == NS_MOOD && child.name() != "text")?;
+        Some(Mood {
+            mood: mood.name().to_string(),
+            text: element
+                .get_child("text", NS_MOOD)
+                .map(|text| text.text())
+                .filter(|text| !text.is_empty()),
+        })
+    }
+}
+
+impl ModTrait for MoodMod {
+    fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
+        {
+            let mut disco = aparte.get_mod_mut::<disco::DiscoMod>();
+            disco.add_feature(format!("{}+notify", NS_MOOD));
+        }
+        aparte.add_command(mood::new());
+        Ok(())
+    }
+
+    fn on_event(&mut self, aparte: &mut Aparte, event: &Event) {
+        match event {
+            Event::PubSub {
+                account,
+                from: Some(from),
+                event: PubSubEvent::PublishedItems { node, items },
+            } if node.0 == NS_MOOD => {
+                let mood = items
+                    .iter()
+                    .filter_map(|item| item.0.payload.as_ref())
+                    .last()
+                    .and_then(Self::from_element);
+                aparte.schedule(Event::Mood {
+                    account: account.clone(),
+                    jid: from.to_bare(),
+                    mood,
+                });
+            }
+            Event::PubSub {
+                account,
+                from: Some(from),
+                event: PubSubEvent::RetractedItems { node, .. },
+            } if node.0 == NS_MOOD => {
+                aparte.schedule(Event::Mood {
+                    account: account.clone(),
+                    jid: from.to_bare(),
+                    mood: None,
+                });
+            }
+            _ => {}
+        }
+    }
+}
+
+impl fmt::Display for MoodMod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "XEP-0107: User Mood")
+    }
+}
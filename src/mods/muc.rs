@@ -0,0 +1,1414 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Local as LocalTz};
+use uuid::Uuid;
+use xmpp_parsers::data_forms::{DataForm, DataFormType, Field, FieldType};
+use xmpp_parsers::delay::Delay;
+use xmpp_parsers::iq::{Iq, IqType};
+use xmpp_parsers::message::Message as XmppParsersMessage;
+use xmpp_parsers::presence::{Presence, Type as PresenceType};
+use xmpp_parsers::stanza_error::{DefinedCondition, StanzaError};
+use xmpp_parsers::{disco, ibr, ns, BareJid, Element, FullJid, Jid};
+
+use crate::account::Account;
+use crate::command::{Command, CommandParser};
+use crate::conversation::{Affiliation, Anonymity, Channel, Conversation};
+use crate::core::{Aparte, AparteAsync, Event, ModTrait};
+use crate::i18n;
+use crate::message::LogMessage;
+use crate::mods::avatar::AvatarMod;
+use crate::mods::conversation::ConversationMod;
+use crate::mods::ui::UIMod;
+
+const NS_MUC_REGISTER: &str = "http://jabber.org/protocol/muc#register";
+const NS_MUC_ADMIN: &str = "http://jabber.org/protocol/muc#admin";
+const NS_MUC_OWNER: &str = "http://jabber.org/protocol/muc#owner";
+const NS_MUC_REQUEST: &str = "http://jabber.org/protocol/muc#request";
+/// Affiliations listed in the members window
+const MEMBER_AFFILIATIONS: [Affiliation; 4] = [
+    Affiliation::Owner,
+    Affiliation::Admin,
+    Affiliation::Member,
+    Affiliation::Outcast,
+];
+/// XEP-0045: extended room information
+const NS_MUC_ROOMINFO: &str = "http://jabber.org/protocol/muc#roominfo";
+/// Room features shown by /room info, with their label
+const ROOM_FEATURES: [(&str, &str); 12] = [
+    ("muc_public", "public"),
+    ("muc_hidden", "hidden"),
+    ("muc_persistent", "persistent"),
+    ("muc_temporary", "temporary"),
+    ("muc_open", "open"),
+    ("muc_membersonly", "members only"),
+    ("muc_moderated", "moderated"),
+    ("muc_unmoderated", "unmoderated"),
+    ("muc_nonanonymous", "non-anonymous"),
+    ("muc_semianonymous", "semi-anonymous"),
+    ("muc_passwordprotected", "password protected"),
+    ("muc_unsecured", "unsecured"),
+];
+/// Extended room information shown by /room info, with their label
+const ROOMINFO_FIELDS: [(&str, &str); 5] = [
+    ("muc#roominfo_description", "Description"),
+    ("muc#roominfo_occupants", "Occupants"),
+    ("muc#roominfo_lang", "Language"),
+    ("muc#roominfo_contactjid", "Contact"),
+    ("muc#roominfo_logs", "Logs"),
+];
+/// Disco node used to discover a nickname reserved in a room
+const NODE_ROOMUSER_ITEM: &str = "x-roomuser-item";
+
+/// Kicks further apart than this don't increase the delay before rejoining
+const REJOIN_BACKOFF_RESET: Duration = Duration::from_secs(600);
+/// Longest delay before rejoining a channel after repeated kicks
+const REJOIN_MAX_DELAY: Duration = Duration::from_secs(3600);
+/// Suffixes tried when our nickname is taken, unless configured otherwise
+const DEFAULT_NICK_SUFFIXES: [&str; 2] = ["_", "{n}"];
+/// Number of nicknames tried with each suffix counting with `{n}`
+const NUMBERED_NICK_ATTEMPTS: usize = 8;
+
+/// Channel displayed in the current window
+pub fn current_channel(aparte: &Aparte) -> Result<Channel> {
+    let (account, current) = aparte.get_mod::<UIMod>().current_conversation()?;
+    let jid = current.get_jid();
+    let conversation = aparte.get_mod::<ConversationMod>();
+    match conversation.get(&account, jid) {
+        Some(Conversation::Channel(channel)) => Ok(channel.clone()),
+        _ => Err(anyhow!("{jid} is not a channel")),
+    }
+}
+
+command_def!(room_register,
+r#"/room register [<nick>]
+
+    nick    Nickname to reserve, current nickname when missing
+
+Description:
+    Register a nickname with the channel of the current window. The form of
+    the channel is shown in the form window with the nickname filled in. The
+    reserved nickname is then automatically used when joining the channel.
+
+Examples:
+    /room register
+    /room register aparte
+"#,
+{
+    nick: Option<String>,
+},
+|aparte, _command| {
+    let channel = current_channel(aparte)?;
+    let nick = nick.unwrap_or_else(|| channel.nick.clone());
+    MucMod::register(aparte, &channel.account, &channel.jid, &nick);
+    Ok(())
+});
+
+command_def!(room_members,
+r#"/room members [<filter>]
+
+    filter    Only show members containing this text (case insensitive)
+
+Description:
+    List owners, admins, members and outcasts of the channel of the current
+    window in the members window.
+
+Examples:
+    /room members
+    /room members example.org
+"#,
+{
+    filter: Option<String>,
+},
+|aparte, _command| {
+    let channel = current_channel(aparte)?;
+    MucMod::fetch_members(aparte, &channel.account, &channel.jid, filter);
+    Ok(())
+});
+
+command_def!(room_affiliation,
+r#"/room affiliation <jid> <affiliation> [<reason>]
+
+    jid            Jid of the user
+    affiliation    One of owner, admin, member, outcast or none
+    reason         Reason given to the user
+
+Description:
+    Change the affiliation of a user with the channel of the current window.
+    Setting outcast bans the user, setting none removes the user from members.
+
+Examples:
+    /room affiliation user@example.org member
+    /room affiliation spammer@example.org outcast "Spam"
+"#,
+{
+    jid: BareJid = {
+        completion: |aparte, _command| {
+            match current_channel(aparte) {
+                Ok(channel) => aparte.get_mod::<MucMod>().member_jids(&channel.account, &channel.jid),
+                Err(_) => Vec::new(),
+            }
+        }
+    },
+    affiliation: Affiliation = {
+        completion: |_aparte, _command| {
+            MEMBER_AFFILIATIONS.iter().chain([Affiliation::None].iter()).map(|affiliation| affiliation.to_string()).collect()
+        }
+    },
+    reason: Option<String>,
+},
+|aparte, _command| {
+    let channel = current_channel(aparte)?;
+    MucMod::change_affiliation(aparte, &channel.account, &channel.jid, &jid, affiliation, reason);
+    Ok(())
+});
+
+command_def!(
+    room_info,
+    r#"/room info
+
+Description:
+    Show the name, description, avatar and features (public, persistent,
+    anonymous...) the channel of the current window announces.
+
+Examples:
+    /room info
+"#,
+    {},
+    |aparte, _command| {
+        let channel = current_channel(aparte)?;
+        MucMod::show_info(aparte, &channel.account, &channel.jid);
+        Ok(())
+    }
+);
+
+command_def!(
+    room_config,
+    r#"/room config
+
+Description:
+    Edit the configuration of the channel of the current window in the form
+    window. Only owners are allowed to configure a channel.
+
+Examples:
+    /room config
+"#,
+    {},
+    |aparte, _command| {
+        let channel = current_channel(aparte)?;
+        MucMod::configure(aparte, &channel.account, &channel.jid);
+        Ok(())
+    }
+);
+
+command_def!(room,
+r#"/room register|members|affiliation|info|config"#,
+{
+    action: Command = {
+        children: {
+            "register": room_register,
+            "members": room_members,
+            "affiliation": room_affiliation,
+            "info": room_info,
+            "config": room_config,
+        }
+    },
+});
+
+command_def!(
+    voice_request,
+    r#"/voice request
+
+Description:
+    Ask moderators of the channel of the current window for voice, i.e. the
+    permission to send messages in a moderated channel.
+
+Examples:
+    /voice request
+"#,
+    {},
+    |aparte, _command| {
+        let channel = current_channel(aparte)?;
+        let mut message = XmppParsersMessage::new(Some(Jid::Bare(channel.jid.clone())));
+        message.payloads.push(MucMod::voice_request_form().into());
+        aparte.send(&channel.account, message);
+        crate::info!(aparte, "Voice requested in {}", channel.jid);
+        Ok(())
+    }
+);
+
+command_def!(voice_approve,
+r#"/voice approve <nick>
+
+    nick    Nickname of the occupant requesting voice
+
+Description:
+    Grant voice to an occupant who requested it in the channel of the current
+    window.
+
+Examples:
+    /voice approve visitor
+"#,
+{
+    nick: String = {
+        completion: |aparte, _command| {
+            match current_channel(aparte) {
+                Ok(channel) => aparte.get_mod::<MucMod>().voice_requesters(&channel.account, &channel.jid),
+                Err(_) => Vec::new(),
+            }
+        }
+    },
+},
+|aparte, _command| {
+    let channel = current_channel(aparte)?;
+    let request = {
+        let mut muc = aparte.get_mod_mut::<MucMod>();
+        muc.take_voice_request(&channel.account, &channel.jid, &nick)
+    }.with_context(|| format!("No voice request from {nick}"))?;
+    let mut message = XmppParsersMessage::new(Some(Jid::Bare(channel.jid.clone())));
+    message.payloads.push(request.approval().into());
+    aparte.send(&channel.account, message);
+    crate::info!(aparte, "Voice granted to {nick} in {}", channel.jid);
+    Ok(())
+});
+
+command_def!(voice_deny,
+r#"/voice deny <nick>
+
+    nick    Nickname of the occupant requesting voice
+
+Description:
+    Dismiss the voice request of an occupant of the channel of the current
+    window.
+
+Examples:
+    /voice deny visitor
+"#,
+{
+    nick: String = {
+        completion: |aparte, _command| {
+            match current_channel(aparte) {
+                Ok(channel) => aparte.get_mod::<MucMod>().voice_requesters(&channel.account, &channel.jid),
+                Err(_) => Vec::new(),
+            }
+        }
+    },
+},
+|aparte, _command| {
+    let channel = current_channel(aparte)?;
+    let request = {
+        let mut muc = aparte.get_mod_mut::<MucMod>();
+        muc.take_voice_request(&channel.account, &channel.jid, &nick)
+    };
+    request.with_context(|| format!("No voice request from {nick}"))?;
+    crate::info!(aparte, "Voice request of {nick} in {} dismissed", channel.jid);
+    Ok(())
+});
+
+command_def!(voice,
+r#"/voice request|approve|deny"#,
+{
+    action: Command = {
+        children: {
+            "request": voice_request,
+            "approve": voice_approve,
+            "deny": voice_deny,
+        }
+    },
+});
+
+command_def!(
+    topic_history,
+    r#"/topic history
+
+Description:
+    List the successive subjects of the channel of the current window, with
+    who set them and when. Subjects are recorded while connected to the
+    channel.
+
+Examples:
+    /topic history
+"#,
+    {},
+    |aparte, _command| {
+        let channel = current_channel(aparte)?;
+        let subjects = aparte
+            .storage
+            .get_channel_subjects(&channel.account, &channel.jid)?;
+        if subjects.is_empty() {
+            anyhow::bail!("No subject recorded for {}", channel.jid);
+        }
+
+        let mut history = format!("Subjects of {}:", channel.jid);
+        for subject in subjects.iter() {
+            let timestamp = DateTime::parse_from_rfc3339(&subject.timestamp)
+                .map(|timestamp| {
+                    timestamp
+                        .with_timezone(&LocalTz)
+                        .format("%F %T")
+                        .to_string()
+                })
+                .unwrap_or_else(|_| subject.timestamp.clone());
+            let author = subject.author.as_deref().unwrap_or("channel");
+            let text = match subject.subject.is_empty() {
+                true => "(cleared)",
+                false => subject.subject.as_str(),
+            };
+            history.push_str(&format!("\n  {timestamp} <{author}> {text}"));
+        }
+        aparte.schedule(Event::ConversationLog {
+            account: channel.account,
+            conversation: channel.jid,
+            message: LogMessage {
+                id: String::from("topic-history"),
+                timestamp: LocalTz::now().into(),
+                body: history,
+                level: log::Level::Info,
+            },
+        });
+        Ok(())
+    }
+);
+
+command_def!(topic,
+r#"/topic history"#,
+{
+    action: Command = {
+        children: {
+            "history": topic_history,
+        }
+    },
+});
+
+/// Voice request forwarded by a moderated channel to its moderators
+struct VoiceRequest {
+    nick: String,
+    /// Form sent by the channel, submitted back to approve the request
+    form: DataForm,
+}
+
+impl VoiceRequest {
+    fn approval(mut self) -> DataForm {
+        self.form.type_ = DataFormType::Submit;
+        match self
+            .form
+            .fields
+            .iter_mut()
+            .find(|field| field.var == "muc#request_allow")
+        {
+            Some(field) => field.values = vec![String::from("true")],
+            None => self.form.fields.push(Field {
+                var: String::from("muc#request_allow"),
+                type_: FieldType::Boolean,
+                label: None,
+                required: false,
+                media: vec![],
+                options: vec![],
+                values: vec![String::from("true")],
+            }),
+        }
+        self.form
+    }
+}
+
+/// Form of a channel shown in the form window
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RoomForm {
+    Registration,
+    Configuration,
+}
+
+/// User affiliated with a channel
+#[derive(Debug, Clone)]
+pub struct RoomMember {
+    pub jid: BareJid,
+    pub affiliation: Affiliation,
+    pub nick: Option<String>,
+    pub reason: Option<String>,
+}
+
+pub struct MucMod {
+    /// Last members listed for each channel
+    members: HashMap<(Account, BareJid), Vec<RoomMember>>,
+    /// Pending voice requests of each moderated channel
+    voice_requests: HashMap<(Account, BareJid), Vec<VoiceRequest>>,
+    /// Visibility of occupants' real jid in joined channels
+    anonymity: HashMap<(Account, BareJid), Anonymity>,
+    /// Number of consecutive kicks from each channel, and when the last one happened
+    kicks: HashMap<(Account, BareJid), (u32, Instant)>,
+    /// Channels being joined, with the nickname asked first and the number of retries
+    joining: HashMap<(Account, BareJid), (String, usize)>,
+    /// Forms of channels shown to the user, indexed by form id
+    forms: HashMap<String, (RoomForm, Account, BareJid)>,
+}
+
+impl MucMod {
+    pub fn new() -> Self {
+        Self {
+            members: HashMap::new(),
+            voice_requests: HashMap::new(),
+            anonymity: HashMap::new(),
+            kicks: HashMap::new(),
+            joining: HashMap::new(),
+            forms: HashMap::new(),
+        }
+    }
+
+    /// Status codes of muc#user payloads
+    fn status_codes(payloads: &[Element]) -> Vec<u16> {
+        payloads
+            .iter()
+            .filter(|payload| payload.is("x", ns::MUC_USER))
+            .flat_map(|payload| payload.children())
+            .filter(|child| child.is("status", ns::MUC_USER))
+            .filter_map(|status| status.attr("code")?.parse().ok())
+            .collect()
+    }
+
+    /// Anonymity announced by status codes, a channel is semi-anonymous unless told
+    /// otherwise when joining
+    fn announced_anonymity(codes: &[u16], joining: bool) -> Option<Anonymity> {
+        if codes.contains(&100) || codes.contains(&172) {
+            Some(Anonymity::NonAnonymous)
+        } else if codes.contains(&173) {
+            Some(Anonymity::SemiAnonymous)
+        } else if codes.contains(&174) {
+            Some(Anonymity::FullyAnonymous)
+        } else if joining && codes.contains(&110) {
+            Some(Anonymity::SemiAnonymous)
+        } else {
+            None
+        }
+    }
+
+    fn update_anonymity(
+        &mut self,
+        aparte: &mut Aparte,
+        account: &Account,
+        channel: &BareJid,
+        codes: &[u16],
+    ) {
+        let key = (account.clone(), channel.clone());
+        let previous = self.anonymity.get(&key).copied();
+        let anonymity = match Self::announced_anonymity(codes, previous.is_none()) {
+            Some(anonymity) if Some(anonymity) != previous => anonymity,
+            _ => return,
+        };
+
+        self.anonymity.insert(key, anonymity);
+        if previous.is_some() {
+            crate::info!(aparte, "{channel} is now {anonymity}");
+        }
+        aparte.schedule(Event::ChannelAnonymity {
+            account: account.clone(),
+            channel: channel.clone(),
+            anonymity,
+        });
+    }
+
+    /// Tell who removed an occupant and why, and rejoin channels we were kicked from if enabled
+    fn handle_removal(
+        &mut self,
+        aparte: &mut Aparte,
+        account: &Account,
+        from: &FullJid,
+        presence: &Presence,
+        codes: &[u16],
+    ) {
+        let channel = from.to_bare();
+        let own = codes.contains(&110);
+        let banned = codes.contains(&301);
+
+        let item = presence
+            .payloads
+            .iter()
+            .filter(|payload| payload.is("x", ns::MUC_USER))
+            .find_map(|payload| payload.get_child("item", ns::MUC_USER));
+        let actor = item
+            .and_then(|item| item.get_child("actor", ns::MUC_USER))
+            .and_then(|actor| actor.attr("nick").or(actor.attr("jid")));
+        let reason = item
+            .and_then(|item| item.get_child("reason", ns::MUC_USER))
+            .map(|reason| reason.text())
+            .filter(|reason| !reason.is_empty());
+
+        let mut body = match own {
+            true => String::from("You have been"),
+            false => format!("{} has been", from.resource()),
+        };
+        body.push_str(match banned {
+            true => " banned",
+            false => " kicked",
+        });
+        if let Some(actor) = actor {
+            body.push_str(&format!(" by {actor}"));
+        }
+        if let Some(reason) = reason {
+            body.push_str(&format!(": {reason}"));
+        }
+        aparte.schedule(Event::ConversationLog {
+            account: account.clone(),
+            conversation: channel.clone(),
+            message: LogMessage {
+                id: Uuid::new_v4().to_string(),
+                timestamp: LocalTz::now().into(),
+                body,
+                level: log::Level::Warn,
+            },
+        });
+
+        let base = match aparte.config.channel_rejoin_delay {
+            Some(base) if own && !banned => Duration::from_secs(base),
+            _ => return,
+        };
+        let key = (account.clone(), channel.clone());
+        let count = match self.kicks.get(&key) {
+            Some((count, last)) if last.elapsed() < REJOIN_BACKOFF_RESET => count + 1,
+            _ => 0,
+        };
+        self.kicks.insert(key, (count, Instant::now()));
+        let delay = base
+            .checked_mul(2u32.saturating_pow(count))
+            .unwrap_or(REJOIN_MAX_DELAY)
+            .min(REJOIN_MAX_DELAY);
+
+        crate::info!(aparte, "Rejoining {channel} in {}s", delay.as_secs());
+        Aparte::spawn({
+            let mut aparte = aparte.proxy();
+            let account = account.clone();
+            let from = from.clone();
+            async move {
+                tokio::time::sleep(delay).await;
+                aparte.schedule(Event::Join {
+                    account,
+                    channel: Jid::Full(from),
+                    user_request: false,
+                });
+            }
+        });
+    }
+
+    /// Nickname to try after `attempt` collisions, counting suffixes with `{n}`
+    /// several times
+    fn nick_candidate(suffixes: &[String], nick: &str, attempt: usize) -> Option<String> {
+        let mut remaining = attempt;
+        for suffix in suffixes {
+            if suffix.contains("{n}") {
+                if remaining < NUMBERED_NICK_ATTEMPTS {
+                    let n = (remaining + 2).to_string();
+                    return Some(format!("{nick}{}", suffix.replace("{n}", &n)));
+                }
+                remaining -= NUMBERED_NICK_ATTEMPTS;
+            } else if remaining == 0 {
+                return Some(format!("{nick}{suffix}"));
+            } else {
+                remaining -= 1;
+            }
+        }
+        None
+    }
+
+    /// Join again with another nickname when ours is taken in a channel being joined
+    fn handle_join_error(
+        &mut self,
+        aparte: &mut Aparte,
+        account: &Account,
+        from: &FullJid,
+        presence: &Presence,
+    ) {
+        let key = (account.clone(), from.to_bare());
+        let (nick, attempt) = match self.joining.remove(&key) {
+            Some(joining) => joining,
+            None => return,
+        };
+        let conflict = presence
+            .payloads
+            .iter()
+            .filter_map(|payload| StanzaError::try_from(payload.clone()).ok())
+            .any(|error| error.defined_condition == DefinedCondition::Conflict);
+        if !conflict {
+            return;
+        }
+
+        let suffixes = match &aparte.config.nick_suffixes {
+            Some(suffixes) => suffixes.clone(),
+            None => DEFAULT_NICK_SUFFIXES
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+        };
+        let channel = from.to_bare();
+        let candidate = match Self::nick_candidate(&suffixes, &nick, attempt) {
+            Some(candidate) => candidate,
+            None => {
+                let err = anyhow!("nickname {} is taken", from.resource());
+                crate::error!(aparte, err, "Cannot join {channel}");
+                return;
+            }
+        };
+        match channel.with_resource_str(&candidate) {
+            Ok(to) => {
+                log::info!(
+                    "Nickname {} taken in {channel}, trying {candidate}",
+                    from.resource()
+                );
+                self.joining.insert(key, (nick, attempt + 1));
+                aparte.schedule(Event::Join {
+                    account: account.clone(),
+                    channel: Jid::Full(to),
+                    user_request: false,
+                });
+            }
+            Err(err) => {
+                let err = anyhow::Error::from(err);
+                crate::error!(aparte, err, "Cannot join {channel} as {candidate}")
+            }
+        }
+    }
+
+    /// Report the nickname finally used when joining required another one
+    fn handle_joined(&mut self, aparte: &mut Aparte, account: &Account, from: &FullJid) {
+        let channel = from.to_bare();
+        if let Some((nick, attempt)) = self.joining.remove(&(account.clone(), channel.clone())) {
+            if attempt > 0 {
+                aparte.schedule(Event::ConversationLog {
+                    account: account.clone(),
+                    conversation: channel,
+                    message: LogMessage {
+                        id: Uuid::new_v4().to_string(),
+                        timestamp: LocalTz::now().into(),
+                        body: format!("Nickname {nick} is taken, joined as {}", from.resource()),
+                        level: log::Level::Info,
+                    },
+                });
+            }
+        }
+    }
+
+    fn voice_request_form() -> DataForm {
+        DataForm {
+            type_: DataFormType::Submit,
+            form_type: Some(String::from(NS_MUC_REQUEST)),
+            title: None,
+            instructions: None,
+            fields: vec![Field {
+                var: String::from("muc#role"),
+                type_: FieldType::ListSingle,
+                label: None,
+                required: false,
+                media: vec![],
+                options: vec![],
+                values: vec![String::from("participant")],
+            }],
+        }
+    }
+
+    fn voice_requesters(&self, account: &Account, channel: &BareJid) -> Vec<String> {
+        self.voice_requests
+            .get(&(account.clone(), channel.clone()))
+            .map(|requests| {
+                requests
+                    .iter()
+                    .map(|request| request.nick.clone())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn take_voice_request(
+        &mut self,
+        account: &Account,
+        channel: &BareJid,
+        nick: &str,
+    ) -> Option<VoiceRequest> {
+        let requests = self
+            .voice_requests
+            .get_mut(&(account.clone(), channel.clone()))?;
+        let index = requests.iter().position(|request| request.nick == nick)?;
+        Some(requests.remove(index))
+    }
+
+    fn voice_request(message: &XmppParsersMessage) -> Option<DataForm> {
+        message
+            .payloads
+            .iter()
+            .filter_map(|payload| DataForm::try_from(payload.clone()).ok())
+            .find(|form| {
+                form.type_ == DataFormType::Form
+                    && form.form_type.as_deref() == Some(NS_MUC_REQUEST)
+            })
+    }
+
+    fn handle_voice_request(
+        &mut self,
+        aparte: &mut Aparte,
+        account: &Account,
+        channel: BareJid,
+        form: DataForm,
+    ) {
+        let value = |var: &str| {
+            form.fields
+                .iter()
+                .find(|field| field.var == var)
+                .and_then(|field| field.values.first().cloned())
+        };
+        let nick = match value("muc#roomnick") {
+            Some(nick) => nick,
+            None => {
+                log::warn!("Voice request without nick in {}", channel);
+                return;
+            }
+        };
+        let requester = match value("muc#jid") {
+            Some(jid) => format!("{nick} ({jid})"),
+            None => nick.clone(),
+        };
+
+        crate::info!(
+            aparte,
+            "{requester} requests voice in {channel}, use /voice approve {nick} or /voice deny {nick}"
+        );
+
+        let requests = self
+            .voice_requests
+            .entry((account.clone(), channel))
+            .or_default();
+        requests.retain(|request| request.nick != nick);
+        requests.push(VoiceRequest { nick, form });
+    }
+
+    fn member_jids(&self, account: &Account, channel: &BareJid) -> Vec<String> {
+        self.members
+            .get(&(account.clone(), channel.clone()))
+            .map(|members| {
+                members
+                    .iter()
+                    .map(|member| member.jid.to_string())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn admin_iq(channel: &BareJid, payload: IqType) -> Iq {
+        Iq {
+            from: None,
+            to: Some(Jid::Bare(channel.clone())),
+            id: Uuid::new_v4().hyphenated().to_string(),
+            payload,
+        }
+    }
+
+    /// Log the disco info and avatar of a channel in its window
+    fn show_info(aparte: &Aparte, account: &Account, channel: &BareJid) {
+        Aparte::spawn({
+            let mut aparte = aparte.proxy();
+            let account = account.clone();
+            let channel = channel.clone();
+            async move {
+                let id = Uuid::new_v4().hyphenated().to_string();
+                let iq = Iq::from_get(id, disco::DiscoInfoQuery { node: None })
+                    .with_to(Jid::Bare(channel.clone()));
+                let info = match Self::get_info(&mut aparte, &account, iq).await {
+                    Ok(info) => info,
+                    Err(err) => {
+                        crate::error!(aparte, err, "Cannot get information of {channel}");
+                        return;
+                    }
+                };
+                // Channels without avatar are common, don't report it
+                let avatar = AvatarMod::fetch_channel_avatar(&mut aparte, &account, &channel)
+                    .await
+                    .ok();
+
+                aparte.schedule(Event::ConversationLog {
+                    account,
+                    conversation: channel.clone(),
+                    message: LogMessage {
+                        id: String::from("room-info"),
+                        timestamp: LocalTz::now().into(),
+                        body: Self::format_info(&channel, &info, avatar),
+                        level: log::Level::Info,
+                    },
+                });
+            }
+        });
+    }
+
+    async fn get_info(
+        aparte: &mut AparteAsync,
+        account: &Account,
+        iq: Iq,
+    ) -> Result<disco::DiscoInfoResult> {
+        match aparte.iq(account, iq).await?.payload {
+            IqType::Result(Some(el)) => Ok(disco::DiscoInfoResult::try_from(el)?),
+            IqType::Error(err) => Err(anyhow!("{}", i18n::xmpp_err_to_string(&err, vec![]).1)),
+            _ => Err(anyhow!("invalid response")),
+        }
+    }
+
+    fn format_info(
+        channel: &BareJid,
+        info: &disco::DiscoInfoResult,
+        avatar: Option<(String, usize)>,
+    ) -> String {
+        let mut output = format!("Information of {channel}:");
+        if let Some(name) = info
+            .identities
+            .iter()
+            .find_map(|identity| identity.name.clone())
+        {
+            output.push_str(&format!("\n  Name: {name}"));
+        }
+
+        let roominfo = info
+            .extensions
+            .iter()
+            .find(|form| form.form_type.as_deref() == Some(NS_MUC_ROOMINFO));
+        if let Some(roominfo) = roominfo {
+            for (var, label) in ROOMINFO_FIELDS {
+                let field = roominfo.fields.iter().find(|field| field.var == var);
+                if let Some(field) = field.filter(|field| !field.values.is_empty()) {
+                    output.push_str(&format!("\n  {label}: {}", field.values.join(", ")));
+                }
+            }
+        }
+
+        if let Some((mime_type, size)) = avatar {
+            output.push_str(&format!("\n  Avatar: {mime_type}, {size} bytes"));
+        }
+
+        let features = ROOM_FEATURES
+            .iter()
+            .filter(|(feature, _)| info.features.iter().any(|f| f.var == *feature))
+            .map(|(_, label)| *label)
+            .collect::<Vec<_>>();
+        if !features.is_empty() {
+            output.push_str(&format!("\n  Features: {}", features.join(", ")));
+        }
+        output
+    }
+
+    fn fetch_members(
+        aparte: &Aparte,
+        account: &Account,
+        channel: &BareJid,
+        filter: Option<String>,
+    ) {
+        Aparte::spawn({
+            let mut aparte = aparte.proxy();
+            let account = account.clone();
+            let channel = channel.clone();
+            async move {
+                match Self::get_members(&mut aparte, &account, &channel).await {
+                    Ok(members) => aparte.schedule(Event::RoomMembers {
+                        account,
+                        channel,
+                        members,
+                        filter,
+                    }),
+                    Err(err) => crate::error!(aparte, err, "Cannot list members of {channel}"),
+                }
+            }
+        });
+    }
+
+    async fn get_members(
+        aparte: &mut AparteAsync,
+        account: &Account,
+        channel: &BareJid,
+    ) -> Result<Vec<RoomMember>> {
+        let mut members = Vec::new();
+        let mut error = None;
+
+        for affiliation in MEMBER_AFFILIATIONS.iter() {
+            // Admins are usually not allowed to list owners, keep other lists anyway
+            match Self::get_affiliated(aparte, account, channel, affiliation).await {
+                Ok(affiliated) => members.extend(affiliated),
+                Err(err) => {
+                    log::warn!("Cannot list {} of {}: {}", affiliation, channel, err);
+                    error = Some(err);
+                }
+            }
+        }
+
+        match error {
+            Some(err) if members.is_empty() => Err(err),
+            _ => Ok(members),
+        }
+    }
+
+    async fn get_affiliated(
+        aparte: &mut AparteAsync,
+        account: &Account,
+        channel: &BareJid,
+        affiliation: &Affiliation,
+    ) -> Result<Vec<RoomMember>> {
+        let query = Element::builder("query", NS_MUC_ADMIN)
+            .append(
+                Element::builder("item", NS_MUC_ADMIN)
+                    .attr("affiliation", affiliation.to_string())
+                    .build(),
+            )
+            .build();
+        let iq = Self::admin_iq(channel, IqType::Get(query));
+        let query = match aparte.iq(account, iq).await?.payload {
+            IqType::Result(Some(el)) => el,
+            IqType::Result(None) => return Ok(Vec::new()),
+            IqType::Error(err) => {
+                return Err(anyhow!("{}", i18n::xmpp_err_to_string(&err, vec![]).1))
+            }
+            _ => return Err(anyhow!("invalid response")),
+        };
+
+        Ok(query
+            .children()
+            .filter(|item| item.is("item", NS_MUC_ADMIN))
+            .filter_map(|item| {
+                let jid = BareJid::from_str(item.attr("jid")?).ok()?;
+                Some(RoomMember {
+                    jid,
+                    affiliation: *affiliation,
+                    nick: item.attr("nick").map(String::from),
+                    reason: item
+                        .get_child("reason", NS_MUC_ADMIN)
+                        .map(|reason| reason.text()),
+                })
+            })
+            .collect())
+    }
+
+    fn change_affiliation(
+        aparte: &Aparte,
+        account: &Account,
+        channel: &BareJid,
+        jid: &BareJid,
+        affiliation: Affiliation,
+        reason: Option<String>,
+    ) {
+        let mut item = Element::builder("item", NS_MUC_ADMIN)
+            .attr("affiliation", affiliation.to_string())
+            .attr("jid", jid.to_string());
+        if let Some(reason) = reason {
+            item = item.append(
+                Element::builder("reason", NS_MUC_ADMIN)
+                    .append(reason)
+                    .build(),
+            );
+        }
+        let query = Element::builder("query", NS_MUC_ADMIN)
+            .append(item.build())
+            .build();
+        let iq = Self::admin_iq(channel, IqType::Set(query));
+
+        Aparte::spawn({
+            let mut aparte = aparte.proxy();
+            let account = account.clone();
+            let channel = channel.clone();
+            let jid = jid.clone();
+            async move {
+                match aparte.iq(&account, iq).await {
+                    Ok(Iq {
+                        payload: IqType::Result(_),
+                        ..
+                    }) => {
+                        crate::info!(aparte, "{jid} is now {affiliation} of {channel}");
+                        match Self::get_members(&mut aparte, &account, &channel).await {
+                            Ok(members) => aparte.schedule(Event::RoomMembers {
+                                account,
+                                channel,
+                                members,
+                                filter: None,
+                            }),
+                            Err(err) => {
+                                crate::error!(aparte, err, "Cannot list members of {channel}")
+                            }
+                        }
+                    }
+                    Ok(Iq {
+                        payload: IqType::Error(err),
+                        ..
+                    }) => {
+                        let err = anyhow!("{}", i18n::xmpp_err_to_string(&err, vec![]).1);
+                        crate::error!(aparte, err, "Cannot change affiliation of {jid}")
+                    }
+                    Ok(_) => {
+                        let err = anyhow!("invalid response");
+                        crate::error!(aparte, err, "Cannot change affiliation of {jid}")
+                    }
+                    Err(err) => crate::error!(aparte, err, "Cannot change affiliation of {jid}"),
+                }
+            }
+        });
+    }
+
+    /// Join a channel with the nickname reserved in it, or with the account
+    /// node when no nickname is reserved
+    pub fn join(aparte: &Aparte, account: &Account, channel: &BareJid, user_request: bool) {
+        Aparte::spawn({
+            let mut aparte = aparte.proxy();
+            let account = account.clone();
+            let channel = channel.clone();
+            async move {
+                let nick = match Self::get_reserved_nick(&mut aparte, &account, &channel).await {
+                    Ok(Some(nick)) => nick,
+                    Ok(None) => account.node().clone().unwrap().to_string(),
+                    Err(err) => {
+                        log::info!("Cannot get reserved nick in {}: {}", channel, err);
+                        account.node().clone().unwrap().to_string()
+                    }
+                };
+
+                match channel.with_resource_str(&nick) {
+                    Ok(channel) => aparte.schedule(Event::Join {
+                        account,
+                        channel: Jid::Full(channel),
+                        user_request,
+                    }),
+                    Err(err) => {
+                        let err = anyhow::Error::from(err);
+                        crate::error!(aparte, err, "Cannot join {channel} as {nick}")
+                    }
+                }
+            }
+        });
+    }
+
+    async fn get_reserved_nick(
+        aparte: &mut AparteAsync,
+        account: &Account,
+        channel: &BareJid,
+    ) -> Result<Option<String>> {
+        let id = Uuid::new_v4().hyphenated().to_string();
+        let iq = Iq::from_get(
+            id,
+            disco::DiscoInfoQuery {
+                node: Some(String::from(NODE_ROOMUSER_ITEM)),
+            },
+        )
+        .with_to(Jid::Bare(channel.clone()));
+        match aparte.iq(account, iq).await?.payload {
+            IqType::Result(Some(el)) => Ok(disco::DiscoInfoResult::try_from(el)?
+                .identities
+                .into_iter()
+                .find_map(|identity| identity.name)),
+            IqType::Result(None) => Ok(None),
+            IqType::Error(err) => Err(anyhow!("{}", i18n::xmpp_err_to_string(&err, vec![]).1)),
+            _ => Err(anyhow!("invalid response")),
+        }
+    }
+
+    /// Remember which channel a form is about until the user answers it
+    fn track_form(
+        aparte: &mut Aparte,
+        kind: RoomForm,
+        account: &Account,
+        channel: &BareJid,
+    ) -> String {
+        let id = match kind {
+            RoomForm::Registration => format!("muc-register/{account}/{channel}"),
+            RoomForm::Configuration => format!("muc-config/{account}/{channel}"),
+        };
+        aparte
+            .get_mod_mut::<MucMod>()
+            .forms
+            .insert(id.clone(), (kind, account.clone(), channel.clone()));
+        id
+    }
+
+    fn register(aparte: &mut Aparte, account: &Account, channel: &BareJid, nick: &str) {
+        let id = Self::track_form(aparte, RoomForm::Registration, account, channel);
+        Aparte::spawn({
+            let mut aparte = aparte.proxy();
+            let account = account.clone();
+            let channel = channel.clone();
+            let nick = nick.to_string();
+            async move {
+                match Self::get_registration_form(&mut aparte, &account, &channel).await {
+                    Ok(form) => aparte.schedule(Event::ShowForm {
+                        id,
+                        form: Self::registration_form(form, &nick),
+                    }),
+                    Err(err) => crate::error!(aparte, err, "Cannot register in {channel}"),
+                }
+            }
+        });
+    }
+
+    fn configure(aparte: &mut Aparte, account: &Account, channel: &BareJid) {
+        let id = Self::track_form(aparte, RoomForm::Configuration, account, channel);
+        Aparte::spawn({
+            let mut aparte = aparte.proxy();
+            let account = account.clone();
+            let channel = channel.clone();
+            async move {
+                match Self::owner_query(&mut aparte, &account, &channel, None).await {
+                    Ok(Some(form)) => aparte.schedule(Event::ShowForm { id, form }),
+                    Ok(None) => crate::info!(aparte, "{channel} has no configuration form"),
+                    Err(err) => crate::error!(aparte, err, "Cannot configure {channel}"),
+                }
+            }
+        });
+    }
+
+    /// Send the form filled by the user back to the channel, or tell it was canceled
+    fn submit_form(
+        aparte: &mut Aparte,
+        kind: RoomForm,
+        account: Account,
+        channel: BareJid,
+        form: Option<DataForm>,
+    ) {
+        Aparte::spawn({
+            let mut aparte = aparte.proxy();
+            async move {
+                match (kind, form) {
+                    (RoomForm::Registration, None) => {
+                        crate::info!(aparte, "Registration in {channel} canceled")
+                    }
+                    (RoomForm::Registration, Some(form)) => {
+                        match Self::register_form(&mut aparte, &account, &channel, form).await {
+                            Ok(()) => crate::info!(aparte, "Registered in {channel}"),
+                            Err(err) => crate::error!(aparte, err, "Cannot register in {channel}"),
+                        }
+                    }
+                    (RoomForm::Configuration, form) => {
+                        let canceled = form.is_none();
+                        // The channel has to be told, a new channel stays locked until configured
+                        let form = form.unwrap_or(DataForm {
+                            type_: DataFormType::Cancel,
+                            form_type: None,
+                            title: None,
+                            instructions: None,
+                            fields: vec![],
+                        });
+                        match Self::owner_query(&mut aparte, &account, &channel, Some(form)).await {
+                            Ok(_) if canceled => {
+                                crate::info!(aparte, "Configuration of {channel} canceled")
+                            }
+                            Ok(_) => crate::info!(aparte, "Configuration of {channel} saved"),
+                            Err(err) => crate::error!(aparte, err, "Cannot configure {channel}"),
+                        }
+                    }
+                }
+            }
+        });
+    }
+
+    async fn get_registration_form(
+        aparte: &mut AparteAsync,
+        account: &Account,
+        channel: &BareJid,
+    ) -> Result<Option<DataForm>> {
+        let query = ibr::Query {
+            fields: HashMap::new(),
+            registered: false,
+            remove: false,
+            form: None,
+        };
+        let id = Uuid::new_v4().hyphenated().to_string();
+        let iq = Iq::from_get(id, query).with_to(Jid::Bare(channel.clone()));
+        match aparte.iq(account, iq).await?.payload {
+            IqType::Result(Some(el)) => Ok(ibr::Query::try_from(el)?.form),
+            IqType::Error(err) => Err(anyhow!("{}", i18n::xmpp_err_to_string(&err, vec![]).1)),
+            _ => Err(anyhow!("invalid response")),
+        }
+    }
+
+    async fn register_form(
+        aparte: &mut AparteAsync,
+        account: &Account,
+        channel: &BareJid,
+        form: DataForm,
+    ) -> Result<()> {
+        let query = ibr::Query {
+            fields: HashMap::new(),
+            registered: false,
+            remove: false,
+            form: Some(form),
+        };
+        let id = Uuid::new_v4().hyphenated().to_string();
+        let iq = Iq::from_set(id, query).with_to(Jid::Bare(channel.clone()));
+        match aparte.iq(account, iq).await?.payload {
+            IqType::Result(_) => Ok(()),
+            IqType::Error(err) => Err(anyhow!("{}", i18n::xmpp_err_to_string(&err, vec![]).1)),
+            _ => Err(anyhow!("invalid response")),
+        }
+    }
+
+    /// Get the configuration form of the channel, or set it when a form is given
+    async fn owner_query(
+        aparte: &mut AparteAsync,
+        account: &Account,
+        channel: &BareJid,
+        form: Option<DataForm>,
+    ) -> Result<Option<DataForm>> {
+        let query = Element::builder("query", NS_MUC_OWNER);
+        let payload = match form {
+            Some(form) => IqType::Set(query.append(Element::from(form)).build()),
+            None => IqType::Get(query.build()),
+        };
+        let iq = Iq {
+            from: None,
+            to: Some(Jid::Bare(channel.clone())),
+            id: Uuid::new_v4().hyphenated().to_string(),
+            payload,
+        };
+        match aparte.iq(account, iq).await?.payload {
+            IqType::Result(Some(el)) => match el.get_child("x", ns::DATA_FORMS) {
+                Some(form) => Ok(Some(DataForm::try_from(form.clone())?)),
+                None => Ok(None),
+            },
+            IqType::Result(None) => Ok(None),
+            IqType::Error(err) => Err(anyhow!("{}", i18n::xmpp_err_to_string(&err, vec![]).1)),
+            _ => Err(anyhow!("invalid response")),
+        }
+    }
+
+    /// Registration form sent by the room, with the requested nickname filled in
+    fn registration_form(form: Option<DataForm>, nick: &str) -> DataForm {
+        let (title, instructions, mut fields) = match form {
+            Some(form) => (form.title, form.instructions, form.fields),
+            None => (None, None, vec![]),
+        };
+        match fields
+            .iter_mut()
+            .find(|field| field.var == "muc#register_roomnick")
+        {
+            Some(field) => field.values = vec![nick.to_string()],
+            None => fields.push(Field {
+                var: String::from("muc#register_roomnick"),
+                type_: FieldType::TextSingle,
+                label: Some(String::from("Nickname")),
+                required: true,
+                media: vec![],
+                options: vec![],
+                values: vec![nick.to_string()],
+            }),
+        }
+
+        DataForm {
+            type_: DataFormType::Form,
+            form_type: Some(String::from(NS_MUC_REGISTER)),
+            title,
+            instructions,
+            fields,
+        }
+    }
+}
+
+impl ModTrait for MucMod {
+    fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
+        aparte.add_command(room::new());
+        aparte.add_command(voice::new());
+        aparte.add_command(topic::new());
+        Ok(())
+    }
+
+    fn can_handle_xmpp_message(
+        &mut self,
+        _aparte: &mut Aparte,
+        _account: &Account,
+        message: &XmppParsersMessage,
+        _delay: &Option<Delay>,
+    ) -> f64 {
+        let anonymity_change = message.bodies.is_empty()
+            && Self::announced_anonymity(&Self::status_codes(&message.payloads), false).is_some();
+        match Self::voice_request(message) {
+            Some(_) => 1f64,
+            None if anonymity_change => 1f64,
+            None => 0f64,
+        }
+    }
+
+    fn handle_xmpp_message(
+        &mut self,
+        aparte: &mut Aparte,
+        account: &Account,
+        message: &XmppParsersMessage,
+        _delay: &Option<Delay>,
+        archive: bool,
+    ) {
+        // Archived requests have most likely already been handled
+        if archive {
+            return;
+        }
+
+        if let (Some(Jid::Bare(channel)), Some(form)) =
+            (message.from.clone(), Self::voice_request(message))
+        {
+            self.handle_voice_request(aparte, account, channel, form);
+        } else if let Some(Jid::Bare(channel)) = &message.from {
+            let codes = Self::status_codes(&message.payloads);
+            self.update_anonymity(aparte, account, channel, &codes);
+        }
+    }
+
+    fn on_event(&mut self, aparte: &mut Aparte, event: &Event) {
+        match event {
+            Event::RoomMembers {
+                account,
+                channel,
+                members,
+                ..
+            } => {
+                self.members
+                    .insert((account.clone(), channel.clone()), members.clone());
+            }
+            Event::Join {
+                account,
+                channel: Jid::Full(channel),
+                ..
+            } => {
+                // Retries keep the nickname asked first
+                self.joining
+                    .entry((account.clone(), channel.to_bare()))
+                    .or_insert_with(|| (channel.resource().to_string(), 0));
+            }
+            Event::Presence(account, presence) => {
+                if let Some(Jid::Full(from)) = &presence.from {
+                    if presence.type_ == PresenceType::Error {
+                        self.handle_join_error(aparte, account, from, presence);
+                        return;
+                    }
+                    let codes = Self::status_codes(&presence.payloads);
+                    if codes.contains(&110) {
+                        self.handle_joined(aparte, account, from);
+                    }
+                    if codes.contains(&307) || codes.contains(&301) {
+                        self.handle_removal(aparte, account, from, presence, &codes);
+                    } else if codes.contains(&110) {
+                        self.update_anonymity(aparte, account, &from.to_bare(), &codes);
+                    }
+                }
+            }
+            Event::Leave(channel) => {
+                let key = (channel.account.clone(), channel.jid.clone());
+                self.anonymity.remove(&key);
+                self.joining.remove(&key);
+            }
+            Event::FormSubmit { id, form } => {
+                if let Some((kind, account, channel)) = self.forms.remove(id) {
+                    Self::submit_form(aparte, kind, account, channel, form.clone());
+                }
+            }
+            Event::Disconnected(account, _) => {
+                self.forms
+                    .retain(|_, (_, form_account, _)| form_account != account);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl fmt::Display for MucMod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Multi-user chat")
+    }
+}
@@ -0,0 +1,112 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::Result;
+use xmpp_parsers::nick::Nick;
+use xmpp_parsers::pubsub::PubSubEvent;
+use xmpp_parsers::{ns, Element, Jid};
+
+use crate::account::Account;
+use crate::core::{Aparte, Event, ModTrait};
+use crate::mods::disco;
+use crate::mods::pubsub::PubSubMod;
+
+/// XEP-0172: User Nickname
+///
+/// The nickname configured for an account is published on connection, and nicknames published by
+/// contacts are used to name those without a name in the roster.
+pub struct NickMod {}
+
+impl NickMod {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    /// Nickname configured for an account
+    fn configured(aparte: &Aparte, account: &Account) -> Option<String> {
+        aparte
+            .config
+            .accounts
+            .values()
+            .find(|info| {
+                Jid::from_str(&info.jid).map(|jid| jid.to_bare()).ok() == Some(account.to_bare())
+            })
+            .and_then(|info| info.nick.clone())
+    }
+
+    /// Last nickname found in published items, none when it is empty
+    fn published(items: &[Element]) -> Option<String> {
+        items
+            .iter()
+            .filter_map(|payload| Nick::try_from(payload.clone()).ok())
+            .last()
+            .map(|nick| nick.0.trim().to_string())
+            .filter(|nick| !nick.is_empty())
+    }
+}
+
+impl ModTrait for NickMod {
+    fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
+        let mut disco = aparte.get_mod_mut::<disco::DiscoMod>();
+        disco.add_feature(format!("{}+notify", ns::NICK));
+        Ok(())
+    }
+
+    fn on_event(&mut self, aparte: &mut Aparte, event: &Event) {
+        match event {
+            Event::Connected(account, _) => {
+                if let Some(nick) = Self::configured(aparte, account) {
+                    Aparte::spawn({
+                        let mut aparte = aparte.proxy();
+                        let account = account.clone();
+                        async move {
+                            let nick = Nick(nick).into();
+                            if let Err(err) =
+                                PubSubMod::publish(&mut aparte, &account, ns::NICK, nick).await
+                            {
+                                crate::error!(aparte, err, "Cannot publish nickname");
+                            }
+                        }
+                    });
+                }
+            }
+            Event::PubSub {
+                account,
+                from: Some(from),
+                event: PubSubEvent::PublishedItems { node, items },
+            } if node.0 == ns::NICK => {
+                let items = items
+                    .iter()
+                    .filter_map(|item| item.0.payload.clone())
+                    .collect::<Vec<_>>();
+                aparte.schedule(Event::Nickname {
+                    account: account.clone(),
+                    jid: from.to_bare(),
+                    nick: Self::published(&items),
+                });
+            }
+            Event::PubSub {
+                account,
+                from: Some(from),
+                event: PubSubEvent::RetractedItems { node, .. },
+            } if node.0 == ns::NICK => {
+                aparte.schedule(Event::Nickname {
+                    account: account.clone(),
+                    jid: from.to_bare(),
+                    nick: None,
+                });
+            }
+            _ => {}
+        }
+    }
+}
+
+impl fmt::Display for NickMod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "XEP-0172: User Nickname")
+    }
+}
@@ -0,0 +1,241 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, FixedOffset, Local as LocalTz};
+use uuid::Uuid;
+use xmpp_parsers::iq::{Iq, IqType};
+use xmpp_parsers::{BareJid, Element};
+
+use crate::account::Account;
+use crate::command::{Command, CommandParser};
+use crate::contact::Note;
+use crate::core::{Aparte, AparteAsync, Event, ModTrait};
+use crate::i18n;
+use crate::mods::contact::ContactMod;
+
+/// XEP-0049: Private XML Storage
+const NS_PRIVATE: &str = "jabber:iq:private";
+const NS_ROSTERNOTES: &str = "storage:rosternotes";
+
+command_def!(note,
+r#"/note <jid> [<text>]
+
+    jid     jid of the contact
+    text    note to keep about the contact, an empty one removes it
+
+Description:
+    Keep a private note about a contact, stored on the server so that it is
+    shared with other clients of the account. The note is shown when no text
+    is given, and by /whois and /contact show.
+
+Examples:
+    /note contact@server.tld "Met at the conference"
+    /note contact@server.tld
+    /note contact@server.tld ""
+"#,
+{
+    jid: String = {
+        completion: |aparte, _command| {
+            aparte
+                .get_mod::<ContactMod>()
+                .iter()
+                .map(|(_, contact)| contact.jid.to_string())
+                .collect()
+        }
+    },
+    text: Option<String>,
+},
+|aparte, _command| {
+    let account = aparte.current_account().context("No connection found")?;
+    let jid = BareJid::from_str(&jid).with_context(|| format!("Invalid contact {jid}"))?;
+    let text = match text {
+        Some(text) => text,
+        None => {
+            let note = aparte.get_mod::<NotesMod>().get(&account, &jid).map(|note| note.text.clone());
+            match note {
+                Some(note) => crate::info!(aparte, "Note on {jid}: {note}"),
+                None => crate::info!(aparte, "No note on {jid}"),
+            }
+            return Ok(());
+        }
+    };
+
+    Aparte::spawn({
+        let mut aparte = aparte.proxy();
+        async move {
+            match NotesMod::save(&mut aparte, &account, &jid, text.trim()).await {
+                Ok(true) => crate::info!(aparte, "Note on {jid} saved"),
+                Ok(false) => crate::info!(aparte, "Note on {jid} removed"),
+                Err(err) => crate::error!(aparte, err, "Cannot save note on {jid}"),
+            }
+        }
+    });
+    Ok(())
+});
+
+/// XEP-0145: Annotations
+///
+/// Notes are kept in private XML storage, which is replaced as a whole. They are fetched again
+/// before each change so that changes made from other clients are not lost.
+pub struct NotesMod {
+    notes: HashMap<Account, Vec<Note>>,
+}
+
+impl NotesMod {
+    pub fn new() -> Self {
+        Self {
+            notes: HashMap::new(),
+        }
+    }
+
+    pub fn get(&self, account: &Account, jid: &BareJid) -> Option<&Note> {
+        self.notes
+            .get(account)?
+            .iter()
+            .find(|note| &note.jid == jid)
+    }
+
+    fn query(storage: Element) -> Element {
+        Element::builder("query", NS_PRIVATE)
+            .append(storage)
+            .build()
+    }
+
+    fn to_element(notes: &[Note]) -> Element {
+        let mut storage = Element::builder("storage", NS_ROSTERNOTES);
+        for note in notes {
+            let mut element = Element::builder("note", NS_ROSTERNOTES)
+                .attr("jid", note.jid.to_string())
+                .append(note.text.as_str());
+            if let Some(cdate) = note.cdate {
+                element = element.attr("cdate", cdate.to_rfc3339());
+            }
+            if let Some(mdate) = note.mdate {
+                element = element.attr("mdate", mdate.to_rfc3339());
+            }
+            storage = storage.append(element.build());
+        }
+        storage.build()
+    }
+
+    fn from_element(storage: &Element) -> Vec<Note> {
+        let date = |element: &Element, name: &str| {
+            element
+                .attr(name)
+                .and_then(|date| DateTime::parse_from_rfc3339(date).ok())
+        };
+        storage
+            .children()
+            .filter(|child| child.is("note", NS_ROSTERNOTES))
+            .filter_map(|child| {
+                Some(Note {
+                    jid: BareJid::from_str(child.attr("jid")?).ok()?,
+                    text: child.text(),
+                    cdate: date(child, "cdate"),
+                    mdate: date(child, "mdate"),
+                })
+            })
+            .collect()
+    }
+
+    async fn get_notes(aparte: &mut AparteAsync, account: &Account) -> Result<Vec<Note>> {
+        let iq = Iq {
+            from: None,
+            to: None,
+            id: Uuid::new_v4().hyphenated().to_string(),
+            payload: IqType::Get(Self::query(
+                Element::builder("storage", NS_ROSTERNOTES).build(),
+            )),
+        };
+        match aparte.iq(account, iq).await?.payload {
+            IqType::Result(Some(query)) => Ok(query
+                .get_child("storage", NS_ROSTERNOTES)
+                .map(Self::from_element)
+                .unwrap_or_default()),
+            IqType::Result(None) => Ok(Vec::new()),
+            IqType::Error(err) => Err(anyhow!("{}", i18n::xmpp_err_to_string(&err, vec![]).1)),
+            _ => Err(anyhow!("Invalid notes response")),
+        }
+    }
+
+    /// Set or remove the note on a contact, telling whether it has been set
+    async fn save(
+        aparte: &mut AparteAsync,
+        account: &Account,
+        jid: &BareJid,
+        text: &str,
+    ) -> Result<bool> {
+        let mut notes = Self::get_notes(aparte, account).await?;
+        let now: DateTime<FixedOffset> = LocalTz::now().into();
+        if text.is_empty() {
+            notes.retain(|note| &note.jid != jid);
+        } else {
+            match notes.iter_mut().find(|note| &note.jid == jid) {
+                Some(note) => {
+                    note.text = text.to_string();
+                    note.mdate = Some(now);
+                }
+                None => notes.push(Note {
+                    jid: jid.clone(),
+                    text: text.to_string(),
+                    cdate: Some(now),
+                    mdate: Some(now),
+                }),
+            }
+        }
+
+        let iq = Iq {
+            from: None,
+            to: None,
+            id: Uuid::new_v4().hyphenated().to_string(),
+            payload: IqType::Set(Self::query(Self::to_element(&notes))),
+        };
+        if let IqType::Error(err) = aparte.iq(account, iq).await?.payload {
+            return Err(anyhow!("{}", i18n::xmpp_err_to_string(&err, vec![]).1));
+        }
+        aparte.schedule(Event::Notes(account.clone(), notes));
+        Ok(!text.is_empty())
+    }
+}
+
+impl ModTrait for NotesMod {
+    fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
+        aparte.add_command(note::new());
+        Ok(())
+    }
+
+    fn on_event(&mut self, aparte: &mut Aparte, event: &Event) {
+        match event {
+            Event::Connected(account, _) => {
+                Aparte::spawn({
+                    let mut aparte = aparte.proxy();
+                    let account = account.clone();
+                    async move {
+                        match Self::get_notes(&mut aparte, &account).await {
+                            Ok(notes) => aparte.schedule(Event::Notes(account, notes)),
+                            Err(err) => crate::error!(aparte, err, "Cannot get notes"),
+                        }
+                    }
+                });
+            }
+            Event::Notes(account, notes) => {
+                self.notes.insert(account.clone(), notes.clone());
+            }
+            Event::Disconnected(account, _) => {
+                self.notes.remove(account);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl fmt::Display for NotesMod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "XEP-0145: Annotations")
+    }
+}
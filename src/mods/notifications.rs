@@ -0,0 +1,256 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use xmpp_parsers::chatstates::ChatState;
+use xmpp_parsers::BareJid;
+
+use crate::account::Account;
+use crate::conversation::Conversation;
+use crate::core::{Aparte, BellReason, Event, ModTrait};
+use crate::mods::ui::UIMod;
+
+/// Quiet period after the last message of a conversation before notifying
+const BATCH_DELAY: Duration = Duration::from_secs(3);
+
+/// Longest time a notification is held back while the contact is composing
+const MAX_HOLD: Duration = Duration::from_secs(30);
+
+/// How new important messages are signaled
+enum Alert {
+    Bell,
+    /// Player command and sound file
+    Sound(String, String),
+    Silent,
+}
+
+/// Important messages of a conversation not notified yet
+struct Pending {
+    conversation: Conversation,
+    count: usize,
+    since: Instant,
+    /// Incremented on each message, only the timer armed by the last one notifies
+    generation: u64,
+}
+
+impl Pending {
+    fn summary(&self) -> String {
+        let jid = self.conversation.get_jid();
+        match (&self.conversation, self.count) {
+            (Conversation::Chat(_), 1) => format!("New message from {jid}"),
+            (Conversation::Chat(_), count) => format!("{count} new messages from {jid}"),
+            (Conversation::Channel(_), 1) => format!("New message in {jid}"),
+            (Conversation::Channel(_), count) => format!("{count} new messages in {jid}"),
+        }
+    }
+}
+
+/// Ring the bell or play a sound, and send desktop notifications for important messages, once per burst of
+/// messages and not while the contact is still typing
+pub struct NotificationsMod {
+    pending: HashMap<(Account, BareJid), Pending>,
+    /// Contacts currently composing a message
+    composing: HashSet<(Account, BareJid)>,
+    /// Channels with pending messages mentioning us, until they are notified or the channel window
+    /// is shown
+    mentioned: HashSet<(Account, BareJid)>,
+}
+
+impl NotificationsMod {
+    pub fn new() -> Self {
+        Self {
+            pending: HashMap::new(),
+            composing: HashSet::new(),
+            mentioned: HashSet::new(),
+        }
+    }
+
+    fn arm(aparte: &mut Aparte, account: Account, conversation: BareJid, generation: u64) {
+        Aparte::spawn({
+            let mut aparte = aparte.proxy();
+            async move {
+                tokio::time::sleep(BATCH_DELAY).await;
+                aparte.schedule(Event::NotificationTimeout {
+                    account,
+                    conversation,
+                    generation,
+                });
+            }
+        });
+    }
+
+    fn push(&mut self, aparte: &mut Aparte, conversation: &Conversation) {
+        let account = conversation.get_account().clone();
+        let jid = conversation.get_jid().clone();
+        let pending = self
+            .pending
+            .entry((account.clone(), jid.clone()))
+            .or_insert_with(|| Pending {
+                conversation: conversation.clone(),
+                count: 0,
+                since: Instant::now(),
+                generation: 0,
+            });
+        pending.count += 1;
+        pending.generation += 1;
+        let generation = pending.generation;
+
+        Self::arm(aparte, account, jid, generation);
+    }
+
+    fn timeout(&mut self, aparte: &mut Aparte, account: &Account, jid: &BareJid, generation: u64) {
+        let index = (account.clone(), jid.clone());
+        let held = match self.pending.get(&index) {
+            Some(pending) if pending.generation == generation => pending.since.elapsed(),
+            _ => return,
+        };
+
+        if self.composing.contains(&index) && held < MAX_HOLD {
+            // A follow-up is coming, it will be notified along
+            Self::arm(aparte, account.clone(), jid.clone(), generation);
+        } else {
+            self.notify(aparte, &index);
+        }
+    }
+
+    fn notify(&mut self, aparte: &mut Aparte, index: &(Account, BareJid)) {
+        let pending = match self.pending.remove(index) {
+            Some(pending) => pending,
+            None => return,
+        };
+        let reason = match self.mentioned.remove(index) {
+            true => BellReason::Mention,
+            false => BellReason::Message,
+        };
+
+        match Self::alert(aparte, pending.conversation.get_jid()) {
+            Alert::Bell => aparte.schedule(Event::Bell(reason)),
+            Alert::Sound(player, file) => Aparte::spawn(async move {
+                let status = tokio::process::Command::new(&player)
+                    .arg(&file)
+                    .status()
+                    .await;
+                if let Err(err) = status {
+                    log::warn!("Cannot play {file} with {player}: {err}");
+                }
+            }),
+            Alert::Silent => {}
+        }
+
+        Self::desktop_notify(aparte, pending.summary());
+    }
+
+    /// Run `notify_command` with this summary when it is configured
+    pub fn desktop_notify(aparte: &mut Aparte, summary: String) {
+        if let Some(command) = aparte.config.notify_command.clone() {
+            Aparte::spawn(async move {
+                let status = tokio::process::Command::new(&command)
+                    .arg("Aparté")
+                    .arg(summary)
+                    .status()
+                    .await;
+                if let Err(err) = status {
+                    log::warn!("Cannot run notify command {command}: {err}");
+                }
+            });
+        }
+    }
+
+    /// Sound of the conversation if any, the default one otherwise, the bell without player
+    fn alert(aparte: &Aparte, jid: &BareJid) -> Alert {
+        let config = &aparte.config;
+        let file = config
+            .sounds
+            .get(&jid.to_string())
+            .or(config.sound_file.as_ref());
+        match (file, &config.sound_command) {
+            (Some(file), _) if file.is_empty() => Alert::Silent,
+            (Some(file), Some(player)) => Alert::Sound(player.clone(), file.clone()),
+            _ => Alert::Bell,
+        }
+    }
+
+    fn handle_chat_state(
+        &mut self,
+        aparte: &mut Aparte,
+        account: &Account,
+        contact: &BareJid,
+        state: &ChatState,
+    ) {
+        let index = (account.clone(), contact.clone());
+        match state {
+            ChatState::Composing => {
+                self.composing.insert(index);
+            }
+            ChatState::Active => {
+                self.composing.remove(&index);
+            }
+            // The contact stopped typing without sending anything
+            ChatState::Paused | ChatState::Inactive | ChatState::Gone => {
+                self.composing.remove(&index);
+                self.notify(aparte, &index);
+            }
+        }
+    }
+}
+
+impl ModTrait for NotificationsMod {
+    fn init(&mut self, _aparte: &mut Aparte) -> Result<(), ()> {
+        Ok(())
+    }
+
+    fn on_event(&mut self, aparte: &mut Aparte, event: &Event) {
+        match event {
+            Event::Notification {
+                conversation,
+                important: true,
+            } => self.push(aparte, conversation),
+            Event::NotificationTimeout {
+                account,
+                conversation,
+                generation,
+            } => self.timeout(aparte, account, conversation, *generation),
+            Event::ContactChatState {
+                account,
+                contact,
+                state,
+            } => self.handle_chat_state(aparte, account, contact, state),
+            Event::Mention { account, message } => {
+                let current = aparte
+                    .get_mod::<UIMod>()
+                    .is_current_conversation(account, &message.from);
+                if !current {
+                    self.mentioned
+                        .insert((account.clone(), message.from.clone()));
+                }
+            }
+            // Mentions were seen, they must not make the next message of the channel ring
+            Event::WindowChanged(window) => {
+                let conversation = aparte
+                    .get_mod::<UIMod>()
+                    .window_conversation(window)
+                    .cloned();
+                if let Some(conversation) = conversation {
+                    self.mentioned.remove(&(
+                        conversation.get_account().clone(),
+                        conversation.get_jid().clone(),
+                    ));
+                }
+            }
+            Event::Disconnected(account, _) => {
+                self.composing.retain(|(composing, _)| composing != account);
+                self.mentioned.retain(|(mentioned, _)| mentioned != account);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl fmt::Display for NotificationsMod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Notifications")
+    }
+}
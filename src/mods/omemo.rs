@@ -8,8 +8,12 @@ use std::fmt::{self, Debug};
 use std::str::FromStr;
 
 use aes_gcm::{
-    aead::{Aead, AeadCore, KeyInit, OsRng},
-    Aes128Gcm,
+    aead::{
+        consts::{U12, U16},
+        Aead, AeadCore, KeyInit, OsRng,
+    },
+    aes::Aes256,
+    Aes128Gcm, Aes256Gcm, AesGcm,
 };
 use anyhow::{anyhow, Context, Result};
 use futures::future::FutureExt;
@@ -33,10 +37,10 @@ use xmpp_parsers::{BareJid, Jid};
 use crate::account::Account;
 use crate::command::{Command, CommandParser};
 use crate::core::{Aparte, AparteAsync, Event, ModTrait};
-use crate::i18n;
-//use crate::mods::disco::DiscoMod;
 use crate::crypto::CryptoEngineTrait;
+use crate::i18n;
 use crate::message::Message;
+use crate::mods::disco::DiscoMod;
 use crate::mods::ui::UIMod;
 use crate::storage::{OmemoOwnDevice, SignalStorage};
 
@@ -45,9 +49,30 @@ use libsignal_protocol::{
     SignedPreKeyStore,
 };
 
+/// Advertised by servers supporting PEP, on which OMEMO devices and bundles are published
+const NS_PUBSUB_PUBLISH: &str = "http://jabber.org/protocol/pubsub#publish";
+
 const KEY_SIZE: usize = 16;
 const MAC_SIZE: usize = 16;
 
+/// Number of prekeys published in our bundle
+const PRE_KEYS_COUNT: usize = 100;
+/// New prekeys are generated once fewer remain, before contacts run out of them
+const PRE_KEYS_THRESHOLD: usize = 20;
+
+/// Consecutive decryption failures from a device after which a new session is started
+const SESSION_RESET_FAILURES: u32 = 3;
+/// Sessions with a device are not reset more often, a device failing anyway shouldn't loop
+const SESSION_RESET_INTERVAL_MINUTES: i64 = 60;
+
+/// Scheme of URLs sharing a file encrypted with the key given in their fragment
+pub const AESGCM_SCHEME: &str = "aesgcm://";
+
+const MEDIA_KEY_SIZE: usize = 32;
+const MEDIA_IV_SIZE: usize = 12;
+// Older clients use 16 bytes IV, still accepted when decrypting
+const MEDIA_LEGACY_IV_SIZE: usize = 16;
+
 command_def!(omemo_enable,
 r#"/omemo enable [<jid>]
 
@@ -64,17 +89,12 @@ Examples:
     jid: Option<String>,
 },
 |aparte, _command| {
-    let current =  {
+    let target = {
         let ui = aparte.get_mod::<UIMod>();
-        ui.current_window().cloned()
+        ui.conversation_target(aparte, jid)
     };
-    let jid = jid.or(current).clone();
-    if let Some(jid) = jid {
-        if let Some(account) = aparte.current_account() {
-            if let Ok(jid) = BareJid::from_str(&jid) {
-                aparte.schedule(Event::Omemo(OmemoEvent::Enable { account, jid }));
-            }
-        }
+    if let Ok((account, jid)) = target {
+        aparte.schedule(Event::Omemo(OmemoEvent::Enable { account, jid }));
     }
     Ok(())
 });
@@ -93,18 +113,23 @@ Examples:
     jid: Option<String>,
 },
     |aparte, _command| {
-        let mut current = {
+        // Own fingerprints outside of conversation windows
+        let target = {
             let ui = aparte.get_mod::<UIMod>();
-            ui.current_window().cloned()
+            match jid {
+                Some(jid) => Some(ui.conversation_target(aparte, Some(jid))?),
+                None => ui
+                    .current_conversation()
+                    .ok()
+                    .map(|(account, conversation)| (account, conversation.get_jid().clone())),
+            }
+        };
+        let (account, contact) = match target {
+            Some((account, contact)) => (Some(account), Some(contact)),
+            None => (aparte.current_account(), None),
         };
 
-        if current == Some(String::from("console")) {
-            current = None;
-        }
-        let contact = jid.or(current).map(|jid| BareJid::from_str(&jid))
-            .transpose()?;
-
-        if let Some(account) = aparte.current_account() {
+        if let Some(account) = account {
             aparte.schedule(Event::Omemo(OmemoEvent::ShowFingerprints {
                 account,
                 jid: contact,
@@ -114,19 +139,53 @@ Examples:
     }
 );
 
+command_def!(omemo_debug,
+r#"/omemo debug [<jid>] [refresh=on]
+
+    jid        jid of the OMEMO enabled contact, current one when missing
+    refresh    Fetch bundles again and start new sessions with every device
+
+Description:
+    Show the state of OMEMO sessions with each device of a contact: whether
+    a session exists, the last successful decryption and the failures since,
+    along with the number of our own prekeys.
+
+Examples:
+    /omemo debug
+    /omemo debug contact@server.tld refresh=on
+"#,
+{
+    jid: Option<String>,
+    refresh: Named<bool>,
+},
+|aparte, _command| {
+    let (account, jid) = aparte.get_mod::<UIMod>().conversation_target(aparte, jid)?;
+    aparte.schedule(Event::Omemo(OmemoEvent::Debug {
+        account,
+        jid,
+        refresh: refresh.unwrap_or(false),
+    }));
+    Ok(())
+});
+
 command_def!(omemo,
-r#"/omemo enable"#,
+r#"/omemo enable|fingerprint|debug"#,
 {
     action: Command = {
         children: {
             "enable": omemo_enable,
             "fingerprint": omemo_fingerprint,
+            "debug": omemo_debug,
         }
     },
 });
 
 #[derive(Debug, Clone)]
 pub enum OmemoEvent {
+    /// Load keys and sessions of a connected account
+    Configure {
+        account: Account,
+    },
     Enable {
         account: Account,
         jid: BareJid,
@@ -135,6 +194,16 @@ pub enum OmemoEvent {
         account: Account,
         jid: Option<BareJid>,
     },
+    Debug {
+        account: Account,
+        jid: BareJid,
+        refresh: bool,
+    },
+    ResetSession {
+        account: Account,
+        jid: BareJid,
+        device_id: u32,
+    },
 }
 
 struct OmemoEngine {
@@ -232,8 +301,84 @@ impl OmemoEngine {
         Ok(())
     }
 
+    /// Build a message without payload carrying a new key to a device, it is sent as a prekey
+    /// message when a new session was just started
+    fn key_transport(&mut self, own_device_id: u32, device_id: u32) -> Result<XmppParsersMessage> {
+        let address = ProtocolAddress::new(self.contact.to_string(), device_id.into());
+        let nonce = Aes128Gcm::generate_nonce(&mut OsRng);
+        let dek_and_mac: [u8; KEY_SIZE + MAC_SIZE] = random();
+
+        let key = match message_encrypt(
+            &dek_and_mac,
+            &address,
+            &mut self.signal_storage.clone(),
+            &mut self.signal_storage.clone(),
+            None,
+        )
+        .now_or_never()
+        .ok_or(anyhow!("Cannot encrypt key for {address}"))??
+        {
+            CiphertextMessage::SignalMessage(msg) => legacy_omemo::Key {
+                rid: device_id,
+                prekey: legacy_omemo::IsPreKey::False,
+                data: msg.serialized().to_vec(),
+            },
+            CiphertextMessage::PreKeySignalMessage(msg) => legacy_omemo::Key {
+                rid: device_id,
+                prekey: legacy_omemo::IsPreKey::True,
+                data: msg.serialized().to_vec(),
+            },
+            _ => unreachable!(),
+        };
+
+        let mut message = XmppParsersMessage::new(Some(Jid::Bare(self.contact.clone())));
+        message.id = Some(Uuid::new_v4().to_string());
+        message.type_ = xmpp_parsers::message::MessageType::Chat;
+        message.payloads.push(
+            legacy_omemo::Encrypted {
+                header: legacy_omemo::Header {
+                    sid: own_device_id,
+                    iv: legacy_omemo::IV {
+                        data: nonce.to_vec(),
+                    },
+                    keys: vec![key],
+                },
+                payload: None,
+            }
+            .into(),
+        );
+        Ok(message)
+    }
+
+    /// Ask for a new session once a device repeatedly sent messages that can't be decrypted
+    fn recover(&self, aparte: &Aparte, address: &ProtocolAddress) {
+        let health = self.signal_storage.decryption_health(address);
+        if health.failures < SESSION_RESET_FAILURES {
+            return;
+        }
+
+        let interval = chrono::Duration::minutes(SESSION_RESET_INTERVAL_MINUTES);
+        if let Some(last_reset) = health.last_reset {
+            if chrono::Local::now() - last_reset < interval {
+                log::info!("Session with {address} was reset recently, not resetting again");
+                return;
+            }
+        }
+
+        self.signal_storage.record_reset(address);
+        aparte
+            .proxy()
+            .schedule(Event::Omemo(OmemoEvent::ResetSession {
+                account: self.account.clone(),
+                jid: self.contact.clone(),
+                device_id: address.device_id().into(),
+            }));
+    }
+
     fn sync_bundle(&self, aparte: &Aparte) -> Result<()> {
         log::info!("Syncing {}'s own bundle", self.account);
+        replenish_pre_keys(&mut self.signal_storage.clone()).context("Cannot generate prekeys")?;
+
         let device = aparte
             .storage
             .get_omemo_own_device(&self.account)?
@@ -285,6 +430,89 @@ impl OmemoEngine {
 
         Ok(())
     }
+
+    /// Decrypt a message sent by a device, the caller records the outcome for diagnostics
+    fn decrypt_from(
+        &mut self,
+        aparte: &Aparte,
+        own_device: &OmemoOwnDevice,
+        remote_address: &ProtocolAddress,
+        encrypted: legacy_omemo::Encrypted,
+        message: &XmppParsersMessage,
+    ) -> Result<XmppParsersMessage> {
+        let key = encrypted
+            .header
+            .keys
+            .iter()
+            .find(|k| i64::from(k.rid) == own_device.id)
+            .ok_or(anyhow!("Missing OMEMO key for current device"))?;
+
+        log::debug!("Found encrypted DEK for current device ({})", own_device.id);
+
+        let ciphertext_message = match key.prekey {
+            legacy_omemo::IsPreKey::True => {
+                log::debug!("Prekey message");
+                libsignal_protocol::CiphertextMessage::PreKeySignalMessage(
+                    libsignal_protocol::PreKeySignalMessage::try_from(key.data.as_slice())
+                        .context("Invalid prekey signal message")?,
+                )
+            }
+            legacy_omemo::IsPreKey::False => libsignal_protocol::CiphertextMessage::SignalMessage(
+                libsignal_protocol::SignalMessage::try_from(key.data.as_slice())
+                    .context("Invalid signal message")?,
+            ),
+        };
+
+        let dek_and_mac = message_decrypt(
+            &ciphertext_message,
+            remote_address,
+            &mut self.signal_storage.clone(),
+            &mut self.signal_storage.clone(),
+            &mut self.signal_storage.clone(),
+            &mut self.signal_storage.clone(),
+            &mut thread_rng(),
+            None,
+        )
+        .now_or_never()
+        .ok_or(anyhow!("Cannot decrypt DEK"))??;
+
+        if self
+            .signal_storage
+            .deleted_pre_keys
+            .swap(false, std::sync::atomic::Ordering::Relaxed)
+        {
+            self.sync_bundle(aparte)?;
+        }
+
+        if dek_and_mac.len() != MAC_SIZE + KEY_SIZE {
+            anyhow::bail!("Invalid DEK and MAC size");
+        }
+
+        let mut decrypted_message = message.clone();
+
+        if let Some(payload) = encrypted.payload {
+            let dek = aes_gcm::Key::<Aes128Gcm>::from_slice(&dek_and_mac[..KEY_SIZE]);
+            let mac = &dek_and_mac[KEY_SIZE..KEY_SIZE + MAC_SIZE];
+            let mut payload_and_mac = Vec::with_capacity(payload.data.len() + mac.len());
+            payload_and_mac.extend(payload.data);
+            payload_and_mac.extend(mac);
+
+            let cipher = Aes128Gcm::new(&dek);
+            let nonce = aes_gcm::Nonce::<<Aes128Gcm as AeadCore>::NonceSize>::from_slice(
+                encrypted.header.iv.data.as_slice(),
+            );
+            let cleartext = cipher
+                .decrypt(nonce, payload_and_mac.as_slice())
+                .map_err(|_| anyhow!("Message decryption failed"))?;
+            let message = String::from_utf8(cleartext)
+                .context("Message decryption resulted in invalid utf-8")?;
+            decrypted_message
+                .bodies
+                .insert(String::new(), xmpp_parsers::message::Body(message));
+        }
+
+        Ok(decrypted_message)
+    }
 }
 
 impl CryptoEngineTrait for OmemoEngine {
@@ -371,7 +599,7 @@ impl CryptoEngineTrait for OmemoEngine {
 
         let mut xmpp_message =
             xmpp_parsers::message::Message::new(Some(Jid::Bare(message.to.clone())));
-        xmpp_message.id = Some(message.id.clone());
+        xmpp_message.id = Some(message.get_last_id().to_string());
         xmpp_message.type_ = xmpp_parsers::message::MessageType::Chat;
         xmpp_message.bodies.insert(
             String::new(),
@@ -402,6 +630,9 @@ impl CryptoEngineTrait for OmemoEngine {
             }
             .into(),
         );
+        if let Some(replace) = message.replace() {
+            xmpp_message.payloads.push(replace.into());
+        }
         Ok(xmpp_message.into())
     }
 
@@ -426,88 +657,67 @@ impl CryptoEngineTrait for OmemoEngine {
             .find_map(|p| legacy_omemo::Encrypted::try_from((*p).clone()).ok())
             .ok_or(anyhow!("Missing encrypted element in EME OMEMO message"))?;
 
-        let key = encrypted
-            .header
-            .keys
-            .iter()
-            .find(|k| i64::from(k.rid) == own_device.id)
-            .ok_or(anyhow!("Missing OMEMO key for current device"))?;
-
-        log::debug!("Found encrypted DEK for current device ({})", own_device.id);
-
-        let ciphertext_message = match key.prekey {
-            legacy_omemo::IsPreKey::True => {
-                log::debug!("Prekey message");
-                libsignal_protocol::CiphertextMessage::PreKeySignalMessage(
-                    libsignal_protocol::PreKeySignalMessage::try_from(key.data.as_slice())
-                        .context("Invalid prekey signal message")?,
-                )
-            }
-            legacy_omemo::IsPreKey::False => libsignal_protocol::CiphertextMessage::SignalMessage(
-                libsignal_protocol::SignalMessage::try_from(key.data.as_slice())
-                    .context("Invalid signal message")?,
-            ),
-        };
-
         let remote_address = ProtocolAddress::new(
             self.contact.to_string(),
             libsignal_protocol::DeviceId::from(encrypted.header.sid),
         );
 
-        let dek_and_mac = message_decrypt(
-            &ciphertext_message,
-            &remote_address,
-            &mut self.signal_storage.clone(),
-            &mut self.signal_storage.clone(),
-            &mut self.signal_storage.clone(),
-            &mut self.signal_storage.clone(),
-            &mut thread_rng(),
-            None,
-        )
-        .now_or_never()
-        .ok_or(anyhow!("Cannot decrypt DEK"))??;
-
-        if self
-            .signal_storage
-            .deleted_pre_keys
-            .swap(false, std::sync::atomic::Ordering::Relaxed)
-        {
-            self.sync_bundle(aparte)?;
-        }
-
-        if dek_and_mac.len() != MAC_SIZE + KEY_SIZE {
-            anyhow::bail!("Invalid DEK and MAC size");
+        let result = self.decrypt_from(aparte, &own_device, &remote_address, encrypted, message);
+        self.signal_storage
+            .record_decryption(&remote_address, &result);
+        if result.is_err() {
+            self.recover(aparte, &remote_address);
         }
+        result
+    }
+}
 
-        let mut decrypted_message = message.clone();
+pub struct OmemoMod {
+    signal_stores: HashMap<Account, SignalStorage>,
+}
 
-        if let Some(payload) = encrypted.payload {
-            let dek = aes_gcm::Key::<Aes128Gcm>::from_slice(&dek_and_mac[..KEY_SIZE]);
-            let mac = &dek_and_mac[KEY_SIZE..KEY_SIZE + MAC_SIZE];
-            let mut payload_and_mac = Vec::with_capacity(payload.data.len() + mac.len());
-            payload_and_mac.extend(payload.data);
-            payload_and_mac.extend(mac);
+/// Generate prekeys following the last generated ones, so that ids are never reused
+fn generate_pre_keys(signal_storage: &mut SignalStorage, count: usize) -> Result<()> {
+    let device = signal_storage
+        .storage
+        .get_omemo_own_device(&signal_storage.account)?
+        .context("OMEMO isn't configured")?;
+    let first: u32 = device
+        .next_pre_key_id
+        .try_into()
+        .context("Corrupted next prekey id")?;
+    let next = first + u32::try_from(count)?;
+
+    for id in first..next {
+        let id = libsignal_protocol::PreKeyId::from(id);
+        let pre_key = KeyPair::generate(&mut thread_rng());
+        signal_storage
+            .save_pre_key(
+                id,
+                &libsignal_protocol::PreKeyRecord::new(id, &pre_key),
+                None,
+            )
+            .now_or_never()
+            .ok_or(anyhow!("Cannot save pre_keys"))??;
+    }
 
-            let cipher = Aes128Gcm::new(&dek);
-            let nonce = aes_gcm::Nonce::<<Aes128Gcm as AeadCore>::NonceSize>::from_slice(
-                encrypted.header.iv.data.as_slice(),
-            );
-            let cleartext = cipher
-                .decrypt(nonce, payload_and_mac.as_slice())
-                .map_err(|_| anyhow!("Message decryption failed"))?;
-            let message = String::from_utf8(cleartext)
-                .context("Message decryption resulted in invalid utf-8")?;
-            decrypted_message
-                .bodies
-                .insert(String::new(), xmpp_parsers::message::Body(message));
-        }
+    signal_storage
+        .storage
+        .set_omemo_next_pre_key_id(&signal_storage.account, next)
+}
 
-        Ok(decrypted_message)
+/// Generate new prekeys when too few remain
+fn replenish_pre_keys(signal_storage: &mut SignalStorage) -> Result<()> {
+    let count = signal_storage
+        .storage
+        .get_all_omemo_pre_key(&signal_storage.account)?
+        .len();
+    if count >= PRE_KEYS_THRESHOLD {
+        return Ok(());
     }
-}
 
-pub struct OmemoMod {
-    signal_stores: HashMap<Account, SignalStorage>,
+    log::info!("Only {count} prekeys left, generating new ones");
+    generate_pre_keys(signal_storage, PRE_KEYS_COUNT - count)
 }
 
 fn fingerprint(pub_key: &PublicKey) -> String {
@@ -526,6 +736,66 @@ fn fingerprint(pub_key: &PublicKey) -> String {
     .collect()
 }
 
+/// XEP-0454: OMEMO Media sharing, encrypt a file and return the encrypted data with the URL
+/// fragment holding the IV and key
+pub fn encrypt_media(data: &[u8]) -> Result<(Vec<u8>, String)> {
+    let key = Aes256Gcm::generate_key(OsRng);
+    let iv = Aes256Gcm::generate_nonce(&mut OsRng);
+    let encrypted = Aes256Gcm::new(&key)
+        .encrypt(&iv, data)
+        .map_err(|_| anyhow!("File encryption failed"))?;
+    let fragment = iv
+        .iter()
+        .chain(key.iter())
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    Ok((encrypted, fragment))
+}
+
+/// XEP-0454: OMEMO Media sharing, decrypt a file with the IV and key of its URL fragment
+pub fn decrypt_media(data: &[u8], fragment: &str) -> Result<Vec<u8>> {
+    let bytes = (0..fragment.len())
+        .step_by(2)
+        .map(|i| {
+            fragment
+                .get(i..i + 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+        })
+        .collect::<Option<Vec<u8>>>()
+        .context("Invalid key in aesgcm URL")?;
+    if bytes.len() < MEDIA_KEY_SIZE {
+        anyhow::bail!("Invalid key in aesgcm URL");
+    }
+
+    let (iv, key) = bytes.split_at(bytes.len() - MEDIA_KEY_SIZE);
+    let key = aes_gcm::Key::<Aes256Gcm>::from_slice(key);
+    let decrypted = match iv.len() {
+        MEDIA_IV_SIZE => Aes256Gcm::new(key).decrypt(aes_gcm::Nonce::<U12>::from_slice(iv), data),
+        MEDIA_LEGACY_IV_SIZE => {
+            AesGcm::<Aes256, U16>::new(key).decrypt(aes_gcm::Nonce::<U16>::from_slice(iv), data)
+        }
+        _ => anyhow::bail!("Invalid IV in aesgcm URL"),
+    };
+    decrypted.map_err(|_| anyhow!("File decryption failed"))
+}
+
+/// aesgcm URL sharing a file uploaded at an https URL
+pub fn aesgcm_url(url: &str, fragment: &str) -> String {
+    let location = url.strip_prefix("https://").unwrap_or(url);
+    format!("{AESGCM_SCHEME}{location}#{fragment}")
+}
+
+/// https URL to download the file shared by an aesgcm URL, and the fragment holding its key
+pub fn parse_aesgcm_url(url: &str) -> Result<(String, String)> {
+    let location = url
+        .strip_prefix(AESGCM_SCHEME)
+        .with_context(|| format!("{url} is not an aesgcm URL"))?;
+    let (location, fragment) = location
+        .split_once('#')
+        .context("Missing key in aesgcm URL")?;
+    Ok((format!("https://{location}"), fragment.to_string()))
+}
+
 impl OmemoMod {
     pub fn new() -> Self {
         Self {
@@ -561,6 +831,12 @@ impl OmemoMod {
         let fingerprint = fingerprint(identity_key_pair.public_key());
         log::info!("Device fingerprint: {fingerprint}");
 
+        let signal_store = self
+            .signal_stores
+            .get_mut(account)
+            .context("Missing signal store")?;
+        replenish_pre_keys(signal_store).context("Cannot generate prekeys")?;
+
         let mut aparte = aparte.proxy();
         let account = account.clone();
 
@@ -644,9 +920,7 @@ impl OmemoMod {
             identity_key_pair.serialize().to_vec(),
         )?;
 
-        let pre_keys = (1..101)
-            .map(|i| (i, KeyPair::generate(&mut thread_rng())))
-            .collect::<Vec<(u32, KeyPair)>>();
+        generate_pre_keys(signal_storage, PRE_KEYS_COUNT)?;
 
         let signed_pre_key_id = 0;
         let signed_pre_key = KeyPair::generate(&mut thread_rng());
@@ -654,20 +928,6 @@ impl OmemoMod {
             .private_key()
             .calculate_signature(&signed_pre_key.public_key.serialize(), &mut thread_rng())?;
 
-        for (i, pre_key) in pre_keys.iter() {
-            signal_storage
-                .save_pre_key(
-                    libsignal_protocol::PreKeyId::from(*i),
-                    &libsignal_protocol::PreKeyRecord::new(
-                        libsignal_protocol::PreKeyId::from(*i),
-                        &pre_key,
-                    ),
-                    None,
-                )
-                .now_or_never()
-                .ok_or(anyhow!("Cannot save pre_keys"))??;
-        }
-
         signal_storage
             .save_signed_pre_key(
                 libsignal_protocol::SignedPreKeyId::from(signed_pre_key_id),
@@ -733,6 +993,94 @@ impl OmemoMod {
         Ok(())
     }
 
+    fn show_debug(&self, aparte: &mut Aparte, account: &Account, jid: &BareJid) -> Result<()> {
+        let signal_store = self
+            .signal_stores
+            .get(account)
+            .ok_or(anyhow!("OMEMO not configured for {account}"))?;
+
+        let devices = aparte.storage.get_omemo_contact_devices(account, jid)?;
+        let enabled = match aparte.is_encrypted(account, jid) {
+            true => "enabled",
+            false => "not enabled",
+        };
+        crate::info!(
+            aparte,
+            "OMEMO {enabled} with {jid}, {} devices:",
+            devices.len()
+        );
+        for device in devices {
+            let device_id: u32 = device.id.try_into().context("Corrupted device id")?;
+            let address = ProtocolAddress::new(jid.to_string(), device_id.into());
+            let session = match aparte.storage.load_omemo_session(account, &address)? {
+                Some(session) if session.has_current_session_state() => "session",
+                Some(_) => "stale session",
+                None => "no session",
+            };
+            let fingerprint = aparte
+                .storage
+                .get_omemo_identity(account, &address)?
+                .map(|identity| fingerprint(identity.public_key()))
+                .unwrap_or_else(|| String::from("unknown identity"));
+            let health = signal_store.decryption_health(&address);
+            let success = match health.last_success {
+                Some(timestamp) => format!("decrypted {}", timestamp.format("%F %T")),
+                None => String::from("nothing decrypted"),
+            };
+            let failure = match health.last_failure {
+                Some((timestamp, err)) if health.failures > 0 => format!(
+                    ", {} failures since, last at {}: {err}",
+                    health.failures,
+                    timestamp.format("%F %T")
+                ),
+                _ => String::new(),
+            };
+            let reset = match health.last_reset {
+                Some(timestamp) => format!(", reset at {}", timestamp.format("%F %T")),
+                None => String::new(),
+            };
+            crate::info!(
+                aparte,
+                "  {device_id}: {session}, {success}{failure}{reset}, 🛡 {fingerprint}"
+            );
+        }
+
+        let pre_keys = aparte.storage.get_all_omemo_pre_key(account)?.len();
+        crate::info!(aparte, "{pre_keys} own prekeys available");
+
+        Ok(())
+    }
+
+    /// Start a new session with a device and send it a key, so that it starts using it too
+    async fn reset_session(
+        aparte: &mut AparteAsync,
+        signal_store: &SignalStorage,
+        account: &Account,
+        jid: &BareJid,
+        device_id: u32,
+    ) -> Result<()> {
+        log::info!("Reset OMEMO session with {jid}'s device {device_id}");
+        let own_device = aparte
+            .storage
+            .get_omemo_own_device(account)?
+            .context("OMEMO isn't configured")?;
+        let own_device_id: u32 = own_device
+            .id
+            .try_into()
+            .context("Corrupted own device id")?;
+
+        let bundle = Self::get_bundle(aparte, account, jid, device_id)
+            .await?
+            .context("No bundle published")?;
+
+        let mut omemo_engine = OmemoEngine::new(account, signal_store.clone(), jid);
+        omemo_engine.update_bundle(device_id, &bundle)?;
+        let message = omemo_engine.key_transport(own_device_id, device_id)?;
+        aparte.send(account, message.into());
+
+        Ok(())
+    }
+
     fn restore_sessions(&mut self, aparte: &mut Aparte, account: &Account) -> Result<()> {
         let signal_store = self
             .signal_stores
@@ -952,7 +1300,21 @@ impl OmemoMod {
     ) -> Result<()> {
         log::info!("Ensure device {device_id}'s bundle is published");
         match Self::get_bundle(aparte, account, &account.to_bare(), device_id).await? {
-            None => {
+            // Every published prekey must still be available locally, otherwise contacts would
+            // start sessions we can't decrypt
+            Some(legacy_omemo::Bundle {
+                prekeys: Some(legacy_omemo::Prekeys { keys }),
+                ..
+            }) if keys.len() >= PRE_KEYS_THRESHOLD
+                && keys
+                    .iter()
+                    .all(|key| pre_keys.iter().any(|(id, _)| *id == key.pre_key_id)) =>
+            {
+                log::info!("Bundle already published with enough prekeys");
+                Ok(())
+            }
+            _ => {
+                log::info!("Published bundle is missing or stale");
                 Self::publish_bundle(
                     aparte,
                     account,
@@ -965,17 +1327,6 @@ impl OmemoMod {
                 )
                 .await
             }
-            Some(legacy_omemo::Bundle {
-                prekeys: Some(legacy_omemo::Prekeys { keys }),
-                ..
-            }) if keys.len() < 20 => {
-                log::info!("Published bundle doesn't have enough prekeys");
-                todo!()
-            }
-            _ => {
-                log::info!("Bundle already published with enough prekeys");
-                Ok(())
-            }
         }
     }
 
@@ -1203,6 +1554,11 @@ impl OmemoMod {
 impl ModTrait for OmemoMod {
     fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
         aparte.add_command(omemo::new());
+        aparte.get_mod_mut::<DiscoMod>().require_feature(
+            "omemo",
+            "XEP-0163: Personal Eventing Protocol",
+            NS_PUBSUB_PUBLISH,
+        );
         //let mut disco = aparte.get_mod_mut::<DiscoMod>();
         //disco.add_feature(ns::OMEMO_DEVICES);
         //disco.add_feature(format!("{ns::OMEMO_DEVICES}+notify"));
@@ -1212,15 +1568,22 @@ impl ModTrait for OmemoMod {
 
     fn on_event(&mut self, aparte: &mut Aparte, event: &Event) {
         match event {
+            // Accounts only connect once storage is migrated, after the first rendering. Key
+            // loading is still queued behind pending events, such as key strokes
             Event::Connected(account, _jid) => {
-                if let Err(err) = self.configure(aparte, account) {
-                    crate::error!(aparte, err, "Cannot configure OMEMO");
-                }
-                if let Err(err) = self.restore_sessions(aparte, account) {
-                    crate::error!(aparte, err, "Cannot restore OMEMO sessions");
-                }
+                aparte.schedule(Event::Omemo(OmemoEvent::Configure {
+                    account: account.clone(),
+                }))
             }
             Event::Omemo(event) => match event {
+                OmemoEvent::Configure { account } => {
+                    if let Err(err) = self.configure(aparte, account) {
+                        crate::error!(aparte, err, "Cannot configure OMEMO");
+                    }
+                    if let Err(err) = self.restore_sessions(aparte, account) {
+                        crate::error!(aparte, err, "Cannot restore OMEMO sessions");
+                    }
+                }
                 // TODO context()?
                 OmemoEvent::Enable { account, jid } => {
                     let mut aparte = aparte.proxy();
@@ -1252,6 +1615,59 @@ impl ModTrait for OmemoMod {
                         crate::error!(aparte, e, "Cannot get own OMEMO fingerprint");
                     }
                 }
+                OmemoEvent::Debug {
+                    account,
+                    jid,
+                    refresh,
+                } => {
+                    if let Err(err) = self.show_debug(aparte, account, jid) {
+                        crate::error!(aparte, err, "Cannot get OMEMO state of {jid}");
+                    }
+                    if *refresh {
+                        // Starting the session again fetches every bundle
+                        aparte.schedule(Event::Omemo(OmemoEvent::Enable {
+                            account: account.clone(),
+                            jid: jid.clone(),
+                        }));
+                    }
+                }
+                OmemoEvent::ResetSession {
+                    account,
+                    jid,
+                    device_id,
+                } => {
+                    let mut aparte = aparte.proxy();
+                    let account = account.clone();
+                    let jid = jid.clone();
+                    let device_id = *device_id;
+                    match self.signal_stores.get(&account) {
+                        None => crate::info!(aparte, "OMEMO not configured for {account}"),
+                        Some(signal_store) => Aparte::spawn({
+                            let signal_store = SignalStorage::clone(signal_store);
+                            async move {
+                                match Self::reset_session(
+                                    &mut aparte,
+                                    &signal_store,
+                                    &account,
+                                    &jid,
+                                    device_id,
+                                )
+                                .await
+                                {
+                                    Ok(()) => crate::info!(
+                                        aparte,
+                                        "Messages from {jid}'s device {device_id} couldn't be decrypted, a new OMEMO session was started"
+                                    ),
+                                    Err(err) => crate::error!(
+                                        aparte,
+                                        err,
+                                        "Can't reset OMEMO session with {jid}'s device {device_id}"
+                                    ),
+                                }
+                            }
+                        }),
+                    }
+                }
             },
             //Event::PubSub { account: _, from: Some(from), event } => match event {
             //    pubsub::PubSubEvent::PublishedItems { node, items } => {
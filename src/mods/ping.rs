@@ -0,0 +1,162 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use uuid::Uuid;
+use xmpp_parsers::iq::{Iq, IqType};
+use xmpp_parsers::ping::Ping;
+use xmpp_parsers::{ns, Jid};
+
+use crate::account::Account;
+use crate::core::{Aparte, Event, ModTrait};
+use crate::mods::disco::DiscoMod;
+
+/// Delay between two pings of the server, unless configured otherwise
+const PING_INTERVAL: u64 = 60;
+
+/// Delay without answer after which the connection is considered dead
+const PING_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// XEP-0199: XMPP Ping
+///
+/// Ping the server of each connected account, and drop connections that stopped answering so
+/// that they are opened again, which a dead TCP connection behind a NAT would never tell.
+pub struct PingMod {
+    /// Connected accounts, with a generation telling their connections apart
+    connected: HashMap<Account, u64>,
+    generation: u64,
+}
+
+impl PingMod {
+    pub fn new() -> Self {
+        Self {
+            connected: HashMap::new(),
+            generation: 0,
+        }
+    }
+
+    fn interval(aparte: &Aparte) -> Option<Duration> {
+        match aparte.config.ping_interval.unwrap_or(PING_INTERVAL) {
+            0 => None,
+            interval => Some(Duration::from_secs(interval)),
+        }
+    }
+
+    /// Schedule the next ping of the server
+    fn schedule(aparte: &Aparte, account: &Account, generation: u64) {
+        let interval = match Self::interval(aparte) {
+            Some(interval) => interval,
+            None => return,
+        };
+        Aparte::spawn({
+            let mut aparte = aparte.proxy();
+            let account = account.clone();
+            async move {
+                tokio::time::sleep(interval).await;
+                aparte.schedule(Event::Ping {
+                    account,
+                    generation,
+                });
+            }
+        });
+    }
+
+    fn ping(&mut self, aparte: &mut Aparte, account: &Account, generation: u64) {
+        // The connection was lost or opened again since the ping was scheduled
+        if self.connected.get(account) != Some(&generation) {
+            return;
+        }
+
+        let interval = match Self::interval(aparte) {
+            Some(interval) => interval,
+            None => return,
+        };
+        let server = match Jid::from_str(&account.domain().to_string()) {
+            Ok(server) => server,
+            Err(err) => {
+                log::warn!("Cannot ping server of {account}: {err}");
+                return;
+            }
+        };
+        let id = Uuid::new_v4().hyphenated().to_string();
+        let iq = Iq::from_get(id, Ping).with_to(server);
+        Aparte::spawn({
+            let mut aparte = aparte.proxy();
+            let account = account.clone();
+            async move {
+                let start = Instant::now();
+                match tokio::time::timeout(PING_TIMEOUT, aparte.iq(&account, iq)).await {
+                    // Even an error tells the server is still there
+                    Ok(Ok(_)) => {
+                        aparte.schedule(Event::Lag(account.clone(), start.elapsed()));
+                        tokio::time::sleep(interval).await;
+                        aparte.schedule(Event::Ping {
+                            account,
+                            generation,
+                        });
+                    }
+                    // Pending iqs fail when the connection is lost
+                    Ok(Err(err)) => log::debug!("Ping of {account} failed: {err}"),
+                    Err(_) => aparte.schedule(Event::ConnectionBroken(
+                        account,
+                        format!("no answer to ping in {}s", PING_TIMEOUT.as_secs()),
+                    )),
+                }
+            }
+        });
+    }
+
+    /// Answer pings of the server or of contacts
+    fn pong(&mut self, aparte: &mut Aparte, account: &Account, iq: &Iq) {
+        match &iq.payload {
+            IqType::Get(payload) if Ping::try_from(payload.clone()).is_ok() => {
+                let pong = Iq {
+                    from: None,
+                    to: iq.from.clone(),
+                    id: iq.id.clone(),
+                    payload: IqType::Result(None),
+                };
+                aparte.send(account, pong);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl ModTrait for PingMod {
+    fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
+        let mut disco = aparte.get_mod_mut::<DiscoMod>();
+        disco.add_feature(ns::PING);
+        Ok(())
+    }
+
+    fn on_event(&mut self, aparte: &mut Aparte, event: &Event) {
+        match event {
+            Event::Connected(account, _) => {
+                self.generation += 1;
+                self.connected.insert(account.clone(), self.generation);
+                Self::schedule(aparte, account, self.generation);
+            }
+            Event::Disconnected(account, _) | Event::ConnectionBroken(account, _) => {
+                self.connected.remove(account);
+            }
+            Event::Ping {
+                account,
+                generation,
+            } => self.ping(aparte, account, *generation),
+            Event::Iq(account, iq) => self.pong(aparte, account, iq),
+            _ => {}
+        }
+    }
+}
+
+impl fmt::Display for PingMod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "XEP-0199: XMPP Ping")
+    }
+}
@@ -0,0 +1,643 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::fmt;
+use std::future::Future;
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use uuid::Uuid;
+use xmpp_parsers::iq::{Iq, IqType};
+use xmpp_parsers::pubsub::pubsub::{Items, Publish, Subscribe};
+use xmpp_parsers::pubsub::{Item, NodeName, PubSub, PubSubEvent};
+use xmpp_parsers::{ns, BareJid, Element, Jid};
+
+use crate::account::Account;
+use crate::command::{Command, CommandParser};
+use crate::core::{Aparte, AparteAsync, Event, ModTrait};
+use crate::i18n;
+use crate::message::Message;
+use crate::mods::disco::DiscoMod;
+
+/// Window where items of subscribed nodes are displayed
+pub const PUBSUB_WINDOW: &str = "pubsub";
+
+/// Items fetched by /pubsub items unless told otherwise
+const DEFAULT_MAX_ITEMS: u32 = 10;
+
+const NS_ATOM: &str = "http://www.w3.org/2005/Atom";
+
+/// XEP-0163: Personal Eventing Protocol, state of the user published on a node of their account,
+/// such as the played track or their location
+pub trait PersonalEvent: Clone + Default + PartialEq + Send + 'static {
+    /// Namespace of the node and of the published element
+    const NS: &'static str;
+    /// Name of the published element
+    const NAME: &'static str;
+    /// Name of the event in error messages
+    const DESCRIPTION: &'static str;
+
+    /// Text of each child of the published element, unset ones being skipped
+    fn fields(&self) -> Vec<(&'static str, Option<String>)>;
+
+    /// Event described by the text of the children of the published element
+    fn from_fields(field: &dyn Fn(&str) -> Option<String>) -> Self;
+
+    /// Published element, an empty one telling that the event is over
+    fn to_element(event: Option<&Self>) -> Element {
+        let mut element = Element::builder(Self::NAME, Self::NS);
+        if let Some(event) = event {
+            for (name, value) in event.fields() {
+                if let Some(value) = value {
+                    element =
+                        element.append(Element::builder(name, Self::NS).append(value).build());
+                }
+            }
+        }
+        element.build()
+    }
+
+    fn from_element(element: &Element) -> Option<Self> {
+        if !element.is(Self::NAME, Self::NS) {
+            return None;
+        }
+        let field = |name: &str| {
+            element
+                .get_child(name, Self::NS)
+                .map(|child| child.text().trim().to_string())
+                .filter(|text| !text.is_empty())
+        };
+        let event = Self::from_fields(&field);
+        // An empty element tells that the contact's event is over
+        match event == Self::default() {
+            true => None,
+            false => Some(event),
+        }
+    }
+}
+
+fn parse_service(service: &str) -> Result<Jid> {
+    Jid::from_str(service).with_context(|| format!("Invalid pubsub service {service}"))
+}
+
+command_def!(pubsub_nodes,
+r#"/pubsub nodes <service> [<node>]
+
+    service    jid of the pubsub service, or of a contact for their PEP nodes
+    node       collection node whose children are listed
+
+Description:
+    List the nodes of a pubsub service.
+
+Examples:
+    /pubsub nodes pubsub.example.org
+    /pubsub nodes pubsub.example.org news
+"#,
+{
+    service: String,
+    node: Option<String>,
+},
+|aparte, _command| {
+    let account = aparte.current_account().context("No connection found")?;
+    let service = parse_service(&service)?;
+    PubSubMod::list_nodes(aparte, &account, &service, node);
+    Ok(())
+});
+
+command_def!(pubsub_items,
+r#"/pubsub items <service> <node> [<max>]
+
+    service    jid of the pubsub service
+    node       node whose items are shown
+    max        number of items to fetch, 10 by default
+
+Description:
+    Show the latest items published on a node in the pubsub window.
+
+Examples:
+    /pubsub items pubsub.example.org news
+    /pubsub items contact@example.org urn:xmpp:microblog:0 5
+"#,
+{
+    service: String,
+    node: String,
+    max: Option<u32>,
+},
+|aparte, _command| {
+    let account = aparte.current_account().context("No connection found")?;
+    let service = parse_service(&service)?;
+    PubSubMod::show_items(aparte, &account, &service, &node, max.unwrap_or(DEFAULT_MAX_ITEMS));
+    Ok(())
+});
+
+command_def!(pubsub_subscribe,
+r#"/pubsub subscribe <service> <node>
+
+    service    jid of the pubsub service
+    node       node to subscribe to
+
+Description:
+    Subscribe to a node, items then published on it are shown in the pubsub
+    window.
+
+Examples:
+    /pubsub subscribe pubsub.example.org news
+"#,
+{
+    service: String,
+    node: String,
+},
+|aparte, _command| {
+    let account = aparte.current_account().context("No connection found")?;
+    let service = parse_service(&service)?;
+    PubSubMod::subscribe(aparte, &account, &service, &node, true);
+    Ok(())
+});
+
+command_def!(pubsub_unsubscribe,
+r#"/pubsub unsubscribe <service> <node>
+
+    service    jid of the pubsub service
+    node       node to unsubscribe from
+
+Examples:
+    /pubsub unsubscribe pubsub.example.org news
+"#,
+{
+    service: String,
+    node: String,
+},
+|aparte, _command| {
+    let account = aparte.current_account().context("No connection found")?;
+    let service = parse_service(&service)?;
+    PubSubMod::subscribe(aparte, &account, &service, &node, false);
+    Ok(())
+});
+
+command_def!(pubsub_subscriptions,
+r#"/pubsub subscriptions <service>
+
+    service    jid of the pubsub service
+
+Description:
+    List the nodes of a pubsub service we are subscribed to. Items published
+    on them are then shown in the pubsub window.
+
+Examples:
+    /pubsub subscriptions pubsub.example.org
+"#,
+{
+    service: String,
+},
+|aparte, _command| {
+    let account = aparte.current_account().context("No connection found")?;
+    let service = parse_service(&service)?;
+    PubSubMod::list_subscriptions(aparte, &account, &service);
+    Ok(())
+});
+
+command_def!(pubsub,
+r#"/pubsub nodes|items|subscribe|unsubscribe|subscriptions"#,
+{
+    action: Command = {
+        children: {
+            "nodes": pubsub_nodes,
+            "items": pubsub_items,
+            "subscribe": pubsub_subscribe,
+            "unsubscribe": pubsub_unsubscribe,
+            "subscriptions": pubsub_subscriptions,
+        }
+    },
+});
+
+/// XEP-0060: Publish-Subscribe
+///
+/// Browse nodes of pubsub services and follow those we subscribe to. Events of nodes used by
+/// other mods (bookmarks, avatars, OMEMO devices…) are left to them.
+pub struct PubSubMod {
+    /// Nodes subscribed to, by account and service, whose events are displayed
+    subscriptions: HashSet<(Account, Jid, String)>,
+}
+
+impl PubSubMod {
+    pub fn new() -> Self {
+        Self {
+            subscriptions: HashSet::new(),
+        }
+    }
+
+    fn list_nodes(aparte: &Aparte, account: &Account, service: &Jid, node: Option<String>) {
+        Aparte::spawn({
+            let mut aparte = aparte.proxy();
+            let account = account.clone();
+            let service = service.clone();
+            async move {
+                match DiscoMod::get_disco_items(&mut aparte, &account, &service, node).await {
+                    Ok(items) if items.is_empty() => aparte.log(format!("{service} has no node")),
+                    Ok(items) => {
+                        let mut output = format!("Nodes of {service}:");
+                        for item in items {
+                            let node = item.node.unwrap_or_default();
+                            match item.name {
+                                Some(name) => output.push_str(&format!("\n  {node}: {name}")),
+                                None => output.push_str(&format!("\n  {node}")),
+                            }
+                        }
+                        aparte.log(output);
+                    }
+                    Err(err) => crate::error!(aparte, err, "Cannot list nodes of {service}"),
+                }
+            }
+        });
+    }
+
+    fn show_items(aparte: &Aparte, account: &Account, service: &Jid, node: &str, max: u32) {
+        let id = Uuid::new_v4().hyphenated().to_string();
+        let items = Items {
+            max_items: Some(max),
+            node: NodeName(node.to_string()),
+            subid: None,
+            items: vec![],
+        };
+        let iq = Iq::from_get(id, PubSub::Items(items)).with_to(service.clone());
+
+        Aparte::spawn({
+            let mut aparte = aparte.proxy();
+            let account = account.clone();
+            let service = service.clone();
+            let node = node.to_string();
+            async move {
+                match Self::get_items(&mut aparte, &account, iq).await {
+                    Ok(items) if items.is_empty() => {
+                        aparte.log(format!("No item published on {node} of {service}"))
+                    }
+                    Ok(items) => {
+                        for item in items.iter() {
+                            let body = Self::format_item(&service, &node, item);
+                            aparte.schedule(Event::PubSubLog(Message::log(body)));
+                        }
+                        aparte.schedule(Event::Win(PUBSUB_WINDOW.to_string()));
+                    }
+                    Err(err) => {
+                        crate::error!(aparte, err, "Cannot get items of {node} on {service}")
+                    }
+                }
+            }
+        });
+    }
+
+    async fn get_items(aparte: &mut AparteAsync, account: &Account, iq: Iq) -> Result<Vec<Item>> {
+        match aparte.iq(account, iq).await?.payload {
+            IqType::Result(Some(el)) => match PubSub::try_from(el)? {
+                PubSub::Items(items) => Ok(items.items.into_iter().map(|item| item.0).collect()),
+                _ => Err(anyhow!("invalid response")),
+            },
+            IqType::Error(err) => Err(anyhow!("{}", i18n::xmpp_err_to_string(&err, vec![]).1)),
+            _ => Err(anyhow!("invalid response")),
+        }
+    }
+
+    async fn request(
+        aparte: &mut AparteAsync,
+        account: &Account,
+        iq: Iq,
+    ) -> Result<Option<Element>> {
+        match aparte.iq(account, iq).await?.payload {
+            IqType::Result(payload) => Ok(payload),
+            IqType::Error(err) => Err(anyhow!("{}", i18n::xmpp_err_to_string(&err, vec![]).1)),
+            _ => Err(anyhow!("invalid response")),
+        }
+    }
+
+    /// Publish an item to a PEP node of the account, replacing the previous one
+    pub async fn publish(
+        aparte: &mut AparteAsync,
+        account: &Account,
+        node: &str,
+        payload: Element,
+    ) -> Result<()> {
+        let id = Uuid::new_v4().hyphenated().to_string();
+        let item = Item {
+            id: None,
+            payload: Some(payload),
+            publisher: None,
+        };
+        let publish = Publish {
+            node: NodeName(node.to_string()),
+            items: vec![xmpp_parsers::pubsub::pubsub::Item(item)],
+        };
+        let pubsub = PubSub::Publish {
+            publish,
+            publish_options: None,
+        };
+        Self::request(aparte, account, Iq::from_set(id, pubsub)).await?;
+        Ok(())
+    }
+
+    /// Publish a personal event of the account, none telling that it is over
+    pub fn publish_personal<T: PersonalEvent>(
+        aparte: &mut Aparte,
+        account: &Account,
+        event: Option<&T>,
+    ) {
+        let payload = T::to_element(event);
+        Aparte::spawn({
+            let mut aparte = aparte.proxy();
+            let account = account.clone();
+            async move {
+                if let Err(err) = Self::publish(&mut aparte, &account, T::NS, payload).await {
+                    crate::error!(
+                        aparte,
+                        err,
+                        "Cannot publish {} of {account}",
+                        T::DESCRIPTION
+                    );
+                }
+            }
+        });
+    }
+
+    /// Read a personal event periodically, scheduling the event built by `changed` each time it
+    /// changes. Reading stops at the first error.
+    pub fn watch_personal<T, F, Fut>(
+        aparte: &mut Aparte,
+        interval: Duration,
+        read: F,
+        changed: fn(Option<T>) -> Event,
+    ) where
+        T: PersonalEvent,
+        F: Fn() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<Option<T>>> + Send + 'static,
+    {
+        Aparte::spawn({
+            let mut aparte = aparte.proxy();
+            async move {
+                let mut current = None;
+                loop {
+                    match read().await {
+                        Ok(event) if event != current => {
+                            current = event.clone();
+                            aparte.schedule(changed(event));
+                        }
+                        Ok(_) => {}
+                        Err(err) => {
+                            crate::error!(aparte, err, "Cannot read {}", T::DESCRIPTION);
+                            break;
+                        }
+                    }
+                    tokio::time::sleep(interval).await;
+                }
+            }
+        });
+    }
+
+    /// Personal event of a contact carried by a pubsub event, none when it is over
+    pub fn personal_event<T: PersonalEvent>(
+        event: &Event,
+    ) -> Option<(Account, BareJid, Option<T>)> {
+        match event {
+            Event::PubSub {
+                account,
+                from: Some(from),
+                event: PubSubEvent::PublishedItems { node, items },
+            } if node.0 == T::NS => {
+                let personal = items
+                    .iter()
+                    .filter_map(|item| item.0.payload.as_ref())
+                    .last()
+                    .and_then(T::from_element);
+                Some((account.clone(), from.to_bare(), personal))
+            }
+            Event::PubSub {
+                account,
+                from: Some(from),
+                event: PubSubEvent::RetractedItems { node, .. },
+            } if node.0 == T::NS => Some((account.clone(), from.to_bare(), None)),
+            _ => None,
+        }
+    }
+
+    fn subscribe(aparte: &Aparte, account: &Account, service: &Jid, node: &str, subscribe: bool) {
+        let id = Uuid::new_v4().hyphenated().to_string();
+        let iq = match subscribe {
+            true => Iq::from_set(
+                id,
+                PubSub::Subscribe {
+                    subscribe: Some(Subscribe {
+                        node: Some(NodeName(node.to_string())),
+                        jid: Jid::Full(account.clone()),
+                    }),
+                    options: None,
+                },
+            ),
+            false => Iq {
+                from: None,
+                to: None,
+                id,
+                payload: IqType::Set(
+                    Element::builder("pubsub", ns::PUBSUB)
+                        .append(
+                            Element::builder("unsubscribe", ns::PUBSUB)
+                                .attr("node", node)
+                                .attr("jid", account.to_string()),
+                        )
+                        .build(),
+                ),
+            },
+        }
+        .with_to(service.clone());
+
+        Aparte::spawn({
+            let mut aparte = aparte.proxy();
+            let account = account.clone();
+            let service = service.clone();
+            let node = node.to_string();
+            async move {
+                match Self::request(&mut aparte, &account, iq).await {
+                    Ok(_) => {
+                        match subscribe {
+                            true => crate::info!(aparte, "Subscribed to {node} on {service}"),
+                            false => crate::info!(aparte, "Unsubscribed from {node} on {service}"),
+                        }
+                        aparte.schedule(Event::PubSubSubscription {
+                            account,
+                            service,
+                            node,
+                            subscribed: subscribe,
+                        });
+                    }
+                    Err(err) => crate::error!(aparte, err, "Cannot change subscription to {node}"),
+                }
+            }
+        });
+    }
+
+    fn list_subscriptions(aparte: &Aparte, account: &Account, service: &Jid) {
+        let iq = Iq {
+            from: None,
+            to: Some(service.clone()),
+            id: Uuid::new_v4().hyphenated().to_string(),
+            payload: IqType::Get(
+                Element::builder("pubsub", ns::PUBSUB)
+                    .append(Element::builder("subscriptions", ns::PUBSUB))
+                    .build(),
+            ),
+        };
+
+        Aparte::spawn({
+            let mut aparte = aparte.proxy();
+            let account = account.clone();
+            let service = service.clone();
+            async move {
+                let response = match Self::request(&mut aparte, &account, iq).await {
+                    Ok(response) => response,
+                    Err(err) => {
+                        crate::error!(aparte, err, "Cannot list subscriptions on {service}");
+                        return;
+                    }
+                };
+                let subscriptions = response
+                    .iter()
+                    .filter_map(|pubsub| pubsub.get_child("subscriptions", ns::PUBSUB))
+                    .flat_map(|subscriptions| subscriptions.children())
+                    .filter(|child| child.is("subscription", ns::PUBSUB))
+                    .filter_map(|subscription| {
+                        let node = subscription.attr("node")?.to_string();
+                        let state = subscription.attr("subscription").unwrap_or("subscribed");
+                        Some((node, state.to_string()))
+                    })
+                    .collect::<Vec<_>>();
+                if subscriptions.is_empty() {
+                    aparte.log(format!("No subscription on {service}"));
+                    return;
+                }
+
+                let mut output = format!("Subscriptions on {service}:");
+                for (node, state) in subscriptions {
+                    output.push_str(&format!("\n  {node}: {state}"));
+                    if state == "subscribed" {
+                        aparte.schedule(Event::PubSubSubscription {
+                            account: account.clone(),
+                            service: service.clone(),
+                            node,
+                            subscribed: true,
+                        });
+                    }
+                }
+                aparte.log(output);
+            }
+        });
+    }
+
+    /// Summary of an item, Atom entries being shown with their title
+    fn format_item(service: &Jid, node: &str, item: &Item) -> String {
+        let mut output = format!("{service} {node}");
+        if let Some(id) = &item.id {
+            output.push_str(&format!(" [{}]", id.0));
+        }
+        if let Some(publisher) = &item.publisher {
+            output.push_str(&format!(" by {publisher}"));
+        }
+        match &item.payload {
+            Some(entry) if entry.is("entry", NS_ATOM) => {
+                for name in ["title", "summary", "content"] {
+                    if let Some(text) = entry.get_child(name, NS_ATOM).map(|child| child.text()) {
+                        if !text.trim().is_empty() {
+                            output.push_str(&format!("\n  {}", text.trim()));
+                        }
+                    }
+                }
+            }
+            Some(payload) => {
+                output.push_str(&format!(
+                    "\n  <{} xmlns='{}'/>",
+                    payload.name(),
+                    payload.ns()
+                ));
+                let text = payload.text();
+                if !text.trim().is_empty() {
+                    output.push_str(&format!(" {}", text.trim()));
+                }
+            }
+            None => {}
+        }
+        output
+    }
+
+    fn handle_event(
+        &self,
+        aparte: &mut Aparte,
+        account: &Account,
+        from: &Jid,
+        event: &PubSubEvent,
+    ) {
+        let node = match event {
+            PubSubEvent::PublishedItems { node, .. }
+            | PubSubEvent::RetractedItems { node, .. }
+            | PubSubEvent::Delete { node, .. }
+            | PubSubEvent::Purge { node } => node,
+            _ => return,
+        };
+        let key = (account.clone(), from.clone(), node.0.clone());
+        if !self.subscriptions.contains(&key) {
+            return;
+        }
+
+        let node = &node.0;
+        let bodies = match event {
+            PubSubEvent::PublishedItems { items, .. } => items
+                .iter()
+                .map(|item| Self::format_item(from, node, &item.0))
+                .collect(),
+            PubSubEvent::RetractedItems { items, .. } => items
+                .iter()
+                .map(|id| format!("{from} {node} [{}] retracted", id.0))
+                .collect(),
+            PubSubEvent::Delete { .. } => vec![format!("{from} {node} deleted")],
+            PubSubEvent::Purge { .. } => vec![format!("{from} {node} purged")],
+            _ => vec![],
+        };
+        for body in bodies {
+            aparte.schedule(Event::PubSubLog(Message::log(body)));
+        }
+    }
+}
+
+impl ModTrait for PubSubMod {
+    fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
+        aparte.add_command(pubsub::new());
+        Ok(())
+    }
+
+    fn on_event(&mut self, aparte: &mut Aparte, event: &Event) {
+        match event {
+            Event::PubSub {
+                account,
+                from: Some(from),
+                event,
+            } => self.handle_event(aparte, account, from, event),
+            Event::PubSubSubscription {
+                account,
+                service,
+                node,
+                subscribed,
+            } => {
+                let key = (account.clone(), service.clone(), node.clone());
+                match subscribed {
+                    true => self.subscriptions.insert(key),
+                    false => self.subscriptions.remove(&key),
+                };
+            }
+            _ => {}
+        }
+    }
+}
+
+impl fmt::Display for PubSubMod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "XEP-0060: Publish-Subscribe")
+    }
+}
@@ -0,0 +1,99 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::Result;
+use chrono::Local as LocalTz;
+use xmpp_parsers::Jid;
+
+use crate::core::{Aparte, Event, ModTrait};
+use crate::quiet_hours::{self, QuietHours};
+
+/// Disconnect autoconnected accounts when their quiet hours start and connect them back once over
+pub struct QuietHoursMod {
+    /// Whether each account with quiet hours is within them, by account name
+    quiet: HashMap<String, bool>,
+}
+
+impl QuietHoursMod {
+    pub fn new() -> Self {
+        Self {
+            quiet: HashMap::new(),
+        }
+    }
+
+    fn update(&mut self, aparte: &mut Aparte) {
+        let now = LocalTz::now().naive_local();
+        for (name, info) in aparte.config.accounts.clone() {
+            if !info.autoconnect || info.quiet_hours.is_empty() {
+                continue;
+            }
+
+            let quiet = quiet_hours::is_quiet(&info.quiet_hours, now);
+            if self.quiet.insert(name.clone(), quiet) == Some(quiet) {
+                continue;
+            }
+
+            let bare = match Jid::from_str(&info.jid) {
+                Ok(jid) => jid.to_bare(),
+                Err(_) => continue,
+            };
+            let connected = aparte
+                .connected_accounts()
+                .into_iter()
+                .filter(|account| account.to_bare() == bare)
+                .collect::<Vec<_>>();
+            match quiet {
+                true => {
+                    for account in connected {
+                        crate::info!(aparte, "Quiet hours of {name} started, disconnecting");
+                        aparte.schedule(Event::Disconnect(account, String::from("quiet hours")));
+                    }
+                }
+                false if connected.is_empty() => {
+                    crate::info!(aparte, "Quiet hours of {name} are over, connecting");
+                    aparte.schedule(Event::RawCommand(
+                        None,
+                        "console".to_string(),
+                        format!("/connect {name}"),
+                    ));
+                }
+                false => {}
+            }
+        }
+    }
+}
+
+impl ModTrait for QuietHoursMod {
+    fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
+        let now = LocalTz::now().naive_local();
+        for (name, info) in aparte.config.accounts.iter() {
+            for window in info.quiet_hours.iter() {
+                if let Err(err) = QuietHours::from_str(window) {
+                    log::warn!("Ignoring quiet hours of {name}: {err}");
+                }
+            }
+            // Accounts in their quiet hours aren't connected at startup
+            if info.autoconnect && !info.quiet_hours.is_empty() {
+                let quiet = quiet_hours::is_quiet(&info.quiet_hours, now);
+                self.quiet.insert(name.clone(), quiet);
+            }
+        }
+        Ok(())
+    }
+
+    fn on_event(&mut self, aparte: &mut Aparte, event: &Event) {
+        if let Event::Tick = event {
+            self.update(aparte)
+        }
+    }
+}
+
+impl fmt::Display for QuietHoursMod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Quiet hours")
+    }
+}
@@ -0,0 +1,285 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use std::fmt;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use uuid::Uuid;
+use xmpp_parsers::delay::Delay;
+use xmpp_parsers::message::{Message as XmppParsersMessage, MessageType};
+use xmpp_parsers::{BareJid, Element, Jid};
+
+use crate::account::Account;
+use crate::command::{Command, CommandParser};
+use crate::conversation::Conversation;
+use crate::core::{Aparte, Event, ModTrait};
+use crate::intern::intern;
+use crate::message::{Message, VersionedXmppMessage, XmppMessageType};
+use crate::mods::conversation::ConversationMod;
+use crate::mods::disco;
+use crate::mods::messages::MessagesMod;
+use crate::mods::ui::UIMod;
+
+/// XEP-0444: Message Reactions
+const NS_REACTIONS: &str = "urn:xmpp:reactions:0";
+/// XEP-0334: Message Processing Hints
+const NS_HINTS: &str = "urn:xmpp:hints";
+
+/// Favorites proposed by the picker when none are configured
+const DEFAULT_FAVORITES: [&str; 8] = ["👍", "❤️", "😂", "😮", "😢", "🙏", "🎉", "👀"];
+
+/// Number of recently used emojis remembered
+const RECENT_SIZE: usize = 8;
+
+command_def!(react,
+r#"/react <emoji>
+
+    emoji    Emoji to add, or to remove when already given
+
+Description:
+    React to the message picked with Alt+r in the current window, or to the
+    last one. Alt++ opens a picker of recent and favorite emojis instead.
+
+Examples:
+    /react 👍
+"#,
+{
+    emoji: String,
+},
+|aparte, _command| {
+    let target = {
+        let ui = aparte.get_mod::<UIMod>();
+        ui.reaction_target(aparte)
+    };
+    let (account, message) = target.context("No message to react to")?;
+    ReactionsMod::react(aparte, &account, &message, &emoji)
+});
+
+/// XEP-0444: Message Reactions
+///
+/// Each reaction stanza holds the whole set of emojis of its sender for a message, so that
+/// removing an emoji is sending the set without it.
+pub struct ReactionsMod {
+    favorites: Vec<String>,
+    /// Emojis used during this session, most recent first
+    recent: Vec<String>,
+}
+
+impl ReactionsMod {
+    pub fn new() -> Self {
+        Self {
+            favorites: Vec::new(),
+            recent: Vec::new(),
+        }
+    }
+
+    /// Emojis proposed by the picker: recently used ones then favorites
+    pub fn choices(&self) -> Vec<String> {
+        let mut choices = self.recent.clone();
+        for favorite in self.favorites.iter() {
+            if !choices.contains(favorite) {
+                choices.push(favorite.clone());
+            }
+        }
+        choices
+    }
+
+    fn used(&mut self, emoji: &str) {
+        self.recent.retain(|recent| recent != emoji);
+        self.recent.insert(0, emoji.to_string());
+        self.recent.truncate(RECENT_SIZE);
+    }
+
+    /// Toggle our reaction with this emoji to a message
+    pub fn react(
+        aparte: &mut Aparte,
+        account: &Account,
+        message: &VersionedXmppMessage,
+        emoji: &str,
+    ) -> Result<()> {
+        let emoji = emoji.trim();
+        if emoji.is_empty() {
+            anyhow::bail!("No emoji given");
+        }
+
+        // Channels identify messages and reactors by their own id and nicks
+        let (to, id, reactor, type_) = match message.type_ {
+            XmppMessageType::Channel => {
+                let channel = message.from.clone();
+                let id = message
+                    .stanza_id
+                    .clone()
+                    .with_context(|| format!("{channel} didn't give an id to this message"))?;
+                let nick = match aparte.get_mod::<ConversationMod>().get(account, &channel) {
+                    Some(Conversation::Channel(channel)) => intern(&channel.nick),
+                    _ => anyhow::bail!("{channel} is not joined"),
+                };
+                (channel, id, nick, MessageType::Groupchat)
+            }
+            XmppMessageType::Chat => {
+                let contact = match message.from == account.to_bare() {
+                    true => message.to.clone(),
+                    false => message.from.clone(),
+                };
+                let reactor = intern(&account.to_bare().to_string());
+                (contact, message.id.clone(), reactor, MessageType::Chat)
+            }
+        };
+
+        let mut emojis = message.reactions.get(&reactor).cloned().unwrap_or_default();
+        match emojis.iter().position(|given| given == emoji) {
+            Some(position) => {
+                emojis.remove(position);
+            }
+            None => emojis.push(emoji.to_string()),
+        }
+
+        let mut reactions = Element::builder("reactions", NS_REACTIONS).attr("id", id.as_str());
+        for emoji in emojis.iter() {
+            reactions = reactions.append(
+                Element::builder("reaction", NS_REACTIONS)
+                    .append(emoji.as_str())
+                    .build(),
+            );
+        }
+        let mut stanza = XmppParsersMessage::new(Some(Jid::Bare(to)));
+        stanza.id = Some(Uuid::new_v4().to_string());
+        stanza.type_ = type_;
+        stanza.payloads.push(reactions.build());
+        stanza
+            .payloads
+            .push(Element::builder("store", NS_HINTS).build());
+        aparte.send(account, stanza);
+
+        aparte.get_mod_mut::<ReactionsMod>().used(emoji);
+
+        // Channels reflect our reaction back, as for any occupant
+        if message.type_ == XmppMessageType::Chat {
+            Self::apply(aparte, account, None, &id, reactor, emojis);
+        }
+        Ok(())
+    }
+
+    /// Replace the emojis of a reactor to a chat message, or to a message of this channel
+    fn apply(
+        aparte: &mut Aparte,
+        account: &Account,
+        channel: Option<&BareJid>,
+        id: &str,
+        reactor: Arc<str>,
+        emojis: Vec<String>,
+    ) {
+        let event = {
+            let mut messages = aparte.get_mod_mut::<MessagesMod>();
+            let original = match channel {
+                Some(channel) => messages.get_channel_message_mut(account, channel, id),
+                None => match messages.get_mut(&Some(account.clone()), &id.to_string()) {
+                    Some(Message::Xmpp(original)) => Some(original),
+                    _ => None,
+                },
+            };
+            let original = match original {
+                Some(original) => original,
+                None => {
+                    log::info!("Reaction to unknown message {id}");
+                    return;
+                }
+            };
+            // Only participants of a chat can react to its messages
+            if channel.is_none()
+                && original.from.to_string() != *reactor
+                && original.to.to_string() != *reactor
+            {
+                log::warn!("{reactor} can't react to message {id}");
+                return;
+            }
+            match emojis.is_empty() {
+                true => original.reactions.remove(&reactor),
+                false => original.reactions.insert(reactor, emojis),
+            };
+            Event::Message(Some(account.clone()), Message::Xmpp(original.clone()))
+        };
+        aparte.schedule(event);
+    }
+}
+
+impl ModTrait for ReactionsMod {
+    fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
+        {
+            let mut disco = aparte.get_mod_mut::<disco::DiscoMod>();
+            disco.add_feature(NS_REACTIONS);
+        }
+        self.favorites = match &aparte.config.reactions {
+            Some(favorites) => favorites.clone(),
+            None => DEFAULT_FAVORITES.iter().map(ToString::to_string).collect(),
+        };
+        aparte.add_command(react::new());
+
+        Ok(())
+    }
+
+    fn can_handle_xmpp_message(
+        &mut self,
+        _aparte: &mut Aparte,
+        _account: &Account,
+        message: &XmppParsersMessage,
+        _delay: &Option<Delay>,
+    ) -> f64 {
+        match message
+            .payloads
+            .iter()
+            .any(|payload| payload.is("reactions", NS_REACTIONS))
+        {
+            true => 1f64,
+            false => 0f64,
+        }
+    }
+
+    fn handle_xmpp_message(
+        &mut self,
+        aparte: &mut Aparte,
+        account: &Account,
+        message: &XmppParsersMessage,
+        _delay: &Option<Delay>,
+        _archive: bool,
+    ) {
+        let Some(from) = &message.from else {
+            return;
+        };
+        for payload in message.payloads.iter() {
+            if !payload.is("reactions", NS_REACTIONS) {
+                continue;
+            }
+            let Some(id) = payload.attr("id") else {
+                continue;
+            };
+            let emojis = payload
+                .children()
+                .filter(|child| child.is("reaction", NS_REACTIONS))
+                .map(|child| child.text().trim().to_string())
+                .filter(|emoji| !emoji.is_empty())
+                .collect::<Vec<_>>();
+            match (&message.type_, from) {
+                (MessageType::Groupchat, Jid::Full(from)) => {
+                    let channel = from.to_bare();
+                    let reactor = intern(from.resource().as_str());
+                    Self::apply(aparte, account, Some(&channel), id, reactor, emojis);
+                }
+                (MessageType::Groupchat, Jid::Bare(_)) => {}
+                (_, from) => {
+                    let reactor = intern(&from.to_bare().to_string());
+                    Self::apply(aparte, account, None, id, reactor, emojis);
+                }
+            }
+        }
+    }
+
+    fn on_event(&mut self, _aparte: &mut Aparte, _event: &Event) {}
+}
+
+impl fmt::Display for ReactionsMod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "XEP-0444: Message Reactions")
+    }
+}
@@ -0,0 +1,621 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{Context, Result};
+use chrono::Local;
+use uuid::Uuid;
+use xmpp_parsers::chat_markers::{Displayed, Markable};
+use xmpp_parsers::chatstates::ChatState;
+use xmpp_parsers::message::{Message as XmppParsersMessage, MessageType};
+use xmpp_parsers::receipts::{Received, Request};
+use xmpp_parsers::roster::Subscription;
+use xmpp_parsers::{BareJid, Jid};
+
+use crate::account::Account;
+use crate::command::{Command, CommandParser};
+use crate::core::{Aparte, Event, ModTrait};
+use crate::message::{Delivery, LogMessage, Message, VersionedXmppMessage, XmppMessageType};
+use crate::mods::blocking::BlockingMod;
+use crate::mods::contact::ContactMod;
+use crate::mods::conversation::ConversationMod;
+use crate::mods::messages::MessagesMod;
+use crate::mods::ui::{PrivacyEntry, UIMod};
+use crate::word;
+
+command_def!(privacy_set,
+r#"/privacy set <notification> <on|off|default> [<jid>]
+
+    notification    One of receipts, markers or states
+    jid             jid of the contact/channel, current one when missing
+
+Description:
+    Choose whether delivery receipts, displayed markers or typing
+    notifications are sent in the current or a given conversation. default
+    follows the global setting.
+
+Examples:
+    /privacy set markers off
+    /privacy set states default contact@server.tld
+"#,
+{
+    notification: Notification = {
+        completion: |_aparte, _command| {
+            Notification::ALL.iter().map(|notification| notification.to_string()).collect()
+        }
+    },
+    value: String = {
+        completion: |_aparte, _command| {
+            vec![String::from("on"), String::from("off"), String::from("default")]
+        }
+    },
+    jid: Option<String>,
+},
+|aparte, _command| {
+    let (account, jid) = aparte.get_mod::<UIMod>().conversation_target(aparte, jid)?;
+    let value = match value.as_str() {
+        "on" => Some(true),
+        "off" => Some(false),
+        "default" => None,
+        _ => anyhow::bail!("Unknown value {value}, expected on, off or default"),
+    };
+    let withheld = {
+        let mut receipts = aparte.get_mod_mut::<ReceiptsMod>();
+        receipts.set(aparte, &account, &jid, notification, value)?
+    };
+    aparte.schedule(Event::Privacy {
+        conversation: Some(jid.clone()),
+        withheld,
+    });
+    crate::info!(aparte, "Privacy of {jid} updated");
+    Ok(())
+});
+
+command_def!(privacy_show,
+r#"/privacy show [<jid>]
+
+    jid    jid of the contact/channel, current one when missing
+
+Description:
+    Show which notifications are sent in the current or a given conversation
+
+Examples:
+    /privacy show
+"#,
+{
+    jid: Option<String>,
+},
+|aparte, _command| {
+    let (account, jid) = aparte.get_mod::<UIMod>().conversation_target(aparte, jid)?;
+    let summary = {
+        let receipts = aparte.get_mod::<ReceiptsMod>();
+        Notification::ALL
+            .iter()
+            .map(|notification| {
+                let sent = match receipts.allowed(&account, &jid, *notification) {
+                    true => "sent",
+                    false => "not sent",
+                };
+                format!("{notification}: {sent}")
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    crate::info!(aparte, "{jid}: {summary}");
+    Ok(())
+});
+
+command_def!(
+    privacy_overview,
+    r#"/privacy overview
+
+Description:
+    Open a window listing blocked contacts, muted conversations and
+    notification settings of the current account. Select a line with the
+    up and down arrows and press enter, with an empty input, to revert it.
+
+Examples:
+    /privacy overview
+"#,
+    {},
+    |aparte, _command| {
+        let account = aparte.current_account().context("No connection found")?;
+        let mut entries = Vec::new();
+        for jid in aparte.get_mod::<BlockingMod>().blocked(&account) {
+            entries.push(PrivacyEntry {
+                kind: String::from("blocked"),
+                jid: jid.to_string(),
+                setting: String::from("all communications"),
+                undo: format!("/unblock {jid}"),
+            });
+        }
+        for jid in aparte.get_mod::<ConversationMod>().muted(&account) {
+            entries.push(PrivacyEntry {
+                kind: String::from("muted"),
+                jid: jid.to_string(),
+                setting: String::from("notifications"),
+                undo: format!("/unmute {jid}"),
+            });
+        }
+        for (jid, notification, sent) in aparte.get_mod::<ReceiptsMod>().overrides(&account) {
+            let sent = match sent {
+                true => "sent",
+                false => "not sent",
+            };
+            entries.push(PrivacyEntry {
+                kind: String::from("privacy"),
+                jid: jid.to_string(),
+                setting: format!("{notification} {sent}"),
+                undo: format!("/privacy set {notification} default {jid}"),
+            });
+        }
+
+        if entries.is_empty() {
+            crate::info!(aparte, "Nothing blocked, muted or overridden");
+        } else {
+            let mut ui = aparte.get_mod_mut::<UIMod>();
+            ui.show_privacy(entries);
+        }
+        Ok(())
+    }
+);
+
+command_def!(privacy,
+r#"/privacy set|show|overview"#,
+{
+    action: Command = {
+        children: {
+            "set": privacy_set,
+            "show": privacy_show,
+            "overview": privacy_overview,
+        }
+    },
+});
+
+command_def!(delivery,
+r#"/delivery [<text>]
+
+    text    Text contained in the message
+
+Description:
+    Show when the last message sent in the current conversation, or the last
+    one containing the given text, was sent, then received and displayed by
+    the contact.
+
+Examples:
+    /delivery
+    /delivery "see you"
+"#,
+{
+    text: Option<String>,
+},
+|aparte, _command| {
+    let (account, jid) = aparte.get_mod::<UIMod>().conversation_target(aparte, None)?;
+    let message = {
+        let messages = aparte.get_mod::<MessagesMod>();
+        messages.find_last_sent(&account, &jid, text.as_deref())
+    };
+    let message = match (message, text) {
+        (Some(message), _) => message,
+        (None, Some(text)) => anyhow::bail!("No message containing \"{text}\" sent in {jid}"),
+        (None, None) => anyhow::bail!("No message sent in {jid}"),
+    };
+    if message.type_ != XmppMessageType::Chat {
+        anyhow::bail!("Delivery is only tracked in chats");
+    }
+    aparte.schedule(Event::ConversationLog {
+        account,
+        conversation: jid,
+        message: LogMessage {
+            id: format!("delivery-{}", message.id),
+            timestamp: Local::now().into(),
+            body: delivery_timeline(&message),
+            level: log::Level::Info,
+        },
+    });
+    Ok(())
+});
+
+/// When each delivery state of a message we sent was reached
+fn delivery_timeline(message: &VersionedXmppMessage) -> String {
+    let sent = *message.get_original_timestamp();
+    let mut timeline = format!(
+        "Delivery of \"{}\":",
+        word::excerpt(message.get_last_body(), 40)
+    );
+    for delivery in [Delivery::Sent, Delivery::Received, Delivery::Displayed] {
+        let reached = message
+            .delivery_timeline
+            .iter()
+            .find(|(reached, _)| *reached == delivery)
+            .map(|(_, timestamp)| *timestamp);
+        // Contacts may send displayed markers without receipts
+        let later = message
+            .delivery_timeline
+            .iter()
+            .any(|(reached, _)| *reached > delivery);
+        let state = match reached {
+            Some(timestamp) if delivery == Delivery::Sent => {
+                timestamp.with_timezone(&Local).format("%T").to_string()
+            }
+            Some(timestamp) => format!(
+                "{} (+{}s)",
+                timestamp.with_timezone(&Local).format("%T"),
+                (timestamp - sent).num_seconds()
+            ),
+            None if later => String::from("not reported"),
+            None if message.delivery >= Some(delivery) => String::from("before startup"),
+            None => String::from("pending"),
+        };
+        timeline.push_str(&format!("\n  {:<10} {state}", delivery.to_string()));
+    }
+    timeline
+}
+
+/// Notifications revealing our activity to the peer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Notification {
+    /// XEP-0184: Message Delivery Receipts
+    Receipts,
+    /// XEP-0333: Displayed Markers
+    Markers,
+    /// XEP-0085: Chat State Notifications
+    ChatStates,
+}
+
+impl Notification {
+    pub const ALL: [Notification; 3] = [
+        Notification::Receipts,
+        Notification::Markers,
+        Notification::ChatStates,
+    ];
+}
+
+impl FromStr for Notification {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "receipts" => Ok(Notification::Receipts),
+            "markers" => Ok(Notification::Markers),
+            "states" => Ok(Notification::ChatStates),
+            _ => anyhow::bail!("Unknown notification {s}"),
+        }
+    }
+}
+
+impl fmt::Display for Notification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Notification::Receipts => write!(f, "receipts"),
+            Notification::Markers => write!(f, "markers"),
+            Notification::ChatStates => write!(f, "states"),
+        }
+    }
+}
+
+/// Notifications explicitly enabled or disabled, unset ones follow global settings
+#[derive(Debug, Clone, Default)]
+struct Overrides(HashMap<Notification, bool>);
+
+pub struct ReceiptsMod {
+    global: HashMap<Notification, bool>,
+    overrides: HashMap<(Account, BareJid), Overrides>,
+    /// Last markable message received in each conversation, with its sender
+    markable: HashMap<(Account, BareJid), (Jid, String)>,
+    /// Conversations in which we told we are composing
+    composing: HashSet<(Account, BareJid)>,
+}
+
+impl ReceiptsMod {
+    pub fn new() -> Self {
+        Self {
+            global: Notification::ALL
+                .iter()
+                .map(|notification| (*notification, true))
+                .collect(),
+            overrides: HashMap::new(),
+            markable: HashMap::new(),
+            composing: HashSet::new(),
+        }
+    }
+
+    pub fn allowed(&self, account: &Account, jid: &BareJid, notification: Notification) -> bool {
+        self.overrides
+            .get(&(account.clone(), jid.clone()))
+            .and_then(|overrides| overrides.0.get(&notification))
+            .or_else(|| self.global.get(&notification))
+            .cloned()
+            .unwrap_or(true)
+    }
+
+    /// Notifications explicitly enabled or disabled in conversations of an account
+    pub fn overrides(&self, account: &Account) -> Vec<(BareJid, Notification, bool)> {
+        let mut overrides: Vec<(BareJid, Notification, bool)> = self
+            .overrides
+            .iter()
+            .filter(|((overridden, _), _)| overridden == account)
+            .flat_map(|((_, jid), overrides)| {
+                Notification::ALL.iter().filter_map(move |notification| {
+                    overrides
+                        .0
+                        .get(notification)
+                        .map(|sent| (jid.clone(), *notification, *sent))
+                })
+            })
+            .collect();
+        overrides.sort_by_key(|(jid, notification, _)| (jid.to_string(), notification.to_string()));
+        overrides
+    }
+
+    fn withheld(&self, overrides: Option<&Overrides>) -> Vec<Notification> {
+        Notification::ALL
+            .iter()
+            .filter(|notification| {
+                let allowed = overrides
+                    .and_then(|overrides| overrides.0.get(notification))
+                    .or_else(|| self.global.get(notification));
+                allowed == Some(&false)
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Persist a conversation setting, returns notifications now withheld in it
+    fn set(
+        &mut self,
+        aparte: &Aparte,
+        account: &Account,
+        jid: &BareJid,
+        notification: Notification,
+        value: Option<bool>,
+    ) -> Result<Vec<Notification>> {
+        let index = (account.clone(), jid.clone());
+        let mut overrides = self.overrides.get(&index).cloned().unwrap_or_default();
+        match value {
+            Some(value) => overrides.0.insert(notification, value),
+            None => overrides.0.remove(&notification),
+        };
+
+        aparte.storage.set_conversation_privacy(
+            account,
+            jid,
+            overrides.0.get(&Notification::Receipts).cloned(),
+            overrides.0.get(&Notification::Markers).cloned(),
+            overrides.0.get(&Notification::ChatStates).cloned(),
+        )?;
+
+        let withheld = self.withheld(Some(&overrides));
+        self.overrides.insert(index, overrides);
+        Ok(withheld)
+    }
+
+    fn load(&mut self, aparte: &mut Aparte, account: &Account) -> Result<()> {
+        for privacy in aparte.storage.get_conversation_privacy(account)? {
+            let jid = match BareJid::from_str(&privacy.jid) {
+                Ok(jid) => jid,
+                Err(_) => continue,
+            };
+            let overrides = Overrides(
+                [
+                    (Notification::Receipts, privacy.receipts),
+                    (Notification::Markers, privacy.chat_markers),
+                    (Notification::ChatStates, privacy.chat_states),
+                ]
+                .iter()
+                .filter_map(|(notification, value)| value.map(|value| (*notification, value)))
+                .collect(),
+            );
+            aparte.schedule(Event::Privacy {
+                conversation: Some(jid.clone()),
+                withheld: self.withheld(Some(&overrides)),
+            });
+            self.overrides.insert((account.clone(), jid), overrides);
+        }
+        Ok(())
+    }
+
+    fn send(
+        &self,
+        aparte: &mut Aparte,
+        account: &Account,
+        to: Jid,
+        payload: xmpp_parsers::Element,
+    ) {
+        let mut message = XmppParsersMessage::new(Some(to));
+        message.id = Some(Uuid::new_v4().hyphenated().to_string());
+        message.type_ = MessageType::Chat;
+        message.payloads.push(payload);
+        aparte.send(account, message);
+    }
+
+    fn send_displayed(&mut self, aparte: &mut Aparte, account: &Account, jid: &BareJid) {
+        let index = (account.clone(), jid.clone());
+        if let Some((from, id)) = self.markable.remove(&index) {
+            if self.allowed(account, jid, Notification::Markers) {
+                self.send(aparte, account, from, Displayed { id }.into());
+            }
+        }
+    }
+
+    fn handle_message(
+        &mut self,
+        aparte: &mut Aparte,
+        account: &Account,
+        message: &XmppParsersMessage,
+    ) {
+        let from = match &message.from {
+            Some(from) if message.type_ == MessageType::Chat => from,
+            _ => return,
+        };
+        let jid = from.to_bare();
+        if jid == account.to_bare() {
+            return;
+        }
+
+        for payload in message.payloads.iter() {
+            if let Ok(received) = Received::try_from(payload.clone()) {
+                aparte.schedule(Event::MessageDelivery {
+                    account: account.clone(),
+                    contact: jid.clone(),
+                    id: received.id,
+                    delivery: Delivery::Received,
+                });
+            } else if let Ok(displayed) = Displayed::try_from(payload.clone()) {
+                aparte.schedule(Event::MessageDelivery {
+                    account: account.clone(),
+                    contact: jid.clone(),
+                    id: displayed.id,
+                    delivery: Delivery::Displayed,
+                });
+            }
+        }
+
+        let id = match &message.id {
+            Some(id) => id,
+            None => return,
+        };
+
+        // A receipt tells we are online, only contacts seeing our presence get one
+        let subscribed = matches!(
+            aparte
+                .get_mod::<ContactMod>()
+                .get(account, &jid)
+                .map(|contact| &contact.subscription),
+            Some(Subscription::From | Subscription::Both)
+        );
+        for payload in message.payloads.iter() {
+            if Request::try_from(payload.clone()).is_ok()
+                && subscribed
+                && self.allowed(account, &jid, Notification::Receipts)
+            {
+                let received = Received { id: id.clone() };
+                self.send(aparte, account, from.clone(), received.into());
+            } else if Markable::try_from(payload.clone()).is_ok() {
+                self.markable
+                    .insert((account.clone(), jid.clone()), (from.clone(), id.clone()));
+            }
+        }
+
+        // Message is displayed right away in the current window unless it's scrolled up
+        let displayed = {
+            let mut ui = aparte.get_mod_mut::<UIMod>();
+            let window = ui.current_window().cloned();
+            ui.is_current_conversation(account, &jid)
+                && window.map_or(false, |window| ui.scrolled_to_bottom(&window))
+        };
+        if displayed {
+            self.send_displayed(aparte, account, &jid);
+        }
+    }
+
+    fn send_chat_state(
+        &mut self,
+        aparte: &mut Aparte,
+        account: &Account,
+        contact: &BareJid,
+        state: &ChatState,
+    ) {
+        let index = (account.clone(), contact.clone());
+        match state {
+            ChatState::Composing => {
+                if !self.composing.insert(index) {
+                    return;
+                }
+            }
+            _ => {
+                if !self.composing.remove(&index) {
+                    return;
+                }
+            }
+        }
+
+        if self.allowed(account, contact, Notification::ChatStates) {
+            self.send(
+                aparte,
+                account,
+                Jid::Bare(contact.clone()),
+                state.clone().into(),
+            );
+        }
+    }
+}
+
+impl ModTrait for ReceiptsMod {
+    fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
+        let config = &aparte.config;
+        for (notification, value) in [
+            (Notification::Receipts, config.send_receipts),
+            (Notification::Markers, config.send_chat_markers),
+            (Notification::ChatStates, config.send_chat_states),
+        ] {
+            self.global.insert(notification, value.unwrap_or(true));
+        }
+        aparte.schedule(Event::Privacy {
+            conversation: None,
+            withheld: self.withheld(None),
+        });
+        aparte.add_command(privacy::new());
+        aparte.add_command(delivery::new());
+        Ok(())
+    }
+
+    fn on_event(&mut self, aparte: &mut Aparte, event: &Event) {
+        match event {
+            Event::Connected(account, _) => {
+                if let Err(err) = self.load(aparte, account) {
+                    crate::error!(aparte, err, "Cannot load privacy settings");
+                }
+            }
+            Event::RawMessage {
+                account,
+                message,
+                archive: false,
+                ..
+            } => self.handle_message(aparte, account, message),
+            Event::WindowChanged(window) => {
+                let conversation = {
+                    let mut ui = aparte.get_mod_mut::<UIMod>();
+                    match ui.scrolled_to_bottom(window) {
+                        true => ui.window_conversation(window).cloned(),
+                        false => None,
+                    }
+                };
+                if let Some(conversation) = conversation {
+                    self.send_displayed(aparte, conversation.get_account(), conversation.get_jid());
+                }
+            }
+            Event::ChatScrolledToBottom { account, contact } => {
+                let current = {
+                    let ui = aparte.get_mod::<UIMod>();
+                    ui.is_current_conversation(account, contact)
+                };
+                if current {
+                    self.send_displayed(aparte, account, contact);
+                }
+            }
+            Event::ChatState {
+                account,
+                contact,
+                state,
+            } => self.send_chat_state(aparte, account, contact, state),
+            Event::SendMessage(account, Message::Xmpp(message))
+                if message.type_ == XmppMessageType::Chat =>
+            {
+                let contact = message.to.clone();
+                self.send_chat_state(aparte, account, &contact, &ChatState::Active);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl fmt::Display for ReceiptsMod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Receipts, chat markers and chat states")
+    }
+}
@@ -0,0 +1,294 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Context, Result};
+use uuid::Uuid;
+use xmpp_parsers::delay::Delay;
+use xmpp_parsers::iq::{Iq, IqType};
+use xmpp_parsers::message::{Body, Message as XmppParsersMessage, MessageType};
+use xmpp_parsers::{Element, Jid};
+
+use crate::account::Account;
+use crate::command::{Command, CommandParser};
+use crate::core::{Aparte, Event, ModTrait};
+use crate::i18n;
+use crate::intern::intern;
+use crate::message::{Message, Moderation, XmppMessageType, NS_FALLBACK};
+use crate::mods::disco;
+use crate::mods::messages::MessagesMod;
+use crate::mods::muc::current_channel;
+use crate::mods::ui::UIMod;
+
+/// XEP-0424: Message Retraction
+pub const NS_RETRACT: &str = "urn:xmpp:message-retract:1";
+/// XEP-0425: Moderated Message Retraction
+const NS_MODERATE: &str = "urn:xmpp:message-moderate:1";
+/// XEP-0334: Message Processing Hints
+const NS_HINTS: &str = "urn:xmpp:hints";
+
+/// Body displayed by clients not supporting retractions
+const FALLBACK_BODY: &str =
+    "This person attempted to retract a previous message, but it's unsupported by your client.";
+
+command_def!(
+    retract,
+    r#"/retract
+
+Description:
+    Retract the last message sent in the current conversation. It is removed
+    by clients supporting retractions, others display a notice instead.
+
+Examples:
+    /retract
+"#,
+    {},
+    |aparte, _command| {
+        let (account, conversation) = aparte.get_mod::<UIMod>().current_conversation()?;
+        let jid = conversation.get_jid().clone();
+
+        let message = {
+            let messages = aparte.get_mod::<MessagesMod>();
+            messages.find_last_sent(&account, &jid, None)
+        };
+        let mut message = message.with_context(|| format!("No message sent in {jid}"))?;
+        if message.retracted {
+            anyhow::bail!("Last message sent in {jid} is already retracted");
+        }
+
+        let mut retraction = XmppParsersMessage::new(Some(Jid::Bare(jid.clone())));
+        retraction.id = Some(Uuid::new_v4().to_string());
+        retraction.type_ = match message.type_ {
+            XmppMessageType::Chat => MessageType::Chat,
+            XmppMessageType::Channel => MessageType::Groupchat,
+        };
+        retraction
+            .bodies
+            .insert(String::new(), Body(String::from(FALLBACK_BODY)));
+        retraction.payloads.push(
+            Element::builder("retract", NS_RETRACT)
+                .attr("id", message.id.as_str())
+                .build(),
+        );
+        retraction.payloads.push(
+            Element::builder("fallback", NS_FALLBACK)
+                .attr("for", NS_RETRACT)
+                .build(),
+        );
+        retraction
+            .payloads
+            .push(Element::builder("store", NS_HINTS).build());
+        aparte.send(&account, retraction);
+
+        message.retracted = true;
+        aparte.schedule(Event::Message(Some(account), Message::Xmpp(message)));
+        Ok(())
+    }
+);
+
+command_def!(moderate,
+r#"/moderate <text> [<reason>]
+
+    text      Text contained in the message to remove
+    reason    Reason given to occupants
+
+Description:
+    Remove the last message containing the given text from the channel of
+    the current window for every occupant. Only moderators of the channel can
+    remove messages.
+
+Examples:
+    /moderate "buy cheap"
+    /moderate "buy cheap" "No advertising"
+"#,
+{
+    text: String,
+    reason: Option<String>,
+},
+|aparte, _command| {
+    let channel = current_channel(aparte)?;
+    let message = {
+        let messages = aparte.get_mod::<MessagesMod>();
+        messages.find_last(&channel.account, &channel.jid, Some(&text))
+    };
+    let message = message.with_context(|| format!("No message containing \"{text}\" in {}", channel.jid))?;
+    let stanza_id = message.stanza_id.with_context(|| format!("{} didn't give an id to this message", channel.jid))?;
+
+    let mut moderate = Element::builder("moderate", NS_MODERATE)
+        .attr("id", stanza_id)
+        .append(Element::builder("retract", NS_RETRACT).build());
+    if let Some(reason) = reason {
+        moderate = moderate.append(Element::builder("reason", NS_MODERATE).append(reason).build());
+    }
+    let iq = Iq {
+        from: None,
+        to: Some(Jid::Bare(channel.jid.clone())),
+        id: Uuid::new_v4().hyphenated().to_string(),
+        payload: IqType::Set(moderate.build()),
+    };
+
+    Aparte::spawn({
+        let mut aparte = aparte.proxy();
+        async move {
+            // The channel broadcasts the retraction once the message is removed
+            let err = match aparte.iq(&channel.account, iq).await {
+                Ok(Iq { payload: IqType::Result(_), .. }) => return,
+                Ok(Iq { payload: IqType::Error(err), .. }) => {
+                    anyhow!("{}", i18n::xmpp_err_to_string(&err, vec![]).1)
+                }
+                Ok(_) => anyhow!("invalid response"),
+                Err(err) => err,
+            };
+            crate::error!(aparte, err, "Cannot remove message from {}", channel.jid);
+        }
+    });
+    Ok(())
+});
+
+pub struct RetractionMod {}
+
+impl RetractionMod {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    fn handle_retract(
+        &mut self,
+        aparte: &mut Aparte,
+        account: &Account,
+        message: &XmppParsersMessage,
+        id: &str,
+    ) {
+        let from = match &message.from {
+            Some(from) => from,
+            None => return,
+        };
+
+        let event = {
+            let mut messages = aparte.get_mod_mut::<MessagesMod>();
+            match messages.get_mut(&Some(account.clone()), &id.to_string()) {
+                Some(Message::Xmpp(original)) => {
+                    // Only the author of a message can retract it
+                    let author = match original.type_ {
+                        XmppMessageType::Channel => from == &original.from_full,
+                        XmppMessageType::Chat => from.to_bare() == original.from,
+                    };
+                    if !author {
+                        log::warn!("{from} can't retract message {id} of {}", original.from);
+                        return;
+                    }
+                    original.retracted = true;
+                    Event::Message(Some(account.clone()), Message::Xmpp(original.clone()))
+                }
+                _ => {
+                    log::info!("Retraction of unknown message {id}");
+                    return;
+                }
+            }
+        };
+        aparte.schedule(event);
+    }
+
+    /// Apply the removal of a message by a moderator, announced by the channel itself
+    fn handle_moderation(
+        &mut self,
+        aparte: &mut Aparte,
+        account: &Account,
+        message: &XmppParsersMessage,
+        retract: &Element,
+        moderated: &Element,
+    ) {
+        let (Some(Jid::Bare(channel)), Some(id)) = (&message.from, retract.attr("id")) else {
+            return;
+        };
+
+        let moderation = Moderation {
+            by: moderated
+                .attr("by")
+                .and_then(|by| Jid::from_str(by).ok())
+                .and_then(|by| match by {
+                    Jid::Full(by) => Some(intern(by.resource().as_str())),
+                    Jid::Bare(_) => None,
+                }),
+            reason: retract
+                .get_child("reason", NS_RETRACT)
+                .map(|reason| reason.text())
+                .filter(|reason| !reason.is_empty()),
+        };
+
+        let event = {
+            let mut messages = aparte.get_mod_mut::<MessagesMod>();
+            match messages.get_channel_message_mut(account, channel, id) {
+                Some(original) => {
+                    original.retracted = true;
+                    original.moderation = Some(moderation);
+                    Event::Message(Some(account.clone()), Message::Xmpp(original.clone()))
+                }
+                None => {
+                    log::info!("Moderation of unknown message {id} in {channel}");
+                    return;
+                }
+            }
+        };
+        aparte.schedule(event);
+    }
+}
+
+impl ModTrait for RetractionMod {
+    fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
+        {
+            let mut disco = aparte.get_mod_mut::<disco::DiscoMod>();
+            disco.add_feature(NS_RETRACT);
+        }
+        aparte.add_command(retract::new());
+        aparte.add_command(moderate::new());
+
+        Ok(())
+    }
+
+    fn can_handle_xmpp_message(
+        &mut self,
+        _aparte: &mut Aparte,
+        _account: &Account,
+        message: &XmppParsersMessage,
+        _delay: &Option<Delay>,
+    ) -> f64 {
+        match message
+            .payloads
+            .iter()
+            .any(|payload| payload.is("retract", NS_RETRACT))
+        {
+            true => 1f64,
+            false => 0f64,
+        }
+    }
+
+    fn handle_xmpp_message(
+        &mut self,
+        aparte: &mut Aparte,
+        account: &Account,
+        message: &XmppParsersMessage,
+        _delay: &Option<Delay>,
+        _archive: bool,
+    ) {
+        for payload in message.payloads.iter() {
+            if payload.is("retract", NS_RETRACT) {
+                if let Some(moderated) = payload.get_child("moderated", NS_MODERATE) {
+                    self.handle_moderation(aparte, account, message, payload, moderated);
+                } else if let Some(id) = payload.attr("id") {
+                    self.handle_retract(aparte, account, message, id);
+                }
+            }
+        }
+    }
+
+    fn on_event(&mut self, _aparte: &mut Aparte, _event: &Event) {}
+}
+
+impl fmt::Display for RetractionMod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "XEP-0424: Message Retraction")
+    }
+}
@@ -0,0 +1,247 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local as LocalTz};
+
+use crate::account::Account;
+use crate::command::{Command, CommandParser};
+use crate::core::{Aparte, Event, ModTrait};
+use crate::message::{Direction, Message};
+use crate::mods::upload::expand_home;
+use crate::storage::SessionJournalEntry;
+
+/// Entries shown by /sessions show
+const SHOWN_ENTRIES: i64 = 50;
+
+/// Entries kept in the journal of each account, older ones are dropped on connection
+const KEPT_ENTRIES: i64 = 1000;
+
+/// Ids of the last counted messages, corrections and retractions following them closely
+const RECENT_IDS: usize = 64;
+
+command_def!(sessions_show,
+r#"/sessions show [<account>]
+
+    account    account whose connections are shown, all by default
+
+Description:
+    Show the latest entries of the connection journal: connections,
+    disconnections with the number of messages exchanged during the session,
+    reconnections and authentication failures.
+
+Examples:
+    /sessions show
+    /sessions show me@example.org/aparte
+"#,
+{
+    account: Option<String>,
+},
+|aparte, _command| {
+    let account = match account {
+        Some(account) => Some(Account::from_str(&account).with_context(|| format!("Invalid account {account}"))?),
+        None => None,
+    };
+    let entries = aparte.storage.get_session_journal(account.as_ref(), Some(SHOWN_ENTRIES))?;
+    if entries.is_empty() {
+        anyhow::bail!("No connection recorded");
+    }
+
+    let mut journal = String::from("Connection journal:");
+    for entry in entries.iter() {
+        journal.push_str(&format!("\n  {}", SessionsMod::format_entry(entry)));
+    }
+    aparte.log(journal);
+    Ok(())
+});
+
+command_def!(sessions_export,
+r#"/sessions export <file>
+
+    file    path of the file to write
+
+Description:
+    Write the whole connection journal of all accounts to a file, one entry
+    per line, to keep it for later review.
+
+Examples:
+    /sessions export ~/aparte-sessions.log
+"#,
+{
+    file: String,
+},
+|aparte, _command| {
+    let entries = aparte.storage.get_session_journal(None, None)?;
+    let mut journal = String::new();
+    for entry in entries.iter() {
+        journal.push_str(&format!("{} {}\n", entry.account, SessionsMod::format_entry(entry)));
+    }
+    let path = expand_home(&file);
+    std::fs::write(&path, journal).with_context(|| format!("Cannot write {}", path.display()))?;
+    crate::info!(aparte, "{} journal entries written to {}", entries.len(), path.display());
+    Ok(())
+});
+
+command_def!(sessions,
+r#"/sessions show|export"#,
+{
+    action: Command = {
+        children: {
+            "show": sessions_show,
+            "export": sessions_export,
+        }
+    },
+});
+
+/// Connection of an account, from the moment it got online
+struct Session {
+    since: DateTime<LocalTz>,
+    received: u64,
+    sent: u64,
+    /// Ids of the last counted messages, their corrections and retractions aren't counted again
+    recent: VecDeque<String>,
+}
+
+/// Journal of connections kept in storage, to diagnose flaky servers
+pub struct SessionsMod {
+    sessions: HashMap<Account, Session>,
+    /// Accounts whose connection was lost, their next connection being a reconnection
+    lost: HashSet<Account>,
+}
+
+impl SessionsMod {
+    pub fn new() -> Self {
+        Self {
+            sessions: HashMap::new(),
+            lost: HashSet::new(),
+        }
+    }
+
+    fn record(aparte: &Aparte, account: &Account, event: &str, detail: Option<&str>) {
+        let timestamp = LocalTz::now().to_rfc3339();
+        if let Err(err) = aparte
+            .storage
+            .add_session_journal_entry(account, &timestamp, event, detail)
+        {
+            log::warn!("Cannot record {event} in connection journal: {err}");
+        }
+    }
+
+    fn format_entry(entry: &SessionJournalEntry) -> String {
+        let timestamp = DateTime::parse_from_rfc3339(&entry.timestamp)
+            .map(|timestamp| {
+                timestamp
+                    .with_timezone(&LocalTz)
+                    .format("%F %T")
+                    .to_string()
+            })
+            .unwrap_or_else(|_| entry.timestamp.clone());
+        match &entry.detail {
+            Some(detail) => format!("{timestamp} {}: {detail}", entry.event),
+            None => format!("{timestamp} {}", entry.event),
+        }
+    }
+
+    fn format_duration(seconds: i64) -> String {
+        match seconds {
+            seconds if seconds < 60 => format!("{seconds}s"),
+            seconds if seconds < 3600 => format!("{}m{:02}s", seconds / 60, seconds % 60),
+            seconds => format!("{}h{:02}m", seconds / 3600, seconds % 3600 / 60),
+        }
+    }
+
+    fn connected(&mut self, aparte: &Aparte, account: &Account) {
+        let event = match self.lost.remove(account) {
+            true => "reconnected",
+            false => "connected",
+        };
+        Self::record(aparte, account, event, None);
+        if let Err(err) = aparte.storage.prune_session_journal(account, KEPT_ENTRIES) {
+            log::warn!("Cannot prune connection journal: {err}");
+        }
+        self.sessions.insert(
+            account.clone(),
+            Session {
+                since: LocalTz::now(),
+                received: 0,
+                sent: 0,
+                recent: VecDeque::new(),
+            },
+        );
+    }
+
+    fn disconnected(&mut self, aparte: &Aparte, account: &Account, reason: &str) {
+        self.lost.insert(account.clone());
+        let detail = match self.sessions.remove(account) {
+            Some(session) => format!(
+                "{reason} after {}, {} messages received, {} sent",
+                Self::format_duration((LocalTz::now() - session.since).num_seconds()),
+                session.received,
+                session.sent
+            ),
+            None => reason.to_string(),
+        };
+        Self::record(aparte, account, "disconnected", Some(&detail));
+    }
+
+    fn count(&mut self, account: &Account, message: &Message) {
+        let (session, message) = match (self.sessions.get_mut(account), message) {
+            (Some(session), Message::Xmpp(message)) if !message.archive => (session, message),
+            _ => return,
+        };
+        if session.recent.contains(&message.id) {
+            return;
+        }
+        if session.recent.len() >= RECENT_IDS {
+            session.recent.pop_front();
+        }
+        session.recent.push_back(message.id.clone());
+        match message.direction {
+            Direction::Incoming => session.received += 1,
+            Direction::Outgoing => session.sent += 1,
+        }
+    }
+}
+
+impl ModTrait for SessionsMod {
+    fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
+        aparte.add_command(sessions::new());
+        Ok(())
+    }
+
+    fn on_event(&mut self, aparte: &mut Aparte, event: &Event) {
+        match event {
+            Event::Connected(account, _) => self.connected(aparte, account),
+            Event::Disconnected(account, reason) => self.disconnected(aparte, account, reason),
+            Event::ConnectionBroken(account, reason) => {
+                Self::record(aparte, account, "connection broken", Some(reason))
+            }
+            Event::ConnectionFailed {
+                account,
+                attempt,
+                error,
+                ..
+            } => Self::record(
+                aparte,
+                account,
+                "connection failed",
+                Some(&format!("{error} (attempt {})", attempt + 1)),
+            ),
+            Event::AuthError(account, err) => {
+                Self::record(aparte, account, "auth failure", Some(err))
+            }
+            Event::Message(Some(account), message) => self.count(account, message),
+            _ => {}
+        }
+    }
+}
+
+impl fmt::Display for SessionsMod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Connection journal")
+    }
+}
@@ -0,0 +1,121 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use std::collections::VecDeque;
+use std::fmt;
+
+use chrono::{DateTime, FixedOffset, Local as LocalTz};
+use uuid::Uuid;
+
+use crate::command::{Command, CommandParser};
+use crate::core::{Aparte, Event, ModTrait};
+use crate::message::{LogMessage, Message};
+use crate::redact;
+
+command_def!(events,
+r#"/events [<filter>]
+
+    filter    Only show events containing this text (case insensitive)
+
+Description:
+    Show the last core events in the events window
+
+Examples:
+    /events
+    /events Presence
+"#,
+{
+    filter: Option<String>
+},
+|aparte, _command| {
+    let entries = {
+        let trace = aparte.get_mod::<TraceMod>();
+        if trace.size == 0 {
+            anyhow::bail!("Event tracing is disabled, set event_trace_size to enable it");
+        }
+        trace.filter(filter.as_deref())
+    };
+    aparte.schedule(Event::EventTrace(entries));
+    Ok(())
+});
+
+struct TraceEntry {
+    timestamp: DateTime<FixedOffset>,
+    event: String,
+}
+
+pub struct TraceMod {
+    /// Last core events, oldest first
+    entries: VecDeque<TraceEntry>,
+    /// Number of kept events, tracing is disabled by default as each event is formatted
+    size: usize,
+}
+
+impl TraceMod {
+    pub fn new() -> Self {
+        Self {
+            entries: VecDeque::new(),
+            size: 0,
+        }
+    }
+
+    fn record(&mut self, event: &Event) {
+        let event = match event {
+            // Displaying trace would trace the trace
+            Event::EventTrace(_) => return,
+            // Don't keep typed keys, they might be a password
+            Event::Key(_) => String::from("Key(..)"),
+            event => redact::redact(&format!("{event:?}")),
+        };
+
+        while self.entries.len() >= self.size {
+            self.entries.pop_front();
+        }
+
+        self.entries.push_back(TraceEntry {
+            timestamp: LocalTz::now().into(),
+            event,
+        });
+    }
+
+    pub fn filter(&self, filter: Option<&str>) -> Vec<Message> {
+        let filter = filter.map(|filter| filter.to_lowercase());
+        self.entries
+            .iter()
+            .filter(|entry| match &filter {
+                Some(filter) => entry.event.to_lowercase().contains(filter),
+                None => true,
+            })
+            .map(|entry| {
+                Message::Log(LogMessage {
+                    id: Uuid::new_v4().to_string(),
+                    timestamp: entry.timestamp,
+                    body: entry.event.clone(),
+                    level: log::Level::Info,
+                })
+            })
+            .collect()
+    }
+}
+
+impl ModTrait for TraceMod {
+    fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
+        if let Some(size) = aparte.config.event_trace_size {
+            self.size = size;
+        }
+        aparte.add_command(events::new());
+        Ok(())
+    }
+
+    fn on_event(&mut self, _aparte: &mut Aparte, event: &Event) {
+        if self.size > 0 {
+            self.record(event);
+        }
+    }
+}
+
+impl fmt::Display for TraceMod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Event trace")
+    }
+}
@@ -0,0 +1,146 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use std::convert::TryFrom;
+use std::fmt;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+
+use crate::contact::Tune;
+use crate::core::{Aparte, Event, ModTrait};
+use crate::mods::disco;
+use crate::mods::pubsub::{PersonalEvent, PubSubMod};
+
+const NS_TUNE: &str = "http://jabber.org/protocol/tune";
+
+/// Delay between two readings of the played track
+const POLL_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Metadata of the current MPRIS player printed by playerctl, one field per line
+const PLAYERCTL_FORMAT: &str = "{{status}}\n{{artist}}\n{{title}}\n{{album}}\n{{mpris:length}}";
+
+impl PersonalEvent for Tune {
+    const NS: &'static str = NS_TUNE;
+    const NAME: &'static str = "tune";
+    const DESCRIPTION: &'static str = "tune";
+
+    fn fields(&self) -> Vec<(&'static str, Option<String>)> {
+        vec![
+            ("artist", self.artist.clone()),
+            ("length", self.length.map(|length| length.to_string())),
+            ("source", self.source.clone()),
+            ("title", self.title.clone()),
+        ]
+    }
+
+    fn from_fields(field: &dyn Fn(&str) -> Option<String>) -> Self {
+        Tune {
+            artist: field("artist"),
+            title: field("title"),
+            source: field("source"),
+            length: field("length").and_then(|length| length.parse().ok()),
+        }
+    }
+}
+
+/// XEP-0118: User Tune
+///
+/// When `now_playing` is enabled, the track played by MPRIS media players is read over D-Bus with
+/// playerctl and published to contacts.
+pub struct TuneMod {
+    /// Track currently played, published to accounts once they get online
+    playing: Option<Tune>,
+}
+
+impl TuneMod {
+    pub fn new() -> Self {
+        Self { playing: None }
+    }
+
+    /// Track described by playerctl metadata, none unless it is playing
+    fn parse_metadata(metadata: &str) -> Option<Tune> {
+        let mut lines = metadata.lines().map(str::trim);
+        if lines.next() != Some("Playing") {
+            return None;
+        }
+        let mut field = || {
+            lines
+                .next()
+                .filter(|value| !value.is_empty())
+                .map(str::to_string)
+        };
+        let tune = Tune {
+            artist: field(),
+            title: field(),
+            source: field(),
+            // MPRIS length is in microseconds
+            length: field()
+                .and_then(|length| length.parse::<u64>().ok())
+                .and_then(|length| u16::try_from(length / 1_000_000).ok()),
+        };
+        match tune.artist.is_some() || tune.title.is_some() {
+            true => Some(tune),
+            false => None,
+        }
+    }
+
+    /// Track played by the current MPRIS player, failing when playerctl cannot be run
+    async fn now_playing() -> Result<Option<Tune>> {
+        let output = tokio::process::Command::new("playerctl")
+            .arg("metadata")
+            .arg("--format")
+            .arg(PLAYERCTL_FORMAT)
+            .output()
+            .await
+            .context("Cannot run playerctl")?;
+        // playerctl fails when no player is running
+        match output.status.success() {
+            true => Ok(Self::parse_metadata(&String::from_utf8_lossy(
+                &output.stdout,
+            ))),
+            false => Ok(None),
+        }
+    }
+}
+
+impl ModTrait for TuneMod {
+    fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
+        let mut disco = aparte.get_mod_mut::<disco::DiscoMod>();
+        disco.add_feature(format!("{}+notify", NS_TUNE));
+        Ok(())
+    }
+
+    fn on_event(&mut self, aparte: &mut Aparte, event: &Event) {
+        match event {
+            Event::Start if aparte.config.now_playing => PubSubMod::watch_personal(
+                aparte,
+                POLL_INTERVAL,
+                Self::now_playing,
+                Event::NowPlaying,
+            ),
+            Event::NowPlaying(tune) => {
+                self.playing = tune.clone();
+                for account in aparte.connected_accounts() {
+                    PubSubMod::publish_personal(aparte, &account, tune.as_ref());
+                }
+            }
+            Event::Connected(account, _) => {
+                if let Some(tune) = &self.playing {
+                    PubSubMod::publish_personal(aparte, account, Some(tune));
+                }
+            }
+            event => {
+                if let Some((account, jid, tune)) = PubSubMod::personal_event(event) {
+                    aparte.schedule(Event::Tune { account, jid, tune });
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for TuneMod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "XEP-0118: User Tune")
+    }
+}
@@ -4,10 +4,14 @@
 use backtrace::Backtrace;
 use chrono::offset::{Local, TimeZone};
 use chrono::Local as LocalTz;
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime};
 use futures::task::{AtomicWaker, Context, Poll};
 use futures::Stream;
+use fuzzy_matcher::skim::SkimMatcherV2;
+use fuzzy_matcher::FuzzyMatcher;
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::cmp;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::hash::{Hash, Hasher};
 use std::io::{Error as IoError, ErrorKind as IoErrorKind};
@@ -16,6 +20,7 @@ use std::os::fd::AsFd;
 use std::panic;
 use std::pin::Pin;
 use std::rc::Rc;
+use std::str::FromStr;
 use std::sync::mpsc;
 use std::sync::{Arc, Mutex};
 use std::thread;
@@ -25,36 +30,575 @@ use termion::event::{parse_event as termion_parse_event, Event as TermionEvent,
 use termion::get_tty;
 use termion::raw::IntoRawMode;
 use termion::screen::IntoAlternateScreen;
+use unicode_segmentation::UnicodeSegmentation;
 use uuid::Uuid;
+use xmpp_parsers::chatstates::ChatState;
+use xmpp_parsers::data_forms::DataForm;
+use xmpp_parsers::disco;
+use xmpp_parsers::roster::Subscription;
 use xmpp_parsers::{BareJid, Jid};
 
+use crate::account::Account;
 use crate::color::{id_to_rgb, ColorTuple};
-use crate::command::Command;
+use crate::command::{Command, CommandParser};
 use crate::config::Config;
-use crate::conversation::{Channel, Chat, Conversation};
-use crate::core::{Aparte, Event, ModTrait};
+use crate::conversation::{Anonymity, Channel, Chat, Conversation};
+use crate::core::{Aparte, BellReason, Event, ModTrait};
 use crate::cursor::Cursor;
 use crate::i18n;
-use crate::message::{Direction, Message, XmppMessageType};
+use crate::intern::intern;
+use crate::message::{
+    Delivery, Direction, LogMessage, Message, Reply, VersionedXmppMessage, XmppMessageType,
+};
+use crate::mods::bookmarks::BookmarksMod;
+use crate::mods::channels::ChannelListing;
+use crate::mods::contact::ContactMod;
+use crate::mods::conversation::ConversationMod;
+use crate::mods::disco::DiscoMod;
+use crate::mods::messages::MessagesMod;
+use crate::mods::omemo::AESGCM_SCHEME;
+use crate::mods::pubsub::PUBSUB_WINDOW;
+use crate::mods::reactions::ReactionsMod;
+use crate::mods::receipts::Notification;
 use crate::terminus::{
-    self, BufferedScreen, BufferedWin, Dimension, FrameLayout, Input, Layout, Layouts,
-    LinearLayout, ListView, Orientation, Screen, View, Window as _,
+    self, BufferedScreen, BufferedWin, Dimension, FormView, FrameLayout, GridView, Input, Layout,
+    Layouts, LinearLayout, ListView, Orientation, Screen, TableView, View, Window as _,
 };
+use crate::word;
 use crate::{contact, conversation};
 
+/// Number of chars from which the length of the input is displayed
+const INPUT_COUNTER_THRESHOLD: usize = 200;
+
 // Debounce rendering at 350ms pace (based on Doherty Threshold)
 const UI_DEBOUNCE_NS: u32 = 35_000_000u32;
 
+// Number of coalesced events per render above which we consider being flooded
+const UI_FLOOD_THRESHOLD: u32 = 16;
+
+// Duration of the status line flash of a visual bell
+const FLASH_DURATION: Duration = Duration::from_millis(150);
+
+// Window displaying traced core events, see /events
+const EVENTS_WINDOW: &str = "events";
+const CHANNELS_WINDOW: &str = "channels";
+const MEMBERS_WINDOW: &str = "members";
+const MENTIONS_WINDOW: &str = "mentions";
+// Window displaying connection, subscription, bookmark and error events, see /activity
+const ACTIVITY_WINDOW: &str = "activity";
+// Window displaying pinned messages, see /pins
+const PINS_WINDOW: &str = "pins";
+// Window listing destinations of the quick switcher while it is open, see Alt+k
+const QUICK_SWITCH_WINDOW: &str = "switch";
+// Window proposing emojis to react with while it is open, see Alt++
+const REACTIONS_WINDOW: &str = "reactions";
+// Window listing commands while the command palette is open, see Alt+x
+const COMMAND_PALETTE_WINDOW: &str = "commands";
+// Window listing blocked jids, muted conversations and notification overrides, see /privacy overview
+const PRIVACY_WINDOW: &str = "privacy";
+const DISCO_WINDOW: &str = "disco";
+// Window displaying the data form being filled, see FormView
+const FORM_WINDOW: &str = "form";
+
+// Number of destinations proposed by the quick switcher
+const QUICK_SWITCH_SIZE: usize = 10;
+// Number of commands proposed by the command palette
+const COMMAND_PALETTE_SIZE: usize = 15;
+
+// Messages kept in memory per conversation window, unless configured otherwise
+const WINDOW_HISTORY_LIMIT: usize = 2000;
+
+// Longer bodies are truncated until expanded, unless configured otherwise
+const BODY_MAX_LINES: usize = 30;
+const BODY_MAX_CHARS: usize = 4096;
+
+command_def!(
+    code,
+    r#"/code
+
+Description:
+    Send the next message of the current window as a preformatted block.
+
+Examples:
+    /code
+"#,
+    {},
+    |aparte, command| {
+        {
+            let mut ui = aparte.get_mod_mut::<UIMod>();
+            ui.set_pending_markup(&command.context, PendingMarkup::Code)?;
+        }
+        crate::info!(aparte, "Next message will be sent as a preformatted block");
+        Ok(())
+    }
+);
+
+command_def!(
+    mentions,
+    r#"/mentions
+
+Description:
+    Show messages mentioning you in every channel since startup.
+
+Examples:
+    /mentions
+"#,
+    {},
+    |aparte, _command| {
+        let mut ui = aparte.get_mod_mut::<UIMod>();
+        ui.show_mentions();
+        Ok(())
+    }
+);
+
+command_def!(spoiler,
+r#"/spoiler [<hint>]
+
+    hint    Hint displayed instead of the hidden message
+
+Description:
+    Send the next message of the current window as a spoiler. Received
+    spoilers are hidden until revealed with Alt+s, which reveals the message
+    picked with Alt+r or all of them when none is picked.
+
+Examples:
+    /spoiler
+    /spoiler "End of the movie"
+"#,
+{
+    hint: Option<String>,
+},
+|aparte, command| {
+    {
+        let mut ui = aparte.get_mod_mut::<UIMod>();
+        ui.set_pending_markup(&command.context, PendingMarkup::Spoiler(hint))?;
+    }
+    crate::info!(aparte, "Next message will be sent as a spoiler");
+    Ok(())
+});
+
+command_def!(roster_group,
+r#"/roster group <strategy>
+
+    strategy    One of groups, domain, presence or activity
+
+Description:
+    Change how contacts are grouped in the roster: by roster groups (the
+    default), by server domain, by presence or by recent activity.
+
+Examples:
+    /roster group domain
+    /roster group groups
+"#,
+{
+    strategy: RosterGrouping = {
+        completion: |_aparte, _command| {
+            RosterGrouping::ALL.iter().map(|strategy| strategy.to_string()).collect()
+        }
+    },
+},
+|aparte, _command| {
+    {
+        let mut ui = aparte.get_mod_mut::<UIMod>();
+        ui.set_roster_grouping(strategy);
+    }
+    crate::info!(aparte, "Roster grouped by {strategy}");
+    Ok(())
+});
+
+command_def!(roster_collapse,
+r#"/roster collapse <group>
+
+    group    name of the roster group
+
+Description:
+    Hide contacts of a roster group, only its name and the number of hidden
+    contacts being displayed. Collapsed groups are remembered across restarts.
+
+Examples:
+    /roster collapse Work
+"#,
+{
+    group: String = {
+        completion: |aparte, _command| {
+            aparte.get_mod_mut::<UIMod>().roster_groups()
+        }
+    },
+},
+|aparte, _command| {
+    {
+        let mut ui = aparte.get_mod_mut::<UIMod>();
+        ui.set_roster_group_collapsed(aparte, &group, true)?;
+    }
+    crate::info!(aparte, "Roster group {group} collapsed");
+    Ok(())
+});
+
+command_def!(roster_expand,
+r#"/roster expand <group>
+
+    group    name of the roster group
+
+Description:
+    Show again contacts of a collapsed roster group.
+
+Examples:
+    /roster expand Work
+"#,
+{
+    group: String = {
+        completion: |aparte, _command| {
+            aparte.get_mod_mut::<UIMod>().roster_groups()
+        }
+    },
+},
+|aparte, _command| {
+    {
+        let mut ui = aparte.get_mod_mut::<UIMod>();
+        ui.set_roster_group_collapsed(aparte, &group, false)?;
+    }
+    crate::info!(aparte, "Roster group {group} expanded");
+    Ok(())
+});
+
+command_def!(roster,
+r#"/roster group|collapse|expand"#,
+{
+    action: Command = {
+        children: {
+            "group": roster_group,
+            "collapse": roster_collapse,
+            "expand": roster_expand,
+        }
+    },
+});
+
+command_def!(search,
+r#"/search <text>
+
+    text    Text to look for, case insensitively
+
+Description:
+    Scroll the current window up to the previous message containing the
+    given text. Repeat the command to find older ones.
+
+Examples:
+    /search timeout
+    /search "connection lost"
+"#,
+{
+    text: String,
+},
+|aparte, _command| {
+    let found = {
+        let mut ui = aparte.get_mod_mut::<UIMod>();
+        ui.search(&text)
+    };
+    if !found {
+        crate::info!(aparte, "No previous message containing {text}");
+    }
+    Ok(())
+});
+
+command_def!(goto,
+r#"/goto <date>
+
+    date    YYYY-MM-DD, YYYY-MM-DD HH:MM or HH:MM for today
+
+Description:
+    Scroll the current window to the first message since the given date.
+
+Examples:
+    /goto 2024-03-12
+    /goto "2024-03-12 18:30"
+    /goto 09:00
+"#,
+{
+    date: String,
+},
+|aparte, _command| {
+    let date = parse_date(&date)?;
+    let found = {
+        let mut ui = aparte.get_mod_mut::<UIMod>();
+        ui.jump(date)
+    };
+    if !found {
+        crate::info!(aparte, "No message since {}", date.format("%F %R"));
+    }
+    Ok(())
+});
+
+command_def!(console_level,
+r#"/console level <level>
+
+    level    One of error, warn, info or debug
+
+Description:
+    Only show console messages at least as severe as the given level.
+
+Examples:
+    /console level error
+    /console level debug
+"#,
+{
+    level: String = {
+        completion: |_aparte, _command| {
+            ["error", "warn", "info", "debug"].iter().map(|level| level.to_string()).collect()
+        }
+    },
+},
+|aparte, _command| {
+    let level = log::Level::from_str(&level)
+        .map_err(|_| anyhow::anyhow!("Unknown level {level}, expected error, warn, info or debug"))?;
+    let mut ui = aparte.get_mod_mut::<UIMod>();
+    ui.set_console_level(level);
+    Ok(())
+});
+
+command_def!(console,
+r#"/console level"#,
+{
+    action: Command = {
+        children: {
+            "level": console_level,
+        }
+    },
+});
+
+/// Strategy used to group contacts in the roster
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RosterGrouping {
+    /// Groups defined in the roster
+    Groups,
+    /// Server part of the contact jid
+    Domain,
+    Presence,
+    /// Date of the last exchanged message
+    Activity,
+}
+
+impl RosterGrouping {
+    const ALL: [RosterGrouping; 4] = [
+        RosterGrouping::Groups,
+        RosterGrouping::Domain,
+        RosterGrouping::Presence,
+        RosterGrouping::Activity,
+    ];
+
+    fn groups(
+        &self,
+        contact: &contact::Contact,
+        activity: Option<&DateTime<FixedOffset>>,
+    ) -> Vec<contact::Group> {
+        let name = match self {
+            RosterGrouping::Groups if !contact.groups.is_empty() => return contact.groups.clone(),
+            RosterGrouping::Groups => String::from("Contacts"),
+            RosterGrouping::Domain => contact.jid.domain().to_string(),
+            RosterGrouping::Presence => match contact.presence {
+                contact::Presence::Available | contact::Presence::Chat => String::from("Online"),
+                contact::Presence::Away | contact::Presence::Xa => String::from("Away"),
+                contact::Presence::Dnd => String::from("Busy"),
+                contact::Presence::Unavailable => String::from("Offline"),
+            },
+            RosterGrouping::Activity => match activity {
+                Some(last) => match LocalTz::now().signed_duration_since(*last) {
+                    elapsed if elapsed < chrono::Duration::days(1) => String::from("Today"),
+                    elapsed if elapsed < chrono::Duration::weeks(1) => String::from("This week"),
+                    _ => String::from("Older"),
+                },
+                None => String::from("Inactive"),
+            },
+        };
+        vec![contact::Group(name)]
+    }
+}
+
+impl FromStr for RosterGrouping {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "groups" => Ok(RosterGrouping::Groups),
+            "domain" => Ok(RosterGrouping::Domain),
+            "presence" => Ok(RosterGrouping::Presence),
+            "activity" => Ok(RosterGrouping::Activity),
+            _ => anyhow::bail!("Unknown roster grouping {s}"),
+        }
+    }
+}
+
+impl fmt::Display for RosterGrouping {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RosterGrouping::Groups => write!(f, "groups"),
+            RosterGrouping::Domain => write!(f, "domain"),
+            RosterGrouping::Presence => write!(f, "presence"),
+            RosterGrouping::Activity => write!(f, "activity"),
+        }
+    }
+}
+
+/// How the bell rings
+#[derive(Clone, Copy, PartialEq)]
+enum BellStyle {
+    Audible,
+    /// Status line colors are inverted for a moment
+    Visual,
+    Both,
+}
+
+impl FromStr for BellStyle {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "audible" => Ok(BellStyle::Audible),
+            "visual" => Ok(BellStyle::Visual),
+            "both" => Ok(BellStyle::Both),
+            _ => anyhow::bail!("Unknown bell style {s}, expected audible, visual or both"),
+        }
+    }
+}
+
+/// Contacts known by the roster view and their grouping
+struct RosterContacts {
+    grouping: RosterGrouping,
+    contacts: HashMap<BareJid, contact::Contact>,
+    /// Date of the last message exchanged with each contact
+    activity: HashMap<BareJid, DateTime<FixedOffset>>,
+}
+
+type RosterView = ListView<UIEvent, Stdout, contact::Group, RosterItem>;
+
+impl RosterContacts {
+    fn groups(&self, contact: &contact::Contact) -> Vec<contact::Group> {
+        self.grouping
+            .groups(contact, self.activity.get(&contact.jid))
+    }
+
+    /// Insert or move a contact to the groups it belongs to
+    fn update(&mut self, view: &mut RosterView, contact: &contact::Contact) {
+        let groups = self.groups(contact);
+        if let Some(previous) = self.contacts.get(&contact.jid) {
+            for group in self.groups(previous) {
+                if !groups.contains(&group) {
+                    let _ = view.remove(RosterItem::Contact(previous.clone()), Some(group));
+                }
+            }
+        }
+
+        for group in groups {
+            view.insert(RosterItem::Contact(contact.clone()), Some(group));
+        }
+        self.contacts.insert(contact.jid.clone(), contact.clone());
+    }
+
+    fn touch(&mut self, view: &mut RosterView, jid: &BareJid, date: &DateTime<FixedOffset>) {
+        if matches!(self.activity.get(jid), Some(last) if last >= date) {
+            return;
+        }
+
+        let contact = self.contacts.get(jid).cloned();
+        if let Some(contact) = contact {
+            if self.grouping == RosterGrouping::Activity {
+                let previous = self.groups(&contact);
+                self.activity.insert(jid.clone(), *date);
+                for group in previous {
+                    let _ = view.remove(RosterItem::Contact(contact.clone()), Some(group));
+                }
+                for group in self.groups(&contact) {
+                    view.insert(RosterItem::Contact(contact.clone()), Some(group));
+                }
+                return;
+            }
+        }
+        self.activity.insert(jid.clone(), *date);
+    }
+
+    /// First group a contact is listed in
+    fn group_of(&self, jid: &BareJid) -> Option<contact::Group> {
+        let contact = self.contacts.get(jid)?;
+        self.groups(contact).into_iter().next()
+    }
+
+    /// Rebuild all contact groups
+    fn regroup(&mut self, view: &mut RosterView) {
+        view.retain(|item| !matches!(item, RosterItem::Contact(_)));
+        for contact in self.contacts.values() {
+            for group in self.groups(contact) {
+                view.insert(RosterItem::Contact(contact.clone()), Some(group));
+            }
+        }
+    }
+}
+
+/// Markup applied to the next outgoing message of a window
+#[derive(Debug, Clone)]
+pub enum PendingMarkup {
+    /// XEP-0393: Message Styling preformatted block
+    Code,
+    /// XEP-0382: Spoiler messages with an optional hint
+    Spoiler(Option<String>),
+}
+
 enum UIEvent {
     Core(Event),
+    /// Archived messages of an account, inserted at once in their windows
+    Messages(Option<Account>, Vec<Message>),
     Validate(Rc<RefCell<Option<(String, bool)>>>),
     GetInput(Rc<RefCell<Option<(String, Cursor, bool)>>>),
+    /// Change the strategy used to group contacts in the roster
+    RosterGrouping(RosterGrouping),
+    /// Groups listed in the roster
+    GetRosterGroups(Rc<RefCell<Vec<String>>>),
+    /// Roster group of a contact, or of open windows without contact
+    GetRosterGroupOf(Option<BareJid>, Rc<RefCell<Option<String>>>),
+    /// Hide or show contacts of a roster group
+    CollapseRosterGroup(String, bool),
+    /// Scroll offset, id and timestamp of the last displayed message of a conversation window
+    GetReadPosition(
+        BareJid,
+        Rc<RefCell<Option<(usize, Option<(String, DateTime<FixedOffset>)>)>>>,
+    ),
+    /// Messages received in a window while away, counted without being notified
+    Unread(String, u64),
+    /// Rows of the channels window and the selected one
+    ChannelRows(Vec<Vec<String>>, usize),
+    /// Scroll the current window up to the previous message containing this text
+    Search(String, Rc<RefCell<bool>>),
+    /// Scroll the current window to the first message since this date
+    Jump(DateTime<FixedOffset>, Rc<RefCell<bool>>),
+    /// Only show console messages at least as severe as this level
+    ConsoleLevel(log::Level),
+    /// Rows of destinations proposed by the quick switcher
+    QuickSwitch(Vec<Vec<String>>),
+    /// Rows of commands proposed by the command palette
+    CommandPalette(Vec<Vec<String>>),
+    /// Title and emojis proposed by the reaction picker
+    ReactionChoices(String, Vec<String>),
+    /// Move the reaction picker selection by columns and lines
+    MoveReaction(isize, isize),
+    /// Emoji selected in the reaction picker
+    GetReaction(Rc<RefCell<Option<String>>>),
+    /// Rows of the privacy overview and the selected one
+    PrivacyOverview(Vec<Vec<String>>, usize),
+    /// Rows of the disco browser and the selected one
+    DiscoTree(Vec<Vec<String>>, usize),
+    /// Key typed while a data form is displayed
+    FormKey(Key),
+    /// Form to submit, unset when not valid yet
+    SubmitForm(Rc<RefCell<Option<DataForm>>>),
+    /// Invert colors of the status line for a visual bell, or restore them
+    Flash(bool),
     AddWindow(String, Option<Box<dyn View<UIEvent, Stdout>>>),
 }
 
 struct TitleBar {
     name: Option<String>,
     subjects: HashMap<String, HashMap<String, String>>,
+    anonymity: HashMap<String, Anonymity>,
+    /// Chat state of contacts, by window
+    chat_states: HashMap<String, ChatState>,
     dirty: bool,
     pub color: ColorTuple,
 }
@@ -64,6 +608,8 @@ impl TitleBar {
         Self {
             name: None,
             subjects: HashMap::new(),
+            anonymity: HashMap::new(),
+            chat_states: HashMap::new(),
             dirty: true,
             color: color.clone(),
         }
@@ -83,6 +629,20 @@ impl TitleBar {
         }
         self.subjects.insert(jid, subjects);
     }
+
+    fn set_anonymity(&mut self, jid: String, anonymity: Anonymity) {
+        if Some(&jid) == self.name.as_ref() {
+            self.dirty = true;
+        }
+        self.anonymity.insert(jid, anonymity);
+    }
+
+    fn set_chat_state(&mut self, jid: String, state: ChatState) {
+        if Some(&jid) == self.name.as_ref() {
+            self.dirty = true;
+        }
+        self.chat_states.insert(jid, state);
+    }
 }
 
 impl<W> View<UIEvent, W> for TitleBar
@@ -114,8 +674,17 @@ where
         );
 
         if let Some(name) = &self.name {
+            let mut title = match self.anonymity.get(name) {
+                Some(Anonymity::NonAnonymous) | None => name.clone(),
+                Some(anonymity) => format!("{name} [{anonymity}]"),
+            };
+            match self.chat_states.get(name) {
+                Some(ChatState::Composing) => title.push_str(" (typing…)"),
+                Some(ChatState::Paused) => title.push_str(" (paused)"),
+                _ => {}
+            }
             let clean_name = terminus::term_string_visible_truncate(
-                name,
+                &title,
                 dimension.w.unwrap().into(),
                 Some("…"),
             );
@@ -127,7 +696,9 @@ where
             if remaining > 0 {
                 let subjects = self.subjects.get(name).unwrap();
                 if !subjects.is_empty() {
-                    if let Some((_lang, subject)) = i18n::get_best(subjects, vec![]) {
+                    if let Some((_lang, subject)) =
+                        i18n::get_best(subjects, i18n::preferred_langs())
+                    {
                         let clean_subject = terminus::term_string_visible_truncate(
                             subject,
                             remaining.into(),
@@ -170,6 +741,14 @@ where
                         .collect(),
                 );
             }
+            UIEvent::Core(Event::ChannelAnonymity {
+                channel, anonymity, ..
+            }) => {
+                self.set_anonymity(channel.to_string(), *anonymity);
+            }
+            UIEvent::Core(Event::ContactChatState { contact, state, .. }) => {
+                self.set_chat_state(contact.to_string(), state.clone());
+            }
             _ => {}
         }
     }
@@ -182,22 +761,111 @@ where
     }
 }
 
+/// Status line format used when none is configured
+const DEFAULT_STATUS_FORMAT: &str = " {account} {privacy}{unread}";
+
+#[derive(Eq, PartialEq, Ord, PartialOrd, Clone, Copy)]
+enum UnreadKind {
+    Private,
+    Mention,
+    Activity,
+}
+
+impl UnreadKind {
+    /// Prefix of the status line section listing windows of this kind
+    fn label(&self) -> &'static str {
+        match self {
+            UnreadKind::Private => "msg: ",
+            UnreadKind::Mention => "@: ",
+            UnreadKind::Activity => "",
+        }
+    }
+}
+
+/// Unread messages of a window
+#[derive(Default)]
+struct Unread {
+    total: u64,
+    /// Messages mentioning us or in a VIP conversation
+    important: u64,
+    /// Window of a one to one conversation
+    private: bool,
+}
+
+impl Unread {
+    fn kind(&self) -> UnreadKind {
+        if self.private {
+            UnreadKind::Private
+        } else if self.important > 0 {
+            UnreadKind::Mention
+        } else {
+            UnreadKind::Activity
+        }
+    }
+
+    fn render(&self, window: &str) -> String {
+        match self.kind() {
+            UnreadKind::Private => format!(
+                "{}{}{} ({})",
+                termion::style::Bold,
+                window,
+                termion::style::NoBold,
+                self.total,
+            ),
+            UnreadKind::Mention => format!(
+                "{}{}{} ({}{}{}, {})",
+                termion::style::Bold,
+                window,
+                termion::style::NoBold,
+                termion::style::Bold,
+                self.important,
+                termion::style::NoBold,
+                self.total,
+            ),
+            UnreadKind::Activity => format!(
+                "{}{} ({}){}",
+                termion::style::Faint,
+                window,
+                self.total,
+                termion::style::NoFaint,
+            ),
+        }
+    }
+}
+
 struct WinBar {
     connection: Option<String>,
+    presence: Option<String>,
+    lag: Option<Duration>,
     windows: Vec<String>,
     current_window: Option<String>,
-    highlighted: HashMap<String, (u64, u64)>,
+    highlighted: HashMap<String, Unread>,
+    /// Notifications not sent by default
+    withheld: Vec<Notification>,
+    /// Notifications not sent in each window, when different from the default
+    withheld_windows: HashMap<String, Vec<Notification>>,
+    format: String,
+    clock: bool,
+    /// Colors are inverted by a visual bell
+    flashing: bool,
     dirty: bool,
     pub color: ColorTuple,
 }
 
 impl WinBar {
-    pub fn new(color: &ColorTuple) -> Self {
+    pub fn new(color: &ColorTuple, format: Option<&str>, clock: bool) -> Self {
         Self {
             connection: None,
+            presence: None,
+            lag: None,
             windows: Vec::new(),
             current_window: None,
             highlighted: HashMap::new(),
+            withheld: Vec::new(),
+            withheld_windows: HashMap::new(),
+            format: format.unwrap_or(DEFAULT_STATUS_FORMAT).to_string(),
+            clock,
+            flashing: false,
             dirty: true,
             color: color.clone(),
         }
@@ -219,16 +887,182 @@ impl WinBar {
         self.dirty = self.highlighted.remove(window).is_some();
     }
 
-    pub fn highlight_window(&mut self, window: &str, important: bool) {
+    /// Count messages received in a channel before being seen live, such as archived ones
+    pub fn add_unread(&mut self, window: &str, count: u64) {
+        if self.current_window.as_deref() != Some(window) {
+            self.highlighted
+                .entry(window.to_string())
+                .or_default()
+                .total += count;
+            self.dirty = true;
+        }
+    }
+
+    pub fn highlight_window(&mut self, window: &str, private: bool, important: bool) {
         if self.current_window.as_deref() != Some(window) {
-            let state = self.highlighted.entry(window.to_string()).or_insert((0, 0));
-            state.0 += 1;
+            let unread = self.highlighted.entry(window.to_string()).or_default();
+            unread.total += 1;
+            unread.private |= private;
             if important {
-                state.1 += 1;
+                unread.important += 1;
             }
             self.dirty = true;
         }
     }
+
+    /// Expand every placeholder of the format except `{unread}` which depends on the space left
+    fn expand(&self, name: &str) -> Option<String> {
+        match name {
+            "account" => Some(self.connection.clone().unwrap_or_default()),
+            "presence" => Some(self.presence.clone().unwrap_or_default()),
+            "clock" => Some(Local::now().format("%H:%M").to_string()),
+            "lag" => Some(
+                self.lag
+                    .map(|lag| format!("{}ms", lag.as_millis()))
+                    .unwrap_or_default(),
+            ),
+            "privacy" => Some(self.render_privacy()),
+            _ => None,
+        }
+    }
+
+    /// Notifications withheld in the current window, followed by a space
+    fn render_privacy(&self) -> String {
+        let withheld = self
+            .current_window
+            .as_ref()
+            .and_then(|window| self.withheld_windows.get(window))
+            .unwrap_or(&self.withheld);
+        match withheld.is_empty() {
+            true => String::new(),
+            false => format!(
+                "[no {}] ",
+                withheld
+                    .iter()
+                    .map(|notification| notification.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
+
+    /// Render the unread summary with at most `max` visible chars, private messages first,
+    /// then windows mentioning us, then other windows with activity
+    fn render_unread(&self, max: usize) -> String {
+        let mut output = String::new();
+        let mut written = 0;
+        let mut section = None;
+        let mut remaining = self.highlighted.len();
+
+        let mut sorted = self.highlighted.iter().collect::<Vec<_>>();
+        sorted.sort_by(|(a_window, a), (b_window, b)| {
+            a.kind()
+                .cmp(&b.kind())
+                .then(b.important.cmp(&a.important))
+                .then(b.total.cmp(&a.total))
+                .then(a_window.cmp(b_window))
+        });
+
+        for (window, unread) in sorted {
+            let kind = unread.kind();
+            let separator = match section {
+                Some(section) if section == kind => String::from(", "),
+                Some(_) => format!("] [{}", kind.label()),
+                None => format!("[{}", kind.label()),
+            };
+            let entry = unread.render(window);
+            let len = terminus::term_string_visible_len(&separator)
+                + terminus::term_string_visible_len(&entry);
+
+            // Keep space for the closing bracket and at least ", +X"
+            let reserved = match remaining {
+                1 => 1,
+                _ => format!(", +{remaining}").len() + 1,
+            };
+
+            if written + len + reserved > max {
+                if section.is_some() {
+                    output.push_str(&format!(", +{remaining}"));
+                }
+                break;
+            }
+
+            output.push_str(&separator);
+            output.push_str(&entry);
+            written += len;
+            section = Some(kind);
+            remaining -= 1;
+        }
+
+        if section.is_some() {
+            output.push(']');
+        }
+
+        output
+    }
+
+    /// Render the right aligned clock and presence widget
+    fn render_widget(&self) -> String {
+        match self.clock {
+            true => format!(
+                " {} {} ",
+                self.presence.as_deref().unwrap_or("offline"),
+                Local::now().format("%H:%M")
+            ),
+            false => String::new(),
+        }
+    }
+
+    /// Evaluate the status format for the given width
+    fn render_status(&self, width: usize) -> String {
+        let fixed = format_status(&self.format, |name| match name {
+            "unread" => Some(String::new()),
+            name => self.expand(name),
+        });
+        let available = width.saturating_sub(terminus::term_string_visible_len(&fixed));
+
+        let status = format_status(&self.format, |name| match name {
+            "unread" => Some(self.render_unread(available)),
+            name => self.expand(name),
+        });
+
+        if terminus::term_string_visible_len(&status) > width {
+            terminus::term_string_visible_truncate(&status, width, Some("…"))
+        } else {
+            status
+        }
+    }
+}
+
+/// Replace each `{name}` placeholder of `format` with the value given by `expand`.
+/// Unknown placeholders are kept verbatim.
+fn format_status<F>(format: &str, expand: F) -> String
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut output = String::new();
+    let mut rest = format;
+
+    while let Some(start) = rest.find('{') {
+        output.push_str(&rest[..start]);
+        match rest[start..].find('}') {
+            Some(end) => {
+                let placeholder = &rest[start..start + end + 1];
+                match expand(&placeholder[1..placeholder.len() - 1]) {
+                    Some(value) => output.push_str(&value),
+                    None => output.push_str(placeholder),
+                }
+                rest = &rest[start + end + 1..];
+            }
+            None => {
+                output.push_str(&rest[start..]);
+                rest = "";
+            }
+        }
+    }
+    output.push_str(rest);
+
+    output
 }
 
 impl<W> View<UIEvent, W> for WinBar
@@ -238,14 +1072,15 @@ where
     fn render(&mut self, dimension: &Dimension, screen: &mut Screen<W>) {
         save_cursor!(screen);
 
-        let mut written = 0;
-
         vprint!(
             screen,
             "{}",
             termion::cursor::Goto(dimension.x, dimension.y)
         );
         vprint!(screen, "{}{}", self.color.bg, self.color.fg,);
+        if self.flashing {
+            vprint!(screen, "{}", termion::style::Invert);
+        }
 
         for _ in 0..dimension.w.unwrap() {
             vprint!(screen, " ");
@@ -256,73 +1091,27 @@ where
             "{}",
             termion::cursor::Goto(dimension.x, dimension.y)
         );
-        if let Some(connection) = &self.connection {
-            vprint!(screen, " {}", connection);
-            written += 1 + connection.len();
-        }
 
-        let mut first = true;
-        let mut remaining = self.highlighted.len();
-
-        let mut sorted = self.highlighted.iter().collect::<Vec<_>>();
-        sorted.sort_by(|(_, (_, a)), (_, (_, b))| b.partial_cmp(a).unwrap());
-
-        for (window, state) in sorted {
-            // Keep space for at least ", +X]"
-            let remaining_len = if remaining > 1 {
-                format!("{remaining}").len() + 4
-            } else {
-                0
-            };
-
-            if window.len() + written + remaining_len > dimension.w.unwrap() as usize {
-                if !first {
-                    vprint!(screen, ", +{}", remaining);
-                }
-                break;
-            }
+        let width = dimension.w.unwrap() as usize;
+        let widget = self.render_widget();
+        let widget_len = terminus::term_string_visible_len(&widget);
 
-            if first {
-                vprint!(screen, " [");
-                written += 3; // Also count the closing bracket
-                first = false;
-            } else {
-                vprint!(screen, ", ");
-                written += 2;
-            }
-
-            if state.1 > 0 {
-                vprint!(
-                    screen,
-                    "{}{}{} ({}{}{}, {})",
-                    termion::style::Bold,
-                    window,
-                    termion::style::NoBold,
-                    termion::style::Bold,
-                    state.1,
-                    termion::style::NoBold,
-                    state.0,
-                );
-                written += window.len();
-                written += 5; // " (" + ", " + ")"
-                written += state.0.to_string().len();
-                written += state.1.to_string().len();
-            } else {
-                vprint!(screen, "{} ({})", window, state.0);
-                written += window.len();
-                written += 3; // " (" + ")"
-                written += state.0.to_string().len();
-            }
-            remaining -= 1;
-        }
+        let status = self.render_status(width.saturating_sub(widget_len));
+        vprint!(screen, "{}", status);
 
-        if !first {
-            vprint!(screen, "]");
+        if widget_len > 0 && widget_len <= width {
+            vprint!(
+                screen,
+                "{}{}",
+                termion::cursor::Goto(dimension.x + (width - widget_len) as u16, dimension.y),
+                widget
+            );
         }
 
         vprint!(
             screen,
-            "{}{}",
+            "{}{}{}",
+            termion::style::NoInvert,
             color::Bg(color::Reset),
             color::Fg(color::Reset)
         );
@@ -340,21 +1129,69 @@ where
             UIEvent::Core(Event::ChangeWindow(name)) => {
                 self.set_current_window(&terminus::clean(name));
             }
+            UIEvent::Flash(flashing) => {
+                self.flashing = *flashing;
+                self.dirty = true;
+            }
             UIEvent::AddWindow(name, _) => {
                 self.add_window(terminus::clean(name));
             }
             UIEvent::Core(Event::Close(window)) => {
                 self.del_window(window);
             }
+            UIEvent::Core(Event::Connecting(account, _)) => {
+                let account = terminus::clean(&account.to_string());
+                if self.presence.as_deref() != Some("online")
+                    || self.connection.as_deref() == Some(&account)
+                {
+                    self.connection = Some(account);
+                    self.presence = Some(String::from("connecting…"));
+                    self.dirty = true;
+                }
+            }
             UIEvent::Core(Event::Connected(account, _)) => {
                 self.connection = Some(terminus::clean(&account.to_string()));
+                self.presence = Some(String::from("online"));
                 self.dirty = true;
             }
+            UIEvent::Core(Event::Disconnected(account, _)) => {
+                if self.connection.as_deref() == Some(&terminus::clean(&account.to_string())) {
+                    self.presence = Some(String::from("offline"));
+                    self.lag = None;
+                    self.dirty = true;
+                }
+            }
+            UIEvent::Core(Event::Lag(account, lag)) => {
+                if self.connection.as_deref() == Some(&terminus::clean(&account.to_string())) {
+                    self.lag = Some(*lag);
+                    self.dirty = true;
+                }
+            }
             UIEvent::Core(Event::Notification {
                 conversation,
                 important,
             }) => {
-                self.highlight_window(&conversation.get_jid().to_string(), *important);
+                let private = matches!(conversation, Conversation::Chat(_));
+                self.highlight_window(&conversation.get_jid().to_string(), private, *important);
+            }
+            UIEvent::Unread(window, count) => {
+                self.add_unread(window, *count);
+            }
+            UIEvent::Core(Event::Tick) => {
+                self.dirty |= self.clock || self.format.contains("{clock}");
+            }
+            UIEvent::Core(Event::Privacy {
+                conversation,
+                withheld,
+            }) => {
+                match conversation {
+                    Some(conversation) => {
+                        self.withheld_windows
+                            .insert(conversation.to_string(), withheld.clone());
+                    }
+                    None => self.withheld = withheld.clone(),
+                }
+                self.dirty = true;
             }
             _ => {}
         }
@@ -368,9 +1205,19 @@ where
     }
 }
 
+/// Message displayed with the body limits of its window
+struct LimitedMessage<'a>(&'a Message, BodyLimits);
+
 impl fmt::Display for Message {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
+        LimitedMessage(self, BodyLimits::default()).fmt(f)
+    }
+}
+
+impl fmt::Display for LimitedMessage<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let LimitedMessage(message, limits) = self;
+        match message {
             Message::Log(message) => {
                 let timestamp = Local.from_utc_datetime(&message.timestamp.naive_local());
                 for line in message.body.lines() {
@@ -380,7 +1227,7 @@ impl fmt::Display for Message {
                         color::Bg(color::Reset),
                         color::Fg(color::Reset),
                         timestamp.format("%T"),
-                        line
+                        terminus::clean(line)
                     )?;
                 }
 
@@ -397,7 +1244,27 @@ impl fmt::Display for Message {
 
                 let timestamp =
                     Local.from_utc_datetime(&message.get_original_timestamp().naive_local());
-                let body = message.get_last_body();
+                let body = match &message.spoiler {
+                    _ if message.retracted && !message.expanded => {
+                        format!("{}\n… show original (Alt+e)", removal_notice(message))
+                    }
+                    _ if message.retracted => {
+                        format!("{} {}", removal_notice(message), message.get_last_body())
+                    }
+                    Some(spoiler) if !spoiler.revealed && spoiler.hint.is_empty() => {
+                        String::from("[spoiler]")
+                    }
+                    Some(spoiler) if !spoiler.revealed => format!("[spoiler: {}]", spoiler.hint),
+                    _ => {
+                        let body = message.get_last_body();
+                        match limits.cutoff(body) {
+                            Some(cutoff) if !message.expanded => {
+                                format!("{}\n… show more (Alt+e)", &body[..cutoff])
+                            }
+                            _ => body.to_string(),
+                        }
+                    }
+                };
                 let me = body.starts_with("/me");
                 let padding_len = match me {
                     true => format!("{} - {}: ", timestamp.format("%T"), author).len(),
@@ -407,7 +1274,31 @@ impl fmt::Display for Message {
 
                 let (r, g, b) = id_to_rgb(&author);
 
-                let mut attributes = "".to_string();
+                if let Some(reply) = &message.reply {
+                    let quoted = match (&reply.to, &message.type_) {
+                        (Some(Jid::Full(to)), XmppMessageType::Channel) => {
+                            format!("{}: ", to.resource())
+                        }
+                        (Some(to), _) => format!("{}: ", to.to_bare()),
+                        (None, _) => String::new(),
+                    };
+                    let excerpt = match &reply.quote {
+                        Some(quote) => word::excerpt(quote, 80),
+                        None => String::from("unknown message"),
+                    };
+                    writeln!(
+                        f,
+                        "{}{}{}↱ {}{}{}",
+                        color::Bg(color::Reset),
+                        color::Fg(color::LightBlack),
+                        padding,
+                        terminus::clean(&quoted),
+                        terminus::clean(&excerpt),
+                        color::Fg(color::Reset)
+                    )?;
+                }
+
+                let mut attributes = "".to_string();
                 if message.has_multiple_version() {
                     attributes.push_str("✎ ");
                 }
@@ -442,12 +1333,68 @@ impl fmt::Display for Message {
                     false => body.lines(),
                 };
 
+                // Messages not sent yet are dimmed
+                let pending = message.delivery == Some(Delivery::Pending);
+                if pending {
+                    write!(f, "{}", color::Fg(color::LightBlack))?;
+                }
                 if let Some(line) = iter.next() {
                     write!(f, "{}", terminus::clean(line))?;
                 }
                 for line in iter {
                     write!(f, "\n{}{}", padding, terminus::clean(line))?;
                 }
+                if pending {
+                    write!(f, "{}", color::Fg(color::Reset))?;
+                }
+
+                let hidden = matches!(&message.spoiler, Some(spoiler) if !spoiler.revealed)
+                    || (message.retracted && !message.expanded);
+                if !hidden {
+                    for attachment in message.attachments() {
+                        let name = word::url_file_name(&attachment).unwrap_or(&attachment);
+                        let encrypted = match attachment.starts_with(AESGCM_SCHEME) {
+                            true => " (encrypted)",
+                            false => "",
+                        };
+                        write!(
+                            f,
+                            "\n{}{}⤓ {}{}{}",
+                            padding,
+                            color::Fg(color::Cyan),
+                            terminus::clean(name),
+                            encrypted,
+                            color::Fg(color::Reset)
+                        )?;
+                    }
+                }
+
+                let reactions = message.reaction_counts();
+                if !reactions.is_empty() && !message.retracted {
+                    let reactions = reactions
+                        .iter()
+                        .map(|(emoji, count)| format!("{} {}", terminus::clean(emoji), count))
+                        .collect::<Vec<_>>()
+                        .join("  ");
+                    write!(
+                        f,
+                        "\n{}{}{}{}",
+                        padding,
+                        color::Fg(color::LightBlack),
+                        reactions,
+                        color::Fg(color::Reset)
+                    )?;
+                }
+
+                if message.read_marker {
+                    write!(
+                        f,
+                        "\n{}{}── read up to here ──{}",
+                        padding,
+                        color::Fg(color::LightBlack),
+                        color::Fg(color::Reset)
+                    )?;
+                }
 
                 Ok(())
             }
@@ -499,10 +1446,86 @@ impl PartialEq for RosterItem {
 
 impl Eq for RosterItem {}
 
+/// Two chars block of initials colored from the given identifier, name must already be cleaned
+/// for its initials to be displayable
+fn avatar_block(identifier: &str, name: &str) -> String {
+    let words = name
+        .split_whitespace()
+        .filter_map(|word| word.graphemes(true).next())
+        .collect::<Vec<_>>();
+    let initials = match words.len() {
+        0 => String::from("  "),
+        1 => {
+            let mut initials = name.trim().graphemes(true).take(2).collect::<String>();
+            if initials.graphemes(true).count() < 2 {
+                initials.push(' ');
+            }
+            initials
+        }
+        _ => format!("{}{}", words[0], words[1]),
+    };
+
+    let (r, g, b) = id_to_rgb(identifier);
+    format!(
+        "{}{}{}{}{} ",
+        color::Bg(color::Rgb(r, g, b)),
+        color::Fg(color::Black),
+        initials.to_uppercase(),
+        color::Bg(color::Reset),
+        color::Fg(color::Reset)
+    )
+}
+
+/// Compact glyph of the presence subscription with a contact, arrows show where presence goes
+fn subscription_glyph(contact: &contact::Contact) -> &'static str {
+    match contact.subscription {
+        Subscription::Both => "⇄",
+        Subscription::To => "←",
+        _ if contact.pending => "…",
+        Subscription::From => "→",
+        Subscription::None | Subscription::Remove => "·",
+    }
+}
+
+impl RosterItem {
+    /// Initials block displayed before the item when `roster_avatars` is enabled
+    fn avatar(&self) -> Option<String> {
+        match self {
+            Self::Contact(contact) => {
+                let jid = contact.jid.to_string();
+                let name = match contact.display_name() {
+                    Some(name) => terminus::clean(name),
+                    None => contact
+                        .jid
+                        .node()
+                        .map(|node| terminus::clean(&node.to_string()))
+                        .unwrap_or_else(|| terminus::clean(&jid)),
+                };
+                // Color changes along with the avatar of the contact
+                let identifier = contact.avatar.as_deref().unwrap_or(&jid);
+                Some(avatar_block(identifier, &name))
+            }
+            Self::Bookmark(bookmark) => {
+                let jid = bookmark.jid.to_string();
+                let name = terminus::clean(bookmark.name.as_deref().unwrap_or(&jid));
+                Some(avatar_block(&jid, &name))
+            }
+            Self::Window(_) => None,
+        }
+    }
+}
+
 impl fmt::Display for RosterItem {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match &self {
             Self::Contact(contact) => {
+                write!(
+                    f,
+                    "{}{} ",
+                    color::Fg(color::LightBlack),
+                    subscription_glyph(contact)
+                )?;
+
                 match contact.presence {
                     contact::Presence::Available | contact::Presence::Chat => {
                         write!(f, "{}", color::Fg(color::Green))?
@@ -513,7 +1536,7 @@ impl fmt::Display for RosterItem {
                     | contact::Presence::Unavailable => write!(f, "{}", color::Fg(color::Reset))?,
                 };
 
-                let disp = match &contact.name {
+                let disp = match contact.display_name() {
                     Some(name) => format!(
                         "{} ({})",
                         terminus::clean(name),
@@ -522,7 +1545,26 @@ impl fmt::Display for RosterItem {
                     None => terminus::clean(&contact.jid.to_string()),
                 };
 
-                write!(f, "{}{}", disp, color::Fg(color::Reset))
+                write!(f, "{}{}", disp, color::Fg(color::Reset))?;
+                if let Some(mood) = &contact.mood {
+                    write!(
+                        f,
+                        " {}[{}]{}",
+                        color::Fg(color::LightBlack),
+                        terminus::clean(&mood.mood),
+                        color::Fg(color::Reset)
+                    )?;
+                }
+                if let Some(idle) = &contact.idle {
+                    write!(
+                        f,
+                        " {}idle {}{}",
+                        color::Fg(color::LightBlack),
+                        contact::format_idle(idle),
+                        color::Fg(color::Reset)
+                    )?;
+                }
+                Ok(())
             }
 
             Self::Bookmark(bookmark) => {
@@ -545,15 +1587,28 @@ impl fmt::Display for RosterItem {
 impl fmt::Display for conversation::Occupant {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let (r, g, b) = id_to_rgb(&self.nick);
-        let nick = self.nick.clone();
+        let nick = terminus::clean(&self.nick);
 
         write!(
             f,
             "{}{}{}",
             color::Fg(color::Rgb(r, g, b)),
-            terminus::clean(&nick),
+            nick,
             color::Fg(color::Reset)
-        )
+        )?;
+
+        // Only known when the channel isn't anonymous to us
+        if let Some(jid) = &self.jid {
+            write!(
+                f,
+                " {}{}{}",
+                color::Fg(color::LightBlack),
+                terminus::clean(&jid.to_string()),
+                color::Fg(color::Reset)
+            )?;
+        }
+
+        Ok(())
     }
 }
 
@@ -654,18 +1709,393 @@ impl Drop for PanicHandler {
     }
 }
 
+/// Truncation of bodies too long to be displayed inline until they are expanded, 0 meaning no
+/// limit
+#[derive(Clone, Copy)]
+struct BodyLimits {
+    max_lines: usize,
+    max_chars: usize,
+}
+
+impl Default for BodyLimits {
+    fn default() -> Self {
+        Self {
+            max_lines: BODY_MAX_LINES,
+            max_chars: BODY_MAX_CHARS,
+        }
+    }
+}
+
+impl BodyLimits {
+    /// Index at which a body too long to be displayed inline should be truncated
+    fn cutoff(&self, body: &str) -> Option<usize> {
+        let by_lines = match self.max_lines {
+            0 => None,
+            max_lines => body
+                .match_indices('\n')
+                .nth(max_lines - 1)
+                .map(|(index, _)| index),
+        };
+        let by_chars = match self.max_chars {
+            0 => None,
+            max_chars => body.char_indices().nth(max_chars).map(|(index, _)| index),
+        };
+
+        match (by_lines, by_chars) {
+            (Some(by_lines), Some(by_chars)) => Some(cmp::min(by_lines, by_chars)),
+            (by_lines, by_chars) => by_lines.or(by_chars),
+        }
+    }
+}
+
+/// Stored scroll position of a conversation as view offset, last read message id and its
+/// timestamp
+fn stored_read_position(
+    aparte: &Aparte,
+    conversation: &Conversation,
+) -> Option<(usize, String, Option<DateTime<FixedOffset>>)> {
+    match aparte
+        .storage
+        .get_read_position(conversation.get_account(), conversation.get_jid())
+    {
+        Ok(position) => position.and_then(|position| {
+            let read_at = position
+                .read_at
+                .and_then(|read_at| DateTime::parse_from_rfc3339(&read_at).ok());
+            position
+                .last_read
+                .map(|last_read| (position.view as usize, last_read, read_at))
+        }),
+        Err(err) => {
+            log::warn!("Cannot load read position: {err}");
+            None
+        }
+    }
+}
+
+/// Read position of a window waiting to be written to storage
+struct UnsavedReadPosition {
+    conversation: Conversation,
+    view: usize,
+    last_read: Option<String>,
+    read_at: Option<DateTime<FixedOffset>>,
+}
+
+/// Current scroll position of a conversation window
+fn read_position_of(
+    view: &BufferedWin<UIEvent, Stdout, Message>,
+) -> (usize, Option<(String, DateTime<FixedOffset>)>) {
+    let last_read = view
+        .history
+        .iter()
+        .next_back()
+        .map(|message| (message.id().to_string(), *message.timestamp()));
+    (view.view, last_read)
+}
+
+/// Expand the truncated message with the given id, or the most recent one displayed in the
+/// given window
+fn expand_message(
+    view: &mut BufferedWin<UIEvent, Stdout, Message>,
+    limits: &BodyLimits,
+    id: Option<&str>,
+) {
+    let expanded = view.history.iter().rev().find_map(|message| match message {
+        Message::Xmpp(message)
+            if !message.expanded
+                && (id.is_none() || id == Some(message.id.as_str()))
+                && (message.retracted || limits.cutoff(message.get_last_body()).is_some()) =>
+        {
+            let mut message = message.clone();
+            message.expanded = true;
+            Some(Message::Xmpp(message))
+        }
+        _ => None,
+    });
+
+    if let Some(message) = expanded {
+        view.history.replace(message);
+        view.dirty = true;
+    }
+}
+
+/// Move the read marker of the contact to the message with the given id
+fn move_read_marker(view: &mut BufferedWin<UIEvent, Stdout, Message>, id: &str) {
+    let moved = view
+        .history
+        .iter()
+        .filter_map(|message| match message {
+            Message::Xmpp(message) if message.read_marker != (message.id == id) => {
+                let mut message = message.clone();
+                message.read_marker = !message.read_marker;
+                Some(Message::Xmpp(message))
+            }
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    // Marker received before the message got displayed
+    if !moved.iter().any(|message| message.id() == id) {
+        return;
+    }
+
+    for message in moved {
+        view.history.replace(message);
+    }
+    view.dirty = true;
+}
+
+/// Upgrade the delivery state of a displayed outgoing message
+fn update_delivery(view: &mut BufferedWin<UIEvent, Stdout, Message>, id: &str, delivery: Delivery) {
+    let updated = view.history.iter().find_map(|message| match message {
+        Message::Xmpp(message) if message.id == id && message.delivery < Some(delivery) => {
+            let mut message = message.clone();
+            message.delivery = Some(delivery);
+            Some(Message::Xmpp(message))
+        }
+        _ => None,
+    });
+
+    if let Some(message) = updated {
+        view.history.replace(message);
+        view.dirty = true;
+    }
+}
+
+/// Whether the displayed body of a message contains the text, case insensitively
+fn message_contains(message: &Message, text: &str) -> bool {
+    if let Message::Xmpp(message) = message {
+        if matches!(&message.spoiler, Some(spoiler) if !spoiler.revealed) {
+            return false;
+        }
+    }
+    message.body().to_lowercase().contains(&text.to_lowercase())
+}
+
+/// Parse a date given as `YYYY-MM-DD`, `YYYY-MM-DD HH:MM` or `HH:MM` for today, in local time
+fn parse_date(date: &str) -> anyhow::Result<DateTime<FixedOffset>> {
+    let naive = NaiveDateTime::parse_from_str(date, "%Y-%m-%d %H:%M")
+        .or_else(|_| {
+            NaiveDate::parse_from_str(date, "%Y-%m-%d").map(|date| date.and_time(NaiveTime::MIN))
+        })
+        .or_else(|_| {
+            NaiveTime::parse_from_str(date, "%H:%M")
+                .map(|time| LocalTz::now().date_naive().and_time(time))
+        })
+        .map_err(|_| {
+            anyhow::anyhow!("Invalid date {date}, expected YYYY-MM-DD [HH:MM] or HH:MM")
+        })?;
+    let date = Local
+        .from_local_datetime(&naive)
+        .earliest()
+        .ok_or_else(|| anyhow::anyhow!("Invalid local date {date}"))?;
+    Ok(date.into())
+}
+
+/// Displayed in place of a retracted message, telling who removed it from a channel
+fn removal_notice(message: &VersionedXmppMessage) -> String {
+    match &message.moderation {
+        None => String::from("[message removed]"),
+        Some(moderation) => {
+            let by = match &moderation.by {
+                Some(by) => format!(" by {by}"),
+                None => String::from(" by a moderator"),
+            };
+            let reason = match &moderation.reason {
+                Some(reason) => format!(": {reason}"),
+                None => String::new(),
+            };
+            format!("[message removed{by}{reason}]")
+        }
+    }
+}
+
+/// Reveal the hidden spoiler of the message with the given id, or all of them displayed in the
+/// given window
+fn reveal_spoilers(view: &mut BufferedWin<UIEvent, Stdout, Message>, id: Option<&str>) {
+    let revealed = view
+        .history
+        .iter()
+        .filter_map(|message| match message {
+            Message::Xmpp(message) if id.is_none() || id == Some(message.id.as_str()) => {
+                let mut message = message.clone();
+                match message.reveal_spoiler() {
+                    true => Some(Message::Xmpp(message)),
+                    false => None,
+                }
+            }
+            Message::Xmpp(_) | Message::Log(_) => None,
+        })
+        .collect::<Vec<_>>();
+
+    if !revealed.is_empty() {
+        for message in revealed {
+            view.history.replace(message);
+        }
+        view.dirty = true;
+    }
+}
+
+/// Destination proposed by the quick switcher
+#[derive(Clone)]
+enum SwitchTarget {
+    Window(String),
+    Contact(Account, BareJid),
+    Bookmark(contact::Bookmark),
+}
+
+/// Setting listed in the privacy overview, with the command reverting it
+pub struct PrivacyEntry {
+    pub kind: String,
+    pub jid: String,
+    pub setting: String,
+    pub undo: String,
+}
+
+/// Entity or feature listed in the disco browser
+struct DiscoEntry {
+    account: Account,
+    jid: Jid,
+    node: Option<String>,
+    name: String,
+    /// Identities of an entity, once explored
+    kind: String,
+    depth: usize,
+    expanded: bool,
+    /// Features are leaves of the entity above them
+    feature: bool,
+}
+
+struct SwitchCandidate {
+    name: String,
+    jid: String,
+    kind: String,
+    target: SwitchTarget,
+}
+
+/// Quick switcher state while it is open
+struct QuickSwitch {
+    /// Window displayed before opening the switcher
+    previous: Option<String>,
+    /// Input typed before opening the switcher, restored when closing it
+    stash: (String, Cursor),
+    candidates: Vec<SwitchCandidate>,
+    /// Indexes of candidates matching the typed query, best first
+    matches: Vec<usize>,
+    selected: usize,
+}
+
+/// Command proposed by the command palette
+struct PaletteCommand {
+    name: String,
+    /// First line of the help
+    usage: String,
+    /// First line of the description of the help
+    description: String,
+}
+
+impl PaletteCommand {
+    fn new(name: &str, help: &str) -> Self {
+        let usage = help.lines().next().unwrap_or_default().trim().to_string();
+        let description = help
+            .lines()
+            .skip_while(|line| line.trim() != "Description:")
+            .nth(1)
+            .map(|line| line.trim().to_string())
+            .unwrap_or_default();
+        Self {
+            name: name.to_string(),
+            usage,
+            description,
+        }
+    }
+
+    /// Whether arguments must be typed before running the command
+    fn needs_arguments(&self) -> bool {
+        let arguments = match self.usage.split_once(' ') {
+            Some((_, arguments)) => arguments.trim(),
+            None => "",
+        };
+        !arguments.is_empty() && !arguments.starts_with('[')
+    }
+}
+
+/// Command palette state while it is open
+struct CommandPalette {
+    /// Window displayed before opening the palette
+    previous: Option<String>,
+    /// Input typed before opening the palette, restored when closing it
+    stash: (String, Cursor),
+    commands: Vec<PaletteCommand>,
+    /// Indexes of commands matching the typed query, best first
+    matches: Vec<usize>,
+    selected: usize,
+}
+
+/// Reaction picker state while it is open
+struct ReactionPicker {
+    /// Window displayed before opening the picker
+    previous: Option<String>,
+    /// Input typed before opening the picker, restored when closing it
+    stash: (String, Cursor),
+    account: Account,
+    message: VersionedXmppMessage,
+}
+
 pub struct UIMod {
     screen: Screen<Stdout>,
     windows: Vec<String>,
     current_window: Option<String>,
     unread_windows: HashMap<String, u64>,
+    /// Timestamp of the last message read in channel windows, from previous sessions too
+    read_markers: HashMap<String, DateTime<FixedOffset>>,
+    /// Read positions of windows left since the last write to storage
+    unsaved_read_positions: HashMap<String, UnsavedReadPosition>,
+    /// Ids of messages counted as unread in channel windows, to count each of them once
+    unread_ids: HashMap<String, HashSet<String>>,
     conversations: HashMap<String, Conversation>,
     root: LinearLayout<UIEvent, Stdout>,
     last_render: Instant,
     debounced: u32,
+    /// Configured debounce delay, actual delay adapts between a quarter and four times this value
+    base_debounce: Duration,
+    /// Current debounce delay
+    debounce: Duration,
+    /// Terminal has been resized since last render
+    resized: bool,
+    /// End of the current visual bell
+    flash_until: Option<Instant>,
+    bell_style: BellStyle,
+    roster_grouping: RosterGrouping,
+    /// Display initials blocks in roster and occupant lists
+    roster_avatars: bool,
+    body_limits: BodyLimits,
+    /// Roster groups whose contacts are hidden, kept in storage
+    collapsed_groups: HashSet<String>,
     dimension: Option<Dimension>,
     password_command: Option<Command>,
+    pending_markups: HashMap<String, PendingMarkup>,
+    /// Message the next outgoing message of a window replies to, picked with Alt+r
+    pending_replies: HashMap<String, Reply>,
     outgoing_event_queue: Rc<RefCell<Vec<Event>>>,
+    quick_switch: Option<QuickSwitch>,
+    reaction_picker: Option<ReactionPicker>,
+    command_palette: Option<CommandPalette>,
+    privacy: Vec<PrivacyEntry>,
+    privacy_selected: usize,
+    disco: Vec<DiscoEntry>,
+    disco_selected: usize,
+    /// Last listed channels and the account they were listed with
+    channels: Option<(Account, Vec<ChannelListing>)>,
+    /// Only show listed channels containing this text (lowercase)
+    channels_filter: Option<String>,
+    channels_selected: usize,
+    /// Id of the data form being filled
+    form: Option<String>,
+    /// Archived messages received since the last history request ended
+    backfill: Vec<(Option<Account>, Message)>,
+    /// Message over the size limit, sent split if validated again
+    oversized: Option<String>,
     #[allow(dead_code)]
     panic_handler: PanicHandler, // Defining panic_handler last guarantee that it will be dropped last (after terminal restoration)
 }
@@ -681,6 +2111,29 @@ impl UIMod {
 
         let panic_handler = PanicHandler::new();
 
+        let body_limits = BodyLimits {
+            max_lines: config.body_max_lines.unwrap_or(BODY_MAX_LINES),
+            max_chars: config.body_max_chars.unwrap_or(BODY_MAX_CHARS),
+        };
+
+        let roster_grouping = match config
+            .roster_grouping
+            .as_deref()
+            .map(RosterGrouping::from_str)
+        {
+            Some(Ok(grouping)) => grouping,
+            Some(Err(err)) => {
+                log::warn!("Invalid roster grouping: {err}");
+                RosterGrouping::Groups
+            }
+            None => RosterGrouping::Groups,
+        };
+
+        let base_debounce = match config.render_debounce_ms {
+            Some(ms) => Duration::from_millis(ms),
+            None => Duration::new(0, UI_DEBOUNCE_NS),
+        };
+
         let mut layout = LinearLayout::<UIEvent, Stdout>::new(Orientation::Vertical).with_event(
             |layout, event| {
                 for child in layout.iter_children_mut() {
@@ -715,7 +2168,9 @@ impl UIMod {
                     }
                 }
                 UIEvent::Core(Event::Key(Key::PageUp))
-                | UIEvent::Core(Event::Key(Key::PageDown)) => {
+                | UIEvent::Core(Event::Key(Key::PageDown))
+                | UIEvent::Search(_, _)
+                | UIEvent::Jump(_, _) => {
                     if let Some(current) = frame.get_current_mut() {
                         current.event(event);
                     }
@@ -726,41 +2181,47 @@ impl UIMod {
                     }
                 }
             });
-        let win_bar = WinBar::new(&config.theme.win_bar);
-        let input = Input::new().with_event(|input, event| match event {
-            UIEvent::Core(Event::Key(Key::Char(c))) => input.key(*c),
-            UIEvent::Core(Event::Key(Key::Backspace)) => input.backspace(),
-            UIEvent::Core(Event::Key(Key::Delete)) => input.delete(),
-            UIEvent::Core(Event::Key(Key::Home)) => input.home(),
-            UIEvent::Core(Event::Key(Key::End)) => input.end(),
-            UIEvent::Core(Event::Key(Key::Up)) => input.previous(),
-            UIEvent::Core(Event::Key(Key::Down)) => input.next(),
-            UIEvent::Core(Event::Key(Key::Left)) => input.left(),
-            UIEvent::Core(Event::Key(Key::Right)) => input.right(),
-            UIEvent::Core(Event::Key(Key::Ctrl('a'))) => input.home(),
-            UIEvent::Core(Event::Key(Key::Ctrl('b'))) => input.left(),
-            UIEvent::Core(Event::Key(Key::Ctrl('e'))) => input.end(),
-            UIEvent::Core(Event::Key(Key::Ctrl('f'))) => input.right(),
-            UIEvent::Core(Event::Key(Key::Ctrl('h'))) => input.backspace(),
-            UIEvent::Core(Event::Key(Key::Ctrl('w'))) => input.backward_delete_word(),
-            UIEvent::Core(Event::Key(Key::Ctrl('u'))) => input.delete_from_cursor_to_start(),
-            UIEvent::Core(Event::Key(Key::Ctrl('k'))) => input.delete_from_cursor_to_end(),
-            UIEvent::Validate(result) => {
-                let mut result = result.borrow_mut();
-                result.replace(input.validate());
-            }
-            UIEvent::GetInput(result) => {
-                let mut result = result.borrow_mut();
-                result.replace((input.buf.clone(), input.cursor.clone(), input.password));
-            }
-            UIEvent::Core(Event::Completed(raw_buf, cursor)) => {
-                input.buf = raw_buf.clone();
-                input.cursor = cursor.clone();
-                input.dirty = true;
-            }
-            UIEvent::Core(Event::ReadPassword(_)) => input.password(),
-            _ => {}
-        });
+        let win_bar = WinBar::new(
+            &config.theme.win_bar,
+            config.status_format.as_deref(),
+            config.status_clock,
+        );
+        let input = Input::new()
+            .with_counter(INPUT_COUNTER_THRESHOLD, config.max_message_size)
+            .with_event(|input, event| match event {
+                UIEvent::Core(Event::Key(Key::Char(c))) => input.key(*c),
+                UIEvent::Core(Event::Key(Key::Backspace)) => input.backspace(),
+                UIEvent::Core(Event::Key(Key::Delete)) => input.delete(),
+                UIEvent::Core(Event::Key(Key::Home)) => input.home(),
+                UIEvent::Core(Event::Key(Key::End)) => input.end(),
+                UIEvent::Core(Event::Key(Key::Up)) => input.previous(),
+                UIEvent::Core(Event::Key(Key::Down)) => input.next(),
+                UIEvent::Core(Event::Key(Key::Left)) => input.left(),
+                UIEvent::Core(Event::Key(Key::Right)) => input.right(),
+                UIEvent::Core(Event::Key(Key::Ctrl('a'))) => input.home(),
+                UIEvent::Core(Event::Key(Key::Ctrl('b'))) => input.left(),
+                UIEvent::Core(Event::Key(Key::Ctrl('e'))) => input.end(),
+                UIEvent::Core(Event::Key(Key::Ctrl('f'))) => input.right(),
+                UIEvent::Core(Event::Key(Key::Ctrl('h'))) => input.backspace(),
+                UIEvent::Core(Event::Key(Key::Ctrl('w'))) => input.backward_delete_word(),
+                UIEvent::Core(Event::Key(Key::Ctrl('u'))) => input.delete_from_cursor_to_start(),
+                UIEvent::Core(Event::Key(Key::Ctrl('k'))) => input.delete_from_cursor_to_end(),
+                UIEvent::Validate(result) => {
+                    let mut result = result.borrow_mut();
+                    result.replace(input.validate());
+                }
+                UIEvent::GetInput(result) => {
+                    let mut result = result.borrow_mut();
+                    result.replace((input.buf.clone(), input.cursor.clone(), input.password));
+                }
+                UIEvent::Core(Event::Completed(raw_buf, cursor)) => {
+                    input.buf = raw_buf.clone();
+                    input.cursor = cursor.clone();
+                    input.dirty = true;
+                }
+                UIEvent::Core(Event::ReadPassword(_)) => input.password(),
+                _ => {}
+            });
 
         layout.push(title_bar);
         layout.push(frame);
@@ -773,13 +2234,40 @@ impl UIMod {
             dimension: None,
             windows: Vec::new(),
             unread_windows: HashMap::new(),
+            read_markers: HashMap::new(),
+            unsaved_read_positions: HashMap::new(),
+            unread_ids: HashMap::new(),
             current_window: None,
             conversations: HashMap::new(),
             password_command: None,
+            pending_markups: HashMap::new(),
+            pending_replies: HashMap::new(),
             outgoing_event_queue: Rc::new(RefCell::new(Vec::new())),
+            quick_switch: None,
+            reaction_picker: None,
+            command_palette: None,
+            privacy: Vec::new(),
+            privacy_selected: 0,
+            disco: Vec::new(),
+            disco_selected: 0,
+            channels: None,
+            channels_filter: None,
+            channels_selected: 0,
+            form: None,
+            backfill: Vec::new(),
+            oversized: None,
             panic_handler,
             last_render: Instant::now(),
             debounced: 0,
+            base_debounce,
+            debounce: base_debounce,
+            resized: false,
+            flash_until: None,
+            bell_style: BellStyle::Audible,
+            roster_grouping,
+            roster_avatars: config.roster_avatars,
+            body_limits,
+            collapsed_groups: HashSet::new(),
         }
     }
 
@@ -793,128 +2281,1815 @@ impl UIMod {
         }
     }
 
-    fn add_conversation(&mut self, _aparte: &mut Aparte, conversation: Conversation) {
+    /// Window of a chat, a separate window is used when the contact is already
+    /// chatted with from another account
+    fn chat_window_name(&self, account: &Account, contact: &BareJid) -> String {
+        let name = contact.to_string();
+        match self.conversations.get(&name) {
+            Some(Conversation::Chat(chat)) if &chat.account != account => {
+                format!("{} [{}]", contact, account.to_bare())
+            }
+            _ => name,
+        }
+    }
+
+    /// Send a message typed in the input to the conversation
+    fn send_input(
+        aparte: &mut Aparte,
+        conversation: &Conversation,
+        body: String,
+        spoiler: Option<String>,
+        reply: Option<Reply>,
+    ) {
+        match conversation {
+            Conversation::Chat(chat) => {
+                let account = &chat.account;
+                let us = account.clone().into();
+                let from: Jid = us;
+                let to: Jid = chat.contact.clone().into();
+                let id = Uuid::new_v4();
+                let timestamp = LocalTz::now().into();
+                let lang =
+                    aparte
+                        .get_mod::<ConversationMod>()
+                        .get_lang(aparte, account, &chat.contact);
+                let mut bodies = HashMap::new();
+                bodies.insert(lang, body);
+                let message =
+                    Message::outgoing_chat(id.to_string(), timestamp, &from, &to, &bodies, false)
+                        .pending()
+                        .with_spoiler(spoiler)
+                        .with_reply(reply);
+                aparte.schedule(Event::SendMessage(account.clone(), message));
+            }
+            Conversation::Channel(channel) => {
+                let account = &channel.account;
+                let us = account.to_bare().with_resource_str(&channel.nick).unwrap(); // TODO avoid unwrap
+                let from: Jid = us.into();
+                let to: Jid = channel.jid.clone().into();
+                let id = Uuid::new_v4();
+                let timestamp = LocalTz::now().into();
+                let lang =
+                    aparte
+                        .get_mod::<ConversationMod>()
+                        .get_lang(aparte, account, &channel.jid);
+                let mut bodies = HashMap::new();
+                bodies.insert(lang, body);
+                let message = Message::outgoing_channel(
+                    id.to_string(),
+                    timestamp,
+                    &from,
+                    &to,
+                    &bodies,
+                    false,
+                )
+                .with_spoiler(spoiler)
+                .with_reply(reply);
+                aparte.schedule(Event::SendMessage(account.clone(), message));
+            }
+        }
+    }
+
+    /// Window of messages displayed with the configured body limits
+    fn message_win(&self) -> BufferedWin<UIEvent, Stdout, Message> {
+        let limits = self.body_limits;
+        BufferedWin::new().with_format(move |message| LimitedMessage(message, limits).to_string())
+    }
+
+    fn add_conversation(&mut self, aparte: &mut Aparte, conversation: Conversation) {
         let scheduler = self.get_scheduler();
+        let read_position = stored_read_position(aparte, &conversation);
+        let history_limit = match aparte
+            .config
+            .window_history_limit
+            .unwrap_or(WINDOW_HISTORY_LIMIT)
+        {
+            0 => None,
+            limit => Some(limit),
+        };
+        let body_limits = self.body_limits;
         match &conversation {
             Conversation::Chat(chat) => {
+                let win_name = self.chat_window_name(&chat.account, &chat.contact);
                 let chat_for_event = chat.clone();
-                let chatwin = BufferedWin::<UIEvent, Stdout, Message>::new().with_event(
-                    move |view, event| {
-                        match event {
-                            UIEvent::Core(Event::Message(_, Message::Xmpp(message))) => {
-                                match message.direction {
-                                    // TODO check to == us
-                                    Direction::Incoming => {
-                                        if message.from == chat_for_event.contact {
-                                            view.insert(Message::Xmpp(message.clone()));
+                // Start of the history when it was last loaded to fill the window
+                let mut filling_from = None;
+                let mut chatwin =
+                    self.message_win()
+                        .with_limit(history_limit)
+                        .with_event(move |view, event| {
+                            match event {
+                                UIEvent::Core(Event::Message(account, Message::Xmpp(message)))
+                                    if account.as_ref().map_or(true, |account| {
+                                        *account == chat_for_event.account
+                                    }) =>
+                                {
+                                    match message.direction {
+                                        // TODO check to == us
+                                        Direction::Incoming => {
+                                            if message.from == chat_for_event.contact {
+                                                view.insert(Message::Xmpp(message.clone()));
+                                            }
+                                        }
+                                        Direction::Outgoing => {
+                                            // TODO check from == us
+                                            if message.to == chat_for_event.contact {
+                                                view.insert(Message::Xmpp(message.clone()));
+                                            }
                                         }
                                     }
-                                    Direction::Outgoing => {
-                                        // TODO check from == us
-                                        if message.to == chat_for_event.contact {
-                                            view.insert(Message::Xmpp(message.clone()));
+                                }
+                                UIEvent::Core(Event::Key(Key::PageUp)) => {
+                                    if view.page_up() {
+                                        let from = view.first().map(|message| message.timestamp());
+                                        scheduler.schedule(Event::LoadChatHistory {
+                                            account: chat_for_event.account.clone(),
+                                            contact: chat_for_event.contact.clone(),
+                                            from: from.cloned(),
+                                        });
+                                    }
+                                }
+                                UIEvent::Core(Event::Key(Key::PageDown)) => {
+                                    if view.page_down() {
+                                        scheduler.schedule(Event::ChatScrolledToBottom {
+                                            account: chat_for_event.account.clone(),
+                                            contact: chat_for_event.contact.clone(),
+                                        });
+                                    }
+                                }
+                                UIEvent::Search(text, found) => {
+                                    *found.borrow_mut() =
+                                        view.search(|message| message_contains(message, text));
+                                }
+                                UIEvent::Jump(date, found) => {
+                                    *found.borrow_mut() =
+                                        view.jump(|message| message.timestamp() >= date);
+                                }
+                                UIEvent::Core(Event::ConversationLog {
+                                    account,
+                                    conversation,
+                                    message,
+                                }) => {
+                                    if *account == chat_for_event.account
+                                        && *conversation == chat_for_event.contact
+                                    {
+                                        view.insert(Message::Log(message.clone()));
+                                    }
+                                }
+                                UIEvent::Messages(account, messages)
+                                    if account.as_ref().map_or(true, |account| {
+                                        *account == chat_for_event.account
+                                    }) =>
+                                {
+                                    view.insert_all(
+                                        messages
+                                            .iter()
+                                            .filter(|message| match message {
+                                                Message::Xmpp(message) => match message.direction {
+                                                    Direction::Incoming => {
+                                                        message.from == chat_for_event.contact
+                                                    }
+                                                    Direction::Outgoing => {
+                                                        message.to == chat_for_event.contact
+                                                    }
+                                                },
+                                                Message::Log(_) => false,
+                                            })
+                                            .cloned(),
+                                    );
+                                }
+                                UIEvent::Core(Event::MessageDelivery {
+                                    account,
+                                    contact,
+                                    id,
+                                    delivery,
+                                }) => {
+                                    if *account == chat_for_event.account
+                                        && *contact == chat_for_event.contact
+                                    {
+                                        if *delivery == Delivery::Displayed {
+                                            move_read_marker(view, id);
                                         }
+                                        update_delivery(view, id, *delivery);
                                     }
                                 }
-                            }
-                            UIEvent::Core(Event::Key(Key::PageUp)) => {
-                                if view.page_up() {
-                                    let from = view.first().map(|message| message.timestamp());
-                                    scheduler.schedule(Event::LoadChatHistory {
-                                        account: chat_for_event.account.clone(),
-                                        contact: chat_for_event.contact.clone(),
-                                        from: from.cloned(),
-                                    });
+                                UIEvent::Core(Event::HistoryLoaded {
+                                    account,
+                                    conversation,
+                                    complete: false,
+                                }) => {
+                                    // Keep loading until the window is filled, unless the last page
+                                    // added nothing older and the same one would be asked again
+                                    if *account == chat_for_event.account
+                                        && *conversation == chat_for_event.contact
+                                        && !view.is_filled()
+                                    {
+                                        let from = view.first().map(|message| *message.timestamp());
+                                        if filling_from.as_ref() != Some(&from) {
+                                            filling_from = Some(from);
+                                            scheduler.schedule(Event::LoadChatHistory {
+                                                account: chat_for_event.account.clone(),
+                                                contact: chat_for_event.contact.clone(),
+                                                from,
+                                            });
+                                        }
+                                    }
                                 }
+                                UIEvent::Core(Event::RevealSpoilers {
+                                    conversation, id, ..
+                                }) => {
+                                    if *conversation == chat_for_event.contact {
+                                        reveal_spoilers(view, id.as_deref());
+                                    }
+                                }
+                                UIEvent::Core(Event::ExpandMessage {
+                                    conversation, id, ..
+                                }) => {
+                                    if *conversation == chat_for_event.contact {
+                                        expand_message(view, &body_limits, id.as_deref());
+                                    }
+                                }
+                                UIEvent::GetReadPosition(jid, result) => {
+                                    if *jid == chat_for_event.contact {
+                                        let mut result = result.borrow_mut();
+                                        result.replace(read_position_of(view));
+                                    }
+                                }
+                                _ => {}
                             }
-                            UIEvent::Core(Event::Key(Key::PageDown)) => {
-                                view.page_down();
-                            }
-                            _ => {}
+                        });
+                if let Some((offset, last_read, _)) = read_position {
+                    chatwin.set_anchor(move |message| message.id() == last_read, offset);
+                }
+
+                self.add_window(win_name.clone(), Box::new(chatwin));
+                self.conversations.insert(win_name, conversation.clone());
+            }
+            Conversation::Channel(channel) => {
+                if let Some((_, _, Some(read_at))) = &read_position {
+                    self.read_markers.insert(channel.get_name(), *read_at);
+                }
+
+                let mut layout = LinearLayout::<UIEvent, Stdout>::new(Orientation::Horizontal)
+                    .with_event(|layout, event| {
+                        for child in layout.iter_children_mut() {
+                            child.event(event);
                         }
-                    },
-                );
+                    });
 
-                self.add_window(chat.contact.to_string(), Box::new(chatwin));
-                self.conversations
-                    .insert(chat.contact.to_string(), conversation.clone());
+                let channel_for_event = channel.clone();
+                // Start of the history when it was last loaded to fill the window
+                let mut filling_from = None;
+                let mut chanwin =
+                    self.message_win()
+                        .with_limit(history_limit)
+                        .with_event(move |view, event| {
+                            match event {
+                                UIEvent::Core(Event::Message(_, Message::Xmpp(message))) => {
+                                    match message.direction {
+                                        // TODO check to == us
+                                        Direction::Incoming => {
+                                            if message.from == channel_for_event.jid {
+                                                view.insert(Message::Xmpp(message.clone()));
+                                            }
+                                        }
+                                        Direction::Outgoing => {
+                                            // TODO check from == us
+                                            if message.to == channel_for_event.jid {
+                                                view.insert(Message::Xmpp(message.clone()));
+                                            }
+                                        }
+                                    }
+                                }
+                                UIEvent::Messages(_, messages) => {
+                                    view.insert_all(
+                                        messages
+                                            .iter()
+                                            .filter(|message| match message {
+                                                Message::Xmpp(message) => match message.direction {
+                                                    Direction::Incoming => {
+                                                        message.from == channel_for_event.jid
+                                                    }
+                                                    Direction::Outgoing => {
+                                                        message.to == channel_for_event.jid
+                                                    }
+                                                },
+                                                Message::Log(_) => false,
+                                            })
+                                            .cloned(),
+                                    );
+                                }
+                                UIEvent::Core(Event::MessageReflected {
+                                    echo,
+                                    message: Message::Xmpp(message),
+                                    ..
+                                }) => {
+                                    if message.to == channel_for_event.jid {
+                                        view.remove(echo);
+                                        view.insert(Message::Xmpp(message.clone()));
+                                    }
+                                }
+                                UIEvent::Core(Event::Key(Key::PageUp)) => {
+                                    if view.page_up() {
+                                        let from = view.first().map(|message| message.timestamp());
+                                        scheduler.schedule(Event::LoadChannelHistory {
+                                            account: channel_for_event.account.clone(),
+                                            jid: channel_for_event.jid.clone(),
+                                            from: from.cloned(),
+                                        });
+                                    }
+                                }
+                                UIEvent::Core(Event::Key(Key::PageDown)) => {
+                                    view.page_down();
+                                }
+                                UIEvent::Search(text, found) => {
+                                    *found.borrow_mut() =
+                                        view.search(|message| message_contains(message, text));
+                                }
+                                UIEvent::Jump(date, found) => {
+                                    *found.borrow_mut() =
+                                        view.jump(|message| message.timestamp() >= date);
+                                }
+                                UIEvent::Core(Event::HistoryLoaded {
+                                    account,
+                                    conversation,
+                                    complete: false,
+                                }) => {
+                                    // Keep loading until the window is filled, unless the last page
+                                    // added nothing older and the same one would be asked again
+                                    if *account == channel_for_event.account
+                                        && *conversation == channel_for_event.jid
+                                        && !view.is_filled()
+                                    {
+                                        let from = view.first().map(|message| *message.timestamp());
+                                        if filling_from.as_ref() != Some(&from) {
+                                            filling_from = Some(from);
+                                            scheduler.schedule(Event::LoadChannelHistory {
+                                                account: channel_for_event.account.clone(),
+                                                jid: channel_for_event.jid.clone(),
+                                                from,
+                                            });
+                                        }
+                                    }
+                                }
+                                UIEvent::Core(Event::ConversationLog {
+                                    account,
+                                    conversation,
+                                    message,
+                                }) => {
+                                    if *account == channel_for_event.account
+                                        && *conversation == channel_for_event.jid
+                                    {
+                                        view.insert(Message::Log(message.clone()));
+                                    }
+                                }
+                                UIEvent::Core(Event::RevealSpoilers {
+                                    conversation, id, ..
+                                }) => {
+                                    if *conversation == channel_for_event.jid {
+                                        reveal_spoilers(view, id.as_deref());
+                                    }
+                                }
+                                UIEvent::Core(Event::ExpandMessage {
+                                    conversation, id, ..
+                                }) => {
+                                    if *conversation == channel_for_event.jid {
+                                        expand_message(view, &body_limits, id.as_deref());
+                                    }
+                                }
+                                UIEvent::GetReadPosition(jid, result) => {
+                                    if *jid == channel_for_event.jid {
+                                        let mut result = result.borrow_mut();
+                                        result.replace(read_position_of(view));
+                                    }
+                                }
+                                _ => {}
+                            }
+                        });
+                if let Some((offset, last_read, _)) = read_position {
+                    chanwin.set_anchor(move |message| message.id() == last_read, offset);
+                }
+                layout.push(chanwin);
+
+                let roster_jid = channel.jid.clone();
+                let avatars = self.roster_avatars;
+                let roster =
+                    ListView::<UIEvent, Stdout, conversation::Role, conversation::Occupant>::new()
+                        .with_layouts(Layouts {
+                            width: Layout::wrap_content(),
+                            height: Layout::match_parent(),
+                        })
+                        .with_format_item(move |occupant| match avatars {
+                            true => {
+                                let nick = terminus::clean(&occupant.nick);
+                                format!("{}{occupant}", avatar_block(&occupant.nick, &nick))
+                            }
+                            false => occupant.to_string(),
+                        })
+                        .with_none_group()
+                        .with_unique_item()
+                        .with_sort_item()
+                        .with_event(move |view, event| match event {
+                            UIEvent::Core(Event::Occupant {
+                                conversation,
+                                occupant,
+                                ..
+                            }) => {
+                                if roster_jid == *conversation {
+                                    view.insert(occupant.clone(), Some(occupant.role));
+                                }
+                            }
+                            UIEvent::Core(Event::OccupantRenamed {
+                                conversation,
+                                previous,
+                                occupant,
+                                ..
+                            }) => {
+                                if roster_jid == *conversation {
+                                    let mut previous_occupant = occupant.clone();
+                                    previous_occupant.nick = intern(previous);
+                                    let _ = view.remove(previous_occupant, Some(occupant.role));
+                                    view.insert(occupant.clone(), Some(occupant.role));
+                                }
+                            }
+                            _ => {}
+                        });
+                layout.push(roster);
+
+                self.add_window(channel.get_name(), Box::new(layout));
+                self.conversations
+                    .insert(channel.get_name(), conversation.clone());
+            }
+        }
+    }
+
+    pub fn set_roster_grouping(&mut self, grouping: RosterGrouping) {
+        self.roster_grouping = grouping;
+        self.root.event(&mut UIEvent::RosterGrouping(grouping));
+    }
+
+    /// Scroll the current window up to the previous message containing the text
+    pub fn search(&mut self, text: &str) -> bool {
+        let found = Rc::new(RefCell::new(false));
+        self.root
+            .event(&mut UIEvent::Search(text.to_string(), Rc::clone(&found)));
+        let found = *found.borrow();
+        found
+    }
+
+    /// Scroll the current window to the first message since the date
+    pub fn jump(&mut self, date: DateTime<FixedOffset>) -> bool {
+        let found = Rc::new(RefCell::new(false));
+        self.root.event(&mut UIEvent::Jump(date, Rc::clone(&found)));
+        let found = *found.borrow();
+        found
+    }
+
+    pub fn set_console_level(&mut self, level: log::Level) {
+        self.root.event(&mut UIEvent::ConsoleLevel(level));
+    }
+
+    pub fn show_mentions(&mut self) {
+        if !self.windows.iter().any(|window| window == MENTIONS_WINDOW) {
+            self.add_mentions_window();
+        }
+        self.change_window(MENTIONS_WINDOW);
+    }
+
+    pub fn filter_channels(&mut self, filter: Option<String>) {
+        self.channels_filter = filter.map(|filter| filter.to_lowercase());
+        self.channels_selected = 0;
+        if self.windows.iter().any(|window| window == CHANNELS_WINDOW) {
+            self.render_channels();
+            self.change_window(CHANNELS_WINDOW);
+        }
+    }
+
+    /// Listed channels matching the current filter, most populated first
+    fn filtered_channels(&self) -> Vec<&ChannelListing> {
+        let Some((_, channels)) = &self.channels else {
+            return Vec::new();
+        };
+        channels
+            .iter()
+            .filter(|channel| match &self.channels_filter {
+                Some(filter) => [
+                    Some(channel.jid.to_string()),
+                    channel.name.clone(),
+                    channel.description.clone(),
+                ]
+                .iter()
+                .flatten()
+                .any(|text| text.to_lowercase().contains(filter)),
+                None => true,
+            })
+            .collect()
+    }
+
+    fn render_channels(&mut self) {
+        let rows = self
+            .filtered_channels()
+            .into_iter()
+            .enumerate()
+            .map(|(position, channel)| {
+                let marker = match position == self.channels_selected {
+                    true => ">",
+                    false => "",
+                };
+                vec![
+                    marker.to_string(),
+                    channel.jid.to_string(),
+                    channel.name.clone().unwrap_or_default(),
+                    channel
+                        .occupants
+                        .map(|count| count.to_string())
+                        .unwrap_or_default(),
+                    channel.description.clone().unwrap_or_default(),
+                ]
+            })
+            .collect();
+        self.root
+            .event(&mut UIEvent::ChannelRows(rows, self.channels_selected));
+    }
+
+    /// Keys typed in the channels window while the input is empty, Enter joins the selected one
+    fn channels_key(&mut self, aparte: &mut Aparte, key: &Key) {
+        let count = self.filtered_channels().len();
+        match key {
+            Key::Up => self.channels_selected = self.channels_selected.saturating_sub(1),
+            Key::Down => {
+                self.channels_selected =
+                    cmp::min(self.channels_selected + 1, count.saturating_sub(1))
+            }
+            Key::Char('\n') => {
+                let selected = self
+                    .filtered_channels()
+                    .get(self.channels_selected)
+                    .cloned();
+                if let (Some((account, _)), Some(channel)) = (&self.channels, selected) {
+                    aparte.schedule(Event::Join {
+                        account: account.clone(),
+                        channel: Jid::Bare(channel.jid.clone()),
+                        user_request: true,
+                    });
+                }
+            }
+            _ => {}
+        }
+        self.render_channels();
+    }
+
+    /// Tell the contact of the current window that we are typing a message
+    fn notify_composing(&mut self, aparte: &mut Aparte) {
+        let chat = match self
+            .current_window
+            .as_ref()
+            .and_then(|window| self.conversations.get(window))
+        {
+            Some(Conversation::Chat(chat)) => chat.clone(),
+            _ => return,
+        };
+
+        let result = Rc::new(RefCell::new(None));
+        self.root.event(&mut UIEvent::GetInput(Rc::clone(&result)));
+        let typing = match result.borrow().as_ref() {
+            Some((raw_buf, _, password)) => !password && !raw_buf.starts_with('/'),
+            None => false,
+        };
+
+        if typing {
+            aparte.schedule(Event::ChatState {
+                account: chat.account,
+                contact: chat.contact,
+                state: ChatState::Composing,
+            });
+        }
+    }
+
+    /// Ring the bell if enabled for this reason, audibly or by flashing the status line
+    fn ring(&mut self, aparte: &mut Aparte, reason: BellReason) {
+        let config = &aparte.config;
+        let enabled = match &config.bell_events {
+            Some(events) => events.iter().any(|event| event == reason.name()),
+            None => reason != BellReason::Error,
+        };
+        if !config.bell || !enabled {
+            return;
+        }
+
+        if matches!(self.bell_style, BellStyle::Audible | BellStyle::Both) {
+            vprint!(self.screen, "\x07");
+        }
+        if matches!(self.bell_style, BellStyle::Visual | BellStyle::Both) {
+            self.flash_until = Some(Instant::now() + FLASH_DURATION);
+            self.root.event(&mut UIEvent::Flash(true));
+            Aparte::spawn({
+                let mut aparte = aparte.proxy();
+                async move {
+                    tokio::time::sleep(FLASH_DURATION).await;
+                    aparte.schedule(Event::UIRender);
+                }
+            });
+        }
+    }
+
+    /// Names of the groups currently listed in the roster
+    fn roster_groups(&mut self) -> Vec<String> {
+        let groups = Rc::new(RefCell::new(Vec::new()));
+        self.root
+            .event(&mut UIEvent::GetRosterGroups(Rc::clone(&groups)));
+        groups.take()
+    }
+
+    pub fn set_roster_group_collapsed(
+        &mut self,
+        aparte: &Aparte,
+        group: &str,
+        collapsed: bool,
+    ) -> anyhow::Result<()> {
+        aparte
+            .storage
+            .set_roster_group_collapsed(group, collapsed)?;
+        match collapsed {
+            true => self.collapsed_groups.insert(group.to_string()),
+            false => self.collapsed_groups.remove(group),
+        };
+        self.root.event(&mut UIEvent::CollapseRosterGroup(
+            group.to_string(),
+            collapsed,
+        ));
+        Ok(())
+    }
+
+    /// Collapse or expand the roster group of the current conversation
+    fn toggle_current_roster_group(&mut self, aparte: &Aparte) {
+        let jid = self
+            .current_window
+            .as_ref()
+            .and_then(|window| self.conversations.get(window))
+            .map(|conversation| conversation.get_jid().clone());
+        let result = Rc::new(RefCell::new(None));
+        self.root
+            .event(&mut UIEvent::GetRosterGroupOf(jid, Rc::clone(&result)));
+        let group = match result.take() {
+            Some(group) => group,
+            None => return,
+        };
+
+        let collapsed = !self.collapsed_groups.contains(&group);
+        if let Err(err) = self.set_roster_group_collapsed(aparte, &group, collapsed) {
+            log::warn!("Cannot save collapsed state of roster group {group}: {err}");
+        }
+    }
+
+    /// Remember where the window was left, it is written to storage later on
+    fn remember_read_position(&mut self, window: &str) {
+        let conversation = match self.conversations.get(window) {
+            Some(conversation) => conversation.clone(),
+            None => return,
+        };
+
+        let result = Rc::new(RefCell::new(None));
+        self.root.event(&mut UIEvent::GetReadPosition(
+            conversation.get_jid().clone(),
+            Rc::clone(&result),
+        ));
+
+        let position = result.borrow_mut().take();
+        if let Some((view, last_read)) = position {
+            let (last_read, read_at) = match last_read {
+                Some((last_read, read_at)) => (Some(last_read), Some(read_at)),
+                None => (None, None),
+            };
+            if let (Conversation::Channel(_), Some(read_at)) = (&conversation, read_at) {
+                self.read_markers.insert(window.to_string(), read_at);
+            }
+            self.unsaved_read_positions.insert(
+                window.to_string(),
+                UnsavedReadPosition {
+                    conversation,
+                    view,
+                    last_read,
+                    read_at,
+                },
+            );
+        }
+    }
+
+    /// Write remembered read positions to storage, of the given window only or of all windows
+    fn save_read_positions(&mut self, aparte: &Aparte, window: Option<&str>) {
+        let windows: Vec<String> = match window {
+            Some(window) => vec![window.to_string()],
+            None => self.unsaved_read_positions.keys().cloned().collect(),
+        };
+        for window in windows {
+            let Some(position) = self.unsaved_read_positions.remove(&window) else {
+                continue;
+            };
+            if let Err(err) = aparte.storage.set_read_position(
+                position.conversation.get_account(),
+                position.conversation.get_jid(),
+                position.view,
+                position.last_read.as_deref(),
+                position
+                    .read_at
+                    .map(|read_at| read_at.to_rfc3339())
+                    .as_deref(),
+            ) {
+                log::warn!("Cannot save read position of {window}: {err}");
+            }
+        }
+    }
+
+    /// Lengthen debounce delay under message floods and shorten it back when idle
+    fn adapt_debounce(&mut self) {
+        let min = self.base_debounce / 4;
+        let max = self.base_debounce * 4;
+        if self.debounced > UI_FLOOD_THRESHOLD {
+            self.debounce = cmp::min(self.debounce * 2, max);
+        } else if self.debounced == 0 {
+            self.debounce = cmp::max(self.debounce / 2, min);
+        }
+    }
+
+    fn add_events_window(&mut self) {
+        let events = self.message_win().with_event(|view, event| match event {
+            UIEvent::Core(Event::EventTrace(entries)) => {
+                view.history.clear();
+                for entry in entries.iter() {
+                    view.insert(entry.clone());
+                }
+                view.view = 0;
+                view.dirty = true;
+            }
+            UIEvent::Core(Event::Key(Key::PageUp)) => {
+                view.page_up();
+            }
+            UIEvent::Core(Event::Key(Key::PageDown)) => {
+                view.page_down();
+            }
+            _ => {}
+        });
+
+        self.add_window(EVENTS_WINDOW.to_string(), Box::new(events));
+    }
+
+    fn add_mentions_window(&mut self) {
+        let mentions = self.message_win().with_event(|view, event| match event {
+            UIEvent::Core(Event::Mention { message, .. }) => {
+                let nick = match &message.from_full {
+                    Jid::Full(from) => from.resource().to_string(),
+                    Jid::Bare(_) => String::new(),
+                };
+                let body = match &message.spoiler {
+                    Some(_) => "(spoiler)",
+                    None => message.get_last_body(),
+                };
+                view.insert(Message::Log(LogMessage {
+                    id: message.id.clone(),
+                    timestamp: *message.get_original_timestamp(),
+                    body: format!("{} <{}> {}", message.from, nick, body),
+                    level: log::Level::Info,
+                }));
+            }
+            UIEvent::Core(Event::Key(Key::PageUp)) => {
+                view.page_up();
+            }
+            UIEvent::Core(Event::Key(Key::PageDown)) => {
+                view.page_down();
+            }
+            _ => {}
+        });
+
+        self.add_window(MENTIONS_WINDOW.to_string(), Box::new(mentions));
+    }
+
+    fn add_activity_window(&mut self) {
+        // Only account whose activity is displayed, if any
+        let mut filter: Option<BareJid> = None;
+        let activity = self
+            .message_win()
+            .with_event(move |view, event| match event {
+                UIEvent::Core(Event::ActivityLog {
+                    filter: new_filter,
+                    entries,
+                }) => {
+                    filter = new_filter.clone();
+                    view.history.clear();
+                    for entry in entries.iter() {
+                        view.insert(entry.clone());
+                    }
+                    view.view = 0;
+                    view.dirty = true;
+                }
+                UIEvent::Core(Event::Activity { account, message }) => {
+                    let displayed = match (&filter, account) {
+                        (None, _) => true,
+                        (Some(filter), Some(account)) => &account.to_bare() == filter,
+                        (Some(_), None) => false,
+                    };
+                    if displayed {
+                        view.insert(message.clone());
+                    }
+                }
+                UIEvent::Core(Event::Key(Key::PageUp)) => {
+                    view.page_up();
+                }
+                UIEvent::Core(Event::Key(Key::PageDown)) => {
+                    view.page_down();
+                }
+                _ => {}
+            });
+
+        self.add_window(ACTIVITY_WINDOW.to_string(), Box::new(activity));
+    }
+
+    fn add_pins_window(&mut self) {
+        let pins = self.message_win().with_event(|view, event| match event {
+            UIEvent::Core(Event::Pins { entries, .. }) => {
+                view.history.clear();
+                for entry in entries.iter() {
+                    view.insert(entry.clone());
+                }
+                view.view = 0;
+                view.dirty = true;
+            }
+            UIEvent::Core(Event::Key(Key::PageUp)) => {
+                view.page_up();
+            }
+            UIEvent::Core(Event::Key(Key::PageDown)) => {
+                view.page_down();
+            }
+            _ => {}
+        });
+
+        self.add_window(PINS_WINDOW.to_string(), Box::new(pins));
+    }
+
+    fn add_pubsub_window(&mut self) {
+        let pubsub = self
+            .message_win()
+            .with_limit(Some(WINDOW_HISTORY_LIMIT))
+            .with_event(|view, event| match event {
+                UIEvent::Core(Event::PubSubLog(message)) => {
+                    view.insert(message.clone());
+                }
+                UIEvent::Core(Event::Key(Key::PageUp)) => {
+                    view.page_up();
+                }
+                UIEvent::Core(Event::Key(Key::PageDown)) => {
+                    view.page_down();
+                }
+                _ => {}
+            });
+
+        self.add_window(PUBSUB_WINDOW.to_string(), Box::new(pubsub));
+    }
+
+    fn add_channels_window(&mut self) {
+        let channels = TableView::<UIEvent, Stdout>::new(vec![
+            "",
+            "Channel",
+            "Name",
+            "Occupants",
+            "Description",
+        ])
+        .with_event(|view, event| match event {
+            UIEvent::ChannelRows(rows, selected) => {
+                view.set_rows(rows.clone());
+                view.scroll_to(*selected);
+            }
+            UIEvent::Core(Event::Key(Key::PageUp)) => {
+                view.page_up();
+            }
+            UIEvent::Core(Event::Key(Key::PageDown)) => {
+                view.page_down();
+            }
+            _ => {}
+        });
+
+        self.add_window(CHANNELS_WINDOW.to_string(), Box::new(channels));
+    }
+
+    fn add_members_window(&mut self) {
+        let members =
+            TableView::<UIEvent, Stdout>::new(vec!["Jid", "Affiliation", "Nick", "Reason"])
+                .with_event(|view, event| match event {
+                    UIEvent::Core(Event::RoomMembers {
+                        members, filter, ..
+                    }) => {
+                        // Members are already ordered by affiliation
+                        view.set_rows(
+                            members
+                                .iter()
+                                .map(|member| {
+                                    vec![
+                                        member.jid.to_string(),
+                                        member.affiliation.to_string(),
+                                        member.nick.clone().unwrap_or_default(),
+                                        member.reason.clone().unwrap_or_default(),
+                                    ]
+                                })
+                                .collect(),
+                        );
+                        view.set_filter(filter.clone());
+                    }
+                    UIEvent::Core(Event::Key(Key::PageUp)) => {
+                        view.page_up();
+                    }
+                    UIEvent::Core(Event::Key(Key::PageDown)) => {
+                        view.page_down();
+                    }
+                    _ => {}
+                });
+
+        self.add_window(MEMBERS_WINDOW.to_string(), Box::new(members));
+    }
+
+    fn add_quick_switch_window(&mut self) {
+        let switch = TableView::<UIEvent, Stdout>::new(vec!["", "Name", "Jid", "Kind"]).with_event(
+            |view, event| {
+                if let UIEvent::QuickSwitch(rows) = event {
+                    view.set_rows(rows.clone());
+                }
+            },
+        );
+
+        self.add_window(QUICK_SWITCH_WINDOW.to_string(), Box::new(switch));
+    }
+
+    /// Open windows, roster contacts and bookmarks without window
+    fn switch_candidates(&self, aparte: &Aparte) -> Vec<SwitchCandidate> {
+        let mut candidates: Vec<SwitchCandidate> = self
+            .windows
+            .iter()
+            .filter(|window| *window != QUICK_SWITCH_WINDOW)
+            .map(|window| SwitchCandidate {
+                name: window.clone(),
+                jid: String::new(),
+                kind: String::from("window"),
+                target: SwitchTarget::Window(window.clone()),
+            })
+            .collect();
+
+        let mut contacts: Vec<SwitchCandidate> = aparte
+            .get_mod::<ContactMod>()
+            .iter()
+            .filter(|(account, contact)| {
+                !self
+                    .windows
+                    .contains(&self.chat_window_name(account, &contact.jid))
+            })
+            .map(|(account, contact)| SwitchCandidate {
+                name: contact
+                    .display_name()
+                    .map(ToString::to_string)
+                    .unwrap_or_else(|| contact.jid.to_string()),
+                jid: contact.jid.to_string(),
+                kind: format!("contact of {}", account.to_bare()),
+                target: SwitchTarget::Contact(account.clone(), contact.jid.clone()),
+            })
+            .collect();
+        contacts.sort_by(|a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
+        candidates.extend(contacts);
+
+        let bookmarks = aparte.get_mod::<BookmarksMod>();
+        candidates.extend(
+            bookmarks
+                .bookmarks
+                .iter()
+                .filter(|bookmark| !self.windows.contains(&bookmark.jid.to_string()))
+                .map(|bookmark| SwitchCandidate {
+                    name: bookmark
+                        .name
+                        .clone()
+                        .unwrap_or_else(|| bookmark.jid.to_string()),
+                    jid: bookmark.jid.to_string(),
+                    kind: String::from("bookmark"),
+                    target: SwitchTarget::Bookmark(bookmark.clone()),
+                }),
+        );
+
+        candidates
+    }
+
+    fn open_quick_switch(&mut self, aparte: &mut Aparte) {
+        let result = Rc::new(RefCell::new(None));
+        self.root.event(&mut UIEvent::GetInput(Rc::clone(&result)));
+        let (raw_buf, cursor, password) = result.borrow_mut().take().unwrap();
+        if password {
+            return;
+        }
+
+        let candidates = self.switch_candidates(aparte);
+        self.root.event(&mut UIEvent::Core(Event::Completed(
+            String::new(),
+            Cursor::new(0),
+        )));
+        if !self
+            .windows
+            .iter()
+            .any(|window| window == QUICK_SWITCH_WINDOW)
+        {
+            self.add_quick_switch_window();
+        }
+        self.quick_switch = Some(QuickSwitch {
+            previous: self.current_window.clone(),
+            stash: (raw_buf, cursor),
+            candidates,
+            matches: Vec::new(),
+            selected: 0,
+        });
+        self.change_window(QUICK_SWITCH_WINDOW);
+        self.update_quick_switch();
+    }
+
+    /// Match candidates against the typed query
+    fn update_quick_switch(&mut self) {
+        let result = Rc::new(RefCell::new(None));
+        self.root.event(&mut UIEvent::GetInput(Rc::clone(&result)));
+        let query = match result.borrow().as_ref() {
+            Some((raw_buf, _, _)) => raw_buf.clone(),
+            None => String::new(),
+        };
+
+        let switch = match self.quick_switch.as_mut() {
+            Some(switch) => switch,
+            None => return,
+        };
+        let matcher = SkimMatcherV2::default();
+        let mut scored: Vec<(i64, usize)> = switch
+            .candidates
+            .iter()
+            .enumerate()
+            .filter_map(|(index, candidate)| match query.is_empty() {
+                true => Some((0, index)),
+                false => matcher
+                    .fuzzy_match(&format!("{} {}", candidate.name, candidate.jid), &query)
+                    .map(|score| (score, index)),
+            })
+            .collect();
+        scored.sort_by(|(a_score, a), (b_score, b)| b_score.cmp(a_score).then(a.cmp(b)));
+        switch.matches = scored
+            .into_iter()
+            .take(QUICK_SWITCH_SIZE)
+            .map(|(_, index)| index)
+            .collect();
+        switch.selected = 0;
+
+        self.render_quick_switch();
+    }
+
+    fn render_quick_switch(&mut self) {
+        let rows = match &self.quick_switch {
+            Some(switch) => switch
+                .matches
+                .iter()
+                .enumerate()
+                .map(|(position, index)| {
+                    let candidate = &switch.candidates[*index];
+                    let marker = match position == switch.selected {
+                        true => ">",
+                        false => "",
+                    };
+                    vec![
+                        marker.to_string(),
+                        candidate.name.clone(),
+                        candidate.jid.clone(),
+                        candidate.kind.clone(),
+                    ]
+                })
+                .collect(),
+            None => return,
+        };
+        self.root.event(&mut UIEvent::QuickSwitch(rows));
+    }
+
+    fn move_quick_switch_selection(&mut self, down: bool) {
+        if let Some(switch) = self.quick_switch.as_mut() {
+            switch.selected = match down {
+                true => cmp::min(switch.selected + 1, switch.matches.len().saturating_sub(1)),
+                false => switch.selected.saturating_sub(1),
+            };
+        }
+        self.render_quick_switch();
+    }
+
+    /// Close the quick switcher and go to the selected destination if validated
+    fn close_quick_switch(&mut self, aparte: &mut Aparte, validate: bool) {
+        let switch = match self.quick_switch.take() {
+            Some(switch) => switch,
+            None => return,
+        };
+
+        let (raw_buf, cursor) = switch.stash;
+        self.root
+            .event(&mut UIEvent::Core(Event::Completed(raw_buf, cursor)));
+        self.windows.retain(|window| window != QUICK_SWITCH_WINDOW);
+        self.root.event(&mut UIEvent::Core(Event::Close(
+            QUICK_SWITCH_WINDOW.to_string(),
+        )));
+
+        let previous = switch
+            .previous
+            .filter(|previous| self.windows.contains(previous))
+            .or_else(|| self.windows.first().cloned());
+        if let Some(previous) = previous {
+            self.change_window(&previous);
+        }
+
+        let target = match validate {
+            true => switch
+                .matches
+                .get(switch.selected)
+                .map(|index| switch.candidates[*index].target.clone()),
+            false => None,
+        };
+        match target {
+            Some(SwitchTarget::Window(window)) => {
+                if self.windows.contains(&window) {
+                    self.change_window(&window);
+                }
+            }
+            Some(SwitchTarget::Contact(account, contact)) => {
+                aparte.schedule(Event::Chat { account, contact });
+            }
+            Some(SwitchTarget::Bookmark(bookmark)) => match aparte.current_account() {
+                Some(account) => {
+                    let channel = match &bookmark.nick {
+                        Some(nick) => match bookmark.jid.with_resource_str(nick) {
+                            Ok(full) => Jid::Full(full),
+                            Err(_) => Jid::Bare(bookmark.jid.clone()),
+                        },
+                        None => Jid::Bare(bookmark.jid.clone()),
+                    };
+                    aparte.schedule(Event::Join {
+                        account,
+                        channel,
+                        user_request: true,
+                    });
+                }
+                None => crate::info!(aparte, "No connection found"),
+            },
+            None => {}
+        }
+    }
+
+    /// Keys typed while the quick switcher is open
+    fn quick_switch_key(&mut self, aparte: &mut Aparte, key: &Key) {
+        match key {
+            Key::Char('\n') => self.close_quick_switch(aparte, true),
+            Key::Esc | Key::Alt('k') => self.close_quick_switch(aparte, false),
+            Key::Up => self.move_quick_switch_selection(false),
+            Key::Down => self.move_quick_switch_selection(true),
+            Key::Char('\t') | Key::PageUp | Key::PageDown => {}
+            _ => {
+                self.root.event(&mut UIEvent::Core(Event::Key(*key)));
+                self.update_quick_switch();
+            }
+        }
+    }
+
+    fn add_command_palette_window(&mut self) {
+        let palette = TableView::<UIEvent, Stdout>::new(vec!["", "Command", "Description"])
+            .with_event(|view, event| {
+                if let UIEvent::CommandPalette(rows) = event {
+                    view.set_rows(rows.clone());
+                }
+            });
+
+        self.add_window(COMMAND_PALETTE_WINDOW.to_string(), Box::new(palette));
+    }
+
+    fn open_command_palette(&mut self, aparte: &mut Aparte) {
+        let result = Rc::new(RefCell::new(None));
+        self.root.event(&mut UIEvent::GetInput(Rc::clone(&result)));
+        let (raw_buf, cursor, password) = result.borrow_mut().take().unwrap();
+        if password {
+            return;
+        }
+
+        let mut commands: Vec<PaletteCommand> = aparte
+            .command_parsers
+            .iter()
+            .map(|(name, parser)| PaletteCommand::new(name, &parser.help))
+            .collect();
+        commands.sort_by(|a, b| a.name.cmp(&b.name));
+        self.root.event(&mut UIEvent::Core(Event::Completed(
+            String::new(),
+            Cursor::new(0),
+        )));
+        if !self
+            .windows
+            .iter()
+            .any(|window| window == COMMAND_PALETTE_WINDOW)
+        {
+            self.add_command_palette_window();
+        }
+        self.command_palette = Some(CommandPalette {
+            previous: self.current_window.clone(),
+            stash: (raw_buf, cursor),
+            commands,
+            matches: Vec::new(),
+            selected: 0,
+        });
+        self.change_window(COMMAND_PALETTE_WINDOW);
+        self.update_command_palette();
+    }
+
+    /// Match commands and their description against the typed query
+    fn update_command_palette(&mut self) {
+        let result = Rc::new(RefCell::new(None));
+        self.root.event(&mut UIEvent::GetInput(Rc::clone(&result)));
+        let query = match result.borrow().as_ref() {
+            Some((raw_buf, _, _)) => raw_buf.trim_start_matches('/').to_string(),
+            None => String::new(),
+        };
+
+        let palette = match self.command_palette.as_mut() {
+            Some(palette) => palette,
+            None => return,
+        };
+        let matcher = SkimMatcherV2::default();
+        let mut scored: Vec<(i64, usize)> = palette
+            .commands
+            .iter()
+            .enumerate()
+            .filter_map(|(index, command)| match query.is_empty() {
+                true => Some((0, index)),
+                false => matcher
+                    .fuzzy_match(&format!("{} {}", command.name, command.description), &query)
+                    .map(|score| (score, index)),
+            })
+            .collect();
+        scored.sort_by(|(a_score, a), (b_score, b)| b_score.cmp(a_score).then(a.cmp(b)));
+        palette.matches = scored
+            .into_iter()
+            .take(COMMAND_PALETTE_SIZE)
+            .map(|(_, index)| index)
+            .collect();
+        palette.selected = 0;
+
+        self.render_command_palette();
+    }
+
+    fn render_command_palette(&mut self) {
+        let rows = match &self.command_palette {
+            Some(palette) => palette
+                .matches
+                .iter()
+                .enumerate()
+                .map(|(position, index)| {
+                    let command = &palette.commands[*index];
+                    let marker = match position == palette.selected {
+                        true => ">",
+                        false => "",
+                    };
+                    vec![
+                        marker.to_string(),
+                        command.usage.clone(),
+                        command.description.clone(),
+                    ]
+                })
+                .collect(),
+            None => return,
+        };
+        self.root.event(&mut UIEvent::CommandPalette(rows));
+    }
+
+    fn move_command_palette_selection(&mut self, down: bool) {
+        if let Some(palette) = self.command_palette.as_mut() {
+            palette.selected = match down {
+                true => cmp::min(
+                    palette.selected + 1,
+                    palette.matches.len().saturating_sub(1),
+                ),
+                false => palette.selected.saturating_sub(1),
+            };
+        }
+        self.render_command_palette();
+    }
+
+    /// Close the command palette and run the selected command if validated, its arguments being
+    /// prompted for in the input when it needs some
+    fn close_command_palette(&mut self, aparte: &mut Aparte, validate: bool) {
+        let palette = match self.command_palette.take() {
+            Some(palette) => palette,
+            None => return,
+        };
+
+        let command = match validate {
+            true => palette
+                .matches
+                .get(palette.selected)
+                .map(|index| &palette.commands[*index]),
+            false => None,
+        };
+        let (raw_buf, cursor) = match command {
+            Some(command) if command.needs_arguments() => {
+                let raw_buf = format!("/{} ", command.name);
+                let cursor = Cursor::new(raw_buf.chars().count());
+                (raw_buf, cursor)
+            }
+            _ => palette.stash.clone(),
+        };
+        self.root
+            .event(&mut UIEvent::Core(Event::Completed(raw_buf, cursor)));
+        self.windows
+            .retain(|window| window != COMMAND_PALETTE_WINDOW);
+        self.root.event(&mut UIEvent::Core(Event::Close(
+            COMMAND_PALETTE_WINDOW.to_string(),
+        )));
+
+        let previous = palette
+            .previous
+            .clone()
+            .filter(|previous| self.windows.contains(previous))
+            .or_else(|| self.windows.first().cloned());
+        if let Some(previous) = &previous {
+            self.change_window(previous);
+        }
+
+        match command {
+            Some(command) if command.needs_arguments() => {
+                crate::info!(aparte, "Usage: {}", command.usage);
+            }
+            Some(command) => {
+                let window = previous.unwrap_or_default();
+                let account = self
+                    .conversations
+                    .get(&window)
+                    .map(|conversation| conversation.get_account().clone());
+                aparte.schedule(Event::RawCommand(
+                    account,
+                    window,
+                    format!("/{}", command.name),
+                ));
+            }
+            None => {}
+        }
+    }
+
+    /// Keys typed while the command palette is open
+    fn command_palette_key(&mut self, aparte: &mut Aparte, key: &Key) {
+        match key {
+            Key::Char('\n') => self.close_command_palette(aparte, true),
+            Key::Esc | Key::Alt('x') => self.close_command_palette(aparte, false),
+            Key::Up => self.move_command_palette_selection(false),
+            Key::Down => self.move_command_palette_selection(true),
+            Key::Char('\t') | Key::PageUp | Key::PageDown => {}
+            _ => {
+                self.root.event(&mut UIEvent::Core(Event::Key(*key)));
+                self.update_command_palette();
+            }
+        }
+    }
+
+    fn add_reactions_window(&mut self) {
+        let picker = GridView::<UIEvent, Stdout>::new().with_event(|view, event| match event {
+            UIEvent::ReactionChoices(title, emojis) => {
+                view.set_title(title.clone());
+                view.set_cells(emojis.clone());
+            }
+            UIEvent::MoveReaction(dx, dy) => view.move_by(*dx, *dy),
+            UIEvent::GetReaction(result) => {
+                result.replace(view.selected().map(ToString::to_string));
+            }
+            _ => {}
+        });
+
+        self.add_window(REACTIONS_WINDOW.to_string(), Box::new(picker));
+    }
+
+    /// Message picked with Alt+r in the current window, or its last message
+    pub fn reaction_target(&self, aparte: &Aparte) -> Option<(Account, VersionedXmppMessage)> {
+        let window = self.current_window.as_ref()?;
+        let conversation = self.conversations.get(window)?;
+        let account = conversation.get_account();
+        let messages = aparte.get_mod::<MessagesMod>();
+        let message = match self.pending_replies.get(window) {
+            Some(reply) => match messages.get(&Some(account.clone()), &reply.id) {
+                Some(Message::Xmpp(message)) => Some(message.clone()),
+                _ => None,
+            },
+            None => messages.adjacent(account, conversation.get_jid(), None, true),
+        };
+        message.map(|message| (account.clone(), message))
+    }
+
+    fn open_reaction_picker(&mut self, aparte: &mut Aparte) {
+        let Some((account, message)) = self.reaction_target(aparte) else {
+            crate::info!(aparte, "No message to react to");
+            return;
+        };
+
+        let result = Rc::new(RefCell::new(None));
+        self.root.event(&mut UIEvent::GetInput(Rc::clone(&result)));
+        let (raw_buf, cursor, password) = result.borrow_mut().take().unwrap();
+        if password {
+            return;
+        }
+        self.root.event(&mut UIEvent::Core(Event::Completed(
+            String::new(),
+            Cursor::new(0),
+        )));
+
+        if !self.windows.iter().any(|window| window == REACTIONS_WINDOW) {
+            self.add_reactions_window();
+        }
+        let title = format!(
+            "React to \"{}\"",
+            word::excerpt(message.get_last_body(), 50)
+        );
+        let choices = aparte.get_mod::<ReactionsMod>().choices();
+        self.reaction_picker = Some(ReactionPicker {
+            previous: self.current_window.clone(),
+            stash: (raw_buf, cursor),
+            account,
+            message,
+        });
+        self.change_window(REACTIONS_WINDOW);
+        self.root
+            .event(&mut UIEvent::ReactionChoices(title, choices));
+    }
+
+    /// Close the reaction picker and react with the selected emoji if validated
+    fn close_reaction_picker(&mut self, aparte: &mut Aparte, validate: bool) {
+        let picker = match self.reaction_picker.take() {
+            Some(picker) => picker,
+            None => return,
+        };
+
+        let emoji = Rc::new(RefCell::new(None));
+        self.root
+            .event(&mut UIEvent::GetReaction(Rc::clone(&emoji)));
+        let emoji = emoji.borrow_mut().take();
+
+        let (raw_buf, cursor) = picker.stash;
+        self.root
+            .event(&mut UIEvent::Core(Event::Completed(raw_buf, cursor)));
+        self.windows.retain(|window| window != REACTIONS_WINDOW);
+        self.root.event(&mut UIEvent::Core(Event::Close(
+            REACTIONS_WINDOW.to_string(),
+        )));
+
+        let previous = picker
+            .previous
+            .filter(|previous| self.windows.contains(previous))
+            .or_else(|| self.windows.first().cloned());
+        if let Some(previous) = &previous {
+            self.change_window(previous);
+        }
+
+        if let (true, Some(emoji)) = (validate, emoji) {
+            if let Err(err) = ReactionsMod::react(aparte, &picker.account, &picker.message, &emoji)
+            {
+                crate::error!(aparte, err, "Cannot react to message");
+            } else if let Some(previous) = previous {
+                self.pending_replies.remove(&previous);
+            }
+        }
+    }
+
+    /// Keys typed while the reaction picker is open
+    fn reaction_picker_key(&mut self, aparte: &mut Aparte, key: &Key) {
+        let (dx, dy) = match key {
+            Key::Char('\n') => return self.close_reaction_picker(aparte, true),
+            Key::Esc | Key::Alt('+') => return self.close_reaction_picker(aparte, false),
+            Key::Left => (-1, 0),
+            Key::Right => (1, 0),
+            Key::Up => (0, -1),
+            Key::Down => (0, 1),
+            _ => return,
+        };
+        self.root.event(&mut UIEvent::MoveReaction(dx, dy));
+    }
+
+    fn add_privacy_window(&mut self) {
+        let privacy = TableView::<UIEvent, Stdout>::new(vec!["", "Kind", "Jid", "Setting", "Undo"])
+            .with_event(|view, event| match event {
+                UIEvent::PrivacyOverview(rows, selected) => {
+                    view.set_rows(rows.clone());
+                    view.scroll_to(*selected);
+                }
+                UIEvent::Core(Event::Key(Key::PageUp)) => {
+                    view.page_up();
+                }
+                UIEvent::Core(Event::Key(Key::PageDown)) => {
+                    view.page_down();
+                }
+                _ => {}
+            });
+
+        self.add_window(PRIVACY_WINDOW.to_string(), Box::new(privacy));
+    }
+
+    /// Open the privacy overview listing the given settings
+    pub fn show_privacy(&mut self, entries: Vec<PrivacyEntry>) {
+        if !self.windows.iter().any(|window| window == PRIVACY_WINDOW) {
+            self.add_privacy_window();
+        }
+        self.privacy = entries;
+        self.privacy_selected = 0;
+        self.render_privacy();
+        self.change_window(PRIVACY_WINDOW);
+    }
+
+    fn render_privacy(&mut self) {
+        let rows = self
+            .privacy
+            .iter()
+            .enumerate()
+            .map(|(position, entry)| {
+                let marker = match position == self.privacy_selected {
+                    true => ">",
+                    false => "",
+                };
+                vec![
+                    marker.to_string(),
+                    entry.kind.clone(),
+                    entry.jid.clone(),
+                    entry.setting.clone(),
+                    entry.undo.clone(),
+                ]
+            })
+            .collect();
+        self.root
+            .event(&mut UIEvent::PrivacyOverview(rows, self.privacy_selected));
+    }
+
+    fn add_disco_window(&mut self) {
+        let disco = TableView::<UIEvent, Stdout>::new(vec!["", "Name", "Jid", "Node", "Kind"])
+            .with_event(|view, event| match event {
+                UIEvent::DiscoTree(rows, selected) => {
+                    view.set_rows(rows.clone());
+                    view.scroll_to(*selected);
+                }
+                UIEvent::Core(Event::Key(Key::PageUp)) => {
+                    view.page_up();
+                }
+                UIEvent::Core(Event::Key(Key::PageDown)) => {
+                    view.page_down();
+                }
+                _ => {}
+            });
+
+        self.add_window(DISCO_WINDOW.to_string(), Box::new(disco));
+    }
+
+    /// Start browsing from the given entity
+    fn reset_disco(&mut self, account: &Account, jid: &Jid, node: &Option<String>) {
+        if !self.windows.iter().any(|window| window == DISCO_WINDOW) {
+            self.add_disco_window();
+        }
+        self.disco = vec![DiscoEntry {
+            account: account.clone(),
+            jid: jid.clone(),
+            node: node.clone(),
+            name: jid.to_string(),
+            kind: String::new(),
+            depth: 0,
+            expanded: false,
+            feature: false,
+        }];
+        self.disco_selected = 0;
+        self.change_window(DISCO_WINDOW);
+    }
+
+    /// List the items and features of an explored entity below it
+    fn expand_disco(
+        &mut self,
+        account: &Account,
+        jid: &Jid,
+        node: &Option<String>,
+        identities: &[String],
+        features: &[String],
+        items: &[disco::Item],
+    ) {
+        let position = match self.disco.iter().position(|entry| {
+            !entry.feature
+                && !entry.expanded
+                && &entry.account == account
+                && &entry.jid == jid
+                && &entry.node == node
+        }) {
+            Some(position) => position,
+            None => return,
+        };
+
+        let depth = self.disco[position].depth + 1;
+        self.disco[position].kind = identities.join(", ");
+        self.disco[position].expanded = true;
+        let items = items.iter().map(|item| DiscoEntry {
+            account: account.clone(),
+            jid: item.jid.clone(),
+            node: item.node.clone(),
+            name: item.name.clone().unwrap_or_else(|| item.jid.to_string()),
+            kind: String::new(),
+            depth,
+            expanded: false,
+            feature: false,
+        });
+        let features = features.iter().map(|feature| DiscoEntry {
+            account: account.clone(),
+            jid: jid.clone(),
+            node: node.clone(),
+            name: feature.clone(),
+            kind: String::from("feature"),
+            depth,
+            expanded: false,
+            feature: true,
+        });
+        let children = items.chain(features).collect::<Vec<_>>();
+        self.disco.splice(position + 1..position + 1, children);
+        self.render_disco();
+    }
+
+    fn render_disco(&mut self) {
+        let rows = self
+            .disco
+            .iter()
+            .enumerate()
+            .map(|(position, entry)| {
+                let marker = match position == self.disco_selected {
+                    true => ">",
+                    false => "",
+                };
+                let fold = match (entry.feature, entry.expanded) {
+                    (true, _) => "·",
+                    (false, true) => "▾",
+                    (false, false) => "▸",
+                };
+                let (jid, node) = match entry.feature {
+                    true => (String::new(), String::new()),
+                    false => (
+                        entry.jid.to_string(),
+                        entry.node.clone().unwrap_or_default(),
+                    ),
+                };
+                vec![
+                    marker.to_string(),
+                    format!(
+                        "{}{fold} {}",
+                        "  ".repeat(entry.depth),
+                        terminus::clean(&entry.name)
+                    ),
+                    jid,
+                    terminus::clean(&node),
+                    terminus::clean(&entry.kind),
+                ]
+            })
+            .collect();
+        self.root
+            .event(&mut UIEvent::DiscoTree(rows, self.disco_selected));
+    }
+
+    /// Keys typed in the disco browser while the input is empty
+    fn disco_key(&mut self, aparte: &mut Aparte, key: &Key) {
+        match key {
+            Key::Up => self.disco_selected = self.disco_selected.saturating_sub(1),
+            Key::Down => {
+                self.disco_selected =
+                    cmp::min(self.disco_selected + 1, self.disco.len().saturating_sub(1))
+            }
+            Key::Char('\n') => match self.disco.get_mut(self.disco_selected) {
+                Some(entry) if entry.feature => {}
+                Some(entry) if entry.expanded => {
+                    // Fold the entry by removing everything below it
+                    entry.expanded = false;
+                    let depth = entry.depth;
+                    let start = self.disco_selected + 1;
+                    let end = self.disco[start..]
+                        .iter()
+                        .position(|entry| entry.depth <= depth)
+                        .map_or(self.disco.len(), |end| start + end);
+                    self.disco.drain(start..end);
+                }
+                Some(entry) => DiscoMod::browse(
+                    aparte,
+                    &entry.account,
+                    &entry.jid,
+                    entry.node.clone(),
+                    false,
+                ),
+                None => {}
+            },
+            _ => {}
+        }
+        self.render_disco();
+    }
+
+    /// Insert buffered archived messages in their windows, in one pass per account
+    /// Whether an archived channel message is newer than the last one read in its window and
+    /// was not counted yet, backfilled history overlapping what was received live
+    fn is_unread_archive(&mut self, window: &str, message: &VersionedXmppMessage) -> bool {
+        match self.read_markers.get(window) {
+            Some(read_at) if message.get_original_timestamp() > read_at => self
+                .unread_ids
+                .entry(window.to_string())
+                .or_default()
+                .insert(message.id.clone()),
+            _ => false,
+        }
+    }
+
+    fn flush_backfill(&mut self) {
+        let mut accounts: Vec<(Option<Account>, Vec<Message>)> = Vec::new();
+        for (account, message) in self.backfill.drain(..) {
+            match accounts
+                .iter_mut()
+                .find(|(existing, _)| *existing == account)
+            {
+                Some((_, messages)) => messages.push(message),
+                None => accounts.push((account, vec![message])),
+            }
+        }
+        for (account, messages) in accounts {
+            self.root.event(&mut UIEvent::Messages(account, messages));
+        }
+    }
+
+    fn add_form_window(&mut self, form: DataForm) {
+        let form = FormView::<UIEvent, Stdout>::new(form).with_event(|view, event| match event {
+            UIEvent::Core(Event::ShowForm { form, .. }) => view.set_form(form.clone()),
+            UIEvent::FormKey(key) => match key {
+                Key::Up | Key::BackTab => view.previous(),
+                Key::Down | Key::Char('\t') => view.next(),
+                Key::Left => view.left(),
+                Key::Right => view.right(),
+                Key::Backspace => view.backspace(),
+                Key::Char(c) => view.key(*c),
+                _ => {}
+            },
+            UIEvent::SubmitForm(result) => {
+                result.replace(view.validate());
             }
-            Conversation::Channel(channel) => {
-                let mut layout = LinearLayout::<UIEvent, Stdout>::new(Orientation::Horizontal)
-                    .with_event(|layout, event| {
-                        for child in layout.iter_children_mut() {
-                            child.event(event);
-                        }
-                    });
+            _ => {}
+        });
 
-                let channel_for_event = channel.clone();
-                let chanwin = BufferedWin::<UIEvent, Stdout, Message>::new().with_event(
-                    move |view, event| {
-                        match event {
-                            UIEvent::Core(Event::Message(_, Message::Xmpp(message))) => {
-                                match message.direction {
-                                    // TODO check to == us
-                                    Direction::Incoming => {
-                                        if message.from == channel_for_event.jid {
-                                            view.insert(Message::Xmpp(message.clone()));
-                                        }
-                                    }
-                                    Direction::Outgoing => {
-                                        // TODO check from == us
-                                        if message.to == channel_for_event.jid {
-                                            view.insert(Message::Xmpp(message.clone()));
-                                        }
-                                    }
-                                }
-                            }
-                            UIEvent::Core(Event::Key(Key::PageUp)) => {
-                                if view.page_up() {
-                                    let from = view.first().map(|message| message.timestamp());
-                                    scheduler.schedule(Event::LoadChannelHistory {
-                                        account: channel_for_event.account.clone(),
-                                        jid: channel_for_event.jid.clone(),
-                                        from: from.cloned(),
-                                    });
-                                }
-                            }
-                            UIEvent::Core(Event::Key(Key::PageDown)) => {
-                                view.page_down();
-                            }
-                            _ => {}
-                        }
-                    },
-                );
-                layout.push(chanwin);
+        self.add_window(FORM_WINDOW.to_string(), Box::new(form));
+    }
 
-                let roster_jid = channel.jid.clone();
-                let roster =
-                    ListView::<UIEvent, Stdout, conversation::Role, conversation::Occupant>::new()
-                        .with_layouts(Layouts {
-                            width: Layout::wrap_content(),
-                            height: Layout::match_parent(),
-                        })
-                        .with_none_group()
-                        .with_unique_item()
-                        .with_sort_item()
-                        .with_event(move |view, event| match event {
-                            UIEvent::Core(Event::Occupant {
-                                conversation,
-                                occupant,
-                                ..
-                            }) => {
-                                if roster_jid == *conversation {
-                                    view.insert(occupant.clone(), Some(occupant.role));
-                                }
-                            }
-                            _ => {}
-                        });
-                layout.push(roster);
+    /// Keys typed while a data form is displayed, enter submitting it and escape canceling it
+    fn form_key(&mut self, aparte: &mut Aparte, key: &Key) {
+        match key {
+            Key::Char('\n') => {
+                let result = Rc::new(RefCell::new(None));
+                self.root
+                    .event(&mut UIEvent::SubmitForm(Rc::clone(&result)));
+                let form = result.borrow_mut().take();
+                if let Some(form) = form {
+                    let id = self.form.take().unwrap();
+                    aparte.schedule(Event::FormSubmit {
+                        id,
+                        form: Some(form),
+                    });
+                    aparte.schedule(Event::Close(FORM_WINDOW.to_string()));
+                }
+            }
+            Key::Esc => {
+                let id = self.form.take().unwrap();
+                aparte.schedule(Event::FormSubmit { id, form: None });
+                aparte.schedule(Event::Close(FORM_WINDOW.to_string()));
+            }
+            key => self.root.event(&mut UIEvent::FormKey(*key)),
+        }
+    }
 
-                self.add_window(channel.get_name(), Box::new(layout));
-                self.conversations
-                    .insert(channel.get_name(), conversation.clone());
+    fn input_is_empty(&mut self) -> bool {
+        let result = Rc::new(RefCell::new(None));
+        self.root.event(&mut UIEvent::GetInput(Rc::clone(&result)));
+        let empty = match result.borrow().as_ref() {
+            Some((raw_buf, _, password)) => !password && raw_buf.is_empty(),
+            None => false,
+        };
+        empty
+    }
+
+    /// Keys typed in the privacy overview while the input is empty
+    fn privacy_key(&mut self, aparte: &mut Aparte, key: &Key) {
+        match key {
+            Key::Up => self.privacy_selected = self.privacy_selected.saturating_sub(1),
+            Key::Down => {
+                self.privacy_selected = cmp::min(
+                    self.privacy_selected + 1,
+                    self.privacy.len().saturating_sub(1),
+                )
             }
+            Key::Char('\n') if self.privacy_selected < self.privacy.len() => {
+                // The setting is reverted by running its undo command as if typed
+                let entry = self.privacy.remove(self.privacy_selected);
+                aparte.schedule(Event::RawCommand(
+                    None,
+                    PRIVACY_WINDOW.to_string(),
+                    entry.undo,
+                ));
+                self.privacy_selected =
+                    cmp::min(self.privacy_selected, self.privacy.len().saturating_sub(1));
+            }
+            _ => {}
         }
+        self.render_privacy();
     }
 
     fn add_window(&mut self, name: String, window: Box<dyn View<UIEvent, Stdout>>) {
@@ -959,11 +4134,180 @@ impl UIMod {
     pub fn current_window(&self) -> Option<&String> {
         self.current_window.as_ref()
     }
+
+    /// Conversation displayed in a window, none for other windows such as the console
+    pub fn window_conversation(&self, window: &str) -> Option<&Conversation> {
+        self.conversations.get(window)
+    }
+
+    /// Account and conversation of the current window, windows of conversations on other
+    /// accounts being named after the account as well
+    pub fn current_conversation(&self) -> anyhow::Result<(Account, Conversation)> {
+        let conversation = self
+            .current_window()
+            .and_then(|window| self.window_conversation(window))
+            .ok_or_else(|| anyhow::anyhow!("Current window is not a conversation"))?;
+        Ok((conversation.get_account().clone(), conversation.clone()))
+    }
+
+    /// Whether the current window displays the conversation with the jid on the account
+    pub fn is_current_conversation(&self, account: &Account, jid: &BareJid) -> bool {
+        matches!(
+            self.current_conversation(),
+            Ok((current, conversation)) if &current == account && conversation.get_jid() == jid
+        )
+    }
+
+    /// Account and jid of a conversation given by its window name or by its jid on the current
+    /// account, the conversation of the current window when none is given
+    pub fn conversation_target(
+        &self,
+        aparte: &Aparte,
+        target: Option<String>,
+    ) -> anyhow::Result<(Account, BareJid)> {
+        let target = match target {
+            Some(target) => target,
+            None => {
+                let (account, conversation) = self.current_conversation()?;
+                return Ok((account, conversation.get_jid().clone()));
+            }
+        };
+        if let Some(conversation) = self.window_conversation(&target) {
+            return Ok((
+                conversation.get_account().clone(),
+                conversation.get_jid().clone(),
+            ));
+        }
+        // Other windows such as the console have names that parse as jids
+        if self.get_windows().contains(&target) {
+            anyhow::bail!("{target} is not a conversation");
+        }
+        let account = aparte
+            .current_account()
+            .ok_or_else(|| anyhow::anyhow!("No connection found"))?;
+        let jid = BareJid::from_str(&target)
+            .map_err(|_| anyhow::anyhow!("Invalid conversation {target}"))?;
+        Ok((account, jid))
+    }
+
+    /// Whether the last message of a conversation window is visible
+    pub fn scrolled_to_bottom(&mut self, window: &str) -> bool {
+        let conversation = match self.conversations.get(window) {
+            Some(conversation) => conversation.clone(),
+            None => return false,
+        };
+
+        let result = Rc::new(RefCell::new(None));
+        self.root.event(&mut UIEvent::GetReadPosition(
+            conversation.get_jid().clone(),
+            Rc::clone(&result),
+        ));
+
+        let position = result.borrow_mut().take();
+        matches!(position, Some((0, _)))
+    }
+
+    /// Fill the input with the correction of the last message sent in the current window,
+    /// return false when there is none
+    fn edit_last_message(&mut self, aparte: &mut Aparte) -> bool {
+        let Some(conversation) = self
+            .current_window
+            .as_ref()
+            .and_then(|window| self.conversations.get(window))
+        else {
+            return false;
+        };
+
+        let message = aparte.get_mod::<MessagesMod>().find_last_sent(
+            conversation.get_account(),
+            conversation.get_jid(),
+            None,
+        );
+        match message {
+            Some(message) if !message.retracted => {
+                let args = [String::from("correct"), message.get_last_body().to_string()];
+                let buf = format!("/{}", Command::assemble_args(&args));
+                let cursor = Cursor::new(buf.chars().count());
+                self.root
+                    .event(&mut UIEvent::Core(Event::Completed(buf, cursor)));
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Select the message the next one of the current window replies to, going back from the
+    /// most recent one when older, forward otherwise until the reply is cancelled
+    fn pick_reply(&mut self, aparte: &mut Aparte, older: bool) {
+        let Some(window) = self.current_window.clone() else {
+            return;
+        };
+        let Some(conversation) = self.conversations.get(&window) else {
+            return;
+        };
+
+        let current = self
+            .pending_replies
+            .get(&window)
+            .map(|reply| reply.id.clone());
+        let picked = aparte.get_mod::<MessagesMod>().adjacent(
+            conversation.get_account(),
+            conversation.get_jid(),
+            current.as_deref(),
+            older,
+        );
+        match picked {
+            Some(message) => {
+                let author = terminus::clean(&match &message.type_ {
+                    XmppMessageType::Channel => match &message.from_full {
+                        Jid::Full(from) => from.resource().to_string(),
+                        Jid::Bare(from) => from.to_string(),
+                    },
+                    XmppMessageType::Chat => message.from.to_string(),
+                });
+                let excerpt = word::excerpt(message.get_last_body(), 50);
+                crate::info!(aparte, "Replying to {author}: {excerpt}");
+                self.pending_replies.insert(window, Reply::from(&message));
+            }
+            None if !older && self.pending_replies.remove(&window).is_some() => {
+                crate::info!(aparte, "Reply cancelled");
+            }
+            None => {}
+        }
+    }
+
+    pub fn set_pending_markup(
+        &mut self,
+        window: &str,
+        markup: PendingMarkup,
+    ) -> anyhow::Result<()> {
+        if !self.conversations.contains_key(window) {
+            anyhow::bail!("Can't use markup in non XMPP window");
+        }
+        self.pending_markups.insert(window.to_string(), markup);
+        Ok(())
+    }
 }
 
 impl ModTrait for UIMod {
-    fn init(&mut self, _aparte: &mut Aparte) -> Result<(), ()> {
+    fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
+        aparte.add_command(code::new());
+        aparte.add_command(spoiler::new());
+        aparte.add_command(mentions::new());
+        aparte.add_command(roster::new());
+        aparte.add_command(search::new());
+        aparte.add_command(goto::new());
+        aparte.add_command(console::new());
+
+        if let Some(style) = aparte.config.bell_style.clone() {
+            match BellStyle::from_str(&style) {
+                Ok(style) => self.bell_style = style,
+                Err(err) => crate::error!(aparte, err, "Invalid bell_style in config"),
+            }
+        }
+
         vprint!(&mut self.screen, "{}", termion::clear::All);
+        vprint!(&mut self.screen, "{}", ENABLE_FOCUS_REPORTING);
 
         let (width, height) = termion::terminal_size().unwrap();
         let mut dimension = Dimension::new();
@@ -979,28 +4323,49 @@ impl ModTrait for UIMod {
                 }
             },
         );
-        console.push(
-            BufferedWin::<UIEvent, Stdout, Message>::new().with_event(|view, event| match event {
-                UIEvent::Core(Event::Message(_, Message::Log(message))) => {
-                    view.insert(Message::Log(message.clone()));
-                }
-                UIEvent::Core(Event::Key(Key::PageUp)) => {
-                    view.page_up();
-                }
-                UIEvent::Core(Event::Key(Key::PageDown)) => {
-                    view.page_down();
-                }
-                _ => {}
-            }),
-        );
-        let roster = ListView::<UIEvent, Stdout, contact::Group, RosterItem>::new()
+        console.push(self.message_win().with_event(|view, event| match event {
+            UIEvent::Core(Event::Message(_, Message::Log(message))) => {
+                view.insert(Message::Log(message.clone()));
+            }
+            UIEvent::Core(Event::Key(Key::PageUp)) => {
+                view.page_up();
+            }
+            UIEvent::Core(Event::Key(Key::PageDown)) => {
+                view.page_down();
+            }
+            UIEvent::Search(text, found) => {
+                *found.borrow_mut() = view.search(|message| message_contains(message, text));
+            }
+            UIEvent::Jump(date, found) => {
+                *found.borrow_mut() = view.jump(|message| message.timestamp() >= date);
+            }
+            UIEvent::ConsoleLevel(level) => {
+                let level = *level;
+                view.set_filter(move |message| match message {
+                    Message::Log(message) => message.level <= level,
+                    Message::Xmpp(_) => true,
+                });
+            }
+            _ => {}
+        }));
+        let mut contacts = RosterContacts {
+            grouping: self.roster_grouping,
+            contacts: HashMap::new(),
+            activity: HashMap::new(),
+        };
+        let avatars = self.roster_avatars;
+        let mut roster = ListView::<UIEvent, Stdout, contact::Group, RosterItem>::new()
             .with_layouts(Layouts {
                 width: Layout::wrap_content().with_relative_max(0.3),
                 height: Layout::match_parent(),
             })
             .with_none_group()
             .with_sort_item()
-            .with_event(|view, event| match event {
+            .with_format_item(move |item| match item.avatar() {
+                Some(avatar) if avatars => format!("{avatar}{item}"),
+                _ => item.to_string(),
+            })
+            .with_event(move |view, event| match event {
                 UIEvent::Core(Event::Connected(_, _)) => {
                     view.add_group(contact::Group(String::from("Windows")));
                     view.add_group(contact::Group(String::from("Contacts")));
@@ -1008,15 +4373,54 @@ impl ModTrait for UIMod {
                 }
                 UIEvent::Core(Event::Contact(_, contact))
                 | UIEvent::Core(Event::ContactUpdate(_, contact)) => {
-                    if !contact.groups.is_empty() {
-                        for group in &contact.groups {
-                            view.insert(RosterItem::Contact(contact.clone()), Some(group.clone()));
+                    contacts.update(view, contact);
+                }
+                UIEvent::Core(Event::Message(_, Message::Xmpp(message)))
+                    if message.type_ == XmppMessageType::Chat =>
+                {
+                    let jid = match message.direction {
+                        Direction::Incoming => &message.from,
+                        Direction::Outgoing => &message.to,
+                    };
+                    contacts.touch(view, jid, message.get_original_timestamp());
+                }
+                UIEvent::Messages(_, messages) => {
+                    for message in messages {
+                        if let Message::Xmpp(message) = message {
+                            if message.type_ == XmppMessageType::Chat {
+                                let jid = match message.direction {
+                                    Direction::Incoming => &message.from,
+                                    Direction::Outgoing => &message.to,
+                                };
+                                contacts.touch(view, jid, message.get_original_timestamp());
+                            }
                         }
-                    } else {
-                        let group = contact::Group(String::from("Contacts"));
-                        view.insert(RosterItem::Contact(contact.clone()), Some(group));
                     }
                 }
+                UIEvent::Core(Event::Tick) => {
+                    if contacts.grouping == RosterGrouping::Activity {
+                        contacts.regroup(view);
+                    }
+                    // Idle durations change along time
+                    view.refresh();
+                }
+                UIEvent::RosterGrouping(grouping) => {
+                    contacts.grouping = *grouping;
+                    contacts.regroup(view);
+                }
+                UIEvent::GetRosterGroups(groups) => {
+                    *groups.borrow_mut() = view.groups().map(|group| group.0.clone()).collect();
+                }
+                UIEvent::GetRosterGroupOf(jid, result) => {
+                    let of_contact = jid.as_ref().and_then(|jid| contacts.group_of(jid));
+                    *result.borrow_mut() = Some(match of_contact {
+                        Some(group) => group.0,
+                        None => String::from("Windows"),
+                    });
+                }
+                UIEvent::CollapseRosterGroup(group, collapsed) => {
+                    view.set_collapsed(contact::Group(group.clone()), *collapsed);
+                }
                 UIEvent::Core(Event::Bookmark(_, bookmark)) => {
                     let group = contact::Group(String::from("Bookmarks"));
                     view.insert(RosterItem::Bookmark(bookmark.clone()), Some(group));
@@ -1052,26 +4456,47 @@ impl ModTrait for UIMod {
     }
 
     fn on_event(&mut self, aparte: &mut Aparte, event: &Event) {
+        self.on_owned_event(aparte, event.clone());
+    }
+
+    /// Events are moved into views and back, instead of cloned for them
+    fn on_owned_event(&mut self, aparte: &mut Aparte, event: Event) -> Event {
+        let mut ui_event = UIEvent::Core(event);
+        self.handle_event(aparte, &mut ui_event);
+        match ui_event {
+            UIEvent::Core(event) => event,
+            _ => unreachable!("views must not replace core events"),
+        }
+    }
+}
+
+impl UIMod {
+    fn handle_event(&mut self, aparte: &mut Aparte, ui_event: &mut UIEvent) {
+        let event = match &*ui_event {
+            UIEvent::Core(event) => event,
+            _ => return,
+        };
         let mut force_render = false;
+        let previous_window = self.current_window.clone();
 
         match event {
             Event::ReadPassword(command) => {
                 self.password_command = Some(command.clone());
-                self.root
-                    .event(&mut UIEvent::Core(Event::ReadPassword(command.clone())));
-            }
-            Event::Connected(account, jid) => {
-                self.root.event(&mut UIEvent::Core(Event::Connected(
-                    account.clone(),
-                    jid.clone(),
-                )));
+                self.root.event(ui_event);
             }
+            Event::Connected(..) => self.root.event(ui_event),
             Event::Message(account, message) => {
                 match message {
                     Message::Xmpp(message) => {
-                        let window_name = match message.direction {
-                            Direction::Incoming => message.from.to_string(),
-                            Direction::Outgoing => message.to.to_string(),
+                        let contact = match message.direction {
+                            Direction::Incoming => &message.from,
+                            Direction::Outgoing => &message.to,
+                        };
+                        let window_name = match (&message.type_, account) {
+                            (XmppMessageType::Chat, Some(account)) => {
+                                self.chat_window_name(account, contact)
+                            }
+                            _ => contact.to_string(),
                         };
 
                         if !self.conversations.contains_key(&window_name) {
@@ -1093,6 +4518,7 @@ impl ModTrait for UIMod {
                                         nick: account.as_ref().unwrap().resource().to_string(),
                                         name: None,
                                         occupants: HashMap::new(),
+                                        nick_aliases: HashMap::new(),
                                     }),
                                     Direction::Outgoing => Conversation::Channel(Channel {
                                         account: account.clone().unwrap(),
@@ -1100,6 +4526,7 @@ impl ModTrait for UIMod {
                                         nick: account.as_ref().unwrap().resource().to_string(),
                                         name: None,
                                         occupants: HashMap::new(),
+                                        nick_aliases: HashMap::new(),
                                     }),
                                 },
                             };
@@ -1110,7 +4537,7 @@ impl ModTrait for UIMod {
                         if message.direction == Direction::Incoming {
                             let mut window = None;
                             for existing in &self.windows {
-                                if &message.from.to_string() == existing
+                                if &window_name == existing
                                     && Some(existing) != self.current_window.as_ref()
                                 {
                                     window = Some(existing.clone());
@@ -1119,8 +4546,27 @@ impl ModTrait for UIMod {
 
                             if window != self.current_window {
                                 if let Some(window) = window {
-                                    let important = self.unread_windows.entry(window).or_insert(0);
-                                    *important += 1;
+                                    match message.type_ {
+                                        XmppMessageType::Channel if message.archive => {
+                                            if self.is_unread_archive(&window, message) {
+                                                *self
+                                                    .unread_windows
+                                                    .entry(window.clone())
+                                                    .or_insert(0) += 1;
+                                                self.root.event(&mut UIEvent::Unread(window, 1));
+                                            }
+                                        }
+                                        XmppMessageType::Channel => {
+                                            self.unread_ids
+                                                .entry(window.clone())
+                                                .or_default()
+                                                .insert(message.id.clone());
+                                            *self.unread_windows.entry(window).or_insert(0) += 1;
+                                        }
+                                        XmppMessageType::Chat => {
+                                            *self.unread_windows.entry(window).or_insert(0) += 1;
+                                        }
+                                    }
                                 }
                             }
                         }
@@ -1128,14 +4574,34 @@ impl ModTrait for UIMod {
                     Message::Log(_message) => {}
                 };
 
-                self.root.event(&mut UIEvent::Core(Event::Message(
-                    account.clone(),
-                    message.clone(),
-                )));
+                match message {
+                    // Inserted along with the rest of the page once the request is over
+                    Message::Xmpp(xmpp) if xmpp.archive => {
+                        self.backfill.push((account.clone(), message.clone()))
+                    }
+                    _ => self.root.event(ui_event),
+                }
+            }
+            Event::StorageReady => match aparte.storage.get_collapsed_roster_groups() {
+                Ok(groups) => {
+                    for group in groups {
+                        self.root
+                            .event(&mut UIEvent::CollapseRosterGroup(group.clone(), true));
+                        self.collapsed_groups.insert(group);
+                    }
+                }
+                Err(err) => log::warn!("Cannot load collapsed roster groups: {err}"),
+            },
+            Event::HistoryLoaded { .. } | Event::HistoryImported { .. } | Event::Tick => {
+                self.flush_backfill();
+                if let Event::Tick = event {
+                    // Positions of windows left during the last minute, written at once
+                    self.save_read_positions(aparte, None);
+                }
+                self.root.event(ui_event);
             }
             Event::Chat { account, contact } => {
-                // Should we store account association?
-                let win_name = contact.to_string();
+                let win_name = self.chat_window_name(account, contact);
                 if !self.windows.contains(&win_name) {
                     self.add_conversation(
                         aparte,
@@ -1154,7 +4620,11 @@ impl ModTrait for UIMod {
             } => {
                 let bare: BareJid = channel.to_bare();
                 let win_name = bare.to_string();
-                if !self.windows.contains(&win_name) {
+                if let Some(Conversation::Channel(existing)) = self.conversations.get_mut(&win_name)
+                {
+                    // Joined again, possibly with another nickname
+                    existing.nick = channel.resource().to_string();
+                } else if !self.windows.contains(&win_name) {
                     self.add_conversation(
                         aparte,
                         Conversation::Channel(Channel {
@@ -1163,6 +4633,7 @@ impl ModTrait for UIMod {
                             nick: channel.resource().to_string(),
                             name: None, // TODO use name from bookmark
                             occupants: HashMap::new(),
+                            nick_aliases: HashMap::new(),
                         }),
                     );
                 }
@@ -1170,6 +4641,102 @@ impl ModTrait for UIMod {
                     self.change_window(&win_name);
                 }
             }
+            Event::OccupantRenamed {
+                conversation,
+                previous,
+                occupant,
+                ..
+            } => {
+                if let Some(Conversation::Channel(channel)) =
+                    self.conversations.get_mut(&conversation.to_string())
+                {
+                    channel.rename_occupant(previous, &occupant.nick);
+                }
+                self.root.event(ui_event);
+            }
+            Event::EventTrace(_) => {
+                if !self.windows.iter().any(|window| window == EVENTS_WINDOW) {
+                    self.add_events_window();
+                }
+                self.root.event(ui_event);
+                self.change_window(EVENTS_WINDOW);
+            }
+            Event::ChannelList {
+                account, channels, ..
+            } => {
+                if !self.windows.iter().any(|window| window == CHANNELS_WINDOW) {
+                    self.add_channels_window();
+                }
+                let mut channels = channels.clone();
+                channels.sort_by(|a, b| b.occupants.cmp(&a.occupants));
+                self.channels = Some((account.clone(), channels));
+                self.channels_selected = 0;
+                self.render_channels();
+                self.change_window(CHANNELS_WINDOW);
+            }
+            Event::Mention { .. } => {
+                // Collect mentions even before the window is first displayed
+                if !self.windows.iter().any(|window| window == MENTIONS_WINDOW) {
+                    self.add_mentions_window();
+                }
+                self.root.event(ui_event);
+            }
+            Event::DiscoBrowse {
+                account,
+                jid,
+                node,
+                root,
+                identities,
+                features,
+                items,
+            } => {
+                if *root {
+                    self.reset_disco(account, jid, node);
+                }
+                self.expand_disco(account, jid, node, identities, features, items);
+            }
+            Event::Pins { .. } => {
+                if !self.windows.iter().any(|window| window == PINS_WINDOW) {
+                    self.add_pins_window();
+                }
+                self.root.event(ui_event);
+                self.change_window(PINS_WINDOW);
+            }
+            Event::PubSubLog(_) => {
+                if !self.windows.iter().any(|window| window == PUBSUB_WINDOW) {
+                    self.add_pubsub_window();
+                }
+                self.root.event(ui_event);
+            }
+            Event::ActivityLog { .. } => {
+                if !self.windows.iter().any(|window| window == ACTIVITY_WINDOW) {
+                    self.add_activity_window();
+                }
+                self.root.event(ui_event);
+                self.change_window(ACTIVITY_WINDOW);
+            }
+            Event::RoomMembers { .. } => {
+                if !self.windows.iter().any(|window| window == MEMBERS_WINDOW) {
+                    self.add_members_window();
+                }
+                self.root.event(ui_event);
+                self.change_window(MEMBERS_WINDOW);
+            }
+            Event::ShowForm { id, form } => {
+                // A form left unanswered is canceled
+                if let Some(previous) = self.form.replace(id.clone()) {
+                    aparte.schedule(Event::FormSubmit {
+                        id: previous,
+                        form: None,
+                    });
+                }
+                if self.windows.iter().any(|window| window == FORM_WINDOW) {
+                    self.root.event(ui_event);
+                } else {
+                    self.add_form_window(form.clone());
+                }
+                self.change_window(FORM_WINDOW);
+            }
             Event::Win(window) => {
                 if self.windows.contains(window) {
                     self.change_window(window);
@@ -1178,27 +4745,67 @@ impl ModTrait for UIMod {
                 }
             }
             Event::WindowChange => {
-                let (width, height) = termion::terminal_size().unwrap();
-                let mut dimension = Dimension::new();
-                self.root.measure(&mut dimension, Some(width), Some(height));
-                self.root.layout(&mut dimension, 1, 1);
-                self.root.render(&dimension, &mut self.screen);
-                self.dimension = Some(dimension);
+                // Coalesce resize storms into a single relayout
+                self.resized = true;
             }
             Event::Close(window) => {
                 if window != "console" {
+                    if window == FORM_WINDOW {
+                        if let Some(id) = self.form.take() {
+                            aparte.schedule(Event::FormSubmit { id, form: None });
+                        }
+                    }
+                    self.remember_read_position(window);
+                    self.save_read_positions(aparte, Some(window));
                     self.windows.retain(|win| win != window);
                     self.unread_windows.remove(window);
+                    self.unread_ids.remove(window);
                     if Some(window) == self.current_window.as_ref() {
                         let current = self.windows.first().cloned();
                         if let Some(current) = current {
                             self.change_window(&current);
                         }
                     }
-                    self.root
-                        .event(&mut UIEvent::Core(Event::Close(window.clone())))
+                    self.root.event(ui_event)
                 }
             }
+            Event::Key(key) if self.quick_switch.is_some() => self.quick_switch_key(aparte, key),
+            Event::Key(key) if self.command_palette.is_some() => {
+                self.command_palette_key(aparte, key)
+            }
+            Event::Key(key) if self.reaction_picker.is_some() => {
+                self.reaction_picker_key(aparte, key)
+            }
+            Event::Key(
+                key @ (Key::Up
+                | Key::Down
+                | Key::Left
+                | Key::Right
+                | Key::BackTab
+                | Key::Backspace
+                | Key::Esc
+                | Key::Char(_)),
+            ) if self.current_window.as_deref() == Some(FORM_WINDOW) && self.form.is_some() => {
+                self.form_key(aparte, key)
+            }
+            Event::Key(key @ (Key::Up | Key::Down | Key::Char('\n')))
+                if self.current_window.as_deref() == Some(PRIVACY_WINDOW)
+                    && self.input_is_empty() =>
+            {
+                self.privacy_key(aparte, key)
+            }
+            Event::Key(key @ (Key::Up | Key::Down | Key::Char('\n')))
+                if self.current_window.as_deref() == Some(CHANNELS_WINDOW)
+                    && self.input_is_empty() =>
+            {
+                self.channels_key(aparte, key)
+            }
+            Event::Key(key @ (Key::Up | Key::Down | Key::Char('\n')))
+                if self.current_window.as_deref() == Some(DISCO_WINDOW)
+                    && self.input_is_empty() =>
+            {
+                self.disco_key(aparte, key)
+            }
             Event::Key(key) => {
                 match key {
                     Key::Char('\t') => {
@@ -1242,7 +4849,9 @@ impl ModTrait for UIMod {
                             let mut command = self.password_command.take().unwrap();
                             command.args.push(raw_buf);
                             aparte.schedule(Event::Command(command));
-                        } else if raw_buf.starts_with('/') {
+                        } else if raw_buf.starts_with('/')
+                            || Command::split_account_prefix(&raw_buf).is_some()
+                        {
                             let window = self.current_window.clone().unwrap();
                             let account = match self.conversations.get(&window) {
                                 Some(Conversation::Chat(chat)) => Some(chat.account.clone()),
@@ -1254,61 +4863,98 @@ impl ModTrait for UIMod {
                             aparte.schedule(Event::RawCommand(account, window, raw_buf));
                         } else if !raw_buf.is_empty() {
                             if let Some(current_window) = self.current_window.clone() {
+                                let markup = self.pending_markups.remove(&current_window);
+                                let raw_buf = match markup {
+                                    Some(PendingMarkup::Code) => format!("```\n{raw_buf}\n```"),
+                                    _ => raw_buf,
+                                };
+                                let spoiler = match markup {
+                                    Some(PendingMarkup::Spoiler(hint)) => {
+                                        Some(hint.unwrap_or_default())
+                                    }
+                                    _ => None,
+                                };
+                                // Only the first chunk of a split message is a reply
+                                let mut reply = self.pending_replies.remove(&current_window);
+                                let oversized = self.oversized.take();
+                                let split = aparte.config.split_messages.unwrap_or(false);
+                                let chunks = match aparte.config.max_message_size {
+                                    // Give the message back, it is split when validated again
+                                    Some(limit)
+                                        if raw_buf.len() > limit
+                                            && !split
+                                            && oversized.as_deref() != Some(raw_buf.as_str()) =>
+                                    {
+                                        let count = word::split_at_words(&raw_buf, limit).len();
+                                        aparte.log(format!(
+                                            "Message of {} bytes is over the limit of {limit} bytes, press Enter again to send it in {count} messages",
+                                            raw_buf.len()
+                                        ));
+                                        let cursor = Cursor::new(raw_buf.chars().count());
+                                        self.oversized = Some(raw_buf.clone());
+                                        self.root.event(&mut UIEvent::Core(Event::Completed(
+                                            raw_buf, cursor,
+                                        )));
+                                        Vec::new()
+                                    }
+                                    Some(limit) if raw_buf.len() > limit => {
+                                        word::split_at_words(&raw_buf, limit)
+                                            .into_iter()
+                                            .map(String::from)
+                                            .collect()
+                                    }
+                                    _ => vec![raw_buf],
+                                };
                                 if let Some(conversation) = self.conversations.get(&current_window)
                                 {
-                                    match conversation {
-                                        Conversation::Chat(chat) => {
-                                            let account = &chat.account;
-                                            let us = account.clone().into();
-                                            let from: Jid = us;
-                                            let to: Jid = chat.contact.clone().into();
-                                            let id = Uuid::new_v4();
-                                            let timestamp = LocalTz::now().into();
-                                            let mut bodies = HashMap::new();
-                                            bodies.insert("".to_string(), raw_buf);
-                                            let message = Message::outgoing_chat(
-                                                id.to_string(),
-                                                timestamp,
-                                                &from,
-                                                &to,
-                                                &bodies,
-                                                false,
-                                            );
-                                            aparte.schedule(Event::SendMessage(
-                                                account.clone(),
-                                                message,
-                                            ));
-                                        }
-                                        Conversation::Channel(channel) => {
-                                            let account = &channel.account;
-                                            let us = account
-                                                .to_bare()
-                                                .with_resource_str(&channel.nick)
-                                                .unwrap(); // TODO avoid unwrap
-                                            let from: Jid = us.into();
-                                            let to: Jid = channel.jid.clone().into();
-                                            let id = Uuid::new_v4();
-                                            let timestamp = LocalTz::now().into();
-                                            let mut bodies = HashMap::new();
-                                            bodies.insert("".to_string(), raw_buf);
-                                            let message = Message::outgoing_channel(
-                                                id.to_string(),
-                                                timestamp,
-                                                &from,
-                                                &to,
-                                                &bodies,
-                                                false,
-                                            );
-                                            aparte.schedule(Event::SendMessage(
-                                                account.clone(),
-                                                message,
-                                            ));
-                                        }
+                                    for chunk in chunks {
+                                        Self::send_input(
+                                            aparte,
+                                            conversation,
+                                            chunk,
+                                            spoiler.clone(),
+                                            reply.take(),
+                                        );
                                     }
                                 }
                             }
                         }
                     }
+                    Key::Alt('s') => {
+                        if let Some(window) = self.current_window.as_ref() {
+                            if let Some(conversation) = self.conversations.get(window) {
+                                aparte.schedule(Event::RevealSpoilers {
+                                    account: conversation.get_account().clone(),
+                                    conversation: conversation.get_jid().clone(),
+                                    id: self
+                                        .pending_replies
+                                        .get(window)
+                                        .map(|reply| reply.id.clone()),
+                                });
+                            }
+                        }
+                    }
+                    Key::Alt('e') => {
+                        if let Some(window) = self.current_window.as_ref() {
+                            if let Some(conversation) = self.conversations.get(window) {
+                                aparte.schedule(Event::ExpandMessage {
+                                    account: conversation.get_account().clone(),
+                                    conversation: conversation.get_jid().clone(),
+                                    id: self
+                                        .pending_replies
+                                        .get(window)
+                                        .map(|reply| reply.id.clone()),
+                                });
+                            }
+                        }
+                    }
+                    Key::Up if self.input_is_empty() && self.edit_last_message(aparte) => {}
+                    Key::Alt('r') => self.pick_reply(aparte, true),
+                    Key::Alt('R') => self.pick_reply(aparte, false),
+                    Key::Alt('k') => self.open_quick_switch(aparte),
+                    Key::Alt('+') => self.open_reaction_picker(aparte),
+                    Key::Alt('x') => self.open_command_palette(aparte),
+                    Key::Alt('g') => self.toggle_current_roster_group(aparte),
                     Key::Alt('a') => {
                         if !self.unread_windows.is_empty() {
                             let next = {
@@ -1322,44 +4968,61 @@ impl ModTrait for UIMod {
                         }
                     }
                     _ => {
+                        let typed = matches!(key, Key::Char(_));
                         aparte.schedule(Event::ResetCompletion);
-                        self.root.event(&mut UIEvent::Core(Event::Key(*key)));
+                        self.root.event(ui_event);
+                        if typed {
+                            self.notify_composing(aparte);
+                        }
                     }
                 }
             }
-            Event::Completed(raw_buf, cursor) => {
-                self.root.event(&mut UIEvent::Core(Event::Completed(
-                    raw_buf.clone(),
-                    cursor.clone(),
-                )));
-            }
-            Event::Notification {
-                conversation,
-                important,
-            } => {
-                if *important && aparte.config.bell {
-                    vprint!(self.screen, "\x07");
-                }
-                self.root.event(&mut UIEvent::Core(Event::Notification {
-                    conversation: conversation.clone(),
-                    important: *important,
-                }));
+            Event::Completed(..) => self.root.event(ui_event),
+            Event::Bell(reason) => self.ring(aparte, *reason),
+            Event::Error(_) => {
+                self.ring(aparte, BellReason::Error);
+                self.root.event(ui_event);
             }
             Event::UIRender => {
                 log::debug!("Force render");
+                if matches!(self.flash_until, Some(until) if until <= Instant::now()) {
+                    self.flash_until = None;
+                    self.root.event(&mut UIEvent::Flash(false));
+                }
                 force_render = true;
             }
+            Event::Quit => {
+                if let Some(current) = self.current_window.clone() {
+                    self.remember_read_position(&current);
+                }
+                self.save_read_positions(aparte, None);
+                vprint!(&mut self.screen, "{}", DISABLE_FOCUS_REPORTING);
+                flush!(self.screen);
+            }
             // Forward all unknown events
-            event => self.root.event(&mut UIEvent::Core(event.clone())),
+            _ => self.root.event(ui_event),
+        }
+
+        // Remember where we left the window we are moving away from
+        if self.current_window != previous_window {
+            if let Some(previous) = previous_window {
+                self.remember_read_position(&previous);
+            }
+            if let Some(current) = &self.current_window {
+                self.unread_ids.remove(current);
+                aparte.schedule(Event::WindowChanged(current.clone()));
+            }
         }
 
         // Debounce rendering
-        if force_render || self.last_render.elapsed() > Duration::new(0, UI_DEBOUNCE_NS) {
+        if force_render || self.last_render.elapsed() > self.debounce {
             // Update rendering
-            if self.root.is_layout_dirty() {
+            if self.resized || self.root.is_layout_dirty() {
                 log::debug!("Render (saved {} rendering)", self.debounced);
+                self.adapt_debounce();
                 self.last_render = Instant::now();
                 self.debounced = 0;
+                self.resized = false;
 
                 let (width, height) = termion::terminal_size().unwrap();
                 let mut dimension = Dimension::new();
@@ -1368,14 +5031,17 @@ impl ModTrait for UIMod {
                 self.root.render(&dimension, &mut self.screen);
                 flush!(self.screen);
                 self.dimension = Some(dimension);
+                aparte.stats.record_render(self.last_render.elapsed());
             } else if self.root.is_dirty() {
                 log::debug!("Render (saved {} rendering)", self.debounced);
+                self.adapt_debounce();
                 self.last_render = Instant::now();
                 self.debounced = 0;
 
                 let dimension: &Dimension = self.dimension.as_ref().unwrap();
                 self.root.render(dimension, &mut self.screen);
                 flush!(self.screen);
+                aparte.stats.record_render(self.last_render.elapsed());
             }
         } else {
             log::debug!("Debounce rendering");
@@ -1383,8 +5049,9 @@ impl ModTrait for UIMod {
                 // Ensure we will render this debounced event right in time
                 Aparte::spawn({
                     let mut aparte = aparte.proxy();
+                    let debounce = self.debounce;
                     async move {
-                        thread::sleep(Duration::new(0, UI_DEBOUNCE_NS));
+                        thread::sleep(debounce);
                         aparte.schedule(Event::UIRender)
                     }
                 })
@@ -1405,6 +5072,12 @@ impl fmt::Display for UIMod {
     }
 }
 
+/// Reported by the terminal once focus reporting is enabled
+const FOCUS_IN: &[u8] = b"\x1b[I";
+const FOCUS_OUT: &[u8] = b"\x1b[O";
+const ENABLE_FOCUS_REPORTING: &str = "\x1b[?1004h";
+const DISABLE_FOCUS_REPORTING: &str = "\x1b[?1004l";
+
 struct TermionEventStream {
     channel: mpsc::Receiver<Result<u8, IoError>>,
     waker: Arc<AtomicWaker>,
@@ -1482,12 +5155,23 @@ impl Stream for TermionEventStream {
             Err(mpsc::TryRecvError::Disconnected) => return Poll::Ready(None),
         };
 
-        let mut iter = IterWrapper::new(&mut self.channel);
-        if let Ok(event) = termion_parse_event(byte, &mut iter) {
-            Poll::Ready(Some(event))
-        } else {
-            self.waker.register(cx.waker());
-            Poll::Pending
+        let mut sequence = vec![byte];
+        let parsed = {
+            let mut iter = IterWrapper::new(&mut self.channel).inspect(|byte| {
+                if let Ok(byte) = byte {
+                    sequence.push(*byte);
+                }
+            });
+            termion_parse_event(byte, &mut iter)
+        };
+        match parsed {
+            Ok(event) => Poll::Ready(Some(event)),
+            // Escape sequences termion doesn't know about, such as focus reports
+            Err(_) if byte == b'\x1b' => Poll::Ready(Some(TermionEvent::Unsupported(sequence))),
+            Err(_) => {
+                self.waker.register(cx.waker());
+                Poll::Pending
+            }
         }
     }
 }
@@ -1543,6 +5227,7 @@ impl Stream for EventStream {
                     Key::Right => Poll::Ready(Some(Event::Key(Key::Right))),
                     Key::Ctrl(c) => Poll::Ready(Some(Event::Key(Key::Ctrl(c)))),
                     Key::Alt(c) => Poll::Ready(Some(Event::Key(Key::Alt(c)))),
+                    Key::Esc => Poll::Ready(Some(Event::Key(Key::Esc))),
                     Key::PageUp => Poll::Ready(Some(Event::Key(Key::PageUp))),
                     Key::PageDown => Poll::Ready(Some(Event::Key(Key::PageDown))),
                     _ => {
@@ -1555,10 +5240,14 @@ impl Stream for EventStream {
                 self.inner.waker.register(cx.waker());
                 Poll::Pending
             }
-            Poll::Ready(Some(TermionEvent::Unsupported(_))) => {
-                self.inner.waker.register(cx.waker());
-                Poll::Pending
-            }
+            Poll::Ready(Some(TermionEvent::Unsupported(sequence))) => match sequence.as_slice() {
+                FOCUS_IN => Poll::Ready(Some(Event::Focus(true))),
+                FOCUS_OUT => Poll::Ready(Some(Event::Focus(false))),
+                _ => {
+                    self.inner.waker.register(cx.waker());
+                    Poll::Pending
+                }
+            },
             Poll::Ready(None) => Poll::Ready(None),
             Poll::Pending => {
                 self.inner.waker.register(cx.waker());
@@ -0,0 +1,325 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, FixedOffset, Local as LocalTz};
+use futures::stream::{self, StreamExt};
+use log::Level;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+use xmpp_parsers::http_upload::{Header, Put, SlotRequest, SlotResult};
+use xmpp_parsers::iq::{Iq, IqType};
+use xmpp_parsers::{ns, BareJid, Jid};
+
+use crate::account::Account;
+use crate::command::{Command, CommandParser};
+use crate::conversation::Conversation;
+use crate::core::{Aparte, AparteAsync, Event, ModTrait};
+use crate::http;
+use crate::i18n;
+use crate::message::{LogMessage, Message};
+use crate::mods::conversation::ConversationMod;
+use crate::mods::disco::DiscoMod;
+use crate::mods::omemo;
+
+/// Size of the chunks the file is sent by, progress is updated after each one
+const UPLOAD_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Minimal progress in percent between two reports
+const PROGRESS_STEP: usize = 10;
+
+command_def!(upload,
+r#"/upload <path>
+
+    path    Path of the file to share
+
+Description:
+    Upload a file to the HTTP upload service of the server and send its
+    URL in the current conversation. Progress is reported in the window.
+    In OMEMO encrypted conversations the file is encrypted before being
+    uploaded and shared with an aesgcm:// URL.
+
+Examples:
+    /upload ~/Pictures/cat.png
+"#,
+{
+    path: String,
+},
+|aparte, command| {
+    let account = command
+        .account
+        .clone()
+        .context("Can't upload in non XMPP window")?;
+    let jid = BareJid::from_str(&command.context).context("Can't upload in non XMPP window")?;
+    let (conversation, lang) = {
+        let conversations = aparte.get_mod::<ConversationMod>();
+        let conversation = conversations
+            .get(&account, &jid)
+            .cloned()
+            .with_context(|| format!("Unknown conversation {jid}"))?;
+        let lang = conversations.get_lang(aparte, &account, &jid);
+        (conversation, lang)
+    };
+    // Only chats are encrypted with OMEMO
+    let encrypted = matches!(conversation, Conversation::Chat(_)) && aparte.is_encrypted(&account, &jid);
+    UploadMod::upload(aparte, &account, conversation, lang, encrypted, expand_home(&path));
+    Ok(())
+});
+
+/// Replace a leading `~` by the home directory
+pub fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), dirs::home_dir()) {
+        (Some(path), Some(home)) => home.join(path),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// File being uploaded, its status is displayed in the conversation window
+struct Upload {
+    account: Account,
+    conversation: Conversation,
+    lang: String,
+    filename: String,
+    /// XEP-0454: OMEMO Media sharing
+    encrypted: bool,
+    id: String,
+    timestamp: DateTime<FixedOffset>,
+}
+
+impl Upload {
+    fn report(&self, aparte: &mut AparteAsync, level: Level, status: &str) {
+        aparte.schedule(Event::ConversationLog {
+            account: self.account.clone(),
+            conversation: self.conversation.get_jid().clone(),
+            message: LogMessage {
+                id: self.id.clone(),
+                timestamp: self.timestamp,
+                body: format!("Upload of {}: {}", self.filename, status),
+                level,
+            },
+        });
+    }
+
+    /// Message sharing the uploaded file
+    fn message(&self, url: String) -> Result<Message> {
+        let id = Uuid::new_v4().to_string();
+        let timestamp = LocalTz::now().into();
+        let mut bodies = HashMap::new();
+        bodies.insert(self.lang.clone(), url.clone());
+        let message = match &self.conversation {
+            Conversation::Chat(chat) => {
+                let from: Jid = chat.account.clone().into();
+                let to: Jid = chat.contact.clone().into();
+                Message::outgoing_chat(id, timestamp, &from, &to, &bodies, false)
+            }
+            Conversation::Channel(channel) => {
+                let us = channel
+                    .account
+                    .to_bare()
+                    .with_resource_str(&channel.nick)
+                    .context("Invalid nick")?;
+                let from: Jid = us.into();
+                let to: Jid = channel.jid.clone().into();
+                Message::outgoing_channel(id, timestamp, &from, &to, &bodies, false)
+            }
+        };
+        // The OOB element isn't encrypted, it would disclose the key of encrypted files
+        match self.encrypted {
+            true => Ok(message),
+            false => Ok(message.with_oob(url)),
+        }
+    }
+}
+
+/// XEP-0363: HTTP File Upload
+pub struct UploadMod {}
+
+impl UploadMod {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    fn upload(
+        aparte: &Aparte,
+        account: &Account,
+        conversation: Conversation,
+        lang: String,
+        encrypted: bool,
+        path: PathBuf,
+    ) {
+        let filename = path
+            .file_name()
+            .map(|filename| filename.to_string_lossy().to_string())
+            .unwrap_or_else(|| path.display().to_string());
+        let upload = Upload {
+            account: account.clone(),
+            conversation,
+            lang,
+            filename,
+            encrypted,
+            id: Uuid::new_v4().to_string(),
+            timestamp: LocalTz::now().into(),
+        };
+
+        Aparte::spawn({
+            let mut aparte = aparte.proxy();
+            async move {
+                upload.report(&mut aparte, Level::Info, "starting");
+                match Self::upload_file(&mut aparte, &upload, &path).await {
+                    Ok(url) => match upload.message(url) {
+                        Ok(message) => {
+                            upload.report(&mut aparte, Level::Info, "done");
+                            aparte.schedule(Event::SendMessage(upload.account.clone(), message));
+                        }
+                        Err(err) => crate::error!(aparte, err, "Cannot share {}", upload.filename),
+                    },
+                    Err(err) => {
+                        upload.report(&mut aparte, Level::Error, &format!("{err:#}"));
+                        crate::error!(aparte, err, "Cannot upload {}", path.display());
+                    }
+                }
+            }
+        });
+    }
+
+    /// Upload the file and return the URL it is shared at
+    async fn upload_file(aparte: &mut AparteAsync, upload: &Upload, path: &Path) -> Result<String> {
+        let data = tokio::fs::read(path)
+            .await
+            .with_context(|| format!("Cannot read {}", path.display()))?;
+        if data.is_empty() {
+            anyhow::bail!("{} is empty", path.display());
+        }
+        let (data, fragment) = match upload.encrypted {
+            true => {
+                let (data, fragment) = omemo::encrypt_media(&data)?;
+                (data, Some(fragment))
+            }
+            false => (data, None),
+        };
+
+        let service = Self::find_service(aparte, &upload.account).await?;
+        let slot = Self::request_slot(
+            aparte,
+            &upload.account,
+            &service,
+            &upload.filename,
+            data.len() as u64,
+        )
+        .await?;
+        if !slot.put.url.starts_with("https://") {
+            anyhow::bail!("Upload service offered an insecure URL {}", slot.put.url);
+        }
+        if !slot.get.url.starts_with("https://") {
+            anyhow::bail!("Upload service offered an insecure URL {}", slot.get.url);
+        }
+
+        Self::put(aparte, upload, slot.put, data).await?;
+        match fragment {
+            Some(fragment) => Ok(omemo::aesgcm_url(&slot.get.url, &fragment)),
+            None => Ok(slot.get.url),
+        }
+    }
+
+    /// Server or server component providing HTTP upload
+    async fn find_service(aparte: &mut AparteAsync, account: &Account) -> Result<Jid> {
+        let server = Jid::from_str(&account.domain().to_string())?;
+        let features = DiscoMod::get_features(aparte, account, &server).await?;
+        if features.iter().any(|feature| feature == ns::HTTP_UPLOAD) {
+            return Ok(server);
+        }
+
+        DiscoMod::find_component(aparte, account, &server, ns::HTTP_UPLOAD)
+            .await?
+            .ok_or_else(|| anyhow!("No upload service found on {server}"))
+    }
+
+    async fn request_slot(
+        aparte: &mut AparteAsync,
+        account: &Account,
+        service: &Jid,
+        filename: &str,
+        size: u64,
+    ) -> Result<SlotResult> {
+        let id = Uuid::new_v4().hyphenated().to_string();
+        let request = SlotRequest {
+            filename: filename.to_string(),
+            size,
+            content_type: None,
+        };
+        let iq = Iq::from_get(id, request).with_to(service.clone());
+        match aparte.iq(account, iq).await?.payload {
+            IqType::Result(Some(el)) => Ok(SlotResult::try_from(el)?),
+            IqType::Error(err) => Err(anyhow!(
+                "Cannot get upload slot: {}",
+                i18n::xmpp_err_to_string(&err, vec![]).1
+            )),
+            _ => Err(anyhow!("Cannot get upload slot: invalid response")),
+        }
+    }
+
+    async fn put(aparte: &mut AparteAsync, upload: &Upload, put: Put, data: Vec<u8>) -> Result<()> {
+        let size = data.len();
+        let chunks: Vec<Vec<u8>> = data.chunks(UPLOAD_CHUNK_SIZE).map(<[u8]>::to_vec).collect();
+
+        // Chunks are counted when taken by the HTTP client
+        let (progress_tx, mut progress_rx) = mpsc::unbounded_channel();
+        let body = stream::iter(chunks).map(move |chunk| {
+            let _ = progress_tx.send(chunk.len());
+            chunk
+        });
+
+        let mut headers = vec![("Content-Length", size.to_string())];
+        headers.extend(put.headers.into_iter().map(|header| match header {
+            Header::Authorization(value) => ("Authorization", value),
+            Header::Cookie(value) => ("Cookie", value),
+            Header::Expires(value) => ("Expires", value),
+        }));
+
+        let response = http::put(&put.url, &headers, body);
+        tokio::pin!(response);
+        let mut sent = 0;
+        let mut reported = 0;
+        let response = loop {
+            tokio::select! {
+                response = &mut response => break response?,
+                Some(len) = progress_rx.recv() => {
+                    sent += len;
+                    let percent = sent * 100 / size;
+                    if percent >= reported + PROGRESS_STEP && percent < 100 {
+                        reported = percent;
+                        upload.report(aparte, Level::Info, &format!("{percent}%"));
+                    }
+                }
+            }
+        };
+        response.error_for_status()?;
+        Ok(())
+    }
+}
+
+impl ModTrait for UploadMod {
+    fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
+        aparte.add_command(upload::new());
+        aparte.get_mod_mut::<DiscoMod>().require_feature(
+            "upload",
+            "XEP-0363: HTTP File Upload",
+            ns::HTTP_UPLOAD,
+        );
+        Ok(())
+    }
+
+    fn on_event(&mut self, _aparte: &mut Aparte, _event: &Event) {}
+}
+
+impl fmt::Display for UploadMod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "XEP-0363: HTTP File Upload")
+    }
+}
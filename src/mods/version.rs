@@ -0,0 +1,171 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Context, Result};
+use uuid::Uuid;
+use xmpp_parsers::iq::{Iq, IqType};
+use xmpp_parsers::stanza_error::{DefinedCondition, ErrorType, StanzaError};
+use xmpp_parsers::version::{VersionQuery, VersionResult};
+use xmpp_parsers::{ns, Jid};
+
+use crate::account::Account;
+use crate::command::{Command, CommandParser};
+use crate::core::{Aparte, AparteAsync, Event, ModTrait};
+use crate::i18n;
+use crate::mods::contact::ContactMod;
+use crate::mods::disco::DiscoMod;
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+command_def!(version,
+r#"/version <jid>
+
+    jid    jid of the server, component or contact to query
+
+Description:
+    Show the name and version of the software behind an entity, and its
+    operating system when disclosed. Each connected resource of a contact is
+    queried when the contact is given without resource.
+
+Examples:
+    /version server.tld
+    /version contact@server.tld
+    /version contact@server.tld/phone
+"#,
+{
+    jid: String,
+},
+|aparte, _command| {
+    let account = aparte.current_account().context("No connection found")?;
+    let jid = Jid::from_str(&jid).with_context(|| format!("Invalid jid {jid}"))?;
+    let targets = match &jid {
+        Jid::Bare(bare) => {
+            let resources = aparte.get_mod::<ContactMod>().resources(&account, bare);
+            match resources.is_empty() {
+                true => vec![jid.clone()],
+                false => resources
+                    .into_iter()
+                    .filter_map(|(resource, _)| bare.with_resource_str(&resource).ok())
+                    .map(Jid::Full)
+                    .collect(),
+            }
+        }
+        Jid::Full(_) => vec![jid.clone()],
+    };
+
+    Aparte::spawn({
+        let mut aparte = aparte.proxy();
+        async move {
+            for target in targets {
+                match VersionMod::query(&mut aparte, &account, &target).await {
+                    Ok(version) => aparte.log(VersionMod::format(&target, &version)),
+                    Err(err) => crate::error!(aparte, err, "Cannot get version of {target}"),
+                }
+            }
+        }
+    });
+    Ok(())
+});
+
+/// XEP-0092: Software Version
+pub struct VersionMod {}
+
+impl VersionMod {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    async fn query(
+        aparte: &mut AparteAsync,
+        account: &Account,
+        jid: &Jid,
+    ) -> Result<VersionResult> {
+        let id = Uuid::new_v4().hyphenated().to_string();
+        let iq = Iq::from_get(id, VersionQuery).with_to(jid.clone());
+        match aparte.iq(account, iq).await?.payload {
+            IqType::Result(Some(el)) => Ok(VersionResult::try_from(el)?),
+            IqType::Result(None) => Err(anyhow!("empty response")),
+            IqType::Error(err) => Err(anyhow!("{}", i18n::xmpp_err_to_string(&err, vec![]).1)),
+            _ => Err(anyhow!("invalid response")),
+        }
+    }
+
+    fn format(jid: &Jid, version: &VersionResult) -> String {
+        let mut output = format!("{jid} runs {} {}", version.name, version.version);
+        if let Some(os) = &version.os {
+            output.push_str(&format!(" on {os}"));
+        }
+        output
+    }
+
+    /// Answer version queries with the configured name and version, or refuse them
+    fn answer(&mut self, aparte: &mut Aparte, account: &Account, iq: &Iq) {
+        let payload = match &iq.payload {
+            IqType::Get(payload) => payload.clone(),
+            _ => return,
+        };
+        if VersionQuery::try_from(payload).is_err() {
+            return;
+        }
+
+        let response = match aparte.config.answer_version.unwrap_or(true) {
+            true => Iq::from_result(
+                iq.id.clone(),
+                Some(VersionResult {
+                    name: aparte
+                        .config
+                        .client_name
+                        .clone()
+                        .unwrap_or_else(|| String::from("aparte")),
+                    version: aparte
+                        .config
+                        .client_version
+                        .clone()
+                        .unwrap_or_else(|| String::from(VERSION)),
+                    os: None,
+                }),
+            ),
+            false => Iq::from_error(
+                iq.id.clone(),
+                StanzaError::new(
+                    ErrorType::Cancel,
+                    DefinedCondition::ServiceUnavailable,
+                    "en",
+                    "Software version is not disclosed",
+                ),
+            ),
+        };
+        let response = match &iq.from {
+            Some(from) => response.with_to(from.clone()),
+            None => response,
+        };
+        aparte.send(account, response);
+    }
+}
+
+impl ModTrait for VersionMod {
+    fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
+        aparte.add_command(version::new());
+        if aparte.config.answer_version.unwrap_or(true) {
+            let mut disco = aparte.get_mod_mut::<DiscoMod>();
+            disco.add_feature(ns::VERSION);
+        }
+        Ok(())
+    }
+
+    fn on_event(&mut self, aparte: &mut Aparte, event: &Event) {
+        if let Event::Iq(account, iq) = event {
+            self.answer(aparte, account, iq);
+        }
+    }
+}
+
+impl fmt::Display for VersionMod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "XEP-0092: Software Version")
+    }
+}
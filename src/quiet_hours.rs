@@ -0,0 +1,158 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use std::str::FromStr;
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{Datelike, NaiveDateTime, NaiveTime, Weekday};
+
+/// Time window during which an account stays disconnected, written as `mon-fri 18:00-09:00`,
+/// `18:00-09:00` for every day or `sat-sun` for whole days
+///
+/// A window ending before it starts goes on the next day, days being the ones it starts on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuietHours {
+    days: Option<(Weekday, Weekday)>,
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl QuietHours {
+    fn has_day(&self, day: Weekday) -> bool {
+        match self.days {
+            None => true,
+            Some((first, last)) => {
+                let (first, last, day) = (
+                    first.num_days_from_monday(),
+                    last.num_days_from_monday(),
+                    day.num_days_from_monday(),
+                );
+                match first <= last {
+                    true => first <= day && day <= last,
+                    false => day >= first || day <= last,
+                }
+            }
+        }
+    }
+
+    pub fn contains(&self, at: NaiveDateTime) -> bool {
+        let time = at.time();
+        if self.start < self.end {
+            self.has_day(at.weekday()) && self.start <= time && time < self.end
+        } else {
+            (self.has_day(at.weekday()) && time >= self.start)
+                || (self.has_day(at.weekday().pred()) && time < self.end)
+        }
+    }
+}
+
+fn parse_days(days: &str) -> Result<(Weekday, Weekday)> {
+    let parse_day = |day: &str| Weekday::from_str(day).map_err(|_| anyhow!("Invalid day {day}"));
+    match days.split_once('-') {
+        Some((first, last)) => Ok((parse_day(first)?, parse_day(last)?)),
+        None => {
+            let day = parse_day(days)?;
+            Ok((day, day))
+        }
+    }
+}
+
+fn parse_time(time: &str) -> Result<NaiveTime> {
+    NaiveTime::parse_from_str(time, "%H:%M").with_context(|| format!("Invalid time {time}"))
+}
+
+impl FromStr for QuietHours {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.split_whitespace();
+        let (days, hours) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(days), Some(hours), None) => (Some(days), Some(hours)),
+            (Some(part), None, None) if part.contains(':') => (None, Some(part)),
+            (Some(days), None, None) => (Some(days), None),
+            _ => anyhow::bail!("Invalid quiet hours {s}"),
+        };
+
+        let days = days.map(parse_days).transpose()?;
+        let (start, end) = match hours {
+            Some(hours) => {
+                let (start, end) = hours
+                    .split_once('-')
+                    .with_context(|| format!("Invalid hours {hours}"))?;
+                (parse_time(start)?, parse_time(end)?)
+            }
+            // From midnight to midnight
+            None => (NaiveTime::default(), NaiveTime::default()),
+        };
+
+        Ok(Self { days, start, end })
+    }
+}
+
+/// Whether one of the windows contains the given date, invalid windows being ignored
+pub fn is_quiet(windows: &[String], at: NaiveDateTime) -> bool {
+    windows
+        .iter()
+        .filter_map(|window| QuietHours::from_str(window).ok())
+        .any(|window| window.contains(at))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn at(day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        // 2024-05-06 is a monday
+        NaiveDate::from_ymd_opt(2024, 5, day)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_parse_quiet_hours() {
+        assert_eq!(
+            QuietHours::from_str("mon-fri 18:00-09:00").unwrap(),
+            QuietHours {
+                days: Some((Weekday::Mon, Weekday::Fri)),
+                start: NaiveTime::from_hms_opt(18, 0, 0).unwrap(),
+                end: NaiveTime::from_hms_opt(9, 0, 0).unwrap(),
+            }
+        );
+        assert!(QuietHours::from_str("12:00-14:00").is_ok());
+        assert!(QuietHours::from_str("sat-sun").is_ok());
+        assert!(QuietHours::from_str("someday 18:00-09:00").is_err());
+        assert!(QuietHours::from_str("mon 18:00").is_err());
+        assert!(QuietHours::from_str("").is_err());
+    }
+
+    #[test]
+    fn test_overnight_quiet_hours() {
+        // Given
+        let hours = QuietHours::from_str("mon-fri 18:00-09:00").unwrap();
+
+        // Then
+        assert!(!hours.contains(at(6, 8, 59)));
+        assert!(!hours.contains(at(6, 12, 0)));
+        assert!(hours.contains(at(6, 18, 0)));
+        assert!(hours.contains(at(7, 8, 59)));
+        assert!(!hours.contains(at(7, 9, 0)));
+        // Window started friday goes on saturday morning only
+        assert!(hours.contains(at(11, 8, 0)));
+        assert!(!hours.contains(at(11, 18, 0)));
+        assert!(!hours.contains(at(12, 8, 0)));
+    }
+
+    #[test]
+    fn test_whole_days_quiet_hours() {
+        // Given
+        let windows = vec![String::from("sat-sun"), String::from("invalid")];
+
+        // Then
+        assert!(!is_quiet(&windows, at(10, 23, 59)));
+        assert!(is_quiet(&windows, at(11, 0, 0)));
+        assert!(is_quiet(&windows, at(12, 23, 59)));
+        assert!(!is_quiet(&windows, at(13, 0, 0)));
+    }
+}
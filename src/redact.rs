@@ -0,0 +1,230 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering::Relaxed};
+
+use flexi_logger::DeferredNow;
+use log::Record;
+
+const REDACTED: &str = "[redacted]";
+
+/// Elements whose text is always secret: passwords, SASL exchanges and tokens
+const SECRET_ELEMENTS: [&str; 6] = [
+    "password",
+    "auth",
+    "response",
+    "challenge",
+    "success",
+    "token",
+];
+
+/// Debug formatted fields holding secrets
+const SECRET_FIELDS: [&str; 2] = ["password: ", "password: Some("];
+
+/// Elements holding message contents
+const CONTENT_ELEMENTS: [&str; 3] = ["body", "subject", "payload"];
+
+/// Debug formatted fields holding message contents
+const CONTENT_FIELDS: [&str; 4] = ["Body(", "Subject(", "bodies: {", "hint: "];
+
+// Message contents are redacted until told otherwise by the config
+static REDACT_CONTENTS: AtomicBool = AtomicBool::new(true);
+
+pub fn set_redact_contents(redact: bool) {
+    REDACT_CONTENTS.store(redact, Relaxed);
+}
+
+/// Scrub secrets, and message contents unless configured otherwise, from a log line
+pub fn redact(line: &str) -> String {
+    let mut line = line.to_string();
+    for name in SECRET_ELEMENTS {
+        line = redact_element(&line, name);
+    }
+    for prefix in SECRET_FIELDS {
+        line = redact_strings(&line, prefix);
+    }
+
+    if REDACT_CONTENTS.load(Relaxed) {
+        for name in CONTENT_ELEMENTS {
+            line = redact_element(&line, name);
+        }
+        for prefix in CONTENT_FIELDS {
+            line = redact_strings(&line, prefix);
+        }
+    }
+
+    line
+}
+
+/// Log format used for the log file, same as flexi_logger's default one but redacted
+pub fn log_format(
+    w: &mut dyn io::Write,
+    _now: &mut DeferredNow,
+    record: &Record,
+) -> Result<(), io::Error> {
+    write!(
+        w,
+        "{} [{}] {}",
+        record.level(),
+        record.module_path().unwrap_or("<unnamed>"),
+        redact(&record.args().to_string())
+    )
+}
+
+/// Replace the text of each `<name …>text</name>` element of serialized xml
+fn redact_element(line: &str, name: &str) -> String {
+    let open = format!("<{name}");
+    let close = format!("</{name}>");
+    let mut output = String::with_capacity(line.len());
+    let mut rest = line;
+
+    while let Some(start) = rest.find(&open) {
+        output.push_str(&rest[..start]);
+        let attrs = &rest[start + open.len()..];
+        let tag_end = match attrs.find('>') {
+            // Ignore other elements starting with the same name and empty elements
+            Some(end)
+                if attrs.starts_with(&['>', ' ', '/'][..]) && !attrs[..end].ends_with('/') =>
+            {
+                end
+            }
+            _ => {
+                output.push_str(&open);
+                rest = attrs;
+                continue;
+            }
+        };
+
+        output.push_str(&open);
+        output.push_str(&attrs[..=tag_end]);
+        output.push_str(REDACTED);
+        let text = &attrs[tag_end + 1..];
+        rest = match text.find(&close) {
+            Some(text_end) => &text[text_end..],
+            // Truncated element, drop its remaining text
+            None => "",
+        };
+    }
+
+    output.push_str(rest);
+    output
+}
+
+/// Replace the content of debug formatted strings following the prefix, e.g. `password: "…"`.
+/// When the prefix opens a bracket, all strings up to the closing bracket are replaced.
+fn redact_strings(line: &str, prefix: &str) -> String {
+    let mut output = String::with_capacity(line.len());
+    let mut rest = line;
+
+    while let Some(start) = rest.find(prefix) {
+        let (before, after) = rest.split_at(start + prefix.len());
+        output.push_str(before);
+
+        let mut depth = match prefix.ends_with(&['{', '[', '('][..]) {
+            true => 1,
+            false => 0,
+        };
+        let mut in_string = false;
+        let mut escaped = false;
+        let mut end = after.len();
+        for (i, c) in after.char_indices() {
+            if in_string {
+                if escaped {
+                    escaped = false;
+                } else if c == '\\' {
+                    escaped = true;
+                } else if c == '"' {
+                    in_string = false;
+                    output.push(c);
+                    if depth == 0 {
+                        end = i + 1;
+                        break;
+                    }
+                }
+                continue;
+            }
+
+            match c {
+                '"' => {
+                    in_string = true;
+                    output.push(c);
+                    output.push_str(REDACTED);
+                }
+                '{' | '[' | '(' if depth > 0 => {
+                    depth += 1;
+                    output.push(c);
+                }
+                '}' | ']' | ')' if depth > 0 => {
+                    depth -= 1;
+                    output.push(c);
+                    if depth == 0 {
+                        end = i + 1;
+                        break;
+                    }
+                }
+                // Not a string
+                _ if depth == 0 => {
+                    end = i;
+                    break;
+                }
+                _ => output.push(c),
+            }
+        }
+
+        rest = &after[end..];
+    }
+
+    output.push_str(rest);
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redact_secret_elements() {
+        // Given
+        let line = r#"SEND: <auth xmlns="urn:ietf:params:xml:ns:xmpp-sasl" mechanism="PLAIN">AG1lAHNlY3JldA==</auth>"#;
+
+        // When
+        let redacted = redact_element(line, "auth");
+
+        // Then
+        assert_eq!(
+            redacted,
+            r#"SEND: <auth xmlns="urn:ietf:params:xml:ns:xmpp-sasl" mechanism="PLAIN">[redacted]</auth>"#
+        );
+    }
+
+    #[test]
+    fn test_redact_element_keeps_others() {
+        // Given
+        let line = "<bodyguard>a</bodyguard><body/><body xml:lang='en'>b</body><body>c";
+
+        // When
+        let redacted = redact_element(line, "body");
+
+        // Then
+        assert_eq!(
+            redacted,
+            "<bodyguard>a</bodyguard><body/><body xml:lang='en'>[redacted]</body><body>[redacted]"
+        );
+    }
+
+    #[test]
+    fn test_redact_debug_strings() {
+        // Given
+        let line = r#"Config { password: "p\"w", port: None } { bodies: {"en": "hi {"} }"#;
+
+        // When
+        let redacted = redact_strings(&redact_strings(line, "password: "), "bodies: {");
+
+        // Then
+        assert_eq!(
+            redacted,
+            r#"Config { password: "[redacted]", port: None } { bodies: {"[redacted]": "[redacted]"} }"#
+        );
+    }
+}
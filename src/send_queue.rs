@@ -0,0 +1,110 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+
+use xmpp_parsers::message::Message as XmppParsersMessage;
+use xmpp_parsers::Element;
+
+use crate::account::Account;
+
+/// Outgoing messages kept until their account is online, exposed with /queue
+pub struct SendQueue {
+    online: HashSet<Account>,
+    queued: HashMap<Account, Vec<Element>>,
+}
+
+impl SendQueue {
+    pub fn new() -> Self {
+        Self {
+            online: HashSet::new(),
+            queued: HashMap::new(),
+        }
+    }
+
+    pub fn set_online(&mut self, account: &Account, online: bool) {
+        match online {
+            true => self.online.insert(account.clone()),
+            false => self.online.remove(account),
+        };
+    }
+
+    pub fn is_online(&self, account: &Account) -> bool {
+        self.online.contains(account)
+    }
+
+    pub fn push(&mut self, account: Account, stanza: Element) {
+        self.queued.entry(account).or_default().push(stanza);
+    }
+
+    /// Remove all messages queued for the account
+    pub fn take(&mut self, account: &Account) -> Vec<Element> {
+        self.queued.remove(account).unwrap_or_default()
+    }
+
+    pub fn accounts(&self) -> Vec<Account> {
+        let mut accounts: Vec<Account> = self
+            .queued
+            .iter()
+            .filter(|(_, queued)| !queued.is_empty())
+            .map(|(account, _)| account.clone())
+            .collect();
+        accounts.sort_by_key(|account| account.to_string());
+        accounts
+    }
+
+    /// Queued messages, numbered from 1 in the order of accounts
+    pub fn entries(&self) -> Vec<(usize, Account, String)> {
+        self.accounts()
+            .into_iter()
+            .flat_map(|account| {
+                self.queued[&account]
+                    .iter()
+                    .map(|stanza| (account.clone(), Self::describe(stanza)))
+                    .collect::<Vec<_>>()
+            })
+            .enumerate()
+            .map(|(index, (account, description))| (index + 1, account, description))
+            .collect()
+    }
+
+    /// Remove the message numbered as in entries()
+    pub fn remove(&mut self, number: usize) -> Option<Element> {
+        let mut number = number.checked_sub(1)?;
+        for account in self.accounts() {
+            let queued = self.queued.get_mut(&account)?;
+            if number < queued.len() {
+                return Some(queued.remove(number));
+            }
+            number -= queued.len();
+        }
+        None
+    }
+
+    pub fn clear(&mut self) -> usize {
+        let count = self.queued.values().map(Vec::len).sum();
+        self.queued.clear();
+        count
+    }
+
+    /// Recipient and body of a message
+    pub fn describe(stanza: &Element) -> String {
+        match XmppParsersMessage::try_from(stanza.clone()) {
+            Ok(message) => {
+                let to = message
+                    .to
+                    .map(|to| to.to_string())
+                    .unwrap_or_else(|| String::from("?"));
+                let body = message
+                    .bodies
+                    .values()
+                    .next()
+                    .map(|body| body.0.clone())
+                    .unwrap_or_default();
+                format!("{to}: {body}")
+            }
+            Err(_) => String::from("invalid message"),
+        }
+    }
+}
@@ -0,0 +1,91 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use std::any::TypeId;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Accumulated durations of a measured operation
+#[derive(Default, Clone, Copy)]
+pub struct Timing {
+    pub count: u64,
+    pub total: Duration,
+    pub max: Duration,
+}
+
+impl Timing {
+    fn record(&mut self, duration: Duration) {
+        self.count += 1;
+        self.total += duration;
+        self.max = self.max.max(duration);
+    }
+
+    pub fn average(&self) -> Duration {
+        match self.count {
+            0 => Duration::ZERO,
+            // Counts beyond u32 would wrap, possibly to 0
+            count => self.total.div_f64(count as f64),
+        }
+    }
+}
+
+/// Performance counters, exposed with /stats
+pub struct Stats {
+    pub started: Instant,
+    /// Event handling time of each mod
+    pub mods: HashMap<TypeId, Timing>,
+    pub renders: Timing,
+    pub received_stanzas: u64,
+    pub sent_stanzas: u64,
+    /// Duration of each startup phase, in order
+    pub startup: Vec<(&'static str, Duration)>,
+    /// Time from startup to the first render
+    pub first_render: Option<Duration>,
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            mods: HashMap::new(),
+            renders: Timing::default(),
+            received_stanzas: 0,
+            sent_stanzas: 0,
+            startup: Vec::new(),
+            first_render: None,
+        }
+    }
+
+    pub fn record_event(&mut self, r#mod: TypeId, duration: Duration) {
+        self.mods.entry(r#mod).or_default().record(duration);
+    }
+
+    pub fn record_render(&mut self, duration: Duration) {
+        self.renders.record(duration);
+        if self.first_render.is_none() {
+            self.first_render = Some(self.started.elapsed());
+        }
+    }
+
+    /// Record a startup phase begun at `since`
+    pub fn record_startup(&mut self, phase: &'static str, since: Instant) {
+        self.startup.push((phase, since.elapsed()));
+    }
+
+    pub fn startup_report(&self) -> String {
+        let mut phases = self
+            .startup
+            .iter()
+            .map(|(phase, duration)| format!("{phase} {duration:?}"))
+            .collect::<Vec<_>>();
+        if let Some(first_render) = self.first_render {
+            phases.push(format!("first render after {first_render:?}"));
+        }
+        phases.join(", ")
+    }
+
+    /// Stanzas per second since startup
+    pub fn throughput(&self, count: u64) -> f64 {
+        count as f64 / self.started.elapsed().as_secs_f64().max(1f64)
+    }
+}
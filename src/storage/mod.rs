@@ -4,14 +4,16 @@
 mod models;
 mod schema;
 
+use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::path::PathBuf;
 use std::str::FromStr;
 use std::sync::atomic::AtomicBool;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
 use anyhow::{anyhow, Error, Result};
 use async_trait::async_trait;
+use chrono::{DateTime, Local};
 use diesel::prelude::*;
 use diesel::r2d2::{ConnectionManager, Pool};
 use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
@@ -20,8 +22,9 @@ use xmpp_parsers::BareJid;
 use crate::account::Account;
 
 pub use models::{
-    OmemoContactDevice, OmemoIdentity, OmemoOwnDevice, OmemoPreKey, OmemoSenderKey, OmemoSession,
-    OmemoSignedPreKey,
+    Avatar, ChannelSubject, ConversationPrivacy, OmemoContactDevice, OmemoIdentity, OmemoOwnDevice,
+    OmemoPreKey, OmemoSenderKey, OmemoSession, OmemoSignedPreKey, PinnedMessage, ReadPosition,
+    SessionJournalEntry,
 };
 
 pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!();
@@ -40,12 +43,17 @@ impl Storage {
         let manager = ConnectionManager::<SqliteConnection>::new(path);
         let pool = Pool::builder().build(manager)?;
 
-        let mut conn = pool.get()?;
-        conn.run_pending_migrations(MIGRATIONS).unwrap();
-
         Ok(Self { pool })
     }
 
+    /// Bring the database schema up to date, to be done before any other use of the storage
+    pub fn migrate(&self) -> Result<()> {
+        let mut conn = self.pool.get()?;
+        conn.run_pending_migrations(MIGRATIONS)
+            .map_err(|err| anyhow!("Cannot run migrations: {err}"))?;
+        Ok(())
+    }
+
     pub fn get_omemo_own_device(&self, account: &Account) -> Result<Option<OmemoOwnDevice>> {
         use schema::omemo_own_device;
         let mut conn = self.pool.get()?;
@@ -69,11 +77,22 @@ impl Storage {
                 omemo_own_device::account.eq(account.to_string()),
                 omemo_own_device::id.eq::<i64>(device_id.into()),
                 omemo_own_device::identity.eq(Some(identity_key_pair)),
+                omemo_own_device::next_pre_key_id.eq(1),
             ))
             .get_result(&mut conn)?;
         Ok(device)
     }
 
+    pub fn set_omemo_next_pre_key_id(&mut self, account: &Account, next: u32) -> Result<()> {
+        use schema::omemo_own_device;
+        let mut conn = self.pool.get()?;
+        diesel::update(omemo_own_device::table)
+            .filter(omemo_own_device::account.eq(account.to_string()))
+            .set(omemo_own_device::next_pre_key_id.eq::<i64>(next.into()))
+            .execute(&mut conn)?;
+        Ok(())
+    }
+
     pub fn upsert_omemo_contact_device(
         &mut self,
         account: &Account,
@@ -527,6 +546,529 @@ impl Storage {
             })
             .transpose()?)
     }
+
+    pub fn get_vip_conversations(&self, account: &Account) -> Result<Vec<BareJid>> {
+        use schema::vip_conversation;
+        let mut conn = self.pool.get()?;
+
+        Ok(vip_conversation::table
+            .select(vip_conversation::jid)
+            .filter(vip_conversation::account.eq(account.to_string()))
+            .get_results::<String>(&mut conn)?
+            .iter()
+            .filter_map(|jid| BareJid::from_str(jid).ok())
+            .collect())
+    }
+
+    pub fn add_vip_conversation(&self, account: &Account, jid: &BareJid) -> Result<()> {
+        use schema::vip_conversation;
+        let mut conn = self.pool.get()?;
+        diesel::insert_into(vip_conversation::table)
+            .values((
+                vip_conversation::account.eq(account.to_string()),
+                vip_conversation::jid.eq(jid.to_string()),
+            ))
+            .on_conflict((vip_conversation::account, vip_conversation::jid))
+            .do_nothing()
+            .execute(&mut conn)?;
+
+        Ok(())
+    }
+
+    pub fn remove_vip_conversation(&self, account: &Account, jid: &BareJid) -> Result<()> {
+        use schema::vip_conversation;
+        let mut conn = self.pool.get()?;
+        diesel::delete(
+            vip_conversation::table
+                .filter(vip_conversation::account.eq(account.to_string()))
+                .filter(vip_conversation::jid.eq(jid.to_string())),
+        )
+        .execute(&mut conn)?;
+
+        Ok(())
+    }
+
+    pub fn get_muted_conversations(&self, account: &Account) -> Result<Vec<BareJid>> {
+        use schema::muted_conversation;
+        let mut conn = self.pool.get()?;
+
+        Ok(muted_conversation::table
+            .select(muted_conversation::jid)
+            .filter(muted_conversation::account.eq(account.to_string()))
+            .get_results::<String>(&mut conn)?
+            .iter()
+            .filter_map(|jid| BareJid::from_str(jid).ok())
+            .collect())
+    }
+
+    pub fn add_muted_conversation(&self, account: &Account, jid: &BareJid) -> Result<()> {
+        use schema::muted_conversation;
+        let mut conn = self.pool.get()?;
+        diesel::insert_into(muted_conversation::table)
+            .values((
+                muted_conversation::account.eq(account.to_string()),
+                muted_conversation::jid.eq(jid.to_string()),
+            ))
+            .on_conflict((muted_conversation::account, muted_conversation::jid))
+            .do_nothing()
+            .execute(&mut conn)?;
+
+        Ok(())
+    }
+
+    pub fn remove_muted_conversation(&self, account: &Account, jid: &BareJid) -> Result<()> {
+        use schema::muted_conversation;
+        let mut conn = self.pool.get()?;
+        diesel::delete(
+            muted_conversation::table
+                .filter(muted_conversation::account.eq(account.to_string()))
+                .filter(muted_conversation::jid.eq(jid.to_string())),
+        )
+        .execute(&mut conn)?;
+
+        Ok(())
+    }
+
+    /// Roster groups whose contacts are hidden
+    pub fn get_collapsed_roster_groups(&self) -> Result<Vec<String>> {
+        use schema::collapsed_roster_group;
+        let mut conn = self.pool.get()?;
+
+        Ok(collapsed_roster_group::table
+            .select(collapsed_roster_group::name)
+            .get_results::<String>(&mut conn)?)
+    }
+
+    pub fn set_roster_group_collapsed(&self, name: &str, collapsed: bool) -> Result<()> {
+        use schema::collapsed_roster_group;
+        let mut conn = self.pool.get()?;
+        match collapsed {
+            true => diesel::insert_into(collapsed_roster_group::table)
+                .values(collapsed_roster_group::name.eq(name))
+                .on_conflict(collapsed_roster_group::name)
+                .do_nothing()
+                .execute(&mut conn)?,
+            false => diesel::delete(
+                collapsed_roster_group::table.filter(collapsed_roster_group::name.eq(name)),
+            )
+            .execute(&mut conn)?,
+        };
+
+        Ok(())
+    }
+
+    /// Messages pinned in a conversation, or in all conversations of the account
+    pub fn get_pinned_messages(
+        &self,
+        account: &Account,
+        jid: Option<&BareJid>,
+    ) -> Result<Vec<PinnedMessage>> {
+        use schema::pinned_message;
+        let mut conn = self.pool.get()?;
+
+        let mut query = pinned_message::table
+            .filter(pinned_message::account.eq(account.to_string()))
+            .order(pinned_message::pinned_message_pk)
+            .into_boxed();
+        if let Some(jid) = jid {
+            query = query.filter(pinned_message::jid.eq(jid.to_string()));
+        }
+
+        Ok(query.load(&mut conn)?)
+    }
+
+    pub fn add_pinned_message(
+        &self,
+        account: &Account,
+        jid: &BareJid,
+        message_id: &str,
+        timestamp: &str,
+        author: &str,
+        body: &str,
+    ) -> Result<()> {
+        use schema::pinned_message;
+        let mut conn = self.pool.get()?;
+        diesel::insert_into(pinned_message::table)
+            .values((
+                pinned_message::account.eq(account.to_string()),
+                pinned_message::jid.eq(jid.to_string()),
+                pinned_message::message_id.eq(message_id),
+                pinned_message::timestamp.eq(timestamp),
+                pinned_message::author.eq(author),
+                pinned_message::body.eq(body),
+            ))
+            .on_conflict((
+                pinned_message::account,
+                pinned_message::jid,
+                pinned_message::message_id,
+            ))
+            .do_nothing()
+            .execute(&mut conn)?;
+
+        Ok(())
+    }
+
+    pub fn remove_pinned_message(&self, pinned_message_pk: i32) -> Result<()> {
+        use schema::pinned_message;
+        let mut conn = self.pool.get()?;
+        diesel::delete(pinned_message::table.find(pinned_message_pk)).execute(&mut conn)?;
+
+        Ok(())
+    }
+
+    pub fn get_avatar(&self, account: &Account, jid: &BareJid) -> Result<Option<Avatar>> {
+        use schema::avatar;
+        let mut conn = self.pool.get()?;
+
+        Ok(avatar::table
+            .filter(avatar::account.eq(account.to_string()))
+            .filter(avatar::jid.eq(jid.to_string()))
+            .first(&mut conn)
+            .optional()?)
+    }
+
+    pub fn set_avatar(
+        &self,
+        account: &Account,
+        jid: &BareJid,
+        hash: &str,
+        mime_type: &str,
+        data: &[u8],
+    ) -> Result<()> {
+        use schema::avatar;
+        let mut conn = self.pool.get()?;
+        diesel::insert_into(avatar::table)
+            .values((
+                avatar::account.eq(account.to_string()),
+                avatar::jid.eq(jid.to_string()),
+                avatar::hash.eq(hash),
+                avatar::mime_type.eq(mime_type),
+                avatar::data.eq(data),
+            ))
+            .on_conflict((avatar::account, avatar::jid))
+            .do_update()
+            .set((
+                avatar::hash.eq(hash),
+                avatar::mime_type.eq(mime_type),
+                avatar::data.eq(data),
+            ))
+            .execute(&mut conn)?;
+
+        Ok(())
+    }
+
+    /// Disco info result, serialized, of the entity capabilities with this verification string
+    pub fn get_caps(&self, algo: &str, ver: &str) -> Result<Option<String>> {
+        use schema::caps_cache;
+        let mut conn = self.pool.get()?;
+
+        Ok(caps_cache::table
+            .select(caps_cache::disco)
+            .filter(caps_cache::algo.eq(algo))
+            .filter(caps_cache::ver.eq(ver))
+            .first(&mut conn)
+            .optional()?)
+    }
+
+    pub fn add_caps(&self, algo: &str, ver: &str, disco: &str) -> Result<()> {
+        use schema::caps_cache;
+        let mut conn = self.pool.get()?;
+        diesel::insert_into(caps_cache::table)
+            .values((
+                caps_cache::algo.eq(algo),
+                caps_cache::ver.eq(ver),
+                caps_cache::disco.eq(disco),
+            ))
+            .on_conflict((caps_cache::algo, caps_cache::ver))
+            .do_nothing()
+            .execute(&mut conn)?;
+
+        Ok(())
+    }
+
+    /// Subjects successively set in a channel, oldest first
+    pub fn get_channel_subjects(
+        &self,
+        account: &Account,
+        jid: &BareJid,
+    ) -> Result<Vec<ChannelSubject>> {
+        use schema::channel_subject;
+        let mut conn = self.pool.get()?;
+
+        Ok(channel_subject::table
+            .filter(channel_subject::account.eq(account.to_string()))
+            .filter(channel_subject::jid.eq(jid.to_string()))
+            .order(channel_subject::channel_subject_pk)
+            .load(&mut conn)?)
+    }
+
+    pub fn add_channel_subject(
+        &self,
+        account: &Account,
+        jid: &BareJid,
+        timestamp: &str,
+        author: Option<&str>,
+        subject: &str,
+    ) -> Result<()> {
+        use schema::channel_subject;
+        let mut conn = self.pool.get()?;
+        diesel::insert_into(channel_subject::table)
+            .values((
+                channel_subject::account.eq(account.to_string()),
+                channel_subject::jid.eq(jid.to_string()),
+                channel_subject::timestamp.eq(timestamp),
+                channel_subject::author.eq(author),
+                channel_subject::subject.eq(subject),
+            ))
+            .execute(&mut conn)?;
+
+        Ok(())
+    }
+
+    /// Known delivery states of outgoing messages of a conversation, by message id
+    pub fn get_message_deliveries(
+        &self,
+        account: &Account,
+        jid: &BareJid,
+    ) -> Result<Vec<(String, String)>> {
+        use schema::message_delivery;
+        let mut conn = self.pool.get()?;
+
+        Ok(message_delivery::table
+            .select((message_delivery::message_id, message_delivery::state))
+            .filter(message_delivery::account.eq(account.to_string()))
+            .filter(message_delivery::jid.eq(jid.to_string()))
+            .load(&mut conn)?)
+    }
+
+    pub fn set_message_delivery(
+        &self,
+        account: &Account,
+        jid: &BareJid,
+        message_id: &str,
+        state: &str,
+    ) -> Result<()> {
+        use schema::message_delivery;
+        let mut conn = self.pool.get()?;
+        diesel::insert_into(message_delivery::table)
+            .values((
+                message_delivery::account.eq(account.to_string()),
+                message_delivery::jid.eq(jid.to_string()),
+                message_delivery::message_id.eq(message_id),
+                message_delivery::state.eq(state),
+            ))
+            .on_conflict((
+                message_delivery::account,
+                message_delivery::jid,
+                message_delivery::message_id,
+            ))
+            .do_update()
+            .set(message_delivery::state.eq(state))
+            .execute(&mut conn)?;
+
+        Ok(())
+    }
+
+    pub fn get_read_position(
+        &self,
+        account: &Account,
+        jid: &BareJid,
+    ) -> Result<Option<ReadPosition>> {
+        use schema::read_position;
+        let mut conn = self.pool.get()?;
+
+        Ok(read_position::table
+            .filter(read_position::account.eq(account.to_string()))
+            .filter(read_position::jid.eq(jid.to_string()))
+            .first(&mut conn)
+            .optional()?)
+    }
+
+    pub fn set_read_position(
+        &self,
+        account: &Account,
+        jid: &BareJid,
+        view: usize,
+        last_read: Option<&str>,
+        read_at: Option<&str>,
+    ) -> Result<()> {
+        use schema::read_position;
+        let mut conn = self.pool.get()?;
+        diesel::insert_into(read_position::table)
+            .values((
+                read_position::account.eq(account.to_string()),
+                read_position::jid.eq(jid.to_string()),
+                read_position::view.eq(view as i64),
+                read_position::last_read.eq(last_read),
+                read_position::read_at.eq(read_at),
+            ))
+            .on_conflict((read_position::account, read_position::jid))
+            .do_update()
+            .set((
+                read_position::view.eq(view as i64),
+                read_position::last_read.eq(last_read),
+                read_position::read_at.eq(read_at),
+            ))
+            .execute(&mut conn)?;
+
+        Ok(())
+    }
+
+    pub fn get_conversation_langs(&self, account: &Account) -> Result<Vec<(BareJid, String)>> {
+        use schema::conversation_lang;
+        let mut conn = self.pool.get()?;
+
+        Ok(conversation_lang::table
+            .select((conversation_lang::jid, conversation_lang::lang))
+            .filter(conversation_lang::account.eq(account.to_string()))
+            .get_results::<(String, String)>(&mut conn)?
+            .into_iter()
+            .filter_map(|(jid, lang)| BareJid::from_str(&jid).ok().map(|jid| (jid, lang)))
+            .collect())
+    }
+
+    pub fn set_conversation_lang(
+        &self,
+        account: &Account,
+        jid: &BareJid,
+        lang: &str,
+    ) -> Result<()> {
+        use schema::conversation_lang;
+        let mut conn = self.pool.get()?;
+        diesel::insert_into(conversation_lang::table)
+            .values((
+                conversation_lang::account.eq(account.to_string()),
+                conversation_lang::jid.eq(jid.to_string()),
+                conversation_lang::lang.eq(lang),
+            ))
+            .on_conflict((conversation_lang::account, conversation_lang::jid))
+            .do_update()
+            .set(conversation_lang::lang.eq(lang))
+            .execute(&mut conn)?;
+
+        Ok(())
+    }
+
+    pub fn remove_conversation_lang(&self, account: &Account, jid: &BareJid) -> Result<()> {
+        use schema::conversation_lang;
+        let mut conn = self.pool.get()?;
+        diesel::delete(
+            conversation_lang::table
+                .filter(conversation_lang::account.eq(account.to_string()))
+                .filter(conversation_lang::jid.eq(jid.to_string())),
+        )
+        .execute(&mut conn)?;
+
+        Ok(())
+    }
+
+    pub fn get_conversation_privacy(&self, account: &Account) -> Result<Vec<ConversationPrivacy>> {
+        use schema::conversation_privacy;
+        let mut conn = self.pool.get()?;
+
+        Ok(conversation_privacy::table
+            .filter(conversation_privacy::account.eq(account.to_string()))
+            .load(&mut conn)?)
+    }
+
+    pub fn set_conversation_privacy(
+        &self,
+        account: &Account,
+        jid: &BareJid,
+        receipts: Option<bool>,
+        chat_markers: Option<bool>,
+        chat_states: Option<bool>,
+    ) -> Result<()> {
+        use schema::conversation_privacy;
+        let mut conn = self.pool.get()?;
+        diesel::insert_into(conversation_privacy::table)
+            .values((
+                conversation_privacy::account.eq(account.to_string()),
+                conversation_privacy::jid.eq(jid.to_string()),
+                conversation_privacy::receipts.eq(receipts),
+                conversation_privacy::chat_markers.eq(chat_markers),
+                conversation_privacy::chat_states.eq(chat_states),
+            ))
+            .on_conflict((conversation_privacy::account, conversation_privacy::jid))
+            .do_update()
+            .set((
+                conversation_privacy::receipts.eq(receipts),
+                conversation_privacy::chat_markers.eq(chat_markers),
+                conversation_privacy::chat_states.eq(chat_states),
+            ))
+            .execute(&mut conn)?;
+
+        Ok(())
+    }
+
+    /// Latest entries of the connection journal, of one account or all of them, oldest first
+    pub fn get_session_journal(
+        &self,
+        account: Option<&Account>,
+        limit: Option<i64>,
+    ) -> Result<Vec<SessionJournalEntry>> {
+        use schema::session_journal;
+        let mut conn = self.pool.get()?;
+
+        let mut query = session_journal::table
+            .order(session_journal::session_journal_pk.desc())
+            .into_boxed();
+        if let Some(account) = account {
+            query = query.filter(session_journal::account.eq(account.to_string()));
+        }
+        if let Some(limit) = limit {
+            query = query.limit(limit);
+        }
+
+        let mut entries: Vec<SessionJournalEntry> = query.load(&mut conn)?;
+        entries.reverse();
+        Ok(entries)
+    }
+
+    pub fn add_session_journal_entry(
+        &self,
+        account: &Account,
+        timestamp: &str,
+        event: &str,
+        detail: Option<&str>,
+    ) -> Result<()> {
+        use schema::session_journal;
+        let mut conn = self.pool.get()?;
+        diesel::insert_into(session_journal::table)
+            .values((
+                session_journal::account.eq(account.to_string()),
+                session_journal::timestamp.eq(timestamp),
+                session_journal::event.eq(event),
+                session_journal::detail.eq(detail),
+            ))
+            .execute(&mut conn)?;
+
+        Ok(())
+    }
+
+    /// Drop the oldest journal entries of the account, keeping the given number of entries
+    pub fn prune_session_journal(&self, account: &Account, kept: i64) -> Result<()> {
+        use schema::session_journal;
+        let mut conn = self.pool.get()?;
+
+        let newest_pruned: Option<i32> = session_journal::table
+            .select(session_journal::session_journal_pk)
+            .filter(session_journal::account.eq(account.to_string()))
+            .order(session_journal::session_journal_pk.desc())
+            .offset(kept)
+            .first(&mut conn)
+            .optional()?;
+        if let Some(newest_pruned) = newest_pruned {
+            diesel::delete(
+                session_journal::table
+                    .filter(session_journal::account.eq(account.to_string()))
+                    .filter(session_journal::session_journal_pk.le(newest_pruned)),
+            )
+            .execute(&mut conn)?;
+        }
+
+        Ok(())
+    }
 }
 
 fn signal_storage_error<T>(
@@ -574,11 +1116,22 @@ impl std::fmt::Display for UnwindSafeResultError {
 
 impl std::error::Error for UnwindSafeResultError {}
 
+/// Outcome of the decryptions of messages sent by a device, kept for diagnostics
+#[derive(Debug, Clone, Default)]
+pub struct DecryptionHealth {
+    pub last_success: Option<DateTime<Local>>,
+    pub last_failure: Option<(DateTime<Local>, String)>,
+    /// Failures since the last success or session reset
+    pub failures: u32,
+    pub last_reset: Option<DateTime<Local>>,
+}
+
 #[derive(Clone)]
 pub struct SignalStorage {
     pub account: Account,
     pub storage: Storage,
     pub deleted_pre_keys: Arc<AtomicBool>,
+    pub decryptions: Arc<Mutex<HashMap<libsignal_protocol::ProtocolAddress, DecryptionHealth>>>,
 }
 
 impl SignalStorage {
@@ -587,8 +1140,45 @@ impl SignalStorage {
             account,
             storage,
             deleted_pre_keys: Arc::new(AtomicBool::new(false)),
+            decryptions: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Record the outcome of the decryption of a message sent by a device
+    pub fn record_decryption<T>(
+        &self,
+        address: &libsignal_protocol::ProtocolAddress,
+        result: &Result<T>,
+    ) {
+        let mut decryptions = self.decryptions.lock().unwrap();
+        let health = decryptions.entry(address.clone()).or_default();
+        match result {
+            Ok(_) => {
+                health.last_success = Some(Local::now());
+                health.failures = 0;
+            }
+            Err(err) => {
+                health.last_failure = Some((Local::now(), format!("{err:#}")));
+                health.failures += 1;
+            }
         }
     }
+
+    /// Record that a new session was started with a device after repeated failures
+    pub fn record_reset(&self, address: &libsignal_protocol::ProtocolAddress) {
+        let mut decryptions = self.decryptions.lock().unwrap();
+        let health = decryptions.entry(address.clone()).or_default();
+        health.last_reset = Some(Local::now());
+        health.failures = 0;
+    }
+
+    pub fn decryption_health(
+        &self,
+        address: &libsignal_protocol::ProtocolAddress,
+    ) -> DecryptionHealth {
+        let decryptions = self.decryptions.lock().unwrap();
+        decryptions.get(address).cloned().unwrap_or_default()
+    }
 }
 
 #[async_trait(?Send)]
@@ -9,6 +9,7 @@ pub struct OmemoOwnDevice {
     pub account: String,
     pub id: i64,
     pub identity: Option<Vec<u8>>,
+    pub next_pre_key_id: i64,
 }
 
 #[derive(Queryable, Debug)]
@@ -73,3 +74,73 @@ pub struct OmemoSenderKey {
     pub distribution_id: Vec<u8>,
     pub sender_key: Vec<u8>,
 }
+
+#[derive(Queryable, Debug)]
+pub struct ReadPosition {
+    pub read_position_pk: i32,
+    pub account: String,
+    pub jid: String,
+    /// Scroll offset from the bottom of the window, in lines
+    pub view: i64,
+    /// Id of the last message displayed in the window
+    pub last_read: Option<String>,
+    /// Timestamp of the last message displayed in the window, as RFC 3339
+    pub read_at: Option<String>,
+}
+
+#[derive(Queryable, Debug)]
+pub struct ConversationPrivacy {
+    pub conversation_privacy_pk: i32,
+    pub account: String,
+    pub jid: String,
+    /// Whether delivery receipts are sent, global setting is used when unset
+    pub receipts: Option<bool>,
+    pub chat_markers: Option<bool>,
+    pub chat_states: Option<bool>,
+}
+
+#[derive(Queryable, Debug)]
+pub struct Avatar {
+    pub avatar_pk: i32,
+    pub account: String,
+    pub jid: String,
+    /// SHA-1 of the image, hex encoded
+    pub hash: String,
+    pub mime_type: String,
+    pub data: Vec<u8>,
+}
+
+#[derive(Queryable, Debug)]
+pub struct ChannelSubject {
+    pub channel_subject_pk: i32,
+    pub account: String,
+    pub jid: String,
+    /// Time of the change, RFC 3339 formatted
+    pub timestamp: String,
+    /// Nick of the occupant who changed the subject, unset when set by the channel itself
+    pub author: Option<String>,
+    pub subject: String,
+}
+
+#[derive(Queryable, Debug)]
+pub struct PinnedMessage {
+    pub pinned_message_pk: i32,
+    pub account: String,
+    pub jid: String,
+    pub message_id: String,
+    /// Original timestamp of the message, RFC 3339 formatted
+    pub timestamp: String,
+    pub author: String,
+    pub body: String,
+}
+
+#[derive(Queryable, Debug)]
+pub struct SessionJournalEntry {
+    pub session_journal_pk: i32,
+    pub account: String,
+    /// RFC 3339 formatted
+    pub timestamp: String,
+    /// What happened to the connection: connected, disconnected, auth failure…
+    pub event: String,
+    pub detail: Option<String>,
+}
@@ -1,5 +1,81 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    avatar (avatar_pk) {
+        avatar_pk -> Integer,
+        account -> Text,
+        jid -> Text,
+        hash -> Text,
+        mime_type -> Text,
+        data -> Binary,
+    }
+}
+
+diesel::table! {
+    caps_cache (caps_cache_pk) {
+        caps_cache_pk -> Integer,
+        algo -> Text,
+        ver -> Text,
+        disco -> Text,
+    }
+}
+
+diesel::table! {
+    channel_subject (channel_subject_pk) {
+        channel_subject_pk -> Integer,
+        account -> Text,
+        jid -> Text,
+        timestamp -> Text,
+        author -> Nullable<Text>,
+        subject -> Text,
+    }
+}
+
+diesel::table! {
+    collapsed_roster_group (collapsed_roster_group_pk) {
+        collapsed_roster_group_pk -> Integer,
+        name -> Text,
+    }
+}
+
+diesel::table! {
+    conversation_lang (conversation_lang_pk) {
+        conversation_lang_pk -> Integer,
+        account -> Text,
+        jid -> Text,
+        lang -> Text,
+    }
+}
+
+diesel::table! {
+    conversation_privacy (conversation_privacy_pk) {
+        conversation_privacy_pk -> Integer,
+        account -> Text,
+        jid -> Text,
+        receipts -> Nullable<Bool>,
+        chat_markers -> Nullable<Bool>,
+        chat_states -> Nullable<Bool>,
+    }
+}
+
+diesel::table! {
+    message_delivery (message_delivery_pk) {
+        message_delivery_pk -> Integer,
+        account -> Text,
+        jid -> Text,
+        message_id -> Text,
+        state -> Text,
+    }
+}
+
+diesel::table! {
+    muted_conversation (muted_conversation_pk) {
+        muted_conversation_pk -> Integer,
+        account -> Text,
+        jid -> Text,
+    }
+}
+
 diesel::table! {
     omemo_contact_device (contact_device_pk) {
         contact_device_pk -> Integer,
@@ -25,6 +101,7 @@ diesel::table! {
         account -> Text,
         id -> BigInt,
         identity -> Nullable<Binary>,
+        next_pre_key_id -> BigInt,
     }
 }
 
@@ -67,7 +144,56 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    pinned_message (pinned_message_pk) {
+        pinned_message_pk -> Integer,
+        account -> Text,
+        jid -> Text,
+        message_id -> Text,
+        timestamp -> Text,
+        author -> Text,
+        body -> Text,
+    }
+}
+
+diesel::table! {
+    read_position (read_position_pk) {
+        read_position_pk -> Integer,
+        account -> Text,
+        jid -> Text,
+        view -> BigInt,
+        last_read -> Nullable<Text>,
+        read_at -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    session_journal (session_journal_pk) {
+        session_journal_pk -> Integer,
+        account -> Text,
+        timestamp -> Text,
+        event -> Text,
+        detail -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    vip_conversation (vip_conversation_pk) {
+        vip_conversation_pk -> Integer,
+        account -> Text,
+        jid -> Text,
+    }
+}
+
 diesel::allow_tables_to_appear_in_same_query!(
+    avatar,
+    caps_cache,
+    channel_subject,
+    collapsed_roster_group,
+    conversation_lang,
+    conversation_privacy,
+    message_delivery,
+    muted_conversation,
     omemo_contact_device,
     omemo_identity,
     omemo_own_device,
@@ -75,4 +201,8 @@ diesel::allow_tables_to_appear_in_same_query!(
     omemo_sender_key,
     omemo_session,
     omemo_signed_pre_key,
+    pinned_message,
+    read_position,
+    session_journal,
+    vip_conversation,
 );
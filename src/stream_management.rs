@@ -0,0 +1,196 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+
+use xmpp_parsers::Element;
+
+use crate::account::Account;
+
+/// XEP-0198: Stream Management
+pub const NS_SM: &str = "urn:xmpp:sm:3";
+
+/// Number of sent stanzas after which an acknowledgement is requested right away
+const ACK_BATCH: u32 = 5;
+
+/// Delay after which an acknowledgement is requested for stanzas sent since the last request
+pub const ACK_DELAY: Duration = Duration::from_secs(2);
+
+/// What a stream management element received from the server means
+pub enum Handled {
+    Enabled,
+    Failed,
+    /// Answer to send back to the server
+    Answer(Element),
+    /// Stanzas the server acknowledged
+    Acked(Vec<Element>),
+    Ignored,
+}
+
+/// When to ask the server to acknowledge sent stanzas
+pub enum AckRequest {
+    Now(Element),
+    /// First stanza not covered by a request, one must be sent after ACK_DELAY
+    Later,
+    /// A request is already planned
+    Wait,
+}
+
+/// Counters of a stream on which stream management was asked for
+#[derive(Default)]
+struct Stream {
+    /// Set once the server enabled stream management, received stanzas are counted from then
+    enabled: bool,
+    /// Stanzas received since stream management was enabled
+    inbound: u32,
+    /// Stanzas sent since stream management was asked for
+    outbound: u32,
+    /// Stanzas sent since the last acknowledgement request
+    unrequested: u32,
+    /// Sent stanzas waiting for an acknowledgement, with their number
+    unacked: VecDeque<(u32, Element)>,
+}
+
+impl Stream {
+    /// Drop stanzas acknowledged by h, returning them
+    fn ack(&mut self, h: u32) -> Vec<Element> {
+        let mut acked = Vec::new();
+        // Counters wrap around, a stanza is acked when h isn't behind its number
+        while let Some((number, _)) = self.unacked.front() {
+            if (h.wrapping_sub(*number) as i32) < 0 {
+                break;
+            }
+            acked.push(self.unacked.pop_front().unwrap().1);
+        }
+        acked
+    }
+
+    fn take_unacked(&mut self) -> Vec<Element> {
+        self.unacked.drain(..).map(|(_, stanza)| stanza).collect()
+    }
+}
+
+/// Acknowledgements of stanzas sent on each connection. Sessions are not resumed: the client
+/// binds a resource before stream management can be enabled, so stanzas left unacknowledged
+/// when a connection is lost are sent again on the next session.
+pub struct StreamManagement {
+    streams: HashMap<Account, Stream>,
+}
+
+impl StreamManagement {
+    pub fn new() -> Self {
+        Self {
+            streams: HashMap::new(),
+        }
+    }
+
+    pub fn request() -> Element {
+        Element::builder("enable", NS_SM).build()
+    }
+
+    fn is_stanza(element: &Element) -> bool {
+        matches!(element.name(), "message" | "presence" | "iq")
+    }
+
+    /// Stream management was asked for, sent stanzas are counted from now
+    pub fn start(&mut self, account: &Account) {
+        self.streams.insert(account.clone(), Stream::default());
+    }
+
+    pub fn is_started(&self, account: &Account) -> bool {
+        self.streams.contains_key(account)
+    }
+
+    /// Count a stanza sent to the server, telling when to request an acknowledgement for it.
+    /// Nothing is returned for untracked stanzas.
+    pub fn sent(&mut self, account: &Account, stanza: Element) -> Option<AckRequest> {
+        let stream = self.streams.get_mut(account)?;
+        if !Self::is_stanza(&stanza) {
+            return None;
+        }
+        stream.outbound = stream.outbound.wrapping_add(1);
+        stream.unacked.push_back((stream.outbound, stanza));
+        stream.unrequested += 1;
+        Some(match stream.unrequested {
+            count if count >= ACK_BATCH => {
+                stream.unrequested = 0;
+                AckRequest::Now(Element::builder("r", NS_SM).build())
+            }
+            1 => AckRequest::Later,
+            _ => AckRequest::Wait,
+        })
+    }
+
+    /// Acknowledgement request for stanzas sent since the last one, if any
+    pub fn request_ack(&mut self, account: &Account) -> Option<Element> {
+        let stream = self.streams.get_mut(account)?;
+        if stream.unrequested == 0 {
+            return None;
+        }
+        stream.unrequested = 0;
+        Some(Element::builder("r", NS_SM).build())
+    }
+
+    /// Messages sent on each stream and not acknowledged by the server yet, by account
+    pub fn unacked(&self) -> Vec<(Account, &Element)> {
+        let mut unacked: Vec<(Account, &Element)> = self
+            .streams
+            .iter()
+            .flat_map(|(account, stream)| {
+                stream
+                    .unacked
+                    .iter()
+                    .filter(|(_, stanza)| stanza.name() == "message")
+                    .map(move |(_, stanza)| (account.clone(), stanza))
+            })
+            .collect();
+        unacked.sort_by_key(|(account, _)| account.to_string());
+        unacked
+    }
+
+    /// Count a stanza received from the server
+    pub fn received(&mut self, account: &Account, stanza: &Element) {
+        if let Some(stream) = self.streams.get_mut(account) {
+            if stream.enabled && Self::is_stanza(stanza) {
+                stream.inbound = stream.inbound.wrapping_add(1);
+            }
+        }
+    }
+
+    pub fn handle(&mut self, account: &Account, element: &Element) -> Handled {
+        let stream = match self.streams.get_mut(account) {
+            Some(stream) => stream,
+            None => return Handled::Ignored,
+        };
+        match element.name() {
+            "enabled" => {
+                stream.enabled = true;
+                Handled::Enabled
+            }
+            "failed" => {
+                self.streams.remove(account);
+                Handled::Failed
+            }
+            "r" => Handled::Answer(
+                Element::builder("a", NS_SM)
+                    .attr("h", stream.inbound.to_string())
+                    .build(),
+            ),
+            "a" => match element.attr("h").and_then(|h| h.parse::<u32>().ok()) {
+                Some(h) => Handled::Acked(stream.ack(h)),
+                None => Handled::Ignored,
+            },
+            _ => Handled::Ignored,
+        }
+    }
+
+    /// A connection is lost, its stream is forgotten and the stanzas left unacknowledged are
+    /// returned
+    pub fn lost(&mut self, account: &Account) -> Vec<Element> {
+        self.streams
+            .remove(account)
+            .map(|mut stream| stream.take_unacked())
+            .unwrap_or_default()
+    }
+}
@@ -14,6 +14,8 @@ use std::rc::Rc;
 use termion::raw::RawTerminal;
 use termion::screen::AlternateScreen;
 use unicode_segmentation::UnicodeSegmentation;
+use xmpp_parsers::data_forms::{DataForm, DataFormType, Field, FieldType};
+use xmpp_parsers::Jid;
 
 pub type Screen<W> = BufferedScreen<AlternateScreen<RawTerminal<W>>>;
 
@@ -79,31 +81,52 @@ pub fn term_string_visible_len(string: &str) -> usize {
     len
 }
 
-/// Remove all terminal specific chars sequences
+/// Whether a char can be written as is to the terminal. Control chars could move the
+/// cursor and bidirectional overrides could visually reorder the surrounding UI.
+fn is_displayable(c: char) -> bool {
+    !c.is_control()
+        && !matches!(
+            c,
+            '\u{200e}' | '\u{200f}' | '\u{202a}'..='\u{202e}' | '\u{2066}'..='\u{2069}'
+        )
+}
+
+/// Remove all terminal specific chars sequences, only keeping displayable chars.
+/// Tabulations and new lines are replaced by spaces.
 pub fn clean(string: &str) -> String {
     let mut output = String::new();
-    let mut iter = string.chars();
+    let mut iter = string.chars().peekable();
 
     while let Some(c) = iter.next() {
         match c {
-            '\x1b' => {
-                if let Some(c) = iter.next() {
-                    match c {
-                        '[' => {
-                            for c in iter.by_ref() {
-                                match c {
-                                    '\x30'..='\x3f' => {}     // parameter bytes
-                                    '\x20'..='\x2f' => {}     // intermediate bytes
-                                    '\x40'..='\x7e' => break, // final byte
-                                    _ => output.push(c),
-                                }
+            '\x1b' => match iter.next() {
+                // CSI: parameter and intermediate bytes up to the final byte
+                Some('[') => {
+                    for c in iter.by_ref() {
+                        if ('\x40'..='\x7e').contains(&c) {
+                            break;
+                        }
+                    }
+                }
+                // OSC, DCS, SOS, PM and APC: strings terminated by BEL or ST
+                Some(']' | 'P' | 'X' | '^' | '_') => {
+                    while let Some(c) = iter.next() {
+                        match c {
+                            '\x07' => break,
+                            '\x1b' => {
+                                iter.next_if_eq(&'\\');
+                                break;
                             }
+                            _ => {}
                         }
-                        _ => output.push(c),
                     }
                 }
-            }
-            _ => output.push(c),
+                // Other sequences are made of a single char
+                _ => {}
+            },
+            '\t' | '\n' => output.push(' '),
+            c if is_displayable(c) => output.push(c),
+            _ => {}
         }
     }
 
@@ -969,6 +992,10 @@ pub struct Input<E> {
     pub event_handler: Option<Rc<RefCell<Box<dyn FnMut(&mut Self, &mut E)>>>>,
     pub dirty: bool,
     width: usize,
+    /// Number of chars from which the length of the input is displayed
+    counter_threshold: Option<usize>,
+    /// Size limit in bytes of the input, the counter is highlighted above it
+    size_limit: Option<usize>,
 }
 
 impl<E> Input<E> {
@@ -984,6 +1011,40 @@ impl<E> Input<E> {
             event_handler: None,
             dirty: true,
             width: 0,
+            counter_threshold: None,
+            size_limit: None,
+        }
+    }
+
+    pub fn with_counter(mut self, threshold: usize, size_limit: Option<usize>) -> Self {
+        self.counter_threshold = Some(threshold);
+        self.size_limit = size_limit;
+        self
+    }
+
+    /// Length of the input, displayed at the end of the line for long inputs
+    fn counter(&self) -> String {
+        let threshold = match self.counter_threshold {
+            Some(threshold) if !self.password => threshold,
+            _ => return String::new(),
+        };
+
+        let len = self.buf.chars().count();
+        match self.size_limit {
+            Some(limit) if self.buf.len() > limit => format!(
+                " {}{}/{} bytes{}",
+                termion::color::Fg(termion::color::Red),
+                self.buf.len(),
+                limit,
+                termion::color::Fg(termion::color::Reset)
+            ),
+            _ if len >= threshold => format!(
+                " {}{}{}",
+                termion::color::Fg(termion::color::LightBlack),
+                len,
+                termion::color::Fg(termion::color::Reset)
+            ),
+            _ => String::new(),
         }
     }
 
@@ -1206,7 +1267,10 @@ where
     W: Write + AsFd,
 {
     fn render(&mut self, dimension: &Dimension, screen: &mut Screen<W>) {
-        self.width = dimension.w.unwrap() as usize;
+        let counter = self.counter();
+        let counter_len = term_string_visible_len(&counter) as u16;
+        let width = dimension.w.unwrap().saturating_sub(counter_len).max(1);
+        self.width = width as usize;
         match self.password {
             true => {
                 goto!(screen, dimension.x, dimension.y);
@@ -1217,17 +1281,17 @@ where
             }
             false => {
                 // Max displayable size is view width less 1 for cursor
-                let max_size = (dimension.w.unwrap() - 1) as usize;
+                let max_size = (width - 1) as usize;
 
                 // cursor must always be inside the view
                 if self.cursor < self.view {
                     if self.cursor < max_size {
                         self.view = Cursor::new(0);
                     } else {
-                        self.view = &self.cursor - (dimension.w.unwrap() as usize - 1);
+                        self.view = &self.cursor - max_size;
                     }
-                } else if self.cursor > &self.view + (dimension.w.unwrap() as usize - 1) {
-                    self.view = &self.cursor - (dimension.w.unwrap() as usize - 1);
+                } else if self.cursor > &self.view + max_size {
+                    self.view = &self.cursor - max_size;
                 }
                 assert!(self.cursor >= self.view);
                 assert!(self.cursor <= &self.view + (max_size + 1));
@@ -1238,10 +1302,15 @@ where
                 let cursor = &self.cursor - &self.view;
 
                 goto!(screen, dimension.x, dimension.y);
-                for _ in 0..max_size {
+                for _ in 0..dimension.w.unwrap() - 1 {
                     vprint!(screen, " ");
                 }
 
+                if !counter.is_empty() {
+                    goto!(screen, dimension.x + width, dimension.y);
+                    vprint!(screen, "{}", counter);
+                }
+
                 goto!(screen, dimension.x, dimension.y);
                 vprint!(screen, "{}", buf);
                 goto!(screen, dimension.x + cursor.get() as u16, dimension.y);
@@ -1302,6 +1371,14 @@ where
     width: usize,
     height: usize,
     layouts: Layouts,
+    /// Item the view is kept on, with an offset in lines, until next scroll
+    anchor: Option<(Box<dyn Fn(&I) -> bool>, usize)>,
+    /// Only items matching this filter are displayed
+    filter: Option<Box<dyn Fn(&I) -> bool>>,
+    /// Oldest items beyond this count are dropped while the bottom is displayed
+    limit: Option<usize>,
+    /// Display of items, their Display implementation by default
+    format: Option<Box<dyn Fn(&I) -> String>>,
 }
 
 impl<E, W, I> BufferedWin<E, W, I>
@@ -1321,6 +1398,10 @@ where
                 width: Layout::match_parent(),
                 height: Layout::match_parent(),
             },
+            anchor: None,
+            filter: None,
+            limit: None,
+            format: None,
         }
     }
 
@@ -1332,18 +1413,148 @@ where
         self
     }
 
+    /// Keep at most `limit` items, older ones being loaded again when scrolling up to them
+    pub fn with_limit(mut self, limit: Option<usize>) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// Display items with this function instead of their Display implementation
+    pub fn with_format<F>(mut self, format: F) -> Self
+    where
+        F: Fn(&I) -> String + 'static,
+    {
+        self.format = Some(Box::new(format));
+        self
+    }
+
+    /// Drop oldest items beyond the limit, unless scrolled up where they may be read
+    fn trim(&mut self) {
+        let limit = match self.limit {
+            Some(limit) => limit,
+            None => return,
+        };
+        if self.view != 0 || self.anchor.is_some() {
+            return;
+        }
+        while self.history.len() > limit {
+            self.history.pop_first();
+            self.dirty = true;
+        }
+    }
+
     #[allow(unused)]
     pub fn with_layouts(mut self, layouts: Layouts) -> Self {
         self.layouts = layouts;
         self
     }
 
+    /// Only display items matching `filter`, the view is moved back to the bottom
+    pub fn set_filter<F>(&mut self, filter: F)
+    where
+        F: Fn(&I) -> bool + 'static,
+    {
+        self.filter = Some(Box::new(filter));
+        self.anchor = None;
+        self.view = 0;
+        self.dirty = true;
+    }
+
+    fn visible_items(&self) -> impl DoubleEndedIterator<Item = &I> + '_ {
+        self.history
+            .iter()
+            .filter(move |item| self.filter.as_ref().map_or(true, |filter| filter(*item)))
+    }
+
+    /// Scroll up to the most recent item matching `matches` that is not entirely displayed yet,
+    /// it is then displayed at the bottom of the view. Return false when nothing matches.
+    pub fn search<F>(&mut self, matches: F) -> bool
+    where
+        F: Fn(&I) -> bool,
+    {
+        let mut below = 0;
+        let mut after = None;
+        for item in self.visible_items().rev() {
+            let len = self.render_items(std::iter::once(item)).len();
+            if below + len > self.view + self.height && matches(item) {
+                after = Some(below);
+                break;
+            }
+            below += len;
+        }
+
+        match after {
+            Some(after) => {
+                let count = self.get_rendered_items().len();
+                self.anchor = None;
+                self.view = cmp::min(after, count.saturating_sub(self.height));
+                self.dirty = true;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Scroll to the first item matching `matches`, it is then displayed at the top of the view.
+    /// Return false when nothing matches.
+    pub fn jump<F>(&mut self, matches: F) -> bool
+    where
+        F: Fn(&I) -> bool,
+    {
+        let index = match self.visible_items().position(|item| matches(item)) {
+            Some(index) => index,
+            None => return false,
+        };
+
+        let from = self.render_items(self.visible_items().skip(index)).len();
+        let count = self.get_rendered_items().len();
+        self.anchor = None;
+        self.view = cmp::min(
+            from.saturating_sub(self.height),
+            count.saturating_sub(self.height),
+        );
+        self.dirty = true;
+        true
+    }
+
+    /// Keep the bottom of the first item matching `anchor` `offset` lines below the bottom of the
+    /// view, until next scroll
+    pub fn set_anchor<F>(&mut self, anchor: F, offset: usize)
+    where
+        F: Fn(&I) -> bool + 'static,
+    {
+        self.anchor = Some((Box::new(anchor), offset));
+        self.dirty = true;
+    }
+
+    /// Update view according to anchor, once the anchored item is known
+    fn apply_anchor(&mut self, count: usize) {
+        if let Some((anchor, offset)) = &self.anchor {
+            if let Some(index) = self.visible_items().position(|item| anchor(item)) {
+                let after = self
+                    .render_items(self.visible_items().skip(index + 1))
+                    .len();
+                self.view = cmp::min(after + offset, count.saturating_sub(self.height));
+            }
+        }
+    }
+
     fn get_rendered_items(&self) -> Vec<String> {
+        self.render_items(self.visible_items())
+    }
+
+    fn render_items<'a>(&self, items: impl Iterator<Item = &'a I>) -> Vec<String>
+    where
+        I: 'a,
+    {
         let max_len = self.width;
         let mut buffers: Vec<String> = Vec::new();
 
-        for buf in &self.history {
-            let formatted = format!("{buf}");
+        for buf in items {
+            let formatted = match &self.format {
+                Some(format) => format(buf),
+                None => format!("{buf}"),
+            };
             for line in formatted.lines() {
                 let mut words = line.split_word_bounds();
 
@@ -1437,10 +1648,32 @@ where
         buffers
     }
 
+    /// Insert many items at once, e.g. a page of history, without looking for each of them
+    /// whether it is visible. Items sharing their identity replace each other in order.
+    pub fn insert_all<T: IntoIterator<Item = I>>(&mut self, items: T) {
+        for item in items {
+            self.history.replace(item);
+            self.dirty = true;
+        }
+        self.trim();
+    }
+
+    /// Remove an item, looked up with the same ordering it was inserted with
+    pub fn remove(&mut self, item: &I) {
+        if self.history.remove(item) {
+            self.dirty = true;
+        }
+    }
+
     #[allow(dead_code)]
     pub fn first<'a>(&'a self) -> Option<&'a I> {
         self.history.iter().next()
     }
+
+    /// Whether rendered history covers the whole viewport, unknown size counts as filled
+    pub fn is_filled(&self) -> bool {
+        self.height == 0 || self.get_rendered_items().len() >= self.height
+    }
 }
 
 impl<E, W, I> Window<E, W, I> for BufferedWin<E, W, I>
@@ -1460,9 +1693,11 @@ where
                 .unwrap_or(self.history.len());
         self.history.replace(item);
         self.dirty |= position >= self.view && position <= self.view + self.height;
+        self.trim();
     }
 
     fn page_up(&mut self) -> bool {
+        self.anchor = None;
         let buffers = self.get_rendered_items();
         let count = buffers.len();
 
@@ -1484,12 +1719,14 @@ where
     }
 
     fn page_down(&mut self) -> bool {
+        self.anchor = None;
         self.dirty = true;
         if self.view > self.height {
             self.view -= self.height;
             false
         } else {
             self.view = 0;
+            self.trim();
             true
         }
     }
@@ -1509,6 +1746,7 @@ where
 
         let buffers = self.get_rendered_items();
         let count = buffers.len();
+        self.apply_anchor(count);
         let mut iter = buffers.iter();
 
         if count > dimension.h.unwrap() as usize {
@@ -1560,10 +1798,14 @@ where
     V: fmt::Display + Hash + Eq,
 {
     items: LinkedHashMap<Option<G>, HashSet<V>>,
+    /// Groups whose items are hidden
+    collapsed: HashSet<G>,
     unique: bool,
     sort_item: Option<Box<dyn FnMut(&V, &V) -> cmp::Ordering>>,
     #[allow(dead_code)]
     sort_group: Option<Box<dyn FnMut(&G, &G) -> cmp::Ordering>>,
+    /// Display of items, their Display implementation by default
+    format_item: Option<Box<dyn Fn(&V) -> String>>,
     event_handler: Option<Rc<RefCell<Box<dyn FnMut(&mut Self, &mut E)>>>>,
     dirty: bool,
     layouts: Layouts,
@@ -1577,9 +1819,11 @@ where
     pub fn new() -> Self {
         Self {
             items: LinkedHashMap::new(),
+            collapsed: HashSet::new(),
             unique: false,
             sort_item: None,
             sort_group: None,
+            format_item: None,
             event_handler: None,
             dirty: true,
             layouts: Layouts {
@@ -1602,6 +1846,15 @@ where
         self
     }
 
+    /// Display items with this function instead of their Display implementation
+    pub fn with_format_item<F>(mut self, format: F) -> Self
+    where
+        F: Fn(&V) -> String + 'static,
+    {
+        self.format_item = Some(Box::new(format));
+        self
+    }
+
     pub fn with_none_group(mut self) -> Self {
         if let Entry::Vacant(vacant) = self.items.entry(None) {
             vacant.insert(HashSet::new());
@@ -1687,6 +1940,61 @@ where
             }
         }
     }
+
+    /// Hide or show the items of a group, only its name and item count being shown when hidden
+    pub fn set_collapsed(&mut self, group: G, collapsed: bool) {
+        match collapsed {
+            true => self.dirty |= self.collapsed.insert(group),
+            false => self.dirty |= self.collapsed.remove(&group),
+        }
+    }
+
+    /// Render again items whose display changes along time
+    pub fn refresh(&mut self) {
+        self.dirty = true;
+    }
+
+    pub fn groups(&self) -> impl Iterator<Item = &G> {
+        self.items.keys().flatten()
+    }
+
+    pub fn is_collapsed(&self, group: &G) -> bool {
+        self.collapsed.contains(group)
+    }
+
+    fn is_group_collapsed(&self, group: &Option<G>) -> bool {
+        matches!(group, Some(group) if self.collapsed.contains(group))
+    }
+
+    fn format_item(&self, item: &V) -> String {
+        match &self.format_item {
+            Some(format) => format(item),
+            None => format!("{item}"),
+        }
+    }
+
+    fn format_group(&self, group: &G, items: &HashSet<V>) -> String {
+        match self.collapsed.contains(group) {
+            true => format!("{group} (+{})", items.len()),
+            false => format!("{group}"),
+        }
+    }
+
+    /// Keep only items matching the predicate, groups emptied by the operation are removed
+    pub fn retain<F>(&mut self, mut keep: F)
+    where
+        F: FnMut(&V) -> bool,
+    {
+        let items = std::mem::replace(&mut self.items, LinkedHashMap::new());
+        for (group, mut values) in items {
+            let len = values.len();
+            values.retain(|value| keep(value));
+            self.dirty |= values.len() != len;
+            if len == 0 || !values.is_empty() {
+                self.items.insert(group, values);
+            }
+        }
+    }
 }
 
 impl<E, W, G, V> View<E, W> for ListView<E, W, G, V>
@@ -1708,8 +2016,13 @@ where
                 let mut width: u16 = 0;
                 for (group, items) in &self.items {
                     if let Some(group) = group {
-                        width =
-                            cmp::max(width, term_string_visible_len(&format!("{group}")) as u16);
+                        width = cmp::max(
+                            width,
+                            term_string_visible_len(&self.format_group(group, items)) as u16,
+                        );
+                    }
+                    if self.is_group_collapsed(group) {
+                        continue;
                     }
 
                     let indent = match group {
@@ -1720,7 +2033,8 @@ where
                     for item in items {
                         width = cmp::max(
                             width,
-                            term_string_visible_len(&format!("{indent}{item}")) as u16,
+                            term_string_visible_len(&format!("{indent}{}", self.format_item(item)))
+                                as u16,
                         );
                     }
                 }
@@ -1744,6 +2058,9 @@ where
                     if group.is_some() {
                         height += 1;
                     }
+                    if self.is_group_collapsed(group) {
+                        continue;
+                    }
 
                     height += items.len() as u16;
                 }
@@ -1782,13 +2099,16 @@ where
 
             goto!(screen, dimension.x, y);
 
-            if group.is_some() {
-                let mut disp = format!("{}", group.as_ref().unwrap());
+            if let Some(group) = group {
+                let mut disp = self.format_group(group, items);
                 if term_string_visible_len(&disp) > width {
                     disp = term_string_visible_truncate(&disp, width, Some("…"));
                 }
                 vprint!(screen, "{}", disp);
                 y += 1;
+                if self.collapsed.contains(group) {
+                    continue;
+                }
             }
 
             let mut items = items.iter().collect::<Vec<&V>>();
@@ -1804,8 +2124,8 @@ where
                 goto!(screen, dimension.x, y);
 
                 let mut disp = match group {
-                    Some(_) => format!("  {item}"),
-                    None => format!("{item}"),
+                    Some(_) => format!("  {}", self.format_item(item)),
+                    None => self.format_item(item),
                 };
                 if term_string_visible_len(&disp) > width {
                     disp = term_string_visible_truncate(&disp, width, Some("…"));
@@ -1838,98 +2158,1017 @@ where
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use mockall::predicate::*;
-    use mockall::*;
+pub struct TableView<E, W> {
+    headers: Vec<String>,
+    rows: Vec<Vec<String>>,
+    /// Only display rows containing this text (case insensitive)
+    filter: Option<String>,
+    /// Index of the first displayed row among filtered ones
+    view: usize,
+    height: usize,
+    event_handler: Option<Rc<RefCell<Box<dyn FnMut(&mut Self, &mut E)>>>>,
+    dirty: bool,
+    layouts: Layouts,
+}
 
-    #[test]
-    fn test_term_string_visible_len_is_correct() {
-        assert_eq!(
-            term_string_visible_len(&format!(
-                "{}ab{}",
-                termion::color::Bg(termion::color::Red),
-                termion::cursor::Goto(1, 123)
-            )),
-            2
-        );
-        assert_eq!(
-            term_string_visible_len(&format!(
-                "{}ab{}",
-                termion::cursor::Goto(1, 123),
-                termion::color::Bg(termion::color::Red)
-            )),
-            2
-        );
-        assert_eq!(
-            term_string_visible_len(&format!(
-                "{}🍻{}",
-                termion::cursor::Goto(1, 123),
-                termion::color::Bg(termion::color::Red)
-            )),
-            1
-        );
-        assert_eq!(
-            term_string_visible_len(&format!(
-                "{}12:34:56 - {}me:{}",
-                termion::color::Fg(termion::color::White),
-                termion::color::Fg(termion::color::Yellow),
-                termion::color::Fg(termion::color::White)
-            )),
-            14
-        )
+impl<E, W> TableView<E, W> {
+    pub fn new<S: Into<String>>(headers: Vec<S>) -> Self {
+        Self {
+            headers: headers.into_iter().map(Into::into).collect(),
+            rows: Vec::new(),
+            filter: None,
+            view: 0,
+            height: 0,
+            event_handler: None,
+            dirty: true,
+            layouts: Layouts {
+                width: Layout::match_parent(),
+                height: Layout::match_parent(),
+            },
+        }
     }
 
-    mock! {
-        Writer {
-        }
+    pub fn with_event<F>(mut self, event_handler: F) -> Self
+    where
+        F: FnMut(&mut Self, &mut E) + 'static,
+    {
+        self.event_handler = Some(Rc::new(RefCell::new(Box::new(event_handler))));
+        self
+    }
 
-        impl Write for Writer {
-            fn write(&mut self, buf: &[u8]) -> std::result::Result<usize, std::io::Error> {
-            }
-            fn flush(&mut self) -> std::result::Result<(), std::io::Error> {
+    /// Set displayed rows, cells are cleaned as they usually hold remote content
+    pub fn set_rows(&mut self, rows: Vec<Vec<String>>) {
+        self.rows = rows
+            .iter()
+            .map(|row| row.iter().map(|cell| clean(cell)).collect())
+            .collect();
+        self.view = 0;
+        self.dirty = true;
+    }
+
+    pub fn set_filter(&mut self, filter: Option<String>) {
+        self.filter = filter.map(|filter| filter.to_lowercase());
+        self.view = 0;
+        self.dirty = true;
+    }
+
+    /// Rows matching the current filter
+    pub fn filtered_rows(&self) -> Vec<&Vec<String>> {
+        self.rows
+            .iter()
+            .filter(|row| match &self.filter {
+                Some(filter) => row.iter().any(|cell| cell.to_lowercase().contains(filter)),
+                None => true,
+            })
+            .collect()
+    }
+
+    /// Width of each column, enough to display its largest cell
+    fn column_widths(&self, rows: &[&Vec<String>]) -> Vec<usize> {
+        let mut widths = self
+            .headers
+            .iter()
+            .map(|header| term_string_visible_len(header))
+            .collect::<Vec<_>>();
+        for row in rows {
+            for (i, cell) in row.iter().enumerate() {
+                let len = term_string_visible_len(cell);
+                match widths.get_mut(i) {
+                    Some(width) => *width = cmp::max(*width, len),
+                    None => widths.push(len),
+                }
             }
         }
+        widths
     }
 
-    #[test]
-    fn test_input_backspace() {
-        // Given
-        let mut input = Input::<()>::new();
+    fn format_row<S: AsRef<str>>(row: &[S], widths: &[usize]) -> String {
+        row.iter()
+            .zip(widths.iter())
+            .map(|(cell, width)| {
+                let cell = cell.as_ref();
+                let padding = width - term_string_visible_len(cell);
+                format!("{}{}", cell, " ".repeat(padding))
+            })
+            .collect::<Vec<_>>()
+            .join("  ")
+            .trim_end()
+            .to_string()
+    }
 
-        // When
-        input.key('a');
-        input.key('b');
-        input.key('c');
-        input.backspace();
+    pub fn page_up(&mut self) {
+        let page = self.height.saturating_sub(1);
+        self.view = self.view.saturating_sub(page);
+        self.dirty = true;
+    }
 
-        // Then
-        assert_eq!(input.buf, "ab".to_string());
+    pub fn page_down(&mut self) {
+        let page = self.height.saturating_sub(1);
+        let count = self.filtered_rows().len();
+        self.view = cmp::min(self.view + page, count.saturating_sub(page));
+        self.dirty = true;
     }
 
-    #[test]
-    fn test_term_string_clean() {
-        // Given
-        let input = "test \x1b[5mBlink";
+    /// Scroll just enough for a filtered row to be displayed
+    pub fn scroll_to(&mut self, row: usize) {
+        let page = self.height.saturating_sub(1);
+        if row < self.view {
+            self.view = row;
+        } else if page > 0 && row >= self.view + page {
+            self.view = row + 1 - page;
+        }
+        self.dirty = true;
+    }
+}
 
-        // When
-        let cleaned = clean(input);
+impl<E, W> View<E, W> for TableView<E, W>
+where
+    W: Write + AsFd,
+{
+    fn render(&mut self, dimension: &Dimension, screen: &mut Screen<W>) {
+        save_cursor!(screen);
+        let width: usize = dimension.w.unwrap().into();
+        self.height = dimension.h.unwrap().into();
+
+        let rows = self.filtered_rows();
+        let widths = self.column_widths(&rows);
+        let mut lines = vec![format!(
+            "{}{}{}",
+            termion::style::Bold,
+            Self::format_row(&self.headers, &widths),
+            termion::style::Reset
+        )];
+        lines.extend(
+            rows.iter()
+                .skip(self.view)
+                .map(|row| Self::format_row(row, &widths)),
+        );
 
-        // Then
-        assert_eq!(cleaned, "test Blink");
-    }
+        let mut lines = lines.iter();
+        for y in dimension.y..dimension.y + dimension.h.unwrap() {
+            goto!(screen, dimension.x, y);
+            for _ in dimension.x..dimension.x + dimension.w.unwrap() {
+                vprint!(screen, " ");
+            }
 
-    #[test]
-    fn test_term_string_visible_truncate() {
-        // Given
-        let input = "test \x1b[5mBlink";
+            goto!(screen, dimension.x, y);
+            if let Some(line) = lines.next() {
+                if term_string_visible_len(line) > width {
+                    vprint!(
+                        screen,
+                        "{}",
+                        term_string_visible_truncate(line, width, Some("…"))
+                    );
+                } else {
+                    vprint!(screen, "{}", line);
+                }
+            }
+        }
 
-        // When
-        let truncated = term_string_visible_truncate(input, 6, None);
+        restore_cursor!(screen);
 
-        // Then
-        assert_eq!(truncated, "test \x1b[5mB");
+        self.dirty = false;
+    }
+
+    fn event(&mut self, event: &mut E) {
+        if let Some(handler) = &self.event_handler {
+            let handler = Rc::clone(handler);
+            let handler = &mut *handler.borrow_mut();
+            handler(self, event);
+        }
+    }
+
+    fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    fn get_layouts(&self) -> Layouts {
+        self.layouts.clone()
+    }
+}
+
+/// Width given to each cell of a GridView, wide enough for an emoji and its padding
+const GRID_CELL_WIDTH: u16 = 4;
+
+/// Grid of short cells, one of them being selected
+pub struct GridView<E, W> {
+    title: String,
+    cells: Vec<String>,
+    selected: usize,
+    /// Number of cells per line, depends on the width given at render
+    columns: usize,
+    event_handler: Option<Rc<RefCell<Box<dyn FnMut(&mut Self, &mut E)>>>>,
+    dirty: bool,
+    layouts: Layouts,
+}
+
+impl<E, W> GridView<E, W> {
+    pub fn new() -> Self {
+        Self {
+            title: String::new(),
+            cells: Vec::new(),
+            selected: 0,
+            columns: 1,
+            event_handler: None,
+            dirty: true,
+            layouts: Layouts {
+                width: Layout::match_parent(),
+                height: Layout::match_parent(),
+            },
+        }
+    }
+
+    pub fn with_event<F>(mut self, event_handler: F) -> Self
+    where
+        F: FnMut(&mut Self, &mut E) + 'static,
+    {
+        self.event_handler = Some(Rc::new(RefCell::new(Box::new(event_handler))));
+        self
+    }
+
+    pub fn set_title<S: Into<String>>(&mut self, title: S) {
+        self.title = title.into();
+        self.dirty = true;
+    }
+
+    /// Set displayed cells and select the first one
+    pub fn set_cells(&mut self, cells: Vec<String>) {
+        self.cells = cells.iter().map(|cell| clean(cell)).collect();
+        self.selected = 0;
+        self.dirty = true;
+    }
+
+    pub fn selected(&self) -> Option<&str> {
+        self.cells.get(self.selected).map(String::as_str)
+    }
+
+    /// Move the selection by columns and lines, staying within the grid
+    pub fn move_by(&mut self, dx: isize, dy: isize) {
+        if self.cells.is_empty() {
+            return;
+        }
+        let columns = cmp::max(self.columns, 1) as isize;
+        let last = self.cells.len() as isize - 1;
+        let mut x = self.selected as isize % columns + dx;
+        let mut y = self.selected as isize / columns + dy;
+        x = x.clamp(0, columns - 1);
+        y = y.clamp(0, last / columns);
+        self.selected = cmp::min(y * columns + x, last) as usize;
+        self.dirty = true;
+    }
+}
+
+impl<E, W> View<E, W> for GridView<E, W>
+where
+    W: Write + AsFd,
+{
+    fn render(&mut self, dimension: &Dimension, screen: &mut Screen<W>) {
+        save_cursor!(screen);
+        let width = dimension.w.unwrap();
+        self.columns = cmp::max(width / GRID_CELL_WIDTH, 1).into();
+
+        for y in dimension.y..dimension.y + dimension.h.unwrap() {
+            goto!(screen, dimension.x, y);
+            for _ in dimension.x..dimension.x + width {
+                vprint!(screen, " ");
+            }
+        }
+
+        goto!(screen, dimension.x, dimension.y);
+        vprint!(
+            screen,
+            "{}{}{}",
+            termion::style::Bold,
+            term_string_visible_truncate(&self.title, width.into(), Some("…")),
+            termion::style::Reset
+        );
+
+        for (index, cell) in self.cells.iter().enumerate() {
+            let y = dimension.y + 2 + (index / self.columns) as u16;
+            if y >= dimension.y + dimension.h.unwrap() {
+                break;
+            }
+            let x = dimension.x + (index % self.columns) as u16 * GRID_CELL_WIDTH;
+            goto!(screen, x, y);
+            match index == self.selected {
+                true => vprint!(
+                    screen,
+                    "{} {} {}",
+                    termion::style::Invert,
+                    cell,
+                    termion::style::NoInvert
+                ),
+                false => vprint!(screen, " {} ", cell),
+            }
+        }
+
+        restore_cursor!(screen);
+
+        self.dirty = false;
+    }
+
+    fn event(&mut self, event: &mut E) {
+        if let Some(handler) = &self.event_handler {
+            let handler = Rc::clone(handler);
+            let handler = &mut *handler.borrow_mut();
+            handler(self, event);
+        }
+    }
+
+    fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    fn get_layouts(&self) -> Layouts {
+        self.layouts.clone()
+    }
+}
+
+/// Value being edited of a data form field
+#[derive(Debug, Clone)]
+enum FormValue {
+    /// Text fields, multiple values being separated by commas
+    Text(String),
+    Boolean(bool),
+    /// Index of the chosen option
+    Single(Option<usize>),
+    /// Chosen options, and the option currently pointed at
+    Multi(Vec<bool>, usize),
+    /// Fixed and hidden fields, not editable
+    Fixed,
+}
+
+/// Interactive rendering of a data form (XEP-0004)
+///
+/// Keys are given by the event handler: `previous` and `next` move between editable fields,
+/// `key` and `backspace` edit text fields, `left` and `right` choose among options and
+/// `validate` returns the form to submit once every field is valid.
+pub struct FormView<E, W> {
+    form: DataForm,
+    values: Vec<FormValue>,
+    /// Index of the selected field among all fields
+    selected: Option<usize>,
+    /// Why the form cannot be submitted yet
+    error: Option<String>,
+    view: usize,
+    height: usize,
+    event_handler: Option<Rc<RefCell<Box<dyn FnMut(&mut Self, &mut E)>>>>,
+    dirty: bool,
+    layouts: Layouts,
+}
+
+impl<E, W> FormView<E, W> {
+    pub fn new(form: DataForm) -> Self {
+        let values = form.fields.iter().map(Self::initial_value).collect();
+        let mut view = Self {
+            form,
+            values,
+            selected: None,
+            error: None,
+            view: 0,
+            height: 0,
+            event_handler: None,
+            dirty: true,
+            layouts: Layouts {
+                width: Layout::match_parent(),
+                height: Layout::match_parent(),
+            },
+        };
+        let first = view.editable().next();
+        view.selected = first;
+        view
+    }
+
+    pub fn with_event<F>(mut self, event_handler: F) -> Self
+    where
+        F: FnMut(&mut Self, &mut E) + 'static,
+    {
+        self.event_handler = Some(Rc::new(RefCell::new(Box::new(event_handler))));
+        self
+    }
+
+    fn initial_value(field: &Field) -> FormValue {
+        match field.type_ {
+            FieldType::Fixed | FieldType::Hidden => FormValue::Fixed,
+            FieldType::Boolean => FormValue::Boolean(matches!(
+                field.values.first().map(String::as_str),
+                Some("1") | Some("true")
+            )),
+            FieldType::ListSingle => FormValue::Single(
+                field
+                    .values
+                    .first()
+                    .and_then(|value| field.options.iter().position(|o| &o.value == value)),
+            ),
+            FieldType::ListMulti => FormValue::Multi(
+                field
+                    .options
+                    .iter()
+                    .map(|option| field.values.contains(&option.value))
+                    .collect(),
+                0,
+            ),
+            _ => FormValue::Text(field.values.join(", ")),
+        }
+    }
+
+    /// Indexes of fields that can be edited
+    fn editable(&self) -> impl DoubleEndedIterator<Item = usize> + '_ {
+        self.values
+            .iter()
+            .enumerate()
+            .filter(|(_, value)| !matches!(value, FormValue::Fixed))
+            .map(|(index, _)| index)
+    }
+
+    pub fn set_form(&mut self, form: DataForm) {
+        *self = Self {
+            event_handler: self.event_handler.take(),
+            ..Self::new(form)
+        };
+    }
+
+    pub fn previous(&mut self) {
+        if let Some(selected) = self.selected {
+            let previous = self.editable().rev().find(|index| *index < selected);
+            if let Some(previous) = previous {
+                self.selected = Some(previous);
+                self.dirty = true;
+            }
+        }
+    }
+
+    pub fn next(&mut self) {
+        if let Some(selected) = self.selected {
+            let next = self.editable().find(|index| *index > selected);
+            if let Some(next) = next {
+                self.selected = Some(next);
+                self.dirty = true;
+            }
+        }
+    }
+
+    fn selected_value(&mut self) -> Option<&mut FormValue> {
+        self.dirty = true;
+        self.values.get_mut(self.selected?)
+    }
+
+    /// Type in a text field, space toggling booleans and options
+    pub fn key(&mut self, c: char) {
+        let options = self.selected_options();
+        match self.selected_value() {
+            Some(FormValue::Text(text)) => text.push(c),
+            Some(FormValue::Boolean(value)) if c == ' ' => *value = !*value,
+            Some(FormValue::Single(choice)) if c == ' ' && options > 0 => {
+                *choice = Some(choice.map_or(0, |choice| (choice + 1) % options))
+            }
+            Some(FormValue::Multi(chosen, cursor)) if c == ' ' => {
+                if let Some(chosen) = chosen.get_mut(*cursor) {
+                    *chosen = !*chosen;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn backspace(&mut self) {
+        if let Some(FormValue::Text(text)) = self.selected_value() {
+            let last = text.grapheme_indices(true).last().map(|(index, _)| index);
+            if let Some(index) = last {
+                text.truncate(index);
+            }
+        }
+    }
+
+    pub fn left(&mut self) {
+        let options = self.selected_options();
+        match self.selected_value() {
+            Some(FormValue::Boolean(value)) => *value = !*value,
+            Some(FormValue::Single(choice)) if options > 0 => {
+                *choice = Some(choice.map_or(0, |choice| (choice + options - 1) % options))
+            }
+            Some(FormValue::Multi(_, cursor)) => *cursor = cursor.saturating_sub(1),
+            _ => {}
+        }
+    }
+
+    pub fn right(&mut self) {
+        let options = self.selected_options();
+        match self.selected_value() {
+            Some(FormValue::Boolean(value)) => *value = !*value,
+            Some(FormValue::Single(choice)) if options > 0 => {
+                *choice = Some(choice.map_or(0, |choice| (choice + 1) % options))
+            }
+            Some(FormValue::Multi(_, cursor)) if options > 0 => {
+                *cursor = cmp::min(*cursor + 1, options - 1)
+            }
+            _ => {}
+        }
+    }
+
+    fn selected_options(&self) -> usize {
+        self.selected
+            .and_then(|selected| self.form.fields.get(selected))
+            .map_or(0, |field| field.options.len())
+    }
+
+    /// Values of a field as they would be submitted
+    fn field_values(field: &Field, value: &FormValue) -> Vec<String> {
+        match value {
+            FormValue::Text(text) => match field.type_ {
+                FieldType::TextMulti | FieldType::JidMulti => text
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|value| !value.is_empty())
+                    .map(String::from)
+                    .collect(),
+                _ if text.is_empty() => vec![],
+                _ => vec![text.clone()],
+            },
+            FormValue::Boolean(value) => vec![String::from(if *value { "1" } else { "0" })],
+            FormValue::Single(choice) => choice
+                .and_then(|choice| field.options.get(choice))
+                .map(|option| vec![option.value.clone()])
+                .unwrap_or_default(),
+            FormValue::Multi(chosen, _) => field
+                .options
+                .iter()
+                .zip(chosen.iter())
+                .filter(|(_, chosen)| **chosen)
+                .map(|(option, _)| option.value.clone())
+                .collect(),
+            FormValue::Fixed => field.values.clone(),
+        }
+    }
+
+    fn check_field(field: &Field, values: &[String]) -> Result<(), String> {
+        let name = field.label.as_ref().unwrap_or(&field.var);
+        if field.required && values.is_empty() {
+            return Err(format!("{name} is required"));
+        }
+        if matches!(field.type_, FieldType::JidSingle | FieldType::JidMulti) {
+            if let Some(value) = values.iter().find(|value| value.parse::<Jid>().is_err()) {
+                return Err(format!("{value} is not a valid jid for {name}"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Form to submit, or None after selecting the first invalid field and telling why
+    pub fn validate(&mut self) -> Option<DataForm> {
+        let mut fields = Vec::new();
+        for (index, (field, value)) in self.form.fields.iter().zip(self.values.iter()).enumerate() {
+            if field.type_ == FieldType::Fixed {
+                continue;
+            }
+            let values = Self::field_values(field, value);
+            if let Err(err) = Self::check_field(field, &values) {
+                self.selected = Some(index);
+                self.error = Some(err);
+                self.dirty = true;
+                return None;
+            }
+            fields.push(Field {
+                values,
+                ..field.clone()
+            });
+        }
+
+        self.error = None;
+        self.dirty = true;
+        Some(DataForm {
+            type_: DataFormType::Submit,
+            form_type: self.form.form_type.clone(),
+            title: None,
+            instructions: None,
+            fields,
+        })
+    }
+
+    /// Lines of the form, with the index of the first line of the selected field
+    fn lines(&self) -> (Vec<String>, usize) {
+        let mut lines = Vec::new();
+        if let Some(title) = &self.form.title {
+            lines.push(format!(
+                "{}{}{}",
+                termion::style::Bold,
+                clean(title),
+                termion::style::Reset
+            ));
+        }
+        if let Some(instructions) = &self.form.instructions {
+            lines.extend(instructions.lines().map(clean));
+        }
+        if !lines.is_empty() {
+            lines.push(String::new());
+        }
+
+        let mut selected_line = 0;
+        for (index, (field, value)) in self.form.fields.iter().zip(self.values.iter()).enumerate() {
+            let selected = self.selected == Some(index);
+            if selected {
+                selected_line = lines.len();
+            }
+            let marker = if selected { ">" } else { " " };
+            let name = clean(field.label.as_ref().unwrap_or(&field.var));
+            let required = if field.required { "*" } else { "" };
+            match value {
+                FormValue::Fixed if field.type_ == FieldType::Hidden => {}
+                FormValue::Fixed => lines.extend(field.values.iter().map(|value| clean(value))),
+                FormValue::Text(text) if field.type_ == FieldType::TextPrivate => {
+                    let masked = "*".repeat(text.graphemes(true).count());
+                    lines.push(format!("{marker} {name}{required}: {masked}"));
+                }
+                FormValue::Text(text) => {
+                    lines.push(format!("{marker} {name}{required}: {}", clean(text)))
+                }
+                FormValue::Boolean(value) => {
+                    let check = if *value { "x" } else { " " };
+                    lines.push(format!("{marker} {name}{required}: [{check}]"));
+                }
+                FormValue::Single(choice) => {
+                    let option = choice
+                        .and_then(|choice| field.options.get(choice))
+                        .map(|option| clean(option.label.as_ref().unwrap_or(&option.value)))
+                        .unwrap_or_default();
+                    lines.push(format!("{marker} {name}{required}: < {option} >"));
+                }
+                FormValue::Multi(chosen, cursor) => {
+                    lines.push(format!("{marker} {name}{required}:"));
+                    for (position, (option, chosen)) in
+                        field.options.iter().zip(chosen.iter()).enumerate()
+                    {
+                        let pointer = if selected && position == *cursor {
+                            ">"
+                        } else {
+                            " "
+                        };
+                        let check = if *chosen { "x" } else { " " };
+                        let label = clean(option.label.as_ref().unwrap_or(&option.value));
+                        lines.push(format!("   {pointer} [{check}] {label}"));
+                    }
+                }
+            }
+        }
+
+        if let Some(error) = &self.error {
+            lines.push(String::new());
+            lines.push(format!(
+                "{}{}{}",
+                termion::color::Fg(termion::color::Red),
+                clean(error),
+                termion::color::Fg(termion::color::Reset)
+            ));
+        }
+
+        (lines, selected_line)
+    }
+}
+
+impl<E, W> View<E, W> for FormView<E, W>
+where
+    W: Write + AsFd,
+{
+    fn render(&mut self, dimension: &Dimension, screen: &mut Screen<W>) {
+        save_cursor!(screen);
+        let width: usize = dimension.w.unwrap().into();
+        self.height = dimension.h.unwrap().into();
+
+        let (lines, selected_line) = self.lines();
+        // Keep the selected field displayed
+        if selected_line < self.view {
+            self.view = selected_line;
+        } else if self.height > 0 && selected_line >= self.view + self.height {
+            self.view = selected_line + 1 - self.height;
+        }
+
+        let mut lines = lines.iter().skip(self.view);
+        for y in dimension.y..dimension.y + dimension.h.unwrap() {
+            goto!(screen, dimension.x, y);
+            for _ in dimension.x..dimension.x + dimension.w.unwrap() {
+                vprint!(screen, " ");
+            }
+
+            goto!(screen, dimension.x, y);
+            if let Some(line) = lines.next() {
+                if term_string_visible_len(line) > width {
+                    vprint!(
+                        screen,
+                        "{}",
+                        term_string_visible_truncate(line, width, Some("…"))
+                    );
+                } else {
+                    vprint!(screen, "{}", line);
+                }
+            }
+        }
+
+        restore_cursor!(screen);
+
+        self.dirty = false;
+    }
+
+    fn event(&mut self, event: &mut E) {
+        if let Some(handler) = &self.event_handler {
+            let handler = Rc::clone(handler);
+            let handler = &mut *handler.borrow_mut();
+            handler(self, event);
+        }
+    }
+
+    fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    fn get_layouts(&self) -> Layouts {
+        self.layouts.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockall::predicate::*;
+    use mockall::*;
+
+    #[test]
+    fn test_term_string_visible_len_is_correct() {
+        assert_eq!(
+            term_string_visible_len(&format!(
+                "{}ab{}",
+                termion::color::Bg(termion::color::Red),
+                termion::cursor::Goto(1, 123)
+            )),
+            2
+        );
+        assert_eq!(
+            term_string_visible_len(&format!(
+                "{}ab{}",
+                termion::cursor::Goto(1, 123),
+                termion::color::Bg(termion::color::Red)
+            )),
+            2
+        );
+        assert_eq!(
+            term_string_visible_len(&format!(
+                "{}🍻{}",
+                termion::cursor::Goto(1, 123),
+                termion::color::Bg(termion::color::Red)
+            )),
+            1
+        );
+        assert_eq!(
+            term_string_visible_len(&format!(
+                "{}12:34:56 - {}me:{}",
+                termion::color::Fg(termion::color::White),
+                termion::color::Fg(termion::color::Yellow),
+                termion::color::Fg(termion::color::White)
+            )),
+            14
+        )
+    }
+
+    mock! {
+        Writer {
+        }
+
+        impl Write for Writer {
+            fn write(&mut self, buf: &[u8]) -> std::result::Result<usize, std::io::Error> {
+            }
+            fn flush(&mut self) -> std::result::Result<(), std::io::Error> {
+            }
+        }
+    }
+
+    #[test]
+    fn test_table_filter_is_case_insensitive() {
+        // Given
+        let mut table = TableView::<(), MockWriter>::new(vec!["Jid", "Name"]);
+        table.set_rows(vec![
+            vec![
+                "aparte@conference.fariello.eu".to_string(),
+                "Aparté".to_string(),
+            ],
+            vec![
+                "rust@conference.example.org".to_string(),
+                "Rust".to_string(),
+            ],
+        ]);
+
+        // When
+        table.set_filter(Some("RUST".to_string()));
+
+        // Then
+        let rows = table.filtered_rows();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0][1], "Rust");
+    }
+
+    #[test]
+    fn test_table_columns_are_aligned() {
+        // Given
+        let table = TableView::<(), MockWriter>::new(vec!["Jid", "Occupants"]);
+        let first = vec!["a@b".to_string(), "12".to_string()];
+        let second = vec!["long@example".to_string(), "3".to_string()];
+
+        // When
+        let widths = table.column_widths(&[&first, &second]);
+
+        // Then
+        assert_eq!(widths, vec![12, 9]);
+        assert_eq!(
+            TableView::<(), MockWriter>::format_row(&first, &widths),
+            format!("a@b{}12", " ".repeat(11))
+        );
+    }
+
+    #[test]
+    fn test_table_scroll_to_row_below_view() {
+        // Given
+        let mut table = TableView::<(), MockWriter>::new(vec!["Jid"]);
+        table.set_rows((0..10).map(|i| vec![format!("{i}@server")]).collect());
+        table.height = 4;
+
+        // When
+        table.scroll_to(5);
+
+        // Then
+        assert_eq!(table.view, 3);
+
+        // When
+        table.scroll_to(1);
+
+        // Then
+        assert_eq!(table.view, 1);
+    }
+
+    #[test]
+    fn test_list_collapsed_group_hides_its_items() {
+        // Given
+        let mut list = ListView::<(), MockWriter, String, String>::new().with_layouts(Layouts {
+            width: Layout::wrap_content(),
+            height: Layout::wrap_content(),
+        });
+        list.insert("alice@server".to_string(), Some("Friends".to_string()));
+        list.insert("bob@server".to_string(), Some("Friends".to_string()));
+        list.insert("carol@server".to_string(), Some("Work".to_string()));
+        let mut dimension = Dimension::new();
+
+        // When
+        list.set_collapsed("Friends".to_string(), true);
+        list.measure(&mut dimension, None, None);
+
+        // Then
+        assert!(list.is_collapsed(&"Friends".to_string()));
+        assert_eq!(dimension.h, Some(3));
+        assert_eq!(
+            list.format_group(
+                &"Friends".to_string(),
+                &list.items[&Some("Friends".to_string())]
+            ),
+            "Friends (+2)"
+        );
+
+        // When
+        list.set_collapsed("Friends".to_string(), false);
+        list.measure(&mut dimension, None, None);
+
+        // Then
+        assert_eq!(dimension.h, Some(5));
+    }
+
+    fn buffered_win(items: &[&str]) -> BufferedWin<(), MockWriter, String> {
+        let mut win = BufferedWin::<(), MockWriter, String>::new();
+        win.width = 80;
+        win.height = 2;
+        for item in items {
+            win.history.insert(item.to_string());
+        }
+        win
+    }
+
+    #[test]
+    fn test_buffered_win_search_scrolls_to_previous_match() {
+        // Given
+        let mut win = buffered_win(&["a", "b", "c", "d", "e"]);
+
+        // When
+        let found = win.search(|item| item == "c");
+
+        // Then
+        assert!(found);
+        assert_eq!(win.view, 2);
+        assert!(!win.search(|item| item == "c"));
+        assert!(!win.search(|item| item == "d"));
+    }
+
+    #[test]
+    fn test_buffered_win_jump_displays_match_at_top() {
+        // Given
+        let mut win = buffered_win(&["a", "b", "c", "d", "e"]);
+
+        // When
+        let found = win.jump(|item| item.as_str() >= "b");
+
+        // Then
+        assert!(found);
+        assert_eq!(win.view, 2);
+    }
+
+    #[test]
+    fn test_buffered_win_filter() {
+        // Given
+        let mut win = buffered_win(&["a", "b", "c", "d", "e"]);
+
+        // When
+        win.set_filter(|item| item != "c");
+
+        // Then
+        assert_eq!(win.get_rendered_items(), vec!["a", "b", "d", "e"]);
+        assert!(!win.search(|item| item == "c"));
+    }
+
+    #[test]
+    fn test_buffered_win_limit_drops_oldest_at_bottom_only() {
+        // Given
+        let mut win = buffered_win(&["a", "b", "c", "d", "e"]).with_limit(Some(3));
+        win.view = 1;
+
+        // When
+        win.trim();
+
+        // Then
+        assert_eq!(win.history.len(), 5);
+
+        // When
+        win.view = 0;
+        win.trim();
+
+        // Then
+        assert_eq!(win.get_rendered_items(), vec!["c", "d", "e"]);
+    }
+
+    #[test]
+    fn test_input_backspace() {
+        // Given
+        let mut input = Input::<()>::new();
+
+        // When
+        input.key('a');
+        input.key('b');
+        input.key('c');
+        input.backspace();
+
+        // Then
+        assert_eq!(input.buf, "ab".to_string());
+    }
+
+    #[test]
+    fn test_term_string_clean() {
+        // Given
+        let input = "test \x1b[5mBlink";
+
+        // When
+        let cleaned = clean(input);
+
+        // Then
+        assert_eq!(cleaned, "test Blink");
+    }
+
+    #[test]
+    fn test_term_string_clean_strings_and_controls() {
+        // Given
+        let input = "\x1b]0;title\x07a\rb\x08c\u{9b}2J\x1bPdata\x1b\\d\x1b7";
+
+        // When
+        let cleaned = clean(input);
+
+        // Then
+        assert_eq!(cleaned, "abc2Jd");
+    }
+
+    #[test]
+    fn test_term_string_clean_bidi_overrides() {
+        // Given
+        let input = "invoice\u{202e}fdp.exe\tnow";
+
+        // When
+        let cleaned = clean(input);
+
+        // Then
+        assert_eq!(cleaned, "invoicefdp.exe now");
+    }
+
+    #[test]
+    fn test_term_string_visible_truncate() {
+        // Given
+        let input = "test \x1b[5mBlink";
+
+        // When
+        let truncated = term_string_visible_truncate(input, 6, None);
+
+        // Then
+        assert_eq!(truncated, "test \x1b[5mB");
     }
 
     #[test]
@@ -1943,4 +3182,147 @@ mod tests {
         // Then
         assert_eq!(truncated, "test …");
     }
+
+    fn form_field(var: &str, type_: FieldType, required: bool, options: &[&str]) -> Field {
+        Field {
+            var: var.to_string(),
+            type_,
+            label: None,
+            required,
+            media: vec![],
+            options: options
+                .iter()
+                .map(|value| xmpp_parsers::data_forms::Option {
+                    label: None,
+                    value: value.to_string(),
+                })
+                .collect(),
+            values: vec![],
+        }
+    }
+
+    #[test]
+    fn test_grid_move_stays_within_cells() {
+        // Given
+        let mut grid = GridView::<(), MockWriter>::new();
+        grid.set_cells(
+            ["a", "b", "c", "d", "e"]
+                .iter()
+                .map(|c| c.to_string())
+                .collect(),
+        );
+        grid.columns = 3;
+
+        // When
+        grid.move_by(1, 1);
+
+        // Then
+        assert_eq!(grid.selected(), Some("e"));
+        grid.move_by(1, 0);
+        assert_eq!(grid.selected(), Some("e"));
+        grid.move_by(0, -5);
+        assert_eq!(grid.selected(), Some("b"));
+        grid.move_by(-5, 0);
+        assert_eq!(grid.selected(), Some("a"));
+    }
+
+    fn form_view(fields: Vec<Field>) -> FormView<(), MockWriter> {
+        FormView::<(), MockWriter>::new(DataForm {
+            type_: DataFormType::Form,
+            form_type: None,
+            title: None,
+            instructions: None,
+            fields,
+        })
+    }
+
+    #[test]
+    fn test_form_navigation_skips_fixed_and_hidden_fields() {
+        // Given
+        let mut form = form_view(vec![
+            form_field("intro", FieldType::Fixed, false, &[]),
+            form_field("name", FieldType::TextSingle, false, &[]),
+            form_field("token", FieldType::Hidden, false, &[]),
+            form_field("public", FieldType::Boolean, false, &[]),
+        ]);
+        assert_eq!(form.selected, Some(1));
+
+        // When
+        form.next();
+        form.next();
+
+        // Then
+        assert_eq!(form.selected, Some(3));
+        form.previous();
+        assert_eq!(form.selected, Some(1));
+    }
+
+    #[test]
+    fn test_form_validate_selects_missing_required_field() {
+        // Given
+        let mut form = form_view(vec![
+            form_field("name", FieldType::TextSingle, false, &[]),
+            form_field("owner", FieldType::JidSingle, true, &[]),
+        ]);
+
+        // When
+        let submitted = form.validate();
+
+        // Then
+        assert!(submitted.is_none());
+        assert_eq!(form.selected, Some(1));
+        assert!(form.error.is_some());
+    }
+
+    #[test]
+    fn test_form_validate_submits_edited_values() {
+        // Given
+        let mut form = form_view(vec![
+            form_field("name", FieldType::TextSingle, true, &[]),
+            form_field("public", FieldType::Boolean, false, &[]),
+            form_field(
+                "whois",
+                FieldType::ListSingle,
+                false,
+                &["moderators", "anyone"],
+            ),
+            form_field(
+                "roles",
+                FieldType::ListMulti,
+                false,
+                &["visitor", "participant"],
+            ),
+        ]);
+
+        // When
+        form.key('a');
+        form.key('b');
+        form.backspace();
+        form.next();
+        form.key(' ');
+        form.next();
+        form.right();
+        form.right();
+        form.next();
+        form.right();
+        form.key(' ');
+        let submitted = form.validate().unwrap();
+
+        // Then
+        let values = submitted
+            .fields
+            .iter()
+            .map(|field| (field.var.as_str(), field.values.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(submitted.type_, DataFormType::Submit);
+        assert_eq!(
+            values,
+            vec![
+                ("name", vec![String::from("a")]),
+                ("public", vec![String::from("1")]),
+                ("whois", vec![String::from("anyone")]),
+                ("roles", vec![String::from("participant")]),
+            ]
+        );
+    }
 }
@@ -119,6 +119,86 @@ pub fn byte_index(buf: &str, mut cursor: usize) -> usize {
     byte_index
 }
 
+/// Split buf in chunks of at most max bytes, at word boundaries when possible. Spaces ending a
+/// chunk are dropped.
+pub fn split_at_words(buf: &str, max: usize) -> Vec<&str> {
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    let mut end = 0;
+
+    for word in Words::new(buf) {
+        let word_end = end + word.len();
+        if buf[start..word_end].trim_end().len() > max && end > start {
+            chunks.push(buf[start..end].trim_end());
+            start = end;
+        }
+
+        // Words longer than max are cut at char boundaries
+        while buf[start..word_end].trim_end().len() > max {
+            let mut cut = start + max;
+            while !buf.is_char_boundary(cut) {
+                cut -= 1;
+            }
+            if cut == start {
+                cut += buf[start..].chars().next().unwrap().len_utf8();
+            }
+            chunks.push(&buf[start..cut]);
+            start = cut;
+        }
+
+        end = word_end;
+    }
+
+    if !buf[start..].trim_end().is_empty() {
+        chunks.push(buf[start..].trim_end());
+    }
+
+    chunks.retain(|chunk| !chunk.is_empty());
+    chunks
+}
+
+/// Extensions of web pages, links to them aren't considered files
+const PAGE_EXTENSIONS: [&str; 6] = ["html", "htm", "php", "asp", "aspx", "jsp"];
+
+/// URLs found in buf whose path ends with a file name, e.g. https://server.tld/cat.png
+pub fn file_urls(buf: &str) -> Vec<&str> {
+    buf.split_whitespace()
+        .map(|word| {
+            word.trim_start_matches(['(', '<'])
+                .trim_end_matches(['.', ',', ';', ':', '!', '?', ')', '>'])
+        })
+        .filter(|word| url_file_name(word).is_some())
+        .collect()
+}
+
+/// Name of the file a URL points to, None when its path doesn't end with a file name
+pub fn url_file_name(url: &str) -> Option<&str> {
+    let location = ["https://", "http://", "aesgcm://"]
+        .iter()
+        .find_map(|scheme| url.strip_prefix(scheme))?;
+    let (_host, path) = location.split(['?', '#']).next()?.split_once('/')?;
+    let name = path.rsplit('/').next()?;
+    let (stem, extension) = name.rsplit_once('.')?;
+    let valid = !stem.is_empty()
+        && (1..=5).contains(&extension.len())
+        && extension.chars().all(|c| c.is_ascii_alphanumeric())
+        && !PAGE_EXTENSIONS.contains(&extension.to_lowercase().as_str());
+    match valid {
+        true => Some(name),
+        false => None,
+    }
+}
+
+/// First non empty line of buf, cut after max chars
+pub fn excerpt(buf: &str, max: usize) -> String {
+    let line = buf.lines().map(str::trim).find(|line| !line.is_empty());
+    let line = line.unwrap_or_default();
+    match line.char_indices().nth(max) {
+        Some((cut, _)) => format!("{}…", &line[..cut]),
+        None => line.to_string(),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -188,4 +268,76 @@ mod tests {
         // Then
         assert_eq!(words.collect::<Vec<&str>>(), Vec::<&str>::new());
     }
+
+    #[test]
+    fn test_split_at_words() {
+        // Given
+        let input = "three simple words";
+
+        // When
+        let chunks = split_at_words(input, 12);
+
+        // Then
+        assert_eq!(chunks, vec!["three simple", "words"]);
+    }
+
+    #[test]
+    fn test_split_at_words_long_word() {
+        // Given
+        let input = "a 🍺🍺🍺 b";
+
+        // When
+        let chunks = split_at_words(input, 9);
+
+        // Then
+        assert_eq!(chunks, vec!["a", "🍺🍺", "🍺 b"]);
+    }
+
+    #[test]
+    fn test_file_urls() {
+        // Given
+        let input = "look (https://upload.server.tld/a1/cat.png), and https://server.tld/";
+
+        // When
+        let urls = file_urls(input);
+
+        // Then
+        assert_eq!(urls, vec!["https://upload.server.tld/a1/cat.png"]);
+    }
+
+    #[test]
+    fn test_file_urls_ignores_pages() {
+        // Given
+        let input = "https://server.tld/index.html http://server.tld/file.tar.gz?token=1";
+
+        // When
+        let urls = file_urls(input);
+
+        // Then
+        assert_eq!(urls, vec!["http://server.tld/file.tar.gz?token=1"]);
+    }
+
+    #[test]
+    fn test_excerpt_first_line() {
+        // Given
+        let input = "\n  Hello there  \nHow are you?";
+
+        // When
+        let excerpt = excerpt(input, 20);
+
+        // Then
+        assert_eq!(excerpt, "Hello there");
+    }
+
+    #[test]
+    fn test_excerpt_cut() {
+        // Given
+        let input = "🍺🍺🍺 for everyone";
+
+        // When
+        let excerpt = excerpt(input, 2);
+
+        // Then
+        assert_eq!(excerpt, "🍺🍺…");
+    }
 }